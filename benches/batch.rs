@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use czmq::{ZMsg, ZSys};
+use czmq::bench;
+
+const BATCH_SIZE: usize = 100;
+
+fn small_messages() -> Vec<ZMsg> {
+    (0..BATCH_SIZE).map(|_| bench::message(2, 16).unwrap()).collect()
+}
+
+fn send_recv_batches(c: &mut Criterion) {
+    ZSys::init();
+
+    let (mut client, mut server) = bench::push_pull("batch").unwrap();
+
+    c.bench_function("send_recv_100_single", |b| b.iter(|| {
+        for msg in small_messages() {
+            msg.send(&mut client).unwrap();
+        }
+        for _ in 0..BATCH_SIZE {
+            ZMsg::recv(&mut server).unwrap();
+        }
+    }));
+
+    let msgs = small_messages();
+    c.bench_function("send_recv_100_batch", |b| b.iter(|| {
+        ZMsg::send_batch(&msgs, &mut client).unwrap();
+        let mut received = 0;
+        while received < BATCH_SIZE {
+            received += ZMsg::recv_batch(&mut server, BATCH_SIZE - received).unwrap().len();
+        }
+    }));
+}
+
+criterion_group!(benches, send_recv_batches);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use czmq::{ZFrame, ZMsg, ZSys};
+use czmq::bench;
+
+const SIZES: &[usize] = &[16, 1024, 65536];
+
+fn zsock_latency(c: &mut Criterion) {
+    ZSys::init();
+
+    let (server, client) = bench::inproc_pair("zsock_latency").unwrap();
+
+    c.bench_function("zsock_roundtrip_str", |b| b.iter(|| {
+        client.send_str("ping").unwrap();
+        server.recv_str().unwrap().unwrap();
+        server.send_str("pong").unwrap();
+        client.recv_str().unwrap().unwrap();
+    }));
+}
+
+fn zframe_throughput(c: &mut Criterion) {
+    ZSys::init();
+
+    let (mut push, mut pull) = bench::push_pull("zframe_throughput").unwrap();
+    let mut group = c.benchmark_group("zframe_send_recv");
+
+    for size in SIZES {
+        let payload = bench::payload(*size);
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| b.iter(|| {
+            ZFrame::new(payload).unwrap().send(&mut push, None).unwrap();
+            ZFrame::recv(&mut pull).unwrap();
+        }));
+    }
+
+    group.finish();
+}
+
+fn zmsg_throughput(c: &mut Criterion) {
+    ZSys::init();
+
+    let (mut push, mut pull) = bench::push_pull("zmsg_throughput").unwrap();
+    let mut group = c.benchmark_group("zmsg_send_recv_4_frames");
+
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(*size as u64 * 4));
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, size| b.iter(|| {
+            bench::message(4, *size).unwrap().send(&mut push).unwrap();
+            ZMsg::recv(&mut pull).unwrap();
+        }));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, zsock_latency, zframe_throughput, zmsg_throughput);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use czmq::{ZFrame, ZSys};
+use czmq::bench;
+use std::sync::Arc;
+
+const PAYLOAD_SIZE: usize = 10 * 1024 * 1024;
+
+fn send_large_frames(c: &mut Criterion) {
+    ZSys::init();
+
+    let (mut client, mut server) = bench::push_pull("zero_copy").unwrap();
+
+    c.bench_function("send_copy_10mb", |b| b.iter(|| {
+        let data = bench::payload(PAYLOAD_SIZE);
+        ZFrame::new(&data).unwrap().send(&mut client, None).unwrap();
+        ZFrame::recv(&mut server).unwrap();
+    }));
+
+    c.bench_function("send_zero_copy_10mb", |b| b.iter(|| {
+        let data = bench::payload(PAYLOAD_SIZE);
+        client.send_zero_copy(data).unwrap();
+        ZFrame::recv(&mut server).unwrap();
+    }));
+
+    let shared: Arc<[u8]> = Arc::from(bench::payload(PAYLOAD_SIZE));
+    c.bench_function("send_arc_10mb", |b| b.iter(|| {
+        client.send_arc(shared.clone()).unwrap();
+        ZFrame::recv(&mut server).unwrap();
+    }));
+}
+
+criterion_group!(benches, send_large_frames);
+criterion_main!(benches);
@@ -19,6 +19,11 @@ pub use ffi::{
     //
     zauth,
 
+    //
+    // ZBeacon
+    //
+    zbeacon,
+
     //
     // ZCert
     //
@@ -32,6 +37,7 @@ pub use ffi::{
     zcert_public_txt,
     zcert_secret_txt,
     zcert_set_meta,
+    zcert_unset_meta,
     zcert_meta,
     zcert_meta_keys,
     zcert_save,
@@ -59,6 +65,25 @@ pub use ffi::{
     zcertstore_fprint,
     zcertstore_test,
 
+    //
+    // ZConfig
+    //
+    zconfig_t,
+    zconfig_new,
+    zconfig_load,
+    zconfig_destroy,
+    zconfig_name,
+    zconfig_value,
+    zconfig_put,
+    zconfig_get,
+    zconfig_set_value,
+    zconfig_child,
+    zconfig_next,
+    zconfig_locate,
+    zconfig_save,
+    zconfig_str_load,
+    zconfig_str_save,
+
     //
     // ZFrame
     //
@@ -124,7 +149,9 @@ pub use ffi::{
     zlist_new,
     zlist_append,
     zlist_destroy,
+    zlist_first,
     zlist_next,
+    zlist_last,
     zlist_size,
 
     //
@@ -290,6 +317,12 @@ pub use ffi::{
     zsock_set_rcvbuf,
     zsock_linger,
     zsock_set_linger,
+    zsock_heartbeat_ivl,
+    zsock_set_heartbeat_ivl,
+    zsock_heartbeat_ttl,
+    zsock_set_heartbeat_ttl,
+    zsock_heartbeat_timeout,
+    zsock_set_heartbeat_timeout,
     zsock_reconnect_ivl,
     zsock_set_reconnect_ivl,
     zsock_reconnect_ivl_max,
@@ -340,8 +373,112 @@ pub use ffi::{
     zsys_init,
     zsys_create_pipe,
     zsys_interrupted,
+    zsys_version,
+
+    //
+    // ZUuid
+    //
+    zuuid_t,
+    zuuid_new,
+    zuuid_destroy,
+    zuuid_data,
+    zuuid_str,
+
+    //
+    // ZMQ
+    //
+    zmq_errno,
+    zmq_getsockopt,
+    zmq_has,
+    zmq_setsockopt,
+    zmq_version,
+    zmq_free_fn,
+    zmq_msg_t,
+    zmq_msg_init,
+    zmq_msg_init_size,
+    zmq_msg_init_data,
+    zmq_msg_send,
+    zmq_msg_recv,
+    zmq_msg_close,
+    zmq_msg_data,
+    zmq_msg_size,
+    zmq_msg_more,
+    zmq_poll,
+    zmq_pollitem_t,
+    zmq_socket_monitor,
+};
+
+// CZMQ's draft API, only present when CZMQ was built with
+// --enable-drafts.
+#[cfg(feature = "draft")]
+pub use ffi::{
+    //
+    // ZFrame
+    //
+    zframe_routing_id,
+    zframe_set_routing_id,
+    zframe_group,
+    zframe_set_group,
+
+    //
+    // ZProc
+    //
+    zproc_czmq_version,
+    zproc_interrupted,
+    zproc_has_curve,
+    zproc_hostname,
+    zproc_set_io_threads,
+    zproc_set_max_sockets,
+    zproc_set_biface,
+    zproc_biface,
+    zproc_set_log_ident,
+    zproc_set_log_sender,
+    zproc_set_log_system,
+
+    //
+    // ZSock
+    //
+    zsock_new_server,
+    zsock_new_client,
+    zsock_new_radio,
+    zsock_new_dish,
+    zsock_new_gather,
+    zsock_new_scatter,
+    zsock_routing_id,
+    zsock_set_routing_id,
+    zsock_join,
+    zsock_leave,
+
+    //
+    // ZTimerSet
+    //
+    ztimerset_t,
+    ztimerset_fn,
+    ztimerset_new,
+    ztimerset_destroy,
+    ztimerset_add,
+    ztimerset_cancel,
+    ztimerset_set_interval,
+    ztimerset_reset,
+    ztimerset_timeout,
+    ztimerset_execute,
 };
 
+// libzmq's draft API, only present when libzmq was built with
+// --enable-drafts. zmq.h hides these unless ZMQ_BUILD_DRAFT_API is
+// defined, so the generated bindings don't have them.
+#[cfg(feature = "draft")]
+extern "C" {
+    pub fn zmq_socket_monitor_versioned(s: *mut ::std::os::raw::c_void,
+                                        addr: *const ::std::os::raw::c_char,
+                                        events: u64,
+                                        event_version: ::std::os::raw::c_int,
+                                        type_: ::std::os::raw::c_int)
+     -> ::std::os::raw::c_int;
+    pub fn zmq_socket_monitor_pipes_stats(s: *mut ::std::os::raw::c_void)
+     -> ::std::os::raw::c_int;
+}
+
 #[allow(dead_code, non_camel_case_types, non_snake_case)]
 mod ffi {
     include!("ffi.rs");
@@ -0,0 +1,731 @@
+//! Module: czmq-asyncs
+//!
+//! Async sockets, actors and polling. An `AsyncSock` wraps a `ZSock`,
+//! and an `AsyncActor` a `ZActor`, and waits for it on the runtime
+//! chosen by its `Waiter`: `Tokio` with the `tokio` feature, or
+//! `AsyncStd` with the `async-std` feature. The blocking API waits
+//! through the same code using the `Blocking` waiter. `AsyncPoller`
+//! waits for any of several sockets, like `ZPoller`, and
+//! `AsyncBeacon` reports the beacons a `ZBeacon` hears.
+//! `blocking_socket_task()` runs any socket on its own thread behind
+//! async handles.
+//!
+//! ```rust,ignore
+//! use czmq::{ZSock, asyncs::{AsyncSock, Tokio}};
+//!
+//! let mut sock = AsyncSock::<Tokio>::new(ZSock::new_pull("inproc://moo")?)?;
+//! let msg = sock.recv().await?;
+//! ```
+//!
+//! With the `futures` feature, `AsyncSock` is also a
+//! `Sink<ZMsg>`, which is ready whenever the socket is writable, so
+//! combinators like `forward()` and `send_all()` respect the socket's
+//! high-water mark, and `AsyncBeacon` is a `Stream<Item = Discovery>`:
+//!
+//! ```rust,ignore
+//! let mut beacons = AsyncBeacon::<Tokio>::new(beacon)?;
+//! while let Some(peer) = beacons.next().await {
+//!     println!("{} says {:?}", peer.addr, peer.data);
+//! }
+//! ```
+
+use crate::{Discovery, RawInterface, Result, Sockish, SocketLike, ZActor, ZBeacon, ZMsg, ZPoller, ZSock};
+use crate::waiter::wait_for;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(feature = "futures")]
+use crate::Error;
+#[cfg(feature = "futures")]
+use futures::{Sink, Stream};
+
+pub use crate::mdp::{AsyncMdpClient, MdpCall};
+pub use crate::waiter::{Blocking, Sleep, Wait, Waiter, WaiterError};
+#[cfg(feature = "async-std")]
+pub use crate::waiter::AsyncStd;
+#[cfg(feature = "tokio")]
+pub use crate::waiter::Tokio;
+
+/// A socket whose sends and receives can be awaited.
+pub struct AsyncSock<W: Waiter> {
+    // Declared first so that the waiter is dropped before the socket
+    // whose descriptor it watches
+    waiter: W,
+    sock: ZSock,
+}
+
+impl<W: Waiter> AsyncSock<W> {
+    pub fn new(sock: ZSock) -> Result<AsyncSock<W>> {
+        Ok(AsyncSock {
+            waiter: W::register(&sock)?,
+            sock: sock,
+        })
+    }
+
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    pub fn into_inner(self) -> ZSock {
+        self.sock
+    }
+
+    /// Wait until a message can be received without blocking.
+    pub async fn readable(&mut self) -> Result<()> {
+        wait_for(&mut self.waiter, &mut self.sock, ZSock::readable, None).await?;
+        Ok(())
+    }
+
+    /// Wait until a message can be sent without blocking.
+    pub async fn writable(&mut self) -> Result<()> {
+        wait_for(&mut self.waiter, &mut self.sock, ZSock::writable, None).await?;
+        Ok(())
+    }
+
+    /// Receive the next message.
+    pub async fn recv(&mut self) -> Result<ZMsg> {
+        self.readable().await?;
+        ZMsg::recv(&self.sock)
+    }
+
+    /// Receive the next message, or return `None` if none arrives
+    /// within `timeout`.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<ZMsg>> {
+        self.recv_deadline(Instant::now() + timeout).await
+    }
+
+    /// Receive the next message, or return `None` if none arrives by
+    /// `deadline`.
+    ///
+    /// Like the other receives, this is cancellation safe. ZMQ
+    /// delivers all parts of a message at once, and the message is
+    /// received whole in one step after the last await, so dropping
+    /// the future never leaves part of a message consumed.
+    pub async fn recv_deadline(&mut self, deadline: Instant) -> Result<Option<ZMsg>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        if wait_for(&mut self.waiter, &mut self.sock, ZSock::readable, Some(timeout)).await? {
+            ZMsg::recv(&self.sock).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Send `msg`, waiting while the socket is at its high-water mark.
+    pub async fn send(&mut self, msg: ZMsg) -> Result<()> {
+        self.writable().await?;
+        msg.send(&self.sock)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<W: Waiter + Unpin> Sink<ZMsg> for AsyncSock<W> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        let this = self.get_mut();
+
+        while !this.sock.writable() {
+            match this.waiter.poll_wait(cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: ZMsg) -> Result<()> {
+        msg.send(&self.sock)
+    }
+
+    // ZMQ queues sent messages itself, so there's nothing to flush
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An actor whose pipe can be awaited, so that one task can supervise
+/// many actors without a blocked thread for each.
+pub struct AsyncActor<W: Waiter> {
+    waiter: W,
+    // The actor's end of its pipe, which the actor owns
+    pipe: ZSock,
+    actor: ZActor,
+}
+
+impl<W: Waiter> AsyncActor<W> {
+    pub fn new(actor: ZActor) -> Result<AsyncActor<W>> {
+        let pipe = actor.sock();
+
+        Ok(AsyncActor {
+            waiter: W::register(&pipe)?,
+            pipe: pipe,
+            actor: actor,
+        })
+    }
+
+    pub fn get_ref(&self) -> &ZActor {
+        &self.actor
+    }
+
+    pub fn into_inner(self) -> ZActor {
+        self.actor
+    }
+
+    /// Send a message to the actor.
+    pub async fn send(&mut self, msg: ZMsg) -> Result<()> {
+        wait_for(&mut self.waiter, &mut self.pipe, ZSock::writable, None).await?;
+        self.actor.send(msg)
+    }
+
+    /// Receive the next message from the actor.
+    pub async fn recv(&mut self) -> Result<ZMsg> {
+        wait_for(&mut self.waiter, &mut self.pipe, ZSock::readable, None).await?;
+        self.actor.recv()
+    }
+
+    /// Receive the next message from the actor, or return `None` if
+    /// none arrives within `timeout`.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<ZMsg>> {
+        if wait_for(&mut self.waiter, &mut self.pipe, ZSock::readable, Some(timeout)).await? {
+            self.actor.recv().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Send a command and wait for the actor's reply. Taking `&mut
+    /// self` stops two calls from interleaving their replies.
+    pub async fn call(&mut self, msg: ZMsg) -> Result<ZMsg> {
+        self.send(msg).await?;
+        self.recv().await
+    }
+}
+
+/// Waits for any of a set of readers to become readable.
+///
+/// The future returned by `wait()` only reports readiness and never
+/// receives anything itself, so it is cancellation safe: dropping it,
+/// e.g. when another branch of `tokio::select!` wins, loses no
+/// messages. Readers are checked in turn, starting after the last one
+/// returned, so a busy reader can't starve the others.
+///
+/// The `Blocking` waiter can only wait on one descriptor at a time, so
+/// use `ZPoller` for blocking code instead.
+pub struct AsyncPoller<W: Waiter> {
+    readers: Vec<(W, ZSock)>,
+    next: usize,
+}
+
+impl<W: Waiter> AsyncPoller<W> {
+    pub fn new() -> AsyncPoller<W> {
+        AsyncPoller {
+            readers: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Add a reader, which must outlive the poller.
+    pub fn add<S: SocketLike>(&mut self, mut reader: S) -> Result<()> {
+        let sock = unsafe { ZSock::from_raw(reader.as_socket_ptr(), false) };
+        self.readers.push((W::register(&sock)?, sock));
+        Ok(())
+    }
+
+    pub fn remove<S: SocketLike>(&mut self, mut reader: S) {
+        let ptr = reader.as_socket_ptr();
+        if let Some(i) = self.readers.iter_mut().position(|r| r.1.as_mut_ptr() == ptr) {
+            self.readers.remove(i);
+        }
+    }
+
+    /// Wait for a reader to become readable, returning it. Waits
+    /// forever if there are no readers.
+    pub fn wait<S: Sockish>(&mut self) -> PollerWait<'_, W, S> {
+        PollerWait {
+            poller: self,
+            reader: PhantomData,
+        }
+    }
+
+    fn ready(&mut self) -> Option<*mut c_void> {
+        let len = self.readers.len();
+
+        for i in 0..len {
+            let index = (self.next + i) % len;
+            if self.readers[index].1.readable() {
+                self.next = index + 1;
+                return Some(self.readers[index].1.as_mut_ptr());
+            }
+        }
+
+        None
+    }
+}
+
+impl<W: Waiter> Default for AsyncPoller<W> {
+    fn default() -> AsyncPoller<W> {
+        AsyncPoller::new()
+    }
+}
+
+/// The future returned by `AsyncPoller::wait()`.
+pub struct PollerWait<'a, W: Waiter, S> {
+    poller: &'a mut AsyncPoller<W>,
+    reader: PhantomData<fn() -> S>,
+}
+
+impl<'a, W: Waiter, S: Sockish> Future for PollerWait<'a, W, S> {
+    type Output = Result<S>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<S>> {
+        let poller = &mut *self.get_mut().poller;
+
+        loop {
+            if let Some(ptr) = poller.ready() {
+                return Poll::Ready(Ok(unsafe { S::from_raw(ptr, false) }));
+            }
+
+            // Every waiter has to be polled, so that each registers the
+            // task to be woken
+            let mut signalled = false;
+            for &mut (ref mut waiter, _) in &mut poller.readers {
+                match waiter.poll_wait(cx) {
+                    Poll::Ready(Ok(())) => signalled = true,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => (),
+                }
+            }
+
+            if !signalled {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Move `sock` onto a dedicated thread, which blocks on it on the
+/// caller's behalf, and return async handles for sending to and
+/// receiving from it.
+///
+/// This gives async code the use of any socket, including ones whose
+/// blocking sends would stall a runtime, without the draft
+/// thread-safe socket types. Messages pass between the handles and
+/// the thread over inproc sockets, so they keep the socket's own
+/// high-water marks: a full socket holds up the `TaskSender`s, and
+/// an idle `TaskReceiver` holds up the socket.
+///
+/// `TaskSender::try_clone()` adds senders. The thread closes the
+/// socket once every handle has been dropped. Handles register their
+/// waiters when created, so with `Tokio` this must be called, and
+/// senders cloned, from within a runtime.
+pub fn blocking_socket_task<W: Waiter>(sock: ZSock) -> Result<(TaskSender<W>, TaskReceiver<W>)> {
+    let id = TASK_ID.fetch_add(1, Ordering::Relaxed);
+    let outbound = format!("inproc://czmq-blocking-task-{}-out", id);
+    let inbound = format!("inproc://czmq-blocking-task-{}-in", id);
+
+    let from_handles = ZSock::new_pull(&format!("@{}", outbound))?;
+    let to_handle = ZSock::new_push(&format!("@{}", inbound))?;
+    let alive = Arc::new(());
+
+    let sender = TaskSender {
+        sock: AsyncSock::new(ZSock::new_push(&format!(">{}", outbound))?)?,
+        endpoint: outbound,
+        alive: alive.clone(),
+    };
+    let receiver = TaskReceiver {
+        sock: AsyncSock::new(ZSock::new_pull(&format!(">{}", inbound))?)?,
+        _alive: alive.clone(),
+    };
+
+    let mut poller = ZPoller::new()?;
+    poller.add(&sock)?;
+    poller.add(&from_handles)?;
+
+    thread::spawn(move || {
+        // The thread holds one reference itself
+        while Arc::strong_count(&alive) > 1 {
+            let forwarded = match poller.wait_timeout::<ZSock>(Some(TASK_TICK)) {
+                Ok(Some(_)) if sock.readable() => ZMsg::recv(&sock).and_then(|msg| msg.send(&to_handle)),
+                Ok(Some(_)) if from_handles.readable() => ZMsg::recv(&from_handles).and_then(|msg| msg.send(&sock)),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            };
+
+            if forwarded.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok((sender, receiver))
+}
+
+static TASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+// How often a blocking socket task checks whether its handles are gone
+const TASK_TICK: Duration = Duration::from_millis(100);
+
+/// Sends messages to a socket owned by `blocking_socket_task()`.
+pub struct TaskSender<W: Waiter> {
+    sock: AsyncSock<W>,
+    endpoint: String,
+    alive: Arc<()>,
+}
+
+impl<W: Waiter> TaskSender<W> {
+    /// Queue `msg` to be sent on the socket.
+    pub async fn send(&mut self, msg: ZMsg) -> Result<()> {
+        self.sock.send(msg).await
+    }
+
+    /// Create another sender for the same socket.
+    pub fn try_clone(&self) -> Result<TaskSender<W>> {
+        Ok(TaskSender {
+            sock: AsyncSock::new(ZSock::new_push(&format!(">{}", self.endpoint))?)?,
+            endpoint: self.endpoint.clone(),
+            alive: self.alive.clone(),
+        })
+    }
+}
+
+/// Receives messages from a socket owned by `blocking_socket_task()`.
+pub struct TaskReceiver<W: Waiter> {
+    sock: AsyncSock<W>,
+    _alive: Arc<()>,
+}
+
+impl<W: Waiter> TaskReceiver<W> {
+    /// Receive the next message from the socket.
+    pub async fn recv(&mut self) -> Result<ZMsg> {
+        self.sock.recv().await
+    }
+
+    /// Receive the next message, or return `None` if none arrives
+    /// within `timeout`.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<ZMsg>> {
+        self.sock.recv_timeout(timeout).await
+    }
+}
+
+/// A beacon whose discoveries can be awaited.
+pub struct AsyncBeacon<W: Waiter> {
+    waiter: W,
+    pipe: ZSock,
+    beacon: ZBeacon,
+}
+
+impl<W: Waiter> AsyncBeacon<W> {
+    /// Wrap a beacon, which should already be configured and
+    /// subscribed.
+    pub fn new(beacon: ZBeacon) -> Result<AsyncBeacon<W>> {
+        let pipe = beacon.actor().sock();
+
+        Ok(AsyncBeacon {
+            waiter: W::register(&pipe)?,
+            pipe: pipe,
+            beacon: beacon,
+        })
+    }
+
+    pub fn get_ref(&self) -> &ZBeacon {
+        &self.beacon
+    }
+
+    pub fn into_inner(self) -> ZBeacon {
+        self.beacon
+    }
+
+    /// Receive the next beacon.
+    pub async fn recv(&mut self) -> Result<Discovery> {
+        wait_for(&mut self.waiter, &mut self.pipe, ZSock::readable, None).await?;
+        Discovery::from_msg(self.beacon.actor().recv()?)
+    }
+}
+
+/// Yields each beacon heard. Malformed beacons are skipped, and the
+/// stream ends if the beacon's pipe fails.
+#[cfg(feature = "futures")]
+impl<W: Waiter + Unpin> Stream for AsyncBeacon<W> {
+    type Item = Discovery;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Discovery>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.pipe.readable() {
+                match this.beacon.actor().recv() {
+                    Ok(msg) => if let Ok(discovery) = Discovery::from_msg(msg) {
+                        return Poll::Ready(Some(discovery));
+                    },
+                    Err(_) => return Poll::Ready(None),
+                }
+            } else {
+                match this.waiter.poll_wait(cx) {
+                    Poll::Ready(Ok(())) => (),
+                    Poll::Ready(Err(_)) => return Poll::Ready(None),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZActor, ZBeacon, ZMsg, ZSock, ZSys};
+    use crate::waiter::block_on;
+    use std::ptr;
+    use std::os::raw::c_void;
+    use std::time::{Duration, Instant};
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_blocking() {
+        ZSys::init();
+
+        let mut pull = AsyncSock::<Blocking>::new(ZSock::new_pull("inproc://asyncs_test_blocking").unwrap()).unwrap();
+        let mut push = AsyncSock::<Blocking>::new(ZSock::new_push("inproc://asyncs_test_blocking").unwrap()).unwrap();
+
+        assert!(block_on(pull.recv_timeout(TICK)).unwrap().is_none());
+
+        block_on(push.send(ZMsg::from_frames(&[b"moo"]).unwrap())).unwrap();
+        let mut msg = block_on(pull.recv()).unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_sink() {
+        use futures::{SinkExt, StreamExt};
+
+        ZSys::init();
+
+        let pull = ZSock::new_pull("inproc://asyncs_test_sink").unwrap();
+        pull.set_rcvtimeo(Some(1000));
+        let mut push = AsyncSock::<Blocking>::new(ZSock::new_push("inproc://asyncs_test_sink").unwrap()).unwrap();
+
+        let msgs = futures::stream::iter(0..5).map(|i| ZMsg::from_frames(&[i.to_string()]));
+        block_on(msgs.forward(&mut push)).unwrap();
+        for i in 0..5 {
+            let mut msg = ZMsg::recv(&pull).unwrap();
+            assert_eq!(msg.popstr().unwrap().unwrap(), i.to_string());
+        }
+
+        block_on(push.send(ZMsg::from_frames(&[b"moo"]).unwrap())).unwrap();
+        assert_eq!(ZMsg::recv(&pull).unwrap().popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_actor() {
+        ZSys::init();
+
+        let mut actor = AsyncActor::<Blocking>::new(ZActor::new(echo_actor).unwrap()).unwrap();
+        assert!(block_on(actor.recv_timeout(TICK)).unwrap().is_none());
+
+        let mut reply = block_on(actor.call(ZMsg::from_frames(&[b"PING"]).unwrap())).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "PING");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio() {
+        ZSys::init();
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let mut pull = AsyncSock::<Tokio>::new(ZSock::new_pull("inproc://asyncs_test_tokio").unwrap()).unwrap();
+            let mut push = AsyncSock::<Tokio>::new(ZSock::new_push("inproc://asyncs_test_tokio").unwrap()).unwrap();
+
+            let reader = tokio::spawn(async move { pull.recv().await.unwrap().popstr().unwrap().unwrap() });
+            push.send(ZMsg::from_frames(&[b"moo"]).unwrap()).await.unwrap();
+            assert_eq!(reader.await.unwrap(), "moo");
+
+            assert!(push.get_ref().writable());
+        });
+    }
+
+    #[test]
+    fn test_recv_deadline() {
+        ZSys::init();
+
+        let mut pull = AsyncSock::<Blocking>::new(ZSock::new_pull("inproc://asyncs_test_deadline").unwrap()).unwrap();
+        let push = ZSock::new_push("inproc://asyncs_test_deadline").unwrap();
+
+        assert!(block_on(pull.recv_deadline(Instant::now() + TICK)).unwrap().is_none());
+
+        ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap().send(&push).unwrap();
+        // A deadline that has passed still takes a waiting message
+        let msg = block_on(pull.recv_deadline(Instant::now())).unwrap().unwrap();
+        assert_eq!(msg, ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_recv_cancelled() {
+        ZSys::init();
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let mut pull = AsyncSock::<Tokio>::new(ZSock::new_pull("inproc://asyncs_test_cancelled").unwrap()).unwrap();
+            let push = ZSock::new_push("inproc://asyncs_test_cancelled").unwrap();
+
+            tokio::select! {
+                _ = pull.recv_deadline(Instant::now() + Duration::from_secs(1)) => panic!("nothing was sent"),
+                _ = tokio::time::sleep(TICK) => (),
+            }
+
+            ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap().send(&push).unwrap();
+            let msg = pull.recv_deadline(Instant::now() + Duration::from_secs(1)).await.unwrap().unwrap();
+            assert_eq!(msg, ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_poller() {
+        ZSys::init();
+
+        let pull = ZSock::new_pull("inproc://asyncs_test_poller").unwrap();
+        let push = ZSock::new_push("inproc://asyncs_test_poller").unwrap();
+
+        let mut poller = AsyncPoller::<Blocking>::new();
+        poller.add(&pull).unwrap();
+
+        ZMsg::from_frames(&[b"moo"]).unwrap().send(&push).unwrap();
+        let reader = block_on(poller.wait::<ZSock>()).unwrap();
+        assert_eq!(ZMsg::recv(&reader).unwrap().popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_select() {
+        ZSys::init();
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let first = ZSock::new_pull("inproc://asyncs_test_select_1").unwrap();
+            let second = ZSock::new_pull("inproc://asyncs_test_select_2").unwrap();
+            let push = ZSock::new_push("inproc://asyncs_test_select_2").unwrap();
+
+            let mut poller = AsyncPoller::<Tokio>::new();
+            poller.add(&first).unwrap();
+            poller.add(&second).unwrap();
+
+            // A wait that loses a select consumes nothing
+            tokio::select! {
+                _ = poller.wait::<ZSock>() => panic!("no reader should be ready"),
+                _ = tokio::time::sleep(Duration::from_millis(50)) => (),
+            }
+
+            ZMsg::from_frames(&[b"moo"]).unwrap().send(&push).unwrap();
+            let reader = tokio::select! {
+                reader = poller.wait::<ZSock>() => reader.unwrap(),
+                _ = tokio::time::sleep(Duration::from_secs(1)) => panic!("timed out"),
+            };
+            assert_eq!(ZMsg::recv(&reader).unwrap().popstr().unwrap().unwrap(), "moo");
+        });
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_actors() {
+        ZSys::init();
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let tasks: Vec<_> = (0..10).map(|i| {
+                let mut actor = AsyncActor::<Tokio>::new(ZActor::new(echo_actor).unwrap()).unwrap();
+                tokio::spawn(async move {
+                    let mut reply = actor.call(ZMsg::from_frames(&[i.to_string()]).unwrap()).await.unwrap();
+                    reply.popstr().unwrap().unwrap()
+                })
+            }).collect();
+
+            for (i, task) in tasks.into_iter().enumerate() {
+                assert_eq!(task.await.unwrap(), i.to_string());
+            }
+        });
+    }
+
+    #[test]
+    fn test_blocking_socket_task() {
+        ZSys::init();
+
+        let peer = ZSock::new_pair("@inproc://asyncs_test_task").unwrap();
+        peer.set_rcvtimeo(Some(1000));
+        let sock = ZSock::new_pair(">inproc://asyncs_test_task").unwrap();
+
+        let (mut sender, mut receiver) = blocking_socket_task::<Blocking>(sock).unwrap();
+        let mut other = sender.try_clone().unwrap();
+
+        block_on(sender.send(ZMsg::from_frames(&[b"moo"]).unwrap())).unwrap();
+        block_on(other.send(ZMsg::from_frames(&[b"baa"]).unwrap())).unwrap();
+        let mut heard: Vec<String> = (0..2).map(|_| ZMsg::recv(&peer).unwrap().popstr().unwrap().unwrap()).collect();
+        heard.sort();
+        assert_eq!(heard, ["baa", "moo"]);
+
+        ZMsg::from_frames(&[b"oink"]).unwrap().send(&peer).unwrap();
+        let mut msg = block_on(receiver.recv_timeout(Duration::from_secs(1))).unwrap().unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "oink");
+    }
+
+    #[cfg(all(feature = "futures", feature = "tokio"))]
+    #[test]
+    fn test_tokio_beacon() {
+        use futures::StreamExt;
+
+        ZSys::init();
+
+        let speaker = ZBeacon::new().unwrap();
+        // Without a broadcast interface there is nothing to test
+        if speaker.configure(9998).unwrap().is_none() {
+            return;
+        }
+        speaker.publish(b"moo", TICK).unwrap();
+
+        let listener = ZBeacon::new().unwrap();
+        listener.configure(9998).unwrap();
+        listener.subscribe(b"").unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let mut beacons = AsyncBeacon::<Tokio>::new(listener).unwrap();
+            let peer = tokio::time::timeout(Duration::from_secs(2), beacons.next()).await.unwrap().unwrap();
+            assert_eq!(peer.data, b"moo");
+        });
+    }
+
+    // Echo every message back until told to terminate
+    unsafe extern "C" fn echo_actor(pipe: *mut czmq_sys::zsock_t, _args: *mut c_void) {
+        czmq_sys::zsock_signal(pipe as *mut c_void, 0);
+
+        loop {
+            let mut msg = czmq_sys::zmsg_recv(pipe as *mut c_void);
+            if msg == ptr::null_mut() {
+                break;
+            }
+
+            let first = czmq_sys::zmsg_first(msg);
+            if czmq_sys::zframe_streq(first, "$TERM\0".as_ptr() as *const i8) == 1 {
+                czmq_sys::zmsg_destroy(&mut msg);
+                break;
+            }
+
+            czmq_sys::zmsg_send(&mut msg, pipe as *mut c_void);
+        }
+    }
+}
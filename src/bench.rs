@@ -0,0 +1,600 @@
+//! Module: czmq-bench
+//!
+//! Helpers shared by the benchmark suite in `benches/`. These are
+//! only compiled with the `bench-internals` feature, but are kept
+//! stable so that downstream crates can benchmark their own protocols
+//! on the same footing.
+//!
+//! Besides socket and message helpers, there are three reference
+//! actors to measure against: an `EchoActor` that sends back whatever
+//! it receives, a `SinkActor` that counts what it receives, and a
+//! `FloodActor` that sends as fast as it can or pings an echo. They
+//! report their measurements over their pipes, and double as examples
+//! of writing an actor in Rust.
+//!
+//! ```rust
+//! use czmq::ZSys;
+//! use czmq::bench::{self, FloodActor, SinkActor};
+//! use std::time::Duration;
+//!
+//! ZSys::init();
+//!
+//! let endpoint = bench::endpoint("example");
+//! let sink = SinkActor::new(&format!("@{}", endpoint)).unwrap();
+//! let flood = FloodActor::new(&format!(">{}", endpoint)).unwrap();
+//!
+//! flood.flood(1000, 64).unwrap();
+//! let stats = sink.wait(1000, Duration::from_secs(5)).unwrap();
+//! println!("{}", stats);
+//! ```
+
+use crate::{ActorProtocol, Error, ErrorKind, MessageField, RawInterface, Result, ZActor, ZMsg, ZPoller, ZSock};
+use std::{error, fmt};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use zmq::SocketType;
+
+static ENDPOINT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Generate an inproc endpoint that's unique within this process.
+pub fn endpoint(name: &str) -> String {
+    format!("inproc://czmq-bench-{}-{}", name, ENDPOINT_SEQ.fetch_add(1, Ordering::SeqCst))
+}
+
+/// Create a connected pair of PAIR sockets over inproc, returned as
+/// `(bound, connected)`.
+pub fn inproc_pair(name: &str) -> Result<(ZSock, ZSock)> {
+    let endpoint = endpoint(name);
+    let server = ZSock::new_pair(&format!("@{}", endpoint))?;
+    let client = ZSock::new_pair(&format!(">{}", endpoint))?;
+    Ok((server, client))
+}
+
+/// Create a connected PUSH/PULL pipeline over inproc, returned as
+/// `(push, pull)`.
+pub fn push_pull(name: &str) -> Result<(ZSock, ZSock)> {
+    let endpoint = endpoint(name);
+    let pull = ZSock::new_pull(&format!("@{}", endpoint))?;
+    let push = ZSock::new_push(&format!(">{}", endpoint))?;
+    Ok((push, pull))
+}
+
+/// Generate a payload of `size` bytes with a repeating pattern, so
+/// that it isn't trivially compressible.
+pub fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+/// Generate a message of `frames` frames, each `frame_size` bytes.
+pub fn message(frames: usize, frame_size: usize) -> Result<ZMsg> {
+    let frame = payload(frame_size);
+    ZMsg::from_frames((0..frames).map(|_| &frame))
+}
+
+// How long an actor waits for a send, or for a ping's echo, before
+// giving up on it
+const BENCH_TIMEOUT: i32 = 1000;
+// How often an idle actor wakes up
+const BENCH_TICK: Duration = Duration::from_millis(100);
+
+crate::czmq_actor_protocol! {
+    enum BenchCommand, client BenchClient {
+        Attach = "ATTACH" => attach(socket_type: String, endpoints: String, serverish: bool) -> reply(String);
+        Stats = "STATS" => stats() -> reply(u64, u64, u64);
+        Reset = "RESET" => reset() -> signal;
+        Wait = "WAIT" => wait(messages: u64, timeout: u64) -> reply(u64, u64, u64);
+        Flood = "FLOOD" => flood(messages: u64, size: u64) -> reply(u64, u64, u64);
+        Ping = "PING" => ping(messages: u64, size: u64) -> reply(u64, u64, u64, u64);
+    }
+}
+
+/// What an actor has sent or received.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BenchStats {
+    pub messages: u64,
+    pub bytes: u64,
+    /// From the first message to the last.
+    pub elapsed: Duration,
+}
+
+impl BenchStats {
+    fn from_reply((messages, bytes, micros): (u64, u64, u64)) -> BenchStats {
+        BenchStats {
+            messages: messages,
+            bytes: bytes,
+            elapsed: Duration::from_micros(micros),
+        }
+    }
+
+    pub fn msgs_per_sec(&self) -> f64 {
+        per_sec(self.messages as f64, self.elapsed)
+    }
+
+    pub fn mbits_per_sec(&self) -> f64 {
+        per_sec(self.bytes as f64 * 8.0 / 1_000_000.0, self.elapsed)
+    }
+}
+
+impl fmt::Display for BenchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} msgs, {} bytes in {:?} ({:.0} msg/s, {:.1} Mb/s)",
+            self.messages, self.bytes, self.elapsed, self.msgs_per_sec(), self.mbits_per_sec())
+    }
+}
+
+fn per_sec(n: f64, elapsed: Duration) -> f64 {
+    if elapsed == Duration::from_secs(0) {
+        0.0
+    } else {
+        n / elapsed.as_secs_f64()
+    }
+}
+
+/// Round trip times measured by `FloodActor::ping()`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BenchLatency {
+    /// How many pings were echoed. Fewer than were asked for means
+    /// one went unanswered, which ends the run.
+    pub round_trips: u64,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for BenchLatency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} round trips, min/mean/max {:?}/{:?}/{:?}", self.round_trips, self.min, self.mean, self.max)
+    }
+}
+
+/// Sends every message it receives back where it came from, over a
+/// ROUTER socket, so it can serve REQ and DEALER clients alike.
+pub struct EchoActor {
+    actor: ZActor,
+}
+
+impl EchoActor {
+    /// Start an echo on `endpoints`, which are bound by default.
+    pub fn new(endpoints: &str) -> Result<EchoActor> {
+        Ok(EchoActor {
+            actor: start(echo_actor, "ROUTER", endpoints, true)?,
+        })
+    }
+
+    /// What has been echoed so far.
+    pub fn stats(&self) -> Result<BenchStats> {
+        BenchClient::new(&self.actor).stats().map(BenchStats::from_reply)
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        BenchClient::new(&self.actor).reset()
+    }
+}
+
+/// Counts the messages it receives over a PULL socket.
+pub struct SinkActor {
+    actor: ZActor,
+}
+
+impl SinkActor {
+    /// Start a sink on `endpoints`, which are bound by default.
+    pub fn new(endpoints: &str) -> Result<SinkActor> {
+        Ok(SinkActor {
+            actor: start(sink_actor, "PULL", endpoints, true)?,
+        })
+    }
+
+    /// What has been received so far.
+    pub fn stats(&self) -> Result<BenchStats> {
+        BenchClient::new(&self.actor).stats().map(BenchStats::from_reply)
+    }
+
+    /// Wait until at least `messages` have been received, or `timeout`
+    /// expires, and return what has been received.
+    pub fn wait(&self, messages: u64, timeout: Duration) -> Result<BenchStats> {
+        BenchClient::new(&self.actor).wait(messages, timeout.as_millis() as u64).map(BenchStats::from_reply)
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        BenchClient::new(&self.actor).reset()
+    }
+}
+
+/// Sends messages as fast as its socket allows, or pings an
+/// `EchoActor` one message at a time to measure latency.
+pub struct FloodActor {
+    actor: ZActor,
+    dealer: bool,
+}
+
+impl FloodActor {
+    /// Start a flood over a PUSH socket, for feeding a `SinkActor`.
+    /// `endpoints` are connected by default.
+    pub fn new(endpoints: &str) -> Result<FloodActor> {
+        Ok(FloodActor {
+            actor: start(flood_actor, "PUSH", endpoints, false)?,
+            dealer: false,
+        })
+    }
+
+    /// Start a flood over a DEALER socket, for pinging an
+    /// `EchoActor`. `endpoints` are connected by default.
+    pub fn dealer(endpoints: &str) -> Result<FloodActor> {
+        Ok(FloodActor {
+            actor: start(flood_actor, "DEALER", endpoints, false)?,
+            dealer: true,
+        })
+    }
+
+    /// Send `messages` messages of `size` bytes, returning once they
+    /// have all been queued. A send that blocks for longer than a
+    /// second ends the run, so check `messages` in the result.
+    pub fn flood(&self, messages: u64, size: usize) -> Result<BenchStats> {
+        BenchClient::new(&self.actor).flood(messages, size as u64).map(BenchStats::from_reply)
+    }
+
+    /// Send `messages` messages of `size` bytes one at a time, waiting
+    /// for each to be echoed before sending the next.
+    pub fn ping(&self, messages: u64, size: usize) -> Result<BenchLatency> {
+        if !self.dealer {
+            return Err(Error::new(ErrorKind::InvalidArg, BenchError::PingNeedsDealer));
+        }
+
+        let (round_trips, min, mean, max) = BenchClient::new(&self.actor).ping(messages, size as u64)?;
+        Ok(BenchLatency {
+            round_trips: round_trips,
+            min: Duration::from_micros(min),
+            mean: Duration::from_micros(mean),
+            max: Duration::from_micros(max),
+        })
+    }
+
+    /// What has been sent so far, across all runs.
+    pub fn stats(&self) -> Result<BenchStats> {
+        BenchClient::new(&self.actor).stats().map(BenchStats::from_reply)
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        BenchClient::new(&self.actor).reset()
+    }
+}
+
+fn start(task: czmq_sys::zactor_fn, socket_type: &str, endpoints: &str, serverish: bool) -> Result<ZActor> {
+    let actor = ZActor::new(task)?;
+    let error = BenchClient::new(&actor).attach(socket_type.into(), endpoints.into(), serverish)?.0;
+    if error.is_empty() {
+        Ok(actor)
+    } else {
+        Err(Error::new(ErrorKind::NonZero, BenchError::Attach(error)))
+    }
+}
+
+unsafe extern "C" fn echo_actor(pipe: *mut czmq_sys::zsock_t, _args: *mut c_void) {
+    run(pipe, Role::Echo);
+}
+
+unsafe extern "C" fn sink_actor(pipe: *mut czmq_sys::zsock_t, _args: *mut c_void) {
+    run(pipe, Role::Sink);
+}
+
+unsafe extern "C" fn flood_actor(pipe: *mut czmq_sys::zsock_t, _args: *mut c_void) {
+    run(pipe, Role::Flood);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    Echo,
+    Sink,
+    Flood,
+}
+
+// The state shared by the reference actors
+struct Bench {
+    role: Role,
+    sock: Option<ZSock>,
+    counts: Counts,
+    // A WAIT that hasn't been answered yet
+    waiting: Option<(u64, Instant)>,
+}
+
+#[derive(Default)]
+struct Counts {
+    messages: u64,
+    bytes: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Counts {
+    fn add(&mut self, messages: u64, bytes: u64, from: Instant) {
+        self.first.get_or_insert(from);
+        self.last = Some(Instant::now());
+        self.messages += messages;
+        self.bytes += bytes;
+    }
+
+    fn elapsed(&self) -> Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::from_secs(0),
+        }
+    }
+}
+
+unsafe fn run(pipe: *mut czmq_sys::zsock_t, role: Role) {
+    // The pipe belongs to the ZActor on the other end
+    let pipe = ZSock::from_raw(pipe as *mut c_void, false);
+    if pipe.signal(0).is_err() {
+        return;
+    }
+
+    let mut poller = match ZPoller::new() {
+        Ok(poller) => poller,
+        Err(_) => return,
+    };
+    if poller.add(&pipe).is_err() {
+        return;
+    }
+
+    let mut bench = Bench {
+        role: role,
+        sock: None,
+        counts: Counts::default(),
+        waiting: None,
+    };
+
+    loop {
+        let timeout = bench.waiting.map_or(BENCH_TICK, |(_, until)| until.saturating_duration_since(Instant::now()).min(BENCH_TICK));
+        if poller.wait_timeout::<ZSock>(Some(timeout)).is_err() {
+            break;
+        }
+
+        if pipe.readable() {
+            let msg = match ZMsg::recv(&pipe) {
+                Ok(msg) => msg,
+                Err(_) => break,
+            };
+            // Anything we don't understand is taken to be $TERM
+            let command = match BenchCommand::decode(&msg) {
+                Ok(command) => command,
+                Err(_) => break,
+            };
+            if bench.handle(&pipe, &mut poller, command).is_err() {
+                break;
+            }
+        }
+
+        if bench.receive().is_err() || bench.answer_wait(&pipe).is_err() {
+            break;
+        }
+    }
+}
+
+impl Bench {
+    fn handle(&mut self, pipe: &ZSock, poller: &mut ZPoller, command: BenchCommand) -> Result<()> {
+        match command {
+            BenchCommand::Attach { socket_type, endpoints, serverish } => {
+                let error = match self.attach(&socket_type, &endpoints, serverish, poller) {
+                    Ok(()) => String::new(),
+                    Err(e) => e.to_string(),
+                };
+                let msg = ZMsg::new();
+                error.encode_field(&msg)?;
+                msg.send(pipe)
+            },
+            BenchCommand::Stats {} => self.reply_stats(pipe),
+            BenchCommand::Reset {} => {
+                self.counts = Counts::default();
+                pipe.signal(0)
+            },
+            BenchCommand::Wait { messages, timeout } => {
+                self.waiting = Some((messages, Instant::now() + Duration::from_millis(timeout)));
+                self.answer_wait(pipe)
+            },
+            BenchCommand::Flood { messages, size } => {
+                let (sent, bytes, elapsed) = self.flood(messages, size as usize);
+                reply(pipe, &[sent, bytes, elapsed.as_micros() as u64])
+            },
+            BenchCommand::Ping { messages, size } => {
+                let rtts = self.ping(messages, size as usize);
+                let micros = |d: Option<Duration>| d.map_or(0, |d| d.as_micros() as u64);
+                let mean = if rtts.is_empty() { None } else { Some(rtts.iter().sum::<Duration>() / rtts.len() as u32) };
+                reply(pipe, &[rtts.len() as u64, micros(rtts.iter().min().cloned()), micros(mean), micros(rtts.iter().max().cloned())])
+            },
+        }
+    }
+
+    fn attach(&mut self, socket_type: &str, endpoints: &str, serverish: bool, poller: &mut ZPoller) -> Result<()> {
+        let sock_type = match socket_type {
+            "DEALER" => SocketType::DEALER,
+            "PULL" => SocketType::PULL,
+            "PUSH" => SocketType::PUSH,
+            "ROUTER" => SocketType::ROUTER,
+            _ => return Err(Error::new(ErrorKind::InvalidArg, BenchError::Attach(socket_type.into()))),
+        };
+
+        let sock = ZSock::new(sock_type);
+        sock.set_sndtimeo(Some(BENCH_TIMEOUT));
+        sock.set_rcvtimeo(Some(BENCH_TIMEOUT));
+        sock.attach(&[endpoints], serverish)?;
+        poller.add(&sock)?;
+        self.sock = Some(sock);
+        Ok(())
+    }
+
+    // Drain whatever has arrived on the socket
+    fn receive(&mut self) -> Result<()> {
+        let sock = match self.sock {
+            Some(ref sock) => sock,
+            None => return Ok(()),
+        };
+
+        while sock.readable() {
+            let msg = ZMsg::recv(sock)?;
+            let bytes = msg.content_size() as u64;
+            match self.role {
+                // The routing id goes back with the message, so it
+                // finds its way home
+                Role::Echo => {
+                    msg.send(sock)?;
+                    self.counts.add(1, bytes, Instant::now());
+                },
+                Role::Sink => self.counts.add(1, bytes, Instant::now()),
+                // Echoes that missed their ping
+                Role::Flood => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reply_stats(&self, pipe: &ZSock) -> Result<()> {
+        reply(pipe, &[self.counts.messages, self.counts.bytes, self.counts.elapsed().as_micros() as u64])
+    }
+
+    fn answer_wait(&mut self, pipe: &ZSock) -> Result<()> {
+        match self.waiting {
+            Some((messages, until)) if self.counts.messages >= messages || Instant::now() >= until => {
+                self.waiting = None;
+                self.reply_stats(pipe)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn flood(&mut self, messages: u64, size: usize) -> (u64, u64, Duration) {
+        let payload = payload(size);
+        let start = Instant::now();
+        let mut sent = 0;
+
+        if let Some(ref sock) = self.sock {
+            while sent < messages {
+                let result = ZMsg::from_frames(&[&payload]).and_then(|msg| msg.send(sock));
+                if result.is_err() {
+                    break;
+                }
+                sent += 1;
+            }
+        }
+
+        self.counts.add(sent, sent * size as u64, start);
+        (sent, sent * size as u64, start.elapsed())
+    }
+
+    fn ping(&mut self, messages: u64, size: usize) -> Vec<Duration> {
+        let payload = payload(size);
+        let start = Instant::now();
+        let mut rtts = Vec::new();
+
+        if let Some(ref sock) = self.sock {
+            for _ in 0..messages {
+                let sent = Instant::now();
+                let result = ZMsg::from_frames(&[&payload])
+                    .and_then(|msg| msg.send(sock))
+                    .and_then(|_| ZMsg::recv(sock));
+                if result.is_err() {
+                    break;
+                }
+                rtts.push(sent.elapsed());
+            }
+        }
+
+        self.counts.add(rtts.len() as u64, (rtts.len() * size) as u64, start);
+        rtts
+    }
+}
+
+fn reply(pipe: &ZSock, fields: &[u64]) -> Result<()> {
+    let msg = ZMsg::new();
+    for field in fields {
+        field.encode_field(&msg)?;
+    }
+    msg.send(pipe)
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    Attach(String),
+    PingNeedsDealer,
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BenchError::Attach(ref e) => write!(f, "Could not attach actor socket: {}", e),
+            BenchError::PingNeedsDealer => write!(f, "Pinging needs a DEALER flood"),
+        }
+    }
+}
+
+impl error::Error for BenchError {
+    fn description(&self) -> &str {
+        match *self {
+            BenchError::Attach(_) => "Could not attach actor socket",
+            BenchError::PingNeedsDealer => "Pinging needs a DEALER flood",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+
+    #[test]
+    fn test_inproc_pair() {
+        ZSys::init();
+
+        let (server, client) = inproc_pair("test").unwrap();
+        client.send_str("moo").unwrap();
+        assert_eq!(server.recv_str().unwrap().unwrap(), "moo");
+        server.send_str("cow").unwrap();
+        assert_eq!(client.recv_str().unwrap().unwrap(), "cow");
+    }
+
+    #[test]
+    fn test_message() {
+        let mut msg = message(3, 300).unwrap();
+        assert_eq!(msg.size(), 3);
+        assert_eq!(msg.popbytes().unwrap().unwrap(), payload(300));
+    }
+
+    #[test]
+    fn test_flood_sink() {
+        ZSys::init();
+
+        let endpoint = endpoint("test_flood_sink");
+        let sink = SinkActor::new(&endpoint).unwrap();
+        let flood = FloodActor::new(&endpoint).unwrap();
+
+        let sent = flood.flood(100, 10).unwrap();
+        assert_eq!(sent.messages, 100);
+        assert_eq!(sent.bytes, 1000);
+
+        let received = sink.wait(100, Duration::from_secs(5)).unwrap();
+        assert_eq!(received.messages, 100);
+        assert_eq!(received.bytes, 1000);
+        assert_eq!(flood.stats().unwrap(), sent);
+
+        sink.reset().unwrap();
+        assert_eq!(sink.wait(1, Duration::from_millis(10)).unwrap(), BenchStats::default());
+        assert!(flood.ping(1, 10).is_err());
+    }
+
+    #[test]
+    fn test_echo_ping() {
+        ZSys::init();
+
+        let endpoint = endpoint("test_echo_ping");
+        let echo = EchoActor::new(&endpoint).unwrap();
+        let flood = FloodActor::dealer(&endpoint).unwrap();
+
+        let latency = flood.ping(10, 16).unwrap();
+        assert_eq!(latency.round_trips, 10);
+        assert!(latency.min <= latency.mean && latency.mean <= latency.max);
+        assert_eq!(echo.stats().unwrap().messages, 10);
+
+        assert!(EchoActor::new("moo://cow").is_err());
+    }
+}
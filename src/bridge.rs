@@ -0,0 +1,187 @@
+//! Module: czmq-bridge
+//!
+//! Forward messages both ways between two sockets on a background
+//! thread, like `zproxy` but for any pair of sockets, with optional
+//! hooks to rewrite or filter messages in each direction. Useful for
+//! adapters, e.g. between a legacy wire format and a new one, or
+//! from an inproc socket out onto the network.
+//!
+//! ```rust
+//! use czmq::{Bridge, ZSock, ZSys};
+//!
+//! ZSys::init();
+//!
+//! let inside = ZSock::new_pair("@inproc://bridge_example").unwrap();
+//! let outside = ZSock::new_pub("tcp://127.0.0.1:*").unwrap();
+//!
+//! let bridge = Bridge::builder(inside, outside)
+//!     .a_to_b(|msg| { msg.pushstr("v2").ok()?; Some(msg) })
+//!     .start()
+//!     .unwrap();
+//! ```
+
+use crate::{Result, ZMsg, ZPoller, ZSock};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How often the bridge checks whether it has been stopped
+const BRIDGE_TICK: Duration = Duration::from_millis(100);
+
+type Transform = Box<dyn FnMut(ZMsg) -> Option<ZMsg> + Send>;
+
+/// Forward between `a` and `b` untouched, until the returned `Bridge`
+/// is stopped or dropped.
+pub fn bridge(a: ZSock, b: ZSock) -> Result<Bridge> {
+    Bridge::builder(a, b).start()
+}
+
+pub struct Bridge {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<(ZSock, ZSock)>>,
+}
+
+impl Bridge {
+    /// Configure a bridge between `a` and `b`, which should already
+    /// be bound or connected. The bridge owns them while it runs, as
+    /// sockets can't be shared between threads.
+    pub fn builder(a: ZSock, b: ZSock) -> BridgeBuilder {
+        BridgeBuilder {
+            a: a,
+            b: b,
+            transforms: [None, None],
+        }
+    }
+
+    /// Stop forwarding and hand back the sockets, as `(a, b)`. Returns
+    /// `None` if a transform panicked, taking the sockets with it.
+    pub fn stop(mut self) -> Option<(ZSock, ZSock)> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+pub struct BridgeBuilder {
+    a: ZSock,
+    b: ZSock,
+    // Indexed by the socket messages come from
+    transforms: [Option<Transform>; 2],
+}
+
+impl BridgeBuilder {
+    /// Pass each message from `a` through `transform` before sending
+    /// it to `b`. Returning `None` drops the message.
+    pub fn a_to_b<F>(mut self, transform: F) -> BridgeBuilder
+        where F: FnMut(ZMsg) -> Option<ZMsg> + Send + 'static {
+        self.transforms[0] = Some(Box::new(transform));
+        self
+    }
+
+    /// Like `a_to_b()`, for messages from `b` to `a`.
+    pub fn b_to_a<F>(mut self, transform: F) -> BridgeBuilder
+        where F: FnMut(ZMsg) -> Option<ZMsg> + Send + 'static {
+        self.transforms[1] = Some(Box::new(transform));
+        self
+    }
+
+    pub fn start(self) -> Result<Bridge> {
+        let mut poller = ZPoller::new()?;
+        poller.add(&self.a)?;
+        poller.add(&self.b)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let mut transforms = self.transforms;
+        let socks = [self.a, self.b];
+
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                // As with a zproxy, a failed socket just stops the
+                // forwarding
+                if forward(&poller, &socks, &mut transforms).is_err() {
+                    break;
+                }
+            }
+
+            // The poller holds pointers to the sockets
+            drop(poller);
+            let [a, b] = socks;
+            (a, b)
+        });
+
+        Ok(Bridge {
+            stop: stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+fn forward(poller: &ZPoller, socks: &[ZSock; 2], transforms: &mut [Option<Transform>; 2]) -> Result<()> {
+    if poller.wait_timeout::<ZSock>(Some(BRIDGE_TICK))?.is_none() {
+        return Ok(());
+    }
+
+    for from in 0..2 {
+        while socks[from].readable() {
+            let msg = ZMsg::recv(&socks[from])?;
+            let msg = match transforms[from] {
+                Some(ref mut transform) => transform(msg),
+                None => Some(msg),
+            };
+
+            if let Some(msg) = msg {
+                msg.send(&socks[1 - from])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+
+    #[test]
+    fn test_bridge() {
+        ZSys::init();
+
+        let left = ZSock::new_pair("@inproc://bridge_test_left").unwrap();
+        let right = ZSock::new_pair("@inproc://bridge_test_right").unwrap();
+        let a = ZSock::new_pair(">inproc://bridge_test_left").unwrap();
+        let b = ZSock::new_pair(">inproc://bridge_test_right").unwrap();
+        left.set_rcvtimeo(Some(1000));
+        right.set_rcvtimeo(Some(1000));
+
+        let bridge = Bridge::builder(a, b)
+            .a_to_b(|mut msg| {
+                let s = msg.popstr()?.ok()?;
+                ZMsg::from_frames(&[s.to_uppercase()]).ok()
+            })
+            .b_to_a(|msg| if msg.size() > 1 { None } else { Some(msg) })
+            .start()
+            .unwrap();
+
+        left.send_str("moo").unwrap();
+        assert_eq!(right.recv_str().unwrap().unwrap(), "MOO");
+
+        ZMsg::from_frames(&["dropped", "on the floor"]).unwrap().send(&right).unwrap();
+        right.send_str("baa").unwrap();
+        assert_eq!(left.recv_str().unwrap().unwrap(), "baa");
+
+        let (a, _) = bridge.stop().unwrap();
+        a.send_str("direct").unwrap();
+        assert_eq!(left.recv_str().unwrap().unwrap(), "direct");
+    }
+}
@@ -0,0 +1,384 @@
+//! Module: czmq-bstar
+//!
+//! The Binary Star pattern: a primary and a backup server, of which
+//! one is active and serves clients while the other stands by. Each
+//! node publishes its state to the other over PUB/SUB once per
+//! heartbeat.
+//!
+//! Failover is decided by the clients rather than by the nodes. A
+//! passive node that gets a client request after its peer has gone
+//! quiet for two heartbeats takes over, as the client must have given
+//! up on the active node. While both nodes can see each other,
+//! requests to the passive node are dropped.
+//!
+//! ```text
+//! Primary --(peer is backup)--> Active
+//! Primary --(peer is active)--> Passive
+//! Backup  --(peer is active)--> Passive
+//! Passive --(peer is primary or backup)--> Active
+//! Passive --(client request, peer expired)--> Active
+//! ```
+
+use crate::{Error, ErrorKind, Result, SocketType, ZMsg, ZPoller, ZSock};
+use std::{cmp, error, fmt};
+use std::time::{Duration, Instant};
+
+const HEARTBEAT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BStarState {
+    /// A primary node waiting for its peer.
+    Primary,
+    /// A backup node waiting for its peer.
+    Backup,
+    /// Serving clients.
+    Active,
+    /// Standing by for the active peer to fail.
+    Passive,
+}
+
+impl BStarState {
+    fn code(self) -> &'static str {
+        match self {
+            BStarState::Primary => "1",
+            BStarState::Backup => "2",
+            BStarState::Active => "3",
+            BStarState::Passive => "4",
+        }
+    }
+
+    fn from_code(code: &[u8]) -> Option<BStarState> {
+        match code {
+            b"1" => Some(BStarState::Primary),
+            b"2" => Some(BStarState::Backup),
+            b"3" => Some(BStarState::Active),
+            b"4" => Some(BStarState::Passive),
+            _ => None,
+        }
+    }
+}
+
+enum Event {
+    Peer(BStarState),
+    ClientRequest,
+}
+
+/// A request received by `BStarNode::recv()`.
+#[derive(Debug)]
+pub struct BStarRequest {
+    /// The requesting client's address, to be passed back with the
+    /// reply.
+    pub client: Vec<u8>,
+    pub body: ZMsg,
+}
+
+/// One node of a Binary Star pair.
+pub struct BStarNode {
+    state: BStarState,
+    frontend: ZSock,
+    statepub: ZSock,
+    statesub: ZSock,
+    poller: ZPoller,
+    heartbeat: Duration,
+    heartbeat_at: Instant,
+    peer_expiry: Instant,
+}
+
+impl BStarNode {
+    /// Create a primary or backup node. Clients connect to a ROUTER
+    /// socket bound to `frontend`. The node publishes its state on
+    /// `local` and subscribes to its peer's state on `remote`.
+    pub fn new(primary: bool, frontend: &str, local: &str, remote: &str) -> Result<BStarNode> {
+        let frontend = ZSock::new_router(frontend)?;
+        let statepub = ZSock::new_pub(local)?;
+        let statesub = ZSock::new_sub(remote, Some(""))?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&frontend)?;
+        poller.add(&statesub)?;
+
+        Ok(BStarNode {
+            state: if primary { BStarState::Primary } else { BStarState::Backup },
+            frontend: frontend,
+            statepub: statepub,
+            statesub: statesub,
+            poller: poller,
+            heartbeat: HEARTBEAT,
+            heartbeat_at: Instant::now() + HEARTBEAT,
+            peer_expiry: Instant::now() + HEARTBEAT * 2,
+        })
+    }
+
+    /// How often to publish state to the peer, which is presumed dead
+    /// after two silent intervals. Defaults to 1 second, and must
+    /// match the peer's setting.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+        self.heartbeat_at = Instant::now() + heartbeat;
+        self.peer_expiry = Instant::now() + heartbeat * 2;
+    }
+
+    pub fn state(&self) -> BStarState {
+        self.state
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state == BStarState::Active
+    }
+
+    /// Wait for the next client request that this node should serve,
+    /// exchanging state with the peer meanwhile. Returns `None` if no
+    /// such request arrives within `timeout`, or waits forever if
+    /// `timeout` is `None`.
+    ///
+    /// Fails if both nodes are active or both are passive, which
+    /// means the pair is misconfigured.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<BStarRequest>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let now = Instant::now();
+            let mut wait = self.heartbeat_at.checked_duration_since(now).unwrap_or_default();
+            if let Some(deadline) = deadline {
+                wait = cmp::min(wait, deadline.checked_duration_since(now).unwrap_or_default());
+            }
+
+            if self.poller.wait_timeout::<ZSock>(Some(wait))?.is_some() {
+                if self.statesub.readable() {
+                    let msg = ZMsg::recv(&self.statesub)?;
+                    if let Some(peer) = msg.first().and_then(|f| BStarState::from_code(f.as_bytes())) {
+                        self.execute(Event::Peer(peer))?;
+                        self.peer_expiry = Instant::now() + self.heartbeat * 2;
+                    }
+                }
+
+                if self.frontend.readable() {
+                    let mut msg = ZMsg::recv(&self.frontend)?;
+                    if self.execute(Event::ClientRequest)? {
+                        if let (Some(client), Some(_)) = (msg.pop(), msg.pop()) {
+                            return Ok(Some(BStarRequest {
+                                client: client.as_bytes().to_vec(),
+                                body: msg,
+                            }));
+                        }
+                    }
+                }
+            }
+
+            if Instant::now() >= self.heartbeat_at {
+                ZMsg::from_frames(&[self.state.code()])?.send(&self.statepub)?;
+                self.heartbeat_at = Instant::now() + self.heartbeat;
+            }
+
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Reply to a request from `client`.
+    pub fn reply(&self, client: &[u8], body: ZMsg) -> Result<()> {
+        body.pushbytes(&[])?;
+        body.pushbytes(client)?;
+        body.send(&self.frontend)
+    }
+
+    // Returns true if a client request should be served.
+    fn execute(&mut self, event: Event) -> Result<bool> {
+        match (self.state, event) {
+            (BStarState::Primary, Event::Peer(BStarState::Backup)) => self.state = BStarState::Active,
+            (BStarState::Primary, Event::Peer(BStarState::Active)) => self.state = BStarState::Passive,
+            (BStarState::Primary, Event::ClientRequest) | (BStarState::Passive, Event::ClientRequest) => {
+                if Instant::now() < self.peer_expiry {
+                    return Ok(false);
+                }
+                self.state = BStarState::Active;
+            },
+            (BStarState::Backup, Event::Peer(BStarState::Active)) => self.state = BStarState::Passive,
+            (BStarState::Backup, Event::ClientRequest) => return Ok(false),
+            (BStarState::Active, Event::Peer(BStarState::Active)) => {
+                return Err(Error::new(ErrorKind::InvalidArg, BStarError::DualActive));
+            },
+            (BStarState::Passive, Event::Peer(BStarState::Primary)) |
+            (BStarState::Passive, Event::Peer(BStarState::Backup)) => self.state = BStarState::Active,
+            (BStarState::Passive, Event::Peer(BStarState::Passive)) => {
+                return Err(Error::new(ErrorKind::InvalidArg, BStarError::DualPassive));
+            },
+            _ => (),
+        }
+
+        Ok(self.state == BStarState::Active)
+    }
+}
+
+/// Sends requests to a Binary Star pair over REQ, failing over to the
+/// other node when one doesn't answer.
+pub struct BStarClient {
+    endpoints: [String; 2],
+    current: usize,
+    sock: ZSock,
+    poller: ZPoller,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl BStarClient {
+    pub fn new(primary: &str, backup: &str) -> Result<BStarClient> {
+        let (sock, poller) = connect(primary)?;
+
+        Ok(BStarClient {
+            endpoints: [primary.to_string(), backup.to_string()],
+            current: 0,
+            sock: sock,
+            poller: poller,
+            timeout: Duration::from_millis(2500),
+            retries: 3,
+        })
+    }
+
+    /// How long to wait for each reply. Should be more than twice the
+    /// servers' heartbeat, so that the passive node will take over.
+    /// Defaults to 2.5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// How many times to resend a request that timed out, switching
+    /// node each time. Defaults to 3.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// The endpoint of the node requests are currently sent to.
+    pub fn server(&self) -> &str {
+        &self.endpoints[self.current]
+    }
+
+    /// Send a request and wait for its reply.
+    pub fn request(&mut self, body: ZMsg) -> Result<ZMsg> {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                self.current = 1 - self.current;
+                self.poller.remove(&self.sock)?;
+                let (sock, poller) = connect(&self.endpoints[self.current])?;
+                self.sock = sock;
+                self.poller = poller;
+            }
+
+            body.dup()?.send(&self.sock)?;
+
+            if self.poller.wait_timeout::<ZSock>(Some(self.timeout))?.is_some() {
+                return ZMsg::recv(&self.sock);
+            }
+        }
+
+        Err(Error::new(ErrorKind::Timeout, BStarError::NoReply))
+    }
+}
+
+fn connect(endpoint: &str) -> Result<(ZSock, ZPoller)> {
+    let sock = ZSock::new(SocketType::REQ);
+    sock.set_linger(0);
+    sock.connect(endpoint)?;
+
+    let mut poller = ZPoller::new()?;
+    poller.add(&sock)?;
+    Ok((sock, poller))
+}
+
+#[derive(Debug)]
+pub enum BStarError {
+    DualActive,
+    DualPassive,
+    NoReply,
+}
+
+impl fmt::Display for BStarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BStarError::DualActive => write!(f, "Both nodes are active"),
+            BStarError::DualPassive => write!(f, "Both nodes are passive"),
+            BStarError::NoReply => write!(f, "Neither node replied in time"),
+        }
+    }
+}
+
+impl error::Error for BStarError {
+    fn description(&self) -> &str {
+        match *self {
+            BStarError::DualActive => "Both nodes are active",
+            BStarError::DualPassive => "Both nodes are passive",
+            BStarError::NoReply => "Neither node replied in time",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZMsg, ZSock, ZSys};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn client(endpoint: &str) -> ZSock {
+        let sock = ZSock::new(SocketType::REQ);
+        sock.set_linger(0);
+        sock.connect(endpoint).unwrap();
+        sock
+    }
+
+    #[test]
+    fn test_failover() {
+        ZSys::init();
+
+        let mut primary = BStarNode::new(true, "inproc://bstar_test_primary", "inproc://bstar_test_primary_state", "inproc://bstar_test_backup_state").unwrap();
+        let mut backup = BStarNode::new(false, "inproc://bstar_test_backup", "inproc://bstar_test_backup_state", "inproc://bstar_test_primary_state").unwrap();
+        primary.set_heartbeat(TICK);
+        backup.set_heartbeat(TICK);
+
+        for _ in 0..5 {
+            assert!(primary.recv(Some(TICK)).unwrap().is_none());
+            assert!(backup.recv(Some(TICK)).unwrap().is_none());
+        }
+        assert_eq!(primary.state(), BStarState::Active);
+        assert_eq!(backup.state(), BStarState::Passive);
+
+        // The passive node ignores clients while the primary is alive
+        let early = client("inproc://bstar_test_backup");
+        ZMsg::from_frames(&[b"moo"]).unwrap().send(&early).unwrap();
+        primary.recv(Some(TICK)).unwrap();
+        assert!(backup.recv(Some(TICK)).unwrap().is_none());
+
+        drop(primary);
+        thread::sleep(TICK * 3);
+
+        // Once the primary is gone, a client request makes the backup
+        // take over
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_backup = stop.clone();
+        let server = thread::spawn(move || {
+            while !stop_backup.load(Ordering::SeqCst) {
+                if let Some(req) = backup.recv(Some(TICK)).unwrap() {
+                    backup.reply(&req.client, req.body).unwrap();
+                }
+            }
+            backup.state()
+        });
+
+        let mut client = BStarClient::new("inproc://bstar_test_primary", "inproc://bstar_test_backup").unwrap();
+        client.set_timeout(TICK * 4);
+
+        let mut reply = client.request(ZMsg::from_frames(&[b"oink"]).unwrap()).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "oink");
+        assert_eq!(client.server(), "inproc://bstar_test_backup");
+
+        stop.store(true, Ordering::SeqCst);
+        assert_eq!(server.join().unwrap(), BStarState::Active);
+    }
+}
@@ -0,0 +1,358 @@
+//! Module: czmq-bus
+//!
+//! Typed publish-subscribe, enabled with the `serde` feature. Messages
+//! on a bus are values of a single type, usually an enum, that
+//! implements `Topic` to name the topic each value belongs to. A
+//! message is sent as its topic name, followed by the value encoded
+//! with `serde_zmsg`, so subscriptions are filtered by the publisher
+//! as for any other PUB socket.
+//!
+//! Subscribers subscribe by topic key rather than by string, so a
+//! typo is a compile error, and a subscriber never sees values for
+//! topics it didn't ask for, even when one topic name is a prefix of
+//! another.
+//!
+//! `LastValueCache` sits between publishers and subscribers and
+//! replays the latest message on each topic to new subscribers, so
+//! late joiners get the current state without waiting for the next
+//! update.
+
+use crate::{serde_zmsg, Result, SocketType, Subscription, ZMsg, ZPoller, ZSock};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+/// A type that can be sent over a bus.
+pub trait Topic {
+    /// Identifies a topic, typically a fieldless enum with one variant
+    /// per variant of the message type.
+    type Key: Copy + Eq + Hash + fmt::Debug;
+
+    /// The topic this value belongs to.
+    fn key(&self) -> Self::Key;
+
+    /// The topic name sent on the wire.
+    fn name(key: Self::Key) -> &'static str;
+}
+
+/// The publishing end of a bus.
+pub struct Bus<T> {
+    sock: ZSock,
+    marker: PhantomData<T>,
+}
+
+impl<T: Topic + Serialize> Bus<T> {
+    /// Bind a publisher to `endpoint`.
+    pub fn new(endpoint: &str) -> Result<Bus<T>> {
+        Ok(Bus {
+            sock: ZSock::new_pub(endpoint)?,
+            marker: PhantomData,
+        })
+    }
+
+    /// Connect a publisher to `endpoint`, such as the frontend of a
+    /// `LastValueCache`.
+    pub fn connect(endpoint: &str) -> Result<Bus<T>> {
+        let sock = ZSock::new(SocketType::PUB);
+        sock.connect(endpoint)?;
+
+        Ok(Bus {
+            sock: sock,
+            marker: PhantomData,
+        })
+    }
+
+    /// Publish `value` on its topic.
+    pub fn publish(&self, value: &T) -> Result<()> {
+        let msg = serde_zmsg::to_msg(value)?;
+        msg.pushstr(T::name(value.key()))?;
+        msg.send(&self.sock)
+    }
+}
+
+/// The subscribing end of a bus.
+pub struct BusSubscriber<T: Topic> {
+    sock: ZSock,
+    poller: ZPoller,
+    subscriptions: HashSet<T::Key>,
+    all: bool,
+}
+
+impl<T: Topic + DeserializeOwned> BusSubscriber<T> {
+    /// Connect a subscriber to `endpoint`. It receives nothing until
+    /// it subscribes to something.
+    pub fn new(endpoint: &str) -> Result<BusSubscriber<T>> {
+        let sock = ZSock::new(SocketType::SUB);
+        sock.connect(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(BusSubscriber {
+            sock: sock,
+            poller: poller,
+            subscriptions: HashSet::new(),
+            all: false,
+        })
+    }
+
+    /// Receive values on topic `key`. Subscribing twice is harmless.
+    pub fn subscribe(&mut self, key: T::Key) {
+        if self.subscriptions.insert(key) {
+            self.sock.set_subscribe(T::name(key));
+        }
+    }
+
+    /// Stop receiving values on topic `key`.
+    pub fn unsubscribe(&mut self, key: T::Key) {
+        if self.subscriptions.remove(&key) {
+            self.sock.set_unsubscribe(T::name(key));
+        }
+    }
+
+    /// Receive values on every topic, including ones not subscribed
+    /// to individually.
+    pub fn subscribe_all(&mut self) {
+        if !self.all {
+            self.sock.set_subscribe("");
+            self.all = true;
+        }
+    }
+
+    /// Stop receiving values on topics not subscribed to
+    /// individually.
+    pub fn unsubscribe_all(&mut self) {
+        if self.all {
+            self.sock.set_unsubscribe("");
+            self.all = false;
+        }
+    }
+
+    /// Whether values on topic `key` are received.
+    pub fn is_subscribed(&self, key: T::Key) -> bool {
+        self.all || self.subscriptions.contains(&key)
+    }
+
+    /// Wait for the next value on a subscribed topic, or return `None`
+    /// if none arrives within `timeout`. Waits forever if `timeout` is
+    /// `None`.
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Option<T>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let wait = deadline.map(|d| d.checked_duration_since(Instant::now()).unwrap_or_default());
+
+            if self.poller.wait_timeout::<ZSock>(wait)?.is_none() {
+                if deadline.is_some() {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let mut msg = ZMsg::recv(&self.sock)?;
+            let topic = match msg.pop() {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            let value: T = serde_zmsg::from_msg(&msg)?;
+
+            // The socket filters by prefix, so "temp" also matches
+            // "temperature"
+            if topic.as_bytes() == T::name(value.key()).as_bytes() && self.is_subscribed(value.key()) {
+                return Ok(Some(value));
+            }
+        }
+    }
+}
+
+/// Forwards messages from publishers to subscribers, replaying the
+/// last message on each topic to new subscribers.
+///
+/// Replayed messages go out on the XPUB socket, so any existing
+/// subscriber to the same topic gets them again too.
+pub struct LastValueCache {
+    frontend: ZSock,
+    backend: ZSock,
+    poller: ZPoller,
+    cache: HashMap<Vec<u8>, ZMsg>,
+}
+
+impl LastValueCache {
+    /// Subscribe to everything published on `frontend`, and bind an
+    /// XPUB socket to `backend` for subscribers.
+    pub fn new(frontend: &str, backend: &str) -> Result<LastValueCache> {
+        let frontend = ZSock::new_sub(frontend, Some(""))?;
+        let backend = ZSock::new_xpub(backend)?;
+        // Pass on every subscription, not just the first for each
+        // topic, so every new subscriber gets the cached values
+        backend.set_xpub_verbose(true);
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&frontend)?;
+        poller.add(&backend)?;
+
+        Ok(LastValueCache {
+            frontend: frontend,
+            backend: backend,
+            poller: poller,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// The number of topics with a cached value.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Forward messages until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            self.poll(Duration::from_secs(1))?;
+        }
+    }
+
+    /// Wait up to `timeout` for a message or subscription and handle
+    /// it.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        if self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(());
+        }
+
+        if self.frontend.readable() {
+            let msg = ZMsg::recv(&self.frontend)?;
+            if let Some(topic) = msg.first() {
+                self.cache.insert(topic.as_bytes().to_vec(), msg.dup()?);
+            }
+            msg.send(&self.backend)?;
+        }
+
+        if self.backend.readable() {
+            let msg = ZMsg::recv(&self.backend)?;
+            let sub = match msg.first().and_then(|frame| Subscription::decode(frame.as_bytes())) {
+                Some(sub) => sub,
+                None => return Ok(()),
+            };
+
+            if sub.subscribe {
+                for (topic, msg) in &self.cache {
+                    if topic.starts_with(&sub.topic) {
+                        msg.dup()?.send(&self.backend)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+    use serde::Deserialize;
+    use std::thread;
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Weather {
+        Temp(i32),
+        Temperature(i32),
+        Wind { speed: u32 },
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum WeatherTopic {
+        Temp,
+        Temperature,
+        Wind,
+    }
+
+    impl Topic for Weather {
+        type Key = WeatherTopic;
+
+        fn key(&self) -> WeatherTopic {
+            match *self {
+                Weather::Temp(_) => WeatherTopic::Temp,
+                Weather::Temperature(_) => WeatherTopic::Temperature,
+                Weather::Wind { .. } => WeatherTopic::Wind,
+            }
+        }
+
+        fn name(key: WeatherTopic) -> &'static str {
+            match key {
+                WeatherTopic::Temp => "temp",
+                WeatherTopic::Temperature => "temperature",
+                WeatherTopic::Wind => "wind",
+            }
+        }
+    }
+
+    #[test]
+    fn test_subscribe() {
+        ZSys::init();
+
+        let bus = Bus::<Weather>::new("inproc://bus_test_subscribe").unwrap();
+        let mut sub = BusSubscriber::<Weather>::new("inproc://bus_test_subscribe").unwrap();
+        sub.subscribe(WeatherTopic::Temp);
+        sub.subscribe(WeatherTopic::Wind);
+        thread::sleep(TICK);
+
+        bus.publish(&Weather::Temperature(20)).unwrap();
+        bus.publish(&Weather::Temp(21)).unwrap();
+        bus.publish(&Weather::Wind { speed: 5 }).unwrap();
+
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), Some(Weather::Temp(21)));
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), Some(Weather::Wind { speed: 5 }));
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), None);
+
+        sub.unsubscribe(WeatherTopic::Wind);
+        assert!(!sub.is_subscribed(WeatherTopic::Wind));
+        thread::sleep(TICK);
+
+        bus.publish(&Weather::Wind { speed: 6 }).unwrap();
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), None);
+
+        sub.subscribe_all();
+        thread::sleep(TICK);
+
+        bus.publish(&Weather::Temperature(22)).unwrap();
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), Some(Weather::Temperature(22)));
+    }
+
+    #[test]
+    fn test_last_value_cache() {
+        ZSys::init();
+
+        let bus = Bus::<Weather>::new("inproc://bus_test_lvc_front").unwrap();
+        let mut lvc = LastValueCache::new("inproc://bus_test_lvc_front", "inproc://bus_test_lvc_back").unwrap();
+        thread::sleep(TICK);
+
+        bus.publish(&Weather::Temp(18)).unwrap();
+        bus.publish(&Weather::Temp(19)).unwrap();
+        bus.publish(&Weather::Wind { speed: 3 }).unwrap();
+        for _ in 0..3 {
+            lvc.poll(TICK).unwrap();
+        }
+        assert_eq!(lvc.len(), 2);
+
+        // A late subscriber gets the current temperature straight away
+        let mut sub = BusSubscriber::<Weather>::new("inproc://bus_test_lvc_back").unwrap();
+        sub.subscribe(WeatherTopic::Temp);
+        lvc.poll(TICK).unwrap();
+
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), Some(Weather::Temp(19)));
+        assert_eq!(sub.recv(Some(TICK)).unwrap(), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! Module: czmq-capability
+//!
+//! Runtime checks for features that depend on how, and against which
+//! versions, CZMQ and libzmq were built. Code that needs one calls
+//! `require!(Capability::...)`, which returns an
+//! `ErrorKind::Unsupported` error if it's missing, rather than
+//! failing to link or handing CZMQ something it will assert on.
+//!
+//! `capabilities()` reports what the linked libraries support, e.g.
+//! for a startup log line or to skip tests.
+
+use crate::{Error, ErrorKind, Result, ZSys};
+use std::{error, fmt};
+use std::os::raw::c_int;
+
+/// Return an `ErrorKind::Unsupported` error from the enclosing fn if
+/// `capability` is missing.
+macro_rules! require {
+    ($capability:expr) => {
+        $crate::capability::require($capability)?
+    };
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Version {
+    pub major: i32,
+    pub minor: i32,
+    pub patch: i32,
+}
+
+impl Version {
+    fn new(major: i32, minor: i32, patch: i32) -> Version {
+        Version {
+            major: major,
+            minor: minor,
+            patch: patch,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Capability {
+    /// libzmq's draft APIs, like SERVER and RADIO sockets.
+    Draft,
+    Curve,
+    Gssapi,
+    Ipc,
+    Norm,
+    Pgm,
+    Tipc,
+    Vmci,
+    Ws,
+    Wss,
+    /// `ZCert::unset_meta()`, a CZMQ draft.
+    CertUnsetMeta,
+    /// `ZMonitorEvents::Handshake*`, which need libzmq 4.3 and CZMQ
+    /// 4.2.
+    MonitorHandshake,
+}
+
+// Every capability, in the order `capabilities()` lists them
+const ALL: &[Capability] = &[
+    Capability::Draft,
+    Capability::Curve,
+    Capability::Gssapi,
+    Capability::Ipc,
+    Capability::Norm,
+    Capability::Pgm,
+    Capability::Tipc,
+    Capability::Vmci,
+    Capability::Ws,
+    Capability::Wss,
+    Capability::CertUnsetMeta,
+    Capability::MonitorHandshake,
+];
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Capability::Draft => "draft",
+            Capability::Curve => "curve",
+            Capability::Gssapi => "gssapi",
+            Capability::Ipc => "ipc",
+            Capability::Norm => "norm",
+            Capability::Pgm => "pgm",
+            Capability::Tipc => "tipc",
+            Capability::Vmci => "vmci",
+            Capability::Ws => "ws",
+            Capability::Wss => "wss",
+            Capability::CertUnsetMeta => "cert_unset_meta",
+            Capability::MonitorHandshake => "monitor_handshake",
+        }
+    }
+
+    pub(crate) fn is_available(&self) -> bool {
+        let (czmq, zmq) = versions();
+        self.available(czmq, zmq)
+    }
+
+    fn available(&self, czmq: Version, zmq: Version) -> bool {
+        match *self {
+            // Draft symbols are only linked with the `draft` feature
+            Capability::CertUnsetMeta => cfg!(feature = "draft"),
+            Capability::MonitorHandshake => zmq >= Version::new(4, 3, 0) && czmq >= Version::new(4, 2, 0),
+            _ => ZSys::has(self.name()),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What the linked CZMQ and libzmq support.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Capabilities {
+    pub czmq_version: Version,
+    pub zmq_version: Version,
+    available: Vec<Capability>,
+}
+
+impl Capabilities {
+    pub fn has(&self, capability: Capability) -> bool {
+        self.available.contains(&capability)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        self.available.iter().cloned()
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CZMQ {}, libzmq {}", self.czmq_version, self.zmq_version)?;
+        for (i, capability) in self.available.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { ": " } else { ", " }, capability)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn capabilities() -> Capabilities {
+    let (czmq, zmq) = versions();
+
+    Capabilities {
+        czmq_version: czmq,
+        zmq_version: zmq,
+        available: ALL.iter().cloned().filter(|c| c.available(czmq, zmq)).collect(),
+    }
+}
+
+fn versions() -> (Version, Version) {
+    let (mut major, mut minor, mut patch): (c_int, c_int, c_int) = (0, 0, 0);
+    unsafe { czmq_sys::zsys_version(&mut major, &mut minor, &mut patch) };
+    let czmq = Version::new(major, minor, patch);
+
+    unsafe { czmq_sys::zmq_version(&mut major, &mut minor, &mut patch) };
+    (czmq, Version::new(major, minor, patch))
+}
+
+/// Fail with `ErrorKind::Unsupported` if `capability` is missing.
+/// Use `require!` rather than calling this directly.
+pub(crate) fn require(capability: Capability) -> Result<()> {
+    let (czmq, zmq) = versions();
+
+    if capability.available(czmq, zmq) {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::Unsupported, CapabilityError::Missing(capability, czmq, zmq)))
+    }
+}
+
+#[derive(Debug)]
+pub enum CapabilityError {
+    Missing(Capability, Version, Version),
+}
+
+impl fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CapabilityError::Missing(c, czmq, zmq) => write!(f, "{} is not available with CZMQ {} and libzmq {}", c, czmq, zmq),
+        }
+    }
+}
+
+impl error::Error for CapabilityError {
+    fn description(&self) -> &str {
+        match *self {
+            CapabilityError::Missing(..) => "Capability is not available",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn needs(capability: Capability) -> Result<()> {
+        require!(capability);
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let caps = capabilities();
+        assert!(caps.czmq_version >= Version::new(4, 0, 0));
+        assert!(caps.to_string().starts_with(&format!("CZMQ {}, libzmq {}", caps.czmq_version, caps.zmq_version)));
+        assert_eq!(caps.has(Capability::Ipc), ZSys::has("ipc"));
+        assert_eq!(caps.has(Capability::CertUnsetMeta), cfg!(feature = "draft"));
+
+        for &capability in ALL {
+            match needs(capability) {
+                Ok(()) => assert!(caps.has(capability)),
+                Err(e) => {
+                    assert!(!caps.has(capability));
+                    assert_eq!(e.kind(), ErrorKind::Unsupported);
+                },
+            }
+        }
+    }
+}
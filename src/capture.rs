@@ -0,0 +1,310 @@
+//! Module: czmq-capture
+//!
+//! Record streams of messages to a file and replay them later, for
+//! protocol debugging and load testing.
+//!
+//! A capture file starts with the magic bytes `ZCAP` and a version
+//! byte, followed by one record per message. Each record is the
+//! message's offset from the start of the capture in microseconds
+//! (8 bytes, big endian), the length of the encoded message (4 bytes,
+//! big endian), then the message as encoded by `ZMsg::encode()`.
+//!
+//! A `Tap` can record pub-sub or ROUTER-DEALER traffic as it passes
+//! with `Tap::record()`. Replays can be filtered with
+//! `CaptureReader::replay_filtered()`, e.g. to send only one topic.
+
+use crate::{Error, ErrorKind, Result, SocketLike, ZMsg};
+use std::{error, fmt, thread};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"ZCAP";
+const VERSION: u8 = 1;
+
+/// Writes timestamped messages to a capture stream.
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+    start: Instant,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<CaptureWriter<BufWriter<File>>> {
+        let file = File::create(path).map_err(|e| Error::new(ErrorKind::InvalidPath, e))?;
+        CaptureWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Write the capture header to `inner` and start the clock.
+    pub fn new(mut inner: W) -> Result<CaptureWriter<W>> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&[VERSION])?;
+
+        Ok(CaptureWriter {
+            inner: inner,
+            start: Instant::now(),
+        })
+    }
+
+    /// Record `msg` at the time elapsed since the writer was created.
+    pub fn write(&mut self, msg: &ZMsg) -> Result<()> {
+        let offset = self.start.elapsed();
+        self.write_at(offset, msg)
+    }
+
+    /// Record `msg` at an explicit offset from the start of the
+    /// capture.
+    pub fn write_at(&mut self, offset: Duration, msg: &ZMsg) -> Result<()> {
+        let encoded = msg.encode()?;
+        let bytes = encoded.as_bytes();
+        if bytes.len() > u32::max_value() as usize {
+            return Err(Error::new(ErrorKind::InvalidArg, CaptureError::TooLarge));
+        }
+
+        let micros = offset.as_secs() * 1_000_000 + u64::from(offset.subsec_micros());
+        self.inner.write_all(&micros.to_be_bytes())?;
+        self.inner.write_all(&(bytes.len() as u32).to_be_bytes())?;
+        self.inner.write_all(bytes)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.inner.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// A message read back from a capture stream.
+#[derive(Debug)]
+pub struct CaptureRecord {
+    /// Time since the start of the capture.
+    pub offset: Duration,
+    pub msg: ZMsg,
+}
+
+/// Reads messages from a capture stream.
+pub struct CaptureReader<R: Read> {
+    inner: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CaptureReader<BufReader<File>>> {
+        let file = File::open(path).map_err(|e| Error::new(ErrorKind::InvalidPath, e))?;
+        CaptureReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Read and check the capture header.
+    pub fn new(mut inner: R) -> Result<CaptureReader<R>> {
+        let mut header = [0; 5];
+        inner.read_exact(&mut header).map_err(truncated)?;
+
+        if &header[..4] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidArg, CaptureError::BadHeader));
+        }
+        if header[4] != VERSION {
+            return Err(Error::new(ErrorKind::InvalidArg, CaptureError::UnsupportedVersion(header[4])));
+        }
+
+        Ok(CaptureReader { inner: inner })
+    }
+
+    /// Read the next record, or `None` at the end of the stream.
+    pub fn read(&mut self) -> Result<Option<CaptureRecord>> {
+        let mut micros = [0; 8];
+        match self.inner.read_exact(&mut micros[..1]) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        self.inner.read_exact(&mut micros[1..]).map_err(truncated)?;
+
+        let mut len = [0; 4];
+        self.inner.read_exact(&mut len).map_err(truncated)?;
+
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        self.inner.read_exact(&mut bytes).map_err(truncated)?;
+
+        Ok(Some(CaptureRecord {
+            offset: Duration::from_micros(u64::from_be_bytes(micros)),
+            msg: ZMsg::decode_bytes(&bytes)?,
+        }))
+    }
+
+    /// Send every remaining record to `dest`, keeping the original
+    /// spacing between messages divided by `speed`. A speed of 1.0
+    /// replays in real time, 2.0 twice as fast, and
+    /// `f64::INFINITY` sends everything without waiting. Returns the
+    /// number of messages sent.
+    pub fn replay<D: SocketLike>(&mut self, dest: D, speed: f64) -> Result<usize> {
+        self.replay_filtered(dest, speed, |_| true)
+    }
+
+    /// Like `replay()`, but only send messages for which `filter`
+    /// returns true. Skipped messages still take up their time, so the
+    /// spacing of those that are sent is kept.
+    pub fn replay_filtered<D, F>(&mut self, mut dest: D, speed: f64, mut filter: F) -> Result<usize>
+        where D: SocketLike, F: FnMut(&ZMsg) -> bool {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(Error::new(ErrorKind::InvalidArg, CaptureError::InvalidSpeed));
+        }
+
+        let start = Instant::now();
+        let mut sent = 0;
+
+        while let Some(record) = self.read()? {
+            if !filter(&record.msg) {
+                continue;
+            }
+
+            if speed.is_finite() {
+                let due = Duration::from_secs_f64(record.offset.as_secs_f64() / speed);
+                let elapsed = start.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+            }
+
+            record.msg.send(&mut dest)?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Result<CaptureRecord>> {
+        match self.read() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn truncated(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        Error::new(ErrorKind::InvalidArg, CaptureError::Truncated)
+    } else {
+        e.into()
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    BadHeader,
+    InvalidSpeed,
+    TooLarge,
+    Truncated,
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaptureError::BadHeader => write!(f, "Not a capture file"),
+            CaptureError::InvalidSpeed => write!(f, "Replay speed must be greater than zero"),
+            CaptureError::TooLarge => write!(f, "Encoded message is too large to capture"),
+            CaptureError::Truncated => write!(f, "Capture file is truncated"),
+            CaptureError::UnsupportedVersion(v) => write!(f, "Unsupported capture file version: {}", v),
+        }
+    }
+}
+
+impl error::Error for CaptureError {
+    fn description(&self) -> &str {
+        match *self {
+            CaptureError::BadHeader => "Not a capture file",
+            CaptureError::InvalidSpeed => "Replay speed must be greater than zero",
+            CaptureError::TooLarge => "Encoded message is too large to capture",
+            CaptureError::Truncated => "Capture file is truncated",
+            CaptureError::UnsupportedVersion(_) => "Unsupported capture file version",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZSock, ZSys};
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    fn capture() -> Vec<u8> {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer.write_at(Duration::from_millis(0), &ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap()).unwrap();
+        writer.write_at(Duration::from_millis(50), &ZMsg::from_frames(&[b"baa"]).unwrap()).unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut reader = CaptureReader::new(Cursor::new(capture())).unwrap();
+
+        let record = reader.read().unwrap().unwrap();
+        assert_eq!(record.offset, Duration::from_millis(0));
+        assert_eq!(record.msg, ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap());
+
+        let mut record = reader.read().unwrap().unwrap();
+        assert_eq!(record.offset, Duration::from_millis(50));
+        assert_eq!(record.msg.popstr().unwrap().unwrap(), "baa");
+
+        assert!(reader.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_bad_input() {
+        assert!(CaptureReader::new(Cursor::new(b"ZMSG\x01".to_vec())).is_err());
+        assert!(CaptureReader::new(Cursor::new(b"ZCAP\x09".to_vec())).is_err());
+
+        let mut bytes = capture();
+        bytes.pop();
+        let results: Vec<_> = CaptureReader::new(Cursor::new(bytes)).unwrap().collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_replay() {
+        ZSys::init();
+
+        let server = ZSock::new_pull("@inproc://capture_test_replay").unwrap();
+        let client = ZSock::new_push(">inproc://capture_test_replay").unwrap();
+
+        let mut reader = CaptureReader::new(Cursor::new(capture())).unwrap();
+        assert!(reader.replay(&client, 0.0).is_err());
+
+        let start = Instant::now();
+        assert_eq!(reader.replay(&client, 2.0).unwrap(), 2);
+        assert!(start.elapsed() >= Duration::from_millis(25));
+
+        assert_eq!(ZMsg::recv(&server).unwrap().size(), 2);
+        assert_eq!(ZMsg::recv(&server).unwrap().popstr().unwrap().unwrap(), "baa");
+    }
+
+    #[test]
+    fn test_replay_filtered() {
+        ZSys::init();
+
+        let server = ZSock::new_pull("@inproc://capture_test_replay_filtered").unwrap();
+        let client = ZSock::new_push(">inproc://capture_test_replay_filtered").unwrap();
+
+        let mut reader = CaptureReader::new(Cursor::new(capture())).unwrap();
+        let sent = reader.replay_filtered(&client, f64::INFINITY, |msg| msg.iter().next() == Some(&b"baa"[..])).unwrap();
+        assert_eq!(sent, 1);
+        assert_eq!(ZMsg::recv(&server).unwrap().popstr().unwrap().unwrap(), "baa");
+    }
+}
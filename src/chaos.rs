@@ -0,0 +1,360 @@
+//! Module: czmq-chaos
+//!
+//! Inject network faults between two sockets, for testing heartbeat,
+//! retry and reconnection logic against realistic failures. A
+//! `ChaosProxy` forwards messages in both directions, like a
+//! `zproxy`, but drops, delays, duplicates or reorders them according
+//! to a `ChaosPolicy`.
+//!
+//! ```rust
+//! use czmq::{ChaosPolicy, ChaosProxy, ZSock, ZSys};
+//! use std::time::Duration;
+//!
+//! ZSys::init();
+//!
+//! // Clients connect to the proxy, which forwards to the server
+//! let frontend = ZSock::new_router("inproc://chaos_example").unwrap();
+//! let backend = ZSock::new_dealer("inproc://chaos_example_server").unwrap();
+//!
+//! let policy = ChaosPolicy::new()
+//!     .drop(0.1)
+//!     .delay(Duration::from_millis(5), Duration::from_millis(50));
+//! let proxy = ChaosProxy::new(frontend, backend, policy).unwrap();
+//! ```
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZPoller, ZSock};
+use crate::rng::Rng;
+use std::{error, fmt};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How often the proxy checks whether it has been stopped
+const CHAOS_TICK: Duration = Duration::from_millis(100);
+// How long a message held back for reordering waits for another to
+// overtake it
+const REORDER_HOLD: Duration = Duration::from_millis(100);
+
+/// What a `ChaosProxy` does to each message. Probabilities are
+/// between 0 and 1, and are rolled separately for each message.
+#[derive(Clone, Debug)]
+pub struct ChaosPolicy {
+    drop: f64,
+    duplicate: f64,
+    reorder: f64,
+    delay_min: Duration,
+    delay_max: Duration,
+    seed: Option<u64>,
+}
+
+impl ChaosPolicy {
+    /// A policy that forwards everything untouched.
+    pub fn new() -> ChaosPolicy {
+        ChaosPolicy {
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            delay_min: Duration::from_millis(0),
+            delay_max: Duration::from_millis(0),
+            seed: None,
+        }
+    }
+
+    /// Drop messages with probability `p`.
+    pub fn drop(mut self, p: f64) -> ChaosPolicy {
+        self.drop = p;
+        self
+    }
+
+    /// Send messages twice with probability `p`.
+    pub fn duplicate(mut self, p: f64) -> ChaosPolicy {
+        self.duplicate = p;
+        self
+    }
+
+    /// Hold messages back with probability `p`, so that the next
+    /// message in the same direction overtakes them.
+    pub fn reorder(mut self, p: f64) -> ChaosPolicy {
+        self.reorder = p;
+        self
+    }
+
+    /// Delay every message by a random time between `min` and `max`.
+    pub fn delay(mut self, min: Duration, max: Duration) -> ChaosPolicy {
+        self.delay_min = min;
+        self.delay_max = max;
+        self
+    }
+
+    /// Seed the dice, so that a run can be repeated. Without a seed,
+    /// they are seeded from the clock.
+    pub fn seed(mut self, seed: u64) -> ChaosPolicy {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn check(&self) -> Result<()> {
+        for &p in &[self.drop, self.duplicate, self.reorder] {
+            if !(p >= 0.0 && p <= 1.0) {
+                return Err(Error::new(ErrorKind::InvalidArg, ChaosError::InvalidProbability(p)));
+            }
+        }
+
+        if self.delay_min > self.delay_max {
+            return Err(Error::new(ErrorKind::InvalidArg, ChaosError::InvalidDelay));
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts of what a `ChaosProxy` has done.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChaosStats {
+    pub forwarded: u64,
+    pub dropped: u64,
+    pub delayed: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+/// Forwards messages between two sockets on a thread of its own,
+/// misbehaving as its policy says.
+pub struct ChaosProxy {
+    policy: Arc<Mutex<ChaosPolicy>>,
+    stats: Arc<Mutex<ChaosStats>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ChaosProxy {
+    /// Forward between `frontend` and `backend`, which should already
+    /// be bound or connected.
+    pub fn new(frontend: ZSock, backend: ZSock, policy: ChaosPolicy) -> Result<ChaosProxy> {
+        policy.check()?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&frontend)?;
+        poller.add(&backend)?;
+
+        let mut chaos = Chaos {
+            rng: policy.seed.map(Rng::new).unwrap_or_else(Rng::from_time),
+            queue: Vec::new(),
+            held: [None, None],
+        };
+
+        let policy = Arc::new(Mutex::new(policy));
+        let stats = Arc::new(Mutex::new(ChaosStats::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (p, s, stopped) = (policy.clone(), stats.clone(), stop.clone());
+        let handle = thread::spawn(move || {
+            let socks = [frontend, backend];
+            while !stopped.load(Ordering::SeqCst) {
+                // A socket that fails is left to the user's heartbeats
+                // to notice, like any other broken link
+                if chaos.poll(&poller, &socks, &p, &s).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(ChaosProxy {
+            policy: policy,
+            stats: stats,
+            stop: stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Change the policy for messages from now on, e.g. to simulate
+    /// an outage with `drop(1.0)`. The seed is ignored.
+    pub fn set_policy(&self, policy: ChaosPolicy) -> Result<()> {
+        policy.check()?;
+        *self.policy.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Stop forwarding. Messages still being delayed are lost.
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for ChaosProxy {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// A message waiting to be sent, to socks[1 - from]
+struct Pending {
+    due: Instant,
+    from: usize,
+    msg: ZMsg,
+}
+
+struct Chaos {
+    rng: Rng,
+    queue: Vec<Pending>,
+    // A message held back for reordering in each direction, and when
+    held: [Option<(Instant, ZMsg)>; 2],
+}
+
+impl Chaos {
+    fn poll(&mut self, poller: &ZPoller, socks: &[ZSock; 2], policy: &Mutex<ChaosPolicy>, stats: &Mutex<ChaosStats>) -> Result<()> {
+        let now = Instant::now();
+
+        for from in 0..2 {
+            if self.held[from].as_ref().map_or(false, |&(since, _)| now >= since + REORDER_HOLD) {
+                let (_, msg) = self.held[from].take().unwrap();
+                self.queue.push(Pending { due: now, from: from, msg: msg });
+            }
+        }
+
+        // Stable, so messages due at the same time keep their order
+        self.queue.sort_by_key(|p| p.due);
+        while !self.queue.is_empty() && self.queue[0].due <= now {
+            let pending = self.queue.remove(0);
+            pending.msg.send(&socks[1 - pending.from])?;
+            stats.lock().unwrap().forwarded += 1;
+        }
+
+        let timeout = self.queue.first().map_or(CHAOS_TICK, |p| p.due.saturating_duration_since(now).min(CHAOS_TICK));
+        if poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(());
+        }
+
+        for from in 0..2 {
+            while socks[from].readable() {
+                let msg = ZMsg::recv(&socks[from])?;
+                let policy = policy.lock().unwrap().clone();
+                self.schedule(from, msg, &policy, &mut stats.lock().unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn schedule(&mut self, from: usize, msg: ZMsg, policy: &ChaosPolicy, stats: &mut ChaosStats) -> Result<()> {
+        if self.rng.chance(policy.drop) {
+            stats.dropped += 1;
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let delay = self.rng.between(policy.delay_min, policy.delay_max);
+        if delay > Duration::from_millis(0) {
+            stats.delayed += 1;
+        }
+
+        if self.rng.chance(policy.duplicate) {
+            stats.duplicated += 1;
+            self.queue.push(Pending { due: now + delay, from: from, msg: msg.dup()? });
+        }
+
+        if let Some((_, held)) = self.held[from].take() {
+            self.queue.push(Pending { due: now + delay, from: from, msg: msg });
+            self.queue.push(Pending { due: now + delay, from: from, msg: held });
+        } else if self.rng.chance(policy.reorder) {
+            stats.reordered += 1;
+            self.held[from] = Some((now, msg));
+        } else {
+            self.queue.push(Pending { due: now + delay, from: from, msg: msg });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ChaosError {
+    InvalidDelay,
+    InvalidProbability(f64),
+}
+
+impl fmt::Display for ChaosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChaosError::InvalidDelay => write!(f, "Minimum delay is longer than the maximum"),
+            ChaosError::InvalidProbability(p) => write!(f, "Probability must be between 0 and 1, not {}", p),
+        }
+    }
+}
+
+impl error::Error for ChaosError {
+    fn description(&self) -> &str {
+        match *self {
+            ChaosError::InvalidDelay => "Minimum delay is longer than the maximum",
+            ChaosError::InvalidProbability(_) => "Probability must be between 0 and 1",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZSock, ZSys};
+    use std::time::{Duration, Instant};
+
+    fn proxy(name: &str, policy: ChaosPolicy) -> (ChaosProxy, ZSock, ZSock) {
+        let frontend = ZSock::new_pull(&format!("@inproc://chaos_test_{}_in", name)).unwrap();
+        let backend = ZSock::new_push(&format!("@inproc://chaos_test_{}_out", name)).unwrap();
+        let proxy = ChaosProxy::new(frontend, backend, policy).unwrap();
+
+        let push = ZSock::new_push(&format!(">inproc://chaos_test_{}_in", name)).unwrap();
+        let pull = ZSock::new_pull(&format!(">inproc://chaos_test_{}_out", name)).unwrap();
+        pull.set_rcvtimeo(Some(500));
+        (proxy, push, pull)
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        ZSys::init();
+
+        let sock = || ZSock::new(zmq::SocketType::PAIR);
+        assert!(ChaosProxy::new(sock(), sock(), ChaosPolicy::new().drop(1.5)).is_err());
+        assert!(ChaosProxy::new(sock(), sock(), ChaosPolicy::new().delay(Duration::from_secs(2), Duration::from_secs(1))).is_err());
+    }
+
+    #[test]
+    fn test_drop_and_duplicate() {
+        ZSys::init();
+
+        let (proxy, push, pull) = proxy("drop", ChaosPolicy::new().drop(1.0));
+        push.send_str("moo").unwrap();
+        assert!(pull.recv_str().is_err());
+        assert_eq!(proxy.stats().dropped, 1);
+
+        proxy.set_policy(ChaosPolicy::new().duplicate(1.0)).unwrap();
+        push.send_str("baa").unwrap();
+        assert_eq!(pull.recv_str().unwrap().unwrap(), "baa");
+        assert_eq!(pull.recv_str().unwrap().unwrap(), "baa");
+        assert_eq!(proxy.stats().duplicated, 1);
+    }
+
+    #[test]
+    fn test_delay_and_reorder() {
+        ZSys::init();
+
+        let delay = Duration::from_millis(50);
+        let (proxy, push, pull) = proxy("reorder", ChaosPolicy::new().delay(delay, delay).reorder(1.0).seed(1));
+
+        let start = Instant::now();
+        push.send_str("first").unwrap();
+        push.send_str("second").unwrap();
+        assert_eq!(pull.recv_str().unwrap().unwrap(), "second");
+        assert_eq!(pull.recv_str().unwrap().unwrap(), "first");
+        assert!(start.elapsed() >= delay);
+
+        assert_eq!(proxy.stats().reordered, 1);
+    }
+}
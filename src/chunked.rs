@@ -0,0 +1,293 @@
+//! Module: czmq-chunked
+//!
+//! Streams a large payload, such as a multi-gigabyte file, between two
+//! sockets in fixed-size chunks, so neither end holds more than a few
+//! chunks in memory. `ZSock::send_chunked()` reads from any `Read`,
+//! and `ZSock::recv_chunked()` writes to any `Write`.
+//!
+//! The receiver drives the transfer, as in the Guide's file transfer
+//! pattern. It opens with `CHUNK-START`, carrying the offset to start
+//! from (8 bytes, big endian) and a window of credit (4 bytes, big
+//! endian). The sender replies with `CHUNK` messages carrying the
+//! chunk's offset and data, one per credit, and the receiver hands
+//! credit back with `CHUNK-CREDIT` as it writes chunks out. An empty
+//! chunk marks the end.
+//!
+//! A receiver that failed part way can pick up where it left off with
+//! `ZSock::resume_chunked()`, passing the number of bytes it already
+//! has. The sender skips forward through its reader to that offset,
+//! so a restarted sender can serve a resume from a fresh reader.
+//!
+//! The sender may be a ROUTER, in which case it serves whichever peer
+//! last sent `CHUNK-START`. The receiver must not be a ROUTER.
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZSock};
+use std::{error, fmt};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+const CHUNK: &[u8] = b"CHUNK";
+const CHUNK_START: &[u8] = b"CHUNK-START";
+const CHUNK_CREDIT: &[u8] = b"CHUNK-CREDIT";
+// Chunks in flight to the receiver at once
+const CHUNK_WINDOW: u32 = 8;
+
+enum Command {
+    Start(u64, u32),
+    Credit(u32),
+}
+
+impl ZSock {
+    /// Send everything `reader` yields, `chunk_size` bytes at a time,
+    /// to a peer calling `recv_chunked()`. Blocks until the transfer is
+    /// finished and returns the offset of the end, or fails with the
+    /// socket's receive timeout if the receiver stops granting credit.
+    pub fn send_chunked<R: Read>(&self, reader: R, chunk_size: usize) -> Result<u64> {
+        if chunk_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidArg, ChunkError::EmptyChunk));
+        }
+
+        let mut reader = reader;
+        let mut buf = vec![0; chunk_size];
+        let mut receiver: Option<Vec<Vec<u8>>> = None;
+        let mut pos = 0;
+        let mut credit = 0;
+
+        loop {
+            // Block while out of credit, otherwise just take whatever
+            // commands are already waiting
+            while credit == 0 || self.readable() {
+                let (envelope, command) = match recv_command(self)? {
+                    Some(command) => command,
+                    None => continue,
+                };
+
+                match command {
+                    Command::Start(offset, grant) => {
+                        if offset < pos {
+                            return Err(Error::new(ErrorKind::InvalidArg, ChunkError::Rewind(offset)));
+                        }
+                        pos += io::copy(&mut (&mut reader).take(offset - pos), &mut io::sink())?;
+                        credit = grant;
+                        receiver = Some(envelope);
+                    },
+                    // Credit left over from a receiver that restarted
+                    // doesn't count
+                    Command::Credit(grant) if receiver.as_ref() == Some(&envelope) => credit += grant,
+                    Command::Credit(_) => (),
+                }
+            }
+
+            let size = read_full(&mut reader, &mut buf)?;
+            let msg = ZMsg::new();
+            for frame in receiver.as_ref().unwrap() {
+                msg.addbytes(frame)?;
+            }
+            msg.addbytes(CHUNK)?;
+            msg.addbytes(&pos.to_be_bytes())?;
+            msg.addbytes(&buf[..size])?;
+            msg.send(self)?;
+
+            credit -= 1;
+            pos += size as u64;
+            if size == 0 {
+                return Ok(pos);
+            }
+        }
+    }
+
+    /// Receive a transfer from a peer calling `send_chunked()`, writing
+    /// it to `writer`. Returns the number of bytes written.
+    pub fn recv_chunked<W: Write>(&self, writer: W) -> Result<u64> {
+        self.resume_chunked(writer, 0)
+    }
+
+    /// Receive a transfer from `offset` onwards, for when the first
+    /// `offset` bytes were already written by an earlier attempt.
+    /// Returns the offset of the end.
+    pub fn resume_chunked<W: Write>(&self, writer: W, offset: u64) -> Result<u64> {
+        let protocol = || Error::new(ErrorKind::InvalidArg, ChunkError::Protocol);
+
+        let mut writer = writer;
+        let start = ZMsg::new();
+        start.addbytes(CHUNK_START)?;
+        start.addbytes(&offset.to_be_bytes())?;
+        start.addbytes(&CHUNK_WINDOW.to_be_bytes())?;
+        start.send(self)?;
+
+        let mut pos = offset;
+        let mut consumed = 0;
+        loop {
+            let mut msg = ZMsg::recv(self)?;
+            let (command, chunk_offset, data) = match (msg.pop(), msg.pop(), msg.pop()) {
+                (Some(command), Some(offset), Some(data)) => (command, offset, data),
+                _ => return Err(protocol()),
+            };
+            match chunk_offset.as_bytes().try_into() {
+                Ok(bytes) if command.as_bytes() == CHUNK && u64::from_be_bytes(bytes) == pos => (),
+                _ => return Err(protocol()),
+            }
+
+            if data.size() == 0 {
+                writer.flush()?;
+                return Ok(pos);
+            }
+            writer.write_all(data.as_bytes())?;
+            pos += data.size() as u64;
+
+            // Credit goes back in batches of half the window, as for
+            // `CreditedReceiver`
+            consumed += 1;
+            if consumed >= CHUNK_WINDOW / 2 {
+                let grant = ZMsg::new();
+                grant.addbytes(CHUNK_CREDIT)?;
+                grant.addbytes(&(consumed as u32).to_be_bytes())?;
+                grant.send(self)?;
+                consumed = 0;
+            }
+        }
+    }
+}
+
+// Receive a command from the receiver, along with any envelope in
+// front of it. Anything else is ignored.
+fn recv_command(sock: &ZSock) -> Result<Option<(Vec<Vec<u8>>, Command)>> {
+    let msg = ZMsg::recv(sock)?;
+    let mut frames: Vec<Vec<u8>> = msg.iter().map(|f| f.to_vec()).collect();
+    let len = frames.len();
+
+    if len >= 3 && frames[len - 3] == CHUNK_START {
+        if let (Ok(offset), Ok(credit)) = ((&frames[len - 2][..]).try_into(), (&frames[len - 1][..]).try_into()) {
+            frames.truncate(len - 3);
+            return Ok(Some((frames, Command::Start(u64::from_be_bytes(offset), u32::from_be_bytes(credit)))));
+        }
+    } else if len >= 2 && frames[len - 2] == CHUNK_CREDIT {
+        if let Ok(credit) = (&frames[len - 1][..]).try_into() {
+            frames.truncate(len - 2);
+            return Ok(Some((frames, Command::Credit(u32::from_be_bytes(credit)))));
+        }
+    }
+
+    Ok(None)
+}
+
+// Fill `buf` unless the reader runs out first, so that only the last
+// chunk is short.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[derive(Debug)]
+pub enum ChunkError {
+    EmptyChunk,
+    Protocol,
+    Rewind(u64),
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChunkError::EmptyChunk => write!(f, "Chunk size must be at least 1"),
+            ChunkError::Protocol => write!(f, "Unexpected message during chunked transfer"),
+            ChunkError::Rewind(offset) => write!(f, "Cannot rewind reader to offset {}", offset),
+        }
+    }
+}
+
+impl error::Error for ChunkError {
+    fn description(&self) -> &str {
+        match *self {
+            ChunkError::EmptyChunk => "Chunk size must be at least 1",
+            ChunkError::Protocol => "Unexpected message during chunked transfer",
+            ChunkError::Rewind(_) => "Cannot rewind reader",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZSys};
+    use std::thread;
+
+    // Fails every write once `limit` bytes are written
+    struct Failing {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Failing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() >= self.limit {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_chunked() {
+        ZSys::init();
+
+        let (server, client) = crate::testing::pair(SocketType::ROUTER, SocketType::DEALER).unwrap();
+        let sender = thread::spawn(move || server.send_chunked(&payload()[..], 1000).unwrap());
+
+        let mut received = Vec::new();
+        assert_eq!(client.recv_chunked(&mut received).unwrap(), 100_000);
+        assert_eq!(sender.join().unwrap(), 100_000);
+        assert!(received == payload());
+    }
+
+    #[test]
+    fn test_resume() {
+        ZSys::init();
+
+        let server = ZSock::new_router("inproc://chunked_test_resume").unwrap();
+        server.set_rcvtimeo(Some(200));
+
+        // The first receiver goes away part way, so the sender times
+        // out waiting for credit
+        let sender = thread::spawn(move || {
+            assert!(server.send_chunked(&payload()[..], 1000).is_err());
+            server
+        });
+        let mut failing = Failing { written: Vec::new(), limit: 5000 };
+        let client = ZSock::new_dealer(">inproc://chunked_test_resume").unwrap();
+        assert_eq!(client.recv_chunked(&mut failing).unwrap_err().kind(), ErrorKind::Io);
+        drop(client);
+        let server = sender.join().unwrap();
+
+        let sender = thread::spawn(move || server.send_chunked(&payload()[..], 1000).unwrap());
+        let mut received = failing.written;
+        let client = ZSock::new_dealer(">inproc://chunked_test_resume").unwrap();
+        assert_eq!(client.resume_chunked(&mut received, 5000).unwrap(), 100_000);
+        assert_eq!(sender.join().unwrap(), 100_000);
+        assert!(received == payload());
+    }
+
+    #[test]
+    fn test_empty_chunk() {
+        ZSys::init();
+
+        let (server, _client) = crate::testing::pair(SocketType::ROUTER, SocketType::DEALER).unwrap();
+        assert_eq!(server.send_chunked(io::empty(), 0).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+}
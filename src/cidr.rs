@@ -0,0 +1,353 @@
+//! Module: czmq-cidr
+//!
+//! IP address ranges in CIDR notation, like `10.0.0.0/8` or
+//! `fd00::/8`, for allowing and denying peers by network rather than
+//! one address at a time.
+//!
+//! CZMQ's authenticator only matches whole addresses, so
+//! `ZAuth::allow_cidr()` and `ZAuth::deny_cidr()` expand small ranges
+//! into one rule per address. For bigger ranges, an `AddressFilter`
+//! runs a ZAP handler of its own that matches ranges directly. It
+//! takes the place of `ZAuth`, as only one ZAP handler can run in a
+//! process, so it suits deployments that use no security mechanism
+//! and only filter by address.
+
+use crate::{Error, ErrorKind, Result, SocketType, ZMsg, ZPoller, ZSock};
+use crate::zauth::ZapClaim;
+use std::{error, fmt, thread};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+const ZAP_VERSION: &[u8] = b"1.0";
+// How often an address filter checks whether it has been stopped
+const FILTER_TICK: Duration = Duration::from_millis(100);
+
+/// A block of IPv4 or IPv6 addresses.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// The block of addresses sharing `addr`'s first `prefix` bits.
+    /// Any bits of `addr` past the prefix are ignored.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Cidr> {
+        let width = width(&addr);
+        if prefix > width {
+            return Err(Error::new(ErrorKind::InvalidArg, CidrError::BadPrefix(prefix)));
+        }
+
+        Ok(Cidr {
+            network: from_bits(&addr, to_bits(&addr) & mask(width, prefix)),
+            prefix: prefix,
+        })
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// The number of addresses in the block, saturating at
+    /// `u128::MAX` for all of IPv6.
+    pub fn size(&self) -> u128 {
+        let host_bits = (width(&self.network) - self.prefix) as u32;
+        1u128.checked_shl(host_bits).unwrap_or(u128::max_value())
+    }
+
+    /// Whether `addr` is in the block. IPv4 addresses mapped into IPv6,
+    /// as reported by dual-stack sockets, match IPv4 blocks.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match (self.network, addr) {
+            (IpAddr::V4(_), IpAddr::V6(v6)) => match ipv4_mapped(&v6) {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            (IpAddr::V6(_), IpAddr::V4(_)) => return false,
+            (_, addr) => addr,
+        };
+
+        to_bits(&addr) & mask(width(&addr), self.prefix) == to_bits(&self.network)
+    }
+
+    /// Every address in the block, in order. Mind the `size()` first.
+    pub fn addresses(&self) -> impl Iterator<Item = IpAddr> {
+        let network = self.network;
+        let first = to_bits(&network);
+        (0..self.size()).map(move |i| from_bits(&network, first + i))
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    /// Parse `address/prefix`, or a single address as a block of one.
+    fn from_str(s: &str) -> Result<Cidr> {
+        let bad = || Error::new(ErrorKind::InvalidArg, CidrError::Parse(s.into()));
+
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap_or("").trim().parse().map_err(|_| bad())?;
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.trim().parse().map_err(|_| bad())?,
+            None => width(&addr),
+        };
+
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn width(addr: &IpAddr) -> u8 {
+    match *addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask(width: u8, prefix: u8) -> u128 {
+    let all = if width == 128 { u128::max_value() } else { (1u128 << width) - 1 };
+    match prefix {
+        0 => 0,
+        _ => (all << (width - prefix) as u32) & all,
+    }
+}
+
+fn to_bits(addr: &IpAddr) -> u128 {
+    match *addr {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+// An address of the same family as `like`
+fn from_bits(like: &IpAddr, bits: u128) -> IpAddr {
+    match *like {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+    }
+}
+
+fn ipv4_mapped(addr: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match addr.segments() {
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] => Some(Ipv4Addr::from(((hi as u32) << 16) | lo as u32)),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Rules {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl Rules {
+    // As with CZMQ's authenticator, an allow list shuts out everyone
+    // not on it, and the deny list only counts without one.
+    fn admits(&self, address: &str) -> bool {
+        let addr = match address.parse() {
+            Ok(addr) => addr,
+            Err(_) => return self.allow.is_empty() && self.deny.is_empty(),
+        };
+
+        if !self.allow.is_empty() {
+            self.allow.iter().any(|cidr| cidr.contains(addr))
+        } else {
+            !self.deny.iter().any(|cidr| cidr.contains(addr))
+        }
+    }
+}
+
+/// A ZAP handler that allows and denies peers by address range.
+///
+/// It can't check credentials, so it refuses every security mechanism
+/// but NULL. Sockets only consult it if they have a ZAP domain set,
+/// with `ZSock::set_zap_domain()`.
+pub struct AddressFilter {
+    rules: Arc<Mutex<Rules>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    _claim: ZapClaim,
+}
+
+impl AddressFilter {
+    /// Start the handler, first waiting for any other ZAP handler in
+    /// the process to be dropped. It admits everyone until given rules.
+    pub fn start() -> Result<AddressFilter> {
+        let claim = ZapClaim::acquire();
+        let sock = ZSock::new(SocketType::REP);
+        sock.set_linger(0);
+        sock.bind(ZAP_ENDPOINT)?;
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        let rules = Arc::new(Mutex::new(Rules::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (shared, stopped) = (rules.clone(), stop.clone());
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                if serve_zap(&poller, &sock, &shared).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(AddressFilter {
+            rules: rules,
+            stop: stop,
+            handle: Some(handle),
+            _claim: claim,
+        })
+    }
+
+    /// Admit only peers in `cidr` and any other allowed ranges.
+    pub fn allow(&self, cidr: &str) -> Result<()> {
+        let cidr = cidr.parse()?;
+        self.rules.lock().unwrap_or_else(|e| e.into_inner()).allow.push(cidr);
+        Ok(())
+    }
+
+    /// Refuse peers in `cidr`, unless there are allowed ranges.
+    pub fn deny(&self, cidr: &str) -> Result<()> {
+        let cidr = cidr.parse()?;
+        self.rules.lock().unwrap_or_else(|e| e.into_inner()).deny.push(cidr);
+        Ok(())
+    }
+}
+
+impl Drop for AddressFilter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve_zap(poller: &ZPoller, sock: &ZSock, rules: &Mutex<Rules>) -> Result<()> {
+    if poller.wait_timeout::<ZSock>(Some(FILTER_TICK))?.is_none() {
+        return Ok(());
+    }
+
+    // [version, request id, domain, address, routing id, mechanism, credentials..]
+    let msg = ZMsg::recv(sock)?;
+    let frames: Vec<&[u8]> = msg.iter().collect();
+    let admitted = frames.len() >= 6
+        && frames[0] == ZAP_VERSION
+        && frames[5] == b"NULL"
+        && rules.lock().unwrap_or_else(|e| e.into_inner()).admits(&String::from_utf8_lossy(frames[3]));
+
+    let (code, text) = if admitted { ("200", "OK") } else { ("400", "Address not allowed") };
+    let reply = ZMsg::new();
+    reply.addbytes(ZAP_VERSION)?;
+    reply.addbytes(frames.get(1).cloned().unwrap_or(&[]))?;
+    reply.addstr(code)?;
+    reply.addstr(text)?;
+    // No user id or metadata
+    reply.addbytes(&[])?;
+    reply.addbytes(&[])?;
+    reply.send(sock)
+}
+
+#[derive(Debug)]
+pub enum CidrError {
+    BadPrefix(u8),
+    Parse(String),
+    TooLarge(Cidr),
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CidrError::BadPrefix(prefix) => write!(f, "Prefix length {} is too long for the address", prefix),
+            CidrError::Parse(ref s) => write!(f, "Could not parse \"{}\" as an address range", s),
+            CidrError::TooLarge(ref cidr) => write!(f, "Range {} is too large to expand; use AddressFilter instead", cidr),
+        }
+    }
+}
+
+impl error::Error for CidrError {
+    fn description(&self) -> &str {
+        match *self {
+            CidrError::BadPrefix(_) => "Prefix length is too long for the address",
+            CidrError::Parse(_) => "Could not parse address range",
+            CidrError::TooLarge(_) => "Range is too large to expand",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_cidr() {
+        let cidr: Cidr = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.0/8");
+        assert_eq!(cidr.size(), 1 << 24);
+        assert!(cidr.contains("10.255.0.1".parse().unwrap()));
+        assert!(cidr.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains("11.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains("::1".parse().unwrap()));
+
+        let cidr: Cidr = "fd00::/126".parse().unwrap();
+        let addresses: Vec<String> = cidr.addresses().map(|a| a.to_string()).collect();
+        assert_eq!(addresses, ["fd00::", "fd00::1", "fd00::2", "fd00::3"]);
+        assert!(!cidr.contains("fd00::4".parse().unwrap()));
+
+        let cidr: Cidr = "127.0.0.1".parse().unwrap();
+        assert_eq!(cidr.prefix(), 32);
+        assert_eq!("::/0".parse::<Cidr>().unwrap().size(), u128::max_value());
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains("1.2.3.4".parse().unwrap()));
+
+        assert_eq!("10.0.0.0/33".parse::<Cidr>().unwrap_err().kind(), ErrorKind::InvalidArg);
+        assert_eq!("moo/8".parse::<Cidr>().unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_address_filter() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::PULL);
+        server.set_zap_domain("global");
+        server.set_rcvtimeo(Some(100));
+        let port = server.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = format!("tcp://127.0.0.1:{}", port);
+
+        let filter = AddressFilter::start().unwrap();
+        filter.deny("127.0.0.0/8").unwrap();
+
+        let client = ZSock::new(SocketType::PUSH);
+        client.set_linger(100);
+        client.set_sndtimeo(Some(100));
+        client.connect(&endpoint).unwrap();
+        sleep(Duration::from_millis(100));
+        let _ = client.send_str("moo");
+        assert!(server.recv_str().is_err());
+
+        // An allow list overrides the deny list
+        filter.allow("127.0.0.0/16").unwrap();
+        server.set_rcvtimeo(Some(1000));
+        let client = ZSock::new(SocketType::PUSH);
+        client.set_linger(100);
+        client.connect(&endpoint).unwrap();
+        client.send_str("baa").unwrap();
+        assert_eq!(server.recv_str().unwrap().unwrap(), "baa");
+    }
+}
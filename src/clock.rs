@@ -0,0 +1,42 @@
+//! Module: czmq-clock
+//!
+//! The time source for heartbeating, expiry and reconnection backoff.
+//! `Heartbeat`, `PeerTable`, the MDP broker and worker, and the
+//! Paranoid Pirate queue and worker all read the time through a
+//! `Clock`, which is the system clock unless replaced with
+//! `set_clock()`.
+//!
+//! Tests can swap in `testing::MockClock`, which only moves when it is
+//! advanced, to expire peers or trigger reconnects without sleeping.
+//! The timeouts passed to `poll()` and `recv()` calls still run in
+//! real time, as they bound how long the call blocks.
+
+use std::thread;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Block for `duration`, e.g. while backing off before a
+    /// reconnect.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real clock.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
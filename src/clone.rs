@@ -0,0 +1,521 @@
+//! Module: czmq-clone
+//!
+//! The Clustered Hashmap Protocol (CHP), aka the Clone pattern: a
+//! server holds a key-value map and clients keep synchronized local
+//! copies of it, or of a subtree of it.
+//!
+//! A `CloneServer` uses three sockets: a ROUTER that serves
+//! snapshots, a PUB that publishes updates and a PULL that collects
+//! updates from clients. A `CloneClient` first subscribes to updates,
+//! then asks for a snapshot, and from then on applies every update
+//! newer than the snapshot. Keys set with a TTL are deleted by the
+//! server when they expire, and the deletion is published like any
+//! other update.
+
+use crate::{Error, ErrorKind, RawInterface, Result, SocketType, ZMsg, ZPoller, ZSock};
+use std::{error, fmt, str};
+use std::cmp;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const ICANHAZ: &str = "ICANHAZ?";
+const KTHXBAI: &str = "KTHXBAI";
+const HUGZ: &str = "HUGZ";
+const TTL: &str = "ttl";
+
+const HUGZ_INTERVAL: Duration = Duration::from_secs(1);
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A key-value update, as sent between CHP clients and servers. An
+/// empty body deletes the key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KvMsg {
+    pub key: String,
+    /// Assigned by the server; zero for updates sent by clients.
+    pub sequence: i64,
+    pub uuid: Vec<u8>,
+    pub properties: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl KvMsg {
+    pub fn new(key: &str, body: &[u8]) -> KvMsg {
+        KvMsg {
+            key: key.to_string(),
+            body: body.to_vec(),
+            ..KvMsg::default()
+        }
+    }
+
+    /// Time to live, from the `ttl` property in seconds.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.properties.get(TTL)
+            .and_then(|t| t.parse::<f64>().ok())
+            .filter(|t| *t > 0.0)
+            .map(Duration::from_secs_f64)
+    }
+
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.properties.insert(TTL.to_string(), ttl.as_secs_f64().to_string());
+    }
+
+    /// Encode as key, sequence, UUID, properties and body frames.
+    /// Properties are sent as "name=value" lines.
+    pub fn encode(&self) -> Result<ZMsg> {
+        let mut properties = String::new();
+        for (name, value) in &self.properties {
+            properties.push_str(name);
+            properties.push('=');
+            properties.push_str(value);
+            properties.push('\n');
+        }
+
+        let msg = ZMsg::new();
+        msg.addstr(&self.key)?;
+        msg.addbytes(&self.sequence.to_be_bytes())?;
+        msg.addbytes(&self.uuid)?;
+        msg.addbytes(properties.as_bytes())?;
+        msg.addbytes(&self.body)?;
+        Ok(msg)
+    }
+
+    pub fn decode(msg: &ZMsg) -> Result<KvMsg> {
+        let mut frames = msg.iter();
+        let mut next = || frames.next().ok_or_else(|| Error::new(ErrorKind::MissingFrame, CloneError::MissingFrame));
+
+        let key = str::from_utf8(next()?)?.to_string();
+
+        let sequence = next()?;
+        if sequence.len() != 8 {
+            return Err(Error::new(ErrorKind::InvalidArg, CloneError::InvalidSequence));
+        }
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(sequence);
+
+        let uuid = next()?.to_vec();
+
+        let properties = str::from_utf8(next()?)?.lines().filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => Some((name.to_string(), value.to_string())),
+                _ => None,
+            }
+        }).collect();
+
+        Ok(KvMsg {
+            key: key,
+            sequence: i64::from_be_bytes(bytes),
+            uuid: uuid,
+            properties: properties,
+            body: next()?.to_vec(),
+        })
+    }
+}
+
+/// Serves a key-value map to `CloneClient`s.
+pub struct CloneServer {
+    snapshot: ZSock,
+    publisher: ZSock,
+    collector: ZSock,
+    poller: ZPoller,
+    kvmap: HashMap<String, (KvMsg, Option<Instant>)>,
+    sequence: i64,
+    hugz_at: Instant,
+}
+
+impl CloneServer {
+    /// Create a server on the given snapshot (ROUTER), publisher
+    /// (PUB) and collector (PULL) endpoints, all of which bind by
+    /// default.
+    pub fn new(snapshot: &str, publisher: &str, collector: &str) -> Result<CloneServer> {
+        let mut server = CloneServer {
+            snapshot: ZSock::new_router(snapshot)?,
+            publisher: ZSock::new_pub(publisher)?,
+            collector: ZSock::new_pull(collector)?,
+            poller: ZPoller::new()?,
+            kvmap: HashMap::new(),
+            sequence: 0,
+            hugz_at: Instant::now() + HUGZ_INTERVAL,
+        };
+
+        server.poller.add(&server.snapshot)?;
+        server.poller.add(&server.collector)?;
+        Ok(server)
+    }
+
+    /// The sequence number of the latest update.
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    pub fn len(&self) -> usize {
+        self.kvmap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kvmap.is_empty()
+    }
+
+    /// Serve until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            self.poll(HUGZ_INTERVAL)?;
+        }
+    }
+
+    /// Wait up to `timeout` for a snapshot request or an update and
+    /// handle it, then expire keys and heartbeat subscribers as due.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        let timeout = cmp::min(timeout, self.hugz_at.checked_duration_since(Instant::now()).unwrap_or_default());
+
+        if let Some(mut ready) = self.poller.wait_timeout::<ZSock>(Some(timeout))? {
+            if ready.as_mut_ptr() == self.snapshot.as_mut_ptr() {
+                let msg = ZMsg::recv(&self.snapshot)?;
+                self.send_snapshot(msg)?;
+            } else {
+                let msg = ZMsg::recv(&self.collector)?;
+                // Drop malformed updates rather than stop serving
+                if let Ok(kvmsg) = KvMsg::decode(&msg) {
+                    self.publish(kvmsg)?;
+                }
+            }
+        }
+
+        self.expire()?;
+
+        if Instant::now() >= self.hugz_at {
+            let mut hugz = KvMsg::new(HUGZ, &[]);
+            hugz.sequence = self.sequence;
+            hugz.encode()?.send(&self.publisher)?;
+            self.hugz_at = Instant::now() + HUGZ_INTERVAL;
+        }
+
+        Ok(())
+    }
+
+    fn send_snapshot(&self, mut msg: ZMsg) -> Result<()> {
+        let identity = match msg.pop() {
+            Some(frame) => frame.as_bytes().to_vec(),
+            None => return Ok(()),
+        };
+
+        if msg.popstr().and_then(|s| s.ok()).as_deref() != Some(ICANHAZ) {
+            return Ok(());
+        }
+        let subtree = msg.popstr().and_then(|s| s.ok()).unwrap_or_default();
+
+        for &(ref kvmsg, _) in self.kvmap.values() {
+            if kvmsg.key.starts_with(&subtree) {
+                let reply = kvmsg.encode()?;
+                reply.pushbytes(&identity)?;
+                reply.send(&self.snapshot)?;
+            }
+        }
+
+        let mut done = KvMsg::new(KTHXBAI, subtree.as_bytes());
+        done.sequence = self.sequence;
+        let reply = done.encode()?;
+        reply.pushbytes(&identity)?;
+        reply.send(&self.snapshot)
+    }
+
+    fn publish(&mut self, mut kvmsg: KvMsg) -> Result<()> {
+        self.sequence += 1;
+        kvmsg.sequence = self.sequence;
+        kvmsg.encode()?.send(&self.publisher)?;
+
+        if kvmsg.body.is_empty() {
+            self.kvmap.remove(&kvmsg.key);
+        } else {
+            let expiry = kvmsg.ttl().map(|ttl| Instant::now() + ttl);
+            self.kvmap.insert(kvmsg.key.clone(), (kvmsg, expiry));
+        }
+
+        Ok(())
+    }
+
+    fn expire(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let expired: Vec<String> = self.kvmap.iter()
+            .filter(|&(_, &(_, expiry))| expiry.map_or(false, |e| e <= now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            self.publish(KvMsg::new(&key, &[]))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps a local copy of a `CloneServer`'s map, or of the subtree of
+/// keys starting with a given prefix.
+pub struct CloneClient {
+    subscriber: ZSock,
+    publisher: ZSock,
+    poller: ZPoller,
+    kvmap: HashMap<String, KvMsg>,
+    sequence: i64,
+    subtree: String,
+}
+
+impl CloneClient {
+    /// Connect to a server's snapshot, publisher and collector
+    /// endpoints and load a snapshot of `subtree`. Use an empty
+    /// subtree for the whole map.
+    pub fn connect(snapshot: &str, publisher: &str, collector: &str, subtree: &str) -> Result<CloneClient> {
+        // Subscribe before asking for the snapshot, so that no update
+        // falls between the two
+        let subscriber = ZSock::new_sub(publisher, Some(subtree))?;
+        if !HUGZ.starts_with(subtree) {
+            subscriber.set_subscribe(HUGZ);
+        }
+
+        let mut client = CloneClient {
+            subscriber: subscriber,
+            publisher: ZSock::new_push(collector)?,
+            poller: ZPoller::new()?,
+            kvmap: HashMap::new(),
+            sequence: 0,
+            subtree: subtree.to_string(),
+        };
+        client.poller.add(&client.subscriber)?;
+        client.load_snapshot(snapshot)?;
+
+        Ok(client)
+    }
+
+    fn load_snapshot(&mut self, endpoint: &str) -> Result<()> {
+        let sock = ZSock::new(SocketType::DEALER);
+        sock.set_linger(0);
+        sock.connect(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        let request = ZMsg::new();
+        request.addstr(ICANHAZ)?;
+        request.addstr(&self.subtree)?;
+        request.send(&sock)?;
+
+        loop {
+            if poller.wait_timeout::<ZSock>(Some(SNAPSHOT_TIMEOUT))?.is_none() {
+                return Err(Error::new(ErrorKind::Timeout, CloneError::NoSnapshot));
+            }
+
+            let kvmsg = KvMsg::decode(&ZMsg::recv(&sock)?)?;
+            if kvmsg.key == KTHXBAI {
+                self.sequence = kvmsg.sequence;
+                return Ok(());
+            }
+
+            self.kvmap.insert(kvmsg.key.clone(), kvmsg);
+        }
+    }
+
+    /// The sequence number of the latest update applied.
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.kvmap.get(key).map(|kvmsg| kvmsg.body.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.kvmap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kvmap.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.kvmap.keys().map(|k| k.as_str())
+    }
+
+    /// Send an update to the server, optionally expiring after
+    /// `ttl`. The local map changes once the server publishes the
+    /// update back, on a later `poll()`.
+    pub fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> Result<()> {
+        let mut kvmsg = KvMsg::new(key, value);
+        if let Some(ttl) = ttl {
+            kvmsg.set_ttl(ttl);
+        }
+        kvmsg.encode()?.send(&self.publisher)
+    }
+
+    /// Ask the server to delete a key.
+    pub fn delete(&self, key: &str) -> Result<()> {
+        self.set(key, &[], None)
+    }
+
+    /// Apply published updates, waiting up to `timeout` for the first
+    /// one. Returns the number of updates applied.
+    pub fn poll(&mut self, timeout: Duration) -> Result<usize> {
+        let mut applied = 0;
+        let mut timeout = timeout;
+
+        while self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_some() {
+            let msg = ZMsg::recv(&self.subscriber)?;
+            timeout = Duration::from_millis(0);
+
+            let kvmsg = match KvMsg::decode(&msg) {
+                Ok(kvmsg) => kvmsg,
+                Err(_) => continue,
+            };
+
+            // Skip heartbeats and anything already in the snapshot
+            if kvmsg.key == HUGZ || kvmsg.sequence <= self.sequence {
+                continue;
+            }
+
+            self.sequence = kvmsg.sequence;
+            if kvmsg.body.is_empty() {
+                self.kvmap.remove(&kvmsg.key);
+            } else {
+                self.kvmap.insert(kvmsg.key.clone(), kvmsg);
+            }
+            applied += 1;
+        }
+
+        Ok(applied)
+    }
+}
+
+#[derive(Debug)]
+pub enum CloneError {
+    InvalidSequence,
+    MissingFrame,
+    NoSnapshot,
+}
+
+impl fmt::Display for CloneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CloneError::InvalidSequence => write!(f, "Sequence number must be 8 bytes"),
+            CloneError::MissingFrame => write!(f, "Key-value message is missing a frame"),
+            CloneError::NoSnapshot => write!(f, "Server did not send a snapshot"),
+        }
+    }
+}
+
+impl error::Error for CloneError {
+    fn description(&self) -> &str {
+        match *self {
+            CloneError::InvalidSequence => "Sequence number must be 8 bytes",
+            CloneError::MissingFrame => "Key-value message is missing a frame",
+            CloneError::NoSnapshot => "Server did not send a snapshot",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZSys};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn spawn_server(name: &str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        let mut server = CloneServer::new(&format!("inproc://{}_snapshot", name),
+                                          &format!("inproc://{}_publisher", name),
+                                          &format!("inproc://{}_collector", name)).unwrap();
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                server.poll(TICK).unwrap();
+            }
+        })
+    }
+
+    fn connect(name: &str, subtree: &str) -> CloneClient {
+        CloneClient::connect(&format!("inproc://{}_snapshot", name),
+                             &format!("inproc://{}_publisher", name),
+                             &format!("inproc://{}_collector", name),
+                             subtree).unwrap()
+    }
+
+    // Poll until the client has seen `n` updates
+    fn sync(client: &mut CloneClient, n: usize) {
+        let mut applied = 0;
+        for _ in 0..40 {
+            applied += client.poll(TICK).unwrap();
+            if applied >= n {
+                return;
+            }
+        }
+        panic!("Expected {} updates, got {}", n, applied);
+    }
+
+    #[test]
+    fn test_kvmsg() {
+        let mut kvmsg = KvMsg::new("moo", b"cow");
+        kvmsg.sequence = 42;
+        kvmsg.uuid = vec![1, 2, 3];
+        kvmsg.set_ttl(Duration::from_millis(1500));
+
+        let decoded = KvMsg::decode(&kvmsg.encode().unwrap()).unwrap();
+        assert_eq!(decoded, kvmsg);
+        assert_eq!(decoded.ttl(), Some(Duration::from_millis(1500)));
+
+        let msg = ZMsg::from_frames(&[&b"moo"[..], b"\x01"]).unwrap();
+        assert_eq!(KvMsg::decode(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_sync() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = spawn_server("clone_test_sync", stop.clone());
+
+        let mut first = connect("clone_test_sync", "");
+        assert!(first.is_empty());
+
+        first.set("farm/cow", b"moo", None).unwrap();
+        first.set("zoo/lion", b"roar", None).unwrap();
+        sync(&mut first, 2);
+        assert_eq!(first.get("farm/cow"), Some(&b"moo"[..]));
+
+        // A late joiner gets the snapshot, filtered by subtree
+        let mut second = connect("clone_test_sync", "farm/");
+        assert_eq!(second.len(), 1);
+        assert_eq!(second.get("farm/cow"), Some(&b"moo"[..]));
+
+        first.set("farm/pig", b"oink", None).unwrap();
+        first.delete("farm/cow").unwrap();
+        first.set("zoo/seal", b"arf", None).unwrap();
+        sync(&mut second, 2);
+        assert_eq!(second.keys().collect::<Vec<_>>(), vec!["farm/pig"]);
+        assert_eq!(second.sequence(), 4);
+
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_ttl() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = spawn_server("clone_test_ttl", stop.clone());
+
+        let mut client = connect("clone_test_ttl", "");
+        client.set("moo", b"cow", Some(TICK * 2)).unwrap();
+        sync(&mut client, 1);
+        assert!(client.get("moo").is_some());
+
+        // The server publishes the deletion once the key expires
+        sync(&mut client, 1);
+        assert!(client.get("moo").is_none());
+
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+}
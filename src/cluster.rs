@@ -0,0 +1,670 @@
+//! Module: czmq-cluster
+//!
+//! Peer-to-peer clustering on a LAN, modelled on Zyre. Each node has
+//! a UUID and a name, binds a ROUTER socket for incoming traffic and
+//! announces it with a UDP beacon. Nodes that hear each other connect
+//! a DEALER each way and introduce themselves, and from then on can
+//! send each other messages directly (whisper) or to every node in a
+//! group (shout). Peers are pinged while idle, and leave the cluster
+//! when they stop beaconing or stop answering.
+//!
+//! Where there's no broadcast interface, `set_endpoint()` binds a
+//! known endpoint instead of beaconing, and `connect()` introduces a
+//! node to a peer's endpoint directly.
+//!
+//! The design follows the ZRE protocol, but the beacons and commands
+//! are framed for this crate, so nodes don't interoperate with
+//! libzyre. A beacon is `ZRE`, the version byte 1, the node's UUID as
+//! 32 hex digits and its port as 2 bytes, big endian. Commands are a
+//! frame naming the command followed by its arguments:
+//!
+//! * `HELLO` endpoint, name, group count, groups, then header keys
+//!   and values;
+//! * `WHISPER` message frames;
+//! * `SHOUT` group, message frames;
+//! * `JOIN` group, and `LEAVE` group;
+//! * `PING` and `PING-OK`.
+
+use crate::{Discovery, Error, ErrorKind, Heartbeat, HeartbeatEvent, Result, SocketType, ZBeacon, ZMsg, ZPoller, ZSock};
+use crate::zuuid::ZUuid;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::{error, fmt};
+use std::time::Duration;
+
+const BEACON_PREFIX: &[u8] = b"ZRE\x01";
+const UUID_LEN: usize = 32;
+
+const HELLO: &str = "HELLO";
+const WHISPER: &str = "WHISPER";
+const SHOUT: &str = "SHOUT";
+const JOIN: &str = "JOIN";
+const LEAVE: &str = "LEAVE";
+const PING: &str = "PING";
+const PING_OK: &str = "PING-OK";
+
+/// Something that happened in the cluster. Peers are identified by
+/// their UUID.
+#[derive(Debug)]
+pub enum ClusterEvent {
+    Enter {
+        peer: String,
+        name: String,
+        endpoint: String,
+        headers: HashMap<String, String>,
+    },
+    Exit {
+        peer: String,
+        name: String,
+    },
+    Join {
+        peer: String,
+        name: String,
+        group: String,
+    },
+    Leave {
+        peer: String,
+        name: String,
+        group: String,
+    },
+    Whisper {
+        peer: String,
+        name: String,
+        msg: ZMsg,
+    },
+    Shout {
+        peer: String,
+        name: String,
+        group: String,
+        msg: ZMsg,
+    },
+}
+
+struct Peer {
+    name: String,
+    endpoint: String,
+    outbox: ZSock,
+    groups: HashSet<String>,
+    headers: HashMap<String, String>,
+}
+
+/// A member of a cluster.
+pub struct ClusterNode {
+    uuid: String,
+    name: String,
+    headers: HashMap<String, String>,
+    groups: HashSet<String>,
+    port: u16,
+    interval: Duration,
+    liveness: u32,
+    bind: Option<String>,
+    // Set once started
+    endpoint: Option<String>,
+    inbox: Option<ZSock>,
+    beacon: Option<ZBeacon>,
+    poller: ZPoller,
+    heartbeat: Heartbeat,
+    peers: HashMap<String, Peer>,
+    // Outboxes connected by us that haven't had a HELLO back yet
+    pending: Vec<(String, ZSock)>,
+    events: VecDeque<ClusterEvent>,
+}
+
+impl ClusterNode {
+    pub fn new(name: &str) -> Result<ClusterNode> {
+        Ok(ClusterNode {
+            uuid: new_uuid()?,
+            name: name.to_string(),
+            headers: HashMap::new(),
+            groups: HashSet::new(),
+            port: 5670,
+            interval: Duration::from_secs(1),
+            liveness: 5,
+            bind: None,
+            endpoint: None,
+            inbox: None,
+            beacon: None,
+            poller: ZPoller::new()?,
+            heartbeat: Heartbeat::new(Duration::from_secs(1), 5),
+            peers: HashMap::new(),
+            pending: Vec::new(),
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The endpoint peers connect to, once started.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_ref().map(|e| e.as_str())
+    }
+
+    /// Set a header, which is sent to peers when they connect. Must be
+    /// called before `start()`.
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.insert(key.to_string(), value.to_string());
+    }
+
+    /// The UDP port to beacon on. Defaults to 5670.
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+
+    /// How often to beacon and to ping idle peers, and how many
+    /// intervals a peer can be silent before it leaves. Defaults to 1
+    /// second and 5 intervals.
+    pub fn set_interval(&mut self, interval: Duration, liveness: u32) {
+        self.interval = interval;
+        self.liveness = liveness;
+    }
+
+    /// Bind to `endpoint` instead of beaconing, so that peers must be
+    /// introduced with `connect()`. Must be called before `start()`.
+    pub fn set_endpoint(&mut self, endpoint: &str) {
+        self.bind = Some(endpoint.to_string());
+    }
+
+    /// Start taking part in the cluster.
+    pub fn start(&mut self) -> Result<()> {
+        if self.inbox.is_some() {
+            return Err(Error::new(ErrorKind::InvalidArg, ClusterError::Started));
+        }
+
+        let inbox = ZSock::new(SocketType::ROUTER);
+        inbox.set_linger(0);
+
+        match self.bind.clone() {
+            Some(endpoint) => {
+                inbox.bind(&endpoint)?;
+            },
+            None => {
+                let beacon = ZBeacon::new()?;
+                let host = match beacon.configure(self.port)? {
+                    Some(host) => host,
+                    None => return Err(Error::new(ErrorKind::InvalidArg, ClusterError::NoInterface)),
+                };
+                let port = inbox.bind(&format!("tcp://{}:*", host))?;
+
+                beacon.publish(&self.beacon_data(port as u16), self.interval)?;
+                beacon.subscribe(BEACON_PREFIX)?;
+                self.poller.add(beacon.actor())?;
+                self.beacon = Some(beacon);
+            },
+        }
+
+        self.endpoint = match inbox.last_endpoint()? {
+            Ok(endpoint) => Some(endpoint),
+            Err(_) => return Err(Error::new(ErrorKind::StringConversion, ClusterError::InvalidEndpoint)),
+        };
+        self.poller.add(&inbox)?;
+        self.inbox = Some(inbox);
+        self.heartbeat = Heartbeat::new(self.interval, self.liveness);
+        Ok(())
+    }
+
+    /// Introduce this node to the node at `endpoint`.
+    pub fn connect(&mut self, endpoint: &str) -> Result<()> {
+        let outbox = self.outbox(endpoint)?;
+        self.pending.push((endpoint.to_string(), outbox));
+        Ok(())
+    }
+
+    /// Join `group`, telling every peer.
+    pub fn join(&mut self, group: &str) -> Result<()> {
+        if self.groups.insert(group.to_string()) {
+            self.broadcast(&[JOIN, group])?;
+        }
+        Ok(())
+    }
+
+    /// Leave `group`, telling every peer.
+    pub fn leave(&mut self, group: &str) -> Result<()> {
+        if self.groups.remove(group) {
+            self.broadcast(&[LEAVE, group])?;
+        }
+        Ok(())
+    }
+
+    /// The groups this node has joined.
+    pub fn groups(&self) -> impl Iterator<Item = &str> {
+        self.groups.iter().map(|g| g.as_str())
+    }
+
+    /// Send `msg` to one peer.
+    pub fn whisper(&self, peer: &str, msg: ZMsg) -> Result<()> {
+        match self.peers.get(peer) {
+            Some(p) => {
+                msg.pushstr(WHISPER)?;
+                msg.send(&p.outbox)
+            },
+            None => Err(Error::new(ErrorKind::InvalidArg, ClusterError::UnknownPeer)),
+        }
+    }
+
+    /// Send `msg` to every peer in `group`.
+    pub fn shout(&self, group: &str, msg: ZMsg) -> Result<()> {
+        for p in self.peers.values().filter(|p| p.groups.contains(group)) {
+            let msg = msg.dup()?;
+            msg.pushstr(group)?;
+            msg.pushstr(SHOUT)?;
+            msg.send(&p.outbox)?;
+        }
+        Ok(())
+    }
+
+    /// The UUIDs of every peer.
+    pub fn peers(&self) -> impl Iterator<Item = &str> {
+        self.peers.keys().map(|p| p.as_str())
+    }
+
+    /// The UUIDs of the peers in `group`.
+    pub fn peers_by_group<'a>(&'a self, group: &'a str) -> impl Iterator<Item = &'a str> {
+        self.peers.iter().filter(move |&(_, p)| p.groups.contains(group)).map(|(uuid, _)| uuid.as_str())
+    }
+
+    pub fn peer_name(&self, peer: &str) -> Option<&str> {
+        self.peers.get(peer).map(|p| p.name.as_str())
+    }
+
+    pub fn peer_endpoint(&self, peer: &str) -> Option<&str> {
+        self.peers.get(peer).map(|p| p.endpoint.as_str())
+    }
+
+    pub fn peer_header(&self, peer: &str, key: &str) -> Option<&str> {
+        self.peers.get(peer).and_then(|p| p.headers.get(key)).map(|v| v.as_str())
+    }
+
+    /// Wait up to `timeout` for something to happen in the cluster,
+    /// handling beacons, introductions and pings along the way.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<ClusterEvent>> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(Some(event));
+        }
+
+        if self.inbox.is_none() {
+            return Err(Error::new(ErrorKind::InvalidArg, ClusterError::NotStarted));
+        }
+
+        if self.poller.wait_timeout::<ZSock>(Some(timeout.min(self.heartbeat.timeout())))?.is_some() {
+            while let Some(msg) = self.recv_inbox()? {
+                self.handle_peer(msg)?;
+            }
+
+            while let Some(discovery) = self.recv_beacon()? {
+                self.handle_beacon(&discovery.addr, &discovery.data)?;
+            }
+        }
+
+        while let Some(event) = self.heartbeat.poll() {
+            match event {
+                HeartbeatEvent::Ping => self.broadcast(&[PING])?,
+                HeartbeatEvent::Expired(peer) => self.remove_peer(&String::from_utf8_lossy(&peer)),
+            }
+        }
+
+        Ok(self.events.pop_front())
+    }
+
+    /// Leave the cluster. Beaconing peers are told straight away, and
+    /// others notice when this node stops answering pings.
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(beacon) = self.beacon.take() {
+            // A beacon with port 0 says the node is leaving
+            beacon.publish(&self.beacon_data(0), self.interval)?;
+            self.poller.remove(beacon.actor())?;
+        }
+        if let Some(inbox) = self.inbox.take() {
+            self.poller.remove(&inbox)?;
+        }
+
+        self.endpoint = None;
+        self.peers.clear();
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn recv_inbox(&self) -> Result<Option<ZMsg>> {
+        match self.inbox {
+            Some(ref inbox) if inbox.readable() => ZMsg::recv(inbox).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn recv_beacon(&self) -> Result<Option<Discovery>> {
+        match self.beacon {
+            Some(ref beacon) => beacon.recv(Some(Duration::default())),
+            None => Ok(None),
+        }
+    }
+
+    fn handle_peer(&mut self, mut msg: ZMsg) -> Result<()> {
+        let (uuid, command) = match (msg.popstr(), msg.popstr()) {
+            (Some(Ok(uuid)), Some(Ok(command))) => (uuid, command),
+            _ => return Ok(()),
+        };
+
+        if command == HELLO {
+            return self.handle_hello(uuid, msg);
+        }
+
+        // Anything else from a node that hasn't said hello is ignored
+        let name = match self.peers.get(&uuid) {
+            Some(p) => p.name.clone(),
+            None => return Ok(()),
+        };
+        self.heartbeat.heard(uuid.as_bytes());
+
+        match command.as_str() {
+            WHISPER => self.events.push_back(ClusterEvent::Whisper {
+                peer: uuid,
+                name: name,
+                msg: msg,
+            }),
+            SHOUT => if let Some(Ok(group)) = msg.popstr() {
+                self.events.push_back(ClusterEvent::Shout {
+                    peer: uuid,
+                    name: name,
+                    group: group,
+                    msg: msg,
+                });
+            },
+            JOIN | LEAVE => if let Some(Ok(group)) = msg.popstr() {
+                let peer = self.peers.get_mut(&uuid).unwrap();
+                if command == JOIN && peer.groups.insert(group.clone()) {
+                    self.events.push_back(ClusterEvent::Join {
+                        peer: uuid,
+                        name: name,
+                        group: group,
+                    });
+                } else if command == LEAVE && peer.groups.remove(&group) {
+                    self.events.push_back(ClusterEvent::Leave {
+                        peer: uuid,
+                        name: name,
+                        group: group,
+                    });
+                }
+            },
+            PING => {
+                let reply = ZMsg::new();
+                reply.addstr(PING_OK)?;
+                reply.send(&self.peers[&uuid].outbox)?;
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn handle_hello(&mut self, uuid: String, mut msg: ZMsg) -> Result<()> {
+        if self.peers.contains_key(&uuid) {
+            self.heartbeat.heard(uuid.as_bytes());
+            return Ok(());
+        }
+
+        let mut frames = Vec::new();
+        while let Some(Ok(frame)) = msg.popstr() {
+            frames.push(frame);
+        }
+        let mut frames = frames.into_iter();
+
+        let (endpoint, name, count) = match (frames.next(), frames.next(), frames.next().and_then(|c| c.parse().ok())) {
+            (Some(endpoint), Some(name), Some(count)) => (endpoint, name, count),
+            _ => return Ok(()),
+        };
+        let groups: HashSet<String> = frames.by_ref().take(count).collect();
+        let mut headers = HashMap::new();
+        while let (Some(key), Some(value)) = (frames.next(), frames.next()) {
+            headers.insert(key, value);
+        }
+
+        // Use the outbox we connected ourselves if there is one, or
+        // connect one back to introduce ourselves
+        let outbox = match self.pending.iter().position(|&(ref e, _)| *e == endpoint) {
+            Some(i) => self.pending.remove(i).1,
+            None => self.outbox(&endpoint)?,
+        };
+
+        self.events.push_back(ClusterEvent::Enter {
+            peer: uuid.clone(),
+            name: name.clone(),
+            endpoint: endpoint.clone(),
+            headers: headers.clone(),
+        });
+        for group in &groups {
+            self.events.push_back(ClusterEvent::Join {
+                peer: uuid.clone(),
+                name: name.clone(),
+                group: group.clone(),
+            });
+        }
+
+        self.heartbeat.heard(uuid.as_bytes());
+        self.peers.insert(uuid, Peer {
+            name: name,
+            endpoint: endpoint,
+            outbox: outbox,
+            groups: groups,
+            headers: headers,
+        });
+        Ok(())
+    }
+
+    fn handle_beacon(&mut self, addr: &str, data: &[u8]) -> Result<()> {
+        if data.len() != BEACON_PREFIX.len() + UUID_LEN + 2 || !data.starts_with(BEACON_PREFIX) {
+            return Ok(());
+        }
+
+        let (uuid, port) = data[BEACON_PREFIX.len()..].split_at(UUID_LEN);
+        let uuid = String::from_utf8_lossy(uuid).into_owned();
+        let port = u16::from_be_bytes(port.try_into().unwrap());
+
+        if uuid == self.uuid {
+            return Ok(());
+        }
+
+        if port == 0 {
+            self.remove_peer(&uuid);
+            return Ok(());
+        }
+
+        let endpoint = format!("tcp://{}:{}", addr, port);
+        if self.peers.contains_key(&uuid) || self.pending.iter().any(|&(ref e, _)| *e == endpoint) {
+            self.heartbeat.heard(uuid.as_bytes());
+            Ok(())
+        } else {
+            self.connect(&endpoint)
+        }
+    }
+
+    fn remove_peer(&mut self, uuid: &str) {
+        self.heartbeat.forget(uuid.as_bytes());
+
+        if let Some(peer) = self.peers.remove(uuid) {
+            self.events.push_back(ClusterEvent::Exit {
+                peer: uuid.to_string(),
+                name: peer.name,
+            });
+        }
+    }
+
+    // Connect a DEALER to a peer and say hello
+    fn outbox(&self, endpoint: &str) -> Result<ZSock> {
+        let own_endpoint = match self.endpoint {
+            Some(ref endpoint) => endpoint,
+            None => return Err(Error::new(ErrorKind::InvalidArg, ClusterError::NotStarted)),
+        };
+
+        let outbox = ZSock::new(SocketType::DEALER);
+        outbox.set_linger(0);
+        outbox.set_identity(&self.uuid)?;
+        outbox.connect(endpoint)?;
+
+        let hello = ZMsg::new();
+        hello.addstr(HELLO)?;
+        hello.addstr(own_endpoint)?;
+        hello.addstr(&self.name)?;
+        hello.addstr(&self.groups.len().to_string())?;
+        for group in &self.groups {
+            hello.addstr(group)?;
+        }
+        for (key, value) in &self.headers {
+            hello.addstr(key)?;
+            hello.addstr(value)?;
+        }
+        hello.send(&outbox)?;
+
+        Ok(outbox)
+    }
+
+    fn broadcast(&self, frames: &[&str]) -> Result<()> {
+        for peer in self.peers.values() {
+            ZMsg::from_frames(frames)?.send(&peer.outbox)?;
+        }
+        Ok(())
+    }
+
+    fn beacon_data(&self, port: u16) -> Vec<u8> {
+        let mut data = BEACON_PREFIX.to_vec();
+        data.extend_from_slice(self.uuid.as_bytes());
+        data.extend_from_slice(&port.to_be_bytes());
+        data
+    }
+}
+
+fn new_uuid() -> Result<String> {
+    match ZUuid::new() {
+        Some(uuid) => Ok(uuid.string),
+        None => Err(Error::new(ErrorKind::NullPtr, ClusterError::CmdFailed)),
+    }
+}
+
+#[derive(Debug)]
+pub enum ClusterError {
+    CmdFailed,
+    InvalidEndpoint,
+    NoInterface,
+    NotStarted,
+    Started,
+    UnknownPeer,
+}
+
+impl fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClusterError::CmdFailed => write!(f, "Could not generate node UUID"),
+            ClusterError::InvalidEndpoint => write!(f, "Bound endpoint is not valid UTF-8"),
+            ClusterError::NoInterface => write!(f, "No interface to beacon on"),
+            ClusterError::NotStarted => write!(f, "Node has not been started"),
+            ClusterError::Started => write!(f, "Node has already been started"),
+            ClusterError::UnknownPeer => write!(f, "Unknown peer"),
+        }
+    }
+}
+
+impl error::Error for ClusterError {
+    fn description(&self) -> &str {
+        match *self {
+            ClusterError::CmdFailed => "Could not generate node UUID",
+            ClusterError::InvalidEndpoint => "Bound endpoint is not valid UTF-8",
+            ClusterError::NoInterface => "No interface to beacon on",
+            ClusterError::NotStarted => "Node has not been started",
+            ClusterError::Started => "Node has already been started",
+            ClusterError::UnknownPeer => "Unknown peer",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZSys};
+    use std::time::{Duration, Instant};
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn node(name: &str) -> ClusterNode {
+        let mut node = ClusterNode::new(name).unwrap();
+        node.set_endpoint("tcp://127.0.0.1:*");
+        node.set_interval(TICK, 4);
+        node
+    }
+
+    // Poll both nodes, collecting their events, until `done` is true
+    fn pump<F>(a: &mut ClusterNode, b: &mut ClusterNode, events: &mut (Vec<ClusterEvent>, Vec<ClusterEvent>), done: F)
+        where F: Fn(&[ClusterEvent], &[ClusterEvent]) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+
+        while !done(&events.0, &events.1) {
+            assert!(Instant::now() < deadline, "timed out: {:?}", events);
+            if let Some(event) = a.poll(Duration::from_millis(10)).unwrap() {
+                events.0.push(event);
+            }
+            if let Some(event) = b.poll(Duration::from_millis(10)).unwrap() {
+                events.1.push(event);
+            }
+        }
+    }
+
+    fn has<F: Fn(&ClusterEvent) -> bool>(events: &[ClusterEvent], f: F) -> bool {
+        events.iter().any(f)
+    }
+
+    #[test]
+    fn test_cluster() {
+        ZSys::init();
+
+        let mut moo = node("moo");
+        moo.join("farm").unwrap();
+        moo.set_header("X-SOUND", "moo");
+        moo.start().unwrap();
+
+        let mut baa = node("baa");
+        baa.start().unwrap();
+        baa.connect(moo.endpoint().unwrap()).unwrap();
+
+        let mut events = (Vec::new(), Vec::new());
+        pump(&mut moo, &mut baa, &mut events, |m, b| {
+            has(m, |e| matches!(e, ClusterEvent::Enter { name, .. } if name == "baa")) &&
+            has(b, |e| matches!(e, ClusterEvent::Join { group, .. } if group == "farm"))
+        });
+        assert_eq!(baa.peer_header(moo.uuid(), "X-SOUND"), Some("moo"));
+        assert_eq!(baa.peers_by_group("farm").collect::<Vec<_>>(), [moo.uuid()]);
+
+        baa.join("farm").unwrap();
+        baa.shout("farm", ZMsg::from_frames(&[b"hello farm"]).unwrap()).unwrap();
+        moo.whisper(baa.uuid(), ZMsg::from_frames(&[b"hello baa"]).unwrap()).unwrap();
+
+        pump(&mut moo, &mut baa, &mut events, |m, b| {
+            has(m, |e| matches!(e, ClusterEvent::Shout { msg, .. } if msg.iter().next() == Some(&b"hello farm"[..]))) &&
+            has(b, |e| matches!(e, ClusterEvent::Whisper { msg, .. } if msg.iter().next() == Some(&b"hello baa"[..])))
+        });
+        assert!(has(&events.0, |e| matches!(e, ClusterEvent::Join { name, .. } if name == "baa")));
+
+        // A node that stops answering pings leaves
+        baa.stop().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            assert!(Instant::now() < deadline, "baa never left");
+            if let Some(ClusterEvent::Exit { name, .. }) = moo.poll(TICK).unwrap() {
+                assert_eq!(name, "baa");
+                break;
+            }
+        }
+        assert_eq!(moo.peers().count(), 0);
+    }
+
+    #[test]
+    fn test_not_started() {
+        ZSys::init();
+
+        let mut node = ClusterNode::new("moo").unwrap();
+        assert_eq!(node.poll(TICK).unwrap_err().kind(), ErrorKind::InvalidArg);
+        assert_eq!(node.connect("tcp://127.0.0.1:5670").unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+}
@@ -0,0 +1,269 @@
+//! Module: czmq-compression
+//!
+//! Transparent compression of large frames, for shipping bulky
+//! payloads like JSON over slow links. Enabled with the `zstd` and
+//! `lz4` features, one for each codec.
+//!
+//! `compress()` compresses each frame of at least `threshold` bytes
+//! that shrinks, and pushes a header frame onto the message saying
+//! which codec it used and which frames it compressed.
+//! `decompress()` finds the header, restores those frames and removes
+//! it. Messages without a header pass through `decompress()` as they
+//! are, so peers that don't compress can still talk to those that do.
+//! `CompressedSock` does both as messages are sent and received.
+//!
+//! The header frame is `COMPRESSION_MAGIC`, a codec byte, then a
+//! bitmap of the compressed frames that follow, least significant
+//! bit first.
+
+use crate::{Error, ErrorKind, Result, SocketType, ZFrame, ZMsg, ZSock};
+use std::{error, fmt};
+
+/// The start of a compression header frame.
+pub const COMPRESSION_MAGIC: &[u8] = b"\xffCMP";
+
+// Refuse to inflate a frame past this, rather than let a malicious
+// peer exhaust our memory
+const DECOMPRESSED_MAX: usize = 64 * 1024 * 1024;
+
+#[cfg(feature = "zstd")]
+const CODEC_ZSTD: u8 = 1;
+#[cfg(feature = "lz4")]
+const CODEC_LZ4: u8 = 2;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Codec {
+    /// Zstandard at the given level; 3 is zstd's default.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    /// LZ4, which is faster than zstd but compresses less.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Codec {
+    fn id(&self) -> u8 {
+        match *self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(_) => CODEC_ZSTD,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => CODEC_LZ4,
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => Ok(zstd::bulk::compress(data, level)?),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        }
+    }
+}
+
+fn decompress_frame(codec: u8, data: &[u8]) -> Result<Vec<u8>> {
+    match codec {
+        #[cfg(feature = "zstd")]
+        CODEC_ZSTD => zstd::bulk::decompress(data, DECOMPRESSED_MAX).map_err(|_| corrupt()),
+        #[cfg(feature = "lz4")]
+        CODEC_LZ4 => {
+            // The uncompressed size is prepended as a little-endian u32
+            let size = data.iter().take(4).rev().fold(0usize, |n, &b| n << 8 | b as usize);
+            if data.len() < 4 || size > DECOMPRESSED_MAX {
+                return Err(corrupt());
+            }
+            lz4_flex::decompress_size_prepended(data).map_err(|_| corrupt())
+        },
+        _ => Err(Error::new(ErrorKind::InvalidArg, CompressionError::UnknownCodec(codec))),
+    }
+}
+
+fn corrupt() -> Error {
+    Error::new(ErrorKind::InvalidArg, CompressionError::Corrupt)
+}
+
+/// Compress the frames of `msg` that are at least `threshold` bytes,
+/// returning a new message with a header frame in front. The message
+/// is returned unchanged if no frame was worth compressing.
+pub fn compress(msg: ZMsg, codec: Codec, threshold: usize) -> Result<ZMsg> {
+    let mut bitmap = Vec::new();
+    let out = ZMsg::new();
+
+    for (i, frame) in msg.iter().enumerate() {
+        if i % 8 == 0 {
+            bitmap.push(0);
+        }
+
+        let compressed = if frame.len() >= threshold { Some(codec.compress(frame)?) } else { None };
+        match compressed {
+            Some(ref c) if c.len() < frame.len() => {
+                bitmap[i / 8] |= 1 << (i % 8);
+                out.addbytes(c)?;
+            },
+            _ => out.addbytes(frame)?,
+        }
+    }
+
+    if bitmap.iter().all(|&b| b == 0) {
+        return Ok(msg);
+    }
+
+    let mut header = COMPRESSION_MAGIC.to_vec();
+    header.push(codec.id());
+    header.extend_from_slice(&bitmap);
+    out.pushbytes(&header)?;
+    Ok(out)
+}
+
+/// Undo `compress()`. Frames in front of the header, such as a
+/// ROUTER's routing id, are left alone.
+pub fn decompress(msg: ZMsg) -> Result<ZMsg> {
+    let frames: Vec<Vec<u8>> = msg.iter().map(|f| f.to_vec()).collect();
+    let pos = match frames.iter().position(|f| f.starts_with(COMPRESSION_MAGIC) && f.len() > COMPRESSION_MAGIC.len()) {
+        Some(pos) => pos,
+        None => return Ok(msg),
+    };
+
+    let codec = frames[pos][COMPRESSION_MAGIC.len()];
+    let bitmap = &frames[pos][COMPRESSION_MAGIC.len() + 1..];
+    let out = ZMsg::new();
+
+    for frame in &frames[..pos] {
+        out.addbytes(frame)?;
+    }
+
+    for (i, frame) in frames[pos + 1..].iter().enumerate() {
+        if bitmap.get(i / 8).map_or(false, |b| b & (1 << (i % 8)) != 0) {
+            out.append(ZFrame::new(&decompress_frame(codec, frame)?)?)?;
+        } else {
+            out.addbytes(frame)?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// A socket that compresses what it sends and decompresses what it
+/// receives. On a ROUTER socket, the routing id frame is kept in
+/// front of the header.
+pub struct CompressedSock {
+    sock: ZSock,
+    codec: Codec,
+    threshold: usize,
+}
+
+impl CompressedSock {
+    /// Compress frames of at least `threshold` bytes with `codec`.
+    pub fn new(sock: ZSock, codec: Codec, threshold: usize) -> CompressedSock {
+        CompressedSock {
+            sock: sock,
+            codec: codec,
+            threshold: threshold,
+        }
+    }
+
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    pub fn into_inner(self) -> ZSock {
+        self.sock
+    }
+
+    pub fn send(&self, mut msg: ZMsg) -> Result<()> {
+        let routing_id = match self.sock.zsock_type() {
+            SocketType::ROUTER => msg.pop(),
+            _ => None,
+        };
+
+        let msg = compress(msg, self.codec, self.threshold)?;
+        if let Some(id) = routing_id {
+            msg.prepend(id)?;
+        }
+        msg.send(&self.sock)
+    }
+
+    pub fn recv(&self) -> Result<ZMsg> {
+        decompress(ZMsg::recv(&self.sock)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum CompressionError {
+    Corrupt,
+    UnknownCodec(u8),
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompressionError::Corrupt => write!(f, "Compressed frame is corrupt or too large"),
+            CompressionError::UnknownCodec(c) => write!(f, "Unknown or disabled compression codec: {}", c),
+        }
+    }
+}
+
+impl error::Error for CompressionError {
+    fn description(&self) -> &str {
+        match *self {
+            CompressionError::Corrupt => "Compressed frame is corrupt or too large",
+            CompressionError::UnknownCodec(_) => "Unknown or disabled compression codec",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+
+    fn codecs() -> Vec<Codec> {
+        let mut codecs = Vec::new();
+        #[cfg(feature = "zstd")]
+        codecs.push(Codec::Zstd(3));
+        #[cfg(feature = "lz4")]
+        codecs.push(Codec::Lz4);
+        codecs
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let big = "moo".repeat(1000);
+
+        for codec in codecs() {
+            let msg = ZMsg::from_frames(&["id", big.as_str(), "small", big.as_str()]).unwrap();
+            let compressed = compress(msg, codec, 100).unwrap();
+            assert_eq!(compressed.size(), 5);
+            assert!(compressed.content_size() < big.len());
+
+            let msg = decompress(compressed).unwrap();
+            let frames: Vec<&[u8]> = msg.iter().collect();
+            assert_eq!(frames, vec![&b"id"[..], big.as_bytes(), b"small", big.as_bytes()]);
+
+            // Nothing worth compressing, so no header
+            let msg = compress(ZMsg::from_frames(&["small"]).unwrap(), codec, 100).unwrap();
+            assert_eq!(msg.size(), 1);
+            assert_eq!(decompress(msg).unwrap().popstr().unwrap().unwrap(), "small");
+        }
+    }
+
+    #[test]
+    fn test_compressed_sock() {
+        ZSys::init();
+
+        for (i, codec) in codecs().into_iter().enumerate() {
+            let endpoint = format!("inproc://compression_test_{}", i);
+            let router = CompressedSock::new(ZSock::new_router(&endpoint).unwrap(), codec, 100);
+            let dealer = CompressedSock::new(ZSock::new_dealer(&endpoint).unwrap(), codec, 100);
+            let big = "baa".repeat(1000);
+
+            dealer.send(ZMsg::from_frames(&[big.as_str()]).unwrap()).unwrap();
+            let msg = router.recv().unwrap();
+            assert_eq!(msg.size(), 2);
+            assert_eq!(msg.ref_last().unwrap().as_bytes(), big.as_bytes());
+
+            router.send(msg).unwrap();
+            assert_eq!(dealer.recv().unwrap().popstr().unwrap().unwrap(), big);
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Module: czmq-credit
+//!
+//! Credit-based flow control over DEALER/ROUTER, for bulk transfers
+//! that must neither drop messages at the high water mark nor buffer
+//! without limit.
+//!
+//! Each `CreditedReceiver` grants its sender a window of credit, one
+//! message per credit. The `CreditedSender` only sends to receivers
+//! that have credit left, spreading messages between them, and blocks
+//! when none do. Receivers hand credit back in batches as they consume
+//! messages, so at most a window's worth of messages is ever in
+//! flight to each receiver.
+//!
+//! A credit grant is the frame `CREDIT` followed by the number of
+//! credits as 4 bytes, big endian. Messages are sent as is.
+
+use crate::{Error, ErrorKind, Result, SocketType, ZMsg, ZPoller, ZSock};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::{error, fmt};
+use std::time::{Duration, Instant};
+
+const CREDIT: &str = "CREDIT";
+
+/// Sends messages to `CreditedReceiver`s as their credit allows.
+pub struct CreditedSender {
+    sock: ZSock,
+    poller: ZPoller,
+    // Receivers with credit left, in the order they'll be sent to
+    receivers: VecDeque<(Vec<u8>, u32)>,
+}
+
+impl CreditedSender {
+    /// Bind a sender to `endpoint`.
+    pub fn new(endpoint: &str) -> Result<CreditedSender> {
+        let sock = ZSock::new_router(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(CreditedSender {
+            sock: sock,
+            poller: poller,
+            receivers: VecDeque::new(),
+        })
+    }
+
+    /// The total credit granted by all receivers.
+    pub fn credit(&mut self) -> Result<u32> {
+        self.take_credit(Some(Duration::default()))?;
+        Ok(self.receivers.iter().map(|&(_, credit)| credit).sum())
+    }
+
+    /// Send `msg` to the next receiver with credit, waiting for credit
+    /// if there is none. Fails with `ErrorKind::Timeout` if none is
+    /// granted within `timeout`. Waits forever if `timeout` is `None`.
+    pub fn send(&mut self, msg: ZMsg, timeout: Option<Duration>) -> Result<()> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        self.take_credit(Some(Duration::default()))?;
+
+        while self.receivers.is_empty() {
+            let wait = match deadline {
+                Some(deadline) if Instant::now() >= deadline => {
+                    return Err(Error::new(ErrorKind::Timeout, CreditError::NoCredit));
+                },
+                Some(deadline) => Some(deadline.checked_duration_since(Instant::now()).unwrap_or_default()),
+                None => None,
+            };
+            self.take_credit(wait)?;
+        }
+
+        let (receiver, credit) = self.receivers.pop_front().unwrap();
+        msg.pushbytes(&receiver)?;
+        msg.send(&self.sock)?;
+
+        if credit > 1 {
+            self.receivers.push_back((receiver, credit - 1));
+        }
+        Ok(())
+    }
+
+    // Wait up to `timeout`, or forever if `None`, for a credit grant,
+    // then take any others that are waiting.
+    fn take_credit(&mut self, mut timeout: Option<Duration>) -> Result<()> {
+        while self.poller.wait_timeout::<ZSock>(timeout)?.is_some() {
+            timeout = Some(Duration::default());
+
+            let mut msg = ZMsg::recv(&self.sock)?;
+            let (receiver, command, credit) = match (msg.pop(), msg.pop(), msg.pop()) {
+                (Some(receiver), Some(command), Some(credit)) => (receiver, command, credit),
+                _ => continue,
+            };
+
+            let credit = match credit.as_bytes().try_into() {
+                Ok(bytes) if command.as_bytes() == CREDIT.as_bytes() => u32::from_be_bytes(bytes),
+                _ => continue,
+            };
+
+            let receiver = receiver.as_bytes();
+            match self.receivers.iter_mut().find(|entry| entry.0 == receiver) {
+                Some(entry) => entry.1 += credit,
+                None if credit > 0 => self.receivers.push_back((receiver.to_vec(), credit)),
+                None => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Receives messages from a `CreditedSender`, granting credit as it
+/// goes.
+pub struct CreditedReceiver {
+    sock: ZSock,
+    poller: ZPoller,
+    window: u32,
+    consumed: u32,
+}
+
+impl CreditedReceiver {
+    /// Connect a receiver to `endpoint`, allowing up to `window`
+    /// messages in flight.
+    pub fn new(endpoint: &str, window: u32) -> Result<CreditedReceiver> {
+        if window == 0 {
+            return Err(Error::new(ErrorKind::InvalidArg, CreditError::EmptyWindow));
+        }
+
+        let sock = ZSock::new(SocketType::DEALER);
+        sock.set_linger(0);
+        sock.connect(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        let receiver = CreditedReceiver {
+            sock: sock,
+            poller: poller,
+            window: window,
+            consumed: 0,
+        };
+        receiver.grant(window)?;
+        Ok(receiver)
+    }
+
+    /// Wait for the next message, or return `None` if none arrives
+    /// within `timeout`. Waits forever if `timeout` is `None`.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<ZMsg>> {
+        if self.poller.wait_timeout::<ZSock>(timeout)?.is_none() {
+            return Ok(None);
+        }
+
+        let msg = ZMsg::recv(&self.sock)?;
+
+        // Hand credit back in batches of half a window, so the sender
+        // neither stalls nor gets a grant per message
+        self.consumed += 1;
+        if self.consumed >= (self.window / 2).max(1) {
+            self.grant(self.consumed)?;
+            self.consumed = 0;
+        }
+
+        Ok(Some(msg))
+    }
+
+    fn grant(&self, credit: u32) -> Result<()> {
+        let msg = ZMsg::new();
+        msg.addstr(CREDIT)?;
+        msg.addbytes(&credit.to_be_bytes())?;
+        msg.send(&self.sock)
+    }
+}
+
+#[derive(Debug)]
+pub enum CreditError {
+    EmptyWindow,
+    NoCredit,
+}
+
+impl fmt::Display for CreditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CreditError::EmptyWindow => write!(f, "Credit window must be at least 1"),
+            CreditError::NoCredit => write!(f, "No receiver granted credit in time"),
+        }
+    }
+}
+
+impl error::Error for CreditError {
+    fn description(&self) -> &str {
+        match *self {
+            CreditError::EmptyWindow => "Credit window must be at least 1",
+            CreditError::NoCredit => "No receiver granted credit in time",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZMsg, ZSys};
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_credit() {
+        ZSys::init();
+
+        let mut sender = CreditedSender::new("inproc://credit_test").unwrap();
+        let mut receiver = CreditedReceiver::new("inproc://credit_test", 4).unwrap();
+
+        for i in 0..4 {
+            sender.send(ZMsg::from_frames(&[i.to_string()]).unwrap(), Some(TICK)).unwrap();
+        }
+
+        // The window is full until the receiver catches up
+        let e = sender.send(ZMsg::new(), Some(TICK)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+        assert_eq!(sender.credit().unwrap(), 0);
+
+        for i in 0..2 {
+            let mut msg = receiver.recv(Some(TICK)).unwrap().unwrap();
+            assert_eq!(msg.popstr().unwrap().unwrap(), i.to_string());
+        }
+
+        sender.send(ZMsg::from_frames(&[b"4"]).unwrap(), Some(TICK)).unwrap();
+        assert_eq!(sender.credit().unwrap(), 1);
+
+        for i in 2..5 {
+            let mut msg = receiver.recv(Some(TICK)).unwrap().unwrap();
+            assert_eq!(msg.popstr().unwrap().unwrap(), i.to_string());
+        }
+        assert!(receiver.recv(Some(TICK)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_empty_window() {
+        ZSys::init();
+
+        let e = CreditedReceiver::new("inproc://credit_test_empty", 0).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+    }
+}
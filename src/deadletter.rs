@@ -0,0 +1,229 @@
+//! Module: czmq-deadletter
+//!
+//! Somewhere for undeliverable messages to go, so they can be
+//! inspected or replayed instead of vanishing. Brokers and sockets
+//! that would otherwise discard a message hand it to their
+//! `DeadLetterSink`, if they have one:
+//!
+//! * `PpQueue` and `PriorityBroker`, requests whose TTL ran out before
+//!   a worker was free;
+//! * `MdpBroker`, requests for services that no worker offers;
+//! * `MeteredSock::try_send()`, messages refused at the high-water
+//!   mark.
+//!
+//! A dead letter is the original message with three frames pushed in
+//! front: `DEAD_LETTER_MAGIC`, the reason, and a detail such as the
+//! service name, which may be empty. `DeadLetter::from_msg()` takes
+//! them off again. A sink either sends dead letters on a socket or
+//! appends them to a capture file, which `CaptureReader` can read
+//! back.
+
+use crate::{CaptureWriter, Error, ErrorKind, Result, ZMsg, ZSock};
+use std::{error, fmt};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The first frame of a dead letter.
+pub const DEAD_LETTER_MAGIC: &[u8] = b"\xffDLQ";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeadLetterReason {
+    /// The message's deadline passed. See `ZMsg::set_ttl()`.
+    Expired,
+    /// No worker could take the message.
+    NoWorker,
+    /// The socket was at its high-water mark.
+    Dropped,
+}
+
+impl DeadLetterReason {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DeadLetterReason::Expired => "expired",
+            DeadLetterReason::NoWorker => "no-worker",
+            DeadLetterReason::Dropped => "dropped",
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<DeadLetterReason> {
+        match bytes {
+            b"expired" => Some(DeadLetterReason::Expired),
+            b"no-worker" => Some(DeadLetterReason::NoWorker),
+            b"dropped" => Some(DeadLetterReason::Dropped),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeadLetterReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A dead letter taken apart.
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub reason: DeadLetterReason,
+    pub detail: String,
+    /// The message as it was when it was given up on.
+    pub msg: ZMsg,
+}
+
+impl DeadLetter {
+    pub fn from_msg(mut msg: ZMsg) -> Result<DeadLetter> {
+        let not_dead_letter = || Error::new(ErrorKind::InvalidArg, DeadLetterError::NotDeadLetter);
+
+        match msg.pop() {
+            Some(ref frame) if frame.as_bytes() == DEAD_LETTER_MAGIC => (),
+            _ => return Err(not_dead_letter()),
+        }
+        let reason = msg.pop().and_then(|f| DeadLetterReason::from_bytes(f.as_bytes())).ok_or_else(not_dead_letter)?;
+        let detail = msg.popstr().ok_or_else(not_dead_letter)?.map_err(|_| not_dead_letter())?;
+
+        Ok(DeadLetter {
+            reason: reason,
+            detail: detail,
+            msg: msg,
+        })
+    }
+}
+
+enum Target {
+    Socket(ZSock),
+    Journal(CaptureWriter<BufWriter<File>>),
+}
+
+/// Where dead letters are sent. Clones share the same socket or file,
+/// so one sink can collect from several brokers.
+#[derive(Clone)]
+pub struct DeadLetterSink {
+    target: Arc<Mutex<Target>>,
+    lost: Arc<AtomicU64>,
+}
+
+impl DeadLetterSink {
+    /// Send dead letters on `sock`. A dead letter that would block,
+    /// as the socket is at its high-water mark, is lost rather than
+    /// holding up the broker.
+    pub fn socket(sock: ZSock) -> DeadLetterSink {
+        DeadLetterSink::new(Target::Socket(sock))
+    }
+
+    /// Write dead letters to a new capture file at `path`, flushing
+    /// after each one.
+    pub fn journal<P: AsRef<Path>>(path: P) -> Result<DeadLetterSink> {
+        Ok(DeadLetterSink::new(Target::Journal(CaptureWriter::create(path)?)))
+    }
+
+    fn new(target: Target) -> DeadLetterSink {
+        DeadLetterSink {
+            target: Arc::new(Mutex::new(target)),
+            lost: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Send `msg` as a dead letter. On failure the dead letter is
+    /// counted as lost as well as the error returned.
+    pub fn send(&self, msg: ZMsg, reason: DeadLetterReason, detail: &str) -> Result<()> {
+        match self.try_send(msg, reason, detail) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.lost.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            },
+            Err(e) => {
+                self.lost.fetch_add(1, Ordering::SeqCst);
+                Err(e)
+            },
+        }
+    }
+
+    /// The number of dead letters that couldn't be delivered either.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::SeqCst)
+    }
+
+    fn try_send(&self, msg: ZMsg, reason: DeadLetterReason, detail: &str) -> Result<bool> {
+        msg.pushstr(detail)?;
+        msg.pushstr(reason.as_str())?;
+        msg.pushbytes(DEAD_LETTER_MAGIC)?;
+
+        // A panic while holding the lock can't leave the target in a
+        // worse state than a failed send
+        let mut target = self.target.lock().unwrap_or_else(|e| e.into_inner());
+        match *target {
+            Target::Socket(ref sock) if !sock.writable() => Ok(false),
+            Target::Socket(ref sock) => msg.send(sock).map(|_| true),
+            Target::Journal(ref mut journal) => {
+                journal.write(&msg)?;
+                journal.flush()?;
+                Ok(true)
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DeadLetterError {
+    NotDeadLetter,
+}
+
+impl fmt::Display for DeadLetterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeadLetterError::NotDeadLetter => write!(f, "Message is not a dead letter"),
+        }
+    }
+}
+
+impl error::Error for DeadLetterError {
+    fn description(&self) -> &str {
+        match *self {
+            DeadLetterError::NotDeadLetter => "Message is not a dead letter",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureReader, SocketType, ZSys};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_socket_sink() {
+        ZSys::init();
+
+        let (server, client) = crate::testing::pair(SocketType::PULL, SocketType::PUSH).unwrap();
+        let sink = DeadLetterSink::socket(client);
+        sink.clone().send(ZMsg::from_frames(&["moo"]).unwrap(), DeadLetterReason::NoWorker, "cow").unwrap();
+
+        let mut letter = DeadLetter::from_msg(ZMsg::recv(&server).unwrap()).unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::NoWorker);
+        assert_eq!(letter.detail, "cow");
+        assert_eq!(letter.msg.popstr().unwrap().unwrap(), "moo");
+        assert_eq!(sink.lost(), 0);
+
+        assert!(DeadLetter::from_msg(ZMsg::from_frames(&["moo"]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_journal_sink() {
+        let dir = TempDir::new("deadletter_test").unwrap();
+        let path = dir.path().join("dead.zcap");
+
+        let sink = DeadLetterSink::journal(&path).unwrap();
+        sink.send(ZMsg::from_frames(&["baa"]).unwrap(), DeadLetterReason::Expired, "").unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let mut letter = DeadLetter::from_msg(reader.read().unwrap().unwrap().msg).unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::Expired);
+        assert_eq!(letter.detail, "");
+        assert_eq!(letter.msg.popstr().unwrap().unwrap(), "baa");
+        assert!(reader.read().unwrap().is_none());
+    }
+}
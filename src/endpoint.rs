@@ -0,0 +1,362 @@
+//! Module: czmq-endpoint
+
+use crate::{Error, ErrorKind, Result, ZSys};
+use std::{error, fmt};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::result;
+use std::str::FromStr;
+
+/// A typed ZMQ endpoint, which can be passed anywhere a socket
+/// accepts an endpoint string.
+///
+/// ```rust
+/// use czmq::Endpoint;
+///
+/// let endpoint = Endpoint::Tcp { addr: "127.0.0.1".into(), port: 5555 };
+/// assert_eq!(endpoint.to_string(), "tcp://127.0.0.1:5555");
+/// assert_eq!("tcp://127.0.0.1:5555".parse::<Endpoint>().unwrap(), endpoint);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Endpoint {
+    Tcp { addr: String, port: u16 },
+    /// Bind to an ephemeral TCP port, optionally within a range. CZMQ
+    /// returns the port it chose from `ZSock::bind()`.
+    PortRange { addr: String, start: Option<u16>, end: Option<u16> },
+    Ipc(PathBuf),
+    Inproc(String),
+    Ws { addr: String, port: u16, path: String },
+    /// WebSocket over TLS. Set the certificate options on the socket
+    /// (see `ZSock::set_wss_cert_pem()`) before binding or connecting.
+    Wss { addr: String, port: u16, path: String },
+    /// A VMware VMCI socket, between a guest and its host. `None`
+    /// stands for `*`, which binds to any context id or an ephemeral
+    /// port.
+    Vmci { cid: Option<u32>, port: Option<u32> },
+    /// A TIPC address, without the `tipc://` prefix: a service range
+    /// like `{1000,0,10}` to bind, a service like `{1000,5}` to
+    /// connect to, or a port id like `<1.2.3:4>`.
+    Tipc(String),
+    /// Reliable multicast over raw IP, e.g.
+    /// `pgm://eth0;239.192.1.1:5555`. Needs root on most systems.
+    Pgm { interface: String, group: String, port: u16 },
+    /// PGM encapsulated in UDP, which doesn't need raw sockets.
+    Epgm { interface: String, group: String, port: u16 },
+    /// NACK-Oriented Reliable Multicast. `node_id` identifies the
+    /// sender, and defaults to one derived from the host address.
+    Norm { node_id: Option<u32>, interface: Option<String>, group: String, port: u16 },
+}
+
+impl Endpoint {
+    /// The transport part of the endpoint, e.g. `"tcp"`.
+    pub fn transport(&self) -> &'static str {
+        match *self {
+            Endpoint::Tcp { .. } | Endpoint::PortRange { .. } => "tcp",
+            Endpoint::Ipc(_) => "ipc",
+            Endpoint::Inproc(_) => "inproc",
+            Endpoint::Ws { .. } => "ws",
+            Endpoint::Wss { .. } => "wss",
+            Endpoint::Vmci { .. } => "vmci",
+            Endpoint::Tipc(_) => "tipc",
+            Endpoint::Pgm { .. } => "pgm",
+            Endpoint::Epgm { .. } => "epgm",
+            Endpoint::Norm { .. } => "norm",
+        }
+    }
+
+    /// Whether libzmq was built with this endpoint's transport.
+    pub fn is_supported(&self) -> bool {
+        transport_supported(self.transport())
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Endpoint::Tcp { ref addr, port } => write!(f, "tcp://{}:{}", addr, port),
+            Endpoint::PortRange { ref addr, start: None, end: None } => write!(f, "tcp://{}:*", addr),
+            Endpoint::PortRange { ref addr, start, end } => {
+                write!(f, "tcp://{}:*[", addr)?;
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, "-")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                write!(f, "]")
+            },
+            Endpoint::Ipc(ref path) => write!(f, "ipc://{}", path.display()),
+            Endpoint::Inproc(ref name) => write!(f, "inproc://{}", name),
+            Endpoint::Ws { ref addr, port, ref path } => write!(f, "ws://{}:{}/{}", addr, port, path.trim_start_matches('/')),
+            Endpoint::Wss { ref addr, port, ref path } => write!(f, "wss://{}:{}/{}", addr, port, path.trim_start_matches('/')),
+            Endpoint::Vmci { cid, port } => {
+                write!(f, "vmci://")?;
+                match cid {
+                    Some(cid) => write!(f, "{}:", cid)?,
+                    None => write!(f, "*:")?,
+                }
+                match port {
+                    Some(port) => write!(f, "{}", port),
+                    None => write!(f, "*"),
+                }
+            },
+            Endpoint::Tipc(ref addr) => write!(f, "tipc://{}", addr),
+            Endpoint::Pgm { ref interface, ref group, port } => write!(f, "pgm://{};{}:{}", interface, group, port),
+            Endpoint::Epgm { ref interface, ref group, port } => write!(f, "epgm://{};{}:{}", interface, group, port),
+            Endpoint::Norm { node_id, ref interface, ref group, port } => {
+                write!(f, "norm://")?;
+                if let Some(id) = node_id {
+                    write!(f, "{},", id)?;
+                }
+                if let Some(ref interface) = *interface {
+                    write!(f, "{};", interface)?;
+                }
+                write!(f, "{}:{}", group, port)
+            },
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Endpoint> {
+        let invalid = || Error::new(ErrorKind::InvalidArg, EndpointError::Invalid(s.to_string()));
+
+        let (transport, address) = match s.find("://") {
+            Some(i) => (&s[..i], &s[i + 3..]),
+            None => return Err(invalid()),
+        };
+
+        match transport {
+            "inproc" if !address.is_empty() => Ok(Endpoint::Inproc(address.to_string())),
+            "ipc" if !address.is_empty() => Ok(Endpoint::Ipc(PathBuf::from(address))),
+            "tcp" => {
+                let i = address.rfind(':').ok_or_else(&invalid)?;
+                let (addr, port) = (address[..i].to_string(), &address[i + 1..]);
+
+                if port == "*" {
+                    Ok(Endpoint::PortRange { addr: addr, start: None, end: None })
+                } else if port.starts_with("*[") && port.ends_with(']') {
+                    let range = &port[2..port.len() - 1];
+                    let dash = range.find('-').ok_or_else(&invalid)?;
+                    let start = parse_port(&range[..dash]).map_err(|_| invalid())?;
+                    let end = parse_port(&range[dash + 1..]).map_err(|_| invalid())?;
+                    Ok(Endpoint::PortRange { addr: addr, start: start, end: end })
+                } else {
+                    let port = port.parse().map_err(|_| invalid())?;
+                    Ok(Endpoint::Tcp { addr: addr, port: port })
+                }
+            },
+            "ws" | "wss" => {
+                let (hostport, path) = match address.find('/') {
+                    Some(i) => (&address[..i], &address[i + 1..]),
+                    None => (address, ""),
+                };
+                let i = hostport.rfind(':').ok_or_else(&invalid)?;
+                let (addr, port) = (hostport[..i].to_string(), hostport[i + 1..].parse().map_err(|_| invalid())?);
+
+                if transport == "ws" {
+                    Ok(Endpoint::Ws { addr: addr, port: port, path: path.to_string() })
+                } else {
+                    Ok(Endpoint::Wss { addr: addr, port: port, path: path.to_string() })
+                }
+            },
+            "vmci" => {
+                let i = address.rfind(':').ok_or_else(&invalid)?;
+                let cid = parse_wildcard(&address[..i]).map_err(|_| invalid())?;
+                let port = parse_wildcard(&address[i + 1..]).map_err(|_| invalid())?;
+                Ok(Endpoint::Vmci { cid: cid, port: port })
+            },
+            "tipc" if (address.starts_with('{') && address.ends_with('}'))
+                   || (address.starts_with('<') && address.ends_with('>')) => Ok(Endpoint::Tipc(address.to_string())),
+            "pgm" | "epgm" => {
+                let (interface, address) = split_interface(address);
+                let interface = interface.ok_or_else(&invalid)?.to_string();
+                let (group, port) = split_port(address).ok_or_else(&invalid)?;
+
+                if transport == "pgm" {
+                    Ok(Endpoint::Pgm { interface: interface, group: group, port: port })
+                } else {
+                    Ok(Endpoint::Epgm { interface: interface, group: group, port: port })
+                }
+            },
+            "norm" => {
+                let (node_id, address) = match address.find(',') {
+                    Some(i) => (Some(address[..i].parse().map_err(|_| invalid())?), &address[i + 1..]),
+                    None => (None, address),
+                };
+                let (interface, address) = split_interface(address);
+                let (group, port) = split_port(address).ok_or_else(&invalid)?;
+                Ok(Endpoint::Norm { node_id: node_id, interface: interface.map(|i| i.to_string()), group: group, port: port })
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Split the `interface;` prefix off a multicast address.
+fn split_interface(address: &str) -> (Option<&str>, &str) {
+    match address.find(';') {
+        Some(i) => (Some(&address[..i]), &address[i + 1..]),
+        None => (None, address),
+    }
+}
+
+// Split a `group:port` pair.
+fn split_port(address: &str) -> Option<(String, u16)> {
+    let i = address.rfind(':')?;
+    let port = address[i + 1..].parse().ok()?;
+    if i == 0 {
+        None
+    } else {
+        Some((address[..i].to_string(), port))
+    }
+}
+
+// Parse a number that may be given as `*`.
+fn parse_wildcard(s: &str) -> result::Result<Option<u32>, ()> {
+    if s == "*" {
+        Ok(None)
+    } else {
+        s.parse().map(Some).map_err(|_| ())
+    }
+}
+
+// Transports that libzmq may have been built without. zmq_has()
+// reports PGM and EPGM together as "pgm".
+fn transport_supported(transport: &str) -> bool {
+    match transport {
+        "ipc" | "norm" | "tipc" | "vmci" | "ws" | "wss" => ZSys::has(transport),
+        "pgm" | "epgm" => ZSys::has("pgm"),
+        _ => true,
+    }
+}
+
+/// Fail early, with a clear error, if an endpoint's transport isn't
+/// available.
+pub(crate) fn check_transport(endpoint: &str) -> Result<()> {
+    if let Some(i) = endpoint.find("://") {
+        let transport = &endpoint[..i];
+        if !transport_supported(transport) {
+            return Err(Error::new(ErrorKind::InvalidArg, EndpointError::Unsupported(transport.to_string())));
+        }
+    }
+
+    Ok(())
+}
+
+// Parse one end of a port range, which may be left open.
+fn parse_port(port: &str) -> result::Result<Option<u16>, ()> {
+    if port.is_empty() {
+        Ok(None)
+    } else {
+        port.parse().map(Some).map_err(|_| ())
+    }
+}
+
+/// Anything that can be used as an endpoint: strings in ZMQ's
+/// `transport://address` format, or an `Endpoint`.
+pub trait ToEndpoint {
+    fn to_endpoint(&self) -> Cow<str>;
+}
+
+impl ToEndpoint for str {
+    fn to_endpoint(&self) -> Cow<str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl ToEndpoint for String {
+    fn to_endpoint(&self) -> Cow<str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl ToEndpoint for Endpoint {
+    fn to_endpoint(&self) -> Cow<str> {
+        Cow::Owned(self.to_string())
+    }
+}
+
+impl<'a, T: ToEndpoint + ?Sized> ToEndpoint for &'a T {
+    fn to_endpoint(&self) -> Cow<str> {
+        (**self).to_endpoint()
+    }
+}
+
+#[derive(Debug)]
+pub enum EndpointError {
+    Invalid(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for EndpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EndpointError::Invalid(ref e) => write!(f, "Invalid endpoint: {}", e),
+            EndpointError::Unsupported(ref t) => write!(f, "libzmq was built without the {} transport", t),
+        }
+    }
+}
+
+impl error::Error for EndpointError {
+    fn description(&self) -> &str {
+        match *self {
+            EndpointError::Invalid(_) => "Invalid endpoint",
+            EndpointError::Unsupported(_) => "Transport is not supported by libzmq",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_display_parse() {
+        let endpoints = vec![
+            (Endpoint::Tcp { addr: "127.0.0.1".into(), port: 5555 }, "tcp://127.0.0.1:5555"),
+            (Endpoint::PortRange { addr: "*".into(), start: None, end: None }, "tcp://*:*"),
+            (Endpoint::PortRange { addr: "127.0.0.1".into(), start: Some(60000), end: None }, "tcp://127.0.0.1:*[60000-]"),
+            (Endpoint::PortRange { addr: "127.0.0.1".into(), start: Some(1), end: Some(2) }, "tcp://127.0.0.1:*[1-2]"),
+            (Endpoint::Ipc(PathBuf::from("/tmp/moo")), "ipc:///tmp/moo"),
+            (Endpoint::Inproc("moo".into()), "inproc://moo"),
+            (Endpoint::Ws { addr: "localhost".into(), port: 80, path: "cow".into() }, "ws://localhost:80/cow"),
+            (Endpoint::Wss { addr: "localhost".into(), port: 443, path: "cow".into() }, "wss://localhost:443/cow"),
+            (Endpoint::Vmci { cid: Some(3), port: Some(5555) }, "vmci://3:5555"),
+            (Endpoint::Vmci { cid: None, port: None }, "vmci://*:*"),
+            (Endpoint::Tipc("{5560,0,3}".into()), "tipc://{5560,0,3}"),
+            (Endpoint::Tipc("<1.2.3:4>".into()), "tipc://<1.2.3:4>"),
+            (Endpoint::Pgm { interface: "eth0".into(), group: "239.192.1.1".into(), port: 5555 }, "pgm://eth0;239.192.1.1:5555"),
+            (Endpoint::Epgm { interface: "eth0".into(), group: "239.192.1.1".into(), port: 5555 }, "epgm://eth0;239.192.1.1:5555"),
+            (Endpoint::Norm { node_id: Some(2), interface: Some("eth0".into()), group: "224.1.2.3".into(), port: 5556 }, "norm://2,eth0;224.1.2.3:5556"),
+            (Endpoint::Norm { node_id: None, interface: None, group: "224.1.2.3".into(), port: 5556 }, "norm://224.1.2.3:5556"),
+        ];
+
+        for (endpoint, s) in endpoints {
+            assert_eq!(endpoint.to_string(), s);
+            assert_eq!(s.parse::<Endpoint>().unwrap(), endpoint);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in &["moo", "tcp://localhost", "tcp://localhost:moo", "tcp://localhost:*[1]", "pgm://239.192.1.1:5555", "epgm://eth0;:5555", "norm://moo,224.1.2.3:5556", "inproc://", "wss://localhost/cow", "vmci://3", "vmci://moo:1", "tipc://5560"] {
+            assert!(s.parse::<Endpoint>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_check_transport() {
+        assert!(check_transport("tcp://127.0.0.1:5555").is_ok());
+        assert!(check_transport("inproc://moo").is_ok());
+        assert_eq!(Endpoint::Inproc("moo".into()).transport(), "inproc");
+        assert!(Endpoint::Inproc("moo".into()).is_supported());
+
+        let vmci = Endpoint::Vmci { cid: None, port: None };
+        assert_eq!(check_transport(&vmci.to_string()).is_ok(), vmci.is_supported());
+    }
+}
@@ -1,49 +1,83 @@
 //! Module: czmq-error
 
+use crate::ZSys;
 use std::borrow::Borrow;
 use std::convert::{From, Into};
 use std::error;
 use std::ffi::NulError;
+use std::io;
 use std::fmt::{Display, Formatter, Result};
+use std::os::raw::c_int;
 use std::str::Utf8Error;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ErrorKind {
+    Interrupted,
     InvalidArg,
     InvalidPath,
     InvalidPtr,
+    Io,
     MissingFrame,
     NonZero,
     NullPtr,
     StringConversion,
+    Terminated,
+    Timeout,
+    Unsupported,
 }
 
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
-    cause: Box<error::Error>,
+    cause: Box<dyn error::Error>,
 }
 
 impl Error {
     pub fn new<E>(kind: ErrorKind, error: E) -> Error
-        where E: Into<Box<error::Error>> {
+        where E: Into<Box<dyn error::Error>> {
         Error {
             kind: kind,
             cause: error.into(),
         }
     }
+
+    /// Create an error for a failed blocking call from the current
+    /// ZMQ errno. An interrupted call or a terminated context map to
+    /// `ErrorKind::Interrupted` and `ErrorKind::Terminated`
+    /// respectively; anything else falls back to `kind` and `error`.
+    pub fn from_errno<E>(kind: ErrorKind, error: E) -> Error
+        where E: Into<Box<dyn error::Error>> {
+        let errno = unsafe { czmq_sys::zmq_errno() };
+
+        if errno == zmq::Error::ETERM as c_int {
+            Error::new(ErrorKind::Terminated, zmq::Error::ETERM)
+        } else if errno == zmq::Error::EINTR as c_int {
+            Error::new(ErrorKind::Interrupted, zmq::Error::EINTR)
+        } else {
+            Error::new(kind, error)
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self.kind {
+            ErrorKind::Interrupted => write!(f, "Blocking call was interrupted: {}", self.cause),
             ErrorKind::InvalidArg => write!(f, "Argument was invalid: {}", self.cause),
             ErrorKind::InvalidPath => write!(f, "File path was invalid: {}", self.cause),
             ErrorKind::InvalidPtr => write!(f, "CZMQ returned invalid pointer: {}", self.cause),
+            ErrorKind::Io => write!(f, "I/O error: {}", self.cause),
             ErrorKind::MissingFrame => write!(f, "Missing frame in CZMQ reply: {}", self.cause),
             ErrorKind::NonZero => write!(f, "CZMQ returned non-zero code: {}", self.cause),
             ErrorKind::NullPtr => write!(f, "CZMQ returned null pointer: {}", self.cause),
             ErrorKind::StringConversion => write!(f, "String conversion error: {}", self.cause),
+            ErrorKind::Terminated => write!(f, "ZMQ context was terminated: {}", self.cause),
+            ErrorKind::Timeout => write!(f, "Operation timed out: {}", self.cause),
+            ErrorKind::Unsupported => write!(f, "Not supported by the linked libraries: {}", self.cause),
         }
     }
 }
@@ -51,17 +85,22 @@ impl Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match self.kind {
+            ErrorKind::Interrupted => "Blocking call was interrupted",
             ErrorKind::InvalidArg => "Argument was invalid",
             ErrorKind::InvalidPath => "File path was invalid",
             ErrorKind::InvalidPtr => "CZMQ returned invalid pointer",
+            ErrorKind::Io => "I/O error",
             ErrorKind::MissingFrame => "Missing frame in CZMQ reply",
             ErrorKind::NonZero => "CZMQ returned non-zero code",
             ErrorKind::NullPtr => "CZMQ returned null pointer",
             ErrorKind::StringConversion => "Could not convert string to required type",
+            ErrorKind::Terminated => "ZMQ context was terminated",
+            ErrorKind::Timeout => "Operation timed out",
+            ErrorKind::Unsupported => "Not supported by the linked libraries",
         }
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn cause(&self) -> Option<&dyn error::Error> {
         Some(self.cause.borrow())
     }
 }
@@ -72,8 +111,36 @@ impl From<NulError> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(ioe: io::Error) -> Error {
+        Error::new(ErrorKind::Io, ioe)
+    }
+}
+
 impl From<Utf8Error> for Error {
     fn from(u8e: Utf8Error) -> Error {
         Error::new(ErrorKind::StringConversion, u8e)
     }
 }
+
+/// Run a blocking call, repeating it for as long as it fails with
+/// EINTR. Retries only happen when enabled via
+/// `ZSys::set_retry_interrupted()`, and never when CZMQ's own signal
+/// handler has flagged an interrupt (e.g. SIGINT), as that should
+/// always break the call so the application can shut down.
+pub fn retry_interrupted<T, F, P>(mut call: F, failed: P) -> T
+    where F: FnMut() -> T,
+          P: Fn(&T) -> bool {
+    loop {
+        let rv = call();
+
+        if failed(&rv) &&
+           ZSys::retry_interrupted() &&
+           !ZSys::is_interrupted() &&
+           unsafe { czmq_sys::zmq_errno() } == zmq::Error::EINTR as c_int {
+            continue;
+        }
+
+        return rv;
+    }
+}
@@ -0,0 +1,251 @@
+//! Module: czmq-framed
+//!
+//! Raw TCP protocols over a STREAM socket, framed with tokio-util
+//! codecs. A STREAM socket hands over each peer's bytes as they
+//! arrive, in chunks that needn't line up with the protocol's
+//! messages, so `StreamFramed` keeps a buffer and a codec for every
+//! connection and decodes whole items from it. This lets a czmq
+//! event loop terminate protocols like HTTP or Redis itself.
+//!
+//! Peers are identified by the routing id the STREAM socket gives
+//! their connection.
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZPoller, ZSock};
+use bytes::BytesMut;
+use std::collections::{HashMap, VecDeque};
+use std::{error, fmt};
+use std::time::Duration;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Something that happened on a `StreamFramed` connection.
+#[derive(Debug)]
+pub enum StreamEvent<T> {
+    Connected(Vec<u8>),
+    /// An item decoded from a peer's bytes.
+    Item(Vec<u8>, T),
+    /// The peer closed its connection. Any items left in its buffer
+    /// are decoded first.
+    Disconnected(Vec<u8>),
+    /// A peer's bytes couldn't be decoded, so its connection has been
+    /// closed.
+    Failed(Vec<u8>, Error),
+}
+
+/// Applies a codec per peer to the traffic of a STREAM socket.
+pub struct StreamFramed<C: Decoder> {
+    sock: ZSock,
+    poller: ZPoller,
+    new_codec: Box<dyn FnMut() -> C + Send>,
+    peers: HashMap<Vec<u8>, Peer<C>>,
+    events: VecDeque<StreamEvent<<C as Decoder>::Item>>,
+}
+
+struct Peer<C> {
+    codec: C,
+    buffer: BytesMut,
+}
+
+impl<C: Decoder> StreamFramed<C>
+    where <C as Decoder>::Error: Into<Box<dyn error::Error>> {
+    /// Frame the traffic of `sock`, which must be a STREAM socket,
+    /// with a codec from `new_codec` for each connection.
+    pub fn new<F>(sock: ZSock, new_codec: F) -> Result<StreamFramed<C>>
+        where F: FnMut() -> C + Send + 'static {
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(StreamFramed {
+            sock: sock,
+            poller: poller,
+            new_codec: Box::new(new_codec),
+            peers: HashMap::new(),
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    /// The number of connected peers.
+    pub fn peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Wait up to `timeout` for something to happen on a connection.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<StreamEvent<<C as Decoder>::Item>>> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(Some(event));
+        }
+
+        if self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(None);
+        }
+
+        while self.sock.readable() {
+            let mut msg = ZMsg::recv(&self.sock)?;
+            let (peer, data) = match (msg.popbytes()?, msg.popbytes()?) {
+                (Some(peer), Some(data)) => (peer, data),
+                _ => continue,
+            };
+            self.handle(peer, data)?;
+        }
+
+        Ok(self.events.pop_front())
+    }
+
+    /// Encode `item` and send it to `peer`.
+    pub fn send<I>(&mut self, peer: &[u8], item: I) -> Result<()>
+        where C: Encoder<I>,
+              <C as Encoder<I>>::Error: Into<Box<dyn error::Error>> {
+        let mut buffer = BytesMut::new();
+        match self.peers.get_mut(peer) {
+            Some(p) => p.codec.encode(item, &mut buffer).map_err(|e| Error::new(ErrorKind::InvalidArg, e))?,
+            None => return Err(Error::new(ErrorKind::InvalidArg, StreamFramedError::UnknownPeer)),
+        }
+
+        let msg = ZMsg::new();
+        msg.addbytes(peer)?;
+        msg.addbytes(&buffer)?;
+        msg.send(&self.sock)
+    }
+
+    /// Close the connection to `peer`.
+    pub fn close(&mut self, peer: &[u8]) -> Result<()> {
+        self.peers.remove(peer);
+
+        // An empty frame tells a STREAM socket to disconnect the peer
+        let msg = ZMsg::new();
+        msg.addbytes(peer)?;
+        msg.addbytes(&[])?;
+        msg.send(&self.sock)
+    }
+
+    // A STREAM socket sends an empty frame when a peer connects or
+    // disconnects, and the peer's bytes otherwise
+    fn handle(&mut self, peer: Vec<u8>, data: Vec<u8>) -> Result<()> {
+        if data.is_empty() {
+            match self.peers.remove(&peer) {
+                Some(mut p) => {
+                    let failed = loop {
+                        match p.codec.decode_eof(&mut p.buffer) {
+                            Ok(Some(item)) => self.events.push_back(StreamEvent::Item(peer.clone(), item)),
+                            Ok(None) => break None,
+                            Err(e) => break Some(e),
+                        }
+                    };
+                    match failed {
+                        Some(e) => self.events.push_back(StreamEvent::Failed(peer, Error::new(ErrorKind::InvalidArg, e))),
+                        None => self.events.push_back(StreamEvent::Disconnected(peer)),
+                    }
+                },
+                None => {
+                    let codec = (self.new_codec)();
+                    self.peers.insert(peer.clone(), Peer {
+                        codec: codec,
+                        buffer: BytesMut::new(),
+                    });
+                    self.events.push_back(StreamEvent::Connected(peer));
+                },
+            }
+            return Ok(());
+        }
+
+        let p = match self.peers.get_mut(&peer) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        p.buffer.extend_from_slice(&data);
+
+        loop {
+            match p.codec.decode(&mut p.buffer) {
+                Ok(Some(item)) => self.events.push_back(StreamEvent::Item(peer.clone(), item)),
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    self.events.push_back(StreamEvent::Failed(peer.clone(), Error::new(ErrorKind::InvalidArg, e)));
+                    return self.close(&peer);
+                },
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamFramedError {
+    UnknownPeer,
+}
+
+impl fmt::Display for StreamFramedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamFramedError::UnknownPeer => write!(f, "Peer is not connected"),
+        }
+    }
+}
+
+impl error::Error for StreamFramedError {
+    fn description(&self) -> &str {
+        match *self {
+            StreamFramedError::UnknownPeer => "Peer is not connected",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZSock, ZSys};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use tokio_util::codec::LinesCodec;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_lines() {
+        ZSys::init();
+
+        let sock = ZSock::new_stream("@tcp://127.0.0.1:*").unwrap();
+        let endpoint = sock.last_endpoint().unwrap().unwrap();
+        let mut framed = StreamFramed::new(sock, LinesCodec::new).unwrap();
+
+        let mut client = TcpStream::connect(endpoint.trim_start_matches("tcp://")).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        let peer = match framed.poll(Duration::from_secs(1)).unwrap() {
+            Some(StreamEvent::Connected(peer)) => peer,
+            e => panic!("expected a connection, got {:?}", e),
+        };
+
+        // Lines split across writes are put back together
+        client.write_all(b"moo\nco").unwrap();
+        client.flush().unwrap();
+        match framed.poll(Duration::from_secs(1)).unwrap() {
+            Some(StreamEvent::Item(p, line)) => {
+                assert_eq!(p, peer);
+                assert_eq!(line, "moo");
+            },
+            e => panic!("expected a line, got {:?}", e),
+        }
+        assert!(framed.poll(TICK).unwrap().is_none());
+
+        client.write_all(b"w\n").unwrap();
+        match framed.poll(Duration::from_secs(1)).unwrap() {
+            Some(StreamEvent::Item(_, line)) => assert_eq!(line, "cow"),
+            e => panic!("expected a line, got {:?}", e),
+        }
+
+        framed.send(&peer, "baa").unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"baa\n");
+
+        drop(client);
+        match framed.poll(Duration::from_secs(1)).unwrap() {
+            Some(StreamEvent::Disconnected(p)) => assert_eq!(p, peer),
+            e => panic!("expected a disconnection, got {:?}", e),
+        }
+        assert_eq!(framed.peers(), 0);
+    }
+}
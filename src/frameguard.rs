@@ -0,0 +1,104 @@
+//! Module: czmq-frameguard
+
+use crate::{Result, SocketLike, ZFrame};
+use std::ops::Deref;
+
+/// Owns a received frame and lends out its bytes for zero-copy
+/// deserializers such as capnp or flatbuffers.
+///
+/// # Safety
+///
+/// A frame's data pointer stays put for as long as the frame lives
+/// and isn't modified, but `ZFrame` allows modification through a
+/// shared reference (e.g. `ZFrame::reset()`). `FrameGuard` doesn't
+/// hand out the frame itself, so once a frame is guarded its bytes
+/// can't change, and the borrow checker ensures that no view outlives
+/// the guard.
+///
+/// Small frames may be stored inline in the underlying `zmq_msg_t`,
+/// so the data isn't guaranteed to be word aligned. Formats that
+/// require alignment (capnp reads 8-byte words) should check
+/// `is_aligned()` and fall back to copying if it returns false.
+///
+/// ```rust
+/// use czmq::{FrameGuard, ZSock, ZSys};
+///
+/// ZSys::init();
+///
+/// let server = ZSock::new_pull("@inproc://frameguard_doc").unwrap();
+/// let client = ZSock::new_push(">inproc://frameguard_doc").unwrap();
+/// client.send_str("moo").unwrap();
+///
+/// let guard = FrameGuard::recv(&server).unwrap();
+/// let view: &[u8] = &guard;
+/// assert_eq!(view, b"moo");
+/// ```
+#[derive(Debug)]
+pub struct FrameGuard {
+    frame: ZFrame,
+}
+
+impl FrameGuard {
+    pub fn new(frame: ZFrame) -> FrameGuard {
+        FrameGuard { frame: frame }
+    }
+
+    pub fn recv<S: SocketLike>(source: S) -> Result<FrameGuard> {
+        ZFrame::recv(source).map(FrameGuard::new)
+    }
+
+    /// Borrow the frame's bytes for as long as the guard lives.
+    pub fn bytes(&self) -> &[u8] {
+        self.frame.as_bytes()
+    }
+
+    /// Whether the frame's data starts on an `align`-byte boundary.
+    pub fn is_aligned(&self, align: usize) -> bool {
+        self.bytes().as_ptr() as usize % align == 0
+    }
+
+    /// Give up the guard and take back the frame.
+    pub fn into_frame(self) -> ZFrame {
+        self.frame
+    }
+}
+
+impl From<ZFrame> for FrameGuard {
+    fn from(frame: ZFrame) -> FrameGuard {
+        FrameGuard::new(frame)
+    }
+}
+
+impl Deref for FrameGuard {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
+impl AsRef<[u8]> for FrameGuard {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZFrame;
+
+    #[test]
+    fn test_view() {
+        let frame = ZFrame::new(&[0u8; 64]).unwrap();
+        let ptr = frame.as_bytes().as_ptr();
+
+        let guard = FrameGuard::from(frame);
+        assert_eq!(guard.len(), 64);
+        assert_eq!(guard.bytes().as_ptr(), ptr);
+        assert!(guard.is_aligned(1));
+
+        let frame = guard.into_frame();
+        assert_eq!(frame.as_bytes().as_ptr(), ptr);
+    }
+}
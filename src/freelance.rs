@@ -0,0 +1,307 @@
+//! Module: czmq-freelance
+//!
+//! The Freelance pattern: brokerless reliable request-reply. A client
+//! connects a ROUTER socket to several servers, each of which uses its
+//! endpoint as its identity. The client pings every server, and sends
+//! each request to the first server that has recently answered. If
+//! that server goes quiet before replying, the request is resent to
+//! the next live one.
+
+use crate::{Error, ErrorKind, Result, SocketType, ZMsg, ZPoller, ZSock};
+use std::{cmp, error, fmt};
+use std::time::{Duration, Instant};
+
+const PING: &str = "PING";
+const PONG: &str = "PONG";
+
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+const SERVER_LIVENESS: u32 = 3;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct Server {
+    endpoint: String,
+    alive: bool,
+    ping_at: Instant,
+    expiry: Instant,
+}
+
+/// Sends requests to whichever of a set of servers is alive.
+pub struct FreelanceClient {
+    sock: ZSock,
+    poller: ZPoller,
+    servers: Vec<Server>,
+    sequence: u64,
+    ping_interval: Duration,
+    timeout: Duration,
+}
+
+impl FreelanceClient {
+    pub fn new(endpoints: &[&str]) -> Result<FreelanceClient> {
+        let sock = ZSock::new(SocketType::ROUTER);
+        sock.set_linger(0);
+
+        let mut servers = Vec::new();
+        for endpoint in endpoints {
+            sock.connect(*endpoint)?;
+            servers.push(Server {
+                endpoint: endpoint.to_string(),
+                alive: false,
+                ping_at: Instant::now(),
+                expiry: Instant::now(),
+            });
+        }
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(FreelanceClient {
+            sock: sock,
+            poller: poller,
+            servers: servers,
+            sequence: 0,
+            ping_interval: PING_INTERVAL,
+            timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    /// How often to ping each server. A server is presumed dead after
+    /// three unanswered pings. Defaults to 2 seconds.
+    pub fn set_ping_interval(&mut self, interval: Duration) {
+        self.ping_interval = interval;
+    }
+
+    /// How long `request()` keeps trying before giving up. Defaults
+    /// to 3 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The endpoints of the servers currently believed to be alive.
+    pub fn live_servers(&self) -> Vec<&str> {
+        self.servers.iter().filter(|s| s.alive).map(|s| s.endpoint.as_str()).collect()
+    }
+
+    /// Send a request to a live server and wait for its reply,
+    /// failing over to another server if that one dies. Fails with
+    /// `ErrorKind::Timeout` if no server answers in time.
+    pub fn request(&mut self, body: ZMsg) -> Result<ZMsg> {
+        self.sequence += 1;
+        let sequence = self.sequence.to_string();
+        let deadline = Instant::now() + self.timeout;
+        let mut target: Option<usize> = None;
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(ErrorKind::Timeout, FreelanceError::NoServer));
+            }
+
+            for server in &mut self.servers {
+                if server.alive && now >= server.expiry {
+                    server.alive = false;
+                }
+
+                if now >= server.ping_at {
+                    let ping = ZMsg::new();
+                    ping.addstr(&server.endpoint)?;
+                    ping.addstr(PING)?;
+                    ping.send(&self.sock)?;
+                    server.ping_at = now + self.ping_interval;
+                }
+            }
+
+            if target.map_or(true, |i| !self.servers[i].alive) {
+                target = self.servers.iter().position(|s| s.alive);
+
+                if let Some(i) = target {
+                    let msg = body.dup()?;
+                    msg.pushstr(&sequence)?;
+                    msg.pushstr(&self.servers[i].endpoint)?;
+                    msg.send(&self.sock)?;
+                }
+            }
+
+            let next_ping = self.servers.iter().map(|s| s.ping_at).min().unwrap_or(deadline);
+            let wait = cmp::min(deadline, next_ping).checked_duration_since(now).unwrap_or_default();
+
+            if self.poller.wait_timeout::<ZSock>(Some(wait))?.is_some() {
+                let mut msg = ZMsg::recv(&self.sock)?;
+                let endpoint = msg.popstr().and_then(|s| s.ok());
+                let control = msg.popstr().and_then(|s| s.ok());
+
+                if let Some(server) = self.servers.iter_mut().find(|s| Some(&s.endpoint) == endpoint.as_ref()) {
+                    server.alive = true;
+                    server.expiry = Instant::now() + self.ping_interval * SERVER_LIVENESS;
+                }
+
+                // Anything else is a PONG or a late reply to an
+                // earlier request
+                if control.as_ref() == Some(&sequence) {
+                    return Ok(msg);
+                }
+            }
+        }
+    }
+}
+
+/// A request received by `FreelanceServer::recv()`.
+#[derive(Debug)]
+pub struct FreelanceRequest {
+    pub client: Vec<u8>,
+    pub sequence: Vec<u8>,
+    pub body: ZMsg,
+}
+
+/// Serves requests from `FreelanceClient`s, answering pings itself.
+pub struct FreelanceServer {
+    sock: ZSock,
+    poller: ZPoller,
+}
+
+impl FreelanceServer {
+    /// Bind to `endpoint`, which is also the server's identity, so it
+    /// must be given exactly as clients connect to it.
+    pub fn new(endpoint: &str) -> Result<FreelanceServer> {
+        let sock = ZSock::new(SocketType::ROUTER);
+        sock.set_identity(endpoint)?;
+        sock.bind(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(FreelanceServer {
+            sock: sock,
+            poller: poller,
+        })
+    }
+
+    /// Wait for the next request, or return `None` if none arrives
+    /// within `timeout`. Waits forever if `timeout` is `None`.
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Option<FreelanceRequest>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let wait = deadline.map(|d| d.checked_duration_since(Instant::now()).unwrap_or_default());
+
+            if self.poller.wait_timeout::<ZSock>(wait)?.is_none() {
+                if deadline.is_some() {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let mut msg = ZMsg::recv(&self.sock)?;
+            let (client, control) = match (msg.pop(), msg.pop()) {
+                (Some(client), Some(control)) => (client, control),
+                _ => continue,
+            };
+
+            if control.as_bytes() == PING.as_bytes() {
+                let pong = ZMsg::new();
+                pong.append(client)?;
+                pong.addstr(PONG)?;
+                pong.send(&self.sock)?;
+            } else {
+                return Ok(Some(FreelanceRequest {
+                    client: client.as_bytes().to_vec(),
+                    sequence: control.as_bytes().to_vec(),
+                    body: msg,
+                }));
+            }
+        }
+    }
+
+    /// Reply to the request with the given client and sequence.
+    pub fn reply(&self, client: &[u8], sequence: &[u8], body: ZMsg) -> Result<()> {
+        body.pushbytes(sequence)?;
+        body.pushbytes(client)?;
+        body.send(&self.sock)
+    }
+}
+
+#[derive(Debug)]
+pub enum FreelanceError {
+    NoServer,
+}
+
+impl fmt::Display for FreelanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FreelanceError::NoServer => write!(f, "No server replied in time"),
+        }
+    }
+}
+
+impl error::Error for FreelanceError {
+    fn description(&self) -> &str {
+        match *self {
+            FreelanceError::NoServer => "No server replied in time",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZMsg, ZSys};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn spawn_server(endpoint: &'static str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        let server = FreelanceServer::new(endpoint).unwrap();
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                if let Some(req) = server.recv(Some(TICK)).unwrap() {
+                    let body = ZMsg::new();
+                    body.addstr(endpoint).unwrap();
+                    server.reply(&req.client, &req.sequence, body).unwrap();
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_failover() {
+        ZSys::init();
+
+        let stop_a = Arc::new(AtomicBool::new(false));
+        let a = spawn_server("inproc://freelance_test_a", stop_a.clone());
+
+        let mut client = FreelanceClient::new(&["inproc://freelance_test_a", "inproc://freelance_test_b"]).unwrap();
+        client.set_ping_interval(TICK);
+        client.set_timeout(Duration::from_secs(2));
+
+        let mut reply = client.request(ZMsg::new()).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "inproc://freelance_test_a");
+
+        // Once A stops answering, requests fail over to B
+        let stop_b = Arc::new(AtomicBool::new(false));
+        let b = spawn_server("inproc://freelance_test_b", stop_b.clone());
+        stop_a.store(true, Ordering::SeqCst);
+        a.join().unwrap();
+
+        let mut reply = client.request(ZMsg::new()).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "inproc://freelance_test_b");
+        assert_eq!(client.live_servers(), vec!["inproc://freelance_test_b"]);
+
+        stop_b.store(true, Ordering::SeqCst);
+        b.join().unwrap();
+    }
+
+    #[test]
+    fn test_no_server() {
+        ZSys::init();
+
+        let mut client = FreelanceClient::new(&["inproc://freelance_test_none"]).unwrap();
+        client.set_ping_interval(TICK);
+        client.set_timeout(TICK * 2);
+
+        let e = client.request(ZMsg::new()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+}
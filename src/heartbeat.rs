@@ -0,0 +1,258 @@
+//! Module: czmq-heartbeat
+//!
+//! Heartbeating for any protocol. A `Heartbeat` decides when to ping
+//! peers and which peers have gone quiet for too long; the caller
+//! tells it whenever a peer is heard from, and sends the pings.
+//!
+//! A peer expires once it has been silent for `liveness` intervals,
+//! as with MDP and the Paranoid Pirate pattern. In a blocking loop,
+//! pass `timeout()` to the poller and call `poll()` after each wait.
+//! In async code, `next()` waits for the next event on a `Waiter`'s
+//! runtime, and can be raced against receives with `select!`:
+//!
+//! ```rust,ignore
+//! loop {
+//!     tokio::select! {
+//!         msg = sock.recv() => heartbeat.heard(&peer_of(&msg?)),
+//!         event = heartbeat.next::<Tokio>() => match event {
+//!             HeartbeatEvent::Ping => broadcast_ping(&mut sock).await?,
+//!             HeartbeatEvent::Expired(peer) => drop_peer(&peer),
+//!         },
+//!     }
+//! }
+//! ```
+
+use crate::{Clock, Result, SocketLike, ZMsg};
+use crate::clock;
+use crate::waiter::Waiter;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The frame sent by `Heartbeat::ping()`.
+pub const HEARTBEAT_PING: &str = "PING";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HeartbeatEvent {
+    /// Time to ping every peer.
+    Ping,
+    /// A peer hasn't been heard from within its liveness, and has
+    /// been forgotten.
+    Expired(Vec<u8>),
+}
+
+/// Tracks when to ping peers and when they expire.
+pub struct Heartbeat {
+    interval: Duration,
+    liveness: u32,
+    next_ping: Instant,
+    // When each peer was last heard from
+    peers: HashMap<Vec<u8>, Instant>,
+    expired: VecDeque<Vec<u8>>,
+    on_expired: Option<Box<dyn FnMut(&[u8]) + Send>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Heartbeat {
+    /// Ping every `interval`, and expire peers after `liveness`
+    /// intervals of silence.
+    pub fn new(interval: Duration, liveness: u32) -> Heartbeat {
+        Heartbeat {
+            interval: interval,
+            liveness: liveness,
+            next_ping: Instant::now() + interval,
+            peers: HashMap::new(),
+            expired: VecDeque::new(),
+            on_expired: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Replace the clock that pings and expiry are timed by. Any
+    /// peers already tracked are forgotten.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.next_ping = self.clock.now() + self.interval;
+        self.peers.clear();
+    }
+
+    /// Call `f` with each peer as it expires, before it is returned
+    /// as an event.
+    pub fn on_expired<F: FnMut(&[u8]) + Send + 'static>(&mut self, f: F) {
+        self.on_expired = Some(Box::new(f));
+    }
+
+    /// Note that `peer` has been heard from, tracking it if it's new.
+    /// Any message counts, not just pings.
+    pub fn heard(&mut self, peer: &[u8]) {
+        self.peers.insert(peer.to_vec(), self.clock.now());
+    }
+
+    /// Stop tracking `peer`, e.g. when it disconnects cleanly.
+    pub fn forget(&mut self, peer: &[u8]) {
+        self.peers.remove(peer);
+    }
+
+    pub fn is_alive(&self, peer: &[u8]) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    /// The peers being tracked.
+    pub fn peers(&self) -> impl Iterator<Item = &[u8]> {
+        self.peers.keys().map(|p| p.as_slice())
+    }
+
+    /// Send a ping to `peer` on `sock`. For a ROUTER socket, pass the
+    /// peer's routing id; for others, pass `None`.
+    pub fn ping<S: SocketLike>(&self, sock: S, peer: Option<&[u8]>) -> Result<()> {
+        let msg = ZMsg::new();
+        if let Some(peer) = peer {
+            msg.addbytes(peer)?;
+        }
+        msg.addstr(HEARTBEAT_PING)?;
+        msg.send(sock)
+    }
+
+    /// How long until the next event is due, for use as a poll
+    /// timeout.
+    pub fn timeout(&self) -> Duration {
+        self.deadline().saturating_duration_since(self.clock.now())
+    }
+
+    /// Return the next event that is due, if any.
+    pub fn poll(&mut self) -> Option<HeartbeatEvent> {
+        let now = self.clock.now();
+
+        if self.expired.is_empty() {
+            let expiry = self.expiry();
+            let expired: Vec<Vec<u8>> = self.peers.iter()
+                .filter(|&(_, &heard)| now >= heard + expiry)
+                .map(|(peer, _)| peer.clone())
+                .collect();
+
+            for peer in expired {
+                self.peers.remove(&peer);
+                if let Some(ref mut f) = self.on_expired {
+                    f(&peer);
+                }
+                self.expired.push_back(peer);
+            }
+        }
+
+        if let Some(peer) = self.expired.pop_front() {
+            return Some(HeartbeatEvent::Expired(peer));
+        }
+
+        if now >= self.next_ping {
+            self.next_ping = now + self.interval;
+            return Some(HeartbeatEvent::Ping);
+        }
+
+        None
+    }
+
+    /// Wait for the next event, using `W`'s runtime for timers. This
+    /// is cancellation safe; no event is lost if the future is
+    /// dropped.
+    pub async fn next<W: Waiter>(&mut self) -> HeartbeatEvent {
+        loop {
+            if let Some(event) = self.poll() {
+                return event;
+            }
+
+            W::sleep(self.timeout()).await;
+        }
+    }
+
+    fn expiry(&self) -> Duration {
+        self.interval * self.liveness
+    }
+
+    fn deadline(&self) -> Instant {
+        let expiry = self.expiry();
+        self.peers.values()
+            .map(|&heard| heard + expiry)
+            .fold(self.next_ping, |a, b| a.min(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZSock, ZSys};
+    use crate::asyncs::Blocking;
+    use crate::testing::MockClock;
+    use crate::waiter::block_on;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_expiry() {
+        let mut heartbeat = Heartbeat::new(TICK, 2);
+        let expired = Arc::new(Mutex::new(Vec::new()));
+        let seen = expired.clone();
+        heartbeat.on_expired(move |peer| seen.lock().unwrap().push(peer.to_vec()));
+
+        heartbeat.heard(b"moo");
+        heartbeat.heard(b"baa");
+        assert!(heartbeat.poll().is_none());
+
+        assert_eq!(block_on(heartbeat.next::<Blocking>()), HeartbeatEvent::Ping);
+
+        // Keep one peer alive past the other's expiry
+        thread::sleep(TICK);
+        heartbeat.heard(b"baa");
+        let mut events = Vec::new();
+        while events.len() < 2 {
+            events.push(block_on(heartbeat.next::<Blocking>()));
+        }
+        assert!(events.contains(&HeartbeatEvent::Expired(b"moo".to_vec())));
+
+        assert!(!heartbeat.is_alive(b"moo"));
+        assert!(heartbeat.is_alive(b"baa"));
+        assert_eq!(*expired.lock().unwrap(), vec![b"moo".to_vec()]);
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new();
+        let mut heartbeat = Heartbeat::new(TICK, 2);
+        heartbeat.set_clock(clock.clone());
+        heartbeat.heard(b"moo");
+
+        assert!(heartbeat.poll().is_none());
+        assert_eq!(heartbeat.timeout(), TICK);
+
+        clock.advance(TICK);
+        assert_eq!(heartbeat.poll(), Some(HeartbeatEvent::Ping));
+        assert!(heartbeat.poll().is_none());
+
+        clock.advance(TICK);
+        assert_eq!(heartbeat.poll(), Some(HeartbeatEvent::Expired(b"moo".to_vec())));
+        assert_eq!(heartbeat.poll(), Some(HeartbeatEvent::Ping));
+        assert!(!heartbeat.is_alive(b"moo"));
+    }
+
+    #[test]
+    fn test_ping() {
+        ZSys::init();
+
+        let router = ZSock::new_router("inproc://heartbeat_test").unwrap();
+        router.set_rcvtimeo(Some(1000));
+        let dealer = ZSock::new_dealer("inproc://heartbeat_test").unwrap();
+        dealer.set_rcvtimeo(Some(1000));
+
+        let heartbeat = Heartbeat::new(TICK, 3);
+        heartbeat.ping(&dealer, None).unwrap();
+
+        let mut msg = ZMsg::recv(&router).unwrap();
+        let peer = msg.popbytes().unwrap().unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), HEARTBEAT_PING);
+
+        heartbeat.ping(&router, Some(&peer)).unwrap();
+        assert_eq!(ZMsg::recv(&dealer).unwrap().popstr().unwrap().unwrap(), HEARTBEAT_PING);
+    }
+}
@@ -0,0 +1,247 @@
+//! Module: czmq-history
+//!
+//! Per-socket logs of recent operations, for post-mortem debugging of
+//! EFSM errors and silent drops. `ZSock::set_history()` starts
+//! recording the last few sends, receives, binds, connects and option
+//! changes on a socket, and `ZSock::debug_history()` returns them,
+//! oldest first. Failed operations are recorded with their error.
+//!
+//! The log is kept against the underlying CZMQ socket, so it covers
+//! every handle to it, including sends made with `ZMsg::send()` and
+//! `ZFrame::send()`. While no socket has a log, each hook costs an
+//! atomic load.
+//!
+//! Separately, and whether or not it has a log, every socket keeps
+//! its last failure for `ZSock::last_error()`. That helps when the
+//! failing call's `Result` was dropped in a callback or logged far
+//! from where it happened. Only failures pay for this.
+
+use crate::{ErrorKind, Result};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::os::raw::c_void;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+// How many sockets have a log
+static LOGGING: AtomicUsize = AtomicUsize::new(0);
+static LOGS: Mutex<Option<HashMap<usize, Log>>> = Mutex::new(None);
+// How many sockets have a last error
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+static LAST_ERRORS: Mutex<Option<HashMap<usize, LastError>>> = Mutex::new(None);
+
+struct Log {
+    capacity: usize,
+    events: VecDeque<HistoryEvent>,
+}
+
+/// An operation on a socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HistoryOp {
+    Send { frames: usize, bytes: usize },
+    Recv { frames: usize, bytes: usize },
+    Bind(String),
+    Unbind(String),
+    Connect(String),
+    Disconnect(String),
+    /// A `ZSock::signal()` with its status.
+    Signal(u8),
+    /// A `ZSock::wait()` for a signal.
+    Wait,
+    /// An option was set, with its name and value. Passwords and
+    /// secret keys are not recorded.
+    SetOption(&'static str, String),
+}
+
+impl fmt::Display for HistoryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HistoryOp::Send { frames, bytes } => write!(f, "send {} frames, {} bytes", frames, bytes),
+            HistoryOp::Recv { frames, bytes } => write!(f, "recv {} frames, {} bytes", frames, bytes),
+            HistoryOp::Bind(ref e) => write!(f, "bind {}", e),
+            HistoryOp::Unbind(ref e) => write!(f, "unbind {}", e),
+            HistoryOp::Connect(ref e) => write!(f, "connect {}", e),
+            HistoryOp::Disconnect(ref e) => write!(f, "disconnect {}", e),
+            HistoryOp::Signal(status) => write!(f, "signal {}", status),
+            HistoryOp::Wait => write!(f, "wait"),
+            HistoryOp::SetOption(name, ref value) => write!(f, "set {} = {}", name, value),
+        }
+    }
+}
+
+/// A recorded operation.
+#[derive(Clone, Debug)]
+pub struct HistoryEvent {
+    pub time: SystemTime,
+    pub op: HistoryOp,
+    /// The error the operation failed with, if it did.
+    pub error: Option<String>,
+}
+
+impl fmt::Display for HistoryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        if let Some(ref e) = self.error {
+            write!(f, " failed: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// The last operation on a socket that failed.
+#[derive(Clone, Debug)]
+pub struct LastError {
+    pub time: SystemTime,
+    pub op: HistoryOp,
+    pub kind: ErrorKind,
+    /// libzmq's errno after the failure, for errors raised by CZMQ or
+    /// libzmq. `None` for errors caught before reaching the library,
+    /// such as an invalid argument.
+    pub errno: Option<i32>,
+    pub message: String,
+}
+
+impl fmt::Display for LastError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.op, self.message)?;
+        if let Some(errno) = self.errno {
+            write!(f, " (errno {})", errno)?;
+        }
+        Ok(())
+    }
+}
+
+fn logs() -> MutexGuard<'static, Option<HashMap<usize, Log>>> {
+    LOGS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn last_errors() -> MutexGuard<'static, Option<HashMap<usize, LastError>>> {
+    LAST_ERRORS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn logging() -> bool {
+    LOGGING.load(Ordering::Relaxed) > 0
+}
+
+/// Keep the last `capacity` events for `sock`, or stop logging it if
+/// `capacity` is 0.
+pub(crate) fn set_capacity(sock: *mut c_void, capacity: usize) {
+    let mut logs = logs();
+    let logs = logs.get_or_insert_with(HashMap::new);
+
+    if capacity == 0 {
+        if logs.remove(&(sock as usize)).is_some() {
+            LOGGING.fetch_sub(1, Ordering::SeqCst);
+        }
+        return;
+    }
+
+    let log = logs.entry(sock as usize).or_insert_with(|| {
+        LOGGING.fetch_add(1, Ordering::SeqCst);
+        Log {
+            capacity: capacity,
+            events: VecDeque::new(),
+        }
+    });
+    log.capacity = capacity;
+    while log.events.len() > capacity {
+        log.events.pop_front();
+    }
+}
+
+pub(crate) fn events(sock: *mut c_void) -> Vec<HistoryEvent> {
+    match *logs() {
+        Some(ref logs) => logs.get(&(sock as usize)).map(|l| l.events.iter().cloned().collect()).unwrap_or_default(),
+        None => Vec::new(),
+    }
+}
+
+pub(crate) fn last_error(sock: *mut c_void) -> Option<LastError> {
+    if FAILED.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+
+    match *last_errors() {
+        Some(ref errors) => errors.get(&(sock as usize)).cloned(),
+        None => None,
+    }
+}
+
+pub(crate) fn clear_last_error(sock: *mut c_void) {
+    if FAILED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    if let Some(ref mut errors) = *last_errors() {
+        if errors.remove(&(sock as usize)).is_some() {
+            FAILED.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Forget everything recorded for `sock`. Called when a socket is
+/// destroyed, and when one is created, in case it reuses the address
+/// of a socket that was destroyed without passing through here.
+pub(crate) fn forget(sock: *mut c_void) {
+    if logging() {
+        set_capacity(sock, 0);
+    }
+    clear_last_error(sock);
+}
+
+/// Record the outcome of `op` on `sock`, if it failed or is being
+/// logged. `op` is only called if so.
+pub(crate) fn record<T, F: FnOnce() -> HistoryOp>(sock: *mut c_void, result: &Result<T>, op: F) {
+    let error = result.as_ref().err();
+    if error.is_none() && !logging() {
+        return;
+    }
+
+    // Read errno first, before anything else can overwrite it
+    let errno = match error.map(|e| e.kind()) {
+        Some(ErrorKind::Interrupted) |
+        Some(ErrorKind::NonZero) |
+        Some(ErrorKind::NullPtr) |
+        Some(ErrorKind::Terminated) => Some(unsafe { czmq_sys::zmq_errno() }),
+        _ => None,
+    };
+    let time = SystemTime::now();
+    let op = op();
+
+    if let Some(e) = error {
+        let mut errors = last_errors();
+        let last = LastError {
+            time: time,
+            op: op.clone(),
+            kind: e.kind(),
+            errno: errno,
+            message: e.to_string(),
+        };
+        if errors.get_or_insert_with(HashMap::new).insert(sock as usize, last).is_none() {
+            FAILED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    if !logging() {
+        return;
+    }
+
+    if let Some(ref mut logs) = *logs() {
+        if let Some(log) = logs.get_mut(&(sock as usize)) {
+            if log.events.len() == log.capacity {
+                log.events.pop_front();
+            }
+            log.events.push_back(HistoryEvent {
+                time: time,
+                op: op,
+                error: error.map(|e| e.to_string()),
+            });
+        }
+    }
+}
+
+/// Record an option being set on `sock`.
+pub(crate) fn option<T: fmt::Debug + ?Sized>(sock: *mut c_void, name: &'static str, value: &T) {
+    record(sock, &Ok(()), || HistoryOp::SetOption(name, format!("{:?}", value)));
+}
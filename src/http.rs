@@ -0,0 +1,534 @@
+//! Module: czmq-http
+//!
+//! A minimal HTTP/1.x server and client over STREAM sockets, for
+//! health checks, small REST endpoints and webhooks. CZMQ's own
+//! `zhttp_server` and `zhttp_client` are draft classes that need
+//! libmicrohttpd and libcurl, so this doesn't bind them.
+//!
+//! `HttpServer` is polled like any other socket, so it can share an
+//! event loop with the rest of a service. It answers each request
+//! with one response and closes the connection; keep-alive and
+//! chunked bodies aren't supported. Requests with a head over 8 KiB
+//! or a body over 1 MiB are answered with a 413. `HttpClient` makes
+//! blocking HTTP/1.0 requests, which servers answer without chunking.
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZPoller, ZSock};
+use std::collections::{HashMap, VecDeque};
+use std::{error, fmt, result, str};
+use std::time::{Duration, Instant};
+use zmq::SocketType;
+
+// The largest head, and request body, that a message may have
+const HEAD_MAX: usize = 8 * 1024;
+const BODY_MAX: usize = 1024 * 1024;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// The request target, including any query string.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Look up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    // Parse a request from the front of `buf`, returning it with the
+    // number of bytes it used, or `None` if it isn't all there yet.
+    // The error says which status to answer with.
+    fn parse(buf: &[u8]) -> result::Result<Option<(HttpRequest, usize)>, HttpError> {
+        let (head_len, start, headers) = match parse_head(buf)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+
+        let mut parts = start.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/") => (method.to_string(), path.to_string()),
+            _ => return Err(HttpError::Malformed),
+        };
+
+        let body_len = content_length(&headers)?.unwrap_or(0);
+        if body_len > BODY_MAX {
+            return Err(HttpError::TooLarge);
+        }
+        let len = head_len.checked_add(body_len).ok_or(HttpError::TooLarge)?;
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        Ok(Some((HttpRequest {
+            method: method,
+            path: path,
+            headers: headers,
+            body: buf[head_len..len].to_vec(),
+        }, len)))
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            status: status,
+            headers: Vec::new(),
+            body: body.to_vec(),
+        }
+    }
+
+    /// Add a header. `Content-Length` and `Connection` are set when
+    /// the response is sent.
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Look up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for &(ref name, ref value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len()));
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    // Parse a response from `buf`. Without a Content-Length, the body
+    // runs until the server closes the connection, so `closed` says
+    // whether it has.
+    fn parse(buf: &[u8], closed: bool) -> Result<Option<HttpResponse>> {
+        let (head_len, start, headers) = match parse_head(buf).map_err(invalid)? {
+            Some(head) => head,
+            None if closed => return Err(malformed()),
+            None => return Ok(None),
+        };
+
+        let mut parts = start.split_whitespace();
+        let status = match (parts.next(), parts.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/") => status.parse().map_err(|_| malformed())?,
+            _ => return Err(malformed()),
+        };
+
+        let len = match content_length(&headers).map_err(invalid)? {
+            Some(n) => match head_len.checked_add(n) {
+                Some(len) if buf.len() >= len => len,
+                Some(_) if !closed => return Ok(None),
+                _ => return Err(malformed()),
+            },
+            None if closed => buf.len(),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(HttpResponse {
+            status: status,
+            headers: headers,
+            body: buf[head_len..len].to_vec(),
+        }))
+    }
+}
+
+/// Serves HTTP requests on a STREAM socket.
+pub struct HttpServer {
+    sock: ZSock,
+    poller: ZPoller,
+    port: u16,
+    // Bytes received from each connection that don't yet make up a
+    // whole request. Parsing rejects a request as soon as it passes
+    // HEAD_MAX or BODY_MAX, which bounds each buffer.
+    buffers: HashMap<Vec<u8>, Vec<u8>>,
+    requests: VecDeque<(Vec<u8>, HttpRequest)>,
+}
+
+impl HttpServer {
+    /// Listen on a TCP endpoint, e.g. `tcp://*:8080`. Pass port `*`
+    /// to have one chosen, and read it with `port()`.
+    pub fn bind(endpoint: &str) -> Result<HttpServer> {
+        let sock = ZSock::new(SocketType::STREAM);
+        let port = sock.bind(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(HttpServer {
+            sock: sock,
+            poller: poller,
+            port: port as u16,
+            buffers: HashMap::new(),
+            requests: VecDeque::new(),
+        })
+    }
+
+    /// The socket, for adding to an event loop's poller.
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Wait up to `timeout` for a request, returning it with the
+    /// connection to pass to `respond()`. Malformed requests are
+    /// answered with a 400 and never returned.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, HttpRequest)>> {
+        if let Some(request) = self.requests.pop_front() {
+            return Ok(Some(request));
+        }
+
+        if self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(None);
+        }
+
+        while self.sock.readable() {
+            let mut msg = ZMsg::recv(&self.sock)?;
+            let (conn, data) = match (msg.popbytes()?, msg.popbytes()?) {
+                (Some(conn), Some(data)) => (conn, data),
+                _ => continue,
+            };
+            self.handle(conn, data)?;
+        }
+
+        Ok(self.requests.pop_front())
+    }
+
+    /// Send `response` on `conn` and close the connection.
+    pub fn respond(&mut self, conn: &[u8], response: &HttpResponse) -> Result<()> {
+        self.buffers.remove(conn);
+
+        let msg = ZMsg::new();
+        msg.addbytes(conn)?;
+        msg.addbytes(&response.to_bytes())?;
+        msg.send(&self.sock)?;
+
+        // An empty frame tells a STREAM socket to disconnect the peer
+        let msg = ZMsg::new();
+        msg.addbytes(conn)?;
+        msg.addbytes(&[])?;
+        msg.send(&self.sock)
+    }
+
+    // A STREAM socket sends an empty frame when a peer connects or
+    // disconnects, and either way there is nothing buffered to keep
+    fn handle(&mut self, conn: Vec<u8>, data: Vec<u8>) -> Result<()> {
+        if data.is_empty() {
+            self.buffers.remove(&conn);
+            return Ok(());
+        }
+
+        let parsed = {
+            let buffer = self.buffers.entry(conn.clone()).or_insert_with(Vec::new);
+            buffer.extend_from_slice(&data);
+            HttpRequest::parse(buffer)
+        };
+
+        match parsed {
+            Ok(Some((request, _))) => {
+                // Each connection carries one request, so anything
+                // after it is ignored
+                self.buffers.remove(&conn);
+                self.requests.push_back((conn, request));
+                Ok(())
+            },
+            Ok(None) => Ok(()),
+            Err(HttpError::TooLarge) => self.respond(&conn, &HttpResponse::new(413, b"")),
+            Err(_) => self.respond(&conn, &HttpResponse::new(400, b"")),
+        }
+    }
+}
+
+/// Makes blocking HTTP requests over STREAM sockets.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    timeout: Duration,
+}
+
+impl HttpClient {
+    /// A client whose requests time out after five seconds.
+    pub fn new() -> HttpClient {
+        HttpClient {
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn get(&self, url: &str) -> Result<HttpResponse> {
+        self.request("GET", url, &[], &[])
+    }
+
+    pub fn post(&self, url: &str, content_type: &str, body: &[u8]) -> Result<HttpResponse> {
+        self.request("POST", url, &[("Content-Type", content_type)], body)
+    }
+
+    /// Send a request to an `http://` URL and wait for the response.
+    pub fn request(&self, method: &str, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<HttpResponse> {
+        let (host, port, path) = parse_url(url)?;
+        let deadline = Instant::now() + self.timeout;
+
+        let sock = ZSock::new(SocketType::STREAM);
+        sock.set_linger(0);
+        sock.connect(&format!("tcp://{}:{}", host, port))?;
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        // The socket announces the connection with an empty frame
+        let conn = match recv_before(&sock, &mut poller, deadline)? {
+            (conn, ref data) if data.is_empty() => conn,
+            _ => return Err(malformed()),
+        };
+
+        let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, host);
+        for &(name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        let mut request = head.into_bytes();
+        request.extend_from_slice(body);
+
+        let msg = ZMsg::new();
+        msg.addbytes(&conn)?;
+        msg.addbytes(&request)?;
+        msg.send(&sock)?;
+
+        let mut buffer = Vec::new();
+        loop {
+            let (_, data) = recv_before(&sock, &mut poller, deadline)?;
+            let closed = data.is_empty();
+            buffer.extend_from_slice(&data);
+
+            if let Some(response) = HttpResponse::parse(&buffer, closed)? {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+// Receive a STREAM socket's next [connection, data] message, failing
+// if `deadline` passes first
+fn recv_before(sock: &ZSock, poller: &mut ZPoller, deadline: Instant) -> Result<(Vec<u8>, Vec<u8>)> {
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout == Duration::from_millis(0) || poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Err(Error::new(ErrorKind::Timeout, HttpError::Timeout));
+        }
+
+        let mut msg = ZMsg::recv(sock)?;
+        if let (Some(conn), Some(data)) = (msg.popbytes()?, msg.popbytes()?) {
+            return Ok((conn, data));
+        }
+    }
+}
+
+// Split an `http://host[:port][/path]` URL
+fn parse_url(url: &str) -> Result<(String, u16, String)> {
+    let invalid = || Error::new(ErrorKind::InvalidArg, HttpError::InvalidUrl(url.to_string()));
+
+    if !url.starts_with("http://") {
+        return Err(invalid());
+    }
+    let rest = &url[7..];
+    let (hostport, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match hostport.rfind(':') {
+        Some(i) => (&hostport[..i], hostport[i + 1..].parse().map_err(|_| invalid())?),
+        None => (hostport, 80),
+    };
+
+    if host.is_empty() {
+        Err(invalid())
+    } else {
+        Ok((host.to_string(), port, path.to_string()))
+    }
+}
+
+// Find the end of a message's head, and split it into the start line
+// and headers
+fn parse_head(buf: &[u8]) -> result::Result<Option<(usize, String, Vec<(String, String)>)>, HttpError> {
+    let end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) if end + 4 <= HEAD_MAX => end,
+        None if buf.len() < HEAD_MAX => return Ok(None),
+        _ => return Err(HttpError::TooLarge),
+    };
+    let head = str::from_utf8(&buf[..end]).map_err(|_| HttpError::Malformed)?;
+
+    let mut lines = head.split("\r\n");
+    let start = lines.next().unwrap_or("").to_string();
+    let mut headers = Vec::new();
+    for line in lines {
+        let i = line.find(':').ok_or(HttpError::Malformed)?;
+        headers.push((line[..i].trim().to_string(), line[i + 1..].trim().to_string()));
+    }
+
+    Ok(Some((end + 4, start, headers)))
+}
+
+fn content_length(headers: &[(String, String)]) -> result::Result<Option<usize>, HttpError> {
+    match find_header(headers, "Content-Length") {
+        Some(len) => len.parse().map(Some).map_err(|_| HttpError::Malformed),
+        None => Ok(None),
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter()
+        .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, ref v)| v.as_str())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn malformed() -> Error {
+    invalid(HttpError::Malformed)
+}
+
+fn invalid(error: HttpError) -> Error {
+    Error::new(ErrorKind::InvalidArg, error)
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    InvalidUrl(String),
+    Malformed,
+    Timeout,
+    TooLarge,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HttpError::InvalidUrl(ref url) => write!(f, "Invalid http:// URL: {}", url),
+            HttpError::Malformed => write!(f, "Malformed HTTP message"),
+            HttpError::Timeout => write!(f, "HTTP request timed out"),
+            HttpError::TooLarge => write!(f, "HTTP message exceeds the size limit"),
+        }
+    }
+}
+
+impl error::Error for HttpError {
+    fn description(&self) -> &str {
+        match *self {
+            HttpError::InvalidUrl(_) => "Invalid http:// URL",
+            HttpError::Malformed => "Malformed HTTP message",
+            HttpError::Timeout => "HTTP request timed out",
+            HttpError::TooLarge => "HTTP message exceeds the size limit",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZSys};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_request() {
+        let raw = b"POST /moo?volume=11 HTTP/1.1\r\nHost: farm\r\ncontent-length: 3\r\n\r\ncow";
+        assert!(HttpRequest::parse(&raw[..20]).unwrap().is_none());
+        assert!(HttpRequest::parse(&raw[..raw.len() - 1]).unwrap().is_none());
+
+        let (request, len) = HttpRequest::parse(raw).unwrap().unwrap();
+        assert_eq!(len, raw.len());
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/moo?volume=11");
+        assert_eq!(request.header("Content-Length"), Some("3"));
+        assert_eq!(request.body, b"cow");
+
+        assert!(HttpRequest::parse(b"MOO\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_oversize() {
+        let hostile = b"POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n";
+        match HttpRequest::parse(hostile) {
+            Err(HttpError::TooLarge) => (),
+            r => panic!("Expected TooLarge, got {:?}", r),
+        }
+
+        let raw = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", BODY_MAX + 1);
+        match HttpRequest::parse(raw.as_bytes()) {
+            Err(HttpError::TooLarge) => (),
+            r => panic!("Expected TooLarge, got {:?}", r),
+        }
+
+        let endless = vec![b'a'; HEAD_MAX];
+        match HttpRequest::parse(&endless) {
+            Err(HttpError::TooLarge) => (),
+            r => panic!("Expected TooLarge, got {:?}", r),
+        }
+
+        let hostile = b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n";
+        assert_eq!(HttpResponse::parse(hostile, false).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(parse_url("http://localhost:8080/health").unwrap(), ("localhost".into(), 8080, "/health".into()));
+        assert_eq!(parse_url("http://example.com").unwrap(), ("example.com".into(), 80, "/".into()));
+        assert_eq!(parse_url("https://example.com").unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        ZSys::init();
+
+        let mut server = HttpServer::bind("tcp://127.0.0.1:*").unwrap();
+        let url = format!("http://127.0.0.1:{}/health", server.port());
+
+        let client = thread::spawn(move || HttpClient::new().get(&url).map_err(|e| e.to_string()));
+
+        let (conn, request) = loop {
+            if let Some(r) = server.poll(Duration::from_millis(50)).unwrap() {
+                break r;
+            }
+        };
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/health");
+
+        let response = HttpResponse::new(200, b"moo").with_header("Content-Type", "text/plain");
+        server.respond(&conn, &response).unwrap();
+
+        let response = client.join().unwrap().unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body, b"moo");
+    }
+}
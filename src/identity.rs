@@ -0,0 +1,142 @@
+//! Module: czmq-identity
+//!
+//! Identities that survive restarts. A ROUTER knows each DEALER by
+//! its identity, and a DEALER without one is given a new random
+//! identity every time it connects, so after a restart the ROUTER
+//! sees a stranger. `StableIdentity` generates a UUID the first time
+//! and keeps it in a file, so the same process gets the same identity
+//! on every run.
+
+use crate::{Error, ErrorKind, Result, SocketType, ZSock};
+use crate::zuuid::ZUuid;
+use std::{error, fmt, fs};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// A UUID in the hex form zuuid_str() produces
+const IDENTITY_LEN: usize = 32;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StableIdentity {
+    path: PathBuf,
+    identity: String,
+}
+
+impl StableIdentity {
+    /// Read the identity stored at `path`, or generate one and store
+    /// it there if the file doesn't exist.
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<StableIdentity> {
+        let path = path.as_ref().to_path_buf();
+
+        let identity = if path.exists() {
+            let identity = fs::read_to_string(&path)?.trim().to_string();
+            if identity.len() != IDENTITY_LEN || !identity.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(Error::new(ErrorKind::InvalidArg, IdentityError::Corrupt(path)));
+            }
+            identity
+        } else {
+            let identity = new_uuid()?;
+            store(&path, &identity)?;
+            identity
+        };
+
+        Ok(StableIdentity {
+            path: path,
+            identity: identity,
+        })
+    }
+
+    pub fn identity(&self) -> &[u8] {
+        self.identity.as_bytes()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Give `sock` this identity. Call this before the socket
+    /// connects, as libzmq ignores identity changes afterwards.
+    pub fn apply(&self, sock: &ZSock) -> Result<()> {
+        sock.set_identity_bin(self.identity())
+    }
+
+    /// Create a DEALER socket with this identity and connect it to
+    /// `endpoints`.
+    pub fn dealer(&self, endpoints: &str) -> Result<ZSock> {
+        let sock = ZSock::new(SocketType::DEALER);
+        self.apply(&sock)?;
+        sock.attach(&[endpoints], false)?;
+        Ok(sock)
+    }
+}
+
+// Write to a temporary file first, so a crash can't leave a partial
+// identity behind
+fn store(path: &Path, identity: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    writeln!(file, "{}", identity)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn new_uuid() -> Result<String> {
+    match ZUuid::new() {
+        Some(uuid) => Ok(uuid.string),
+        None => Err(Error::new(ErrorKind::NullPtr, IdentityError::Generate)),
+    }
+}
+
+#[derive(Debug)]
+pub enum IdentityError {
+    Corrupt(PathBuf),
+    Generate,
+}
+
+impl fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IdentityError::Corrupt(ref path) => write!(f, "Identity file {} is corrupt", path.display()),
+            IdentityError::Generate => write!(f, "Could not generate an identity"),
+        }
+    }
+}
+
+impl error::Error for IdentityError {
+    fn description(&self) -> &str {
+        match *self {
+            IdentityError::Corrupt(_) => "Identity file is corrupt",
+            IdentityError::Generate => "Could not generate an identity",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PeerTable, ZSys};
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_stable_identity() {
+        ZSys::init();
+
+        let dir = TempDir::new("identity_test").unwrap();
+        let path = dir.path().join("dealer.id");
+        let identity = StableIdentity::load_or_create(&path).unwrap();
+        assert_eq!(identity.identity().len(), IDENTITY_LEN);
+        assert_eq!(StableIdentity::load_or_create(&path).unwrap(), identity);
+
+        let router = ZSock::new_router("inproc://identity_test").unwrap();
+        let mut peers = PeerTable::new(Duration::from_secs(10));
+        let dealer = identity.dealer("inproc://identity_test").unwrap();
+        dealer.send_str("moo").unwrap();
+        let (id, _) = peers.recv(&router).unwrap();
+        assert_eq!(id, identity.identity());
+
+        fs::write(&path, "moo").unwrap();
+        assert_eq!(StableIdentity::load_or_create(&path).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+}
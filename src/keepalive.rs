@@ -0,0 +1,218 @@
+//! Module: czmq-keepalive
+//!
+//! Detecting peers that have gone away without closing the connection,
+//! e.g. behind a NAT that dropped its mapping, where TCP alone would
+//! keep a half-open connection forever. A `Keepalive` gathers libzmq's
+//! ZMTP heartbeat options into one value, applied with
+//! `ZSock::set_keepalive()`. A `KeepaliveWatch` applies it and calls
+//! back when a peer stops answering.
+//!
+//! libzmq closes a connection whose heartbeats time out, and reports
+//! it to monitors as an ordinary disconnect. So the callback can't
+//! tell a silent peer from one that closed the connection cleanly;
+//! either way, the peer is gone.
+
+use crate::{Error, ErrorKind, Result, ZMonitor, ZMonitorEvents, ZPoller, ZSock};
+use std::{error, fmt, thread};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// How often a keepalive watch checks whether it has been stopped
+const WATCH_TICK: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Keepalive {
+    /// How often to send a heartbeat PING. Zero disables heartbeats.
+    pub interval: Duration,
+    /// How long to wait for any traffic after a PING before closing
+    /// the connection. `None` waits for one interval.
+    pub timeout: Option<Duration>,
+    /// How long the peer should wait for traffic from this socket
+    /// before closing the connection its end. `None` leaves it to the
+    /// peer's own settings.
+    pub ttl: Option<Duration>,
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration) -> Keepalive {
+        Keepalive {
+            interval: interval,
+            timeout: None,
+            ttl: None,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Keepalive {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Keepalive {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// How long a silent peer has before its connection is closed.
+    pub fn deadline(&self) -> Duration {
+        self.timeout.unwrap_or(self.interval)
+    }
+
+    pub(crate) fn from_raw(interval: Duration, timeout: Duration, ttl: Duration) -> Keepalive {
+        let zero = Duration::default();
+
+        Keepalive {
+            interval: interval,
+            timeout: if timeout == zero { None } else { Some(timeout) },
+            ttl: if ttl == zero { None } else { Some(ttl) },
+        }
+    }
+}
+
+/// A peer whose connection was closed, as reported by
+/// `KeepaliveWatch`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeerUnresponsive {
+    /// The endpoint the connection was made to or accepted on.
+    pub endpoint: String,
+}
+
+/// Applies a `Keepalive` to a socket and watches for unresponsive
+/// peers on a background thread.
+///
+/// The watch holds a monitor on the socket, so stop it before the
+/// socket is dropped.
+pub struct KeepaliveWatch {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl KeepaliveWatch {
+    /// Apply `keepalive` to `sock` and call `peer_unresponsive` each
+    /// time one of its connections is closed. Fails with
+    /// `ErrorKind::InvalidArg` if `keepalive` has heartbeats disabled,
+    /// as nothing would notice a silent peer.
+    pub fn start<F>(sock: &mut ZSock, keepalive: &Keepalive, mut peer_unresponsive: F) -> Result<KeepaliveWatch>
+        where F: FnMut(PeerUnresponsive) + Send + 'static {
+        if keepalive.interval == Duration::default() {
+            return Err(Error::new(ErrorKind::InvalidArg, KeepaliveError::Disabled));
+        }
+
+        sock.set_keepalive(keepalive);
+        let mut monitor = ZMonitor::builder(sock)
+                                   .events(&[ZMonitorEvents::Disconnected])
+                                   .start()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            let mut poller = match ZPoller::new() {
+                Ok(poller) => poller,
+                Err(_) => return,
+            };
+            if poller.add(monitor.actor()).is_err() {
+                return;
+            }
+
+            while !stopped.load(Ordering::SeqCst) {
+                match poller.wait_timeout::<ZSock>(Some(WATCH_TICK)) {
+                    Ok(Some(_)) => (),
+                    Ok(None) => continue,
+                    Err(_) => break,
+                }
+
+                match monitor.get_event() {
+                    Ok((ZMonitorEvents::Disconnected, endpoint)) => peer_unresponsive(PeerUnresponsive { endpoint: endpoint }),
+                    Ok(_) => (),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(KeepaliveWatch {
+            stop: stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for KeepaliveWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeepaliveError {
+    Disabled,
+}
+
+impl fmt::Display for KeepaliveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeepaliveError::Disabled => write!(f, "Keepalive interval must be more than zero"),
+        }
+    }
+}
+
+impl error::Error for KeepaliveError {
+    fn description(&self) -> &str {
+        match *self {
+            KeepaliveError::Disabled => "Keepalive interval must be more than zero",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZMsg, ZSys};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_keepalive() {
+        ZSys::init();
+
+        let sock = ZSock::new(SocketType::DEALER);
+        let keepalive = Keepalive::new(Duration::from_millis(500)).ttl(Duration::from_secs(2));
+        sock.set_keepalive(&keepalive);
+        assert_eq!(sock.keepalive().unwrap(), keepalive);
+        assert_eq!(keepalive.deadline(), Duration::from_millis(500));
+
+        let keepalive = keepalive.timeout(Duration::from_secs(1));
+        sock.set_keepalive(&keepalive);
+        assert_eq!(sock.keepalive().unwrap(), keepalive);
+    }
+
+    #[test]
+    fn test_watch() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::ROUTER);
+        let endpoint = format!("tcp://127.0.0.1:{}", server.bind("tcp://127.0.0.1:*").unwrap());
+
+        let mut client = ZSock::new(SocketType::DEALER);
+        let (tx, rx) = mpsc::channel();
+        let keepalive = Keepalive::new(Duration::from_millis(100));
+        let watch = KeepaliveWatch::start(&mut client, &keepalive, move |peer| { let _ = tx.send(peer); }).unwrap();
+        client.connect(&endpoint).unwrap();
+        client.send_str("moo").unwrap();
+        ZMsg::recv(&server).unwrap();
+
+        drop(server);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap().endpoint, endpoint);
+        watch.stop();
+
+        let e = KeepaliveWatch::start(&mut client, &Keepalive::new(Duration::default()), |_| ()).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+    }
+}
@@ -0,0 +1,159 @@
+//! Module: czmq-leaks
+//!
+//! Bookkeeping behind `testing::LeakCheck`. While a check is running,
+//! every socket and actor the crate creates is recorded with a
+//! backtrace of where it was created, along with any `ipc://` paths
+//! it binds. When no check is running, each hook costs an atomic
+//! load.
+
+// Checks can only be started through `testing`
+#![cfg_attr(not(any(test, feature = "testing")), allow(dead_code))]
+
+use std::{fmt, fs};
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// How many checks are running
+static CHECKS: AtomicUsize = AtomicUsize::new(0);
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Registry {
+    seq: u64,
+    // Live sockets and actors, by pointer
+    live: HashMap<usize, Entry>,
+    ipc: Vec<String>,
+}
+
+struct Entry {
+    seq: u64,
+    name: String,
+    backtrace: String,
+    ipc: Vec<String>,
+}
+
+/// A socket or actor that was created while a leak check was running
+/// and hasn't been destroyed.
+#[derive(Clone, Debug)]
+pub struct Leak {
+    /// What leaked, like `ZSock(PUB)` or `ZActor`.
+    pub name: String,
+    /// Where it was created.
+    pub backtrace: String,
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} created at:\n{}", self.name, self.backtrace)
+    }
+}
+
+fn registry() -> MutexGuard<'static, Option<Registry>> {
+    // A panic while holding the lock can't leave the map half updated
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn enabled() -> bool {
+    CHECKS.load(Ordering::Relaxed) > 0
+}
+
+/// Start a check, returning the sequence number that anything it
+/// should report on will be created after.
+pub(crate) fn start() -> u64 {
+    let mut registry = registry();
+    CHECKS.fetch_add(1, Ordering::SeqCst);
+    registry.get_or_insert_with(Registry::default).seq
+}
+
+pub(crate) fn stop() {
+    let mut registry = registry();
+    if CHECKS.fetch_sub(1, Ordering::SeqCst) == 1 {
+        *registry = None;
+    }
+}
+
+/// Everything created after `seq` that is still alive.
+pub(crate) fn live_since(seq: u64) -> Vec<Leak> {
+    let registry = registry();
+    let mut entries: Vec<&Entry> = match *registry {
+        Some(ref r) => r.live.values().filter(|e| e.seq > seq).collect(),
+        None => Vec::new(),
+    };
+    entries.sort_by_key(|e| e.seq);
+
+    entries.into_iter().map(|e| Leak {
+        name: e.name.clone(),
+        backtrace: e.backtrace.clone(),
+    }).collect()
+}
+
+/// Remove the files of `ipc://` endpoints that were bound during a
+/// check by sockets that no longer exist, returning their paths.
+pub(crate) fn remove_stale_ipc() -> Vec<String> {
+    let mut registry = registry();
+    let registry = match *registry {
+        Some(ref mut r) => r,
+        None => return Vec::new(),
+    };
+
+    let live = &registry.live;
+    let (in_use, stale): (Vec<String>, Vec<String>) = registry.ipc.drain(..)
+        .partition(|path| live.values().any(|e| e.ipc.contains(path)));
+    registry.ipc = in_use;
+
+    stale.into_iter().filter(|path| fs::remove_file(path).is_ok()).collect()
+}
+
+pub(crate) fn created<F: FnOnce() -> String>(ptr: *mut c_void, name: F) {
+    if !enabled() {
+        return;
+    }
+
+    let entry = Entry {
+        seq: 0,
+        name: name(),
+        backtrace: Backtrace::force_capture().to_string(),
+        ipc: Vec::new(),
+    };
+
+    if let Some(ref mut registry) = *registry() {
+        registry.seq += 1;
+        registry.live.insert(ptr as usize, Entry { seq: registry.seq, ..entry });
+    }
+}
+
+pub(crate) fn destroyed(ptr: *mut c_void) {
+    if !enabled() {
+        return;
+    }
+
+    if let Some(ref mut registry) = *registry() {
+        registry.live.remove(&(ptr as usize));
+    }
+}
+
+/// Note the file of an `ipc://` endpoint bound by the socket at
+/// `ptr`. Connected endpoints must not be passed here, as the file
+/// belongs to whoever bound it.
+pub(crate) fn bound(ptr: *mut c_void, endpoint: &str) {
+    if !enabled() {
+        return;
+    }
+
+    let path = match endpoint.strip_prefix("ipc://") {
+        // Abstract sockets and wildcards don't leave files behind
+        Some(p) if !p.starts_with('@') && p != "*" => p.to_string(),
+        _ => return,
+    };
+
+    if let Some(ref mut registry) = *registry() {
+        // Only paths bound by tracked sockets are ours to clean up
+        if let Some(entry) = registry.live.get_mut(&(ptr as usize)) {
+            entry.ipc.push(path.clone());
+            registry.ipc.push(path);
+        }
+    }
+}
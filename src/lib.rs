@@ -3,45 +3,187 @@
 
 #[macro_use]
 extern crate bitflags;
-extern crate czmq_sys;
-#[cfg(test)]
-extern crate tempdir;
-#[cfg(test)]
-extern crate tempfile;
-extern crate zmq;
 
+pub mod asyncs;
+#[cfg(feature = "bench-internals")]
+pub mod bench;
+mod bridge;
+mod bstar;
+#[cfg(feature = "serde")]
+mod bus;
+#[macro_use]
+mod capability;
+mod capture;
+mod chaos;
+mod chunked;
+mod cidr;
+mod clock;
+mod clone;
+mod cluster;
 mod colander;
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+pub mod compression;
+mod credit;
+mod deadletter;
+mod endpoint;
 mod error;
+#[cfg(feature = "codec")]
+mod framed;
+mod frameguard;
+mod freelance;
+mod heartbeat;
+mod history;
+mod http;
+mod identity;
+mod keepalive;
+mod leaks;
+mod managedsub;
+mod mdp;
+#[macro_use]
+mod message;
+pub mod middleware;
+mod msgpool;
+mod peertable;
+#[cfg(feature = "prelude")]
+pub mod prelude;
+mod ppqueue;
+mod prioqueue;
+#[cfg(feature = "prost")]
+mod protobuf;
+#[macro_use]
+pub mod protocol;
+mod ratelimit;
+mod reconnect;
+mod recvbuffer;
+mod retry;
+mod rng;
+#[cfg(feature = "serde")]
+mod rpc;
+mod scoped;
+#[cfg(feature = "cert-encryption")]
+mod sealedcert;
+mod selector;
+#[cfg(feature = "serde")]
+pub mod serde_zmsg;
+#[cfg(feature = "serde")]
+mod serialize;
+mod sharedsender;
+#[cfg(feature = "signing")]
+mod signing;
 mod socket;
+mod sockopts;
+#[cfg(feature = "metrics")]
+mod socketmetrics;
+mod tap;
+mod tcpbridge;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod titanic;
+mod trace;
+mod ttl;
+mod waiter;
+mod xpub;
 mod zactor;
 mod zauth;
+mod zbeacon;
 mod zcert;
 mod zcertstore;
+mod zconfig;
 mod zframe;
 mod zhashx;
 mod zlist;
 mod zmonitor;
 mod zmsg;
 mod zpoller;
+#[cfg(feature = "draft")]
+mod zproc;
 mod zsock;
 mod zsys;
+#[cfg(feature = "draft")]
+mod ztimerset;
+mod zuuid;
 
+pub use bridge::{bridge, Bridge, BridgeBuilder};
+pub use bstar::{BStarClient, BStarNode, BStarRequest, BStarState};
+#[cfg(feature = "serde")]
+pub use bus::{Bus, BusSubscriber, LastValueCache, Topic};
+pub use capability::{capabilities, Capabilities, Capability, CapabilityError, Version};
+pub use capture::{CaptureReader, CaptureRecord, CaptureWriter};
+pub use chaos::{ChaosPolicy, ChaosProxy, ChaosStats};
+pub use chunked::ChunkError;
+pub use cidr::{AddressFilter, Cidr, CidrError};
+pub use clock::{Clock, SystemClock};
+pub use clone::{CloneClient, CloneServer, KvMsg};
+pub use cluster::{ClusterEvent, ClusterNode};
 pub use colander::Colander;
+pub use credit::{CreditedReceiver, CreditedSender};
 pub use czmq_sys::zcertstore_t as ZCertStoreRaw;
+pub use deadletter::{DeadLetter, DeadLetterError, DeadLetterReason, DeadLetterSink, DEAD_LETTER_MAGIC};
+pub use endpoint::{Endpoint, ToEndpoint};
 pub use error::{Error, ErrorKind};
-pub use zactor::ZActor;
-pub use zauth::ZAuth;
+#[cfg(feature = "codec")]
+pub use framed::{StreamEvent, StreamFramed};
+pub use frameguard::FrameGuard;
+pub use freelance::{FreelanceClient, FreelanceRequest, FreelanceServer};
+pub use heartbeat::{Heartbeat, HeartbeatEvent, HEARTBEAT_PING};
+pub use history::{HistoryEvent, HistoryOp, LastError};
+pub use http::{HttpClient, HttpRequest, HttpResponse, HttpServer};
+pub use identity::{IdentityError, StableIdentity};
+pub use keepalive::{Keepalive, KeepaliveError, KeepaliveWatch, PeerUnresponsive};
+pub use managedsub::{ManagedSub, SubEvent};
+pub use mdp::{MdpBroker, MdpClient, MdpReply, MdpRequest, MdpWorker, MDPC_CLIENT, MDPW_WORKER};
+pub use message::{Message, MessageError, MessageField};
+pub use middleware::{Middleware, MiddlewareSock};
+pub use msgpool::{MessagePool, PooledMsg};
+pub use peertable::{Peer, PeerTable, PeerTableError};
+pub use ppqueue::{PpClient, PpQueue, PpRequest, PpWorker, PPP_HEARTBEAT, PPP_READY};
+pub use prioqueue::{PriorityActor, PriorityBroker};
+pub use protocol::{ActorProtocol, CommandSchema, ProtocolError, ReplyShape};
+pub use ratelimit::{RateLimitError, RateLimiter};
+pub use reconnect::{ConnectionStats, EndpointStats, ReconnectPolicy, StormWatch};
+pub use recvbuffer::RecvBuffer;
+pub use retry::{idempotency_key, IdempotencyCache, Retry, RetryClient, RetryError, RetryPolicy, IDEMPOTENCY_MAGIC};
+#[cfg(feature = "serde")]
+pub use rpc::{RpcClient, RpcError, RpcServer};
+pub use scoped::{with_pair, with_socket};
+#[cfg(feature = "cert-encryption")]
+pub use sealedcert::SealedCertError;
+pub use selector::Selector;
+#[cfg(feature = "serde")]
+pub use serialize::{ZCertWithSecret, ZHashXMap};
+pub use sharedsender::SharedSender;
+#[cfg(feature = "signing")]
+pub use signing::{Signer, SigningError, SIGNATURE_MAGIC};
+pub use socket::with_zmq_socket;
+pub use sockopts::{ZSockOptions, ZSockOptionsError};
+#[cfg(feature = "metrics")]
+pub use socketmetrics::{MeteredSock, SocketMetrics};
+pub use tap::{Tap, TapDirection, TapRecord};
+pub use tcpbridge::TcpBridge;
+pub use titanic::{Titanic, TitanicClient};
+pub use ttl::TTL_MAGIC;
+pub use xpub::{SubscriberCounts, Subscription, XPubError};
+pub use zactor::{commands, ZActor};
+pub use zauth::{ZAuth, ZAuthBuilder};
+pub use zbeacon::{Discovery, ZBeacon, BEACON_MAX};
 pub use zcert::ZCert;
 pub use zcertstore::ZCertStore;
+pub use zconfig::{ZConfig, ZConfigChildren};
+#[cfg(feature = "serde_json")]
+pub use zconfig::JSON_VALUE_KEY;
 pub use zframe::{ZFrame, ZFRAME_MORE, ZFRAME_REUSE, ZFRAME_DONTWAIT};
-pub use zhashx::ZHashX;
-pub use zlist::ZList;
-pub use zmonitor::{ZMonitor, ZMonitorEvents};
+pub use zhashx::{ZHashX, ZHashXKeys};
+pub use zlist::{ZList, ZListIter};
+pub use zmonitor::{ZMonitor, ZMonitorBuilder, ZMonitorEvents};
 pub use zmq::{Mechanism, SocketType};
-pub use zmsg::ZMsg;
+pub use zmsg::{ZMsg, ZMsgFrames, ZMSG_ENCODE_FRAME_MAX};
 pub use zpoller::ZPoller;
-pub use zsock::ZSock;
+#[cfg(feature = "draft")]
+pub use zproc::ZProc;
+pub use zsock::{CloseReport, GssapiNameType, VectoredMode, ZSock};
 pub use zsys::ZSys;
+#[cfg(feature = "draft")]
+pub use ztimerset::ZTimerSet;
 
 use std::os::raw::c_void;
 use std::result;
@@ -55,3 +197,17 @@ pub trait RawInterface<P> {
 }
 
 pub trait Sockish : RawInterface<c_void> {}
+
+/// Anything that CZMQ accepts as a socket: a `ZSock`, a `ZActor`
+/// (via its pipe) or a `zmq::Socket`, or a reference to one. Fns that
+/// send, receive or poll take a `SocketLike` by value, so they can be
+/// passed a socket reference however it's held.
+pub trait SocketLike {
+    fn as_socket_ptr(&mut self) -> *mut c_void;
+}
+
+impl<'a, T: SocketLike + ?Sized> SocketLike for &'a mut T {
+    fn as_socket_ptr(&mut self) -> *mut c_void {
+        (**self).as_socket_ptr()
+    }
+}
@@ -0,0 +1,211 @@
+//! Module: czmq-managedsub
+//!
+//! A SUB socket that notices when it loses its publisher. Anything
+//! published while a SUB is disconnected is gone for good, so after a
+//! reconnect `ManagedSub` re-applies its subscriptions, optionally
+//! fetches a snapshot of current state, and reports the reconnect, so
+//! the application can catch up rather than silently miss updates.
+//!
+//! Reconnects are detected with a `ZMonitor`, so this only works for
+//! connected, not bound, SUB sockets.
+
+use crate::{Result, SocketType, ZMonitor, ZMonitorEvents, ZMsg, ZPoller, ZSock};
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+pub enum SubEvent {
+    Message(ZMsg),
+    /// The socket has reconnected and re-applied its subscriptions.
+    /// `snapshot` holds whatever the snapshot fn returned, if one was
+    /// set.
+    Reconnected { snapshot: Vec<ZMsg> },
+}
+
+type SnapshotFn = Box<dyn FnMut() -> Result<Vec<ZMsg>> + Send>;
+
+pub struct ManagedSub {
+    // Declared before the socket, so that it stops watching the
+    // socket before the socket is destroyed
+    monitor: ZMonitor,
+    sock: ZSock,
+    poller: ZPoller,
+    topics: BTreeSet<String>,
+    disconnected: bool,
+    reconnects: u64,
+    snapshot: Option<SnapshotFn>,
+}
+
+impl ManagedSub {
+    /// Create a SUB socket connected to `endpoints`, with no
+    /// subscriptions.
+    pub fn new(endpoints: &str) -> Result<ManagedSub> {
+        let mut sock = ZSock::new(SocketType::SUB);
+        let monitor = ZMonitor::builder(&mut sock)
+                               .events(&[ZMonitorEvents::Connected, ZMonitorEvents::Disconnected])
+                               .start()?;
+        sock.attach(&[endpoints], false)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+        poller.add(monitor.actor())?;
+
+        Ok(ManagedSub {
+            monitor: monitor,
+            sock: sock,
+            poller: poller,
+            topics: BTreeSet::new(),
+            disconnected: false,
+            reconnects: 0,
+            snapshot: None,
+        })
+    }
+
+    /// Subscribe to `topic`. Subscribing to a topic twice has no
+    /// further effect.
+    pub fn subscribe(&mut self, topic: &str) {
+        if self.topics.insert(topic.to_string()) {
+            self.sock.set_subscribe(topic);
+        }
+    }
+
+    pub fn unsubscribe(&mut self, topic: &str) {
+        if self.topics.remove(topic) {
+            self.sock.set_unsubscribe(topic);
+        }
+    }
+
+    pub fn subscriptions(&self) -> impl Iterator<Item = &str> {
+        self.topics.iter().map(|t| t.as_str())
+    }
+
+    /// Call `snapshot` after each reconnect, once subscriptions have
+    /// been re-applied, and pass what it returns on with
+    /// `SubEvent::Reconnected`. It would typically ask a snapshot
+    /// service for current state, as in the Clone pattern.
+    pub fn set_snapshot<F>(&mut self, snapshot: F)
+        where F: FnMut() -> Result<Vec<ZMsg>> + Send + 'static {
+        self.snapshot = Some(Box::new(snapshot));
+    }
+
+    /// How many times the socket has reconnected.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    pub fn sock(&self) -> &ZSock {
+        &self.sock
+    }
+
+    /// Wait up to `timeout` for a message or reconnect.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<SubEvent>> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.poller.wait_timeout::<ZSock>(Some(remaining))?.is_none() {
+                return Ok(None);
+            }
+
+            if self.monitor.actor().sock().readable() {
+                if let Some(event) = self.monitor_event()? {
+                    return Ok(Some(event));
+                }
+            }
+
+            if self.sock.readable() {
+                return ZMsg::recv(&self.sock).map(|msg| Some(SubEvent::Message(msg)));
+            }
+        }
+    }
+
+    fn monitor_event(&mut self) -> Result<Option<SubEvent>> {
+        match self.monitor.get_attr()? {
+            Ok(ZMonitorEvents::Disconnected) => {
+                self.disconnected = true;
+                Ok(None)
+            },
+            Ok(ZMonitorEvents::Connected) if self.disconnected => {
+                self.disconnected = false;
+                self.reconnects += 1;
+                self.resubscribe();
+
+                let snapshot = match self.snapshot {
+                    Some(ref mut snapshot) => snapshot()?,
+                    None => Vec::new(),
+                };
+                Ok(Some(SubEvent::Reconnected { snapshot: snapshot }))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    // libzmq replays subscriptions to the new connection by itself,
+    // but a restarted publisher's XPUB (e.g. a last value cache) only
+    // reacts to fresh ones. Unsubscribing first keeps libzmq's count
+    // for each topic at one.
+    fn resubscribe(&self) {
+        for topic in &self.topics {
+            self.sock.set_unsubscribe(topic);
+            self.sock.set_subscribe(topic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+
+    // Publish until the subscriber has joined
+    fn publish(publisher: &ZSock, sub: &mut ManagedSub) -> Option<SubEvent> {
+        for _ in 0..50 {
+            ZMsg::from_frames(&["moo", "live"]).unwrap().send(publisher).unwrap();
+            if let Some(event) = sub.recv_timeout(Duration::from_millis(100)).unwrap() {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_resubscribe() {
+        ZSys::init();
+
+        let publisher = ZSock::new(SocketType::PUB);
+        let port = publisher.bind("tcp://127.0.0.1:*").unwrap();
+        let endpoint = format!("tcp://127.0.0.1:{}", port);
+
+        let mut sub = ManagedSub::new(&endpoint).unwrap();
+        sub.subscribe("moo");
+        sub.subscribe("moo");
+        assert_eq!(sub.subscriptions().collect::<Vec<_>>(), vec!["moo"]);
+        sub.set_snapshot(|| Ok(vec![ZMsg::from_frames(&["moo", "snapshot"])?]));
+
+        match publish(&publisher, &mut sub) {
+            Some(SubEvent::Message(mut msg)) => assert_eq!(msg.popstr().unwrap().unwrap(), "moo"),
+            _ => panic!("Expected a message"),
+        }
+
+        // Restart the publisher on the same port
+        drop(publisher);
+        let publisher = ZSock::new(SocketType::PUB);
+        publisher.bind(&endpoint).unwrap();
+
+        let mut snapshot = None;
+        while let Some(event) = publish(&publisher, &mut sub) {
+            if let SubEvent::Reconnected { snapshot: s } = event {
+                snapshot = Some(s);
+                break;
+            }
+        }
+
+        let snapshot = snapshot.expect("Expected a reconnect");
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(sub.reconnects(), 1);
+
+        match publish(&publisher, &mut sub) {
+            Some(SubEvent::Message(mut msg)) => assert_eq!(msg.popstr().unwrap().unwrap(), "moo"),
+            _ => panic!("Expected a message"),
+        }
+    }
+}
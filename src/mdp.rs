@@ -0,0 +1,1014 @@
+//! Module: czmq-mdp
+//!
+//! The Majordomo Protocol (MDP/0.2), a service-oriented, reliable
+//! request-reply protocol. Clients send requests for a named service
+//! to a broker, which queues them and hands them out to workers that
+//! have registered for that service. Brokers and workers heartbeat
+//! each other, so either side notices when the other goes away.
+//!
+//! The broker also answers service discovery requests (the MMI
+//! extension): a request to `mmi.service` whose body is a service
+//! name is answered with "200" if that service has any workers, or
+//! "404" if it doesn't.
+
+use crate::{Clock, DeadLetterReason, DeadLetterSink, Error, ErrorKind, Result, Retry, SocketType, ZMsg, ZPoller, ZSock};
+use crate::clock;
+use crate::waiter::{Sleep, Waiter};
+use std::{error, fmt};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Protocol header for client messages.
+pub const MDPC_CLIENT: &str = "MDPC02";
+/// Protocol header for worker messages.
+pub const MDPW_WORKER: &str = "MDPW02";
+
+const MDPC_REQUEST: u8 = 1;
+const MDPC_PARTIAL: u8 = 2;
+const MDPC_FINAL: u8 = 3;
+
+const MDPW_READY: u8 = 1;
+const MDPW_REQUEST: u8 = 2;
+const MDPW_PARTIAL: u8 = 3;
+const MDPW_FINAL: u8 = 4;
+const MDPW_HEARTBEAT: u8 = 5;
+const MDPW_DISCONNECT: u8 = 6;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(2500);
+const HEARTBEAT_LIVENESS: u32 = 3;
+
+/// A reply received by `MdpClient::recv()`.
+#[derive(Debug)]
+pub struct MdpReply {
+    pub service: String,
+    pub body: ZMsg,
+    /// False for a partial reply, which will be followed by more.
+    pub last: bool,
+}
+
+/// Sends requests to services via a broker.
+pub struct MdpClient {
+    broker: String,
+    sock: ZSock,
+    poller: ZPoller,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl MdpClient {
+    pub fn new(broker: &str) -> Result<MdpClient> {
+        let sock = connect(broker)?;
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(MdpClient {
+            broker: broker.to_string(),
+            sock: sock,
+            poller: poller,
+            timeout: HEARTBEAT_INTERVAL,
+            retries: 3,
+        })
+    }
+
+    /// How long `recv()` waits for a reply. Defaults to 2.5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// How many times `request()` resends a request that timed out.
+    /// Defaults to 3.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// Send a request without waiting for its reply.
+    pub fn send(&self, service: &str, body: ZMsg) -> Result<()> {
+        body.pushstr(service)?;
+        body.pushbytes(&[MDPC_REQUEST])?;
+        body.pushstr(MDPC_CLIENT)?;
+        body.pushbytes(&[])?;
+        body.send(&self.sock)
+    }
+
+    /// Wait for the next reply, or return `None` if none arrives
+    /// within the timeout.
+    pub fn recv(&self) -> Result<Option<MdpReply>> {
+        if !poll(&self.poller, self.timeout)? {
+            return Ok(None);
+        }
+
+        let mut msg = ZMsg::recv(&self.sock)?;
+        expect_header(&mut msg, MDPC_CLIENT)?;
+
+        let last = match pop_command(&mut msg)? {
+            MDPC_PARTIAL => false,
+            MDPC_FINAL => true,
+            _ => return Err(Error::new(ErrorKind::InvalidArg, MdpError::InvalidCommand)),
+        };
+        let service = pop_string(&mut msg)?;
+
+        Ok(Some(MdpReply {
+            service: service,
+            body: msg,
+            last: last,
+        }))
+    }
+
+    /// Send a request and wait for its final reply. If the broker
+    /// doesn't answer in time, the client reconnects and resends the
+    /// request, up to the number of retries. Partial replies are
+    /// skipped; use `send()` and `recv()` to stream them.
+    pub fn request(&mut self, service: &str, body: ZMsg) -> Result<ZMsg> {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                self.reconnect()?;
+            }
+
+            self.send(service, body.dup()?)?;
+
+            while let Some(reply) = self.recv()? {
+                if reply.last {
+                    return Ok(reply.body);
+                }
+            }
+        }
+
+        Err(Error::new(ErrorKind::Timeout, MdpError::NoReply))
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        self.poller.remove(&self.sock)?;
+        self.sock = connect(&self.broker)?;
+        self.poller.add(&self.sock)
+    }
+}
+
+// A late reply to an earlier attempt is as good as the reply to this
+// one, as `RetryClient` sends every attempt with the same key
+impl Retry for MdpClient {
+    fn attempt(&mut self, service: &str, request: ZMsg, timeout: Duration) -> Result<ZMsg> {
+        let deadline = Instant::now() + timeout;
+        self.send(service, request)?;
+
+        while poll(&self.poller, deadline.checked_duration_since(Instant::now()).unwrap_or_default())? {
+            match self.recv()? {
+                Some(ref reply) if !reply.last => (),
+                Some(reply) => return Ok(reply.body),
+                None => break,
+            }
+        }
+
+        Err(Error::new(ErrorKind::Timeout, MdpError::NoReply))
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.reconnect()
+    }
+}
+
+/// Sends requests to services via a broker, with any number of
+/// requests in flight over one connection at once.
+///
+/// Each request's body is prefixed with an 8 byte, big endian request
+/// id, which the worker must send back as the first frame of its
+/// reply; an `MdpWorker` that replies with the request body it was
+/// given, or pushes its reply onto it, does this already. Partial
+/// replies are skipped.
+///
+/// The futures returned by `call()` share the connection, so whichever
+/// is polled receives replies for all of them. Dropping a call forgets
+/// its request, and its reply is discarded if it arrives later.
+///
+/// The `Blocking` waiter can only wait on one thing at a time, so use
+/// `MdpClient` for blocking code instead.
+pub struct AsyncMdpClient<W: Waiter> {
+    inner: Mutex<MdpMux<W>>,
+    timeout: Duration,
+}
+
+struct MdpMux<W: Waiter> {
+    waiter: W,
+    sock: ZSock,
+    next_id: u64,
+    pending: HashMap<u64, MdpPending>,
+}
+
+#[derive(Default)]
+struct MdpPending {
+    reply: Option<ZMsg>,
+    waker: Option<Waker>,
+}
+
+impl<W: Waiter> AsyncMdpClient<W> {
+    pub fn new(broker: &str) -> Result<AsyncMdpClient<W>> {
+        let sock = connect(broker)?;
+
+        Ok(AsyncMdpClient {
+            inner: Mutex::new(MdpMux {
+                waiter: W::register(&sock)?,
+                sock: sock,
+                next_id: 0,
+                pending: HashMap::new(),
+            }),
+            timeout: HEARTBEAT_INTERVAL,
+        })
+    }
+
+    /// How long `call()` waits for a reply. Defaults to 2.5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The number of calls waiting for a reply.
+    pub fn pending(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    /// Send a request, returning a future that resolves to its final
+    /// reply. Fails with `ErrorKind::Timeout` if no reply arrives in
+    /// time.
+    pub fn call(&self, service: &str, body: ZMsg) -> Result<MdpCall<'_, W>> {
+        self.call_timeout(service, body, self.timeout)
+    }
+
+    /// Like `call()`, but waits up to `timeout` for this reply.
+    pub fn call_timeout(&self, service: &str, body: ZMsg, timeout: Duration) -> Result<MdpCall<'_, W>> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        body.pushbytes(&id.to_be_bytes())?;
+        body.pushstr(service)?;
+        body.pushbytes(&[MDPC_REQUEST])?;
+        body.pushstr(MDPC_CLIENT)?;
+        body.pushbytes(&[])?;
+        body.send(&inner.sock)?;
+
+        inner.pending.insert(id, MdpPending::default());
+
+        Ok(MdpCall {
+            client: self,
+            id: id,
+            expiry: W::sleep(timeout),
+        })
+    }
+}
+
+impl<W: Waiter> MdpMux<W> {
+    // Receive whatever replies are waiting, handing each to its call
+    fn dispatch(&mut self) -> Result<()> {
+        while self.sock.readable() {
+            let mut msg = ZMsg::recv(&self.sock)?;
+            expect_header(&mut msg, MDPC_CLIENT)?;
+
+            match pop_command(&mut msg)? {
+                MDPC_PARTIAL => continue,
+                MDPC_FINAL => (),
+                _ => return Err(Error::new(ErrorKind::InvalidArg, MdpError::InvalidCommand)),
+            }
+            // The service name isn't needed to route the reply
+            pop_bytes(&mut msg)?;
+
+            let id = match pop_bytes(&mut msg)?.as_slice().try_into() {
+                Ok(bytes) => u64::from_be_bytes(bytes),
+                Err(_) => return Err(Error::new(ErrorKind::InvalidArg, MdpError::InvalidRequestId)),
+            };
+
+            // Replies to forgotten calls are dropped
+            if let Some(pending) = self.pending.get_mut(&id) {
+                pending.reply = Some(msg);
+                if let Some(waker) = pending.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The future returned by `AsyncMdpClient::call()`.
+pub struct MdpCall<'a, W: Waiter> {
+    client: &'a AsyncMdpClient<W>,
+    id: u64,
+    expiry: Sleep,
+}
+
+impl<'a, W: Waiter> Future for MdpCall<'a, W> {
+    type Output = Result<ZMsg>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<ZMsg>> {
+        let this = self.get_mut();
+        let mut inner = this.client.inner.lock().unwrap();
+
+        loop {
+            if let Some(reply) = inner.pending.get_mut(&this.id).and_then(|p| p.reply.take()) {
+                return Poll::Ready(Ok(reply));
+            }
+
+            if let Err(e) = inner.dispatch() {
+                return Poll::Ready(Err(e));
+            }
+            if inner.pending.get(&this.id).map_or(false, |p| p.reply.is_some()) {
+                continue;
+            }
+
+            if let Some(pending) = inner.pending.get_mut(&this.id) {
+                pending.waker = Some(cx.waker().clone());
+            }
+
+            match inner.waiter.poll_wait(cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => break,
+            }
+        }
+
+        match this.expiry.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Error::new(ErrorKind::Timeout, MdpError::NoReply))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<'a, W: Waiter> Drop for MdpCall<'a, W> {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.client.inner.lock() {
+            inner.pending.remove(&self.id);
+
+            // This call may have been the one registered to wake on the
+            // socket, so wake the others to register themselves
+            for pending in inner.pending.values_mut() {
+                if let Some(waker) = pending.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+/// A request received by `MdpWorker::recv()`.
+#[derive(Debug)]
+pub struct MdpRequest {
+    /// The requesting client's address, to be passed back with the
+    /// reply.
+    pub client: Vec<u8>,
+    pub body: ZMsg,
+}
+
+/// Serves requests for a single service.
+pub struct MdpWorker {
+    broker: String,
+    service: String,
+    sock: ZSock,
+    poller: ZPoller,
+    heartbeat: Duration,
+    reconnect: Duration,
+    heartbeat_at: Instant,
+    expiry: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl MdpWorker {
+    /// Connect to `broker` and register for `service`.
+    pub fn new(broker: &str, service: &str) -> Result<MdpWorker> {
+        let sock = connect(broker)?;
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        let worker = MdpWorker {
+            broker: broker.to_string(),
+            service: service.to_string(),
+            sock: sock,
+            poller: poller,
+            heartbeat: HEARTBEAT_INTERVAL,
+            reconnect: HEARTBEAT_INTERVAL,
+            heartbeat_at: Instant::now() + HEARTBEAT_INTERVAL,
+            expiry: Instant::now() + HEARTBEAT_INTERVAL * HEARTBEAT_LIVENESS,
+            clock: clock::system(),
+        };
+        worker.ready()?;
+        Ok(worker)
+    }
+
+    /// How often to heartbeat the broker. The broker is presumed
+    /// dead after three silent intervals. Defaults to 2.5 seconds,
+    /// and should match the broker's setting.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+        self.heartbeat_at = self.clock.now() + heartbeat;
+        self.expiry = self.clock.now() + heartbeat * HEARTBEAT_LIVENESS;
+    }
+
+    /// Replace the clock used for heartbeats, broker expiry and the
+    /// reconnect delay.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.heartbeat_at = self.clock.now() + self.heartbeat;
+        self.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+    }
+
+    /// How long to wait before reconnecting to a dead broker.
+    /// Defaults to 2.5 seconds.
+    pub fn set_reconnect(&mut self, reconnect: Duration) {
+        self.reconnect = reconnect;
+    }
+
+    /// Wait for the next request, heartbeating and reconnecting to
+    /// the broker as needed. Returns `None` if no request arrives
+    /// within `timeout`, or waits forever if `timeout` is `None`.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<MdpRequest>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let mut wait = self.heartbeat_at.checked_duration_since(self.clock.now()).unwrap_or_default();
+            if let Some(deadline) = deadline {
+                wait = cmp::min(wait, deadline.checked_duration_since(Instant::now()).unwrap_or_default());
+            }
+
+            if poll(&self.poller, wait)? {
+                let mut msg = ZMsg::recv(&self.sock)?;
+                self.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+
+                // Ignore anything that isn't valid MDP
+                if expect_header(&mut msg, MDPW_WORKER).is_ok() {
+                    match pop_command(&mut msg) {
+                        Ok(MDPW_REQUEST) => {
+                            let client = pop_bytes(&mut msg)?;
+                            pop_bytes(&mut msg)?;
+                            return Ok(Some(MdpRequest {
+                                client: client,
+                                body: msg,
+                            }));
+                        },
+                        Ok(MDPW_DISCONNECT) => self.reconnect_to_broker()?,
+                        _ => (),
+                    }
+                }
+            } else if self.clock.now() >= self.expiry {
+                self.clock.sleep(self.reconnect);
+                self.reconnect_to_broker()?;
+            }
+
+            if self.clock.now() >= self.heartbeat_at {
+                self.send_command(MDPW_HEARTBEAT, ZMsg::new())?;
+                self.heartbeat_at = self.clock.now() + self.heartbeat;
+            }
+
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Send the final reply to a request.
+    pub fn reply(&self, client: &[u8], body: ZMsg) -> Result<()> {
+        self.send_reply(MDPW_FINAL, client, body)
+    }
+
+    /// Send a partial reply to a request, to be followed by more.
+    pub fn partial(&self, client: &[u8], body: ZMsg) -> Result<()> {
+        self.send_reply(MDPW_PARTIAL, client, body)
+    }
+
+    fn send_reply(&self, command: u8, client: &[u8], body: ZMsg) -> Result<()> {
+        body.pushbytes(&[])?;
+        body.pushbytes(client)?;
+        self.send_command(command, body)
+    }
+
+    fn send_command(&self, command: u8, msg: ZMsg) -> Result<()> {
+        msg.pushbytes(&[command])?;
+        msg.pushstr(MDPW_WORKER)?;
+        msg.pushbytes(&[])?;
+        msg.send(&self.sock)
+    }
+
+    fn ready(&self) -> Result<()> {
+        let msg = ZMsg::new();
+        msg.addstr(&self.service)?;
+        self.send_command(MDPW_READY, msg)
+    }
+
+    fn reconnect_to_broker(&mut self) -> Result<()> {
+        self.poller.remove(&self.sock)?;
+        self.sock = connect(&self.broker)?;
+        self.poller.add(&self.sock)?;
+
+        self.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+        self.ready()
+    }
+}
+
+struct Worker {
+    service: String,
+    expiry: Instant,
+}
+
+#[derive(Default)]
+struct Service {
+    requests: VecDeque<ZMsg>,
+    waiting: VecDeque<Vec<u8>>,
+}
+
+/// Routes requests from clients to workers by service name.
+pub struct MdpBroker {
+    sock: ZSock,
+    poller: ZPoller,
+    services: HashMap<String, Service>,
+    workers: HashMap<Vec<u8>, Worker>,
+    heartbeat: Duration,
+    heartbeat_at: Instant,
+    clock: Arc<dyn Clock>,
+    dead_letters: Option<DeadLetterSink>,
+}
+
+impl MdpBroker {
+    /// Create a broker on a ROUTER socket, which binds to `endpoint`
+    /// by default.
+    pub fn new(endpoint: &str) -> Result<MdpBroker> {
+        let sock = ZSock::new_router(endpoint)?;
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(MdpBroker {
+            sock: sock,
+            poller: poller,
+            services: HashMap::new(),
+            workers: HashMap::new(),
+            heartbeat: HEARTBEAT_INTERVAL,
+            heartbeat_at: Instant::now() + HEARTBEAT_INTERVAL,
+            clock: clock::system(),
+            dead_letters: None,
+        })
+    }
+
+    /// How often to heartbeat idle workers, which are dropped after
+    /// three silent intervals. Defaults to 2.5 seconds.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+        self.heartbeat_at = self.clock.now() + heartbeat;
+    }
+
+    /// Replace the clock used for heartbeats and worker expiry. Set
+    /// it before any workers connect.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.heartbeat_at = self.clock.now() + self.heartbeat;
+    }
+
+    /// Send requests for services that have no workers to `sink`,
+    /// with the service name as the detail. Without a sink they are
+    /// queued until a worker for the service turns up.
+    pub fn set_dead_letters(&mut self, sink: DeadLetterSink) {
+        self.dead_letters = Some(sink);
+    }
+
+    /// Route messages until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let heartbeat = self.heartbeat;
+            self.poll(heartbeat)?;
+        }
+    }
+
+    /// Wait up to `timeout` for a message and handle it, then drop
+    /// expired workers and send any heartbeats that are due.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        if poll(&self.poller, timeout)? {
+            let msg = ZMsg::recv(&self.sock)?;
+            self.handle(msg)?;
+        }
+
+        if self.clock.now() >= self.heartbeat_at {
+            self.purge();
+
+            for service in self.services.values() {
+                for identity in &service.waiting {
+                    send_to_worker(&self.sock, identity, MDPW_HEARTBEAT, ZMsg::new())?;
+                }
+            }
+
+            self.heartbeat_at = self.clock.now() + self.heartbeat;
+        }
+
+        Ok(())
+    }
+
+    // Invalid messages are dropped rather than treated as errors, so
+    // that a misbehaving peer can't stop the broker.
+    fn handle(&mut self, mut msg: ZMsg) -> Result<()> {
+        let sender = match pop_bytes(&mut msg) {
+            Ok(sender) => sender,
+            Err(_) => return Ok(()),
+        };
+
+        match pop_bytes(&mut msg) {
+            Ok(ref empty) if empty.is_empty() => (),
+            _ => return Ok(()),
+        }
+
+        match pop_bytes(&mut msg) {
+            Ok(ref header) if header == MDPC_CLIENT.as_bytes() => self.client_msg(sender, msg),
+            Ok(ref header) if header == MDPW_WORKER.as_bytes() => self.worker_msg(sender, msg),
+            _ => Ok(()),
+        }
+    }
+
+    fn client_msg(&mut self, sender: Vec<u8>, msg: ZMsg) -> Result<()> {
+        if pop_command(&mut msg).ok() != Some(MDPC_REQUEST) {
+            return Ok(());
+        }
+
+        let service = match pop_string(&mut msg) {
+            Ok(service) => service,
+            Err(_) => return Ok(()),
+        };
+
+        if service.starts_with("mmi.") {
+            let code = if service == "mmi.service" {
+                let name = pop_string(&mut msg).unwrap_or_default();
+                if self.workers.values().any(|w| w.service == name) { "200" } else { "404" }
+            } else {
+                "501"
+            };
+
+            let reply = ZMsg::new();
+            reply.addstr(code)?;
+            return send_to_client(&self.sock, &sender, &service, MDPC_FINAL, reply);
+        }
+
+        msg.pushbytes(&[])?;
+        msg.pushbytes(&sender)?;
+
+        if let Some(ref sink) = self.dead_letters {
+            if !self.workers.values().any(|w| w.service == service) {
+                // The sink counts any it can't deliver
+                let _ = sink.send(msg, DeadLetterReason::NoWorker, &service);
+                return Ok(());
+            }
+        }
+
+        self.services.entry(service.clone()).or_insert_with(Service::default).requests.push_back(msg);
+        self.dispatch(&service)
+    }
+
+    fn worker_msg(&mut self, sender: Vec<u8>, mut msg: ZMsg) -> Result<()> {
+        let command = match pop_command(&mut msg) {
+            Ok(command) => command,
+            Err(_) => return Ok(()),
+        };
+
+        let service = match self.workers.get_mut(&sender) {
+            Some(worker) => {
+                worker.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+                Some(worker.service.clone())
+            },
+            None => None,
+        };
+
+        match (command, service) {
+            (MDPW_READY, None) => {
+                match pop_string(&mut msg) {
+                    Ok(ref name) if !name.starts_with("mmi.") => {
+                        self.workers.insert(sender.clone(), Worker {
+                            service: name.clone(),
+                            expiry: self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS,
+                        });
+                        self.worker_waiting(sender, name)
+                    },
+                    _ => send_to_worker(&self.sock, &sender, MDPW_DISCONNECT, ZMsg::new()),
+                }
+            },
+            (MDPW_PARTIAL, Some(ref service)) | (MDPW_FINAL, Some(ref service)) => {
+                let client = match pop_bytes(&mut msg) {
+                    Ok(client) => client,
+                    Err(_) => return Ok(()),
+                };
+                let _ = pop_bytes(&mut msg);
+
+                let client_command = if command == MDPW_FINAL { MDPC_FINAL } else { MDPC_PARTIAL };
+                send_to_client(&self.sock, &client, service, client_command, msg)?;
+
+                if command == MDPW_FINAL {
+                    self.worker_waiting(sender, service)
+                } else {
+                    Ok(())
+                }
+            },
+            (MDPW_HEARTBEAT, Some(_)) => Ok(()),
+            (MDPW_DISCONNECT, _) => {
+                self.delete_worker(&sender);
+                Ok(())
+            },
+            // Protocol error, e.g. a second READY or a reply from an
+            // unknown worker
+            _ => {
+                self.delete_worker(&sender);
+                send_to_worker(&self.sock, &sender, MDPW_DISCONNECT, ZMsg::new())
+            },
+        }
+    }
+
+    fn worker_waiting(&mut self, identity: Vec<u8>, service: &str) -> Result<()> {
+        {
+            let waiting = &mut self.services.entry(service.to_string()).or_insert_with(Service::default).waiting;
+            if !waiting.contains(&identity) {
+                waiting.push_back(identity);
+            }
+        }
+        self.dispatch(service)
+    }
+
+    fn delete_worker(&mut self, identity: &[u8]) {
+        if let Some(worker) = self.workers.remove(identity) {
+            if let Some(service) = self.services.get_mut(&worker.service) {
+                service.waiting.retain(|w| w.as_slice() != identity);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, service: &str) -> Result<()> {
+        if let Some(service) = self.services.get_mut(service) {
+            while !service.requests.is_empty() && !service.waiting.is_empty() {
+                let identity = service.waiting.pop_front().unwrap();
+                let msg = service.requests.pop_front().unwrap();
+                send_to_worker(&self.sock, &identity, MDPW_REQUEST, msg)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Only idle workers are expected to heartbeat; busy ones are
+    // left alone until they reply.
+    fn purge(&mut self) {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+
+        for service in self.services.values() {
+            for identity in &service.waiting {
+                if self.workers.get(identity).map_or(true, |w| w.expiry <= now) {
+                    expired.push(identity.clone());
+                }
+            }
+        }
+
+        for identity in expired {
+            self.delete_worker(&identity);
+        }
+    }
+}
+
+fn connect(endpoint: &str) -> Result<ZSock> {
+    let sock = ZSock::new(SocketType::DEALER);
+    sock.set_linger(0);
+    sock.connect(endpoint)?;
+    Ok(sock)
+}
+
+fn poll(poller: &ZPoller, timeout: Duration) -> Result<bool> {
+    Ok(poller.wait_timeout::<ZSock>(Some(timeout))?.is_some())
+}
+
+fn send_to_client(sock: &ZSock, client: &[u8], service: &str, command: u8, body: ZMsg) -> Result<()> {
+    body.pushstr(service)?;
+    body.pushbytes(&[command])?;
+    body.pushstr(MDPC_CLIENT)?;
+    body.pushbytes(&[])?;
+    body.pushbytes(client)?;
+    body.send(sock)
+}
+
+fn send_to_worker(sock: &ZSock, worker: &[u8], command: u8, body: ZMsg) -> Result<()> {
+    body.pushbytes(&[command])?;
+    body.pushstr(MDPW_WORKER)?;
+    body.pushbytes(&[])?;
+    body.pushbytes(worker)?;
+    body.send(sock)
+}
+
+fn pop_bytes(msg: &mut ZMsg) -> Result<Vec<u8>> {
+    match msg.pop() {
+        Some(frame) => Ok(frame.as_bytes().to_vec()),
+        None => Err(Error::new(ErrorKind::MissingFrame, MdpError::MissingFrame)),
+    }
+}
+
+fn pop_string(msg: &mut ZMsg) -> Result<String> {
+    String::from_utf8(pop_bytes(msg)?).map_err(|e| Error::new(ErrorKind::StringConversion, e))
+}
+
+fn pop_command(msg: &mut ZMsg) -> Result<u8> {
+    match pop_bytes(msg)?.as_slice() {
+        [command] => Ok(*command),
+        _ => Err(Error::new(ErrorKind::InvalidArg, MdpError::InvalidCommand)),
+    }
+}
+
+// Check for the empty delimiter and protocol header a DEALER gets
+fn expect_header(msg: &mut ZMsg, header: &str) -> Result<()> {
+    if !pop_bytes(msg)?.is_empty() || pop_bytes(msg)? != header.as_bytes() {
+        Err(Error::new(ErrorKind::InvalidArg, MdpError::InvalidHeader))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum MdpError {
+    InvalidCommand,
+    InvalidHeader,
+    InvalidRequestId,
+    MissingFrame,
+    NoReply,
+}
+
+impl fmt::Display for MdpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MdpError::InvalidCommand => write!(f, "Invalid MDP command"),
+            MdpError::InvalidHeader => write!(f, "Invalid MDP header"),
+            MdpError::InvalidRequestId => write!(f, "Reply has an invalid request id"),
+            MdpError::MissingFrame => write!(f, "MDP message is missing a frame"),
+            MdpError::NoReply => write!(f, "No reply from broker"),
+        }
+    }
+}
+
+impl error::Error for MdpError {
+    fn description(&self) -> &str {
+        match *self {
+            MdpError::InvalidCommand => "Invalid MDP command",
+            MdpError::InvalidHeader => "Invalid MDP header",
+            MdpError::InvalidRequestId => "Reply has an invalid request id",
+            MdpError::MissingFrame => "MDP message is missing a frame",
+            MdpError::NoReply => "No reply from broker",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZMsg, ZSock, ZSys};
+    use crate::testing::{expect, FakePeer};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn spawn_broker(endpoint: &'static str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        let mut broker = MdpBroker::new(endpoint).unwrap();
+        broker.set_heartbeat(TICK);
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                broker.poll(TICK).unwrap();
+            }
+        })
+    }
+
+    fn spawn_echo(endpoint: &'static str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut worker = MdpWorker::new(endpoint, "echo").unwrap();
+            worker.set_heartbeat(TICK);
+
+            while !stop.load(Ordering::SeqCst) {
+                if let Some(req) = worker.recv(Some(TICK)).unwrap() {
+                    worker.partial(&req.client, ZMsg::from_frames(&[b"partial"]).unwrap()).unwrap();
+                    worker.reply(&req.client, req.body).unwrap();
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_worker_protocol() {
+        ZSys::init();
+
+        let broker = ZSock::new_router("inproc://mdp_test_worker_protocol").unwrap();
+        let peer = FakePeer::new()
+            .expect_msg(expect().frame(b"").frame_str(MDPW_WORKER).frame(&[MDPW_READY]).frame_str("echo")
+                .then_reply(&[b"", MDPW_WORKER.as_bytes(), &[MDPW_REQUEST], b"client", b"", b"moo"]))
+            .expect_msg(expect().frame(b"").frame_str(MDPW_WORKER).frame(&[MDPW_FINAL]).frame_str("client").frame(b"").frame_str("moo"))
+            .spawn(broker);
+
+        let mut worker = MdpWorker::new("inproc://mdp_test_worker_protocol", "echo").unwrap();
+        let req = worker.recv(Some(Duration::from_secs(1))).unwrap().unwrap();
+        assert_eq!(req.client, b"client");
+        worker.reply(&req.client, req.body).unwrap();
+
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_request() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let broker = spawn_broker("@inproc://mdp_test_request", stop.clone());
+        let worker = spawn_echo("inproc://mdp_test_request", stop.clone());
+
+        let mut client = MdpClient::new("inproc://mdp_test_request").unwrap();
+        client.set_timeout(Duration::from_secs(1));
+
+        let reply = client.request("echo", ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap()).unwrap();
+        assert_eq!(reply, ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap());
+
+        client.send("echo", ZMsg::from_frames(&[b"baa"]).unwrap()).unwrap();
+        let partial = client.recv().unwrap().unwrap();
+        assert_eq!(partial.service, "echo");
+        assert!(!partial.last);
+        let mut last = client.recv().unwrap().unwrap();
+        assert!(last.last);
+        assert_eq!(last.body.popstr().unwrap().unwrap(), "baa");
+
+        stop.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn test_mmi() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let broker = spawn_broker("@inproc://mdp_test_mmi", stop.clone());
+        let worker = spawn_echo("inproc://mdp_test_mmi", stop.clone());
+
+        let mut client = MdpClient::new("inproc://mdp_test_mmi").unwrap();
+        client.set_timeout(Duration::from_secs(1));
+
+        // Give the worker time to register
+        thread::sleep(TICK * 2);
+
+        let mut reply = client.request("mmi.service", ZMsg::from_frames(&[b"echo"]).unwrap()).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "200");
+        let mut reply = client.request("mmi.service", ZMsg::from_frames(&[b"moo"]).unwrap()).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "404");
+        let mut reply = client.request("mmi.moo", ZMsg::new()).unwrap();
+        assert_eq!(reply.popstr().unwrap().unwrap(), "501");
+
+        stop.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn test_no_reply() {
+        ZSys::init();
+
+        let mut client = MdpClient::new("inproc://mdp_test_no_reply").unwrap();
+        client.set_timeout(TICK);
+        client.set_retries(1);
+
+        let e = client.request("echo", ZMsg::new()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_client() {
+        use crate::asyncs::Tokio;
+
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let broker = spawn_broker("@inproc://mdp_test_async", stop.clone());
+        let worker = spawn_echo("inproc://mdp_test_async", stop.clone());
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let mut client = AsyncMdpClient::<Tokio>::new("inproc://mdp_test_async").unwrap();
+            client.set_timeout(Duration::from_secs(1));
+
+            let moo = client.call("echo", ZMsg::from_frames(&[b"moo"]).unwrap()).unwrap();
+            let baa = client.call("echo", ZMsg::from_frames(&[b"baa"]).unwrap()).unwrap();
+            let (moo, baa) = tokio::join!(moo, baa);
+            assert_eq!(moo.unwrap().popstr().unwrap().unwrap(), "moo");
+            assert_eq!(baa.unwrap().popstr().unwrap().unwrap(), "baa");
+
+            let e = client.call_timeout("nobody", ZMsg::new(), TICK).unwrap().await.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Timeout);
+
+            // A dropped call is forgotten
+            drop(client.call("echo", ZMsg::new()).unwrap());
+            assert_eq!(client.pending(), 0);
+        });
+
+        stop.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+        broker.join().unwrap();
+    }
+}
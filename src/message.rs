@@ -0,0 +1,264 @@
+//! Module: czmq-message
+//!
+//! Typed protocol messages, in the spirit of zproto. Each message is
+//! sent as a version frame, a command frame and then one frame per
+//! field. Use the `czmq_message!` macro to define message types.
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZMsgFrames};
+use std::{error, fmt};
+
+/// A typed message that can be mapped to and from a `ZMsg`.
+pub trait Message: Sized {
+    /// Command name, sent as the second frame.
+    const COMMAND: &'static str;
+    /// Protocol version, sent as the first frame.
+    const VERSION: u8;
+
+    fn encode_fields(&self, msg: &ZMsg) -> Result<()>;
+    fn decode_fields(frames: &mut ZMsgFrames) -> Result<Self>;
+
+    fn encode(&self) -> Result<ZMsg> {
+        let msg = ZMsg::new();
+        msg.addbytes(&[Self::VERSION])?;
+        msg.addbytes(Self::COMMAND.as_bytes())?;
+        self.encode_fields(&msg)?;
+        Ok(msg)
+    }
+
+    fn decode(msg: &ZMsg) -> Result<Self> {
+        let mut frames = msg.iter();
+
+        if u8::decode_field(frames.next())? != Self::VERSION {
+            return Err(Error::new(ErrorKind::InvalidArg, MessageError::WrongVersion));
+        }
+
+        if frames.next() != Some(Self::COMMAND.as_bytes()) {
+            return Err(Error::new(ErrorKind::InvalidArg, MessageError::WrongCommand));
+        }
+
+        Self::decode_fields(&mut frames)
+    }
+}
+
+/// A value that can be sent as a single frame of a `Message`.
+/// Integers are sent in network byte order.
+pub trait MessageField: Sized {
+    fn encode_field(&self, msg: &ZMsg) -> Result<()>;
+    fn decode_field(frame: Option<&[u8]>) -> Result<Self>;
+}
+
+fn field_frame(frame: Option<&[u8]>) -> Result<&[u8]> {
+    frame.ok_or_else(|| Error::new(ErrorKind::MissingFrame, MessageError::MissingField))
+}
+
+macro_rules! int_field {
+    ($($t:ty),*) => {$(
+        impl MessageField for $t {
+            fn encode_field(&self, msg: &ZMsg) -> Result<()> {
+                let v = *self as u64;
+                let size = ::std::mem::size_of::<$t>();
+                let bytes: Vec<u8> = (0..size).rev().map(|i| (v >> (8 * i)) as u8).collect();
+                msg.addbytes(&bytes)
+            }
+
+            fn decode_field(frame: Option<&[u8]>) -> Result<$t> {
+                let bytes = field_frame(frame)?;
+                if bytes.len() != ::std::mem::size_of::<$t>() {
+                    return Err(Error::new(ErrorKind::InvalidArg, MessageError::InvalidField));
+                }
+                Ok(bytes.iter().fold(0u64, |v, b| (v << 8) | *b as u64) as $t)
+            }
+        }
+    )*}
+}
+
+int_field!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl MessageField for bool {
+    fn encode_field(&self, msg: &ZMsg) -> Result<()> {
+        (*self as u8).encode_field(msg)
+    }
+
+    fn decode_field(frame: Option<&[u8]>) -> Result<bool> {
+        match u8::decode_field(frame)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::new(ErrorKind::InvalidArg, MessageError::InvalidField)),
+        }
+    }
+}
+
+impl MessageField for String {
+    fn encode_field(&self, msg: &ZMsg) -> Result<()> {
+        msg.addbytes(self.as_bytes())
+    }
+
+    fn decode_field(frame: Option<&[u8]>) -> Result<String> {
+        let bytes = field_frame(frame)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| Error::new(ErrorKind::StringConversion, e))
+    }
+}
+
+impl MessageField for Vec<u8> {
+    fn encode_field(&self, msg: &ZMsg) -> Result<()> {
+        msg.addbytes(self)
+    }
+
+    fn decode_field(frame: Option<&[u8]>) -> Result<Vec<u8>> {
+        Ok(field_frame(frame)?.to_vec())
+    }
+}
+
+/// Define a struct that implements `Message`, with one frame per
+/// field. The command name is the struct name, and the version
+/// defaults to 1:
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate czmq;
+///
+/// use czmq::Message;
+///
+/// czmq_message! {
+///     version 2;
+///     #[derive(Debug, PartialEq)]
+///     pub struct Hello {
+///         pub seq: u32,
+///         pub name: String,
+///     }
+/// }
+///
+/// fn main() {
+///     let hello = Hello { seq: 1, name: "moo".into() };
+///     let msg = hello.encode().unwrap();
+///     assert_eq!(Hello::decode(&msg).unwrap(), hello);
+/// }
+/// ```
+#[macro_export]
+macro_rules! czmq_message {
+    (
+        version $version:expr;
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($fvis:vis $field:ident : $ty:ty),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($fvis $field: $ty),*
+        }
+
+        impl $crate::Message for $name {
+            const COMMAND: &'static str = stringify!($name);
+            const VERSION: u8 = $version;
+
+            fn encode_fields(&self, _msg: &$crate::ZMsg) -> $crate::Result<()> {
+                $($crate::MessageField::encode_field(&self.$field, _msg)?;)*
+                Ok(())
+            }
+
+            fn decode_fields(_frames: &mut $crate::ZMsgFrames) -> $crate::Result<$name> {
+                Ok($name {
+                    $($field: <$ty as $crate::MessageField>::decode_field(_frames.next())?),*
+                })
+            }
+        }
+    };
+
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($fvis:vis $field:ident : $ty:ty),* $(,)*
+        }
+    ) => {
+        $crate::czmq_message! {
+            version 1;
+            $(#[$attr])*
+            $vis struct $name {
+                $($fvis $field: $ty),*
+            }
+        }
+    };
+}
+
+#[derive(Debug)]
+pub enum MessageError {
+    InvalidField,
+    MissingField,
+    WrongCommand,
+    WrongVersion,
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MessageError::InvalidField => write!(f, "Message field could not be decoded"),
+            MessageError::MissingField => write!(f, "Message is missing a field"),
+            MessageError::WrongCommand => write!(f, "Message has the wrong command"),
+            MessageError::WrongVersion => write!(f, "Message has the wrong version"),
+        }
+    }
+}
+
+impl error::Error for MessageError {
+    fn description(&self) -> &str {
+        match *self {
+            MessageError::InvalidField => "Message field could not be decoded",
+            MessageError::MissingField => "Message is missing a field",
+            MessageError::WrongCommand => "Message has the wrong command",
+            MessageError::WrongVersion => "Message has the wrong version",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZMsg};
+
+    czmq_message! {
+        #[derive(Debug, PartialEq)]
+        struct Hello {
+            seq: u32,
+            offset: i16,
+            ready: bool,
+            name: String,
+            data: Vec<u8>,
+        }
+    }
+
+    czmq_message! {
+        version 2;
+        #[derive(Debug)]
+        struct Goodbye {
+            seq: u32,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let hello = Hello {
+            seq: 0xdeadbeef,
+            offset: -2,
+            ready: true,
+            name: "moo".to_string(),
+            data: vec![1, 2, 3],
+        };
+
+        let msg = hello.encode().unwrap();
+        assert_eq!(msg.size(), 7);
+        assert_eq!(Hello::decode(&msg).unwrap(), hello);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let msg = Goodbye { seq: 1 }.encode().unwrap();
+        assert_eq!(Hello::decode(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        let msg = ZMsg::from_frames(&[&[1u8][..], b"Hello", b"\x00\x00\x00\x01"]).unwrap();
+        assert_eq!(Hello::decode(&msg).unwrap_err().kind(), ErrorKind::MissingFrame);
+
+        let msg = ZMsg::from_frames(&[&[2u8][..], b"Goodbye", b"\x01"]).unwrap();
+        assert_eq!(Goodbye::decode(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+}
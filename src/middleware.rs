@@ -0,0 +1,199 @@
+//! Module: czmq-middleware
+//!
+//! Hooks that see every message a socket sends or receives, for
+//! concerns like auth headers, validation or tracing that shouldn't be
+//! repeated at every call site. A `MiddlewareSock` runs a chain of
+//! `Middleware` around a socket: sends pass through the chain in the
+//! order it was built, and receives in reverse, so each layer unwraps
+//! what it wrapped.
+//!
+//! ```rust
+//! use czmq::{middleware, MiddlewareSock, ZMsg, ZSock, ZSys};
+//!
+//! ZSys::init();
+//!
+//! let sock = MiddlewareSock::new(ZSock::new_push("@inproc://middleware_example").unwrap())
+//!     .with(middleware::on_send(|msg: ZMsg| {
+//!         msg.pushstr("token=moo")?;
+//!         Ok(Some(msg))
+//!     }));
+//! ```
+
+use crate::{Result, ZMsg, ZSock};
+use std::time::Duration;
+
+/// A layer in a `MiddlewareSock`'s chain. Each hook can change the
+/// message, return `None` to drop it quietly, or fail to reject it
+/// with an error for the caller.
+pub trait Middleware: Send {
+    fn on_send(&mut self, msg: ZMsg) -> Result<Option<ZMsg>> {
+        Ok(Some(msg))
+    }
+
+    fn on_recv(&mut self, msg: ZMsg) -> Result<Option<ZMsg>> {
+        Ok(Some(msg))
+    }
+}
+
+/// A socket that runs its messages through a chain of middleware.
+pub struct MiddlewareSock {
+    sock: ZSock,
+    chain: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareSock {
+    pub fn new(sock: ZSock) -> MiddlewareSock {
+        MiddlewareSock {
+            sock: sock,
+            chain: Vec::new(),
+        }
+    }
+
+    /// Add `middleware` to the end of the chain, nearest the socket.
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> MiddlewareSock {
+        self.push(middleware);
+        self
+    }
+
+    pub fn push<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.chain.push(Box::new(middleware));
+    }
+
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    pub fn into_inner(self) -> ZSock {
+        self.sock
+    }
+
+    /// Run `msg` through the chain and send it. Returns false if a
+    /// layer dropped it.
+    pub fn send(&mut self, msg: ZMsg) -> Result<bool> {
+        let mut msg = msg;
+        for middleware in self.chain.iter_mut() {
+            msg = match middleware.on_send(msg)? {
+                Some(msg) => msg,
+                None => return Ok(false),
+            };
+        }
+
+        msg.send(&self.sock)?;
+        Ok(true)
+    }
+
+    /// Receive the next message that makes it back through the chain.
+    /// Messages a layer drops are skipped.
+    pub fn recv(&mut self) -> Result<ZMsg> {
+        'recv: loop {
+            let mut msg = ZMsg::recv(&self.sock)?;
+            for middleware in self.chain.iter_mut().rev() {
+                msg = match middleware.on_recv(msg)? {
+                    Some(msg) => msg,
+                    None => continue 'recv,
+                };
+            }
+            return Ok(msg);
+        }
+    }
+}
+
+/// Middleware that only hooks sends, with `f`.
+pub fn on_send<F>(f: F) -> impl Middleware
+    where F: FnMut(ZMsg) -> Result<Option<ZMsg>> + Send {
+    OnSend(f)
+}
+
+/// Middleware that only hooks receives, with `f`.
+pub fn on_recv<F>(f: F) -> impl Middleware
+    where F: FnMut(ZMsg) -> Result<Option<ZMsg>> + Send {
+    OnRecv(f)
+}
+
+struct OnSend<F>(F);
+
+impl<F> Middleware for OnSend<F> where F: FnMut(ZMsg) -> Result<Option<ZMsg>> + Send {
+    fn on_send(&mut self, msg: ZMsg) -> Result<Option<ZMsg>> {
+        (self.0)(msg)
+    }
+}
+
+struct OnRecv<F>(F);
+
+impl<F> Middleware for OnRecv<F> where F: FnMut(ZMsg) -> Result<Option<ZMsg>> + Send {
+    fn on_recv(&mut self, msg: ZMsg) -> Result<Option<ZMsg>> {
+        (self.0)(msg)
+    }
+}
+
+/// Stamps sent messages to expire after a time to live, and drops
+/// received messages that have expired. See `ZMsg::set_ttl()`.
+#[derive(Clone, Copy, Debug)]
+pub struct Ttl(pub Duration);
+
+impl Middleware for Ttl {
+    fn on_send(&mut self, mut msg: ZMsg) -> Result<Option<ZMsg>> {
+        msg.set_ttl(self.0)?;
+        Ok(Some(msg))
+    }
+
+    fn on_recv(&mut self, mut msg: ZMsg) -> Result<Option<ZMsg>> {
+        if msg.is_expired() {
+            Ok(None)
+        } else {
+            msg.take_deadline();
+            Ok(Some(msg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, ErrorKind, SocketType, ZSys};
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_chain() {
+        ZSys::init();
+
+        let (server, client) = crate::testing::pair(SocketType::PULL, SocketType::PUSH).unwrap();
+
+        // The layers nearest the socket add the header last on send
+        // and check it first on recv
+        let header = |msg: ZMsg| -> Result<Option<ZMsg>> {
+            msg.pushstr("v1")?;
+            Ok(Some(msg))
+        };
+        let check = |mut msg: ZMsg| -> Result<Option<ZMsg>> {
+            match msg.popstr() {
+                Some(Ok(ref v)) if v == "v1" => Ok(Some(msg)),
+                _ => Err(Error::new(ErrorKind::InvalidArg, "Missing version header")),
+            }
+        };
+        let no_baa = |msg: ZMsg| -> Result<Option<ZMsg>> {
+            let baa = msg.first().map_or(false, |f| f.as_bytes() == b"baa");
+            Ok(if baa { None } else { Some(msg) })
+        };
+
+        let mut client = MiddlewareSock::new(client).with(Ttl(Duration::from_secs(60))).with(on_send(header));
+        let mut server = MiddlewareSock::new(server).with(on_recv(no_baa)).with(Ttl(Duration::from_secs(60))).with(on_recv(check));
+
+        assert!(client.send(ZMsg::from_frames(&["baa"]).unwrap()).unwrap());
+        assert!(client.send(ZMsg::from_frames(&["moo"]).unwrap()).unwrap());
+        let mut msg = server.recv().unwrap();
+        assert_eq!(msg.size(), 1);
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+
+        // Expired, so dropped by the server's Ttl
+        let mut stale = ZMsg::from_frames(&["oink"]).unwrap();
+        stale.set_deadline(UNIX_EPOCH).unwrap();
+        stale.pushstr("v1").unwrap();
+        stale.send(client.get_ref()).unwrap();
+        client.send(ZMsg::from_frames(&["neigh"]).unwrap()).unwrap();
+        assert_eq!(server.recv().unwrap().popstr().unwrap().unwrap(), "neigh");
+
+        client.get_ref().send_str("no header").unwrap();
+        assert_eq!(server.recv().unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+}
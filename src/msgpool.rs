@@ -0,0 +1,180 @@
+//! Module: czmq-msgpool
+
+use crate::{Result, SocketLike, ZFrame, ZMsg};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// A pool of recycled messages and frames.
+///
+/// At high message rates, creating and destroying `zmsg_t` and
+/// `zframe_t` objects is a measurable cost. Messages taken with
+/// `msg()` return to the pool when dropped, and their frames are kept
+/// for reuse by `PooledMsg::addbytes()`. A frame is only reused for
+/// data of the same size, which is overwritten in place so that its
+/// buffer isn't reallocated. The pool keeps at most
+/// `capacity` idle messages and `capacity` idle frames; anything
+/// beyond that is destroyed as usual.
+pub struct MessagePool {
+    msgs: Mutex<Vec<ZMsg>>,
+    frames: Mutex<Vec<ZFrame>>,
+    capacity: usize,
+}
+
+impl MessagePool {
+    pub fn new(capacity: usize) -> MessagePool {
+        MessagePool {
+            msgs: Mutex::new(Vec::with_capacity(capacity)),
+            frames: Mutex::new(Vec::with_capacity(capacity)),
+            capacity: capacity,
+        }
+    }
+
+    /// Take an empty message from the pool, or create one if the
+    /// pool is empty.
+    pub fn msg(&self) -> PooledMsg {
+        let msg = self.msgs.lock().unwrap().pop().unwrap_or_else(ZMsg::new);
+
+        PooledMsg {
+            msg: Some(msg),
+            pool: self,
+        }
+    }
+
+    /// Number of idle messages held by the pool.
+    pub fn idle(&self) -> usize {
+        self.msgs.lock().unwrap().len()
+    }
+
+    fn frame(&self, data: &[u8]) -> Result<ZFrame> {
+        let idle = {
+            let mut frames = self.frames.lock().unwrap();
+            frames.iter().position(|f| f.size() == data.len()).map(|i| frames.swap_remove(i))
+        };
+
+        match idle {
+            Some(mut frame) => {
+                frame.as_mut_bytes().copy_from_slice(data);
+                Ok(frame)
+            },
+            None => ZFrame::new(data),
+        }
+    }
+
+    fn recycle_frames(&self, msg: &mut ZMsg) {
+        let mut frames = self.frames.lock().unwrap();
+        while let Some(frame) = msg.pop() {
+            if frames.len() < self.capacity {
+                frames.push(frame);
+            }
+        }
+    }
+
+    fn recycle(&self, mut msg: ZMsg) {
+        self.recycle_frames(&mut msg);
+
+        let mut msgs = self.msgs.lock().unwrap();
+        if msgs.len() < self.capacity {
+            msgs.push(msg);
+        }
+    }
+}
+
+/// A message borrowed from a `MessagePool`, which is returned to the
+/// pool when dropped.
+pub struct PooledMsg<'a> {
+    msg: Option<ZMsg>,
+    pool: &'a MessagePool,
+}
+
+impl<'a> PooledMsg<'a> {
+    /// Append a frame, reusing one of the pool's idle frames if
+    /// possible.
+    pub fn addbytes(&self, bytes: &[u8]) -> Result<()> {
+        let frame = self.pool.frame(bytes)?;
+        self.append(frame)
+    }
+
+    /// Send the message and empty it, ready to be refilled. Unlike
+    /// `ZMsg::send()`, the message isn't destroyed by sending.
+    pub fn send<D: SocketLike>(&mut self, dest: D) -> Result<()> {
+        self.msg.as_ref().unwrap().send_reused(dest)?;
+        self.clear();
+        Ok(())
+    }
+
+    /// Remove all frames, returning them to the pool.
+    pub fn clear(&mut self) {
+        self.pool.recycle_frames(self.msg.as_mut().unwrap());
+    }
+
+    /// Take the message out of the pool for good.
+    pub fn detach(mut self) -> ZMsg {
+        self.msg.take().unwrap()
+    }
+}
+
+impl<'a> Deref for PooledMsg<'a> {
+    type Target = ZMsg;
+
+    fn deref(&self) -> &ZMsg {
+        self.msg.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledMsg<'a> {
+    fn deref_mut(&mut self) -> &mut ZMsg {
+        self.msg.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledMsg<'a> {
+    fn drop(&mut self) {
+        if let Some(msg) = self.msg.take() {
+            self.pool.recycle(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZSock, ZSys};
+
+    #[test]
+    fn test_pool() {
+        ZSys::init();
+
+        let mut server = ZSock::new_pull("@inproc://msgpool_test").unwrap();
+        let mut client = ZSock::new_push(">inproc://msgpool_test").unwrap();
+        let pool = MessagePool::new(2);
+        let moo;
+
+        {
+            let mut msg = pool.msg();
+            msg.addbytes(b"moo").unwrap();
+            moo = msg.first().unwrap().as_bytes().as_ptr();
+            msg.addbytes(b"cow").unwrap();
+            msg.send(&mut client).unwrap();
+            assert_eq!(msg.size(), 0);
+
+            msg.addbytes(b"oink").unwrap();
+        }
+        assert_eq!(pool.idle(), 1);
+
+        let mut msg = ZMsg::recv(&mut server).unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "cow");
+
+        let mut msg = pool.msg();
+        assert_eq!(msg.size(), 0);
+        msg.addbytes(b"baa").unwrap();
+        // The idle frame of the same size is overwritten in place
+        assert_eq!(msg.first().unwrap().as_bytes().as_ptr(), moo);
+        assert_eq!(msg.popbytes().unwrap().unwrap(), b"baa");
+        assert_eq!(pool.idle(), 0);
+
+        let detached = msg.detach();
+        drop(detached);
+        assert_eq!(pool.idle(), 0);
+    }
+}
@@ -0,0 +1,214 @@
+//! Module: czmq-peertable
+//!
+//! Bookkeeping for the peers of a ROUTER socket. A `PeerTable` learns
+//! about peers from the messages they send: their routing ids, the
+//! user id a ZAP handler gave them, and when they were last heard
+//! from. Peers that stay quiet for longer than the table's TTL can be
+//! evicted, and messages can be addressed to peers by routing id.
+
+use crate::{Clock, Error, ErrorKind, Result, ZFrame, ZMsg, ZSock};
+use crate::clock;
+use std::{error, fmt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A peer that has sent to the socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Peer {
+    pub routing_id: Vec<u8>,
+    /// The `User-Id` the ZAP handler authenticated the peer as.
+    pub user_id: Option<String>,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    pub messages: u64,
+}
+
+pub struct PeerTable {
+    ttl: Duration,
+    peers: HashMap<Vec<u8>, Peer>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PeerTable {
+    /// Track peers, treating them as dead after `ttl` of silence.
+    pub fn new(ttl: Duration) -> PeerTable {
+        PeerTable {
+            ttl: ttl,
+            peers: HashMap::new(),
+            clock: clock::system(),
+        }
+    }
+
+    /// Replace the clock that liveness is timed by. Any peers already
+    /// tracked are forgotten.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.peers.clear();
+    }
+
+    /// Receive a message from a ROUTER socket, noting its sender, and
+    /// return the sender's routing id along with the rest of the
+    /// message.
+    pub fn recv(&mut self, sock: &ZSock) -> Result<(Vec<u8>, ZMsg)> {
+        let id_frame = ZFrame::recv(sock)?;
+        let routing_id = id_frame.as_bytes().to_vec();
+        let user_id = id_frame.meta("User-Id").and_then(|u| u.ok());
+        let msg = if id_frame.more() { ZMsg::recv(sock)? } else { ZMsg::new() };
+
+        self.heard(&routing_id, user_id.as_ref().map(|u| u.as_str()));
+        Ok((routing_id, msg))
+    }
+
+    /// Note that `routing_id` has been heard from, for messages that
+    /// were received some other way than `recv()`.
+    pub fn heard(&mut self, routing_id: &[u8], user_id: Option<&str>) {
+        let now = self.clock.now();
+        let peer = self.peers.entry(routing_id.to_vec()).or_insert_with(|| Peer {
+            routing_id: routing_id.to_vec(),
+            user_id: None,
+            first_seen: now,
+            last_seen: now,
+            messages: 0,
+        });
+
+        peer.last_seen = now;
+        peer.messages += 1;
+        if let Some(user_id) = user_id {
+            peer.user_id = Some(user_id.to_string());
+        }
+    }
+
+    pub fn get(&self, routing_id: &[u8]) -> Option<&Peer> {
+        self.peers.get(routing_id)
+    }
+
+    /// Stop tracking a peer, e.g. when it says goodbye.
+    pub fn remove(&mut self, routing_id: &[u8]) -> Option<Peer> {
+        self.peers.remove(routing_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// The peers that have been heard from within the TTL.
+    pub fn alive(&self) -> impl Iterator<Item = &Peer> {
+        let now = self.clock.now();
+        let ttl = self.ttl;
+        self.peers.values().filter(move |p| now < p.last_seen + ttl)
+    }
+
+    /// Forget the peers that haven't been heard from within the TTL,
+    /// and return them.
+    pub fn evict(&mut self) -> Vec<Peer> {
+        let now = self.clock.now();
+        let dead: Vec<Vec<u8>> = self.peers.values()
+            .filter(|p| now >= p.last_seen + self.ttl)
+            .map(|p| p.routing_id.clone())
+            .collect();
+
+        dead.iter().filter_map(|id| self.peers.remove(id)).collect()
+    }
+
+    /// Send `msg` to a peer in the table.
+    pub fn send(&self, sock: &ZSock, routing_id: &[u8], msg: ZMsg) -> Result<()> {
+        if !self.peers.contains_key(routing_id) {
+            return Err(Error::new(ErrorKind::InvalidArg, PeerTableError::UnknownPeer(routing_id.to_vec())));
+        }
+
+        msg.pushbytes(routing_id)?;
+        msg.send(sock)
+    }
+
+    /// Send a copy of `msg` to every live peer, returning how many
+    /// were sent.
+    pub fn broadcast(&self, sock: &ZSock, msg: &ZMsg) -> Result<usize> {
+        let mut sent = 0;
+        for peer in self.alive() {
+            let copy = msg.dup()?;
+            copy.pushbytes(&peer.routing_id)?;
+            copy.send(sock)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+#[derive(Debug)]
+pub enum PeerTableError {
+    UnknownPeer(Vec<u8>),
+}
+
+impl fmt::Display for PeerTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PeerTableError::UnknownPeer(ref id) => write!(f, "Unknown peer: {}", String::from_utf8_lossy(id)),
+        }
+    }
+}
+
+impl error::Error for PeerTableError {
+    fn description(&self) -> &str {
+        match *self {
+            PeerTableError::UnknownPeer(_) => "Unknown peer",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+    use crate::testing::MockClock;
+
+    #[test]
+    fn test_peer_table() {
+        ZSys::init();
+
+        let router = ZSock::new_router("inproc://peertable_test").unwrap();
+        let cow = ZSock::new_dealer("inproc://peertable_test").unwrap();
+        cow.set_identity("cow").unwrap();
+        let pig = ZSock::new_dealer("inproc://peertable_test").unwrap();
+        pig.set_identity("pig").unwrap();
+
+        let clock = MockClock::new();
+        let mut table = PeerTable::new(Duration::from_secs(10));
+        table.set_clock(clock.clone());
+
+        cow.send_str("moo").unwrap();
+        let (id, mut msg) = table.recv(&router).unwrap();
+        assert_eq!(id, b"cow");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+
+        clock.advance(Duration::from_secs(5));
+        pig.send_str("oink").unwrap();
+        table.recv(&router).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(b"pig").unwrap().messages, 1);
+        // No ZAP handler, so no user id
+        assert!(table.get(b"pig").unwrap().user_id.is_none());
+
+        table.send(&router, b"pig", ZMsg::from_frames(&["hello"]).unwrap()).unwrap();
+        assert_eq!(pig.recv_str().unwrap().unwrap(), "hello");
+        assert!(table.send(&router, b"horse", ZMsg::new()).is_err());
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(table.alive().count(), 1);
+        assert_eq!(table.broadcast(&router, &ZMsg::from_frames(&["hi"]).unwrap()).unwrap(), 1);
+        assert_eq!(pig.recv_str().unwrap().unwrap(), "hi");
+
+        let evicted = table.evict();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].routing_id, b"cow");
+        assert_eq!(table.len(), 1);
+    }
+}
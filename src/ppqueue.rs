@@ -0,0 +1,462 @@
+//! Module: czmq-ppqueue
+//!
+//! The Paranoid Pirate pattern: reliable request-reply through a
+//! queue. Clients send requests to the queue's frontend, which hands
+//! each one to the least recently used worker on its backend. Queue
+//! and workers heartbeat each other; the queue forgets workers that
+//! go quiet, and workers reconnect, backing off exponentially, when
+//! the queue does. Clients retry requests that go unanswered.
+
+use crate::{Clock, DeadLetterReason, DeadLetterSink, Error, ErrorKind, Result, SocketType, ZMsg, ZPoller, ZSock};
+use crate::clock;
+use std::{cmp, error, fmt};
+use std::sync::Arc;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Sent by a worker when it connects.
+pub const PPP_READY: &[u8] = b"\x01";
+/// Sent by queue and workers to show they're alive.
+pub const PPP_HEARTBEAT: &[u8] = b"\x02";
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const HEARTBEAT_LIVENESS: u32 = 3;
+const RECONNECT_INITIAL: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(32);
+
+/// Load balances requests over workers, dropping workers that stop
+/// heartbeating.
+pub struct PpQueue {
+    frontend: ZSock,
+    backend: ZSock,
+    // Only the backend is polled while no workers are available
+    backend_poller: ZPoller,
+    both_poller: ZPoller,
+    workers: VecDeque<(Vec<u8>, Instant)>,
+    heartbeat: Duration,
+    heartbeat_at: Instant,
+    clock: Arc<dyn Clock>,
+    expired: u64,
+    dead_letters: Option<DeadLetterSink>,
+}
+
+impl PpQueue {
+    /// Create a queue with ROUTER sockets on `frontend` for clients
+    /// and `backend` for workers, both of which bind by default.
+    pub fn new(frontend: &str, backend: &str) -> Result<PpQueue> {
+        let mut queue = PpQueue {
+            frontend: ZSock::new_router(frontend)?,
+            backend: ZSock::new_router(backend)?,
+            backend_poller: ZPoller::new()?,
+            both_poller: ZPoller::new()?,
+            workers: VecDeque::new(),
+            heartbeat: HEARTBEAT_INTERVAL,
+            heartbeat_at: Instant::now() + HEARTBEAT_INTERVAL,
+            clock: clock::system(),
+            expired: 0,
+            dead_letters: None,
+        };
+
+        queue.backend_poller.add(&queue.backend)?;
+        queue.both_poller.add(&queue.backend)?;
+        queue.both_poller.add(&queue.frontend)?;
+        Ok(queue)
+    }
+
+    /// How often to heartbeat idle workers, which are dropped after
+    /// three silent intervals. Defaults to one second, and should
+    /// match the workers' setting.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+        self.heartbeat_at = self.clock.now() + heartbeat;
+    }
+
+    /// Replace the clock used for heartbeats and worker expiry.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.heartbeat_at = self.clock.now() + self.heartbeat;
+        self.workers.clear();
+    }
+
+    /// The number of idle workers.
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// The number of requests dropped because their deadline passed
+    /// before a worker was free. See `ZMsg::set_ttl()`.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    /// Send expired requests to `sink` rather than discarding them.
+    pub fn set_dead_letters(&mut self, sink: DeadLetterSink) {
+        self.dead_letters = Some(sink);
+    }
+
+    /// Route messages until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let heartbeat = self.heartbeat;
+            self.poll(heartbeat)?;
+        }
+    }
+
+    /// Wait up to `timeout` for a message and route it, then drop
+    /// expired workers and send any heartbeats that are due.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        let timeout = cmp::min(timeout, self.heartbeat_at.checked_duration_since(self.clock.now()).unwrap_or_default());
+
+        let poller = if self.workers.is_empty() { &self.backend_poller } else { &self.both_poller };
+        if poller.wait_timeout::<ZSock>(Some(timeout))?.is_some() {
+            // Always drain the backend first, so workers are freed
+            // before more requests are taken on
+            if let Some(msg) = recv_nowait(&self.backend)? {
+                self.backend_msg(msg)?;
+            } else if !self.workers.is_empty() {
+                if let Some(msg) = recv_nowait(&self.frontend)? {
+                    if msg.is_expired() {
+                        self.expired += 1;
+                        if let Some(ref sink) = self.dead_letters {
+                            // The sink counts what it fails to keep,
+                            // and that's no reason to stop routing
+                            let _ = sink.send(msg, DeadLetterReason::Expired, "");
+                        }
+                    } else {
+                        let (worker, _) = self.workers.pop_front().unwrap();
+                        msg.pushbytes(&worker)?;
+                        msg.send(&self.backend)?;
+                    }
+                }
+            }
+        }
+
+        if self.clock.now() >= self.heartbeat_at {
+            for &(ref worker, _) in &self.workers {
+                let msg = ZMsg::new();
+                msg.addbytes(worker)?;
+                msg.addbytes(PPP_HEARTBEAT)?;
+                msg.send(&self.backend)?;
+            }
+            self.heartbeat_at = self.clock.now() + self.heartbeat;
+        }
+
+        let now = self.clock.now();
+        self.workers.retain(|&(_, expiry)| expiry > now);
+
+        Ok(())
+    }
+
+    fn backend_msg(&mut self, mut msg: ZMsg) -> Result<()> {
+        let worker = match msg.pop() {
+            Some(frame) => frame.as_bytes().to_vec(),
+            None => return Ok(()),
+        };
+
+        // Any message from a worker means it's alive and idle
+        self.workers.retain(|&(ref w, _)| *w != worker);
+        self.workers.push_back((worker, self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS));
+
+        if msg.size() == 1 {
+            // READY or HEARTBEAT; anything else is invalid, so drop it
+            Ok(())
+        } else {
+            msg.send(&self.frontend)
+        }
+    }
+}
+
+/// A request received by `PpWorker::recv()`.
+#[derive(Debug)]
+pub struct PpRequest {
+    /// The reply address frames, to be passed back with the reply.
+    pub envelope: Vec<Vec<u8>>,
+    pub body: ZMsg,
+}
+
+/// Serves requests from a `PpQueue`.
+pub struct PpWorker {
+    endpoint: String,
+    sock: ZSock,
+    poller: ZPoller,
+    heartbeat: Duration,
+    heartbeat_at: Instant,
+    expiry: Instant,
+    reconnect: Duration,
+    reconnect_max: Duration,
+    backoff: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl PpWorker {
+    /// Connect to a queue's backend and tell it we're ready.
+    pub fn new(endpoint: &str) -> Result<PpWorker> {
+        let (sock, poller) = connect_worker(endpoint)?;
+
+        Ok(PpWorker {
+            endpoint: endpoint.to_string(),
+            sock: sock,
+            poller: poller,
+            heartbeat: HEARTBEAT_INTERVAL,
+            heartbeat_at: Instant::now() + HEARTBEAT_INTERVAL,
+            expiry: Instant::now() + HEARTBEAT_INTERVAL * HEARTBEAT_LIVENESS,
+            reconnect: RECONNECT_INITIAL,
+            reconnect_max: RECONNECT_MAX,
+            backoff: RECONNECT_INITIAL,
+            clock: clock::system(),
+        })
+    }
+
+    /// How often to heartbeat the queue. The queue is presumed dead
+    /// after three silent intervals. Defaults to one second.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+        self.heartbeat_at = self.clock.now() + heartbeat;
+        self.expiry = self.clock.now() + heartbeat * HEARTBEAT_LIVENESS;
+    }
+
+    /// Replace the clock used for heartbeats, queue expiry and
+    /// reconnection backoff.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.heartbeat_at = self.clock.now() + self.heartbeat;
+        self.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+    }
+
+    /// The initial and maximum delays before reconnecting to a dead
+    /// queue. The delay doubles after each failed attempt. Defaults
+    /// to 1 and 32 seconds.
+    pub fn set_reconnect(&mut self, initial: Duration, max: Duration) {
+        self.reconnect = initial;
+        self.reconnect_max = max;
+        self.backoff = initial;
+    }
+
+    /// Wait for the next request, heartbeating and reconnecting as
+    /// needed. Returns `None` if no request arrives within `timeout`,
+    /// or waits forever if `timeout` is `None`.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<PpRequest>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let mut wait = self.heartbeat_at.checked_duration_since(self.clock.now()).unwrap_or_default();
+            if let Some(deadline) = deadline {
+                wait = cmp::min(wait, deadline.checked_duration_since(Instant::now()).unwrap_or_default());
+            }
+
+            if self.poller.wait_timeout::<ZSock>(Some(wait))?.is_some() {
+                let mut msg = ZMsg::recv(&self.sock)?;
+                self.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+                self.backoff = self.reconnect;
+
+                if msg.size() > 1 {
+                    if let Some(envelope) = msg.pop_envelope()? {
+                        return Ok(Some(PpRequest {
+                            envelope: envelope,
+                            body: msg,
+                        }));
+                    }
+                }
+                // Otherwise it's a heartbeat, or malformed
+            } else if self.clock.now() >= self.expiry {
+                self.clock.sleep(self.backoff);
+                self.backoff = cmp::min(self.backoff * 2, self.reconnect_max);
+
+                self.poller.remove(&self.sock)?;
+                let (sock, poller) = connect_worker(&self.endpoint)?;
+                self.sock = sock;
+                self.poller = poller;
+                self.expiry = self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS;
+            }
+
+            if self.clock.now() >= self.heartbeat_at {
+                let msg = ZMsg::new();
+                msg.addbytes(PPP_HEARTBEAT)?;
+                msg.send(&self.sock)?;
+                self.heartbeat_at = self.clock.now() + self.heartbeat;
+            }
+
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Send a reply to the client whose request came with `envelope`.
+    pub fn reply(&self, envelope: &[Vec<u8>], body: ZMsg) -> Result<()> {
+        body.push_envelope(envelope)?;
+        body.send(&self.sock)
+    }
+}
+
+/// Sends requests to a `PpQueue` over REQ, retrying on timeout (the
+/// Lazy Pirate pattern).
+pub struct PpClient {
+    endpoint: String,
+    sock: ZSock,
+    poller: ZPoller,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl PpClient {
+    pub fn new(endpoint: &str) -> Result<PpClient> {
+        let (sock, poller) = connect(SocketType::REQ, endpoint)?;
+
+        Ok(PpClient {
+            endpoint: endpoint.to_string(),
+            sock: sock,
+            poller: poller,
+            timeout: Duration::from_millis(2500),
+            retries: 3,
+        })
+    }
+
+    /// How long to wait for each reply. Defaults to 2.5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// How many times to resend a request that timed out. Defaults
+    /// to 3.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// Send a request and wait for its reply. A REQ socket can't
+    /// resend after a timeout, so each retry uses a new connection.
+    pub fn request(&mut self, body: ZMsg) -> Result<ZMsg> {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                self.poller.remove(&self.sock)?;
+                let (sock, poller) = connect(SocketType::REQ, &self.endpoint)?;
+                self.sock = sock;
+                self.poller = poller;
+            }
+
+            body.dup()?.send(&self.sock)?;
+
+            if self.poller.wait_timeout::<ZSock>(Some(self.timeout))?.is_some() {
+                return ZMsg::recv(&self.sock);
+            }
+        }
+
+        Err(Error::new(ErrorKind::Timeout, PpError::NoReply))
+    }
+}
+
+fn connect(sock_type: SocketType, endpoint: &str) -> Result<(ZSock, ZPoller)> {
+    let sock = ZSock::new(sock_type);
+    sock.set_linger(0);
+    sock.connect(endpoint)?;
+
+    let mut poller = ZPoller::new()?;
+    poller.add(&sock)?;
+    Ok((sock, poller))
+}
+
+fn connect_worker(endpoint: &str) -> Result<(ZSock, ZPoller)> {
+    let (sock, poller) = connect(SocketType::DEALER, endpoint)?;
+
+    let msg = ZMsg::new();
+    msg.addbytes(PPP_READY)?;
+    msg.send(&sock)?;
+    Ok((sock, poller))
+}
+
+fn recv_nowait(sock: &ZSock) -> Result<Option<ZMsg>> {
+    if sock.readable() {
+        ZMsg::recv(sock).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+#[derive(Debug)]
+pub enum PpError {
+    NoReply,
+}
+
+impl fmt::Display for PpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PpError::NoReply => write!(f, "No reply from queue"),
+        }
+    }
+}
+
+impl error::Error for PpError {
+    fn description(&self) -> &str {
+        match *self {
+            PpError::NoReply => "No reply from queue",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZMsg, ZSys};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_request() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let mut queue = PpQueue::new("inproc://ppqueue_test_frontend", "inproc://ppqueue_test_backend").unwrap();
+        queue.set_heartbeat(TICK);
+        let stop_queue = stop.clone();
+        let queue = thread::spawn(move || {
+            while !stop_queue.load(Ordering::SeqCst) {
+                queue.poll(TICK).unwrap();
+            }
+        });
+
+        let stop_worker = stop.clone();
+        let worker = thread::spawn(move || {
+            let mut worker = PpWorker::new("inproc://ppqueue_test_backend").unwrap();
+            worker.set_heartbeat(TICK);
+
+            while !stop_worker.load(Ordering::SeqCst) {
+                if let Some(req) = worker.recv(Some(TICK)).unwrap() {
+                    worker.reply(&req.envelope, req.body).unwrap();
+                }
+            }
+        });
+
+        let mut client = PpClient::new("inproc://ppqueue_test_frontend").unwrap();
+        client.set_timeout(Duration::from_secs(1));
+
+        for i in 0..3 {
+            let body = ZMsg::new();
+            body.addstr(&i.to_string()).unwrap();
+            let mut reply = client.request(body).unwrap();
+            assert_eq!(reply.popstr().unwrap().unwrap(), i.to_string());
+        }
+
+        stop.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+        queue.join().unwrap();
+    }
+
+    #[test]
+    fn test_no_reply() {
+        ZSys::init();
+
+        let mut client = PpClient::new("inproc://ppqueue_test_no_reply").unwrap();
+        client.set_timeout(TICK);
+        client.set_retries(1);
+
+        let e = client.request(ZMsg::new()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+}
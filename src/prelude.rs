@@ -0,0 +1,18 @@
+//! Module: czmq-prelude
+//!
+//! The traits, error types and enums that most programs need, so they
+//! can be imported in one go:
+//!
+//! ```rust
+//! use czmq::prelude::*;
+//!
+//! ZSys::init();
+//!
+//! let server = ZSock::new(SocketType::PULL);
+//! server.bind(&Endpoint::Inproc("prelude".into())).unwrap();
+//! ```
+
+pub use crate::{Error, ErrorKind, Result};
+pub use crate::{Message, MessageField, RawInterface, Sockish, SocketLike, ToEndpoint};
+pub use crate::{Endpoint, GssapiNameType, Mechanism, SocketType, VectoredMode, ZMonitorEvents};
+pub use crate::{ZFrame, ZMsg, ZSock, ZSys};
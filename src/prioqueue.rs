@@ -0,0 +1,431 @@
+//! Module: czmq-prioqueue
+//!
+//! A broker that hands requests to workers highest priority first.
+//! Clients send requests to the frontend ROUTER with a priority frame
+//! in front of the body: a single byte, where higher is more urgent.
+//! Requests wait in the broker until a worker is free, and each free
+//! worker gets the most urgent one.
+//!
+//! So that a steady stream of urgent work can't starve the rest, a
+//! waiting request gains a level of priority for every aging interval
+//! it has waited. Ties go to whichever arrived first.
+//!
+//! The backend speaks the Paranoid Pirate worker protocol, so
+//! `PpWorker`s serve it unchanged, and a `PpClient` can send requests
+//! by pushing the priority onto its body:
+//!
+//! ```rust
+//! use czmq::{PpClient, PriorityBroker, ZMsg, ZSys};
+//!
+//! ZSys::init();
+//!
+//! let broker = PriorityBroker::new("inproc://prio_doc_frontend", "inproc://prio_doc_backend")
+//!     .unwrap()
+//!     .start();
+//!
+//! let mut client = PpClient::new("inproc://prio_doc_frontend").unwrap();
+//! let body = ZMsg::from_frames(&["urgent work"]).unwrap();
+//! body.pushbytes(&[200]).unwrap();
+//! // client.request(body) once a worker is connected
+//! # drop(client);
+//! # broker.stop();
+//! ```
+
+use crate::{Clock, DeadLetterReason, DeadLetterSink, Result, ZMsg, ZPoller, ZSock, PPP_HEARTBEAT};
+use crate::clock;
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const HEARTBEAT_LIVENESS: u32 = 3;
+const AGING_INTERVAL: Duration = Duration::from_secs(1);
+// How often a started broker checks whether it has been stopped
+const ACTOR_TICK: Duration = Duration::from_millis(100);
+
+struct Pending {
+    priority: u8,
+    queued: Instant,
+    // Client envelope, up to and including the empty delimiter, then
+    // the body
+    msg: ZMsg,
+}
+
+/// Dispatches prioritised requests to workers.
+pub struct PriorityBroker {
+    frontend: ZSock,
+    backend: ZSock,
+    poller: ZPoller,
+    // In arrival order, so the first of equal priority is the oldest
+    pending: Vec<Pending>,
+    workers: VecDeque<(Vec<u8>, Instant)>,
+    heartbeat: Duration,
+    heartbeat_at: Instant,
+    aging: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    expired: u64,
+    dead_letters: Option<DeadLetterSink>,
+}
+
+impl PriorityBroker {
+    /// Create a broker with ROUTER sockets on `frontend` for clients
+    /// and `backend` for workers, both of which bind by default.
+    pub fn new(frontend: &str, backend: &str) -> Result<PriorityBroker> {
+        let mut broker = PriorityBroker {
+            frontend: ZSock::new_router(frontend)?,
+            backend: ZSock::new_router(backend)?,
+            poller: ZPoller::new()?,
+            pending: Vec::new(),
+            workers: VecDeque::new(),
+            heartbeat: HEARTBEAT_INTERVAL,
+            heartbeat_at: Instant::now() + HEARTBEAT_INTERVAL,
+            aging: Some(AGING_INTERVAL),
+            clock: clock::system(),
+            expired: 0,
+            dead_letters: None,
+        };
+
+        broker.poller.add(&broker.backend)?;
+        broker.poller.add(&broker.frontend)?;
+        Ok(broker)
+    }
+
+    /// How often to heartbeat idle workers, as for `PpQueue`. Defaults
+    /// to one second.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+        self.heartbeat_at = self.clock.now() + heartbeat;
+    }
+
+    /// How long a request waits to gain a level of priority, or
+    /// `None` to dispatch strictly by priority. Defaults to one
+    /// second.
+    pub fn set_aging(&mut self, aging: Option<Duration>) {
+        self.aging = aging.filter(|a| *a > Duration::default());
+    }
+
+    /// Replace the clock used for heartbeats, worker expiry and aging.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.heartbeat_at = self.clock.now() + self.heartbeat;
+        self.workers.clear();
+
+        let now = self.clock.now();
+        for pending in &mut self.pending {
+            pending.queued = now;
+        }
+    }
+
+    /// The number of idle workers.
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// The number of requests waiting for a worker.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The number of requests dropped because their deadline passed
+    /// while they waited. See `ZMsg::set_ttl()`.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    /// Send expired requests to `sink` rather than discarding them.
+    pub fn set_dead_letters(&mut self, sink: DeadLetterSink) {
+        self.dead_letters = Some(sink);
+    }
+
+    /// Route messages until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let heartbeat = self.heartbeat;
+            self.poll(heartbeat)?;
+        }
+    }
+
+    /// Run the broker on a background thread until the returned
+    /// `PriorityActor` is stopped or dropped.
+    pub fn start(mut self) -> PriorityActor {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                // A failed socket stops the broker, as with a zproxy
+                let heartbeat = self.heartbeat;
+                if self.poll(cmp::min(heartbeat, ACTOR_TICK)).is_err() {
+                    break;
+                }
+            }
+            self
+        });
+
+        PriorityActor {
+            stop: stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Wait up to `timeout` for messages and queue or route them, then
+    /// hand waiting requests to idle workers, drop expired workers and
+    /// send any heartbeats that are due.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        let timeout = cmp::min(timeout, self.heartbeat_at.checked_duration_since(self.clock.now()).unwrap_or_default());
+
+        if self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_some() {
+            while self.backend.readable() {
+                let msg = ZMsg::recv(&self.backend)?;
+                self.backend_msg(msg)?;
+            }
+
+            while self.frontend.readable() {
+                let msg = ZMsg::recv(&self.frontend)?;
+                self.frontend_msg(msg)?;
+            }
+        }
+
+        let now = self.clock.now();
+        self.workers.retain(|&(_, expiry)| expiry > now);
+        self.dispatch()?;
+
+        if self.clock.now() >= self.heartbeat_at {
+            for &(ref worker, _) in &self.workers {
+                let msg = ZMsg::new();
+                msg.addbytes(worker)?;
+                msg.addbytes(PPP_HEARTBEAT)?;
+                msg.send(&self.backend)?;
+            }
+            self.heartbeat_at = self.clock.now() + self.heartbeat;
+        }
+
+        Ok(())
+    }
+
+    fn frontend_msg(&mut self, mut msg: ZMsg) -> Result<()> {
+        // Split off the envelope, then take the priority from the
+        // front of the body
+        let body = ZMsg::new();
+        while let Some(frame) = msg.pop() {
+            let delimiter = frame.size() == 0;
+            body.append(frame)?;
+            if delimiter {
+                break;
+            }
+        }
+
+        let priority = match msg.pop() {
+            Some(ref frame) if frame.size() == 1 => frame.as_bytes()[0],
+            // Malformed, so drop it
+            _ => return Ok(()),
+        };
+
+        while let Some(frame) = msg.pop() {
+            body.append(frame)?;
+        }
+
+        self.pending.push(Pending {
+            priority: priority,
+            queued: self.clock.now(),
+            msg: body,
+        });
+        Ok(())
+    }
+
+    fn backend_msg(&mut self, mut msg: ZMsg) -> Result<()> {
+        let worker = match msg.pop() {
+            Some(frame) => frame.as_bytes().to_vec(),
+            None => return Ok(()),
+        };
+
+        // Any message from a worker means it's alive and idle
+        self.workers.retain(|&(ref w, _)| *w != worker);
+        self.workers.push_back((worker, self.clock.now() + self.heartbeat * HEARTBEAT_LIVENESS));
+
+        if msg.size() == 1 {
+            // READY or HEARTBEAT
+            Ok(())
+        } else {
+            msg.send(&self.frontend)
+        }
+    }
+
+    fn dispatch(&mut self) -> Result<()> {
+        while !self.workers.is_empty() {
+            let now = self.clock.now();
+            let next = match self.pending.iter().enumerate().max_by_key(|&(i, p)| (self.effective(p, now), cmp::Reverse(i))) {
+                Some((i, _)) => i,
+                None => break,
+            };
+
+            let pending = self.pending.remove(next);
+            if pending.msg.is_expired() {
+                self.expired += 1;
+                if let Some(ref sink) = self.dead_letters {
+                    // Failures are counted by the sink
+                    let _ = sink.send(pending.msg, DeadLetterReason::Expired, "");
+                }
+                continue;
+            }
+
+            let (worker, _) = self.workers.pop_front().unwrap();
+            pending.msg.pushbytes(&worker)?;
+            pending.msg.send(&self.backend)?;
+        }
+
+        Ok(())
+    }
+
+    fn effective(&self, pending: &Pending, now: Instant) -> u64 {
+        let boost = match self.aging {
+            Some(aging) => {
+                let waited = now.checked_duration_since(pending.queued).unwrap_or_default();
+                (waited.as_nanos() / aging.as_nanos()) as u64
+            },
+            None => 0,
+        };
+        pending.priority as u64 + boost
+    }
+}
+
+/// A `PriorityBroker` running on a background thread.
+pub struct PriorityActor {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<PriorityBroker>>,
+}
+
+impl PriorityActor {
+    /// Stop the broker and hand it back, with any requests still
+    /// waiting. Returns `None` if the broker's thread panicked.
+    pub fn stop(mut self) -> Option<PriorityBroker> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl Drop for PriorityActor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeadLetter, PpClient, PpWorker, SocketType, ZSys};
+    use std::time::SystemTime;
+    use crate::testing::MockClock;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn request(client: &ZSock, priority: u8, body: &str) {
+        let msg = ZMsg::from_frames(&[body]).unwrap();
+        msg.pushbytes(&[priority]).unwrap();
+        msg.pushbytes(&[]).unwrap();
+        msg.send(client).unwrap();
+    }
+
+    #[test]
+    fn test_priority_order() {
+        ZSys::init();
+
+        let mut broker = PriorityBroker::new("inproc://prioqueue_test_frontend", "inproc://prioqueue_test_backend").unwrap();
+        let clock = MockClock::new();
+        broker.set_clock(clock.clone());
+        broker.set_aging(Some(Duration::from_secs(10)));
+
+        let client = ZSock::new_dealer("inproc://prioqueue_test_frontend").unwrap();
+        request(&client, 1, "low");
+        request(&client, 5, "high");
+        while broker.pending() < 2 {
+            broker.poll(TICK).unwrap();
+        }
+
+        // Having waited five aging intervals, the low priority request
+        // overtakes a new high priority one
+        clock.advance(Duration::from_secs(50));
+        request(&client, 5, "newer high");
+        request(&client, 9, "urgent");
+        while broker.pending() < 4 {
+            broker.poll(TICK).unwrap();
+        }
+
+        let mut worker = PpWorker::new("inproc://prioqueue_test_backend").unwrap();
+        let mut order = Vec::new();
+        while order.len() < 4 {
+            broker.poll(TICK).unwrap();
+            if let Some(mut req) = worker.recv(Some(TICK)).unwrap() {
+                order.push(req.body.popstr().unwrap().unwrap());
+                worker.reply(&req.envelope, ZMsg::from_frames(&["done"]).unwrap()).unwrap();
+            }
+        }
+        assert_eq!(order, vec!["high", "urgent", "low", "newer high"]);
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        ZSys::init();
+
+        let mut broker = PriorityBroker::new("inproc://prioqueue_test_dlq_frontend", "inproc://prioqueue_test_dlq_backend").unwrap();
+        let (dead, sink) = crate::testing::pair(SocketType::PULL, SocketType::PUSH).unwrap();
+        dead.set_rcvtimeo(Some(1000));
+        broker.set_dead_letters(DeadLetterSink::socket(sink));
+
+        let client = ZSock::new_dealer("inproc://prioqueue_test_dlq_frontend").unwrap();
+        let mut msg = ZMsg::from_frames(&["stale"]).unwrap();
+        msg.set_deadline(SystemTime::now() - Duration::from_secs(1)).unwrap();
+        msg.pushbytes(&[1]).unwrap();
+        msg.pushbytes(&[]).unwrap();
+        msg.send(&client).unwrap();
+
+        let _worker = PpWorker::new("inproc://prioqueue_test_dlq_backend").unwrap();
+        while broker.expired() == 0 {
+            broker.poll(TICK).unwrap();
+        }
+
+        let letter = DeadLetter::from_msg(ZMsg::recv(&dead).unwrap()).unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::Expired);
+        // Still addressed to the client, with the body intact
+        assert_eq!(letter.msg.size(), 4);
+        assert_eq!(letter.msg.ref_last().unwrap().as_bytes(), b"stale");
+    }
+
+    #[test]
+    fn test_actor() {
+        ZSys::init();
+
+        let mut broker = PriorityBroker::new("inproc://prioqueue_test_actor_frontend", "inproc://prioqueue_test_actor_backend").unwrap();
+        broker.set_heartbeat(TICK);
+        let actor = broker.start();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_worker = stop.clone();
+        let worker = thread::spawn(move || {
+            let mut worker = PpWorker::new("inproc://prioqueue_test_actor_backend").unwrap();
+            worker.set_heartbeat(TICK);
+
+            while !stop_worker.load(Ordering::SeqCst) {
+                if let Some(req) = worker.recv(Some(TICK)).unwrap() {
+                    worker.reply(&req.envelope, req.body).unwrap();
+                }
+            }
+        });
+
+        let mut client = PpClient::new("inproc://prioqueue_test_actor_frontend").unwrap();
+        client.set_timeout(Duration::from_secs(1));
+        let body = ZMsg::from_frames(&["moo"]).unwrap();
+        body.pushbytes(&[3]).unwrap();
+        assert_eq!(client.request(body).unwrap().popstr().unwrap().unwrap(), "moo");
+
+        stop.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+        assert_eq!(actor.stop().unwrap().pending(), 0);
+    }
+}
@@ -0,0 +1,112 @@
+//! Module: czmq-protobuf
+//!
+//! Protobuf support via `prost`, enabled with the `prost` feature.
+//! Single messages map onto a frame. For multipart messages, each
+//! protobuf is preceded by a type tag frame, so a receiver can tell
+//! what it's about to decode.
+
+use crate::{Error, ErrorKind, Result, ZFrame, ZMsg};
+use prost::Message;
+use std::{error, fmt};
+
+impl ZFrame {
+    pub fn from_protobuf<M: Message>(msg: &M) -> Result<ZFrame> {
+        ZFrame::new(&encode(msg)?)
+    }
+
+    pub fn to_protobuf<M: Message + Default>(&self) -> Result<M> {
+        M::decode(self.as_bytes()).map_err(|e| Error::new(ErrorKind::InvalidArg, e))
+    }
+}
+
+impl ZMsg {
+    /// Append a type tag frame followed by `msg`.
+    pub fn add_protobuf<M: Message>(&self, tag: &str, msg: &M) -> Result<()> {
+        self.addstr(tag)?;
+        self.addbytes(&encode(msg)?)
+    }
+
+    /// Pop a type tag frame and the protobuf that follows it. Fails
+    /// with `ErrorKind::InvalidArg` if the tag isn't `tag`.
+    pub fn pop_protobuf<M: Message + Default>(&mut self, tag: &str) -> Result<M> {
+        let tag_frame = self.pop().ok_or_else(missing_frame)?;
+        if tag_frame.as_bytes() != tag.as_bytes() {
+            let actual = String::from_utf8_lossy(tag_frame.as_bytes()).into_owned();
+            return Err(Error::new(ErrorKind::InvalidArg, ProtobufError::WrongTag(actual)));
+        }
+
+        self.pop().ok_or_else(missing_frame)?.to_protobuf()
+    }
+
+    /// Return the type tag of the next protobuf without popping it.
+    pub fn peek_protobuf_tag(&self) -> Option<&[u8]> {
+        self.iter().next()
+    }
+}
+
+fn encode<M: Message>(msg: &M) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(msg.encoded_len());
+    msg.encode(&mut buf).map_err(|e| Error::new(ErrorKind::InvalidArg, e))?;
+    Ok(buf)
+}
+
+fn missing_frame() -> Error {
+    Error::new(ErrorKind::MissingFrame, ProtobufError::MissingFrame)
+}
+
+#[derive(Debug)]
+pub enum ProtobufError {
+    MissingFrame,
+    WrongTag(String),
+}
+
+impl fmt::Display for ProtobufError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtobufError::MissingFrame => write!(f, "Message is missing a protobuf frame"),
+            ProtobufError::WrongTag(ref t) => write!(f, "Unexpected protobuf type tag: {}", t),
+        }
+    }
+}
+
+impl error::Error for ProtobufError {
+    fn description(&self) -> &str {
+        match *self {
+            ProtobufError::MissingFrame => "Message is missing a protobuf frame",
+            ProtobufError::WrongTag(_) => "Unexpected protobuf type tag",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorKind, ZFrame, ZMsg};
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Ping {
+        #[prost(uint32, tag = "1")]
+        seq: u32,
+        #[prost(string, tag = "2")]
+        name: String,
+    }
+
+    #[test]
+    fn test_frame() {
+        let ping = Ping { seq: 7, name: "moo".into() };
+        let frame = ZFrame::from_protobuf(&ping).unwrap();
+        assert_eq!(frame.to_protobuf::<Ping>().unwrap(), ping);
+    }
+
+    #[test]
+    fn test_tagged() {
+        let ping = Ping { seq: 7, name: "moo".into() };
+        let mut msg = ZMsg::new();
+        msg.add_protobuf("Ping", &ping).unwrap();
+        msg.add_protobuf("Ping", &ping).unwrap();
+
+        assert_eq!(msg.peek_protobuf_tag(), Some(&b"Ping"[..]));
+        assert_eq!(msg.pop_protobuf::<Ping>("Ping").unwrap(), ping);
+        assert_eq!(msg.pop_protobuf::<Ping>("Pong").unwrap_err().kind(), ErrorKind::InvalidArg);
+        assert_eq!(ZMsg::new().pop_protobuf::<Ping>("Ping").unwrap_err().kind(), ErrorKind::MissingFrame);
+    }
+}
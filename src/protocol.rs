@@ -0,0 +1,348 @@
+//! Module: czmq-protocol
+//!
+//! Declared command sets for actors. Rather than hand-building
+//! "ALLOW"/"LISTEN" style messages on one side and matching strings on
+//! the other, a command set is declared once with the
+//! `czmq_actor_protocol!` macro, which generates a command enum for
+//! the actor's side of the pipe and a client proxy for the caller's.
+//!
+//! Each command is sent as its name followed by one frame per
+//! argument, encoded as `MessageField`s, so declared protocols stay
+//! wire compatible with CZMQ's built-in actors.
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZMsgFrames};
+use crate::trace;
+use std::{error, fmt};
+
+/// What an actor sends back after handling a command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReplyShape {
+    /// Nothing; the command is fire and forget.
+    None,
+    /// A `zsock_signal()`, which the client waits for.
+    Signal,
+    /// A message with one frame per type named here.
+    Frames(&'static [&'static str]),
+}
+
+/// Describes one command of a declared protocol.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommandSchema {
+    pub name: &'static str,
+    /// Argument names and types, in frame order.
+    pub args: &'static [(&'static str, &'static str)],
+    pub reply: ReplyShape,
+}
+
+/// A command set generated by `czmq_actor_protocol!`.
+pub trait ActorProtocol: Sized {
+    /// Every command in the protocol.
+    const COMMANDS: &'static [CommandSchema];
+
+    fn encode(&self) -> Result<ZMsg>;
+
+    /// Decode and validate a command received by the actor.
+    fn decode(msg: &ZMsg) -> Result<Self>;
+
+    /// The schema of this command.
+    fn schema(&self) -> &'static CommandSchema;
+
+    /// Find a command's schema by name.
+    fn lookup(name: &str) -> Option<&'static CommandSchema> {
+        Self::COMMANDS.iter().find(|c| c.name == name)
+    }
+}
+
+#[doc(hidden)]
+pub fn command_frame<'a>(frames: &mut ZMsgFrames<'a>) -> Result<&'a [u8]> {
+    frames.next().ok_or_else(|| Error::new(ErrorKind::MissingFrame, ProtocolError::MissingCommand))
+}
+
+#[doc(hidden)]
+pub fn finish_command<T>(frames: &mut ZMsgFrames, command: T) -> Result<T> {
+    if frames.next().is_some() {
+        Err(Error::new(ErrorKind::InvalidArg, ProtocolError::TrailingFrames))
+    } else {
+        Ok(command)
+    }
+}
+
+#[doc(hidden)]
+pub fn unknown_command<T>(name: &[u8]) -> Result<T> {
+    let name = String::from_utf8_lossy(name).into_owned();
+    Err(Error::new(ErrorKind::InvalidArg, ProtocolError::UnknownCommand(name)))
+}
+
+/// Covers a client method call with a `czmq.command` span when the
+/// `tracing` feature is enabled.
+#[doc(hidden)]
+pub struct CommandSpan {
+    _span: trace::Span,
+}
+
+#[doc(hidden)]
+pub fn command_span(name: &str) -> CommandSpan {
+    CommandSpan {
+        _span: trace::command(name),
+    }
+}
+
+/// Declare an actor's command set. Each line gives the enum variant,
+/// the command name sent on the wire, the client method with its
+/// arguments, and the reply shape: `none`, `signal`, or
+/// `reply(Type, ...)`, which returns the reply frames as a tuple.
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate czmq;
+///
+/// use czmq::{ActorProtocol, ZMsg};
+///
+/// czmq_actor_protocol! {
+///     #[derive(Debug, PartialEq)]
+///     pub enum FarmCommand, client FarmClient {
+///         Feed = "FEED" => feed(animal: String, kilos: u32) -> signal;
+///         Count = "COUNT" => count() -> reply(u32);
+///     }
+/// }
+///
+/// fn main() {
+///     let cmd = FarmCommand::Feed { animal: "cow".into(), kilos: 3 };
+///     let msg = cmd.encode().unwrap();
+///     assert_eq!(FarmCommand::decode(&msg).unwrap(), cmd);
+///
+///     let msg = ZMsg::from_frames(&[b"MILK"]).unwrap();
+///     assert!(FarmCommand::decode(&msg).is_err());
+/// }
+/// ```
+#[macro_export]
+macro_rules! czmq_actor_protocol {
+    (@shape none) => { $crate::ReplyShape::None };
+    (@shape signal) => { $crate::ReplyShape::Signal };
+    (@shape reply ( $($rty:ty),* )) => { $crate::ReplyShape::Frames(&[$(stringify!($rty)),*]) };
+
+    (@method $name:ident :: $variant:ident = $cmd:expr, $method:ident ($($arg:ident : $ty:ty),*) -> none) => {
+        pub fn $method(&self, $($arg: $ty),*) -> $crate::Result<()> {
+            let _span = $crate::protocol::command_span($cmd);
+            let msg = $crate::ActorProtocol::encode(&$name::$variant { $($arg: $arg),* })?;
+            self.actor.send(msg)
+        }
+    };
+
+    (@method $name:ident :: $variant:ident = $cmd:expr, $method:ident ($($arg:ident : $ty:ty),*) -> signal) => {
+        pub fn $method(&self, $($arg: $ty),*) -> $crate::Result<()> {
+            let _span = $crate::protocol::command_span($cmd);
+            let msg = $crate::ActorProtocol::encode(&$name::$variant { $($arg: $arg),* })?;
+            self.actor.send(msg)?;
+            self.actor.sock().wait()
+        }
+    };
+
+    (@method $name:ident :: $variant:ident = $cmd:expr, $method:ident ($($arg:ident : $ty:ty),*) -> reply ( $($rty:ty),* )) => {
+        pub fn $method(&self, $($arg: $ty),*) -> $crate::Result<($($rty,)*)> {
+            let _span = $crate::protocol::command_span($cmd);
+            let msg = $crate::ActorProtocol::encode(&$name::$variant { $($arg: $arg),* })?;
+            self.actor.send(msg)?;
+
+            let reply = self.actor.recv()?;
+            let mut frames = reply.iter();
+            let fields = ($(<$rty as $crate::MessageField>::decode_field(frames.next())?,)*);
+            $crate::protocol::finish_command(&mut frames, fields)
+        }
+    };
+
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident, client $client:ident {
+            $(
+                $variant:ident = $cmd:expr => $method:ident ( $($arg:ident : $ty:ty),* $(,)* ) -> $reply:ident $( ( $($rty:ty),* $(,)* ) )* ;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $($variant { $($arg: $ty),* }),*
+        }
+
+        impl $crate::ActorProtocol for $name {
+            const COMMANDS: &'static [$crate::CommandSchema] = &[
+                $($crate::CommandSchema {
+                    name: $cmd,
+                    args: &[$((stringify!($arg), stringify!($ty))),*],
+                    reply: $crate::czmq_actor_protocol!(@shape $reply $( ( $($rty),* ) )*),
+                }),*
+            ];
+
+            fn encode(&self) -> $crate::Result<$crate::ZMsg> {
+                let msg = $crate::ZMsg::new();
+                match *self {
+                    $($name::$variant { $(ref $arg),* } => {
+                        msg.addbytes($cmd.as_bytes())?;
+                        $($crate::MessageField::encode_field($arg, &msg)?;)*
+                    })*
+                }
+                Ok(msg)
+            }
+
+            fn decode(msg: &$crate::ZMsg) -> $crate::Result<$name> {
+                let mut frames = msg.iter();
+                let _cmd = $crate::protocol::command_frame(&mut frames)?;
+                $(
+                    if _cmd == $cmd.as_bytes() {
+                        let command = $name::$variant {
+                            $($arg: <$ty as $crate::MessageField>::decode_field(frames.next())?),*
+                        };
+                        return $crate::protocol::finish_command(&mut frames, command);
+                    }
+                )*
+                $crate::protocol::unknown_command(_cmd)
+            }
+
+            fn schema(&self) -> &'static $crate::CommandSchema {
+                let mut _index = 0;
+                $(
+                    if let $name::$variant { .. } = *self {
+                        return &Self::COMMANDS[_index];
+                    }
+                    _index += 1;
+                )*
+                unreachable!()
+            }
+        }
+
+        /// Client proxy for the actor's declared commands.
+        $vis struct $client<'a> {
+            actor: &'a $crate::ZActor,
+        }
+
+        impl<'a> $client<'a> {
+            pub fn new(actor: &'a $crate::ZActor) -> $client<'a> {
+                $client { actor: actor }
+            }
+
+            $(
+                $crate::czmq_actor_protocol! {
+                    @method $name::$variant = $cmd, $method ($($arg: $ty),*) -> $reply $( ( $($rty),* ) )*
+                }
+            )*
+        }
+    };
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    MissingCommand,
+    TrailingFrames,
+    UnknownCommand(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolError::MissingCommand => write!(f, "Message has no command frame"),
+            ProtocolError::TrailingFrames => write!(f, "Message has more frames than its command declares"),
+            ProtocolError::UnknownCommand(ref c) => write!(f, "Unknown command: {}", c),
+        }
+    }
+}
+
+impl error::Error for ProtocolError {
+    fn description(&self) -> &str {
+        match *self {
+            ProtocolError::MissingCommand => "Message has no command frame",
+            ProtocolError::TrailingFrames => "Message has more frames than its command declares",
+            ProtocolError::UnknownCommand(_) => "Unknown command",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, RawInterface, ZActor, ZMsg, ZSys};
+    use std::os::raw::c_void;
+    use std::ptr;
+
+    czmq_actor_protocol! {
+        #[derive(Debug, PartialEq)]
+        enum FarmCommand, client FarmClient {
+            Feed = "FEED" => feed(animal: String, kilos: u32) -> signal;
+            Count = "COUNT" => count() -> reply(u32, String);
+            Sleep = "SLEEP" => sleep() -> none;
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        assert_eq!(FarmCommand::COMMANDS.len(), 3);
+
+        let feed = FarmCommand::lookup("FEED").unwrap();
+        assert_eq!(feed.args, &[("animal", "String"), ("kilos", "u32")]);
+        assert_eq!(feed.reply, ReplyShape::Signal);
+        assert_eq!(FarmCommand::lookup("COUNT").unwrap().reply, ReplyShape::Frames(&["u32", "String"]));
+        assert!(FarmCommand::lookup("MILK").is_none());
+
+        assert_eq!(FarmCommand::Sleep {}.schema().name, "SLEEP");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let msg = ZMsg::new();
+        assert_eq!(FarmCommand::decode(&msg).unwrap_err().kind(), ErrorKind::MissingFrame);
+
+        let msg = ZMsg::from_frames(&[&b"FEED"[..], b"cow"]).unwrap();
+        assert_eq!(FarmCommand::decode(&msg).unwrap_err().kind(), ErrorKind::MissingFrame);
+
+        let msg = ZMsg::from_frames(&[&b"SLEEP"[..], b"now"]).unwrap();
+        assert_eq!(FarmCommand::decode(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        let msg = ZMsg::from_frames(&[b"MILK"]).unwrap();
+        assert_eq!(FarmCommand::decode(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_client() {
+        ZSys::init();
+
+        let actor = ZActor::new(farm_actor).unwrap();
+        let client = FarmClient::new(&actor);
+
+        client.feed("cow".into(), 3).unwrap();
+        client.feed("pig".into(), 2).unwrap();
+        client.sleep().unwrap();
+        assert_eq!(client.count().unwrap(), (5, "pig".to_string()));
+    }
+
+    unsafe extern "C" fn farm_actor(pipe: *mut czmq_sys::zsock_t, _args: *mut c_void) {
+        let pipe = pipe as *mut c_void;
+        czmq_sys::zsock_signal(pipe, 0);
+
+        let mut total = 0;
+        let mut last = String::new();
+
+        loop {
+            let raw = czmq_sys::zmsg_recv(pipe);
+            if raw == ptr::null_mut() {
+                break;
+            }
+
+            let msg = ZMsg::from_raw(raw, true);
+            match FarmCommand::decode(&msg) {
+                Ok(FarmCommand::Feed { animal, kilos }) => {
+                    total += kilos;
+                    last = animal;
+                    czmq_sys::zsock_signal(pipe, 0);
+                },
+                Ok(FarmCommand::Count {}) => {
+                    let reply = ZMsg::new();
+                    crate::MessageField::encode_field(&total, &reply).unwrap();
+                    crate::MessageField::encode_field(&last, &reply).unwrap();
+                    czmq_sys::zmsg_send(&mut reply.into_raw(), pipe);
+                },
+                Ok(FarmCommand::Sleep {}) => (),
+                // $TERM
+                Err(_) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Module: czmq-ratelimit
+//!
+//! Token bucket rate limiting for outgoing messages, so that one busy
+//! tenant of a broker can't flood its peers. A `RateLimiter` can cap
+//! messages per second, bytes per second, or both. Each bucket starts
+//! full and holds up to its burst, so short spikes go straight through
+//! while the long run average stays at the rate.
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZSock};
+use crate::clock::{self, Clock};
+use std::{error, fmt};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    // Tokens per second
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+}
+
+impl Bucket {
+    fn new(rate: u64, burst: u64) -> Bucket {
+        Bucket {
+            rate: rate.max(1) as f64,
+            burst: burst.max(1) as f64,
+            tokens: burst.max(1) as f64,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+    }
+
+    // How long until `n` tokens are available
+    fn wait(&self, n: f64) -> Duration {
+        if self.tokens >= n {
+            Duration::default()
+        } else {
+            Duration::from_secs_f64((n - self.tokens) / self.rate)
+        }
+    }
+}
+
+/// A socket that sends no faster than its budgets allow.
+pub struct RateLimiter {
+    sock: ZSock,
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+    clock: Arc<dyn Clock>,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Wrap `sock` with no limits; add them with `messages_per_sec()`
+    /// and `bytes_per_sec()`.
+    pub fn new(sock: ZSock) -> RateLimiter {
+        let clock = clock::system();
+
+        RateLimiter {
+            sock: sock,
+            messages: None,
+            bytes: None,
+            refilled: clock.now(),
+            clock: clock,
+        }
+    }
+
+    /// Send at most `rate` messages a second on average, and up to
+    /// `burst` at once.
+    pub fn messages_per_sec(mut self, rate: u32, burst: u32) -> RateLimiter {
+        self.messages = Some(Bucket::new(rate as u64, burst as u64));
+        self
+    }
+
+    /// Send at most `rate` bytes of message content a second on
+    /// average, and up to `burst` at once. Messages bigger than
+    /// `burst` can never be sent.
+    pub fn bytes_per_sec(mut self, rate: u64, burst: u64) -> RateLimiter {
+        self.bytes = Some(Bucket::new(rate, burst));
+        self
+    }
+
+    /// Read the time from `clock` rather than the system clock. The
+    /// buckets are refilled to their bursts.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.refilled = self.clock.now();
+        for bucket in self.messages.iter_mut().chain(self.bytes.iter_mut()) {
+            bucket.tokens = bucket.burst;
+        }
+    }
+
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    pub fn into_inner(self) -> ZSock {
+        self.sock
+    }
+
+    /// How long until a message of `size` bytes is within budget.
+    pub fn delay(&mut self, size: usize) -> Result<Duration> {
+        self.refill();
+
+        let mut wait = self.messages.as_ref().map_or(Duration::default(), |b| b.wait(1.0));
+        if let Some(ref bytes) = self.bytes {
+            if size as f64 > bytes.burst {
+                return Err(Error::new(ErrorKind::InvalidArg, RateLimitError::TooLarge(size)));
+            }
+            wait = wait.max(bytes.wait(size as f64));
+        }
+
+        Ok(wait)
+    }
+
+    /// Send `msg`, first sleeping for as long as it takes to be within
+    /// budget.
+    pub fn send(&mut self, msg: ZMsg) -> Result<()> {
+        let wait = self.delay(msg.content_size())?;
+        if wait > Duration::default() {
+            self.clock.sleep(wait);
+        }
+        self.send_now(msg)
+    }
+
+    /// Send `msg` if it's within budget, or fail with
+    /// `ErrorKind::Timeout` if not. The message is dropped either way,
+    /// as sending is what a caller over budget is meant to stop doing.
+    pub fn try_send(&mut self, msg: ZMsg) -> Result<()> {
+        let wait = self.delay(msg.content_size())?;
+        if wait > Duration::default() {
+            return Err(Error::new(ErrorKind::Timeout, RateLimitError::Exceeded(wait)));
+        }
+        self.send_now(msg)
+    }
+
+    pub fn recv(&self) -> Result<ZMsg> {
+        ZMsg::recv(&self.sock)
+    }
+
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.checked_duration_since(self.refilled).unwrap_or_default();
+        self.refilled = now;
+
+        for bucket in self.messages.iter_mut().chain(self.bytes.iter_mut()) {
+            bucket.refill(elapsed);
+        }
+    }
+
+    fn send_now(&mut self, msg: ZMsg) -> Result<()> {
+        self.refill();
+
+        let size = msg.content_size();
+        if let Some(ref mut messages) = self.messages {
+            messages.tokens -= 1.0;
+        }
+        if let Some(ref mut bytes) = self.bytes {
+            bytes.tokens -= size as f64;
+        }
+
+        msg.send(&self.sock)
+    }
+}
+
+#[derive(Debug)]
+pub enum RateLimitError {
+    Exceeded(Duration),
+    TooLarge(usize),
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RateLimitError::Exceeded(ref wait) => write!(f, "Send rate exceeded; retry in {:?}", wait),
+            RateLimitError::TooLarge(size) => write!(f, "Message of {} bytes is bigger than the byte burst", size),
+        }
+    }
+}
+
+impl error::Error for RateLimitError {
+    fn description(&self) -> &str {
+        match *self {
+            RateLimitError::Exceeded(_) => "Send rate exceeded",
+            RateLimitError::TooLarge(_) => "Message is bigger than the byte burst",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZSys};
+    use crate::testing::MockClock;
+
+    #[test]
+    fn test_rate_limiter() {
+        ZSys::init();
+
+        let (server, client) = crate::testing::pair(SocketType::PULL, SocketType::PUSH).unwrap();
+        let clock = MockClock::new();
+        let mut limiter = RateLimiter::new(client)
+            .messages_per_sec(10, 2)
+            .bytes_per_sec(1000, 10);
+        limiter.set_clock(clock.clone());
+
+        limiter.try_send(ZMsg::from_frames(&["moo"]).unwrap()).unwrap();
+        limiter.try_send(ZMsg::from_frames(&["baa"]).unwrap()).unwrap();
+        let err = limiter.try_send(ZMsg::from_frames(&["oink"]).unwrap()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+
+        clock.advance(Duration::from_millis(100));
+        limiter.try_send(ZMsg::from_frames(&["oink"]).unwrap()).unwrap();
+
+        // Blocking waits out the message budget
+        limiter.send(ZMsg::from_frames(&["neigh"]).unwrap()).unwrap();
+        assert!(clock.elapsed() >= Duration::from_millis(199));
+
+        let err = limiter.try_send(ZMsg::from_frames(&["mooooooooooo"]).unwrap()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidArg);
+
+        for expected in &["moo", "baa", "oink", "neigh"] {
+            assert_eq!(server.recv_str().unwrap().unwrap(), *expected);
+        }
+    }
+}
@@ -0,0 +1,410 @@
+//! Module: czmq-reconnect
+//!
+//! How a socket reconnects to endpoints it has lost, and noticing
+//! when it can't. A `ReconnectPolicy` gathers libzmq's reconnect
+//! options into one value that can be applied with
+//! `ZSock::set_reconnect_policy()`. A `StormWatch` monitors a socket
+//! for a burst of failed reconnects, e.g. to a peer that is down or
+//! refusing connections, and calls back so the application can alert
+//! or fail over rather than retry forever. `ConnectionStats` keeps
+//! running counts of connects, retries and disconnects per endpoint,
+//! for health checks and dashboards.
+
+use crate::{Result, ZMonitor, ZMonitorEvents, ZPoller, ZSock};
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_int;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// ZMQ_RECONNECT_STOP flags
+const STOP_CONN_REFUSED: c_int = 0x1;
+const STOP_HANDSHAKE_FAILED: c_int = 0x2;
+const STOP_AFTER_DISCONNECT: c_int = 0x4;
+
+// How often a storm watch or stats collector checks whether it has
+// been stopped
+const STORM_TICK: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReconnectPolicy {
+    /// How long to wait before the first reconnect attempt.
+    pub interval: Duration,
+    /// If set, the wait doubles after each failed attempt, up to this.
+    pub max_interval: Option<Duration>,
+    /// Give up if the peer refuses the connection.
+    pub stop_on_refused: bool,
+    /// Give up if the ZMTP handshake fails, e.g. on bad credentials.
+    pub stop_on_handshake_failure: bool,
+    /// Don't reconnect at all once connected and then disconnected.
+    pub stop_after_disconnect: bool,
+}
+
+impl Default for ReconnectPolicy {
+    /// libzmq's defaults: retry every 100ms, forever.
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy::new(Duration::from_millis(100))
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new(interval: Duration) -> ReconnectPolicy {
+        ReconnectPolicy {
+            interval: interval,
+            max_interval: None,
+            stop_on_refused: false,
+            stop_on_handshake_failure: false,
+            stop_after_disconnect: false,
+        }
+    }
+
+    /// Back off exponentially from the interval up to `max_interval`.
+    pub fn backoff(mut self, max_interval: Duration) -> ReconnectPolicy {
+        self.max_interval = Some(max_interval);
+        self
+    }
+
+    pub fn stop_on_refused(mut self) -> ReconnectPolicy {
+        self.stop_on_refused = true;
+        self
+    }
+
+    pub fn stop_on_handshake_failure(mut self) -> ReconnectPolicy {
+        self.stop_on_handshake_failure = true;
+        self
+    }
+
+    pub fn stop_after_disconnect(mut self) -> ReconnectPolicy {
+        self.stop_after_disconnect = true;
+        self
+    }
+
+    pub(crate) fn stop_flags(&self) -> c_int {
+        let mut flags = 0;
+        if self.stop_on_refused {
+            flags |= STOP_CONN_REFUSED;
+        }
+        if self.stop_on_handshake_failure {
+            flags |= STOP_HANDSHAKE_FAILED;
+        }
+        if self.stop_after_disconnect {
+            flags |= STOP_AFTER_DISCONNECT;
+        }
+        flags
+    }
+
+    pub(crate) fn from_raw(interval: Duration, max_interval: Option<Duration>, stop: c_int) -> ReconnectPolicy {
+        ReconnectPolicy {
+            interval: interval,
+            max_interval: max_interval,
+            stop_on_refused: stop & STOP_CONN_REFUSED != 0,
+            stop_on_handshake_failure: stop & STOP_HANDSHAKE_FAILED != 0,
+            stop_after_disconnect: stop & STOP_AFTER_DISCONNECT != 0,
+        }
+    }
+}
+
+/// Watches a socket for reconnect storms on a background thread.
+///
+/// The watch holds a monitor on the socket, so stop it before the
+/// socket is dropped.
+pub struct StormWatch {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StormWatch {
+    /// Call `on_storm` with the number of retries whenever `sock`
+    /// retries a connection `retries` times within `window`. Retries
+    /// are counted afresh after each call, and after each successful
+    /// connection.
+    pub fn start<F>(sock: &mut ZSock, retries: usize, window: Duration, mut on_storm: F) -> Result<StormWatch>
+        where F: FnMut(usize) + Send + 'static {
+        let mut monitor = ZMonitor::builder(sock)
+                                   .events(&[ZMonitorEvents::ConnectRetried, ZMonitorEvents::Connected])
+                                   .start()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            let mut poller = match ZPoller::new() {
+                Ok(poller) => poller,
+                Err(_) => return,
+            };
+            if poller.add(monitor.actor()).is_err() {
+                return;
+            }
+
+            let mut retried = VecDeque::new();
+            while !stopped.load(Ordering::SeqCst) {
+                match poller.wait_timeout::<ZSock>(Some(STORM_TICK)) {
+                    Ok(Some(_)) => (),
+                    Ok(None) => continue,
+                    Err(_) => break,
+                }
+
+                match monitor.get_attr() {
+                    Ok(Ok(ZMonitorEvents::ConnectRetried)) => {
+                        let now = Instant::now();
+                        retried.push_back(now);
+                        while retried.front().map_or(false, |&t| now.duration_since(t) > window) {
+                            retried.pop_front();
+                        }
+
+                        if retried.len() >= retries {
+                            on_storm(retried.len());
+                            retried.clear();
+                        }
+                    },
+                    Ok(Ok(ZMonitorEvents::Connected)) => retried.clear(),
+                    Ok(_) => (),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(StormWatch {
+            stop: stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for StormWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Connection counts for one endpoint, from `ConnectionStats`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EndpointStats {
+    /// Connection attempts, including retries.
+    pub attempts: u64,
+    /// Attempts after the first failed.
+    pub retries: u64,
+    /// Connections made or accepted.
+    pub connects: u64,
+    pub disconnects: u64,
+    /// How long the last connection took, from the first attempt.
+    pub last_connect_time: Option<Duration>,
+    /// The sum of all timed connections, for averaging.
+    pub total_connect_time: Duration,
+    pub connected: bool,
+    // When the attempts in progress began
+    pending: Option<Instant>,
+    timed: u32,
+}
+
+impl EndpointStats {
+    /// The mean time to connect, over connections that were timed.
+    pub fn average_connect_time(&self) -> Option<Duration> {
+        if self.timed == 0 {
+            None
+        } else {
+            Some(self.total_connect_time / self.timed)
+        }
+    }
+
+    fn record(&mut self, event: &ZMonitorEvents, now: Instant) {
+        match *event {
+            ZMonitorEvents::ConnectDelayed => {
+                self.attempts += 1;
+                self.pending.get_or_insert(now);
+            },
+            ZMonitorEvents::ConnectRetried => {
+                self.attempts += 1;
+                self.retries += 1;
+                self.pending.get_or_insert(now);
+            },
+            ZMonitorEvents::Connected | ZMonitorEvents::Accepted => {
+                self.connects += 1;
+                self.connected = true;
+                if let Some(started) = self.pending.take() {
+                    let elapsed = now.duration_since(started);
+                    self.last_connect_time = Some(elapsed);
+                    self.total_connect_time += elapsed;
+                    self.timed += 1;
+                }
+            },
+            ZMonitorEvents::Disconnected => {
+                self.disconnects += 1;
+                self.connected = false;
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Collects `EndpointStats` for a socket's endpoints from its monitor,
+/// on a background thread.
+///
+/// The collector holds a monitor on the socket, so stop it before the
+/// socket is dropped.
+pub struct ConnectionStats {
+    stats: Arc<Mutex<HashMap<String, EndpointStats>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ConnectionStats {
+    /// Start counting events on `sock`. Start before connecting, or
+    /// the first attempts will be missed.
+    pub fn start(sock: &mut ZSock) -> Result<ConnectionStats> {
+        let mut monitor = ZMonitor::builder(sock)
+                                   .events(&[ZMonitorEvents::ConnectDelayed,
+                                             ZMonitorEvents::ConnectRetried,
+                                             ZMonitorEvents::Connected,
+                                             ZMonitorEvents::Accepted,
+                                             ZMonitorEvents::Disconnected])
+                                   .start()?;
+
+        let stats = Arc::new(Mutex::new(HashMap::<String, EndpointStats>::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (shared, stopped) = (stats.clone(), stop.clone());
+
+        let handle = thread::spawn(move || {
+            let mut poller = match ZPoller::new() {
+                Ok(poller) => poller,
+                Err(_) => return,
+            };
+            if poller.add(monitor.actor()).is_err() {
+                return;
+            }
+
+            while !stopped.load(Ordering::SeqCst) {
+                match poller.wait_timeout::<ZSock>(Some(STORM_TICK)) {
+                    Ok(Some(_)) => (),
+                    Ok(None) => continue,
+                    Err(_) => break,
+                }
+
+                let (event, endpoint) = match monitor.get_event() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                };
+                let mut stats = shared.lock().unwrap_or_else(|e| e.into_inner());
+                stats.entry(endpoint).or_default().record(&event, Instant::now());
+            }
+        });
+
+        Ok(ConnectionStats {
+            stats: stats,
+            stop: stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// A snapshot of the counts so far, by endpoint.
+    pub fn stats(&self) -> HashMap<String, EndpointStats> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for ConnectionStats {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Capability, ErrorKind, SocketType, ZSys};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_policy() {
+        ZSys::init();
+
+        let sock = ZSock::new(SocketType::PUSH);
+        let policy = ReconnectPolicy::new(Duration::from_millis(250)).backoff(Duration::from_secs(5));
+        sock.set_reconnect_policy(&policy).unwrap();
+        assert_eq!(sock.reconnect_policy().unwrap(), policy);
+
+        let policy = ReconnectPolicy::default().stop_on_refused().stop_after_disconnect();
+        match sock.set_reconnect_policy(&policy) {
+            Ok(()) => assert_eq!(sock.reconnect_policy().unwrap(), policy),
+            Err(e) => {
+                assert!(!Capability::Draft.is_available());
+                assert_eq!(e.kind(), ErrorKind::Unsupported);
+            },
+        }
+    }
+
+    #[test]
+    fn test_storm_watch() {
+        ZSys::init();
+
+        // Find a port that nothing is listening on
+        let endpoint = {
+            let listener = ZSock::new(SocketType::PULL);
+            format!("tcp://127.0.0.1:{}", listener.bind("tcp://127.0.0.1:*").unwrap())
+        };
+
+        let mut sock = ZSock::new(SocketType::PUSH);
+        sock.set_reconnect_policy(&ReconnectPolicy::new(Duration::from_millis(10))).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watch = StormWatch::start(&mut sock, 3, Duration::from_secs(1), move |n| { let _ = tx.send(n); }).unwrap();
+        sock.connect(&endpoint).unwrap();
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 3);
+        watch.stop();
+    }
+
+    #[test]
+    fn test_connection_stats() {
+        ZSys::init();
+
+        let now = Instant::now();
+        let mut stats = EndpointStats::default();
+        stats.record(&ZMonitorEvents::ConnectDelayed, now);
+        stats.record(&ZMonitorEvents::ConnectRetried, now + Duration::from_millis(100));
+        stats.record(&ZMonitorEvents::Connected, now + Duration::from_millis(300));
+        stats.record(&ZMonitorEvents::Disconnected, now + Duration::from_secs(1));
+        stats.record(&ZMonitorEvents::ConnectRetried, now + Duration::from_secs(2));
+        stats.record(&ZMonitorEvents::Connected, now + Duration::from_millis(2100));
+        assert_eq!((stats.attempts, stats.retries, stats.connects, stats.disconnects), (3, 2, 2, 1));
+        assert_eq!(stats.last_connect_time, Some(Duration::from_millis(100)));
+        assert_eq!(stats.average_connect_time(), Some(Duration::from_millis(200)));
+        assert!(stats.connected);
+
+        // Count a live connection and its loss
+        let server = ZSock::new(SocketType::PULL);
+        let endpoint = format!("tcp://127.0.0.1:{}", server.bind("tcp://127.0.0.1:*").unwrap());
+
+        let mut sock = ZSock::new(SocketType::PUSH);
+        let collector = ConnectionStats::start(&mut sock).unwrap();
+        sock.connect(&endpoint).unwrap();
+        sock.send_str("moo").unwrap();
+        assert_eq!(server.recv_str().unwrap().unwrap(), "moo");
+        drop(server);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while collector.stats().get(&endpoint).map_or(0, |s| s.disconnects) == 0 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = collector.stats()[&endpoint].clone();
+        assert_eq!(stats.connects, 1);
+        assert!(!stats.connected);
+        collector.stop();
+    }
+}
@@ -0,0 +1,169 @@
+//! Module: czmq-recvbuffer
+
+use crate::{Error, ErrorKind, Result, SocketLike};
+use crate::error::retry_interrupted;
+use std::{error, fmt, result, slice, str};
+use std::os::raw::c_void;
+
+/// Reusable storage for received multipart messages. Frame buffers
+/// are kept between calls to `recv()`, so once they have grown to
+/// fit the traffic, a receive loop no longer allocates.
+///
+/// ```no_run
+/// # use czmq::{RecvBuffer, ZSock};
+/// let mut sock = ZSock::new_pull("inproc://recvbuffer").unwrap();
+/// let mut buffer = RecvBuffer::new();
+///
+/// loop {
+///     buffer.recv(&mut sock).unwrap();
+///     for frame in buffer.frames() {
+///         println!("{} bytes", frame.len());
+///     }
+/// }
+/// ```
+pub struct RecvBuffer {
+    frames: Vec<Vec<u8>>,
+    len: usize,
+}
+
+impl RecvBuffer {
+    pub fn new() -> RecvBuffer {
+        RecvBuffer {
+            frames: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Receive a complete multipart message, overwriting the
+    /// previous one.
+    pub fn recv<S: SocketLike>(&mut self, mut source: S) -> Result<()> {
+        let handle = unsafe { czmq_sys::zsock_resolve(source.as_socket_ptr()) };
+        self.len = 0;
+
+        loop {
+            if self.len == self.frames.len() {
+                self.frames.push(Vec::new());
+            }
+
+            let more = recv_frame_into(handle, &mut self.frames[self.len])?;
+            self.len += 1;
+
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Number of frames in the last message received.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn frame(&self, index: usize) -> Option<&[u8]> {
+        if index < self.len {
+            Some(&self.frames[index][..])
+        } else {
+            None
+        }
+    }
+
+    pub fn frame_str(&self, index: usize) -> Option<result::Result<&str, &[u8]>> {
+        self.frame(index).map(|bytes| match str::from_utf8(bytes) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(bytes),
+        })
+    }
+
+    pub fn frames(&self) -> &[Vec<u8>] {
+        &self.frames[..self.len]
+    }
+
+    /// Forget the last message without releasing frame capacity.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+// Receive one frame from a libzmq socket handle into `buf`, reusing
+// its capacity. Returns whether more frames follow.
+pub fn recv_frame_into(handle: *mut c_void, buf: &mut Vec<u8>) -> Result<bool> {
+    let mut msg = czmq_sys::zmq_msg_t::default();
+    unsafe { czmq_sys::zmq_msg_init(&mut msg) };
+
+    let rc = retry_interrupted(|| unsafe { czmq_sys::zmq_msg_recv(&mut msg, handle, 0) }, |rc| *rc == -1);
+    if rc == -1 {
+        let err = Error::from_errno(ErrorKind::NonZero, RecvBufferError::CmdFailed);
+        unsafe { czmq_sys::zmq_msg_close(&mut msg) };
+        return Err(err);
+    }
+
+    buf.clear();
+
+    let size = unsafe { czmq_sys::zmq_msg_size(&mut msg) } as usize;
+    if size > 0 {
+        let data = unsafe { czmq_sys::zmq_msg_data(&mut msg) } as *const u8;
+        buf.extend_from_slice(unsafe { slice::from_raw_parts(data, size) });
+    }
+
+    let more = unsafe { czmq_sys::zmq_msg_more(&mut msg) } == 1;
+    unsafe { czmq_sys::zmq_msg_close(&mut msg) };
+
+    Ok(more)
+}
+
+#[derive(Debug)]
+pub enum RecvBufferError {
+    CmdFailed,
+}
+
+impl fmt::Display for RecvBufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecvBufferError::CmdFailed => write!(f, "Could not receive frame"),
+        }
+    }
+}
+
+impl error::Error for RecvBufferError {
+    fn description(&self) -> &str {
+        match *self {
+            RecvBufferError::CmdFailed => "Could not receive frame",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZSock, ZSys};
+
+    #[test]
+    fn test_recv() {
+        ZSys::init();
+
+        let mut server = ZSock::new_pull("inproc://recvbuffer_test_recv").unwrap();
+        let mut client = ZSock::new_push("inproc://recvbuffer_test_recv").unwrap();
+
+        let mut buffer = RecvBuffer::new();
+        assert!(buffer.is_empty());
+
+        ZMsg::from_frames(&["moo", "cow"]).unwrap().send(&mut client).unwrap();
+        buffer.recv(&mut server).unwrap();
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.frame_str(0).unwrap().unwrap(), "moo");
+        assert_eq!(buffer.frame(1).unwrap(), b"cow");
+        assert!(buffer.frame(2).is_none());
+
+        let capacity = buffer.frames()[0].capacity();
+
+        ZMsg::from_frames(&["baa"]).unwrap().send(&mut client).unwrap();
+        buffer.recv(&mut server).unwrap();
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.frame(0).unwrap(), b"baa");
+        assert_eq!(buffer.frames()[0].capacity(), capacity);
+    }
+}
@@ -0,0 +1,369 @@
+//! Module: czmq-retry
+//!
+//! Retrying requests safely. A request that times out may still have
+//! been carried out, with only the reply lost, so resending it blindly
+//! can do the work twice. `RetryClient` stamps each request with an
+//! idempotency key before the first attempt and resends it with the
+//! same key, backing off exponentially between attempts. Servers keep
+//! the replies they've sent in an `IdempotencyCache` and answer a
+//! repeated key from there instead of handling the request again.
+//!
+//! `RetryClient` wraps anything that implements `Retry`, which
+//! `MdpClient` and `RpcClient` do. `RpcServer` strips keys before
+//! decoding requests, and deduplicates them once given a cache with
+//! `RpcServer::set_idempotency()`. MDP workers see the key frame in
+//! the request body and can check it against a cache themselves.
+//!
+//! The key frame is `IDEMPOTENCY_MAGIC` followed by the key. Like an
+//! expiry frame, it's found wherever it is in the message.
+
+use crate::{Clock, Error, ErrorKind, RawInterface, Result, ZFrame, ZMsg};
+use crate::clock;
+use crate::rng::Rng;
+use crate::zuuid::ZUuid;
+use std::{cmp, error, fmt};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The start of an idempotency key frame.
+pub const IDEMPOTENCY_MAGIC: &[u8] = b"\xffIDK";
+
+impl ZMsg {
+    /// Stamp the message with idempotency key `key`, replacing any
+    /// key it already has.
+    pub fn set_idempotency_key(&mut self, key: &[u8]) -> Result<()> {
+        self.take_idempotency_key();
+
+        let mut frame = IDEMPOTENCY_MAGIC.to_vec();
+        frame.extend_from_slice(key);
+        self.pushbytes(&frame)
+    }
+
+    pub fn idempotency_key(&self) -> Option<Vec<u8>> {
+        self.iter().find(|f| f.starts_with(IDEMPOTENCY_MAGIC)).map(|f| f[IDEMPOTENCY_MAGIC.len()..].to_vec())
+    }
+
+    /// Remove the idempotency key frame, returning the key.
+    pub fn take_idempotency_key(&mut self) -> Option<Vec<u8>> {
+        let mut frame = self.first();
+
+        while let Some(f) = frame {
+            if f.as_bytes().starts_with(IDEMPOTENCY_MAGIC) {
+                let key = f.as_bytes()[IDEMPOTENCY_MAGIC.len()..].to_vec();
+                // Owned, so the frame is destroyed once removed
+                let mut owned = unsafe { ZFrame::from_raw(f.into_raw(), true) };
+                self.remove(&mut owned);
+                return Some(key);
+            }
+            frame = self.next();
+        }
+
+        None
+    }
+}
+
+/// Generate a new random idempotency key.
+pub fn idempotency_key() -> Result<Vec<u8>> {
+    match ZUuid::new() {
+        Some(uuid) => Ok(uuid.data.to_vec()),
+        None => Err(Error::new(ErrorKind::NullPtr, RetryError::KeyGeneration)),
+    }
+}
+
+/// A client that `RetryClient` can resend requests with.
+pub trait Retry {
+    /// Send `request` to `target` (a service or method name) and wait
+    /// up to `timeout` for its reply. Fails with `ErrorKind::Timeout`
+    /// if none arrives, which is the only error that's retried.
+    fn attempt(&mut self, target: &str, request: ZMsg, timeout: Duration) -> Result<ZMsg>;
+
+    /// Get ready to try again after a timeout, e.g. by reconnecting.
+    fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How many times to try a request, and how long to wait in between.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    attempts: usize,
+    timeout: Duration,
+    initial: Duration,
+    max: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            timeout: Duration::from_millis(2500),
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Make up to `attempts` attempts, waiting `timeout` for each
+    /// reply.
+    pub fn new(attempts: usize, timeout: Duration) -> RetryPolicy {
+        RetryPolicy {
+            attempts: cmp::max(attempts, 1),
+            timeout: timeout,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Wait about `initial` before the first retry, doubling after
+    /// each one up to `max`. Each wait is jittered down by up to half,
+    /// so clients that failed together don't retry together. Defaults
+    /// to 100 milliseconds and 10 seconds.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryPolicy {
+        self.initial = initial;
+        self.max = cmp::max(initial, max);
+        self
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Retries timed out requests with the same idempotency key.
+pub struct RetryClient<C: Retry> {
+    inner: C,
+    policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    rng: Rng,
+}
+
+impl<C: Retry> RetryClient<C> {
+    pub fn new(inner: C, policy: RetryPolicy) -> RetryClient<C> {
+        RetryClient {
+            inner: inner,
+            policy: policy,
+            clock: clock::system(),
+            rng: Rng::from_time(),
+        }
+    }
+
+    /// Replace the clock used to wait between attempts.
+    pub fn set_clock<K: Clock + 'static>(&mut self, clock: K) {
+        self.clock = Arc::new(clock);
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Send `body` to `target`, retrying until a reply arrives or the
+    /// policy's attempts run out. A body that already has a key keeps
+    /// it, so a caller can retry across restarts with a stored key.
+    pub fn request(&mut self, target: &str, mut body: ZMsg) -> Result<ZMsg> {
+        if body.idempotency_key().is_none() {
+            body.set_idempotency_key(&idempotency_key()?)?;
+        }
+
+        let mut backoff = self.policy.initial;
+        for attempt in 0..self.policy.attempts {
+            if attempt > 0 {
+                self.clock.sleep(self.rng.between(backoff / 2, backoff));
+                backoff = cmp::min(backoff * 2, self.policy.max);
+                self.inner.reset()?;
+            }
+
+            match self.inner.attempt(target, body.dup()?, self.policy.timeout) {
+                Err(ref e) if e.kind() == ErrorKind::Timeout => continue,
+                result => return result,
+            }
+        }
+
+        Err(Error::new(ErrorKind::Timeout, RetryError::Exhausted(self.policy.attempts)))
+    }
+}
+
+/// Replies a server has sent, by idempotency key, so repeated requests
+/// get the same reply without being handled twice.
+pub struct IdempotencyCache {
+    ttl: Duration,
+    replies: HashMap<Vec<u8>, ZMsg>,
+    // Keys in the order they were added, which is also the order they
+    // expire in
+    expiries: VecDeque<(Instant, Vec<u8>)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl IdempotencyCache {
+    /// Keep replies for `ttl`, which should outlast a client's whole
+    /// retry schedule.
+    pub fn new(ttl: Duration) -> IdempotencyCache {
+        IdempotencyCache {
+            ttl: ttl,
+            replies: HashMap::new(),
+            expiries: VecDeque::new(),
+            clock: clock::system(),
+        }
+    }
+
+    /// Replace the clock used to expire replies. The cache is
+    /// emptied.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+        self.replies.clear();
+        self.expiries.clear();
+    }
+
+    /// A copy of the reply already sent for `request`'s key, if any.
+    pub fn get(&mut self, request: &ZMsg) -> Option<ZMsg> {
+        let key = request.idempotency_key()?;
+        self.get_key(&key)
+    }
+
+    /// Remember `reply` as the answer to `request`. Requests without
+    /// a key aren't remembered.
+    pub fn insert(&mut self, request: &ZMsg, reply: &ZMsg) -> Result<()> {
+        if let Some(key) = request.idempotency_key() {
+            self.insert_key(key, reply)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn insert_key(&mut self, key: Vec<u8>, reply: &ZMsg) -> Result<()> {
+        self.expire();
+        if self.replies.insert(key.clone(), reply.dup()?).is_none() {
+            self.expiries.push_back((self.clock.now() + self.ttl, key));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_key(&mut self, key: &[u8]) -> Option<ZMsg> {
+        self.expire();
+        self.replies.get(key).and_then(|reply| reply.dup().ok())
+    }
+
+    pub fn len(&self) -> usize {
+        self.replies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty()
+    }
+
+    fn expire(&mut self) {
+        let now = self.clock.now();
+        while self.expiries.front().map_or(false, |&(expiry, _)| expiry <= now) {
+            let (_, key) = self.expiries.pop_front().unwrap();
+            self.replies.remove(&key);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RetryError {
+    Exhausted(usize),
+    KeyGeneration,
+}
+
+impl fmt::Display for RetryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RetryError::Exhausted(attempts) => write!(f, "No reply after {} attempts", attempts),
+            RetryError::KeyGeneration => write!(f, "Could not generate an idempotency key"),
+        }
+    }
+}
+
+impl error::Error for RetryError {
+    fn description(&self) -> &str {
+        match *self {
+            RetryError::Exhausted(_) => "No reply after all attempts",
+            RetryError::KeyGeneration => "Could not generate an idempotency key",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClock;
+
+    // Times out a set number of times before answering, and records
+    // the keys it was sent
+    struct Flaky {
+        failures: usize,
+        keys: Vec<Vec<u8>>,
+        resets: usize,
+    }
+
+    impl Retry for Flaky {
+        fn attempt(&mut self, _: &str, request: ZMsg, _: Duration) -> Result<ZMsg> {
+            self.keys.push(request.idempotency_key().unwrap());
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::new(ErrorKind::Timeout, RetryError::Exhausted(0)));
+            }
+            Ok(request)
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.resets += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retry_client() {
+        let clock = MockClock::new();
+        let policy = RetryPolicy::new(3, Duration::from_secs(1)).backoff(Duration::from_secs(1), Duration::from_secs(10));
+        let mut client = RetryClient::new(Flaky { failures: 2, keys: Vec::new(), resets: 0 }, policy);
+        client.set_clock(clock.clone());
+
+        let mut reply = client.request("echo", ZMsg::from_frames(&["moo"]).unwrap()).unwrap();
+        assert!(reply.take_idempotency_key().is_some());
+        assert_eq!(reply.popstr().unwrap().unwrap(), "moo");
+
+        let flaky = client.get_ref();
+        assert_eq!(flaky.resets, 2);
+        assert_eq!(flaky.keys.len(), 3);
+        assert!(flaky.keys.iter().all(|k| *k == flaky.keys[0]));
+        // Jittered waits of 0.5-1 then 1-2 seconds
+        assert!(clock.elapsed() >= Duration::from_millis(1500) && clock.elapsed() <= Duration::from_secs(3));
+
+        client.get_mut().failures = 3;
+        let e = client.request("echo", ZMsg::new()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+
+    #[test]
+    fn test_idempotency_cache() {
+        let clock = MockClock::new();
+        let mut cache = IdempotencyCache::new(Duration::from_secs(10));
+        cache.set_clock(clock.clone());
+
+        let mut request = ZMsg::from_frames(&["moo"]).unwrap();
+        assert!(cache.get(&request).is_none());
+        cache.insert(&request, &ZMsg::from_frames(&["unkeyed"]).unwrap()).unwrap();
+        assert!(cache.is_empty());
+
+        request.set_idempotency_key(&idempotency_key().unwrap()).unwrap();
+        cache.insert(&request, &ZMsg::from_frames(&["cow"]).unwrap()).unwrap();
+        assert_eq!(cache.get(&request).unwrap().popstr().unwrap().unwrap(), "cow");
+
+        clock.advance(Duration::from_secs(10));
+        assert!(cache.get(&request).is_none());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+//! Module: czmq-rng
+//!
+//! A small xorshift generator for fault injection and jitter, where
+//! being seedable and dependency free matters more than statistical
+//! quality.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Xorshift never leaves zero
+        Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    /// Seed from the system clock.
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        Rng::new(u64::from(nanos))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+
+    /// A duration in `[min, max]`.
+    pub fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        if max <= min {
+            min
+        } else {
+            min + (max - min).mul_f64(self.next_f64())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        assert_eq!(a.next_u64(), b.next_u64());
+
+        for _ in 0..100 {
+            let f = a.next_f64();
+            assert!(f >= 0.0 && f < 1.0);
+        }
+
+        assert!(!a.chance(0.0));
+        assert!(a.chance(1.0));
+
+        let d = a.between(Duration::from_millis(10), Duration::from_millis(20));
+        assert!(d >= Duration::from_millis(10) && d <= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,402 @@
+//! Module: czmq-rpc
+//!
+//! Typed remote procedure calls over DEALER/ROUTER, enabled with the
+//! `serde` feature. Requests and replies are encoded with
+//! `serde_zmsg`.
+//!
+//! A request is a correlation ID, the method name and the encoded
+//! request. The reply carries the same correlation ID, then either
+//! `OK` and the encoded reply, or `ERR` and an error message. As
+//! replies are matched by ID rather than by order, a client can have
+//! any number of requests in flight at once.
+
+use crate::{serde_zmsg, Error, ErrorKind, IdempotencyCache, Result, Retry, RetryClient, SocketType, ZFrame, ZMsg, ZPoller, ZSock};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::{error, fmt};
+use std::time::{Duration, Instant};
+
+const OK: &str = "OK";
+const ERR: &str = "ERR";
+
+type Handler = Box<dyn FnMut(&ZMsg) -> Result<ZMsg> + Send>;
+
+/// Dispatches requests to handlers registered by method name.
+pub struct RpcServer {
+    sock: ZSock,
+    poller: ZPoller,
+    handlers: HashMap<String, Handler>,
+    idempotency: Option<IdempotencyCache>,
+}
+
+impl RpcServer {
+    /// Bind a server to `endpoint`.
+    pub fn new(endpoint: &str) -> Result<RpcServer> {
+        let sock = ZSock::new_router(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(RpcServer {
+            sock: sock,
+            poller: poller,
+            handlers: HashMap::new(),
+            idempotency: None,
+        })
+    }
+
+    /// Answer requests that repeat an idempotency key from `cache`,
+    /// rather than calling the handler again. See `RetryClient`.
+    pub fn set_idempotency(&mut self, cache: IdempotencyCache) {
+        self.idempotency = Some(cache);
+    }
+
+    /// Handle calls to method `name` with `handler`, replacing any
+    /// existing handler. If the handler fails, the caller gets its
+    /// error message.
+    pub fn register<Req, Rep, F>(&mut self, name: &str, mut handler: F)
+        where Req: DeserializeOwned,
+              Rep: Serialize,
+              F: FnMut(Req) -> Result<Rep> + Send + 'static {
+        self.handlers.insert(name.to_string(), Box::new(move |msg: &ZMsg| {
+            let request = serde_zmsg::from_msg(msg)?;
+            serde_zmsg::to_msg(&handler(request)?)
+        }));
+    }
+
+    /// Handle requests until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            self.poll(Duration::from_secs(1))?;
+        }
+    }
+
+    /// Wait up to `timeout` for a request and answer it.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        if self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(());
+        }
+
+        let mut msg = ZMsg::recv(&self.sock)?;
+        let (client, id) = match (msg.pop(), msg.pop()) {
+            (Some(client), Some(id)) => (client, id),
+            _ => return Ok(()),
+        };
+
+        // Handlers never see the key, cache or not
+        let key = msg.take_idempotency_key();
+        if let (Some(key), Some(cache)) = (key.as_ref(), self.idempotency.as_mut()) {
+            if let Some(reply) = cache.get_key(key) {
+                reply.prepend(id)?;
+                reply.prepend(client)?;
+                return reply.send(&self.sock);
+            }
+        }
+
+        let result = match msg.popstr() {
+            Some(Ok(name)) => match self.handlers.get_mut(&name) {
+                Some(handler) => handler(&msg),
+                None => Err(Error::new(ErrorKind::InvalidArg, RpcError::UnknownMethod(name))),
+            },
+            _ => Err(Error::new(ErrorKind::MissingFrame, RpcError::MissingFrame)),
+        };
+
+        let reply = match result {
+            Ok(reply) => {
+                reply.pushstr(OK)?;
+                reply
+            },
+            Err(e) => {
+                let reply = ZMsg::new();
+                reply.addstr(ERR)?;
+                reply.addstr(&e.to_string())?;
+                reply
+            },
+        };
+
+        if let (Some(key), Some(cache)) = (key, self.idempotency.as_mut()) {
+            cache.insert_key(key, &reply)?;
+        }
+
+        reply.prepend(id)?;
+        reply.prepend(client)?;
+        reply.send(&self.sock)
+    }
+}
+
+/// Calls methods on an `RpcServer`.
+pub struct RpcClient {
+    sock: ZSock,
+    poller: ZPoller,
+    next_id: u64,
+    pending: HashSet<u64>,
+    replies: HashMap<u64, ZMsg>,
+}
+
+impl RpcClient {
+    /// Connect a client to `endpoint`.
+    pub fn new(endpoint: &str) -> Result<RpcClient> {
+        let sock = ZSock::new(SocketType::DEALER);
+        sock.set_linger(0);
+        sock.connect(endpoint)?;
+
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        Ok(RpcClient {
+            sock: sock,
+            poller: poller,
+            next_id: 0,
+            pending: HashSet::new(),
+            replies: HashMap::new(),
+        })
+    }
+
+    /// The number of requests sent but not yet waited for.
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Call method `name` and wait up to `timeout` for the reply.
+    pub fn call<Req, Rep>(&mut self, name: &str, request: &Req, timeout: Duration) -> Result<Rep>
+        where Req: Serialize, Rep: DeserializeOwned {
+        let id = self.send(name, request)?;
+        self.wait(id, timeout)
+    }
+
+    /// Call method `name` without waiting for the reply. Returns an ID
+    /// to pass to `wait()`.
+    pub fn send<Req: Serialize>(&mut self, name: &str, request: &Req) -> Result<u64> {
+        self.send_raw(name, serde_zmsg::to_msg(request)?)
+    }
+
+    /// Wait up to `timeout` for the reply to request `id`. Replies to
+    /// other requests that arrive in the meantime are kept for later.
+    /// If this times out, a late reply to `id` is discarded.
+    pub fn wait<Rep: DeserializeOwned>(&mut self, id: u64, timeout: Duration) -> Result<Rep> {
+        decode_reply(&mut self.wait_raw(id, timeout)?)
+    }
+
+    fn send_raw(&mut self, name: &str, msg: ZMsg) -> Result<u64> {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        msg.pushstr(name)?;
+        msg.prepend(ZFrame::new(&id.to_be_bytes())?)?;
+        msg.send(&self.sock)?;
+
+        self.pending.insert(id);
+        Ok(id)
+    }
+
+    fn wait_raw(&mut self, id: u64, timeout: Duration) -> Result<ZMsg> {
+        if !self.pending.contains(&id) {
+            return Err(Error::new(ErrorKind::InvalidArg, RpcError::UnknownRequest(id)));
+        }
+
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(reply) = self.replies.remove(&id) {
+                self.pending.remove(&id);
+                return Ok(reply);
+            }
+
+            let wait = deadline.checked_duration_since(Instant::now()).unwrap_or_default();
+            if self.poller.wait_timeout::<ZSock>(Some(wait))?.is_none() {
+                self.pending.remove(&id);
+                return Err(Error::new(ErrorKind::Timeout, RpcError::NoReply(id)));
+            }
+
+            let mut reply = ZMsg::recv(&self.sock)?;
+            let reply_id = match reply.pop().and_then(|f| f.as_bytes().try_into().ok()) {
+                Some(bytes) => u64::from_be_bytes(bytes),
+                None => continue,
+            };
+
+            if self.pending.contains(&reply_id) {
+                self.replies.insert(reply_id, reply);
+            }
+        }
+    }
+}
+
+// Replies come back still encoded, with their status frame, so a
+// timed out attempt doesn't cost a decode
+impl Retry for RpcClient {
+    fn attempt(&mut self, name: &str, request: ZMsg, timeout: Duration) -> Result<ZMsg> {
+        let id = self.send_raw(name, request)?;
+        self.wait_raw(id, timeout)
+    }
+}
+
+impl RetryClient<RpcClient> {
+    /// Call method `name`, retrying with the same idempotency key as
+    /// the policy allows.
+    pub fn call<Req, Rep>(&mut self, name: &str, request: &Req) -> Result<Rep>
+        where Req: Serialize, Rep: DeserializeOwned {
+        decode_reply(&mut self.request(name, serde_zmsg::to_msg(request)?)?)
+    }
+}
+
+fn decode_reply<Rep: DeserializeOwned>(reply: &mut ZMsg) -> Result<Rep> {
+    match reply.popstr() {
+        Some(Ok(ref status)) if status == OK => serde_zmsg::from_msg(reply),
+        Some(Ok(ref status)) if status == ERR => {
+            let message = match reply.popstr() {
+                Some(Ok(message)) => message,
+                _ => String::new(),
+            };
+            Err(Error::new(ErrorKind::InvalidArg, RpcError::Remote(message)))
+        },
+        _ => Err(Error::new(ErrorKind::MissingFrame, RpcError::MissingFrame)),
+    }
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    MissingFrame,
+    NoReply(u64),
+    Remote(String),
+    UnknownMethod(String),
+    UnknownRequest(u64),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RpcError::MissingFrame => write!(f, "Message is missing a frame"),
+            RpcError::NoReply(id) => write!(f, "No reply to request {}", id),
+            RpcError::Remote(ref e) => write!(f, "Server error: {}", e),
+            RpcError::UnknownMethod(ref name) => write!(f, "Unknown method: {}", name),
+            RpcError::UnknownRequest(id) => write!(f, "Request {} is not in flight", id),
+        }
+    }
+}
+
+impl error::Error for RpcError {
+    fn description(&self) -> &str {
+        match *self {
+            RpcError::MissingFrame => "Message is missing a frame",
+            RpcError::NoReply(_) => "No reply to request",
+            RpcError::Remote(_) => "Server error",
+            RpcError::UnknownMethod(_) => "Unknown method",
+            RpcError::UnknownRequest(_) => "Request is not in flight",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, RetryPolicy, ZSys};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn spawn_server(endpoint: &str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        let mut server = RpcServer::new(endpoint).unwrap();
+        server.register("add", |(a, b): (i32, i32)| Ok(a + b));
+        server.register("echo", |s: String| Ok(s));
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                server.poll(TICK).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn test_call() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = spawn_server("inproc://rpc_test_call", stop.clone());
+
+        let mut client = RpcClient::new("inproc://rpc_test_call").unwrap();
+        let sum: i32 = client.call("add", &(2, 3), Duration::from_secs(1)).unwrap();
+        assert_eq!(sum, 5);
+
+        let e = client.call::<_, i32>("sub", &(2, 3), Duration::from_secs(1)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+        assert_eq!(client.in_flight(), 0);
+
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_idempotency() {
+        ZSys::init();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mut server = RpcServer::new("inproc://rpc_test_idempotency").unwrap();
+        server.register("add", move |(a, b): (i32, i32)| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Ok(a + b)
+        });
+        server.set_idempotency(IdempotencyCache::new(Duration::from_secs(60)));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_server = stop.clone();
+        let server = thread::spawn(move || {
+            while !stop_server.load(Ordering::SeqCst) {
+                server.poll(TICK).unwrap();
+            }
+        });
+
+        let client = RpcClient::new("inproc://rpc_test_idempotency").unwrap();
+        let mut client = RetryClient::new(client, RetryPolicy::new(2, Duration::from_secs(1)));
+        let sum: i32 = client.call("add", &(2, 3)).unwrap();
+        assert_eq!(sum, 5);
+
+        // Resending a key gets the first reply without another call
+        let key = crate::idempotency_key().unwrap();
+        for _ in 0..2 {
+            let mut body = serde_zmsg::to_msg(&(1, 1)).unwrap();
+            body.set_idempotency_key(&key).unwrap();
+            assert_eq!(decode_reply::<i32>(&mut client.request("add", body).unwrap()).unwrap(), 2);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_in_flight() {
+        ZSys::init();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = spawn_server("inproc://rpc_test_in_flight", stop.clone());
+
+        let mut client = RpcClient::new("inproc://rpc_test_in_flight").unwrap();
+        let first = client.send("echo", &"moo").unwrap();
+        let second = client.send("echo", &"oink").unwrap();
+        assert_eq!(client.in_flight(), 2);
+
+        // Waiting out of order keeps the first reply for later
+        assert_eq!(client.wait::<String>(second, Duration::from_secs(1)).unwrap(), "oink");
+        assert_eq!(client.wait::<String>(first, Duration::from_secs(1)).unwrap(), "moo");
+        assert_eq!(client.wait::<String>(first, TICK).unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_no_reply() {
+        ZSys::init();
+
+        let mut client = RpcClient::new("inproc://rpc_test_no_reply").unwrap();
+        let e = client.call::<_, i32>("add", &(2, 3), TICK).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+        assert_eq!(client.in_flight(), 0);
+    }
+}
@@ -0,0 +1,110 @@
+//! Module: czmq-scoped
+//!
+//! Temporary sockets that live for the duration of a closure. The
+//! sockets are created with a linger of 0, so they're torn down
+//! immediately when the closure returns or panics, without waiting
+//! for unsent messages.
+
+use crate::{Error, ErrorKind, RawInterface, Result, ZSock};
+use std::{error, fmt, ptr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zmq::SocketType;
+
+static ENDPOINT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+fn endpoint() -> String {
+    format!("inproc://czmq-scoped-{}", ENDPOINT_SEQ.fetch_add(1, Ordering::SeqCst))
+}
+
+/// Run `f` with a connected pair of PAIR sockets, passed as
+/// `(bound, connected)`.
+///
+/// ```rust
+/// use czmq::{with_pair, ZSys};
+///
+/// ZSys::init();
+///
+/// let reply = with_pair(|server, client| {
+///     client.send_str("moo").unwrap();
+///     server.recv_str().unwrap().unwrap()
+/// }).unwrap();
+/// assert_eq!(reply, "moo");
+/// ```
+pub fn with_pair<F, R>(f: F) -> Result<R>
+    where F: FnOnce(&mut ZSock, &mut ZSock) -> R {
+    let endpoint = endpoint();
+    let mut server = ZSock::new_pair(&format!("@{}", endpoint))?;
+    server.set_linger(0);
+    let mut client = ZSock::new_pair(&format!(">{}", endpoint))?;
+    client.set_linger(0);
+
+    Ok(f(&mut server, &mut client))
+}
+
+/// Run `f` with a socket of type `sock_type` bound to a unique inproc
+/// endpoint. The endpoint is passed to `f` so that peers can connect
+/// to it.
+pub fn with_socket<F, R>(sock_type: SocketType, f: F) -> Result<R>
+    where F: FnOnce(&mut ZSock, &str) -> R {
+    let mut sock = ZSock::new(sock_type);
+    if sock.as_mut_ptr() == ptr::null_mut() {
+        return Err(Error::new(ErrorKind::NullPtr, ScopedError::CreateSock));
+    }
+    sock.set_linger(0);
+
+    let endpoint = endpoint();
+    sock.bind(&endpoint)?;
+
+    Ok(f(&mut sock, &endpoint))
+}
+
+#[derive(Debug)]
+pub enum ScopedError {
+    CreateSock,
+}
+
+impl fmt::Display for ScopedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScopedError::CreateSock => write!(f, "Could not create socket"),
+        }
+    }
+}
+
+impl error::Error for ScopedError {
+    fn description(&self) -> &str {
+        match *self {
+            ScopedError::CreateSock => "Could not create socket",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZSock, ZSys};
+    use zmq::SocketType;
+
+    #[test]
+    fn test_with_pair() {
+        ZSys::init();
+
+        let mut msg = with_pair(|server, client| {
+            server.send_str("moo").unwrap();
+            ZMsg::recv(client).unwrap()
+        }).unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_with_socket() {
+        ZSys::init();
+
+        let s = with_socket(SocketType::PULL, |pull, endpoint| {
+            let push = ZSock::new_push(&format!(">{}", endpoint)).unwrap();
+            push.send_str("cow").unwrap();
+            pull.recv_str().unwrap().unwrap()
+        }).unwrap();
+        assert_eq!(s, "cow");
+    }
+}
@@ -0,0 +1,250 @@
+//! Module: czmq-sealedcert
+//!
+//! Certificates whose secret key is encrypted at rest, for hosts where
+//! a plaintext CURVE secret on disk isn't acceptable. Enabled with the
+//! `cert-encryption` feature.
+//!
+//! `ZCert::save_encrypted()` writes a ZPL file like `ZCert::save()`
+//! does, but in place of `curve/secret-key` it stores the secret key
+//! sealed with XChaCha20-Poly1305, under a key derived from a
+//! passphrase with Argon2id. The public key and metadata stay in the
+//! clear, so a `ZCertStore` pointed at a directory of sealed certs
+//! still loads their public halves for authentication.
+//!
+//! ```no_run
+//! use czmq::ZCert;
+//!
+//! let cert = ZCert::new().unwrap();
+//! cert.save_encrypted("server.cert", "correct horse").unwrap();
+//!
+//! // Later, perhaps asking an operator for the passphrase
+//! let cert = ZCert::load_encrypted_with("server.cert", |public| {
+//!     println!("Passphrase for {}:", public.public_txt());
+//!     let mut passphrase = String::new();
+//!     std::io::stdin().read_line(&mut passphrase)?;
+//!     Ok(passphrase.trim_end().to_string())
+//! }).unwrap();
+//! ```
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use crate::{Error, ErrorKind, Result, ZCert, ZConfig};
+use std::{error, fmt, fs};
+use std::path::Path;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+const KEY_SIZE: usize = 32;
+
+// Argon2id with 19MiB, two passes and one lane, per OWASP's minimum.
+// They're stored with each cert, so can be raised later without
+// breaking existing files.
+const ARGON2_MEMORY: u32 = 19456;
+const ARGON2_PASSES: u32 = 2;
+const ARGON2_LANES: u32 = 1;
+
+impl ZCert {
+    /// Save the cert to `path` with its secret key encrypted under
+    /// `passphrase`. On Unix the file is only readable by its owner.
+    pub fn save_encrypted<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<()> {
+        let mut salt = [0; SALT_SIZE];
+        let mut nonce = [0; NONCE_SIZE];
+        random(&mut salt)?;
+        random(&mut nonce)?;
+
+        let params = [ARGON2_MEMORY, ARGON2_PASSES, ARGON2_LANES];
+        let mut key = derive_key(passphrase, &salt, params)?;
+        let sealed = XChaCha20Poly1305::new(&key.into()).encrypt(XNonce::from_slice(&nonce), Payload {
+            msg: self.secret_key(),
+            // Binds the secret to its public key, so the two can't be
+            // mixed and matched between files
+            aad: self.public_key(),
+        });
+        wipe(&mut key);
+        let sealed = sealed.map_err(|_| Error::new(ErrorKind::InvalidArg, SealedCertError::Encrypt))?;
+
+        let mut nonce_sealed = nonce.to_vec();
+        nonce_sealed.extend_from_slice(&sealed);
+        // Both are multiples of 4 bytes, which is all Z85 asks
+        let encode_err = |_| Error::new(ErrorKind::InvalidArg, SealedCertError::Encrypt);
+
+        let root = ZConfig::new("root")?;
+        for name in self.meta_keys().iter() {
+            if let Some(Ok(value)) = self.meta(name) {
+                root.put(&format!("metadata/{}", name), &value)?;
+            }
+        }
+        root.put("curve/public-key", self.public_txt())?;
+        root.put("curve/sealed-secret-key", &zmq::z85_encode(&nonce_sealed).map_err(encode_err)?)?;
+        root.put("curve/kdf/algorithm", "argon2id")?;
+        root.put("curve/kdf/salt", &zmq::z85_encode(&salt).map_err(encode_err)?)?;
+        root.put("curve/kdf/params", &params.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(","))?;
+
+        // Create the file with tight permissions before the secret
+        // goes anywhere near it
+        fs::write(path.as_ref(), "")?;
+        restrict(path.as_ref())?;
+        root.save(path)
+    }
+
+    /// Load a cert saved with `save_encrypted()`, decrypting its
+    /// secret key with `passphrase`.
+    pub fn load_encrypted<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<ZCert> {
+        ZCert::load_encrypted_with(path, |_| Ok(passphrase.to_string()))
+    }
+
+    /// Like `load_encrypted()`, but asks `prompt` for the passphrase,
+    /// e.g. from a terminal or a secrets manager. The prompt is given
+    /// the cert's public half, so it can say which cert it's for, and
+    /// isn't called if the file can't be read.
+    pub fn load_encrypted_with<P, F>(path: P, prompt: F) -> Result<ZCert>
+        where P: AsRef<Path>,
+              F: FnOnce(&ZCert) -> Result<String> {
+        let path = path.as_ref();
+        let root = ZConfig::load(path)?;
+        let not_sealed = || Error::new(ErrorKind::InvalidArg, SealedCertError::NotSealed(path.display().to_string()));
+
+        let public_txt = root.get("curve/public-key").ok_or_else(not_sealed)?;
+        let sealed = zmq::z85_decode(root.get("curve/sealed-secret-key").ok_or_else(not_sealed)?)?;
+        let salt = zmq::z85_decode(root.get("curve/kdf/salt").ok_or_else(not_sealed)?)?;
+        if root.get("curve/kdf/algorithm") != Some("argon2id") || sealed.len() <= NONCE_SIZE {
+            return Err(not_sealed());
+        }
+        let params = parse_params(root.get("curve/kdf/params").ok_or_else(not_sealed)?).ok_or_else(not_sealed)?;
+
+        let public = ZCert::from_public_txt(public_txt)?;
+        if let Some(metadata) = root.locate("metadata") {
+            for item in metadata.children() {
+                if let (Some(name), Some(value)) = (item.name(), item.value()) {
+                    public.set_meta(name, value);
+                }
+            }
+        }
+
+        let passphrase = prompt(&public)?;
+        let mut key = derive_key(&passphrase, &salt, params)?;
+        let opened = XChaCha20Poly1305::new(&key.into()).decrypt(XNonce::from_slice(&sealed[..NONCE_SIZE]), Payload {
+            msg: &sealed[NONCE_SIZE..],
+            aad: public.public_key(),
+        });
+        wipe(&mut key);
+        let mut secret = opened.map_err(|_| Error::new(ErrorKind::InvalidArg, SealedCertError::Decrypt))?;
+
+        let cert = ZCert::from_keys(public.public_key(), &secret);
+        wipe(&mut secret);
+        cert.decode_meta(&public.encode_meta())?;
+        Ok(cert)
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: [u32; 3]) -> Result<[u8; KEY_SIZE]> {
+    let kdf_err = |_| Error::new(ErrorKind::InvalidArg, SealedCertError::Kdf);
+    let params = Params::new(params[0], params[1], params[2], Some(KEY_SIZE)).map_err(kdf_err)?;
+    let mut key = [0; KEY_SIZE];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(kdf_err)?;
+    Ok(key)
+}
+
+fn parse_params(s: &str) -> Option<[u32; 3]> {
+    let mut parts = s.split(',').map(|p| p.trim().parse().ok());
+    let params = [parts.next()??, parts.next()??, parts.next()??];
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(params)
+}
+
+fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        *b = 0;
+    }
+}
+
+fn random(buf: &mut [u8]) -> Result<()> {
+    getrandom::getrandom(buf).map_err(|_| Error::new(ErrorKind::NullPtr, SealedCertError::Random))
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict(_: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SealedCertError {
+    Decrypt,
+    Encrypt,
+    Kdf,
+    NotSealed(String),
+    Random,
+}
+
+impl fmt::Display for SealedCertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SealedCertError::Decrypt => write!(f, "Wrong passphrase, or the sealed secret key has been tampered with"),
+            SealedCertError::Encrypt => write!(f, "Could not encrypt secret key"),
+            SealedCertError::Kdf => write!(f, "Could not derive a key from the passphrase"),
+            SealedCertError::NotSealed(ref path) => write!(f, "Certificate at {} has no encrypted secret key", path),
+            SealedCertError::Random => write!(f, "Could not read random bytes from the OS"),
+        }
+    }
+}
+
+impl error::Error for SealedCertError {
+    fn description(&self) -> &str {
+        match *self {
+            SealedCertError::Decrypt => "Wrong passphrase, or the sealed secret key has been tampered with",
+            SealedCertError::Encrypt => "Could not encrypt secret key",
+            SealedCertError::Kdf => "Could not derive a key from the passphrase",
+            SealedCertError::NotSealed(_) => "Certificate has no encrypted secret key",
+            SealedCertError::Random => "Could not read random bytes from the OS",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZCertStore;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_save_load_encrypted() {
+        let dir = TempDir::new("sealedcert_test").unwrap();
+        let path = dir.path().join("server.cert");
+
+        let cert = ZCert::new().unwrap();
+        cert.set_meta("name", "moo");
+        cert.save_encrypted(&path, "correct horse").unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains(cert.secret_txt()));
+
+        let loaded = ZCert::load_encrypted_with(&path, |public| {
+            assert_eq!(public.public_txt(), cert.public_txt());
+            assert_eq!(public.meta("name").unwrap().unwrap(), "moo");
+            Ok("correct horse".to_string())
+        }).unwrap();
+        assert_eq!(loaded, cert);
+        assert_eq!(loaded.meta("name").unwrap().unwrap(), "moo");
+
+        assert_eq!(ZCert::load_encrypted(&path, "battery staple").unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        // The store only sees the public half
+        let store = ZCertStore::new(Some(dir.path().to_str().unwrap())).unwrap();
+        let public = store.lookup(cert.public_txt()).unwrap().unwrap();
+        assert_eq!(public.secret_key(), &[0; KEY_SIZE][..]);
+
+        let plain = dir.path().join("plain.cert");
+        cert.save_public(&plain).unwrap();
+        assert_eq!(ZCert::load_encrypted(&plain, "correct horse").unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+}
@@ -0,0 +1,164 @@
+//! Module: czmq-selector
+//!
+//! Fair reading from many sockets. A `ZPoller` returns the first
+//! ready socket it finds, so a loop that reads one message per wait
+//! keeps serving a busy socket while the others queue up. A
+//! `Selector` reads in rounds instead: each tick every ready socket
+//! gets up to its priority's worth of messages, the round starts one
+//! socket further along each tick, and sockets that missed out
+//! because the batch was full go first next time.
+
+use crate::{Result, ZMsg, ZPoller, ZSock};
+use std::cmp;
+use std::time::Duration;
+
+// The most messages `select()` returns at once, unless changed
+const DEFAULT_BATCH: usize = 64;
+
+struct Entry {
+    sock: ZSock,
+    priority: usize,
+    // Was ready last tick, but got nothing as the batch was full
+    starved: bool,
+}
+
+pub struct Selector {
+    poller: ZPoller,
+    entries: Vec<Option<Entry>>,
+    cursor: usize,
+    batch: usize,
+}
+
+impl Selector {
+    pub fn new() -> Result<Selector> {
+        Ok(Selector {
+            poller: ZPoller::new()?,
+            entries: Vec::new(),
+            cursor: 0,
+            batch: DEFAULT_BATCH,
+        })
+    }
+
+    /// Limit how many messages each `select()` returns.
+    pub fn set_batch(&mut self, batch: usize) {
+        self.batch = cmp::max(batch, 1);
+    }
+
+    /// Read from `sock`, taking up to `priority` messages from it per
+    /// tick. Returns the id that `select()` tags its messages with.
+    pub fn add(&mut self, sock: ZSock, priority: usize) -> Result<usize> {
+        self.poller.add(&sock)?;
+        self.entries.push(Some(Entry {
+            sock: sock,
+            priority: cmp::max(priority, 1),
+            starved: false,
+        }));
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Stop reading from a socket and hand it back. Its id is not
+    /// reused.
+    pub fn remove(&mut self, id: usize) -> Result<Option<ZSock>> {
+        match self.entries.get_mut(id).and_then(|e| e.take()) {
+            Some(entry) => {
+                self.poller.remove(&entry.sock)?;
+                Ok(Some(entry.sock))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_priority(&mut self, id: usize, priority: usize) {
+        if let Some(Some(entry)) = self.entries.get_mut(id) {
+            entry.priority = cmp::max(priority, 1);
+        }
+    }
+
+    pub fn sock(&self, id: usize) -> Option<&ZSock> {
+        self.entries.get(id).and_then(|e| e.as_ref()).map(|e| &e.sock)
+    }
+
+    /// Wait up to `timeout` for any socket to be ready, then read a
+    /// round of messages, each tagged with its socket's id. Returns
+    /// an empty batch if the timeout expires.
+    pub fn select(&mut self, timeout: Duration) -> Result<Vec<(usize, ZMsg)>> {
+        let mut batch = Vec::new();
+
+        if !self.any_readable() && self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(batch);
+        }
+
+        let len = self.entries.len();
+        let mut order: Vec<usize> = (0..len).map(|i| (self.cursor + i) % len).collect();
+        // Sockets that were passed over last tick go first
+        order.sort_by_key(|&id| !self.entries[id].as_ref().map_or(false, |e| e.starved));
+        self.cursor = (self.cursor + 1) % cmp::max(len, 1);
+
+        for id in order {
+            let entry = match self.entries[id] {
+                Some(ref mut entry) => entry,
+                None => continue,
+            };
+
+            let mut taken = 0;
+            while taken < entry.priority && batch.len() < self.batch && entry.sock.readable() {
+                batch.push((id, ZMsg::recv(&entry.sock)?));
+                taken += 1;
+            }
+            entry.starved = taken == 0 && entry.sock.readable();
+        }
+
+        Ok(batch)
+    }
+
+    fn any_readable(&self) -> bool {
+        self.entries.iter().flatten().any(|e| e.sock.readable())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+
+    fn count(batch: &[(usize, ZMsg)], id: usize) -> usize {
+        batch.iter().filter(|&&(i, _)| i == id).count()
+    }
+
+    #[test]
+    fn test_select() {
+        ZSys::init();
+
+        let chatty = ZSock::new_push("inproc://selector_test_chatty").unwrap();
+        let quiet = ZSock::new_push("inproc://selector_test_quiet").unwrap();
+
+        let mut selector = Selector::new().unwrap();
+        selector.set_batch(4);
+        let c = selector.add(ZSock::new_pull("@inproc://selector_test_chatty").unwrap(), 3).unwrap();
+        let q = selector.add(ZSock::new_pull("@inproc://selector_test_quiet").unwrap(), 1).unwrap();
+
+        for _ in 0..20 {
+            chatty.send_str("moo").unwrap();
+        }
+        for _ in 0..2 {
+            quiet.send_str("baa").unwrap();
+        }
+
+        // Whichever goes first, the quiet socket gets served
+        let mut served = 0;
+        for _ in 0..2 {
+            let batch = selector.select(Duration::from_secs(1)).unwrap();
+            assert_eq!(batch.len(), 4);
+            assert!(count(&batch, c) <= 3);
+            served += count(&batch, q);
+        }
+        assert_eq!(served, 2);
+
+        let batch = selector.select(Duration::from_secs(1)).unwrap();
+        assert_eq!(count(&batch, c), 3);
+
+        assert!(selector.remove(q).unwrap().is_some());
+        assert!(selector.sock(q).is_none());
+        assert!(selector.remove(q).unwrap().is_none());
+    }
+}
@@ -0,0 +1,603 @@
+//! Module: czmq-serde_zmsg
+//!
+//! A serde data format that maps values onto the frames of a
+//! multipart message, enabled with the `serde` feature. Each field of
+//! a struct or tuple becomes one frame:
+//!
+//! * integers and floats are sent in network byte order;
+//! * `bool` is a single byte, 0 or 1;
+//! * strings, chars and byte arrays (see the `serde_bytes` crate) are
+//!   sent as is;
+//! * `Option` is a one-byte presence frame, followed by the value if
+//!   there is one;
+//! * sequences and maps are a 4-byte length frame, followed by their
+//!   elements;
+//! * enum variants are a 4-byte variant index, followed by the
+//!   variant's fields.
+//!
+//! Nested structs are flattened, so the format isn't self-describing:
+//! the receiver needs to know what type to expect.
+//!
+//! ```rust
+//! use czmq::serde_zmsg;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Hello {
+//!     seq: u32,
+//!     name: String,
+//! }
+//!
+//! let hello = Hello { seq: 1, name: "moo".into() };
+//! let msg = serde_zmsg::to_msg(&hello).unwrap();
+//! assert_eq!(msg.size(), 2);
+//! assert_eq!(serde_zmsg::from_msg::<Hello>(&msg).unwrap(), hello);
+//! ```
+
+use crate::{Error, ErrorKind, Result, ZMsg, ZMsgFrames};
+use serde::{de, ser, Deserialize, Serialize};
+use serde::de::{DeserializeSeed, IntoDeserializer, Visitor};
+use std::convert::TryFrom;
+use std::{error, fmt, mem, str};
+
+/// Serialize `value` into a new message.
+pub fn to_msg<T: Serialize + ?Sized>(value: &T) -> Result<ZMsg> {
+    let msg = ZMsg::new();
+    value.serialize(&mut Serializer { msg: &msg })?;
+    Ok(msg)
+}
+
+/// Deserialize a value from every frame of `msg`. Strings and byte
+/// arrays may borrow from the message.
+pub fn from_msg<'a, T: Deserialize<'a>>(msg: &'a ZMsg) -> Result<T> {
+    let mut deserializer = Deserializer { frames: msg.iter() };
+    let value = T::deserialize(&mut deserializer)?;
+
+    if deserializer.frames.next().is_some() {
+        Err(Error::new(ErrorKind::InvalidArg, SerdeZMsgError::TrailingFrames))
+    } else {
+        Ok(value)
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::new(ErrorKind::InvalidArg, SerdeZMsgError::Custom(msg.to_string()))
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::new(ErrorKind::InvalidArg, SerdeZMsgError::Custom(msg.to_string()))
+    }
+}
+
+/// Appends one frame per field to a message.
+pub struct Serializer<'a> {
+    msg: &'a ZMsg,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(msg: &'a ZMsg) -> Serializer<'a> {
+        Serializer { msg: msg }
+    }
+
+    fn write_len(&self, len: usize) -> Result<()> {
+        let len = u32::try_from(len).map_err(|_| Error::new(ErrorKind::InvalidArg, SerdeZMsgError::TooLong))?;
+        self.msg.addbytes(&len.to_be_bytes())
+    }
+
+    fn write_variant(&self, index: u32) -> Result<()> {
+        self.msg.addbytes(&index.to_be_bytes())
+    }
+}
+
+macro_rules! ser_be {
+    ($($method:ident: $t:ty),*) => {$(
+        fn $method(self, v: $t) -> Result<()> {
+            self.msg.addbytes(&v.to_be_bytes())
+        }
+    )*}
+}
+
+impl<'a, 'b> ser::Serializer for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    ser_be!(serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+            serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64);
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.msg.addbytes(&[v as u8])
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        ser::Serializer::serialize_u32(self, v.to_bits())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        ser::Serializer::serialize_u64(self, v.to_bits())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        ser::Serializer::serialize_str(self, v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.msg.addbytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.msg.addbytes(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.msg.addbytes(&[0])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.msg.addbytes(&[1])?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, _variant: &'static str) -> Result<()> {
+        self.write_variant(index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, index: u32, _variant: &'static str, value: &T) -> Result<()> {
+        self.write_variant(index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self> {
+        self.write_len(len.ok_or_else(|| Error::new(ErrorKind::InvalidArg, SerdeZMsgError::UnknownLength))?)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, index: u32, _variant: &'static str, _len: usize) -> Result<Self> {
+        self.write_variant(index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self> {
+        self.write_len(len.ok_or_else(|| Error::new(ErrorKind::InvalidArg, SerdeZMsgError::UnknownLength))?)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, index: u32, _variant: &'static str, _len: usize) -> Result<Self> {
+        self.write_variant(index)?;
+        Ok(self)
+    }
+}
+
+macro_rules! ser_compound {
+    ($($tr:ident :: $method:ident),*) => {$(
+        impl<'a, 'b> ser::$tr for &'b mut Serializer<'a> {
+            type Ok = ();
+            type Error = Error;
+
+            fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<()> {
+                Ok(())
+            }
+        }
+    )*}
+}
+
+ser_compound!(SerializeSeq::serialize_element, SerializeTuple::serialize_element,
+              SerializeTupleStruct::serialize_field, SerializeTupleVariant::serialize_field);
+
+impl<'a, 'b> ser::SerializeMap for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeStruct for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeStructVariant for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads fields from the frames of a message, in order.
+pub struct Deserializer<'a> {
+    frames: ZMsgFrames<'a>,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(msg: &'a ZMsg) -> Deserializer<'a> {
+        Deserializer { frames: msg.iter() }
+    }
+
+    fn frame(&mut self) -> Result<&'a [u8]> {
+        self.frames.next().ok_or_else(|| Error::new(ErrorKind::MissingFrame, SerdeZMsgError::MissingFrame))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = <[u8; 4]>::try_from(self.frame()?).map_err(|_| invalid_frame())?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        str::from_utf8(self.frame()?).map_err(|e| Error::new(ErrorKind::StringConversion, e))
+    }
+}
+
+fn invalid_frame() -> Error {
+    Error::new(ErrorKind::InvalidArg, SerdeZMsgError::InvalidFrame)
+}
+
+macro_rules! de_be {
+    ($($method:ident => $visit:ident: $t:ty),*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+            let bytes = <[u8; mem::size_of::<$t>()]>::try_from(self.frame()?).map_err(|_| invalid_frame())?;
+            visitor.$visit(<$t>::from_be_bytes(bytes))
+        }
+    )*}
+}
+
+impl<'de, 'b> de::Deserializer<'de> for &'b mut Deserializer<'de> {
+    type Error = Error;
+
+    de_be!(deserialize_i8 => visit_i8: i8, deserialize_i16 => visit_i16: i16,
+           deserialize_i32 => visit_i32: i32, deserialize_i64 => visit_i64: i64,
+           deserialize_u8 => visit_u8: u8, deserialize_u16 => visit_u16: u16,
+           deserialize_u32 => visit_u32: u32, deserialize_u64 => visit_u64: u64);
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::new(ErrorKind::InvalidArg, SerdeZMsgError::NotSelfDescribing))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.frame()? {
+            [0] => visitor.visit_bool(false),
+            [1] => visitor.visit_bool(true),
+            _ => Err(invalid_frame()),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bits = self.u32()?;
+        visitor.visit_f32(f32::from_bits(bits))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bytes = <[u8; 8]>::try_from(self.frame()?).map_err(|_| invalid_frame())?;
+        visitor.visit_f64(f64::from_bits(u64::from_be_bytes(bytes)))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut chars = self.str()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(invalid_frame()),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_str(self.str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_str(self, visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_bytes(self.frame()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_bytes(self, visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.frame()? {
+            [0] => visitor.visit_none(),
+            [1] => visitor.visit_some(self),
+            _ => Err(invalid_frame()),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.u32()? as usize;
+        visitor.visit_seq(Fields { de: self, len: len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len: len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len: len })
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.u32()? as usize;
+        visitor.visit_map(Fields { de: self, len: len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len: fields.len() })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+// Hands out a known number of elements (or map entries) from the
+// underlying deserializer.
+struct Fields<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Fields<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for Fields<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, 'b> de::EnumAccess<'de> for &'b mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index: de::value::U32Deserializer<Error> = self.u32()?.into_deserializer();
+        let value = seed.deserialize(index)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, 'b> de::VariantAccess<'de> for &'b mut Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len: len })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len: fields.len() })
+    }
+}
+
+#[derive(Debug)]
+pub enum SerdeZMsgError {
+    Custom(String),
+    InvalidFrame,
+    MissingFrame,
+    NotSelfDescribing,
+    TooLong,
+    TrailingFrames,
+    UnknownLength,
+}
+
+impl fmt::Display for SerdeZMsgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SerdeZMsgError::Custom(ref e) => write!(f, "{}", e),
+            SerdeZMsgError::InvalidFrame => write!(f, "Frame could not be decoded as the expected type"),
+            SerdeZMsgError::MissingFrame => write!(f, "Message has too few frames"),
+            SerdeZMsgError::NotSelfDescribing => write!(f, "Message format is not self-describing"),
+            SerdeZMsgError::TooLong => write!(f, "Sequence is too long to encode"),
+            SerdeZMsgError::TrailingFrames => write!(f, "Message has too many frames"),
+            SerdeZMsgError::UnknownLength => write!(f, "Sequence length must be known up front"),
+        }
+    }
+}
+
+impl error::Error for SerdeZMsgError {
+    fn description(&self) -> &str {
+        match *self {
+            SerdeZMsgError::Custom(ref e) => e,
+            SerdeZMsgError::InvalidFrame => "Frame could not be decoded as the expected type",
+            SerdeZMsgError::MissingFrame => "Message has too few frames",
+            SerdeZMsgError::NotSelfDescribing => "Message format is not self-describing",
+            SerdeZMsgError::TooLong => "Sequence is too long to encode",
+            SerdeZMsgError::TrailingFrames => "Message has too many frames",
+            SerdeZMsgError::UnknownLength => "Sequence length must be known up front",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZSock, ZSys};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        Ping,
+        Data(Vec<u16>),
+        Moved { x: i32, y: i32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Hello {
+        seq: u32,
+        ready: bool,
+        ratio: f64,
+        name: String,
+        alias: Option<String>,
+        tags: BTreeMap<String, u8>,
+        kind: Kind,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut tags = BTreeMap::new();
+        tags.insert("cow".to_string(), 1);
+
+        for kind in vec![Kind::Ping, Kind::Data(vec![1, 2]), Kind::Moved { x: -1, y: 2 }] {
+            let hello = Hello {
+                seq: 0xdeadbeef,
+                ready: true,
+                ratio: 0.5,
+                name: "moo".to_string(),
+                alias: None,
+                tags: tags.clone(),
+                kind: kind,
+            };
+
+            let msg = to_msg(&hello).unwrap();
+            assert_eq!(msg.iter().next(), Some(&b"\xde\xad\xbe\xef"[..]));
+            assert_eq!(from_msg::<Hello>(&msg).unwrap(), hello);
+        }
+    }
+
+    #[test]
+    fn test_borrowed() {
+        let msg = to_msg(&("moo", 1u8)).unwrap();
+        let (s, n): (&str, u8) = from_msg(&msg).unwrap();
+        assert_eq!(s, "moo");
+        assert_eq!(n, 1);
+    }
+
+    #[test]
+    fn test_frame_count() {
+        let msg = to_msg(&(1u8, 2u8)).unwrap();
+        assert_eq!(from_msg::<(u8, u8, u8)>(&msg).unwrap_err().kind(), ErrorKind::MissingFrame);
+        assert_eq!(from_msg::<u8>(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+        assert_eq!(from_msg::<(u16, u8)>(&msg).unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_send_recv_serde() {
+        ZSys::init();
+
+        let server = ZSock::new_pull("@inproc://serde_zmsg_test").unwrap();
+        let client = ZSock::new_push(">inproc://serde_zmsg_test").unwrap();
+
+        client.send_serde(&Kind::Moved { x: 3, y: 4 }).unwrap();
+        assert_eq!(server.recv_serde::<Kind>().unwrap(), Kind::Moved { x: 3, y: 4 });
+    }
+}
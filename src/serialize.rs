@@ -0,0 +1,322 @@
+//! Module: czmq-serialize
+//!
+//! Serde support, enabled with the `serde` feature.
+//!
+//! A `ZMsg` is serialized as a sequence of frames. Binary formats such
+//! as CBOR get each frame as a byte string; human readable formats
+//! such as JSON get each frame as a base64 string, so that binary
+//! frames survive the round trip.
+//!
+//! A `ZCert` is serialized as a struct of its Z85 encoded public key
+//! and its metadata. The secret key is only included when serializing
+//! `ZCert::with_secret()`, so key material can't leak into config
+//! files or logs by accident. Certificates deserialized without a
+//! secret key get an all-zero one, as CZMQ does for public
+//! certificate files.
+//!
+//! `ZHashX` items are type erased, so a table is serialized as a map
+//! through `ZHashX::as_map::<T>()`, where `T` is the type of every
+//! item in the table, and deserialized with `ZHashX::deserialize_map`.
+
+use crate::{ZCert, ZHashX, ZMsg};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+impl Serialize for ZMsg {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.size()))?;
+        for frame in self {
+            seq.serialize_element(&Frame(frame))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ZMsg {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ZMsg, D::Error> {
+        let frames: Vec<FrameBuf> = Deserialize::deserialize(deserializer)?;
+        ZMsg::from_frames(frames.iter().map(|f| &f.0)).map_err(de::Error::custom)
+    }
+}
+
+struct Frame<'a>(&'a [u8]);
+
+impl<'a> Serialize for Frame<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64_encode(self.0))
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+struct FrameBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for FrameBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FrameBuf, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(FrameVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(FrameVisitor)
+        }
+    }
+}
+
+struct FrameVisitor;
+
+impl<'de> Visitor<'de> for FrameVisitor {
+    type Value = FrameBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a base64 string or byte array")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<FrameBuf, E> {
+        base64_decode(v).map(FrameBuf).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<FrameBuf, E> {
+        Ok(FrameBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<FrameBuf, E> {
+        Ok(FrameBuf(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FrameBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(FrameBuf(bytes))
+    }
+}
+
+impl ZCert {
+    /// Borrow the certificate for serializing, including its secret
+    /// key.
+    pub fn with_secret(&self) -> ZCertWithSecret {
+        ZCertWithSecret(self)
+    }
+}
+
+/// Serializes a `ZCert` including its secret key. See
+/// `ZCert::with_secret()`.
+pub struct ZCertWithSecret<'a>(&'a ZCert);
+
+#[derive(Serialize)]
+struct CertRef<'a> {
+    public_key: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret_key: Option<&'a str>,
+    meta: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct CertBuf {
+    public_key: String,
+    #[serde(default)]
+    secret_key: Option<String>,
+    #[serde(default)]
+    meta: BTreeMap<String, String>,
+}
+
+fn cert_ref(cert: &ZCert, secret: bool) -> CertRef {
+    let meta = cert.meta_keys().iter().filter_map(|k| {
+        cert.meta(k).map(|v| {
+            let v = v.unwrap_or_else(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+            (k.to_string(), v)
+        })
+    }).collect();
+
+    CertRef {
+        public_key: cert.public_txt(),
+        secret_key: if secret { Some(cert.secret_txt()) } else { None },
+        meta: meta,
+    }
+}
+
+impl Serialize for ZCert {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        cert_ref(self, false).serialize(serializer)
+    }
+}
+
+impl<'a> Serialize for ZCertWithSecret<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        cert_ref(self.0, true).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ZCert {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ZCert, D::Error> {
+        let buf = CertBuf::deserialize(deserializer)?;
+
+        let cert = match buf.secret_key {
+            Some(secret) => ZCert::from_txt(&buf.public_key, &secret),
+            None => ZCert::from_public_txt(&buf.public_key),
+        }.map_err(de::Error::custom)?;
+
+        for (k, v) in &buf.meta {
+            cert.set_meta(k, v);
+        }
+
+        Ok(cert)
+    }
+}
+
+impl ZHashX {
+    /// View the table as a serializable map of `T`s. Like `lookup()`,
+    /// this trusts that every item in the table really is a `T`.
+    pub fn as_map<T: Serialize>(&self) -> ZHashXMap<T> {
+        ZHashXMap {
+            hash: self,
+            _item: PhantomData,
+        }
+    }
+
+    /// Deserialize a map of `T`s into a new table.
+    pub fn deserialize_map<'de, T, D>(deserializer: D) -> Result<ZHashX, D::Error>
+        where T: Deserialize<'de> + 'static,
+              D: Deserializer<'de> {
+        let map: BTreeMap<String, T> = Deserialize::deserialize(deserializer)?;
+        let hash = ZHashX::new();
+
+        for (k, v) in map {
+            hash.insert(&k, Box::new(v)).map_err(de::Error::custom)?;
+        }
+
+        Ok(hash)
+    }
+}
+
+/// A `ZHashX` viewed as a map of `T`s. See `ZHashX::as_map()`.
+pub struct ZHashXMap<'a, T> {
+    hash: &'a ZHashX,
+    _item: PhantomData<T>,
+}
+
+impl<'a, T: Serialize> Serialize for ZHashXMap<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.hash.len()))?;
+        for key in self.hash.keys() {
+            if let Some(item) = self.hash.lookup::<T>(key) {
+                map.serialize_entry(key, &*item)?;
+            }
+        }
+        map.end()
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+
+    s
+}
+
+pub(crate) fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if s.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+
+    for (c, chunk) in s.chunks(4).enumerate() {
+        let pad = chunk.iter().rev().take_while(|b| **b == b'=').count();
+        if pad > 2 || (pad > 0 && c != s.len() / 4 - 1) {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for (i, b) in chunk[..4 - pad].iter().enumerate() {
+            let v = BASE64.iter().position(|c| c == b)? as u32;
+            n |= v << (18 - 6 * i);
+        }
+
+        for i in 0..3 - pad {
+            bytes.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZCert, ZHashX, ZMsg};
+
+    #[test]
+    fn test_base64() {
+        for (bytes, s) in &[(&b""[..], ""), (&b"f"[..], "Zg=="), (&b"fo"[..], "Zm8="), (&b"foo"[..], "Zm9v"), (&b"\xff\x00\xfe\x01"[..], "/wD+AQ==")] {
+            assert_eq!(base64_encode(bytes), *s);
+            assert_eq!(base64_decode(s).unwrap(), *bytes);
+        }
+
+        assert!(base64_decode("Zg=").is_none());
+        assert!(base64_decode("Zg==Zm8=").is_none());
+        assert!(base64_decode("Z!==").is_none());
+    }
+
+    #[test]
+    fn test_zmsg_json() {
+        let msg = ZMsg::from_frames(&[&b"moo"[..], b"\x00\xff"]).unwrap();
+
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"["bW9v","AP8="]"#);
+
+        let decoded: ZMsg = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn test_zhashx_json() {
+        let hash = ZHashX::new();
+        hash.insert("moo", Box::new("cow".to_string())).unwrap();
+        hash.insert("baa", Box::new("sheep".to_string())).unwrap();
+
+        let json = serde_json::to_value(hash.as_map::<String>()).unwrap();
+        assert_eq!(json, serde_json::json!({ "moo": "cow", "baa": "sheep" }));
+
+        let hash = ZHashX::deserialize_map::<Vec<u8>, _>(serde_json::json!({ "oink": [1, 2] })).unwrap();
+        assert_eq!(*hash.lookup::<Vec<u8>>("oink").unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_zcert_json() {
+        let cert = ZCert::new().unwrap();
+        cert.set_meta("moo", "cow");
+
+        let json = serde_json::to_string(&cert).unwrap();
+        assert!(json.contains(cert.public_txt()));
+        assert!(!json.contains(cert.secret_txt()));
+
+        let public: ZCert = serde_json::from_str(&json).unwrap();
+        assert_eq!(public.public_txt(), cert.public_txt());
+        assert_eq!(public.secret_key(), &[0; 32][..]);
+        assert_eq!(public.meta("moo").unwrap().unwrap(), "cow");
+
+        let json = serde_json::to_string(&cert.with_secret()).unwrap();
+        let full: ZCert = serde_json::from_str(&json).unwrap();
+        assert_eq!(full, cert);
+    }
+}
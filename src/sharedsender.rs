@@ -0,0 +1,153 @@
+//! Module: czmq-sharedsender
+
+use crate::{RawInterface, Result, ZMsg, ZPoller, ZSock};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+
+static ENDPOINT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// A handle for sending to one socket from many threads. Use
+/// `try_clone()` to make a handle for each producer.
+///
+/// ZMQ sockets aren't thread safe, so the destination socket is owned
+/// by a forwarding thread. Each handle has its own PUSH socket that
+/// feeds the forwarder over inproc, so producers never share a
+/// socket. The forwarder is stopped once the last handle is dropped,
+/// after it has delivered everything already sent.
+pub struct SharedSender {
+    // Declared first so that our PUSH socket is closed before the
+    // forwarder might be stopped.
+    sock: ZSock,
+    forwarder: Arc<Forwarder>,
+}
+
+struct Forwarder {
+    endpoint: String,
+    ctrl: Mutex<ZSock>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        if let Ok(ctrl) = self.ctrl.lock() {
+            let _ = ctrl.signal(0);
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl SharedSender {
+    /// Take ownership of `dest` and start forwarding to it.
+    pub fn new(dest: ZSock) -> Result<SharedSender> {
+        let endpoint = format!("inproc://czmq-shared-sender-{}", ENDPOINT_SEQ.fetch_add(1, Ordering::SeqCst));
+        let pull = ZSock::new_pull(&format!("@{}", endpoint))?;
+        let ctrl_rx = ZSock::new_pair(&format!("@{}-ctrl", endpoint))?;
+        let ctrl_tx = ZSock::new_pair(&format!(">{}-ctrl", endpoint))?;
+        let sock = ZSock::new_push(&format!(">{}", endpoint))?;
+
+        let thread = thread::spawn(move || forward(pull, ctrl_rx, dest));
+
+        Ok(SharedSender {
+            sock: sock,
+            forwarder: Arc::new(Forwarder {
+                endpoint: endpoint,
+                ctrl: Mutex::new(ctrl_tx),
+                thread: Some(thread),
+            }),
+        })
+    }
+
+    /// Create another handle to the same destination. This opens a
+    /// socket for the new handle, so unlike `Clone` it can fail.
+    pub fn try_clone(&self) -> Result<SharedSender> {
+        let sock = ZSock::new_push(&format!(">{}", self.forwarder.endpoint))?;
+
+        Ok(SharedSender {
+            sock: sock,
+            forwarder: self.forwarder.clone(),
+        })
+    }
+
+    pub fn send(&self, msg: ZMsg) -> Result<()> {
+        msg.send(&self.sock)
+    }
+
+    pub fn send_str(&self, data: &str) -> Result<()> {
+        self.sock.send_str(data)
+    }
+}
+
+fn forward(mut pull: ZSock, mut ctrl: ZSock, mut dest: ZSock) {
+    let mut poller = match ZPoller::new() {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+    if poller.add(&mut pull).is_err() || poller.add(&mut ctrl).is_err() {
+        return;
+    }
+
+    let ctrl_ptr = ctrl.as_mut_ptr();
+
+    loop {
+        match poller.wait::<ZSock>(None) {
+            Some(mut sock) => {
+                if sock.as_mut_ptr() == ctrl_ptr {
+                    break;
+                }
+
+                if let Ok(msg) = ZMsg::recv(&mut pull) {
+                    let _ = msg.send(&mut dest);
+                }
+            },
+            None => return,
+        }
+    }
+
+    // Every handle's socket has been closed by now, so whatever is
+    // left is already queued on the inproc pipe.
+    pull.set_rcvtimeo(Some(0));
+    while let Ok(msg) = ZMsg::recv(&mut pull) {
+        let _ = msg.send(&mut dest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZSock, ZSys};
+    use std::thread;
+
+    #[test]
+    fn test_shared_sender() {
+        ZSys::init();
+
+        let server = ZSock::new_pull("@inproc://zsharedsender_test").unwrap();
+        let dest = ZSock::new_push(">inproc://zsharedsender_test").unwrap();
+        let sender = SharedSender::new(dest).unwrap();
+
+        let threads: Vec<_> = (0..4).map(|i| {
+            let sender = sender.try_clone().unwrap();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    sender.send_str(&i.to_string()).unwrap();
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        drop(sender);
+
+        let mut counts = [0; 4];
+        server.set_rcvtimeo(Some(500));
+        while let Ok(Ok(s)) = server.recv_str() {
+            counts[s.parse::<usize>().unwrap()] += 1;
+        }
+        assert_eq!(counts, [10; 4]);
+    }
+}
@@ -0,0 +1,289 @@
+//! Module: czmq-signing
+//!
+//! Message signatures for deployments that run over PLAIN or no
+//! security mechanism at all, where CURVE isn't available but peers
+//! still need to know that a message wasn't altered on the way. A
+//! `Signer` is a `Middleware` that appends a signature frame on send,
+//! and on receive checks and removes it, failing with
+//! `ErrorKind::InvalidArg` if it's missing or doesn't match.
+//!
+//! Two algorithms are supported: HMAC-SHA256 with a shared secret, and
+//! Ed25519, where only the sender holds the secret key and receivers
+//! verify with the public key.
+//!
+//! The signature frame is `SIGNATURE_MAGIC`, then one byte for the
+//! algorithm, a 4 byte big endian count of signed frames and the
+//! signature. It covers that many frames before it, each prefixed with
+//! its length, so a receiving ROUTER's identity frame in front doesn't
+//! get in the way. A sending ROUTER should skip its identity frame with
+//! `Signer::skip()`.
+
+use crate::{Error, ErrorKind, Middleware, Result, ZCert, ZMsg};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer as _};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{error, fmt};
+use std::convert::TryFrom;
+
+/// The start of a signature frame.
+pub const SIGNATURE_MAGIC: &[u8] = b"\xffSIG";
+
+const ALG_HMAC_SHA256: u8 = 1;
+const ALG_ED25519: u8 = 2;
+// Magic, algorithm and frame count
+const HEADER_LEN: usize = 9;
+
+type HmacSha256 = Hmac<Sha256>;
+
+enum Key {
+    Hmac(Vec<u8>),
+    Ed25519(Keypair),
+    Ed25519Public(PublicKey),
+}
+
+/// Signs sent messages and verifies received ones.
+pub struct Signer {
+    key: Key,
+    skip: usize,
+}
+
+impl Signer {
+    /// Sign with HMAC-SHA256 under the shared secret `key`.
+    pub fn hmac(key: &[u8]) -> Signer {
+        Signer::new(Key::Hmac(key.to_vec()))
+    }
+
+    /// Sign with the 32 byte Ed25519 secret key `secret`, and verify
+    /// with its public half.
+    pub fn ed25519(secret: &[u8]) -> Result<Signer> {
+        let secret = SecretKey::from_bytes(secret).map_err(|_| bad_key())?;
+        let public = PublicKey::from(&secret);
+        Ok(Signer::new(Key::Ed25519(Keypair { secret: secret, public: public })))
+    }
+
+    /// Verify with the 32 byte Ed25519 public key `public`. Sending
+    /// fails, as there's no secret key to sign with.
+    pub fn ed25519_verifier(public: &[u8]) -> Result<Signer> {
+        let public = PublicKey::from_bytes(public).map_err(|_| bad_key())?;
+        Ok(Signer::new(Key::Ed25519Public(public)))
+    }
+
+    /// Take the key from `cert`'s metadata item `name`, which holds the
+    /// algorithm and the Z85 encoded key separated by a colon:
+    /// `hmac-sha256:<key>`, `ed25519:<secret key>` or
+    /// `ed25519-public:<public key>`.
+    pub fn from_cert(cert: &ZCert, name: &str) -> Result<Signer> {
+        let value = match cert.meta(name) {
+            Some(Ok(value)) => value,
+            _ => return Err(Error::new(ErrorKind::InvalidArg, SigningError::NoKey(name.into()))),
+        };
+
+        let mut parts = value.splitn(2, ':');
+        let alg = parts.next().unwrap_or("");
+        let key = zmq::z85_decode(parts.next().ok_or_else(bad_key)?).map_err(|_| bad_key())?;
+
+        match alg {
+            "hmac-sha256" => Ok(Signer::hmac(&key)),
+            "ed25519" => Signer::ed25519(&key),
+            "ed25519-public" => Signer::ed25519_verifier(&key),
+            _ => Err(bad_key()),
+        }
+    }
+
+    fn new(key: Key) -> Signer {
+        Signer {
+            key: key,
+            skip: 0,
+        }
+    }
+
+    /// Leave the first `frames` frames of sent messages unsigned, e.g.
+    /// 1 for the identity frame a ROUTER sends to.
+    pub fn skip(mut self, frames: usize) -> Signer {
+        self.skip = frames;
+        self
+    }
+
+    fn algorithm(&self) -> u8 {
+        match self.key {
+            Key::Hmac(_) => ALG_HMAC_SHA256,
+            Key::Ed25519(_) | Key::Ed25519Public(_) => ALG_ED25519,
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.key {
+            Key::Hmac(ref key) => {
+                let mut mac = HmacSha256::new_from_slice(key).map_err(|_| bad_key())?;
+                mac.update(data);
+                Ok(mac.finalize().into_bytes().to_vec())
+            },
+            Key::Ed25519(ref keypair) => Ok(keypair.sign(data).to_bytes().to_vec()),
+            Key::Ed25519Public(_) => Err(Error::new(ErrorKind::Unsupported, SigningError::VerifyOnly)),
+        }
+    }
+
+    fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        match self.key {
+            Key::Hmac(ref key) => match HmacSha256::new_from_slice(key) {
+                Ok(mut mac) => {
+                    mac.update(data);
+                    mac.verify_slice(signature).is_ok()
+                },
+                Err(_) => false,
+            },
+            Key::Ed25519(Keypair { ref public, .. }) | Key::Ed25519Public(ref public) => match Signature::try_from(signature) {
+                Ok(signature) => public.verify_strict(data, &signature).is_ok(),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+impl Middleware for Signer {
+    fn on_send(&mut self, msg: ZMsg) -> Result<Option<ZMsg>> {
+        let frames: Vec<&[u8]> = msg.iter().skip(self.skip).collect();
+        let signature = self.sign(&signed_data(&frames))?;
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + signature.len());
+        frame.extend_from_slice(SIGNATURE_MAGIC);
+        frame.push(self.algorithm());
+        frame.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&signature);
+
+        msg.addbytes(&frame)?;
+        Ok(Some(msg))
+    }
+
+    fn on_recv(&mut self, msg: ZMsg) -> Result<Option<ZMsg>> {
+        let invalid = || Error::new(ErrorKind::InvalidArg, SigningError::Invalid);
+
+        let mut frames: Vec<Vec<u8>> = msg.iter().map(|f| f.to_vec()).collect();
+        let frame = match frames.pop() {
+            Some(ref f) if f.starts_with(SIGNATURE_MAGIC) && f.len() > HEADER_LEN => f.clone(),
+            _ => return Err(Error::new(ErrorKind::InvalidArg, SigningError::Unsigned)),
+        };
+
+        if frame[SIGNATURE_MAGIC.len()] != self.algorithm() {
+            return Err(invalid());
+        }
+        let mut count = [0; 4];
+        count.copy_from_slice(&frame[SIGNATURE_MAGIC.len() + 1..HEADER_LEN]);
+        let count = u32::from_be_bytes(count) as usize;
+        if count > frames.len() {
+            return Err(invalid());
+        }
+
+        let signed: Vec<&[u8]> = frames[frames.len() - count..].iter().map(|f| &f[..]).collect();
+        if !self.verify(&signed_data(&signed), &frame[HEADER_LEN..]) {
+            return Err(invalid());
+        }
+
+        Ok(Some(ZMsg::from_frames(&frames)?))
+    }
+}
+
+// Length prefixing each frame stops frames being merged or split
+// without changing the signature.
+fn signed_data(frames: &[&[u8]]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(frames.iter().map(|f| f.len() + 4).sum());
+    for frame in frames {
+        data.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        data.extend_from_slice(frame);
+    }
+    data
+}
+
+fn bad_key() -> Error {
+    Error::new(ErrorKind::InvalidArg, SigningError::BadKey)
+}
+
+#[derive(Debug)]
+pub enum SigningError {
+    BadKey,
+    Invalid,
+    NoKey(String),
+    Unsigned,
+    VerifyOnly,
+}
+
+impl fmt::Display for SigningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SigningError::BadKey => write!(f, "Signing key is malformed"),
+            SigningError::Invalid => write!(f, "Message signature does not match"),
+            SigningError::NoKey(ref name) => write!(f, "Certificate has no signing key in metadata \"{}\"", name),
+            SigningError::Unsigned => write!(f, "Message is not signed"),
+            SigningError::VerifyOnly => write!(f, "Cannot sign with a public key"),
+        }
+    }
+}
+
+impl error::Error for SigningError {
+    fn description(&self) -> &str {
+        match *self {
+            SigningError::BadKey => "Signing key is malformed",
+            SigningError::Invalid => "Message signature does not match",
+            SigningError::NoKey(_) => "Certificate has no signing key",
+            SigningError::Unsigned => "Message is not signed",
+            SigningError::VerifyOnly => "Cannot sign with a public key",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MiddlewareSock, SocketType, ZSys};
+
+    #[test]
+    fn test_hmac() {
+        ZSys::init();
+
+        let (server, client) = crate::testing::pair(SocketType::ROUTER, SocketType::DEALER).unwrap();
+        let mut client = MiddlewareSock::new(client).with(Signer::hmac(b"moo"));
+        let mut server = MiddlewareSock::new(server).with(Signer::hmac(b"moo").skip(1));
+
+        client.send(ZMsg::from_frames(&["cow", "says"]).unwrap()).unwrap();
+        let mut msg = server.recv().unwrap();
+        assert_eq!(msg.size(), 3);
+        let id = msg.pop().unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "cow");
+
+        // Replies from the ROUTER leave its identity frame unsigned
+        let reply = ZMsg::from_frames(&["moo"]).unwrap();
+        reply.prepend(id).unwrap();
+        server.send(reply).unwrap();
+        assert_eq!(client.recv().unwrap().popstr().unwrap().unwrap(), "moo");
+
+        // Signed under the wrong key
+        let mut wrong = MiddlewareSock::new(client.into_inner()).with(Signer::hmac(b"baa"));
+        wrong.send(ZMsg::from_frames(&["cowsays"]).unwrap()).unwrap();
+        assert_eq!(server.recv().unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        wrong.get_ref().send_str("unsigned").unwrap();
+        assert_eq!(server.recv().unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_ed25519_from_cert() {
+        ZSys::init();
+
+        let secret = [7u8; 32];
+        let public = PublicKey::from(&SecretKey::from_bytes(&secret).unwrap());
+
+        let cert = ZCert::new().unwrap();
+        cert.set_meta("sign", &format!("ed25519:{}", zmq::z85_encode(&secret).unwrap()));
+        cert.set_meta("verify", &format!("ed25519-public:{}", zmq::z85_encode(public.as_bytes()).unwrap()));
+
+        let (server, client) = crate::testing::pair(SocketType::PULL, SocketType::PUSH).unwrap();
+        let mut client = MiddlewareSock::new(client).with(Signer::from_cert(&cert, "sign").unwrap());
+        let mut server = MiddlewareSock::new(server).with(Signer::from_cert(&cert, "verify").unwrap());
+
+        client.send(ZMsg::from_frames(&["moo"]).unwrap()).unwrap();
+        assert_eq!(server.recv().unwrap().popstr().unwrap().unwrap(), "moo");
+
+        assert_eq!(server.send(ZMsg::new()).unwrap_err().kind(), ErrorKind::Unsupported);
+        assert!(Signer::from_cert(&cert, "missing").is_err());
+    }
+}
@@ -1,6 +1,18 @@
 //! Module: trait impl for ZMQ Socket
+//!
+//! rust-zmq sockets can be passed anywhere a `SocketLike` is taken,
+//! such as `ZMsg::send()`, `ZFrame::recv()` and `ZPoller::add()`, and
+//! stay owned by the caller. Going the other way, `with_zmq_socket()`
+//! lends a CZMQ socket to code written against rust-zmq.
+//!
+//! CZMQ creates its sockets in its own context, not in any
+//! `zmq::Context`, so inproc endpoints can't connect sockets from the
+//! two; use ipc or tcp between them. For the same reason a `ZMonitor`
+//! can't watch a rust-zmq socket, as it listens for events over
+//! inproc.
 
-use {RawInterface, Sockish};
+use crate::{RawInterface, Sockish, SocketLike};
+use std::mem::ManuallyDrop;
 use std::os::raw::c_void;
 use zmq::Socket;
 
@@ -19,3 +31,66 @@ impl RawInterface<c_void> for Socket {
 }
 
 impl Sockish for Socket {}
+
+impl SocketLike for Socket {
+    fn as_socket_ptr(&mut self) -> *mut c_void {
+        self.as_mut_ptr()
+    }
+}
+
+/// Lend the libzmq socket underneath `sock` to `f` as a `zmq::Socket`.
+/// The socket still belongs to `sock`, and is not closed when `f`
+/// returns; `f` must not move it out, e.g. with `mem::replace()`.
+pub fn with_zmq_socket<S, F, T>(mut sock: S, f: F) -> T
+    where S: SocketLike,
+          F: FnOnce(&mut Socket) -> T {
+    let raw = unsafe { czmq_sys::zsock_resolve(sock.as_socket_ptr()) };
+    // Never dropped, as dropping a zmq::Socket closes it
+    let mut borrowed = ManuallyDrop::new(unsafe { <Socket as RawInterface<c_void>>::from_raw(raw, false) });
+    f(&mut borrowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZPoller, ZSock, ZSys};
+    use std::time::Duration;
+
+    #[test]
+    fn test_poll_zmq() {
+        let ctx = zmq::Context::new();
+
+        let mut server = ctx.socket(zmq::PULL).unwrap();
+        server.bind("inproc://socket_test_poll").unwrap();
+        let mut client = ctx.socket(zmq::PUSH).unwrap();
+        client.connect("inproc://socket_test_poll").unwrap();
+
+        let mut poller = ZPoller::new().unwrap();
+        poller.add(&mut server).unwrap();
+        assert!(poller.wait_timeout::<Socket>(Some(Duration::from_millis(50))).unwrap().is_none());
+
+        ZMsg::from_frames(&[b"moo"]).unwrap().send(&mut client).unwrap();
+        let mut reader = poller.wait_timeout::<Socket>(Some(Duration::from_secs(1))).unwrap().unwrap();
+        assert_eq!(reader.as_mut_ptr(), server.as_mut_ptr());
+        // The poller only lends the reader
+        reader.into_raw();
+
+        assert_eq!(ZMsg::recv(&mut server).unwrap().popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_with_zmq_socket() {
+        ZSys::init();
+
+        let server = ZSock::new_pull("inproc://socket_test_with").unwrap();
+        let client = ZSock::new_push("inproc://socket_test_with").unwrap();
+
+        with_zmq_socket(&client, |sock| sock.send("moo", 0)).unwrap();
+        let msg = with_zmq_socket(&server, |sock| sock.recv_string(0)).unwrap().unwrap();
+        assert_eq!(msg, "moo");
+
+        // The socket is still open
+        ZMsg::from_frames(&[b"baa"]).unwrap().send(&client).unwrap();
+        assert_eq!(ZMsg::recv(&server).unwrap().popstr().unwrap().unwrap(), "baa");
+    }
+}
@@ -0,0 +1,217 @@
+//! Module: czmq-socketmetrics
+//!
+//! Socket and actor metrics, recorded through the `metrics` crate's
+//! facade so that any exporter, such as `metrics-exporter-prometheus`,
+//! can serve them for scraping. Every metric is labelled `socket` with
+//! the name given to its `SocketMetrics`:
+//!
+//! * `czmq_messages_sent_total` and `czmq_messages_received_total`;
+//! * `czmq_bytes_sent_total` and `czmq_bytes_received_total`;
+//! * `czmq_hwm_drops_total`, messages not sent because the socket was
+//!   at its high-water mark;
+//! * `czmq_connections`, a gauge of open connections;
+//! * `czmq_reconnects_total`;
+//! * `czmq_auth_denials_total`, handshakes refused by authentication.
+//!
+//! `MeteredSock` records traffic as it passes. Connection metrics
+//! come from `ZMonitor` events, which are passed to
+//! `SocketMetrics::record_event()`; actors and proxies can call the
+//! other `record_*` methods directly.
+
+use crate::{DeadLetterReason, DeadLetterSink, Result, ZMonitorEvents, ZMsg, ZSock};
+
+/// Records metrics for one socket, actor or proxy.
+#[derive(Clone, Debug)]
+pub struct SocketMetrics {
+    name: String,
+}
+
+impl SocketMetrics {
+    pub fn new(name: &str) -> SocketMetrics {
+        SocketMetrics {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn record_sent(&self, msg: &ZMsg) {
+        self.record_sent_bytes(msg.content_size());
+    }
+
+    fn record_sent_bytes(&self, bytes: usize) {
+        metrics::counter!("czmq_messages_sent_total", 1, "socket" => self.name.clone());
+        metrics::counter!("czmq_bytes_sent_total", bytes as u64, "socket" => self.name.clone());
+    }
+
+    pub fn record_received(&self, msg: &ZMsg) {
+        metrics::counter!("czmq_messages_received_total", 1, "socket" => self.name.clone());
+        metrics::counter!("czmq_bytes_received_total", msg.content_size() as u64, "socket" => self.name.clone());
+    }
+
+    pub fn record_dropped(&self) {
+        metrics::counter!("czmq_hwm_drops_total", 1, "socket" => self.name.clone());
+    }
+
+    /// Record a monitor event. Events that don't affect any metric are
+    /// ignored.
+    pub fn record_event(&self, event: &ZMonitorEvents) {
+        match *event {
+            ZMonitorEvents::Connected | ZMonitorEvents::Accepted => {
+                metrics::increment_gauge!("czmq_connections", 1.0, "socket" => self.name.clone());
+            },
+            ZMonitorEvents::Disconnected => {
+                metrics::decrement_gauge!("czmq_connections", 1.0, "socket" => self.name.clone());
+            },
+            ZMonitorEvents::ConnectRetried => {
+                metrics::counter!("czmq_reconnects_total", 1, "socket" => self.name.clone());
+            },
+            ZMonitorEvents::HandshakeFailedAuth => {
+                metrics::counter!("czmq_auth_denials_total", 1, "socket" => self.name.clone());
+            },
+            _ => (),
+        }
+    }
+}
+
+/// A socket that records the messages it sends and receives.
+pub struct MeteredSock {
+    sock: ZSock,
+    metrics: SocketMetrics,
+    dead_letters: Option<DeadLetterSink>,
+}
+
+impl MeteredSock {
+    pub fn new(sock: ZSock, name: &str) -> MeteredSock {
+        MeteredSock {
+            sock: sock,
+            metrics: SocketMetrics::new(name),
+            dead_letters: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &ZSock {
+        &self.sock
+    }
+
+    pub fn into_inner(self) -> ZSock {
+        self.sock
+    }
+
+    pub fn metrics(&self) -> &SocketMetrics {
+        &self.metrics
+    }
+
+    /// Send messages that `try_send()` drops to `sink`, with the
+    /// metrics name as the detail.
+    pub fn set_dead_letters(&mut self, sink: DeadLetterSink) {
+        self.dead_letters = Some(sink);
+    }
+
+    /// Send `msg`, blocking while the socket is at its high-water
+    /// mark. Only messages actually sent are recorded.
+    pub fn send(&self, msg: ZMsg) -> Result<()> {
+        // Sending consumes the message, so measure it first
+        let bytes = msg.content_size();
+        msg.send(&self.sock)?;
+        self.metrics.record_sent_bytes(bytes);
+        Ok(())
+    }
+
+    /// Send `msg` if the socket is below its high-water mark, or drop
+    /// it and return false if not.
+    pub fn try_send(&self, msg: ZMsg) -> Result<bool> {
+        if self.sock.writable() {
+            self.send(msg)?;
+            Ok(true)
+        } else {
+            self.metrics.record_dropped();
+            if let Some(ref sink) = self.dead_letters {
+                sink.send(msg, DeadLetterReason::Dropped, self.metrics.name())?;
+            }
+            Ok(false)
+        }
+    }
+
+    pub fn recv(&self) -> Result<ZMsg> {
+        let msg = ZMsg::recv(&self.sock)?;
+        self.metrics.record_received(&msg);
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMonitorEvents, ZMsg, ZSock, ZSys};
+    use metrics::{GaugeValue, Key, Recorder, Unit};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // Counter totals by metric name and `socket` label
+    static COUNTERS: Mutex<Option<HashMap<(String, String), u64>>> = Mutex::new(None);
+
+    struct TestRecorder;
+
+    impl Recorder for TestRecorder {
+        fn register_counter(&self, _: &Key, _: Option<Unit>, _: Option<&'static str>) {}
+        fn register_gauge(&self, _: &Key, _: Option<Unit>, _: Option<&'static str>) {}
+        fn register_histogram(&self, _: &Key, _: Option<Unit>, _: Option<&'static str>) {}
+
+        fn increment_counter(&self, key: &Key, value: u64) {
+            let socket = key.labels().find(|l| l.key() == "socket").map(|l| l.value().to_string()).unwrap_or_default();
+            let mut counters = COUNTERS.lock().unwrap();
+            *counters.get_or_insert_with(HashMap::new).entry((key.name().to_string(), socket)).or_insert(0) += value;
+        }
+
+        fn update_gauge(&self, _: &Key, _: GaugeValue) {}
+        fn record_histogram(&self, _: &Key, _: f64) {}
+    }
+
+    fn counter(name: &str, socket: &str) -> u64 {
+        match *COUNTERS.lock().unwrap() {
+            Some(ref counters) => counters.get(&(name.to_string(), socket.to_string())).cloned().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    #[test]
+    fn test_metered() {
+        ZSys::init();
+
+        // Only one recorder can be installed per process
+        let _ = metrics::set_recorder(&TestRecorder);
+
+        let push = ZSock::new_push("inproc://metrics_test").unwrap();
+        push.set_sndhwm(1);
+        let pull = ZSock::new_pull("inproc://metrics_test").unwrap();
+        pull.set_rcvhwm(1);
+        pull.set_rcvtimeo(Some(1000));
+
+        let sender = MeteredSock::new(push, "sender");
+        let receiver = MeteredSock::new(pull, "receiver");
+        assert_eq!(sender.metrics().name(), "sender");
+
+        assert!(sender.try_send(ZMsg::from_frames(&[b"moo"]).unwrap()).unwrap());
+        assert_eq!(receiver.recv().unwrap().popstr().unwrap().unwrap(), "moo");
+
+        // Fill the pipe so that further sends are dropped
+        let mut sent = 1;
+        while sender.try_send(ZMsg::from_frames(&[b"moo"]).unwrap()).unwrap() {
+            sent += 1;
+        }
+        assert!(!sender.get_ref().writable());
+
+        sender.metrics().record_event(&ZMonitorEvents::HandshakeFailedAuth);
+
+        assert_eq!(counter("czmq_messages_sent_total", "sender"), sent);
+        assert_eq!(counter("czmq_bytes_sent_total", "sender"), sent * 3);
+        assert_eq!(counter("czmq_hwm_drops_total", "sender"), 1);
+        assert_eq!(counter("czmq_auth_denials_total", "sender"), 1);
+        assert_eq!(counter("czmq_messages_received_total", "receiver"), 1);
+        assert_eq!(counter("czmq_bytes_received_total", "receiver"), 3);
+        assert_eq!(counter("czmq_messages_sent_total", "receiver"), 0);
+    }
+}
@@ -0,0 +1,192 @@
+//! Module: czmq-sockopts
+//!
+//! Copying configuration between sockets. `ZSock::options()` reads a
+//! socket's options into a `ZSockOptions`, and `ZSock::apply()` sets
+//! them on another, so a pool of workers can all be configured like
+//! one template socket. As with any option, apply before binding or
+//! connecting; most options don't affect existing connections.
+//!
+//! Options that identify a socket or its peer are left out: the
+//! routing id, subscriptions, ZAP domain and security credentials.
+
+use crate::{Error, ErrorKind, Keepalive, ReconnectPolicy, Result, ZSock};
+use std::{error, fmt};
+
+/// A socket's options, as read by `ZSock::options()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ZSockOptions {
+    pub sndhwm: i32,
+    pub rcvhwm: i32,
+    pub affinity: i32,
+    pub sndbuf: Option<i32>,
+    pub rcvbuf: Option<i32>,
+    pub linger: i32,
+    pub sndtimeo: Option<i32>,
+    pub rcvtimeo: Option<i32>,
+    pub maxmsgsize: Option<i32>,
+    pub backlog: i32,
+    pub immediate: bool,
+    pub ipv6: bool,
+    pub tos: i32,
+    pub reconnect: ReconnectPolicy,
+    pub keepalive: Keepalive,
+    pub tcp_keepalive: Option<i32>,
+    pub tcp_keepalive_idle: Option<i32>,
+    pub tcp_keepalive_cnt: Option<i32>,
+    pub tcp_keepalive_intvl: Option<i32>,
+    pub rate: i32,
+    pub recovery_ivl: i32,
+    pub multicast_hops: i32,
+}
+
+impl ZSock {
+    /// Read this socket's options.
+    pub fn options(&self) -> Result<ZSockOptions> {
+        Ok(ZSockOptions {
+            sndhwm: self.sndhwm()?,
+            rcvhwm: self.rcvhwm()?,
+            affinity: self.affinity()?,
+            sndbuf: self.sndbuf(),
+            rcvbuf: self.rcvbuf(),
+            linger: self.linger()?,
+            sndtimeo: self.sndtimeo(),
+            rcvtimeo: self.rcvtimeo(),
+            maxmsgsize: self.maxmsgsize(),
+            backlog: self.backlog()?,
+            immediate: self.immediate(),
+            ipv6: self.ipv6(),
+            tos: self.tos()?,
+            reconnect: self.reconnect_policy()?,
+            keepalive: self.keepalive()?,
+            tcp_keepalive: self.tcp_keepalive(),
+            tcp_keepalive_idle: self.tcp_keepalive_idle(),
+            tcp_keepalive_cnt: self.tcp_keepalive_cnt(),
+            tcp_keepalive_intvl: self.tcp_keepalive_intvl(),
+            rate: self.rate()?,
+            recovery_ivl: self.recovery_ivl()?,
+            multicast_hops: self.multicast_hops()?,
+        })
+    }
+
+    /// Set every option in `options` on this socket. Fails with
+    /// `ErrorKind::InvalidArg`, naming the first option that didn't
+    /// take, if any reads back differently afterwards.
+    pub fn apply(&self, options: &ZSockOptions) -> Result<()> {
+        self.set_sndhwm(options.sndhwm);
+        self.set_rcvhwm(options.rcvhwm);
+        self.set_affinity(options.affinity);
+        self.set_sndbuf(options.sndbuf);
+        self.set_rcvbuf(options.rcvbuf);
+        self.set_linger(options.linger);
+        self.set_sndtimeo(options.sndtimeo);
+        self.set_rcvtimeo(options.rcvtimeo);
+        self.set_maxmsgsize(options.maxmsgsize);
+        self.set_backlog(options.backlog);
+        self.set_immediate(options.immediate);
+        self.set_ipv6(options.ipv6);
+        self.set_tos(options.tos);
+        self.set_reconnect_policy(&options.reconnect)?;
+        self.set_keepalive(&options.keepalive);
+        self.set_tcp_keepalive(options.tcp_keepalive);
+        self.set_tcp_keepalive_idle(options.tcp_keepalive_idle);
+        self.set_tcp_keepalive_cnt(options.tcp_keepalive_cnt);
+        self.set_tcp_keepalive_intvl(options.tcp_keepalive_intvl);
+        self.set_rate(options.rate);
+        self.set_recovery_ivl(options.recovery_ivl);
+        self.set_multicast_hops(options.multicast_hops);
+
+        // Most setters can't report failure, so check what stuck
+        match first_difference(&self.options()?, options) {
+            Some(name) => Err(Error::new(ErrorKind::InvalidArg, ZSockOptionsError::NotApplied(name))),
+            None => Ok(()),
+        }
+    }
+}
+
+// The name of the first option that differs between `a` and `b`
+fn first_difference(a: &ZSockOptions, b: &ZSockOptions) -> Option<&'static str> {
+    let same = [
+        ("sndhwm", a.sndhwm == b.sndhwm),
+        ("rcvhwm", a.rcvhwm == b.rcvhwm),
+        ("affinity", a.affinity == b.affinity),
+        ("sndbuf", a.sndbuf == b.sndbuf),
+        ("rcvbuf", a.rcvbuf == b.rcvbuf),
+        ("linger", a.linger == b.linger),
+        ("sndtimeo", a.sndtimeo == b.sndtimeo),
+        ("rcvtimeo", a.rcvtimeo == b.rcvtimeo),
+        ("maxmsgsize", a.maxmsgsize == b.maxmsgsize),
+        ("backlog", a.backlog == b.backlog),
+        ("immediate", a.immediate == b.immediate),
+        ("ipv6", a.ipv6 == b.ipv6),
+        ("tos", a.tos == b.tos),
+        ("reconnect", a.reconnect == b.reconnect),
+        ("keepalive", a.keepalive == b.keepalive),
+        ("tcp_keepalive", a.tcp_keepalive == b.tcp_keepalive),
+        ("tcp_keepalive_idle", a.tcp_keepalive_idle == b.tcp_keepalive_idle),
+        ("tcp_keepalive_cnt", a.tcp_keepalive_cnt == b.tcp_keepalive_cnt),
+        ("tcp_keepalive_intvl", a.tcp_keepalive_intvl == b.tcp_keepalive_intvl),
+        ("rate", a.rate == b.rate),
+        ("recovery_ivl", a.recovery_ivl == b.recovery_ivl),
+        ("multicast_hops", a.multicast_hops == b.multicast_hops),
+    ];
+
+    same.iter().find(|&&(_, same)| !same).map(|&(name, _)| name)
+}
+
+#[derive(Debug)]
+pub enum ZSockOptionsError {
+    NotApplied(&'static str),
+}
+
+impl fmt::Display for ZSockOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZSockOptionsError::NotApplied(name) => write!(f, "Socket option {} was not applied", name),
+        }
+    }
+}
+
+impl error::Error for ZSockOptionsError {
+    fn description(&self) -> &str {
+        match *self {
+            ZSockOptionsError::NotApplied(_) => "Socket option was not applied",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZSys};
+    use std::time::Duration;
+
+    #[test]
+    fn test_options() {
+        ZSys::init();
+
+        let template = ZSock::new(SocketType::DEALER);
+        template.set_sndhwm(10);
+        template.set_linger(0);
+        template.set_rcvtimeo(Some(250));
+        template.set_maxmsgsize(Some(1 << 20));
+        template.set_immediate(true);
+        template.set_reconnect_policy(&ReconnectPolicy::new(Duration::from_millis(50)).backoff(Duration::from_secs(1))).unwrap();
+        template.set_keepalive(&Keepalive::new(Duration::from_secs(1)));
+        template.set_tcp_keepalive(Some(1));
+
+        let options = template.options().unwrap();
+        let worker = ZSock::new(SocketType::DEALER);
+        assert!(worker.options().unwrap() != options);
+
+        worker.apply(&options).unwrap();
+        assert_eq!(worker.options().unwrap(), options);
+        assert_eq!(worker.sndhwm().unwrap(), 10);
+        assert_eq!(worker.rcvtimeo(), Some(250));
+
+        assert_eq!(first_difference(&worker.options().unwrap(), &options), None);
+        let mut changed = options.clone();
+        changed.sndbuf = None;
+        changed.tos = 4;
+        assert_eq!(first_difference(&changed, &options), Some("sndbuf"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Module: czmq-tap
+//!
+//! Intercept traffic for live debugging, as in the Espresso pattern.
+//! A `Tap` sits between publishers and subscribers as an XSUB/XPUB
+//! proxy, forwarding messages downstream and subscriptions upstream,
+//! and hands a copy of each one to the caller. `Tap::router_dealer()`
+//! does the same between clients and workers, as a ROUTER/DEALER
+//! broker.
+//!
+//! Each record's offset from when the tap started can be passed to
+//! `CaptureWriter::write_at()` to save the traffic for replay, or
+//! `record()` will do it for you.
+
+use crate::{CaptureWriter, Result, ZMsg, ZPoller, ZSock};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TapDirection {
+    /// A message from a publisher to subscribers, or a request from a
+    /// client to workers.
+    Downstream,
+    /// A subscription or unsubscription from a subscriber to
+    /// publishers, or a reply from a worker to a client.
+    Upstream,
+}
+
+/// A message seen by a `Tap`.
+#[derive(Debug)]
+pub struct TapRecord {
+    pub direction: TapDirection,
+    /// Time since the tap started.
+    pub offset: Duration,
+    pub msg: ZMsg,
+}
+
+/// Proxies pub-sub traffic, exposing a copy of every message.
+pub struct Tap {
+    frontend: ZSock,
+    backend: ZSock,
+    poller: ZPoller,
+    start: Instant,
+}
+
+impl Tap {
+    /// Connect an XSUB socket to the publisher at `upstream`, and bind
+    /// an XPUB socket for subscribers to `endpoint`.
+    pub fn new(upstream: &str, endpoint: &str) -> Result<Tap> {
+        Tap::with_sockets(ZSock::new_xsub(upstream)?, ZSock::new_xpub(endpoint)?)
+    }
+
+    /// Bind a ROUTER socket for clients to `frontend`, and a DEALER
+    /// socket for workers to `backend`. Requests keep the client's
+    /// routing id as their first frame.
+    pub fn router_dealer(frontend: &str, backend: &str) -> Result<Tap> {
+        Tap::with_sockets(ZSock::new_router(frontend)?, ZSock::new_dealer(&format!("@{}", backend))?)
+    }
+
+    fn with_sockets(frontend: ZSock, backend: ZSock) -> Result<Tap> {
+        let mut poller = ZPoller::new()?;
+        poller.add(&frontend)?;
+        poller.add(&backend)?;
+
+        Ok(Tap {
+            frontend: frontend,
+            backend: backend,
+            poller: poller,
+            start: Instant::now(),
+        })
+    }
+
+    /// Wait up to `timeout` for a message in either direction, forward
+    /// it and return a copy.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<TapRecord>> {
+        if self.poller.wait_timeout::<ZSock>(Some(timeout))?.is_none() {
+            return Ok(None);
+        }
+
+        let (direction, source, dest) = if self.frontend.readable() {
+            (TapDirection::Downstream, &self.frontend, &self.backend)
+        } else if self.backend.readable() {
+            (TapDirection::Upstream, &self.backend, &self.frontend)
+        } else {
+            return Ok(None);
+        };
+
+        let msg = ZMsg::recv(source)?;
+        msg.dup()?.send(dest)?;
+
+        Ok(Some(TapRecord {
+            direction: direction,
+            offset: self.start.elapsed(),
+            msg: msg,
+        }))
+    }
+
+    /// Forward traffic for `duration`, writing messages going in
+    /// `direction` to `writer` at their offset from when the tap
+    /// started. Returns the number of messages written.
+    pub fn record<W: Write>(&mut self, writer: &mut CaptureWriter<W>, direction: TapDirection, duration: Duration) -> Result<usize> {
+        let deadline = Instant::now() + duration;
+        let mut written = 0;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_millis(0) {
+                break;
+            }
+
+            if let Some(record) = self.poll(remaining)? {
+                if record.direction == direction {
+                    writer.write_at(record.offset, &record.msg)?;
+                    written += 1;
+                }
+            }
+        }
+
+        writer.flush()?;
+        Ok(written)
+    }
+}
+
+/// Forwards traffic forever, yielding each message as it passes.
+impl Iterator for Tap {
+    type Item = Result<TapRecord>;
+
+    fn next(&mut self) -> Option<Result<TapRecord>> {
+        loop {
+            match self.poll(Duration::from_secs(1)) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureReader, ZMsg, ZSock, ZSys};
+    use std::io::Cursor;
+    use std::thread;
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_tap() {
+        ZSys::init();
+
+        let publisher = ZSock::new_pub("inproc://tap_test_pub").unwrap();
+        let mut tap = Tap::new("inproc://tap_test_pub", "inproc://tap_test").unwrap();
+        let subscriber = ZSock::new_sub("inproc://tap_test", Some("moo")).unwrap();
+        subscriber.set_rcvtimeo(Some(1000));
+
+        let mut record = tap.next().unwrap().unwrap();
+        assert_eq!(record.direction, TapDirection::Upstream);
+        assert_eq!(record.msg.popbytes().unwrap().unwrap(), b"\x01moo");
+
+        // Give the subscription time to reach the publisher
+        assert!(tap.poll(TICK).unwrap().is_none());
+
+        ZMsg::from_frames(&[b"moo"]).unwrap().send(&publisher).unwrap();
+        let mut record = tap.next().unwrap().unwrap();
+        assert_eq!(record.direction, TapDirection::Downstream);
+        assert_eq!(record.msg.popstr().unwrap().unwrap(), "moo");
+
+        let mut msg = ZMsg::recv(&subscriber).unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_record_router_dealer() {
+        ZSys::init();
+
+        let mut tap = Tap::router_dealer("inproc://tap_test_clients", "inproc://tap_test_workers").unwrap();
+        let client = ZSock::new_req("inproc://tap_test_clients").unwrap();
+        let worker = ZSock::new_rep(">inproc://tap_test_workers").unwrap();
+        worker.set_rcvtimeo(Some(1000));
+        client.set_rcvtimeo(Some(1000));
+
+        let handle = thread::spawn(move || {
+            let request = worker.recv_str().unwrap().unwrap();
+            worker.send_str(&format!("{} baa", request)).unwrap();
+        });
+
+        client.send_str("moo").unwrap();
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        assert_eq!(tap.record(&mut writer, TapDirection::Upstream, TICK * 4).unwrap(), 1);
+        assert_eq!(client.recv_str().unwrap().unwrap(), "moo baa");
+        handle.join().unwrap();
+
+        let mut reader = CaptureReader::new(Cursor::new(writer.into_inner())).unwrap();
+        let record = reader.read().unwrap().unwrap();
+        assert_eq!(record.msg.iter().last(), Some(&b"moo baa"[..]));
+        assert!(reader.read().unwrap().is_none());
+    }
+}
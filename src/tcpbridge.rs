@@ -0,0 +1,210 @@
+//! Module: czmq-tcpbridge
+//!
+//! Front a ZMQ service with plain TCP clients. A `TcpBridge` accepts
+//! connections on a `std::net::TcpListener` and gives each one its
+//! own DEALER socket connected to the backend, so a ROUTER backend
+//! sees every TCP client as a separate peer.
+//!
+//! Bytes are forwarded as they arrive, one frame per read, so the
+//! backend must do its own framing of the byte stream. Each frame the
+//! backend sends back is written to the client. As with a STREAM
+//! socket, an empty frame marks a closed connection: the backend
+//! receives one when the client disconnects, and can send one to
+//! close the client's connection.
+
+use crate::{Result, ZFrame, ZMsg, ZPoller, ZSock};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use zmq::SocketType;
+
+// How often the bridge's threads check whether it has been stopped
+const BRIDGE_TICK: Duration = Duration::from_millis(100);
+
+static BRIDGE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Forwards TCP connections to a ZMQ backend.
+pub struct TcpBridge {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TcpBridge {
+    /// Accept connections on `listener` and bridge each one to the
+    /// ROUTER (or DEALER) socket at `backend`.
+    pub fn new(listener: TcpListener, backend: &str) -> Result<TcpBridge> {
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let backend = backend.to_string();
+
+        let handle = thread::spawn(move || {
+            let mut connections = Vec::new();
+
+            while !stopped.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        // Forget connections that have already closed
+                        connections.retain(|c: &JoinHandle<()>| !c.is_finished());
+
+                        let backend = backend.clone();
+                        let stopped = stopped.clone();
+                        connections.push(thread::spawn(move || {
+                            // A connection that fails is simply dropped
+                            let _ = serve(stream, &backend, &stopped);
+                        }));
+                    },
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(BRIDGE_TICK),
+                    Err(_) => break,
+                }
+            }
+
+            for connection in connections {
+                let _ = connection.join();
+            }
+        });
+
+        Ok(TcpBridge {
+            addr: addr,
+            stop: stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The address the bridge is accepting connections on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Close every connection and stop accepting new ones.
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for TcpBridge {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Forward one connection until either side closes it or the bridge
+// stops. Reads block, so they happen on a second thread that hands
+// each chunk over inproc.
+fn serve(stream: TcpStream, backend: &str, stopped: &AtomicBool) -> Result<()> {
+    // Some platforms pass the listener's non-blocking mode on
+    stream.set_nonblocking(false)?;
+
+    let dealer = ZSock::new(SocketType::DEALER);
+    dealer.set_linger(0);
+    dealer.connect(backend)?;
+
+    let endpoint = format!("inproc://czmq-tcpbridge-{}", BRIDGE_ID.fetch_add(1, Ordering::Relaxed));
+    let chunks = ZSock::new_pull(&format!("@{}", endpoint))?;
+    let push = ZSock::new_push(&format!(">{}", endpoint))?;
+
+    let mut reader = stream.try_clone()?;
+    let reading = thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            // An empty chunk tells the other thread the client is gone
+            let n = reader.read(&mut buf).unwrap_or(0);
+            let sent = ZFrame::new(&buf[..n]).and_then(|f| f.send(&push, None));
+            if sent.is_err() || n == 0 {
+                break;
+            }
+        }
+    });
+
+    let mut poller = ZPoller::new()?;
+    poller.add(&chunks)?;
+    poller.add(&dealer)?;
+
+    let mut writer = stream;
+    let result = forward(&mut poller, &chunks, &dealer, &mut writer, stopped);
+
+    let _ = writer.shutdown(Shutdown::Both);
+    let _ = reading.join();
+    result
+}
+
+fn forward(poller: &mut ZPoller, chunks: &ZSock, dealer: &ZSock, writer: &mut TcpStream, stopped: &AtomicBool) -> Result<()> {
+    while !stopped.load(Ordering::SeqCst) {
+        if poller.wait_timeout::<ZSock>(Some(BRIDGE_TICK))?.is_none() {
+            continue;
+        }
+
+        while chunks.readable() {
+            let chunk = ZMsg::recv(chunks)?;
+            let closed = chunk.content_size() == 0;
+            chunk.send(dealer)?;
+            if closed {
+                return Ok(());
+            }
+        }
+
+        while dealer.readable() {
+            let msg = ZMsg::recv(dealer)?;
+            for frame in msg.iter() {
+                if frame.is_empty() {
+                    return Ok(());
+                }
+                writer.write_all(frame)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMsg, ZSock, ZSys};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    #[test]
+    fn test_bridge() {
+        ZSys::init();
+
+        let router = ZSock::new_router("inproc://tcpbridge_test").unwrap();
+        router.set_rcvtimeo(Some(2000));
+
+        let bridge = TcpBridge::new(TcpListener::bind("127.0.0.1:0").unwrap(), "inproc://tcpbridge_test").unwrap();
+        let mut client = TcpStream::connect(bridge.local_addr()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        client.write_all(b"moo").unwrap();
+        let mut msg = ZMsg::recv(&router).unwrap();
+        let peer = msg.popbytes().unwrap().unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+
+        let reply = ZMsg::new();
+        reply.addbytes(&peer).unwrap();
+        reply.addstr("baa").unwrap();
+        reply.send(&router).unwrap();
+
+        let mut buf = [0; 3];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"baa");
+
+        // The backend hears about the client leaving
+        drop(client);
+        let mut msg = ZMsg::recv(&router).unwrap();
+        assert_eq!(msg.popbytes().unwrap().unwrap(), peer);
+        assert_eq!(msg.popbytes().unwrap().unwrap(), b"");
+
+        bridge.stop();
+    }
+}
@@ -0,0 +1,822 @@
+//! Module: czmq-testing
+//!
+//! Helpers for testing code built on this crate, enabled by the
+//! `testing` feature:
+//!
+//! * `pair()` builds a connected pair of sockets of any type over a
+//!   unique inproc endpoint;
+//! * `FakePeer` plays a scripted conversation of expected and sent
+//!   messages against a socket, so one side of a protocol can be
+//!   tested without writing the other. `expect()` builds patterns
+//!   for it frame by frame, for messages that are only partly known;
+//! * `expect_event()` waits for a `ZMonitor` event with a timeout,
+//!   instead of sleeping and hoping the connection is up;
+//! * `MockClock` stands in for the system clock in heartbeating and
+//!   reconnection logic, so timeouts can be tested without sleeping;
+//! * `LeakCheck` reports sockets and actors that outlive a test, with
+//!   a backtrace of where each was created;
+//! * `MockZap` answers ZAP requests with scripted decisions and
+//!   records what it was asked, for testing security configuration
+//!   without certificate directories or password files.
+//!
+//! ```rust
+//! use czmq::{SocketType, ZSys};
+//! use czmq::testing::{pair, FakePeer};
+//!
+//! ZSys::init();
+//!
+//! let (server, client) = pair(SocketType::REP, SocketType::REQ).unwrap();
+//! let peer = FakePeer::new()
+//!     .expect(&[b"PING"])
+//!     .send(&[b"PONG"])
+//!     .spawn(server);
+//!
+//! client.send_str("PING").unwrap();
+//! assert_eq!(client.recv_str().unwrap().unwrap(), "PONG");
+//! peer.join().unwrap();
+//! ```
+//!
+//! With a ROUTER peer, a protocol client can be tested hermetically:
+//!
+//! ```rust
+//! use czmq::{SocketType, ZMsg, ZSys};
+//! use czmq::testing::{expect, pair, FakePeer};
+//!
+//! ZSys::init();
+//!
+//! let (server, client) = pair(SocketType::ROUTER, SocketType::DEALER).unwrap();
+//! let peer = FakePeer::new()
+//!     .expect_msg(expect().frame_str("HELLO").frame_any().then_reply(&[b"WELCOME"]))
+//!     .spawn(server);
+//!
+//! ZMsg::from_frames(&[&b"HELLO"[..], b"moo"]).unwrap().send(&client).unwrap();
+//! assert_eq!(client.recv_str().unwrap().unwrap(), "WELCOME");
+//! peer.join().unwrap();
+//! ```
+
+use crate::{Clock, Error, ErrorKind, Result, ZMonitor, ZMonitorEvents, ZMsg, ZPoller, ZSock};
+use crate::leaks;
+use crate::zauth::ZapClaim;
+use crate::zframe::DebugBytes;
+use std::{error, fmt, result, thread};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use zmq::SocketType;
+
+pub use crate::leaks::Leak;
+
+/// How long a `FakePeer` waits for each expected message by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The endpoint that libzmq sends ZAP requests to.
+pub const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+const ZAP_VERSION: &str = "1.0";
+// How often a MockZap checks whether it has been stopped
+const ZAP_TICK: Duration = Duration::from_millis(100);
+
+static ENDPOINT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Build a pair of connected sockets, passed back as `(bound,
+/// connected)`. Both have a linger of 0 and a receive timeout of
+/// `DEFAULT_TIMEOUT`, so a test that goes wrong fails rather than
+/// hanging.
+pub fn pair(bound: SocketType, connected: SocketType) -> Result<(ZSock, ZSock)> {
+    let endpoint = format!("inproc://czmq-testing-{}", ENDPOINT_SEQ.fetch_add(1, Ordering::SeqCst));
+
+    let server = ZSock::new(bound);
+    server.set_linger(0);
+    server.set_rcvtimeo_duration(Some(DEFAULT_TIMEOUT));
+    server.bind(&endpoint)?;
+
+    let client = ZSock::new(connected);
+    client.set_linger(0);
+    client.set_rcvtimeo_duration(Some(DEFAULT_TIMEOUT));
+    client.connect(&endpoint)?;
+
+    Ok((server, client))
+}
+
+enum Step {
+    Expect(Expect),
+    Send(Vec<Vec<u8>>),
+}
+
+#[derive(Clone, Debug)]
+enum FrameMatch {
+    Exact(Vec<u8>),
+    Any,
+    Rest,
+}
+
+/// Start a pattern for an expected message.
+pub fn expect() -> Expect {
+    Expect {
+        frames: Vec::new(),
+        reply: None,
+    }
+}
+
+/// A pattern for an expected message, matched frame by frame, with an
+/// optional reply. Build one with `expect()` and pass it to
+/// `FakePeer::expect_msg()`.
+#[derive(Clone, Debug)]
+pub struct Expect {
+    frames: Vec<FrameMatch>,
+    reply: Option<Vec<Vec<u8>>>,
+}
+
+impl Expect {
+    /// Match a frame holding exactly `bytes`.
+    pub fn frame(mut self, bytes: &[u8]) -> Expect {
+        self.frames.push(FrameMatch::Exact(bytes.to_vec()));
+        self
+    }
+
+    pub fn frame_str(self, s: &str) -> Expect {
+        self.frame(s.as_bytes())
+    }
+
+    /// Match a single frame, whatever it holds.
+    pub fn frame_any(mut self) -> Expect {
+        self.frames.push(FrameMatch::Any);
+        self
+    }
+
+    /// Match any remaining frames, including none. Nothing after this
+    /// is checked.
+    pub fn frames_rest(mut self) -> Expect {
+        self.frames.push(FrameMatch::Rest);
+        self
+    }
+
+    /// Send this message once the pattern has matched.
+    pub fn then_reply(mut self, frames: &[&[u8]]) -> Expect {
+        self.reply = Some(frames.iter().map(|f| f.to_vec()).collect());
+        self
+    }
+
+    fn matches(&self, frames: &[Vec<u8>]) -> bool {
+        let mut frames = frames.iter();
+
+        for m in &self.frames {
+            match *m {
+                FrameMatch::Exact(ref bytes) => if frames.next() != Some(bytes) {
+                    return false;
+                },
+                FrameMatch::Any => if frames.next().is_none() {
+                    return false;
+                },
+                FrameMatch::Rest => return true,
+            }
+        }
+
+        frames.next().is_none()
+    }
+}
+
+impl fmt::Display for Expect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frames: Vec<String> = self.frames.iter().map(|m| match *m {
+            FrameMatch::Exact(ref bytes) => format!("{:?}", DebugBytes(bytes)),
+            FrameMatch::Any => "*".to_string(),
+            FrameMatch::Rest => "..".to_string(),
+        }).collect();
+        write!(f, "[{}]", frames.join(", "))
+    }
+}
+
+/// A scripted peer. Each step either waits for a message and checks
+/// its frames, or sends one.
+///
+/// On a ROUTER socket the routing id is handled for you: it is left
+/// out of the frames compared by `expect()`, and `send()` replies to
+/// whichever peer sent the last message.
+pub struct FakePeer {
+    steps: Vec<Step>,
+    timeout: Duration,
+}
+
+impl FakePeer {
+    pub fn new() -> FakePeer {
+        FakePeer {
+            steps: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// How long to wait for each expected message.
+    pub fn timeout(mut self, timeout: Duration) -> FakePeer {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Wait for a message with exactly these frames.
+    pub fn expect(self, frames: &[&[u8]]) -> FakePeer {
+        self.expect_msg(frames.iter().fold(expect(), |e, f| e.frame(f)))
+    }
+
+    /// Wait for a message, whatever it holds.
+    pub fn expect_any(self) -> FakePeer {
+        self.expect_msg(expect().frames_rest())
+    }
+
+    /// Wait for a message matching `pattern`, then send its reply, if
+    /// it has one.
+    pub fn expect_msg(mut self, mut pattern: Expect) -> FakePeer {
+        let reply = pattern.reply.take();
+        self.steps.push(Step::Expect(pattern));
+        if let Some(reply) = reply {
+            self.steps.push(Step::Send(reply));
+        }
+        self
+    }
+
+    pub fn send(mut self, frames: &[&[u8]]) -> FakePeer {
+        self.steps.push(Step::Send(frames.iter().map(|f| f.to_vec()).collect()));
+        self
+    }
+
+    /// Play the script against `sock`, failing at the first message
+    /// that doesn't match or doesn't arrive in time.
+    pub fn run(&self, sock: &ZSock) -> Result<()> {
+        let router = sock.zsock_type() == SocketType::ROUTER;
+        let mut poller = ZPoller::new()?;
+        poller.add(sock)?;
+        let mut routing_id: Option<Vec<u8>> = None;
+
+        for (n, step) in self.steps.iter().enumerate() {
+            match *step {
+                Step::Send(ref frames) => {
+                    let msg = ZMsg::new();
+                    if let Some(ref id) = routing_id {
+                        msg.addbytes(id)?;
+                    }
+                    for frame in frames {
+                        msg.addbytes(frame)?;
+                    }
+                    msg.send(sock)?;
+                },
+                Step::Expect(ref pattern) => {
+                    if poller.wait_timeout::<ZSock>(Some(self.timeout))?.is_none() {
+                        return Err(Error::new(ErrorKind::Timeout, TestingError::Timeout(n)));
+                    }
+
+                    let mut msg = ZMsg::recv(sock)?;
+                    if router {
+                        routing_id = msg.popbytes()?;
+                    }
+
+                    let got: Vec<Vec<u8>> = msg.iter().map(|f| f.to_vec()).collect();
+                    if !pattern.matches(&got) {
+                        return Err(Error::new(ErrorKind::InvalidArg, TestingError::Unexpected(n, pattern.to_string(), frames_str(&got))));
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Play the script on a thread of its own, which owns `sock`.
+    pub fn spawn(self, sock: ZSock) -> FakePeerHandle {
+        // Errors aren't Send, so they cross the thread as their parts
+        FakePeerHandle(thread::spawn(move || {
+            self.run(&sock).map_err(|e| (e.kind(), e.to_string()))
+        }))
+    }
+}
+
+/// A `FakePeer` running on its own thread.
+pub struct FakePeerHandle(thread::JoinHandle<result::Result<(), (ErrorKind, String)>>);
+
+impl FakePeerHandle {
+    /// Wait for the script to finish, returning the error it failed
+    /// with, if any.
+    pub fn join(self) -> Result<()> {
+        match self.0.join() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err((kind, e))) => Err(Error::new(kind, TestingError::Failed(e))),
+            Err(_) => Err(Error::new(ErrorKind::Terminated, TestingError::Panicked)),
+        }
+    }
+}
+
+/// Wait up to `timeout` for `monitor` to report `event`, skipping any
+/// other events before it.
+pub fn expect_event(monitor: &mut ZMonitor, event: ZMonitorEvents, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut poller = ZPoller::new()?;
+    poller.add(monitor.actor())?;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_millis(0) || poller.wait_timeout::<ZSock>(Some(remaining))?.is_none() {
+            return Err(Error::new(ErrorKind::Timeout, TestingError::NoEvent(event.to_str())));
+        }
+
+        if monitor.get_attr()? == Ok(event.clone()) {
+            return Ok(());
+        }
+    }
+}
+
+/// A clock that only moves when advanced. Clones share the same
+/// time, so keep one to advance after handing another to
+/// `set_clock()`. Sleeping on it advances it instantly.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::from_millis(0))),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// How far the clock has been advanced.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Reports sockets and actors that outlive a test, which otherwise
+/// tend to show up only as hangs at exit. While a check is running,
+/// everything the crate creates is recorded with a backtrace of
+/// where it was created. `finish()` fails if any of it is still alive,
+/// and removes any ipc files left behind by sockets that are gone.
+///
+/// Tracking is process wide, so a check can see sockets from other
+/// tests running at the same time. Run leak checked tests with
+/// `--test-threads=1`, or in a test binary of their own, as the
+/// crate's own `tests/leak_check.rs` does.
+///
+/// ```rust
+/// use czmq::{ZSock, ZSys};
+/// use czmq::testing::LeakCheck;
+///
+/// ZSys::init();
+///
+/// let check = LeakCheck::start();
+/// let sock = ZSock::new_pull("@inproc://leakcheck_example").unwrap();
+/// assert_eq!(check.leaks().len(), 1);
+/// drop(sock);
+/// check.finish().unwrap();
+/// ```
+pub struct LeakCheck {
+    seq: u64,
+}
+
+impl LeakCheck {
+    pub fn start() -> LeakCheck {
+        LeakCheck { seq: leaks::start() }
+    }
+
+    /// Sockets and actors created since the check started that are
+    /// still alive, oldest first.
+    pub fn leaks(&self) -> Vec<Leak> {
+        leaks::live_since(self.seq)
+    }
+
+    /// Stop checking, failing with a report of every leak.
+    pub fn finish(self) -> Result<()> {
+        leaks::remove_stale_ipc();
+        let leaked = self.leaks();
+
+        if leaked.is_empty() {
+            Ok(())
+        } else {
+            let report: Vec<String> = leaked.iter().map(|l| l.to_string()).collect();
+            Err(Error::new(ErrorKind::InvalidArg, TestingError::Leaked(leaked.len(), report.join("\n"))))
+        }
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        leaks::stop();
+    }
+}
+
+/// A ZAP request, as sent by libzmq when a peer connects to a socket
+/// with a ZAP domain or a security mechanism.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ZapRequest {
+    pub request_id: Vec<u8>,
+    pub domain: String,
+    /// The peer's IP address.
+    pub address: String,
+    pub routing_id: Vec<u8>,
+    /// `NULL`, `PLAIN`, `CURVE` or `GSSAPI`.
+    pub mechanism: String,
+    pub credentials: Vec<Vec<u8>>,
+}
+
+impl ZapRequest {
+    pub fn from_msg(msg: &ZMsg) -> Result<ZapRequest> {
+        let frames: Vec<&[u8]> = msg.iter().collect();
+        if frames.len() < 6 || frames[0] != ZAP_VERSION.as_bytes() {
+            return Err(Error::new(ErrorKind::InvalidArg, TestingError::BadZapRequest));
+        }
+
+        let string = |f: &[u8]| String::from_utf8_lossy(f).into_owned();
+        Ok(ZapRequest {
+            request_id: frames[1].to_vec(),
+            domain: string(frames[2]),
+            address: string(frames[3]),
+            routing_id: frames[4].to_vec(),
+            mechanism: string(frames[5]),
+            credentials: frames[6..].iter().map(|f| f.to_vec()).collect(),
+        })
+    }
+
+    pub fn to_msg(&self) -> Result<ZMsg> {
+        let msg = ZMsg::new();
+        msg.addstr(ZAP_VERSION)?;
+        msg.addbytes(&self.request_id)?;
+        msg.addstr(&self.domain)?;
+        msg.addstr(&self.address)?;
+        msg.addbytes(&self.routing_id)?;
+        msg.addstr(&self.mechanism)?;
+        for credential in &self.credentials {
+            msg.addbytes(credential)?;
+        }
+        Ok(msg)
+    }
+
+    /// The username and password of a PLAIN request.
+    pub fn plain(&self) -> Option<(&[u8], &[u8])> {
+        match (self.mechanism.as_str(), self.credentials.len()) {
+            ("PLAIN", 2) => Some((&self.credentials[0], &self.credentials[1])),
+            _ => None,
+        }
+    }
+
+    /// The client's public key, in binary, of a CURVE request.
+    pub fn curve_key(&self) -> Option<&[u8]> {
+        match (self.mechanism.as_str(), self.credentials.len()) {
+            ("CURVE", 1) => Some(&self.credentials[0]),
+            _ => None,
+        }
+    }
+}
+
+/// How a `MockZap` answers a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ZapReply {
+    /// Let the peer in, as this user id.
+    Allow(String),
+    Deny,
+}
+
+impl ZapReply {
+    /// Build the reply to the request with `request_id`.
+    pub fn to_msg(&self, request_id: &[u8]) -> Result<ZMsg> {
+        let (code, text, user_id) = match *self {
+            ZapReply::Allow(ref user_id) => ("200", "OK", user_id.as_str()),
+            ZapReply::Deny => ("400", "Denied", ""),
+        };
+
+        let msg = ZMsg::new();
+        msg.addstr(ZAP_VERSION)?;
+        msg.addbytes(request_id)?;
+        msg.addstr(code)?;
+        msg.addstr(text)?;
+        msg.addstr(user_id)?;
+        // No metadata
+        msg.addbytes(&[])?;
+        Ok(msg)
+    }
+}
+
+struct ZapState {
+    script: VecDeque<ZapReply>,
+    handler: Box<dyn Fn(&ZapRequest) -> ZapReply + Send>,
+    requests: Vec<ZapRequest>,
+}
+
+/// A ZAP handler for tests, standing in for `ZAuth`. Each request is
+/// answered with the next scripted reply, or once the script runs
+/// out, by the handler, which allows everyone by default.
+///
+/// Only one ZAP handler can run in a process at a time, so `start()`
+/// waits for any other `MockZap` or `ZAuth` to be dropped first.
+pub struct MockZap {
+    state: Arc<Mutex<ZapState>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+    _claim: ZapClaim,
+}
+
+impl MockZap {
+    pub fn start() -> Result<MockZap> {
+        let claim = ZapClaim::acquire();
+        let sock = ZSock::new(SocketType::REP);
+        sock.set_linger(0);
+        sock.bind(ZAP_ENDPOINT)?;
+        let mut poller = ZPoller::new()?;
+        poller.add(&sock)?;
+
+        let state = Arc::new(Mutex::new(ZapState {
+            script: VecDeque::new(),
+            handler: Box::new(|_| ZapReply::Allow(String::new())),
+            requests: Vec::new(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (shared, stopped) = (state.clone(), stop.clone());
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                if serve_zap(&poller, &sock, &shared).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(MockZap {
+            state: state,
+            stop: stop,
+            handle: Some(handle),
+            _claim: claim,
+        })
+    }
+
+    /// Answer the next requests with `replies`, in order.
+    pub fn script(&self, replies: &[ZapReply]) {
+        self.state.lock().unwrap().script.extend(replies.iter().cloned());
+    }
+
+    /// Answer unscripted requests with `handler`.
+    pub fn set_handler<F: Fn(&ZapRequest) -> ZapReply + Send + 'static>(&self, handler: F) {
+        self.state.lock().unwrap().handler = Box::new(handler);
+    }
+
+    /// The requests answered so far.
+    pub fn requests(&self) -> Vec<ZapRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Wait up to `timeout` until at least `count` requests have been
+    /// answered, and return them.
+    pub fn wait_requests(&self, count: usize, timeout: Duration) -> Result<Vec<ZapRequest>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let requests = self.requests();
+            if requests.len() >= count {
+                return Ok(requests);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::Timeout, TestingError::NoZapRequest(count)));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for MockZap {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve_zap(poller: &ZPoller, sock: &ZSock, state: &Mutex<ZapState>) -> Result<()> {
+    if poller.wait_timeout::<ZSock>(Some(ZAP_TICK))?.is_none() {
+        return Ok(());
+    }
+
+    let msg = ZMsg::recv(sock)?;
+    let request = match ZapRequest::from_msg(&msg) {
+        Ok(request) => request,
+        // REP must still answer, so refuse whatever this was
+        Err(_) => return ZapReply::Deny.to_msg(&[])?.send(sock),
+    };
+
+    let reply = {
+        let mut state = state.lock().unwrap();
+        let reply = match state.script.pop_front() {
+            Some(reply) => reply,
+            None => (state.handler)(&request),
+        };
+        state.requests.push(request.clone());
+        reply
+    };
+
+    reply.to_msg(&request.request_id)?.send(sock)
+}
+
+fn frames_str(frames: &[Vec<u8>]) -> String {
+    let frames: Vec<String> = frames.iter().map(|f| format!("{:?}", DebugBytes(f))).collect();
+    format!("[{}]", frames.join(", "))
+}
+
+#[derive(Debug)]
+pub enum TestingError {
+    Failed(String),
+    BadZapRequest,
+    Leaked(usize, String),
+    NoEvent(&'static str),
+    NoZapRequest(usize),
+    Panicked,
+    Timeout(usize),
+    Unexpected(usize, String, String),
+}
+
+impl fmt::Display for TestingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TestingError::Failed(ref e) => write!(f, "Fake peer failed: {}", e),
+            TestingError::BadZapRequest => write!(f, "Not a ZAP request"),
+            TestingError::Leaked(n, ref report) => write!(f, "{} sockets or actors leaked:\n{}", n, report),
+            TestingError::NoEvent(event) => write!(f, "Timed out waiting for monitor event {}", event),
+            TestingError::NoZapRequest(n) => write!(f, "Timed out waiting for {} ZAP requests", n),
+            TestingError::Panicked => write!(f, "Fake peer panicked"),
+            TestingError::Timeout(step) => write!(f, "Timed out waiting for a message at step {}", step),
+            TestingError::Unexpected(step, ref expected, ref got) => write!(f, "At step {}, expected {} but got {}", step, expected, got),
+        }
+    }
+}
+
+impl error::Error for TestingError {
+    fn description(&self) -> &str {
+        match *self {
+            TestingError::Failed(_) => "Fake peer failed",
+            TestingError::BadZapRequest => "Not a ZAP request",
+            TestingError::Leaked(..) => "Sockets or actors leaked",
+            TestingError::NoEvent(_) => "Timed out waiting for monitor event",
+            TestingError::NoZapRequest(_) => "Timed out waiting for ZAP requests",
+            TestingError::Panicked => "Fake peer panicked",
+            TestingError::Timeout(_) => "Timed out waiting for a message",
+            TestingError::Unexpected(..) => "Unexpected message",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ZMonitorEvents, ZSock, ZSys};
+    use std::time::Duration;
+    use zmq::SocketType;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_pair() {
+        ZSys::init();
+
+        let (pull, push) = pair(SocketType::PULL, SocketType::PUSH).unwrap();
+        push.send_str("moo").unwrap();
+        assert_eq!(pull.recv_str().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_fake_router() {
+        ZSys::init();
+
+        let (router, dealer) = pair(SocketType::ROUTER, SocketType::DEALER).unwrap();
+        let peer = FakePeer::new()
+            .expect(&[b"HELLO", b"moo"])
+            .send(&[b"WELCOME"])
+            .expect_any()
+            .spawn(router);
+
+        ZMsg::from_frames(&[&b"HELLO"[..], b"moo"]).unwrap().send(&dealer).unwrap();
+        assert_eq!(dealer.recv_str().unwrap().unwrap(), "WELCOME");
+        dealer.send_str("BYE").unwrap();
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_fake_mismatch() {
+        ZSys::init();
+
+        let (pull, push) = pair(SocketType::PULL, SocketType::PUSH).unwrap();
+        push.send_str("baa").unwrap();
+
+        let e = FakePeer::new().expect(&[b"moo"]).run(&pull).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+
+        let e = FakePeer::new().timeout(TICK).expect_any().run(&pull).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+
+    #[test]
+    fn test_expect_pattern() {
+        let pattern = expect().frame_str("HELLO").frame_any();
+        assert!(pattern.matches(&[b"HELLO".to_vec(), b"moo".to_vec()]));
+        assert!(!pattern.matches(&[b"HELLO".to_vec()]));
+        assert!(!pattern.matches(&[b"HELLO".to_vec(), b"moo".to_vec(), b"baa".to_vec()]));
+        assert_eq!(pattern.to_string(), "[\"HELLO\", *]");
+
+        let pattern = expect().frame(b"").frames_rest();
+        assert!(pattern.matches(&[Vec::new()]));
+        assert!(pattern.matches(&[Vec::new(), b"moo".to_vec()]));
+        assert!(!pattern.matches(&[]));
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+
+        shared.advance(TICK);
+        assert_eq!(clock.now() - start, TICK);
+        clock.sleep(TICK);
+        assert_eq!(shared.elapsed(), TICK * 2);
+    }
+
+    #[test]
+    fn test_zap_fixtures() {
+        let request = ZapRequest {
+            request_id: b"1".to_vec(),
+            domain: "moo".into(),
+            address: "127.0.0.1".into(),
+            routing_id: Vec::new(),
+            mechanism: "PLAIN".into(),
+            credentials: vec![b"moo".to_vec(), b"cow".to_vec()],
+        };
+        let decoded = ZapRequest::from_msg(&request.to_msg().unwrap()).unwrap();
+        assert_eq!(decoded, request);
+        assert_eq!(decoded.plain(), Some((&b"moo"[..], &b"cow"[..])));
+        assert!(decoded.curve_key().is_none());
+
+        let reply = ZapReply::Allow("moo".into()).to_msg(b"1").unwrap();
+        let frames: Vec<&[u8]> = reply.iter().collect();
+        assert_eq!(frames, [&b"1.0"[..], b"1", b"200", b"OK", b"moo", b""]);
+
+        assert!(ZapRequest::from_msg(&ZMsg::from_frames(&[b"2.0"]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_mock_zap() {
+        ZSys::init();
+
+        let zap = MockZap::start().unwrap();
+        zap.set_handler(|_| ZapReply::Deny);
+        zap.script(&[ZapReply::Deny, ZapReply::Allow("baa".into())]);
+
+        let server = ZSock::new(SocketType::PULL);
+        server.set_zap_domain("testing");
+        server.set_plain_server(true);
+        server.set_rcvtimeo(Some(2000));
+        let port = server.bind("tcp://127.0.0.1:*").unwrap();
+
+        let client = |username: &str| {
+            let client = ZSock::new(SocketType::PUSH);
+            client.set_linger(0);
+            // Keep a denied client from coming back for another go
+            client.set_reconnect_ivl(60000);
+            client.set_plain_username(username);
+            client.set_plain_password("cow");
+            client.connect(&format!("tcp://127.0.0.1:{}", port)).unwrap();
+            client.send_str(username).unwrap();
+            client
+        };
+
+        let _denied = client("moo");
+        zap.wait_requests(1, Duration::from_secs(1)).unwrap();
+        let _allowed = client("baa");
+        assert_eq!(server.recv_str().unwrap().unwrap(), "baa");
+
+        let requests = zap.requests();
+        assert_eq!(requests[0].domain, "testing");
+        assert_eq!(requests[0].plain(), Some((&b"moo"[..], &b"cow"[..])));
+        assert_eq!(requests[1].plain(), Some((&b"baa"[..], &b"cow"[..])));
+    }
+
+    #[test]
+    fn test_expect_event() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::PULL);
+        let mut monitor = ZMonitor::builder(&server).events(&[ZMonitorEvents::Listening]).start().unwrap();
+        server.bind("tcp://127.0.0.1:*").unwrap();
+
+        expect_event(&mut monitor, ZMonitorEvents::Listening, Duration::from_secs(1)).unwrap();
+        let e = expect_event(&mut monitor, ZMonitorEvents::Accepted, TICK).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+}
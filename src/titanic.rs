@@ -0,0 +1,421 @@
+//! Module: czmq-titanic
+//!
+//! The Titanic pattern: disconnected, durable request-reply on top of
+//! Majordomo. Titanic registers three services with an MDP broker:
+//!
+//! * `titanic.request` takes a service name and a request, journals
+//!   them to disk and replies `200` with a ticket;
+//! * `titanic.reply` takes a ticket and replies `200` with the
+//!   service's reply, `300` if it isn't ready yet, or `400` if the
+//!   ticket is unknown;
+//! * `titanic.close` takes a ticket and deletes its request and
+//!   reply.
+//!
+//! Meanwhile Titanic passes each journaled request on to its service
+//! once the broker reports that the service is available, and
+//! journals the reply. Requests still waiting when Titanic stops are
+//! picked up again when it restarts on the same directory.
+//!
+//! Each request and reply is a file in the journal directory, named
+//! after its ticket and holding the message as `ZMsg::encode()`
+//! produces it.
+
+use crate::{Error, ErrorKind, MdpClient, MdpWorker, Result, ZMsg};
+use crate::zuuid::ZUuid;
+use std::collections::VecDeque;
+use std::{error, fmt, fs, io};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const REQUEST_SERVICE: &str = "titanic.request";
+const REPLY_SERVICE: &str = "titanic.reply";
+const CLOSE_SERVICE: &str = "titanic.close";
+
+const OK: &str = "200";
+const PENDING: &str = "300";
+const UNKNOWN: &str = "400";
+
+/// Journals requests to disk and serves them to their services.
+pub struct Titanic {
+    dir: PathBuf,
+    request: MdpWorker,
+    reply: MdpWorker,
+    close: MdpWorker,
+    client: MdpClient,
+    pending: VecDeque<String>,
+}
+
+impl Titanic {
+    /// Register Titanic's services with `broker`, journaling to `dir`.
+    /// The directory is created if it doesn't exist, and any requests
+    /// in it that haven't been answered are queued for dispatch.
+    pub fn new<P: AsRef<Path>>(broker: &str, dir: P) -> Result<Titanic> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut pending = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == "req") && !path.with_extension("rep").exists() {
+                if let Some(ticket) = path.file_stem().and_then(|s| s.to_str()) {
+                    pending.push(ticket.to_string());
+                }
+            }
+        }
+        pending.sort();
+
+        let mut client = MdpClient::new(broker)?;
+        // Requests that time out are retried on the next poll anyway
+        client.set_retries(0);
+
+        Ok(Titanic {
+            dir: dir,
+            request: MdpWorker::new(broker, REQUEST_SERVICE)?,
+            reply: MdpWorker::new(broker, REPLY_SERVICE)?,
+            close: MdpWorker::new(broker, CLOSE_SERVICE)?,
+            client: client,
+            pending: pending.into_iter().collect(),
+        })
+    }
+
+    /// How often Titanic's workers heartbeat the broker. Defaults to
+    /// 2.5 seconds, and should match the broker's setting.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.request.set_heartbeat(heartbeat);
+        self.reply.set_heartbeat(heartbeat);
+        self.close.set_heartbeat(heartbeat);
+    }
+
+    /// How long to wait for a service to answer a journaled request
+    /// before trying again on the next poll. Defaults to 2.5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.client.set_timeout(timeout);
+    }
+
+    /// The number of journaled requests that haven't been answered.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Serve requests until interrupted.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            self.poll(Duration::from_secs(1))?;
+        }
+    }
+
+    /// Wait up to `timeout` for requests to each of Titanic's
+    /// services and answer them, then try to dispatch each pending
+    /// request once. The timeout is shared between the services.
+    pub fn poll(&mut self, timeout: Duration) -> Result<()> {
+        let slice = timeout / 3;
+
+        if let Some(req) = self.request.recv(Some(slice))? {
+            let reply = self.store(req.body)?;
+            self.request.reply(&req.client, reply)?;
+        }
+
+        if let Some(req) = self.reply.recv(Some(slice))? {
+            let reply = self.fetch(req.body)?;
+            self.reply.reply(&req.client, reply)?;
+        }
+
+        if let Some(req) = self.close.recv(Some(slice))? {
+            let reply = self.remove(req.body)?;
+            self.close.reply(&req.client, reply)?;
+        }
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            let ticket = self.pending[i].clone();
+            if self.dispatch(&ticket)? {
+                self.pending.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store(&mut self, body: ZMsg) -> Result<ZMsg> {
+        let ticket = new_ticket()?;
+        fs::write(self.path(&ticket, "req"), body.encode()?.as_bytes())?;
+        self.pending.push_back(ticket.clone());
+
+        let reply = ZMsg::new();
+        reply.addstr(OK)?;
+        reply.addstr(&ticket)?;
+        Ok(reply)
+    }
+
+    fn fetch(&self, mut body: ZMsg) -> Result<ZMsg> {
+        let reply = match pop_ticket(&mut body) {
+            Some(ref ticket) if self.path(ticket, "rep").exists() => {
+                let reply = ZMsg::decode_bytes(&fs::read(self.path(ticket, "rep"))?)?;
+                reply.pushstr(OK)?;
+                reply
+            },
+            Some(ref ticket) if self.path(ticket, "req").exists() => ZMsg::from_frames(&[PENDING])?,
+            _ => ZMsg::from_frames(&[UNKNOWN])?,
+        };
+        Ok(reply)
+    }
+
+    fn remove(&mut self, mut body: ZMsg) -> Result<ZMsg> {
+        if let Some(ticket) = pop_ticket(&mut body) {
+            remove_file(&self.path(&ticket, "req"))?;
+            remove_file(&self.path(&ticket, "rep"))?;
+            self.pending.retain(|t| *t != ticket);
+        }
+
+        ZMsg::from_frames(&[OK])
+    }
+
+    // Returns true once the request has been answered or closed.
+    fn dispatch(&mut self, ticket: &str) -> Result<bool> {
+        let path = self.path(ticket, "req");
+        if !path.exists() {
+            return Ok(true);
+        }
+
+        let mut request = ZMsg::decode_bytes(&fs::read(&path)?)?;
+        let service = match request.popstr() {
+            Some(Ok(service)) => service,
+            _ => return Ok(true),
+        };
+
+        let available = match self.client.request("mmi.service", ZMsg::from_frames(&[&service])?) {
+            Ok(mut reply) => reply.popstr().and_then(|s| s.ok()).map_or(false, |code| code == OK),
+            Err(ref e) if e.kind() == ErrorKind::Timeout => false,
+            Err(e) => return Err(e),
+        };
+
+        if !available {
+            return Ok(false);
+        }
+
+        match self.client.request(&service, request) {
+            Ok(reply) => {
+                fs::write(self.path(ticket, "rep"), reply.encode()?.as_bytes())?;
+                Ok(true)
+            },
+            Err(ref e) if e.kind() == ErrorKind::Timeout => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn path(&self, ticket: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", ticket, extension))
+    }
+}
+
+/// Makes requests through Titanic and collects their replies.
+pub struct TitanicClient {
+    client: MdpClient,
+}
+
+impl TitanicClient {
+    pub fn new(broker: &str) -> Result<TitanicClient> {
+        Ok(TitanicClient {
+            client: MdpClient::new(broker)?,
+        })
+    }
+
+    /// How long to wait for Titanic to answer. Defaults to 2.5
+    /// seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.client.set_timeout(timeout);
+    }
+
+    /// Journal a request to `service`, returning a ticket for its
+    /// reply.
+    pub fn request(&mut self, service: &str, body: ZMsg) -> Result<String> {
+        body.pushstr(service)?;
+        let mut reply = self.client.request(REQUEST_SERVICE, body)?;
+
+        match expect_status(&mut reply)?.as_str() {
+            OK => pop_ticket(&mut reply).ok_or_else(|| Error::new(ErrorKind::MissingFrame, TitanicError::InvalidReply)),
+            _ => Err(Error::new(ErrorKind::InvalidArg, TitanicError::InvalidReply)),
+        }
+    }
+
+    /// Fetch the reply for `ticket`, or `None` if the service hasn't
+    /// answered yet.
+    pub fn reply(&mut self, ticket: &str) -> Result<Option<ZMsg>> {
+        let mut reply = self.client.request(REPLY_SERVICE, ZMsg::from_frames(&[ticket])?)?;
+
+        match expect_status(&mut reply)?.as_str() {
+            OK => Ok(Some(reply)),
+            PENDING => Ok(None),
+            UNKNOWN => Err(Error::new(ErrorKind::InvalidArg, TitanicError::UnknownTicket(ticket.to_string()))),
+            _ => Err(Error::new(ErrorKind::InvalidArg, TitanicError::InvalidReply)),
+        }
+    }
+
+    /// Delete the request and reply for `ticket`.
+    pub fn close(&mut self, ticket: &str) -> Result<()> {
+        let mut reply = self.client.request(CLOSE_SERVICE, ZMsg::from_frames(&[ticket])?)?;
+
+        match expect_status(&mut reply)?.as_str() {
+            OK => Ok(()),
+            _ => Err(Error::new(ErrorKind::InvalidArg, TitanicError::InvalidReply)),
+        }
+    }
+}
+
+fn new_ticket() -> Result<String> {
+    match ZUuid::new() {
+        Some(uuid) => Ok(uuid.string),
+        None => Err(Error::new(ErrorKind::NullPtr, TitanicError::CmdFailed)),
+    }
+}
+
+// Tickets become file names, so anything but a hex UUID is rejected
+fn pop_ticket(msg: &mut ZMsg) -> Option<String> {
+    match msg.popstr() {
+        Some(Ok(ticket)) if !ticket.is_empty() && ticket.chars().all(|c| c.is_ascii_hexdigit()) => Some(ticket),
+        _ => None,
+    }
+}
+
+fn expect_status(msg: &mut ZMsg) -> Result<String> {
+    match msg.popstr() {
+        Some(Ok(status)) => Ok(status),
+        _ => Err(Error::new(ErrorKind::MissingFrame, TitanicError::InvalidReply)),
+    }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => Ok(r?),
+    }
+}
+
+#[derive(Debug)]
+pub enum TitanicError {
+    CmdFailed,
+    InvalidReply,
+    UnknownTicket(String),
+}
+
+impl fmt::Display for TitanicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TitanicError::CmdFailed => write!(f, "CZMQ command failed"),
+            TitanicError::InvalidReply => write!(f, "Titanic sent an invalid reply"),
+            TitanicError::UnknownTicket(ref ticket) => write!(f, "Unknown ticket: {}", ticket),
+        }
+    }
+}
+
+impl error::Error for TitanicError {
+    fn description(&self) -> &str {
+        match *self {
+            TitanicError::CmdFailed => "CZMQ command failed",
+            TitanicError::InvalidReply => "Titanic sent an invalid reply",
+            TitanicError::UnknownTicket(_) => "Unknown ticket",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, MdpBroker, MdpWorker, ZMsg, ZSys};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn spawn_broker(endpoint: &'static str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        let mut broker = MdpBroker::new(endpoint).unwrap();
+        broker.set_heartbeat(TICK);
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                broker.poll(TICK).unwrap();
+            }
+        })
+    }
+
+    fn spawn_titanic(endpoint: &'static str, dir: PathBuf, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut titanic = Titanic::new(endpoint, dir).unwrap();
+            titanic.set_heartbeat(TICK);
+            titanic.set_timeout(Duration::from_secs(1));
+
+            while !stop.load(Ordering::SeqCst) {
+                titanic.poll(TICK).unwrap();
+            }
+        })
+    }
+
+    fn spawn_echo(endpoint: &'static str, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut worker = MdpWorker::new(endpoint, "echo").unwrap();
+            worker.set_heartbeat(TICK);
+
+            while !stop.load(Ordering::SeqCst) {
+                if let Some(req) = worker.recv(Some(TICK)).unwrap() {
+                    worker.reply(&req.client, req.body).unwrap();
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_titanic() {
+        ZSys::init();
+
+        let dir = TempDir::new("titanic").unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let broker = spawn_broker("@inproc://titanic_test", stop.clone());
+        let titanic = spawn_titanic("inproc://titanic_test", dir.path().to_path_buf(), stop.clone());
+
+        let mut client = TitanicClient::new("inproc://titanic_test").unwrap();
+        client.set_timeout(Duration::from_secs(1));
+
+        // The echo service isn't running yet, so the request waits
+        let ticket = client.request("echo", ZMsg::from_frames(&[b"moo"]).unwrap()).unwrap();
+        assert!(client.reply(&ticket).unwrap().is_none());
+
+        let echo = spawn_echo("inproc://titanic_test", stop.clone());
+
+        let mut reply = None;
+        for _ in 0..40 {
+            reply = client.reply(&ticket).unwrap();
+            if reply.is_some() {
+                break;
+            }
+            thread::sleep(TICK);
+        }
+        assert_eq!(reply.unwrap().popstr().unwrap().unwrap(), "moo");
+
+        client.close(&ticket).unwrap();
+        assert_eq!(client.reply(&ticket).unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        stop.store(true, Ordering::SeqCst);
+        echo.join().unwrap();
+        titanic.join().unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn test_recover() {
+        ZSys::init();
+
+        let dir = TempDir::new("titanic").unwrap();
+        let request = ZMsg::from_frames(&[&b"echo"[..], b"moo"]).unwrap();
+        fs::write(dir.path().join("0A.req"), request.encode().unwrap().as_bytes()).unwrap();
+        fs::write(dir.path().join("0B.req"), request.encode().unwrap().as_bytes()).unwrap();
+        fs::write(dir.path().join("0B.rep"), request.encode().unwrap().as_bytes()).unwrap();
+
+        let titanic = Titanic::new("inproc://titanic_test_recover", dir.path()).unwrap();
+        assert_eq!(titanic.pending(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+//! Module: czmq-trace
+//!
+//! Spans for the `tracing` feature. Sends, receives and actor
+//! commands each enter a span at `DEBUG` level, named `czmq.send`,
+//! `czmq.recv` or `czmq.command`, with these fields where known:
+//!
+//! * `endpoint`, the socket's endpoint;
+//! * `frames` and `bytes`, the size of the message;
+//! * `command`, the actor command.
+//!
+//! Without the feature, a `Span` is empty and all of this compiles to
+//! nothing.
+
+use crate::ZMsg;
+use std::os::raw::c_void;
+#[cfg(feature = "tracing")]
+use std::ffi::CStr;
+
+/// An entered span, which is exited when dropped.
+pub struct Span(#[cfg(feature = "tracing")] tracing::span::EnteredSpan);
+
+impl Span {
+    /// Record the size of a message that was received.
+    #[cfg(feature = "tracing")]
+    pub fn record_msg(&self, msg: &ZMsg) {
+        self.0.record("frames", &msg.size());
+        self.0.record("bytes", &msg.content_size());
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn record_msg(&self, _: &ZMsg) {
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub fn send(sock: *mut c_void, msg: &ZMsg) -> Span {
+    Span(tracing::debug_span!("czmq.send",
+                              endpoint = endpoint(sock).as_ref().map(|e| e.as_str()),
+                              frames = msg.size(),
+                              bytes = msg.content_size()).entered())
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn send(_: *mut c_void, _: &ZMsg) -> Span {
+    Span()
+}
+
+#[cfg(feature = "tracing")]
+pub fn recv(sock: *mut c_void) -> Span {
+    Span(tracing::debug_span!("czmq.recv",
+                              endpoint = endpoint(sock).as_ref().map(|e| e.as_str()),
+                              frames = tracing::field::Empty,
+                              bytes = tracing::field::Empty).entered())
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn recv(_: *mut c_void) -> Span {
+    Span()
+}
+
+/// A span covering an actor command and its reply.
+#[cfg(feature = "tracing")]
+pub fn command(command: &str) -> Span {
+    Span(tracing::debug_span!("czmq.command", command = command).entered())
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn command(_: &str) -> Span {
+    Span()
+}
+
+// Only CZMQ sockets know their endpoint; actors and rust-zmq sockets
+// don't
+#[cfg(feature = "tracing")]
+fn endpoint(sock: *mut c_void) -> Option<String> {
+    if unsafe { czmq_sys::zsock_is(sock) } == 1 {
+        let endpoint = unsafe { czmq_sys::zsock_endpoint(sock as *mut czmq_sys::zsock_t) };
+        if !endpoint.is_null() {
+            return Some(unsafe { CStr::from_ptr(endpoint) }.to_string_lossy().into_owned());
+        }
+    }
+
+    None
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{ZMsg, ZSock, ZSys};
+
+    #[test]
+    fn test_spans() {
+        ZSys::init();
+
+        let subscriber = tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG).with_test_writer().finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let pull = ZSock::new_pull("inproc://trace_test").unwrap();
+            let push = ZSock::new_push("inproc://trace_test").unwrap();
+
+            ZMsg::from_frames(&[b"moo"]).unwrap().send(&push).unwrap();
+            assert_eq!(ZMsg::recv(&pull).unwrap().popstr().unwrap().unwrap(), "moo");
+        });
+    }
+}
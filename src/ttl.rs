@@ -0,0 +1,140 @@
+//! Module: czmq-ttl
+//!
+//! Deadlines that travel with messages. A sender stamps a message
+//! with an expiry frame, and anything that handles it later (a
+//! broker, a queue, the eventual worker) can check it and drop the
+//! message once it's too late to be useful, rather than working
+//! through a backlog of stale requests after an outage. `PpQueue`
+//! drops expired requests instead of handing them to workers.
+//!
+//! The expiry frame is `TTL_MAGIC` followed by the deadline in
+//! milliseconds since the Unix epoch, as a big-endian u64. It's
+//! pushed onto the front of the message, but found wherever it is,
+//! so routing envelopes added in front of it don't matter. Deadlines
+//! are wall-clock times, so hosts' clocks need to be in sync.
+
+use crate::{RawInterface, Result, SocketLike, ZFrame, ZMsg};
+use std::cmp;
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The start of an expiry frame.
+pub const TTL_MAGIC: &[u8] = b"\xffTTL";
+
+const TTL_FRAME_SIZE: usize = 12;
+
+impl ZMsg {
+    /// Stamp the message to expire `ttl` from now.
+    pub fn set_ttl(&mut self, ttl: Duration) -> Result<()> {
+        self.set_deadline(SystemTime::now() + ttl)
+    }
+
+    /// Stamp the message to expire at `deadline`, replacing any
+    /// deadline it already has.
+    pub fn set_deadline(&mut self, deadline: SystemTime) -> Result<()> {
+        self.take_deadline();
+
+        let millis = deadline.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let mut frame = TTL_MAGIC.to_vec();
+        frame.extend_from_slice(&(cmp::min(millis, u64::max_value() as u128) as u64).to_be_bytes());
+        self.pushbytes(&frame)
+    }
+
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.iter().filter_map(decode).next()
+    }
+
+    /// Whether the message's deadline has passed. Messages without a
+    /// deadline never expire.
+    pub fn is_expired(&self) -> bool {
+        self.deadline().map_or(false, |d| d <= SystemTime::now())
+    }
+
+    /// How long until the message expires, or `None` if it has no
+    /// deadline. Zero once it has expired.
+    pub fn time_left(&self) -> Option<Duration> {
+        self.deadline().map(|d| d.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    /// Remove the expiry frame, returning its deadline, e.g. before
+    /// handing the message's body to application code.
+    pub fn take_deadline(&mut self) -> Option<SystemTime> {
+        let mut frame = self.first();
+
+        while let Some(f) = frame {
+            if let Some(deadline) = decode(f.as_bytes()) {
+                // Owned, so the frame is destroyed once removed
+                let mut owned = unsafe { ZFrame::from_raw(f.into_raw(), true) };
+                self.remove(&mut owned);
+                return Some(deadline);
+            }
+            frame = self.next();
+        }
+
+        None
+    }
+
+    /// Receive the next message that hasn't expired, discarding any
+    /// that have. The expiry frame is left in place, so a broker can
+    /// pass the deadline on.
+    pub fn recv_live<S: SocketLike>(mut source: S) -> Result<ZMsg> {
+        loop {
+            let msg = ZMsg::recv(&mut source)?;
+            if !msg.is_expired() {
+                return Ok(msg);
+            }
+        }
+    }
+}
+
+fn decode(frame: &[u8]) -> Option<SystemTime> {
+    if frame.len() != TTL_FRAME_SIZE || !frame.starts_with(TTL_MAGIC) {
+        return None;
+    }
+
+    let millis = u64::from_be_bytes(frame[TTL_MAGIC.len()..].try_into().ok()?);
+    UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZSys};
+
+    #[test]
+    fn test_deadline() {
+        let mut msg = ZMsg::from_frames(&["moo"]).unwrap();
+        assert!(msg.deadline().is_none());
+        assert!(!msg.is_expired());
+
+        msg.set_ttl(Duration::from_secs(60)).unwrap();
+        msg.pushstr("envelope").unwrap();
+        assert!(msg.time_left().unwrap() > Duration::from_secs(59));
+        assert!(!msg.is_expired());
+
+        msg.set_deadline(UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        assert_eq!(msg.size(), 3);
+        assert!(msg.is_expired());
+        assert_eq!(msg.take_deadline(), Some(UNIX_EPOCH + Duration::from_secs(1)));
+        assert_eq!(msg.size(), 2);
+        assert_eq!(msg.popstr().unwrap().unwrap(), "envelope");
+    }
+
+    #[test]
+    fn test_recv_live() {
+        ZSys::init();
+
+        let (server, client) = crate::testing::pair(SocketType::PULL, SocketType::PUSH).unwrap();
+
+        let mut stale = ZMsg::from_frames(&["stale"]).unwrap();
+        stale.set_deadline(SystemTime::now() - Duration::from_secs(1)).unwrap();
+        stale.send(&client).unwrap();
+        let mut fresh = ZMsg::from_frames(&["fresh"]).unwrap();
+        fresh.set_ttl(Duration::from_secs(60)).unwrap();
+        fresh.send(&client).unwrap();
+
+        let mut msg = ZMsg::recv_live(&server).unwrap();
+        assert!(msg.take_deadline().is_some());
+        assert_eq!(msg.popstr().unwrap().unwrap(), "fresh");
+    }
+}
@@ -0,0 +1,261 @@
+//! Module: czmq-waiter
+//!
+//! Waiting for a socket to become ready, abstracted over how the wait
+//! happens, so that the same logic serves both the blocking API and
+//! the async wrappers in `czmq::asyncs`.
+//!
+//! Every socket has a file descriptor (`ZSock::fd()`) that signals
+//! when its events may have changed. The signal is edge triggered, so
+//! callers must check `ZSock::readable()` or `ZSock::writable()`
+//! before each wait, never after; `wait_for()` does this. A `Waiter`
+//! only has to wait for the descriptor:
+//!
+//! * `Blocking` blocks the thread in `zmq_poll()`;
+//! * `Tokio` (with the `tokio` feature) uses the tokio reactor;
+//! * `AsyncStd` (with the `async-std` feature) uses the async-io
+//!   reactor that async-std runs on.
+
+use crate::{Error, ErrorKind, Result, ZSock};
+use crate::error::retry_interrupted;
+use std::{error, fmt, ptr};
+use std::future::Future;
+use std::os::raw::{c_int, c_long, c_short};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "tokio", feature = "async-std"))]
+use std::os::unix::io::RawFd;
+
+/// The future returned by `Waiter::wait()`, which resolves to false if
+/// the wait timed out.
+pub type Wait<'a> = Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>>;
+
+/// The future returned by `Waiter::sleep()`.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Waits for a socket's file descriptor to signal.
+pub trait Waiter: Sized {
+    /// Watch the file descriptor of `sock`. The waiter must not
+    /// outlive the socket.
+    fn register(sock: &ZSock) -> Result<Self>;
+
+    /// Wait for the descriptor to signal, or for `timeout` to pass.
+    /// Waits forever if `timeout` is `None`.
+    fn wait(&mut self, timeout: Option<Duration>) -> Wait<'_>;
+
+    /// Poll for the descriptor to signal, for use in hand-written
+    /// futures and other poll-based traits.
+    fn poll_wait(&mut self, cx: &mut Context) -> Poll<Result<()>>;
+
+    /// A timer on the same runtime, which completes after `duration`.
+    fn sleep(duration: Duration) -> Sleep;
+}
+
+/// Waits by blocking the current thread.
+pub struct Blocking {
+    fd: c_int,
+}
+
+impl Waiter for Blocking {
+    fn register(sock: &ZSock) -> Result<Blocking> {
+        Ok(Blocking {
+            fd: sock.fd(),
+        })
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Wait<'_> {
+        let fd = self.fd;
+        Box::pin(async move { poll_fd(fd, timeout) })
+    }
+
+    fn poll_wait(&mut self, _: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(poll_fd(self.fd, None).map(|_| ()))
+    }
+
+    fn sleep(duration: Duration) -> Sleep {
+        Box::pin(async move { thread::sleep(duration) })
+    }
+}
+
+/// Waits on the tokio reactor. Must be registered from within a tokio
+/// runtime.
+#[cfg(feature = "tokio")]
+pub struct Tokio {
+    fd: tokio::io::unix::AsyncFd<RawFd>,
+}
+
+#[cfg(feature = "tokio")]
+impl Waiter for Tokio {
+    fn register(sock: &ZSock) -> Result<Tokio> {
+        Ok(Tokio {
+            fd: tokio::io::unix::AsyncFd::with_interest(sock.fd(), tokio::io::Interest::READABLE)?,
+        })
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Wait<'_> {
+        Box::pin(async move {
+            let signalled = async {
+                let mut guard = self.fd.readable().await?;
+                guard.clear_ready();
+                Ok::<bool, Error>(true)
+            };
+
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, signalled).await.unwrap_or(Ok(false)),
+                None => signalled.await,
+            }
+        })
+    }
+
+    fn poll_wait(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        match self.fd.poll_read_ready(cx) {
+            Poll::Ready(Ok(mut guard)) => {
+                guard.clear_ready();
+                Poll::Ready(Ok(()))
+            },
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn sleep(duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Waits on the async-io reactor used by async-std.
+#[cfg(feature = "async-std")]
+pub struct AsyncStd {
+    fd: async_io::Async<RawFd>,
+}
+
+#[cfg(feature = "async-std")]
+impl Waiter for AsyncStd {
+    fn register(sock: &ZSock) -> Result<AsyncStd> {
+        Ok(AsyncStd {
+            fd: async_io::Async::new(sock.fd())?,
+        })
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> Wait<'_> {
+        Box::pin(async move {
+            let signalled = async {
+                self.fd.readable().await?;
+                Ok::<bool, Error>(true)
+            };
+
+            match timeout {
+                Some(timeout) => {
+                    let expired = async {
+                        async_io::Timer::after(timeout).await;
+                        Ok(false)
+                    };
+                    futures_lite::future::or(signalled, expired).await
+                },
+                None => signalled.await,
+            }
+        })
+    }
+
+    fn poll_wait(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        self.fd.poll_readable(cx).map_err(Error::from)
+    }
+
+    fn sleep(duration: Duration) -> Sleep {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+}
+
+/// Wait until `ready(sock)` is true, or for `timeout` to pass.
+/// Returns false on timeout.
+pub(crate) async fn wait_for<W: Waiter>(waiter: &mut W, sock: &mut ZSock, ready: fn(&ZSock) -> bool, timeout: Option<Duration>) -> Result<bool> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+
+    loop {
+        if ready(sock) {
+            return Ok(true);
+        }
+
+        let remaining = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if remaining > Duration::default() => Some(remaining),
+                _ => return Ok(false),
+            },
+            None => None,
+        };
+
+        waiter.wait(remaining).await?;
+    }
+}
+
+/// Run a future to completion on the current thread. With the
+/// `Blocking` waiter the future never has to be woken, so this is how
+/// the blocking API runs the shared async logic.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn poll_fd(fd: c_int, timeout: Option<Duration>) -> Result<bool> {
+    let mut item = czmq_sys::zmq_pollitem_t {
+        socket: ptr::null_mut(),
+        fd: fd,
+        events: POLLIN as c_short,
+        revents: 0,
+    };
+    let t = match timeout {
+        Some(t) => t.as_millis().min(c_long::max_value() as u128) as c_long,
+        None => -1,
+    };
+
+    let rc = retry_interrupted(|| unsafe { czmq_sys::zmq_poll(&mut item, 1, t) }, |rc| *rc == -1);
+    match rc {
+        -1 => Err(Error::from_errno(ErrorKind::NonZero, WaiterError::PollFailed)),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+// ZMQ_POLLIN poll event
+const POLLIN: c_int = 1;
+
+#[derive(Debug)]
+pub enum WaiterError {
+    PollFailed,
+}
+
+impl fmt::Display for WaiterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WaiterError::PollFailed => write!(f, "Could not poll socket descriptor"),
+        }
+    }
+}
+
+impl error::Error for WaiterError {
+    fn description(&self) -> &str {
+        match *self {
+            WaiterError::PollFailed => "Could not poll socket descriptor",
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Module: czmq-xpub
+//!
+//! Decoding for the subscription messages that XPUB and XSUB sockets
+//! exchange: a single frame of 1 (subscribe) or 0 (unsubscribe)
+//! followed by the topic prefix.
+//!
+//! By default an XPUB socket only passes on the first subscription to
+//! a topic and the last unsubscription from it. Set
+//! `ZSock::set_xpub_verbose()` to see every subscription, e.g. to
+//! replay cached values to each new subscriber, and
+//! `ZSock::set_xpub_verboser()` as well to see every unsubscription,
+//! which `SubscriberCounts` needs to count accurately.
+
+use crate::{Error, ErrorKind, Result, SocketLike, ZFrame};
+use std::{error, fmt};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Subscription {
+    pub subscribe: bool,
+    pub topic: Vec<u8>,
+}
+
+impl Subscription {
+    pub fn subscribe(topic: &[u8]) -> Subscription {
+        Subscription {
+            subscribe: true,
+            topic: topic.to_vec(),
+        }
+    }
+
+    pub fn unsubscribe(topic: &[u8]) -> Subscription {
+        Subscription {
+            subscribe: false,
+            topic: topic.to_vec(),
+        }
+    }
+
+    /// Decode a subscription message, or return `None` if `bytes`
+    /// isn't one, e.g. a regular message sent on an XSUB socket.
+    pub fn decode(bytes: &[u8]) -> Option<Subscription> {
+        match bytes.split_first() {
+            Some((&1, topic)) => Some(Subscription::subscribe(topic)),
+            Some((&0, topic)) => Some(Subscription::unsubscribe(topic)),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.topic.len() + 1);
+        bytes.push(if self.subscribe { 1 } else { 0 });
+        bytes.extend_from_slice(&self.topic);
+        bytes
+    }
+
+    /// Receive a subscription from an XPUB socket.
+    pub fn recv<S: SocketLike>(source: S) -> Result<Subscription> {
+        let frame = ZFrame::recv(source)?;
+
+        Subscription::decode(frame.as_bytes()).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidArg, XPubError::NotSubscription(frame.as_bytes().to_vec()))
+        })
+    }
+
+    /// Send the subscription, e.g. from an XSUB socket.
+    pub fn send<D: SocketLike>(&self, dest: D) -> Result<()> {
+        ZFrame::new(&self.encode())?.send(dest, None).map(|_| ())
+    }
+}
+
+impl fmt::Display for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = if self.subscribe { "subscribe" } else { "unsubscribe" };
+        write!(f, "{} {:?}", op, String::from_utf8_lossy(&self.topic))
+    }
+}
+
+/// Counts subscribers to each topic from the subscriptions an XPUB
+/// socket receives. The socket needs both `xpub_verbose` and
+/// `xpub_verboser` set, or the counts will drift.
+#[derive(Debug, Default)]
+pub struct SubscriberCounts {
+    counts: HashMap<Vec<u8>, usize>,
+}
+
+impl SubscriberCounts {
+    pub fn new() -> SubscriberCounts {
+        SubscriberCounts::default()
+    }
+
+    /// Apply a subscription, returning the topic's new count.
+    pub fn apply(&mut self, sub: &Subscription) -> usize {
+        if sub.subscribe {
+            let count = self.counts.entry(sub.topic.clone()).or_insert(0);
+            *count += 1;
+            *count
+        } else {
+            match self.counts.get_mut(&sub.topic) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    *count
+                },
+                Some(_) => {
+                    self.counts.remove(&sub.topic);
+                    0
+                },
+                None => 0,
+            }
+        }
+    }
+
+    pub fn count(&self, topic: &[u8]) -> usize {
+        self.counts.get(topic).cloned().unwrap_or(0)
+    }
+
+    /// The topics with at least one subscriber.
+    pub fn topics(&self) -> impl Iterator<Item = &[u8]> {
+        self.counts.keys().map(|t| t.as_slice())
+    }
+
+    /// The number of subscriptions across all topics.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+#[derive(Debug)]
+pub enum XPubError {
+    NotSubscription(Vec<u8>),
+}
+
+impl fmt::Display for XPubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            XPubError::NotSubscription(ref bytes) => write!(f, "Not a subscription message: {:?}", bytes),
+        }
+    }
+}
+
+impl error::Error for XPubError {
+    fn description(&self) -> &str {
+        match *self {
+            XPubError::NotSubscription(_) => "Not a subscription message",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SocketType, ZSock, ZSys};
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Subscription::decode(b"\x01moo"), Some(Subscription::subscribe(b"moo")));
+        assert_eq!(Subscription::decode(b"\x00"), Some(Subscription::unsubscribe(b"")));
+        assert_eq!(Subscription::decode(b"moo"), None);
+        assert_eq!(Subscription::decode(b""), None);
+        assert_eq!(Subscription::unsubscribe(b"moo").encode(), b"\x00moo");
+        assert_eq!(Subscription::subscribe(b"moo").to_string(), "subscribe \"moo\"");
+    }
+
+    #[test]
+    fn test_subscriber_counts() {
+        ZSys::init();
+
+        let xpub = ZSock::new(SocketType::XPUB);
+        xpub.set_rcvtimeo(Some(1000));
+        xpub.set_xpub_verbose(true);
+        xpub.set_xpub_verboser(true).unwrap();
+        xpub.bind("inproc://xpub_test_subscriber_counts").unwrap();
+
+        let mut counts = SubscriberCounts::new();
+        let sub1 = ZSock::new_sub("inproc://xpub_test_subscriber_counts", Some("moo")).unwrap();
+        let _sub2 = ZSock::new_sub("inproc://xpub_test_subscriber_counts", Some("moo")).unwrap();
+        counts.apply(&Subscription::recv(&xpub).unwrap());
+        assert_eq!(counts.apply(&Subscription::recv(&xpub).unwrap()), 2);
+
+        sub1.set_unsubscribe("moo");
+        assert_eq!(Subscription::recv(&xpub).unwrap(), Subscription::unsubscribe(b"moo"));
+        counts.apply(&Subscription::unsubscribe(b"moo"));
+        assert_eq!(counts.count(b"moo"), 1);
+        assert_eq!(counts.total(), 1);
+
+        counts.apply(&Subscription::unsubscribe(b"moo"));
+        assert_eq!(counts.topics().count(), 0);
+    }
+}
@@ -1,9 +1,31 @@
 //! Module: czmq-zactor
 
-use {czmq_sys, Error, ErrorKind, RawInterface, Result, Sockish, ZMsg, ZSock};
+use crate::{Error, ErrorKind, RawInterface, Result, Sockish, SocketLike, ZMsg, ZSock};
+use crate::error::retry_interrupted;
+use crate::history;
+use crate::leaks;
+use crate::trace;
 use std::{error, fmt, ptr};
 use std::os::raw::c_void;
 
+/// Command names understood by CZMQ's built-in actors. Being
+/// `'static`, these can be sent with `ZActor::send_cmd()` without any
+/// per-call string conversion.
+pub mod commands {
+    pub const ALLOW: &str = "ALLOW";
+    pub const CONFIGURE: &str = "CONFIGURE";
+    pub const CURVE: &str = "CURVE";
+    pub const DENY: &str = "DENY";
+    pub const LISTEN: &str = "LISTEN";
+    pub const PLAIN: &str = "PLAIN";
+    pub const PUBLISH: &str = "PUBLISH";
+    pub const SILENCE: &str = "SILENCE";
+    pub const START: &str = "START";
+    pub const SUBSCRIBE: &str = "SUBSCRIBE";
+    pub const UNSUBSCRIBE: &str = "UNSUBSCRIBE";
+    pub const VERBOSE: &str = "VERBOSE";
+}
+
 pub struct ZActor {
     zactor: *mut czmq_sys::zactor_t,
     owned: bool,
@@ -14,11 +36,23 @@ unsafe impl Send for ZActor {}
 impl Drop for ZActor {
     fn drop(&mut self) {
         if self.owned {
+            // Destroying the actor destroys its pipe too
+            history::forget(unsafe { czmq_sys::zactor_sock(self.zactor) } as *mut c_void);
+            leaks::destroyed(self.zactor as *mut c_void);
             unsafe { czmq_sys::zactor_destroy(&mut self.zactor) };
         }
     }
 }
 
+impl fmt::Debug for ZActor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZActor")
+         .field("owned", &self.owned)
+         .field("pipe", &self.sock())
+         .finish()
+    }
+}
+
 impl ZActor {
     pub fn new(task: czmq_sys::zactor_fn) -> Result<ZActor> {
         let zactor = unsafe { czmq_sys::zactor_new(task, ptr::null_mut()) };
@@ -26,6 +60,8 @@ impl ZActor {
         if zactor == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZActorError::Instantiate))
         } else {
+            history::forget(unsafe { czmq_sys::zactor_sock(zactor) } as *mut c_void);
+            leaks::created(zactor as *mut c_void, || "ZActor".to_string());
             Ok(ZActor {
                 zactor: zactor,
                 owned: true,
@@ -34,9 +70,10 @@ impl ZActor {
     }
 
     pub fn send(&self, msg: ZMsg) -> Result<()> {
+        let _span = trace::send(self.zactor as *mut c_void, &msg);
         let rc = unsafe { czmq_sys::zactor_send(self.zactor, &mut msg.into_raw()) };
         if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZActorError::CmdFailed))
+            Err(Error::from_errno(ErrorKind::NonZero, ZActorError::CmdFailed))
         } else {
             Ok(())
         }
@@ -44,17 +81,40 @@ impl ZActor {
 
     pub fn send_str(&self, string: &str) -> Result<()> {
         let msg = ZMsg::new();
-        try!(msg.addstr(string));
+        msg.addstr(string)?;
+        self.send(msg)
+    }
+
+    /// Send a command followed by its arguments, one frame each.
+    /// Frames are built straight from the string bytes, avoiding the
+    /// `CString` round trip that `send_str()` makes.
+    pub fn send_cmd(&self, cmd: &'static str, args: &[&str]) -> Result<()> {
+        self.send_cmd_iter(cmd, args.iter().cloned())
+    }
+
+    /// Like `send_cmd()`, but takes the arguments from an iterator,
+    /// so they needn't be collected into a slice first.
+    pub fn send_cmd_iter<'a, I>(&self, cmd: &'static str, args: I) -> Result<()>
+        where I: IntoIterator<Item = &'a str> {
+        let _span = trace::command(cmd);
+        let msg = ZMsg::new();
+        msg.addbytes(cmd.as_bytes())?;
+        for arg in args {
+            msg.addbytes(arg.as_bytes())?;
+        }
         self.send(msg)
     }
 
     pub fn recv(&self) -> Result<ZMsg> {
-        let zmsg_ptr = unsafe { czmq_sys::zactor_recv(self.zactor) };
+        let span = trace::recv(self.zactor as *mut c_void);
+        let zmsg_ptr = retry_interrupted(|| unsafe { czmq_sys::zactor_recv(self.zactor) }, |p| *p == ptr::null_mut());
 
         if zmsg_ptr == ptr::null_mut() {
-            Err(Error::new(ErrorKind::NullPtr, ZActorError::CmdFailed))
+            Err(Error::from_errno(ErrorKind::NullPtr, ZActorError::CmdFailed))
         } else {
-            unsafe { Ok(ZMsg::from_raw(zmsg_ptr, true)) }
+            let msg = unsafe { ZMsg::from_raw(zmsg_ptr, true) };
+            span.record_msg(&msg);
+            Ok(msg)
         }
     }
 
@@ -65,6 +125,10 @@ impl ZActor {
 
 impl RawInterface<c_void> for ZActor {
     unsafe fn from_raw(ptr: *mut c_void, owned: bool) -> ZActor {
+        if owned {
+            leaks::created(ptr, || "ZActor".to_string());
+        }
+
         ZActor {
             zactor: ptr as *mut czmq_sys::zactor_t,
             owned: owned,
@@ -82,6 +146,18 @@ impl RawInterface<c_void> for ZActor {
 
 impl Sockish for ZActor {}
 
+impl SocketLike for ZActor {
+    fn as_socket_ptr(&mut self) -> *mut c_void {
+        self.zactor as *mut c_void
+    }
+}
+
+impl<'a> SocketLike for &'a ZActor {
+    fn as_socket_ptr(&mut self) -> *mut c_void {
+        self.zactor as *mut c_void
+    }
+}
+
 #[derive(Debug)]
 pub enum ZActorError {
     Instantiate,
@@ -105,3 +181,51 @@ impl error::Error for ZActorError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::os::raw::c_void;
+    use super::*;
+    use crate::ZSys;
+
+    #[test]
+    fn test_send_cmd() {
+        ZSys::init();
+
+        let actor = ZActor::new(echo_actor).unwrap();
+        actor.send_cmd(commands::ALLOW, &["127.0.0.1", "::1"]).unwrap();
+
+        let mut msg = actor.recv().unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "ALLOW");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "127.0.0.1");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "::1");
+        assert!(msg.popstr().is_none());
+
+        actor.send_cmd_iter(commands::DENY, vec!["10.0.0.1"].into_iter()).unwrap();
+        let mut msg = actor.recv().unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "DENY");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "10.0.0.1");
+        assert!(msg.popstr().is_none());
+    }
+
+    // Echo every message back until told to terminate
+    unsafe extern "C" fn echo_actor(pipe: *mut czmq_sys::zsock_t, _args: *mut c_void) {
+        czmq_sys::zsock_signal(pipe as *mut c_void, 0);
+
+        loop {
+            let mut msg = czmq_sys::zmsg_recv(pipe as *mut c_void);
+            if msg == ptr::null_mut() {
+                break;
+            }
+
+            let first = czmq_sys::zmsg_first(msg);
+            if czmq_sys::zframe_streq(first, "$TERM\0".as_ptr() as *const i8) == 1 {
+                czmq_sys::zmsg_destroy(&mut msg);
+                break;
+            }
+
+            czmq_sys::zmsg_send(&mut msg, pipe as *mut c_void);
+        }
+    }
+}
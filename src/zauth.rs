@@ -1,19 +1,84 @@
 //! Module: czmq-zauth
 
-use {czmq_sys, RawInterface, Result, ZActor, ZCertStore, ZMsg};
-use error::{Error, ErrorKind};
+use crate::{Cidr, CidrError, RawInterface, Result, ZActor, ZCertStore};
+use crate::error::{Error, ErrorKind};
 use std::{error, ptr};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::os::raw::c_void;
+use std::sync::{Condvar, Mutex};
+use crate::zactor::commands;
+
+// The most addresses a CIDR range may expand to
+const MAX_EXPANDED: u128 = 256;
+
+// Whether a ZAP handler is running
+static ZAP_CLAIMED: Mutex<bool> = Mutex::new(false);
+static ZAP_RELEASED: Condvar = Condvar::new();
+
+czmq_actor_protocol! {
+    enum AuthCommand, client AuthClient {
+        Allow = commands::ALLOW => allow(address: String) -> signal;
+        Deny = commands::DENY => deny(address: String) -> signal;
+        Plain = commands::PLAIN => plain(filename: String) -> signal;
+        Curve = commands::CURVE => curve(location: String) -> signal;
+        Verbose = commands::VERBOSE => verbose() -> signal;
+    }
+}
 
+#[derive(Debug)]
 pub struct ZAuth {
     zactor: ZActor,
+    // Dropped after the actor, once the ZAP endpoint is free
+    _claim: ZapClaim,
+}
+
+/// The right to bind the ZAP endpoint. Only one handler can be bound
+/// per process, and CZMQ's authenticator asserts if the endpoint is
+/// taken, so each handler waits for the last to go before starting.
+#[derive(Debug)]
+pub(crate) struct ZapClaim(());
+
+impl ZapClaim {
+    pub fn acquire() -> ZapClaim {
+        let mut claimed = ZAP_CLAIMED.lock().unwrap_or_else(|e| e.into_inner());
+        while *claimed {
+            claimed = ZAP_RELEASED.wait(claimed).unwrap_or_else(|e| e.into_inner());
+        }
+        *claimed = true;
+        ZapClaim(())
+    }
+}
+
+impl Drop for ZapClaim {
+    fn drop(&mut self) {
+        *ZAP_CLAIMED.lock().unwrap_or_else(|e| e.into_inner()) = false;
+        ZAP_RELEASED.notify_one();
+    }
 }
 
 unsafe impl Send for ZAuth {}
 
 impl ZAuth {
+    /// Configure an authenticator before creating it. The builder
+    /// sends VERBOSE first, then the ALLOW/DENY lists, then loads any
+    /// PLAIN or CURVE credentials.
+    pub fn builder() -> ZAuthBuilder {
+        ZAuthBuilder {
+            certstore: None,
+            verbose: false,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            allow_cidr: Vec::new(),
+            deny_cidr: Vec::new(),
+            plain: None,
+            curve: None,
+        }
+    }
+
+    /// Start an authenticator, first waiting for any other ZAP
+    /// handler in the process to be dropped.
     pub fn new(certstore: Option<ZCertStore>) -> Result<ZAuth> {
+        let claim = ZapClaim::acquire();
         let ptr = if let Some(cs) = certstore {
             cs.into_raw()
         } else {
@@ -26,49 +91,44 @@ impl ZAuth {
         } else {
             Ok(ZAuth {
                 zactor: unsafe { ZActor::from_raw(zactor as *mut c_void, true) },
+                _claim: claim,
             })
         }
     }
 
     pub fn allow(&self, address: &str) -> Result<()> {
-        let msg = ZMsg::new();
-        try!(msg.addstr("ALLOW"));
-        try!(msg.addstr(address));
-
-        try!(self.zactor.send(msg));
-        self.zactor.sock().wait()
+        self.client().allow(address.to_string())
     }
 
     pub fn deny(&self, address: &str) -> Result<()> {
-        let msg = ZMsg::new();
-        try!(msg.addstr("DENY"));
-        try!(msg.addstr(address));
+        self.client().deny(address.to_string())
+    }
+
+    /// Allow each address in `cidr`, e.g. `192.168.1.0/24`. CZMQ only
+    /// matches whole addresses, so ranges of more than 256 addresses
+    /// fail with `ErrorKind::Unsupported`; see `AddressFilter`.
+    pub fn allow_cidr(&self, cidr: &str) -> Result<()> {
+        for address in expand(cidr)? {
+            self.allow(&address)?;
+        }
+        Ok(())
+    }
 
-        try!(self.zactor.send(msg));
-        self.zactor.sock().wait()
+    /// Deny each address in `cidr`, with the same limit as
+    /// `allow_cidr()`.
+    pub fn deny_cidr(&self, cidr: &str) -> Result<()> {
+        for address in expand(cidr)? {
+            self.deny(&address)?;
+        }
+        Ok(())
     }
 
     pub fn load_plain(&self, filename: &str) -> Result<()> {
-        let msg = ZMsg::new();
-        try!(msg.addstr("PLAIN"));
-        try!(msg.addstr(filename));
-
-        try!(self.zactor.send(msg));
-        self.zactor.sock().wait()
+        self.client().plain(filename.to_string())
     }
 
     pub fn load_curve(&self, location: Option<&str>) -> Result<()> {
-        let msg = ZMsg::new();
-        try!(msg.addstr("CURVE"));
-
-        if let Some(loc) = location {
-            try!(msg.addstr(loc));
-        } else {
-            try!(msg.addstr("*"));
-        }
-
-        try!(self.zactor.send(msg));
-        self.zactor.sock().wait()
+        self.client().curve(location.unwrap_or("*").to_string())
     }
 
     // XXX This is unimplemented upstream, so it's just a placeholder.
@@ -77,11 +137,110 @@ impl ZAuth {
     }
 
     pub fn verbose(&self) -> Result<()> {
-        try!(self.zactor.send_str("VERBOSE"));
-        self.zactor.sock().wait()
+        self.client().verbose()
+    }
+
+    fn client(&self) -> AuthClient {
+        AuthClient::new(&self.zactor)
     }
 }
 
+pub struct ZAuthBuilder {
+    certstore: Option<ZCertStore>,
+    verbose: bool,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    allow_cidr: Vec<String>,
+    deny_cidr: Vec<String>,
+    plain: Option<String>,
+    curve: Option<Option<String>>,
+}
+
+impl ZAuthBuilder {
+    pub fn certstore(mut self, certstore: ZCertStore) -> ZAuthBuilder {
+        self.certstore = Some(certstore);
+        self
+    }
+
+    pub fn verbose(mut self) -> ZAuthBuilder {
+        self.verbose = true;
+        self
+    }
+
+    pub fn allow(mut self, address: &str) -> ZAuthBuilder {
+        self.allow.push(address.to_string());
+        self
+    }
+
+    pub fn deny(mut self, address: &str) -> ZAuthBuilder {
+        self.deny.push(address.to_string());
+        self
+    }
+
+    pub fn allow_cidr(mut self, cidr: &str) -> ZAuthBuilder {
+        self.allow_cidr.push(cidr.to_string());
+        self
+    }
+
+    pub fn deny_cidr(mut self, cidr: &str) -> ZAuthBuilder {
+        self.deny_cidr.push(cidr.to_string());
+        self
+    }
+
+    pub fn plain(mut self, filename: &str) -> ZAuthBuilder {
+        self.plain = Some(filename.to_string());
+        self
+    }
+
+    pub fn curve(mut self, location: Option<&str>) -> ZAuthBuilder {
+        self.curve = Some(location.map(|l| l.to_string()));
+        self
+    }
+
+    pub fn build(self) -> Result<ZAuth> {
+        let zauth = ZAuth::new(self.certstore)?;
+
+        if self.verbose {
+            zauth.verbose()?;
+        }
+
+        for address in &self.allow {
+            zauth.allow(address)?;
+        }
+
+        for address in &self.deny {
+            zauth.deny(address)?;
+        }
+
+        for cidr in &self.allow_cidr {
+            zauth.allow_cidr(cidr)?;
+        }
+
+        for cidr in &self.deny_cidr {
+            zauth.deny_cidr(cidr)?;
+        }
+
+        if let Some(ref filename) = self.plain {
+            zauth.load_plain(filename)?;
+        }
+
+        if let Some(ref location) = self.curve {
+            zauth.load_curve(location.as_ref().map(|l| l.as_str()))?;
+        }
+
+        Ok(zauth)
+    }
+}
+
+// The addresses in `cidr`, formatted as libzmq reports peers
+fn expand(cidr: &str) -> Result<Vec<String>> {
+    let cidr: Cidr = cidr.parse()?;
+    if cidr.size() > MAX_EXPANDED {
+        return Err(Error::new(ErrorKind::Unsupported, CidrError::TooLarge(cidr)));
+    }
+    Ok(cidr.addresses().map(|a| a.to_string()).collect())
+}
+
 #[derive(Debug)]
 pub enum ZAuthError {
     Instantiate,
@@ -111,9 +270,9 @@ mod tests {
     use super::*;
     use tempdir::TempDir;
     use tempfile::NamedTempFile;
-    use {ZCert, ZFrame, ZSock, SocketType, ZSys};
+    use crate::{ZCert, ZFrame, ZSock, SocketType, ZSys};
     #[cfg(feature = "draft")]
-    use {RawInterface, ZCertStore, ZCertStoreRaw};
+    use crate::{RawInterface, ZCertStore, ZCertStoreRaw};
 
     // There can only be one ZAuth instance per context as each ZAuth
     // instance binds to the same inproc endpoint. The simplest way
@@ -123,7 +282,9 @@ mod tests {
         ZSys::init();
 
         test_verbose();
+        test_builder();
         test_allow_deny();
+        test_allow_cidr();
         test_plain();
         test_curve();
         test_zcertstore();
@@ -134,6 +295,11 @@ mod tests {
         assert!(zauth.verbose().is_ok());
     }
 
+    fn test_builder() {
+        let zauth = ZAuth::builder().verbose().allow("127.0.0.1").curve(None).build();
+        assert!(zauth.is_ok());
+    }
+
     fn test_allow_deny() {
         let server = ZSock::new(SocketType::PULL);
         server.set_zap_domain("compuglobalhypermega.net");
@@ -166,6 +332,13 @@ mod tests {
         assert_eq!(server.recv_str().unwrap().unwrap(), "test");
     }
 
+    fn test_allow_cidr() {
+        let zauth = ZAuth::builder().allow_cidr("127.0.0.0/30").deny_cidr("::1").build().unwrap();
+        assert!(zauth.allow_cidr("fd00::/120").is_ok());
+        assert_eq!(zauth.allow_cidr("10.0.0.0/8").unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(zauth.deny_cidr("10.0.0.0/").unwrap_err().kind(), ErrorKind::InvalidArg);
+    }
+
     fn test_plain() {
         let zauth = ZAuth::new(None).unwrap();
 
@@ -0,0 +1,228 @@
+//! Module: czmq-zbeacon
+//!
+//! LAN discovery over UDP broadcast, using CZMQ's `zbeacon` actor. A
+//! beacon is configured with a UDP port, then publishes a short
+//! payload at a fixed interval, listens for other beacons, or both.
+//!
+//! Each beacon heard is reported as a `Discovery` holding the sender's
+//! IP address and its payload. `asyncs::AsyncBeacon` reports them as
+//! an async stream instead.
+
+use crate::{Error, ErrorKind, RawInterface, Result, ZActor, ZMsg, ZSock};
+use crate::waiter::{self, Blocking, Waiter};
+use crate::zactor::commands;
+use std::{error, fmt, ptr};
+use std::os::raw::c_void;
+use std::time::Duration;
+
+czmq_actor_protocol! {
+    enum BeaconCommand, client BeaconClient {
+        Configure = commands::CONFIGURE => configure(port: String) -> reply(String);
+        Publish = commands::PUBLISH => publish(data: Vec<u8>, interval: String) -> none;
+        Silence = commands::SILENCE => silence() -> none;
+        Subscribe = commands::SUBSCRIBE => subscribe(filter: Vec<u8>) -> none;
+        Unsubscribe = commands::UNSUBSCRIBE => unsubscribe() -> none;
+        Verbose = commands::VERBOSE => verbose() -> none;
+    }
+}
+
+/// The largest payload a beacon can publish.
+pub const BEACON_MAX: usize = 255;
+
+/// A beacon heard from a peer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Discovery {
+    /// The IP address the beacon came from.
+    pub addr: String,
+    pub data: Vec<u8>,
+}
+
+impl Discovery {
+    pub(crate) fn from_msg(mut msg: ZMsg) -> Result<Discovery> {
+        let addr = match msg.popstr() {
+            Some(Ok(addr)) => addr,
+            Some(Err(_)) => return Err(Error::new(ErrorKind::StringConversion, ZBeaconError::InvalidAddr)),
+            None => return Err(Error::new(ErrorKind::MissingFrame, ZBeaconError::MissingAddr)),
+        };
+        let data = match msg.popbytes()? {
+            Some(data) => data,
+            None => return Err(Error::new(ErrorKind::MissingFrame, ZBeaconError::MissingData)),
+        };
+
+        Ok(Discovery {
+            addr: addr,
+            data: data,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ZBeacon {
+    zactor: ZActor,
+}
+
+unsafe impl Send for ZBeacon {}
+
+impl ZBeacon {
+    pub fn new() -> Result<ZBeacon> {
+        let zactor = unsafe { czmq_sys::zactor_new(czmq_sys::zbeacon, ptr::null_mut()) };
+
+        if zactor == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZBeaconError::Instantiate))
+        } else {
+            Ok(ZBeacon {
+                zactor: unsafe { ZActor::from_raw(zactor as *mut c_void, true) },
+            })
+        }
+    }
+
+    /// Bind the beacon to a UDP port, which must be done before
+    /// publishing or subscribing. Returns this host's broadcast
+    /// address, or `None` if there is no interface to broadcast on.
+    pub fn configure(&self, port: u16) -> Result<Option<String>> {
+        let (hostname,) = self.client().configure(port.to_string())?;
+
+        if hostname.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(hostname))
+        }
+    }
+
+    /// Broadcast `data` every `interval`, replacing any payload that
+    /// was already being published.
+    pub fn publish(&self, data: &[u8], interval: Duration) -> Result<()> {
+        if data.len() > BEACON_MAX {
+            return Err(Error::new(ErrorKind::InvalidArg, ZBeaconError::TooLarge));
+        }
+
+        self.client().publish(data.to_vec(), interval.as_millis().to_string())
+    }
+
+    /// Stop publishing.
+    pub fn silence(&self) -> Result<()> {
+        self.client().silence()
+    }
+
+    /// Listen for beacons whose payload starts with `filter`. An empty
+    /// filter hears every beacon.
+    pub fn subscribe(&self, filter: &[u8]) -> Result<()> {
+        self.client().subscribe(filter.to_vec())
+    }
+
+    /// Stop listening.
+    pub fn unsubscribe(&self) -> Result<()> {
+        self.client().unsubscribe()
+    }
+
+    pub fn verbose(&self) -> Result<()> {
+        self.client().verbose()
+    }
+
+    /// Wait for the next beacon, or return `None` if none arrives
+    /// within `timeout`. Waits forever if `timeout` is `None`.
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<Option<Discovery>> {
+        let mut pipe = self.zactor.sock();
+        let mut waiter = Blocking::register(&pipe)?;
+
+        if waiter::block_on(waiter::wait_for(&mut waiter, &mut pipe, ZSock::readable, timeout))? {
+            Discovery::from_msg(self.zactor.recv()?).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) fn actor(&self) -> &ZActor {
+        &self.zactor
+    }
+
+    fn client(&self) -> BeaconClient {
+        BeaconClient::new(&self.zactor)
+    }
+}
+
+#[derive(Debug)]
+pub enum ZBeaconError {
+    Instantiate,
+    InvalidAddr,
+    MissingAddr,
+    MissingData,
+    TooLarge,
+}
+
+impl fmt::Display for ZBeaconError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZBeaconError::Instantiate => write!(f, "Could not instantiate new ZBeacon struct"),
+            ZBeaconError::InvalidAddr => write!(f, "Beacon address is not valid UTF-8"),
+            ZBeaconError::MissingAddr => write!(f, "Beacon has no address frame"),
+            ZBeaconError::MissingData => write!(f, "Beacon has no data frame"),
+            ZBeaconError::TooLarge => write!(f, "Beacon data is larger than BEACON_MAX"),
+        }
+    }
+}
+
+impl error::Error for ZBeaconError {
+    fn description(&self) -> &str {
+        match *self {
+            ZBeaconError::Instantiate => "Could not instantiate new ZBeacon struct",
+            ZBeaconError::InvalidAddr => "Beacon address is not valid UTF-8",
+            ZBeaconError::MissingAddr => "Beacon has no address frame",
+            ZBeaconError::MissingData => "Beacon has no data frame",
+            ZBeaconError::TooLarge => "Beacon data is larger than BEACON_MAX",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ZSys};
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_discovery() {
+        ZSys::init();
+
+        let speaker = ZBeacon::new().unwrap();
+        // Without a broadcast interface there is nothing to test
+        if speaker.configure(9999).unwrap().is_none() {
+            return;
+        }
+        speaker.publish(b"moo-1", TICK).unwrap();
+
+        let listener = ZBeacon::new().unwrap();
+        listener.configure(9999).unwrap();
+        listener.subscribe(b"moo").unwrap();
+
+        let discovery = listener.recv(Some(Duration::from_secs(2))).unwrap().unwrap();
+        assert_eq!(discovery.data, b"moo-1");
+
+        listener.unsubscribe().unwrap();
+        speaker.silence().unwrap();
+    }
+
+    #[test]
+    fn test_too_large() {
+        ZSys::init();
+
+        let beacon = ZBeacon::new().unwrap();
+        let e = beacon.publish(&[0; BEACON_MAX + 1], TICK).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+    }
+
+    #[test]
+    fn test_from_msg() {
+        ZSys::init();
+
+        let msg = ZMsg::from_frames(&[&b"10.0.0.1"[..], b"moo"]).unwrap();
+        let discovery = Discovery::from_msg(msg).unwrap();
+        assert_eq!(discovery.addr, "10.0.0.1");
+        assert_eq!(discovery.data, b"moo");
+
+        let msg = ZMsg::from_frames(&[b"10.0.0.1"]).unwrap();
+        assert_eq!(Discovery::from_msg(msg).unwrap_err().kind(), ErrorKind::MissingFrame);
+    }
+}
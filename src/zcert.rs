@@ -1,6 +1,7 @@
 //! Module: czmq-zcert
 
-use {czmq_sys, Error, ErrorKind, RawInterface, Result, Sockish, zmq, ZList};
+use crate::{Error, ErrorKind, RawInterface, Result, SocketLike, ZList};
+use crate::capability::Capability;
 use std::{convert, error, fmt, ptr, result, slice, str};
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
@@ -8,7 +9,7 @@ use std::path::Path;
 
 const KEY_SIZE: usize = 32;
 
-#[derive(Debug, Eq)]
+#[derive(Eq)]
 pub struct ZCert {
     zcert: *mut czmq_sys::zcert_t,
     owned: bool,
@@ -25,6 +26,17 @@ impl Drop for ZCert {
     }
 }
 
+// The secret key is never printed, so certs can be logged safely.
+impl fmt::Debug for ZCert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZCert")
+         .field("public_key", &self.public_txt())
+         .field("secret_key", &"<redacted>")
+         .field("meta", &self.meta_keys().iter().collect::<Vec<_>>())
+         .finish()
+    }
+}
+
 impl PartialEq for ZCert {
     fn eq(&self, other: &ZCert) -> bool {
         ZCert::eq(self, other)
@@ -53,13 +65,33 @@ impl ZCert {
     }
 
     pub fn from_txt(public_txt: &str, secret_txt: &str) -> Result<ZCert> {
-        let public_key = try!(zmq::z85_decode(public_txt));
-        let secret_key = try!(zmq::z85_decode(secret_txt));
+        let public_key = zmq::z85_decode(public_txt)?;
+        let secret_key = zmq::z85_decode(secret_txt)?;
+
+        // zcert_new_from() reads KEY_SIZE bytes from each key
+        // regardless of how long they really are.
+        if public_key.len() != KEY_SIZE || secret_key.len() != KEY_SIZE {
+            return Err(Error::new(ErrorKind::InvalidArg, ZCertError::InvalidKeySize));
+        }
+
         Ok(ZCert::from_keys(&public_key, &secret_key))
     }
 
+    /// Create a certificate from a public key alone, e.g. a peer's.
+    /// The secret key is all zeroes, as it is for public certificates
+    /// loaded from disk.
+    pub fn from_public_txt(public_txt: &str) -> Result<ZCert> {
+        let public_key = zmq::z85_decode(public_txt)?;
+
+        if public_key.len() != KEY_SIZE {
+            return Err(Error::new(ErrorKind::InvalidArg, ZCertError::InvalidKeySize));
+        }
+
+        Ok(ZCert::from_keys(&public_key, &[0; KEY_SIZE]))
+    }
+
     pub fn load<P: AsRef<Path>>(path: P) -> Result<ZCert> {
-        let path_c = try!(CString::new(path.as_ref().to_str().unwrap()));
+        let path_c = CString::new(path.as_ref().to_str().unwrap())?;
         let zcert = unsafe { czmq_sys::zcert_load(path_c.as_ptr()) };
 
         if zcert == ptr::null_mut() {
@@ -107,6 +139,21 @@ impl ZCert {
         unsafe { czmq_sys::zcert_set_meta(self.zcert, key_c.as_ptr(), "%s\0".as_ptr() as *const i8, value_c.as_ptr()) };
     }
 
+    /// Remove a metadata field. This is a CZMQ draft, so without the
+    /// `draft` feature it fails with `ErrorKind::Unsupported`.
+    #[cfg_attr(not(feature = "draft"), allow(unused_variables))]
+    pub fn unset_meta(&self, key: &str) -> Result<()> {
+        require!(Capability::CertUnsetMeta);
+
+        #[cfg(feature = "draft")]
+        {
+            let key_c = CString::new(key)?;
+            unsafe { czmq_sys::zcert_unset_meta(self.zcert, key_c.as_ptr()) };
+        }
+
+        Ok(())
+    }
+
     pub fn meta(&self, key: &str) -> Option<result::Result<String, Vec<u8>>> {
         let key_c = CString::new(key).unwrap_or(CString::new("").unwrap());
 
@@ -144,7 +191,7 @@ impl ZCert {
     pub fn encode_meta(&self) -> Vec<u8> {
         let mut encoded: Vec<u8> = Vec::new();
 
-        for metakey in self.meta_keys() {
+        for metakey in self.meta_keys().iter() {
             if let Some(Ok(metaval)) = self.meta(metakey) {
                 encoded.push(metakey.len() as u8);
                 encoded.extend_from_slice(metakey.as_bytes());
@@ -178,7 +225,7 @@ impl ZCert {
         let mut index = 0;
 
         while bytes_left > 1 {
-            let name_length: usize = *try!(encoded.get(index).ok_or(Error::new(ErrorKind::InvalidArg, ZCertError::InvalidMetaEncoded))) as usize;
+            let name_length: usize = *encoded.get(index).ok_or(Error::new(ErrorKind::InvalidArg, ZCertError::InvalidMetaEncoded))? as usize;
             index += 1;
             bytes_left -= 1;
 
@@ -186,7 +233,7 @@ impl ZCert {
                 return Err(Error::new(ErrorKind::InvalidArg, ZCertError::InvalidMetaEncoded));
             }
 
-            let name = try!(str::from_utf8(&encoded[index..index + name_length]));
+            let name = str::from_utf8(&encoded[index..index + name_length])?;
             index += name_length;
             bytes_left -= name_length;
 
@@ -205,7 +252,7 @@ impl ZCert {
                 return Err(Error::new(ErrorKind::InvalidArg, ZCertError::InvalidMetaEncoded));
             }
 
-            let value = try!(str::from_utf8(&encoded[index..index + value_length]));
+            let value = str::from_utf8(&encoded[index..index + value_length])?;
             index += value_length;
             bytes_left -= value_length;
 
@@ -216,7 +263,7 @@ impl ZCert {
     }
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let path_c = try!(CString::new(path.as_ref().to_str().unwrap()));
+        let path_c = CString::new(path.as_ref().to_str().unwrap())?;
 
         unsafe {
             let rc = czmq_sys::zcert_save(self.zcert, path_c.as_ptr());
@@ -229,7 +276,7 @@ impl ZCert {
     }
 
     pub fn save_public<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let path_c = try!(CString::new(path.as_ref().to_str().unwrap()));
+        let path_c = CString::new(path.as_ref().to_str().unwrap())?;
 
         unsafe {
             let rc = czmq_sys::zcert_save_public(self.zcert, path_c.as_ptr());
@@ -242,7 +289,7 @@ impl ZCert {
     }
 
     pub fn save_secret<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let path_c = try!(CString::new(path.as_ref().to_str().unwrap()));
+        let path_c = CString::new(path.as_ref().to_str().unwrap())?;
 
         unsafe {
             let rc = czmq_sys::zcert_save_secret(self.zcert, path_c.as_ptr());
@@ -254,8 +301,8 @@ impl ZCert {
         }
     }
 
-    pub fn apply<S: Sockish>(&self, sock: &mut S) {
-        unsafe { czmq_sys::zcert_apply(self.zcert, sock.as_mut_ptr()) };
+    pub fn apply<S: SocketLike>(&self, mut sock: S) {
+        unsafe { czmq_sys::zcert_apply(self.zcert, sock.as_socket_ptr()) };
     }
 
     pub fn dup(&self) -> ZCert {
@@ -299,6 +346,7 @@ impl RawInterface<czmq_sys::zcert_t> for ZCert {
 pub enum ZCertError {
     Instantiate,
     InvalidCert(String),
+    InvalidKeySize,
     InvalidMetaEncoded,
     SavePath(String),
     ZmqDecode(zmq::DecodeError),
@@ -309,6 +357,7 @@ impl fmt::Display for ZCertError {
         match *self {
             ZCertError::Instantiate => write!(f, "Could not instantiate new ZCert struct"),
             ZCertError::InvalidCert(ref e) => write!(f, "Could not open certificate at path: {}", e),
+            ZCertError::InvalidKeySize => write!(f, "Key must be {} bytes long", KEY_SIZE),
             ZCertError::InvalidMetaEncoded => write!(f, "Encoded metadata is invalid"),
             ZCertError::SavePath(ref e) => write!(f, "Could not save certificate file to path: {}", e),
             ZCertError::ZmqDecode(ref e) => write!(f, "Could not decode Z85 string: {}", e),
@@ -321,6 +370,7 @@ impl error::Error for ZCertError {
         match *self {
             ZCertError::Instantiate => "Could not instantiate new ZCert struct",
             ZCertError::InvalidCert(_) => "Certificate was invalid or non-existent",
+            ZCertError::InvalidKeySize => "Key has the wrong length",
             ZCertError::InvalidMetaEncoded => "Encoded metadata is invalid",
             ZCertError::SavePath(_) => "Could not save certificate file to given path",
             ZCertError::ZmqDecode(_) => "Could not decode Z85 string",
@@ -336,12 +386,11 @@ impl convert::From<zmq::DecodeError> for Error {
 
 #[cfg(test)]
 mod tests {
-    use {ZSock, ZSys};
+    use crate::{ZSock, ZSys};
     use super::*;
-    use zmq;
 
-    const PUBLIC_TXT: &'static str = "Ko9/&3Uw)$U]Zyp>+4$-i/yaDea2QqlDPGl-&V1s";
-    const SECRET_TXT: &'static str = "[MfOo!1^1N}zZY/x{[A6^9>VRC.+O6vX&]zYvDC-";
+    const PUBLIC_TXT: &str = "Ko9/&3Uw)$U]Zyp>+4$-i/yaDea2QqlDPGl-&V1s";
+    const SECRET_TXT: &str = "[MfOo!1^1N}zZY/x{[A6^9>VRC.+O6vX&]zYvDC-";
 
     #[test]
     fn test_public_key() {
@@ -381,6 +430,28 @@ mod tests {
         assert_eq!(cert.secret_txt(), SECRET_TXT);
     }
 
+    #[test]
+    fn test_from_txt_invalid() {
+        assert!(ZCert::from_txt("", SECRET_TXT).is_err());
+        assert!(ZCert::from_txt(PUBLIC_TXT, "abcde").is_err());
+    }
+
+    #[test]
+    fn test_from_public_txt() {
+        let cert = ZCert::from_public_txt(PUBLIC_TXT).unwrap();
+        assert_eq!(cert.public_txt(), PUBLIC_TXT);
+        assert_eq!(cert.secret_key(), &[0; KEY_SIZE][..]);
+        assert!(ZCert::from_public_txt("abcde").is_err());
+    }
+
+    #[test]
+    fn test_decode_meta_malformed() {
+        let cert = create_cert();
+        assert!(cert.decode_meta(&[5, b'm', b'o']).is_err());
+        assert!(cert.decode_meta(&[3, b'm', b'o', b'o', 0, 0]).is_err());
+        assert!(cert.decode_meta(&[3, b'm', b'o', b'o', 0, 0, 0, 9, b'c']).is_err());
+    }
+
     #[test]
     fn test_getset_meta() {
         let cert = create_cert();
@@ -388,6 +459,19 @@ mod tests {
         assert_eq!(cert.meta("moo").unwrap().unwrap(), "cow");
     }
 
+    #[test]
+    fn test_unset_meta() {
+        let cert = create_cert();
+        cert.set_meta("moo", "cow");
+
+        if cfg!(feature = "draft") {
+            cert.unset_meta("moo").unwrap();
+            assert!(cert.meta("moo").is_none());
+        } else {
+            assert_eq!(cert.unset_meta("moo").unwrap_err().kind(), ErrorKind::Unsupported);
+        }
+    }
+
     #[test]
     fn test_meta_keys() {
         let cert = create_cert();
@@ -397,6 +481,24 @@ mod tests {
         assert_eq!(keys.next().unwrap(), "moo");
     }
 
+    #[test]
+    fn test_meta_keys_iter() {
+        let cert = create_cert();
+        cert.set_meta("moo", "cow");
+
+        let keys = cert.meta_keys();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys.iter().collect::<Vec<_>>(), vec!["moo"]);
+
+        // Iterating leaves the cursor used by next() alone
+        cert.set_meta("oink", "pig");
+        let mut keys = cert.meta_keys();
+        let first = keys.next().unwrap();
+        assert_eq!(keys.iter().count(), 2);
+        assert_ne!(keys.next().unwrap(), first);
+        assert!(keys.next().is_none());
+    }
+
     #[test]
     fn test_encode_decode_meta() {
         let cert = create_cert();
@@ -441,6 +543,14 @@ mod tests {
         assert_eq!(cert.secret_txt(), dup.secret_txt());
     }
 
+    #[test]
+    fn test_debug() {
+        let cert = create_cert();
+        let debug = format!("{:?}", cert);
+        assert!(debug.contains(PUBLIC_TXT));
+        assert!(!debug.contains(SECRET_TXT));
+    }
+
     #[test]
     fn test_eq() {
         let c1 = create_cert();
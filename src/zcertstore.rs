@@ -1,6 +1,6 @@
 //! Module: czmq-zcertstore
 
-use {czmq_sys, Colander, Error, ErrorKind, RawInterface, Result, ZCert, ZHashX};
+use crate::{Colander, Error, ErrorKind, RawInterface, Result, ZCert, ZHashX};
 use std::{error, fmt, mem, ptr};
 #[cfg(feature = "draft")]
 use std::any::Any;
@@ -28,7 +28,7 @@ impl ZCertStore {
     pub fn new(location: Option<&str>) -> Result<ZCertStore> {
         let zcertstore = match location {
             Some(l) => {
-                let location_c = try!(CString::new(l));
+                let location_c = CString::new(l)?;
                 unsafe { czmq_sys::zcertstore_new(location_c.as_ptr()) }
             },
             None => unsafe { czmq_sys::zcertstore_new(ptr::null()) },
@@ -50,7 +50,7 @@ impl ZCertStore {
     }
 
     #[cfg(feature = "draft")]
-    pub fn set_loader_with_state(&self, loader: czmq_sys::zcertstore_loader, state: Box<Any>, destructor: Option<czmq_sys::zcertstore_destructor>) {
+    pub fn set_loader_with_state(&self, loader: czmq_sys::zcertstore_loader, state: Box<dyn Any>, destructor: Option<czmq_sys::zcertstore_destructor>) {
         let destructor_ptr = match destructor {
             Some(d) => d,
             None => default_destructor as czmq_sys::zcertstore_destructor,
@@ -60,7 +60,7 @@ impl ZCertStore {
     }
 
     pub fn lookup(&self, public_key: &str) -> Result<Option<ZCert>> {
-        let public_key_c = try!(CString::new(public_key));
+        let public_key_c = CString::new(public_key)?;
         let zcert = unsafe { czmq_sys::zcertstore_lookup(self.zcertstore, public_key_c.as_ptr()) };
 
         if zcert == ptr::null_mut() {
@@ -159,9 +159,9 @@ impl error::Error for ZCertStoreError {
 
 #[cfg(test)]
 mod tests {
-    use {czmq_sys, RawInterface, ZCert};
+    use crate::{RawInterface, ZCert};
     #[cfg(feature = "draft")]
-    use {ZCertStoreRaw, ZSys};
+    use crate::{ZCertStoreRaw, ZSys};
     use super::*;
     use tempdir::TempDir;
     #[cfg(feature = "draft")]
@@ -0,0 +1,398 @@
+//! Module: czmq-zconfig
+
+use crate::{Error, ErrorKind, RawInterface, Result};
+use std::{error, fmt, ptr};
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(feature = "serde_json")]
+use serde_json::{Map, Value};
+
+/// Key used for a node's own value when a node with children is
+/// converted to JSON.
+#[cfg(feature = "serde_json")]
+pub const JSON_VALUE_KEY: &str = "$value";
+
+/// A node in a ZPL configuration tree. Nodes returned by `child()`,
+/// `children()` and `locate()` are owned by the tree.
+pub struct ZConfig {
+    zconfig: *mut czmq_sys::zconfig_t,
+    owned: bool,
+}
+
+unsafe impl Send for ZConfig {}
+
+impl Drop for ZConfig {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { czmq_sys::zconfig_destroy(&mut self.zconfig) };
+        }
+    }
+}
+
+impl fmt::Debug for ZConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZConfig")
+         .field("name", &self.name())
+         .field("value", &self.value())
+         .finish()
+    }
+}
+
+impl ZConfig {
+    /// Create an empty root node.
+    pub fn new(name: &str) -> Result<ZConfig> {
+        let name_c = CString::new(name)?;
+        let zconfig = unsafe { czmq_sys::zconfig_new(name_c.as_ptr(), ptr::null_mut()) };
+
+        if zconfig == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZConfigError::Instantiate))
+        } else {
+            Ok(ZConfig {
+                zconfig: zconfig,
+                owned: true,
+            })
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ZConfig> {
+        let path_c = CString::new(path.as_ref().to_str().unwrap())?;
+        let zconfig = unsafe { czmq_sys::zconfig_load(path_c.as_ptr()) };
+
+        if zconfig == ptr::null_mut() {
+            Err(Error::new(ErrorKind::InvalidPath, ZConfigError::Load(path_c.into_string().unwrap())))
+        } else {
+            Ok(ZConfig {
+                zconfig: zconfig,
+                owned: true,
+            })
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path_c = CString::new(path.as_ref().to_str().unwrap())?;
+        let rc = unsafe { czmq_sys::zconfig_save(self.zconfig, path_c.as_ptr()) };
+
+        if rc == -1 {
+            Err(Error::new(ErrorKind::InvalidPath, ZConfigError::Save(path_c.into_string().unwrap())))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Serialize the tree as a ZPL document.
+    pub fn to_zpl(&self) -> Result<String> {
+        let mut ptr = unsafe { czmq_sys::zconfig_str_save(self.zconfig) };
+
+        if ptr == ptr::null_mut() {
+            return Err(Error::new(ErrorKind::NullPtr, ZConfigError::CmdFailed));
+        }
+
+        let s = unsafe { CStr::from_ptr(ptr) }.to_str().map(|s| s.to_string());
+        unsafe { czmq_sys::zstr_free(&mut ptr) };
+        s.map_err(Error::from)
+    }
+
+    /// Add a child node, which is owned by this node.
+    pub fn add_child(&self, name: &str) -> Result<ZConfig> {
+        let name_c = CString::new(name)?;
+        let zconfig = unsafe { czmq_sys::zconfig_new(name_c.as_ptr(), self.zconfig) };
+
+        if zconfig == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZConfigError::Instantiate))
+        } else {
+            Ok(ZConfig {
+                zconfig: zconfig,
+                owned: false,
+            })
+        }
+    }
+
+    /// The node's name, or `None` if it isn't valid UTF-8.
+    pub fn name<'a>(&'a self) -> Option<&'a str> {
+        unsafe { borrow_str(czmq_sys::zconfig_name(self.zconfig)) }
+    }
+
+    /// The node's value, or `None` if it has no value or the value
+    /// isn't valid UTF-8.
+    pub fn value<'a>(&'a self) -> Option<&'a str> {
+        unsafe { borrow_str(czmq_sys::zconfig_value(self.zconfig)) }
+    }
+
+    pub fn set_value(&self, value: &str) -> Result<()> {
+        let value_c = CString::new(value)?;
+        unsafe { czmq_sys::zconfig_set_value(self.zconfig, "%s\0".as_ptr() as *const c_char, value_c.as_ptr()) };
+        Ok(())
+    }
+
+    /// Set the value at `path` (e.g. "server/port"), creating any
+    /// missing nodes along the way.
+    pub fn put(&self, path: &str, value: &str) -> Result<()> {
+        let path_c = CString::new(path)?;
+        let value_c = CString::new(value)?;
+        unsafe { czmq_sys::zconfig_put(self.zconfig, path_c.as_ptr(), value_c.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn get<'a>(&'a self, path: &str) -> Option<&'a str> {
+        let path_c = CString::new(path).ok()?;
+        unsafe { borrow_str(czmq_sys::zconfig_get(self.zconfig, path_c.as_ptr(), ptr::null())) }
+    }
+
+    pub fn locate(&self, path: &str) -> Option<ZConfig> {
+        let path_c = CString::new(path).ok()?;
+        let zconfig = unsafe { czmq_sys::zconfig_locate(self.zconfig, path_c.as_ptr()) };
+
+        if zconfig == ptr::null_mut() {
+            None
+        } else {
+            Some(ZConfig {
+                zconfig: zconfig,
+                owned: false,
+            })
+        }
+    }
+
+    pub fn child(&self) -> Option<ZConfig> {
+        self.children().next()
+    }
+
+    pub fn children(&self) -> ZConfigChildren {
+        ZConfigChildren {
+            next: unsafe { czmq_sys::zconfig_child(self.zconfig) },
+            _parent: PhantomData,
+        }
+    }
+
+    /// Convert the tree beneath this node to JSON. Leaf nodes become
+    /// strings (or null if they have no value) and other nodes become
+    /// objects keyed by child name. Children that share a name are
+    /// collected into an array, and a value on a node that also has
+    /// children is kept under `JSON_VALUE_KEY`.
+    #[cfg(feature = "serde_json")]
+    pub fn to_json(&self) -> Value {
+        let value = self.value().map(|v| Value::String(v.to_string()));
+        let children: Vec<_> = self.children().collect();
+
+        if children.is_empty() {
+            return value.unwrap_or(Value::Null);
+        }
+
+        let mut map = Map::new();
+        if let Some(value) = value {
+            map.insert(JSON_VALUE_KEY.to_string(), value);
+        }
+
+        for child in children {
+            let name = child.name().unwrap_or("").to_string();
+            let json = child.to_json();
+
+            match map.remove(&name) {
+                None => {
+                    map.insert(name, json);
+                },
+                Some(Value::Array(mut items)) => {
+                    items.push(json);
+                    map.insert(name, Value::Array(items));
+                },
+                Some(prev) => {
+                    map.insert(name, Value::Array(vec![prev, json]));
+                },
+            }
+        }
+
+        Value::Object(map)
+    }
+
+    /// Build a tree from JSON, the inverse of `to_json()`. Numbers and
+    /// bools are stored as their string form.
+    #[cfg(feature = "serde_json")]
+    pub fn from_json(name: &str, json: &Value) -> Result<ZConfig> {
+        let root = ZConfig::new(name)?;
+        root.fill_json(json)?;
+        Ok(root)
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn fill_json(&self, json: &Value) -> Result<()> {
+        match *json {
+            Value::Null => Ok(()),
+            Value::Bool(b) => self.set_value(&b.to_string()),
+            Value::Number(ref n) => self.set_value(&n.to_string()),
+            Value::String(ref s) => self.set_value(s),
+            Value::Array(ref items) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.add_child(&i.to_string())?.fill_json(item)?;
+                }
+                Ok(())
+            },
+            Value::Object(ref map) => {
+                for (k, v) in map {
+                    match *v {
+                        _ if k == JSON_VALUE_KEY => self.fill_json(v)?,
+                        Value::Array(ref items) => for item in items {
+                            self.add_child(k)?.fill_json(item)?;
+                        },
+                        _ => self.add_child(k)?.fill_json(v)?,
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl FromStr for ZConfig {
+    type Err = Error;
+
+    /// Parse a ZPL document.
+    fn from_str(s: &str) -> Result<ZConfig> {
+        let s_c = CString::new(s)?;
+        let zconfig = unsafe { czmq_sys::zconfig_str_load(s_c.as_ptr()) };
+
+        if zconfig == ptr::null_mut() {
+            Err(Error::new(ErrorKind::InvalidArg, ZConfigError::Parse))
+        } else {
+            Ok(ZConfig {
+                zconfig: zconfig,
+                owned: true,
+            })
+        }
+    }
+}
+
+unsafe fn borrow_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr == ptr::null() {
+        None
+    } else {
+        CStr::from_ptr(ptr).to_str().ok()
+    }
+}
+
+/// Iterator over a node's children. See `ZConfig::children()`.
+pub struct ZConfigChildren<'a> {
+    next: *mut czmq_sys::zconfig_t,
+    _parent: PhantomData<&'a ZConfig>,
+}
+
+impl<'a> Iterator for ZConfigChildren<'a> {
+    type Item = ZConfig;
+
+    fn next(&mut self) -> Option<ZConfig> {
+        if self.next == ptr::null_mut() {
+            None
+        } else {
+            let zconfig = self.next;
+            self.next = unsafe { czmq_sys::zconfig_next(zconfig) };
+            Some(ZConfig {
+                zconfig: zconfig,
+                owned: false,
+            })
+        }
+    }
+}
+
+impl RawInterface<czmq_sys::zconfig_t> for ZConfig {
+    unsafe fn from_raw(ptr: *mut czmq_sys::zconfig_t, owned: bool) -> ZConfig {
+        ZConfig {
+            zconfig: ptr,
+            owned: owned,
+        }
+    }
+
+    fn into_raw(mut self) -> *mut czmq_sys::zconfig_t {
+        self.owned = false;
+        self.zconfig
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut czmq_sys::zconfig_t {
+        self.zconfig
+    }
+}
+
+#[derive(Debug)]
+pub enum ZConfigError {
+    CmdFailed,
+    Instantiate,
+    Load(String),
+    Parse,
+    Save(String),
+}
+
+impl fmt::Display for ZConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZConfigError::CmdFailed => write!(f, "ZConfig command failed"),
+            ZConfigError::Instantiate => write!(f, "Could not instantiate new ZConfig struct"),
+            ZConfigError::Load(ref e) => write!(f, "Could not load config file at path: {}", e),
+            ZConfigError::Parse => write!(f, "Could not parse ZPL document"),
+            ZConfigError::Save(ref e) => write!(f, "Could not save config file to path: {}", e),
+        }
+    }
+}
+
+impl error::Error for ZConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ZConfigError::CmdFailed => "ZConfig command failed",
+            ZConfigError::Instantiate => "Could not instantiate new ZConfig struct",
+            ZConfigError::Load(_) => "Could not load config file",
+            ZConfigError::Parse => "Could not parse ZPL document",
+            ZConfigError::Save(_) => "Could not save config file",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZPL: &str = "server\n    port = 5555\n    peer = \"tcp://a\"\n    peer = \"tcp://b\"\n";
+
+    #[test]
+    fn test_put_get() {
+        let config = ZConfig::new("root").unwrap();
+        config.put("server/port", "5555").unwrap();
+
+        assert_eq!(config.get("server/port"), Some("5555"));
+        assert_eq!(config.get("server/host"), None);
+
+        let server = config.locate("server").unwrap();
+        assert_eq!(server.name(), Some("server"));
+        assert_eq!(server.child().unwrap().value(), Some("5555"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let config: ZConfig = ZPL.parse().unwrap();
+        let peers: Vec<_> = config.locate("server").unwrap().children()
+                                  .filter(|c| c.name() == Some("peer"))
+                                  .map(|c| c.value().unwrap().to_string())
+                                  .collect();
+        assert_eq!(peers, vec!["tcp://a", "tcp://b"]);
+        assert!(config.to_zpl().unwrap().contains("port = 5555"));
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_json() {
+        let config: ZConfig = ZPL.parse().unwrap();
+        config.locate("server").unwrap().set_value("main").unwrap();
+
+        let json = config.to_json();
+        assert_eq!(json, serde_json::json!({
+            "server": {
+                "$value": "main",
+                "port": "5555",
+                "peer": ["tcp://a", "tcp://b"],
+            }
+        }));
+
+        let config = ZConfig::from_json("root", &json).unwrap();
+        assert_eq!(config.get("server"), Some("main"));
+        assert_eq!(config.get("server/port"), Some("5555"));
+        assert_eq!(config.to_json(), json);
+    }
+}
@@ -1,7 +1,10 @@
 //! Module: czmq-zframe
 
-use {czmq_sys, Error, ErrorKind, RawInterface, Result, Sockish};
-use std::{error, fmt, ptr, result, slice};
+use crate::{Error, ErrorKind, RawInterface, Result, SocketLike};
+use crate::error::retry_interrupted;
+use crate::history::{self, HistoryOp};
+use std::{error, fmt, ptr, result, slice, str};
+use std::convert::TryInto;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_void;
 
@@ -30,6 +33,16 @@ impl Drop for ZFrame {
     }
 }
 
+impl fmt::Debug for ZFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZFrame")
+         .field("size", &self.size())
+         .field("more", &self.more())
+         .field("data", &DebugBytes(self.as_bytes()))
+         .finish()
+    }
+}
+
 impl PartialEq for ZFrame {
     fn eq(&self, other: &ZFrame) -> bool {
         ZFrame::eq(self, other)
@@ -50,6 +63,14 @@ impl ZFrame {
         })
     }
 
+    /// Create a frame with its MORE flag set as given, e.g. to build
+    /// arbitrary frames in property tests.
+    pub fn with_more(data: &[u8], more: bool) -> Result<ZFrame> {
+        let frame = ZFrame::new(data)?;
+        frame.set_more(more);
+        Ok(frame)
+    }
+
     pub fn empty() -> Result<ZFrame> {
         let zframe = unsafe { czmq_sys::zframe_new_empty() };
 
@@ -70,43 +91,79 @@ impl ZFrame {
         Self::new(frame.as_bytes())
     }
 
-    pub fn recv<S: Sockish>(source: &mut S) -> Result<ZFrame> {
-        let zframe = unsafe { czmq_sys::zframe_recv(source.as_mut_ptr()) };
+    // Numbers are encoded as zproto encodes number fields: 1, 2, 4 or
+    // 8 bytes, most significant first. Bools are a one byte number,
+    // 0 or 1.
 
-        if zframe == ptr::null_mut() {
-            return Err(Error::new(ErrorKind::NullPtr, ZFrameError::CmdFailed));
-        }
+    pub fn from_u8(n: u8) -> Result<ZFrame> {
+        Self::new(&[n])
+    }
 
-        Ok(ZFrame {
-            zframe: zframe,
-            owned: true,
-        })
+    pub fn from_u16(n: u16) -> Result<ZFrame> {
+        Self::new(&n.to_be_bytes())
+    }
+
+    pub fn from_u32(n: u32) -> Result<ZFrame> {
+        Self::new(&n.to_be_bytes())
+    }
+
+    pub fn from_u64(n: u64) -> Result<ZFrame> {
+        Self::new(&n.to_be_bytes())
+    }
+
+    pub fn from_bool(b: bool) -> Result<ZFrame> {
+        Self::from_u8(b as u8)
+    }
+
+    pub fn recv<S: SocketLike>(mut source: S) -> Result<ZFrame> {
+        let source = source.as_socket_ptr();
+        let zframe = retry_interrupted(|| unsafe { czmq_sys::zframe_recv(source) }, |p| *p == ptr::null_mut());
+
+        let result = if zframe == ptr::null_mut() {
+            Err(Error::from_errno(ErrorKind::NullPtr, ZFrameError::CmdFailed))
+        } else {
+            Ok(ZFrame {
+                zframe: zframe,
+                owned: true,
+            })
+        };
+
+        history::record(source, &result, || match result {
+            Ok(ref frame) => HistoryOp::Recv { frames: 1, bytes: frame.size() },
+            Err(_) => HistoryOp::Recv { frames: 0, bytes: 0 },
+        });
+        result
     }
 
     // This fn consumes the ZFrame, implying no REUSE flag
-    pub fn send<D: Sockish>(self, dest: &mut D, flags: Option<Flags>) -> Result<i32> {
+    pub fn send<D: SocketLike>(self, mut dest: D, flags: Option<Flags>) -> Result<i32> {
         let mut zframe = self;
-        zframe.do_send(dest.as_mut_ptr(), flags)
+        zframe.do_send(dest.as_socket_ptr(), flags)
     }
 
     // This fn doesn't consume the ZFrame, which implies REUSE flag
-    pub fn send_reuse<D: Sockish>(&mut self, dest: &mut D, flags: Option<Flags>) -> Result<i32> {
+    pub fn send_reuse<D: SocketLike>(&mut self, mut dest: D, flags: Option<Flags>) -> Result<i32> {
         let flags = if let Some(f) = flags {
             f | ZFRAME_REUSE
         } else {
             ZFRAME_REUSE
         };
-        self.do_send(dest.as_mut_ptr(), Some(flags))
+        self.do_send(dest.as_socket_ptr(), Some(flags))
     }
 
     fn do_send(&mut self, dest: *mut c_void, flags: Option<Flags>) -> Result<i32> {
         let flags_c = if let Some(f) = flags { f.bits() } else { 0 };
-        let size = unsafe { czmq_sys::zframe_send(&mut self.zframe as *mut *mut czmq_sys::zframe_t, dest, flags_c) };
-        if size == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZFrameError::CmdFailed))
+        let bytes = self.size();
+        let zframe = &mut self.zframe as *mut *mut czmq_sys::zframe_t;
+        let size = retry_interrupted(|| unsafe { czmq_sys::zframe_send(zframe, dest, flags_c) }, |rc| *rc == -1);
+        let result = if size == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZFrameError::CmdFailed))
         } else {
             Ok(size)
-        }
+        };
+
+        history::record(dest, &result, || HistoryOp::Send { frames: 1, bytes: bytes });
+        result
     }
 
     pub fn size(&self) -> usize {
@@ -127,6 +184,73 @@ impl ZFrame {
         }
     }
 
+    /// Borrow the frame data without copying it.
+    pub fn as_bytes(&self) -> &[u8] {
+        let data = unsafe { czmq_sys::zframe_data(self.zframe) };
+
+        if data == ptr::null_mut() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(data, self.size()) }
+        }
+    }
+
+    /// Borrow the frame data mutably, e.g. to overwrite it in place.
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        let data = unsafe { czmq_sys::zframe_data(self.zframe) };
+
+        if data == ptr::null_mut() {
+            &mut []
+        } else {
+            unsafe { slice::from_raw_parts_mut(data, self.size()) }
+        }
+    }
+
+    /// Read a frame made by `from_u8()`, failing with
+    /// `ErrorKind::InvalidArg` if it's the wrong size. Likewise for
+    /// the other `to_*()` readers.
+    pub fn to_u8(&self) -> Result<u8> {
+        Ok(self.sized(1)?[0])
+    }
+
+    pub fn to_u16(&self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.sized(2)?.try_into().unwrap()))
+    }
+
+    pub fn to_u32(&self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.sized(4)?.try_into().unwrap()))
+    }
+
+    pub fn to_u64(&self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.sized(8)?.try_into().unwrap()))
+    }
+
+    /// Any non-zero byte reads as true, as zproto's C code does.
+    pub fn to_bool(&self) -> Result<bool> {
+        self.to_u8().map(|b| b != 0)
+    }
+
+    // The frame's bytes, if there are exactly `size` of them
+    pub(crate) fn sized(&self, size: usize) -> Result<&[u8]> {
+        let bytes = self.as_bytes();
+        if bytes.len() == size {
+            Ok(bytes)
+        } else {
+            Err(Error::new(ErrorKind::InvalidArg, ZFrameError::WrongSize(size, bytes.len())))
+        }
+    }
+
+    /// Copy the frame data into `buf`, reusing its allocation.
+    pub fn copy_into(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+
+        let size = self.size();
+        if size > 0 {
+            let data = unsafe { czmq_sys::zframe_data(self.zframe) };
+            buf.extend_from_slice(unsafe { slice::from_raw_parts(data, size) });
+        }
+    }
+
     pub fn meta(&self, property: &str) -> Option<result::Result<String, Vec<u8>>> {
         let property_c = CString::new(property).unwrap_or(CString::new("").unwrap());
         let meta = unsafe { czmq_sys::zframe_meta(self.zframe, property_c.as_ptr()) };
@@ -202,13 +326,56 @@ impl ZFrame {
         unsafe { czmq_sys::zframe_eq(self.zframe, other.zframe) == 1 }
     }
 
+    /// The routing id of the SERVER peer a frame came from, or 0.
+    #[cfg(feature = "draft")]
+    pub fn routing_id(&self) -> u32 {
+        unsafe { czmq_sys::zframe_routing_id(self.zframe) }
+    }
+
+    /// Address a frame sent on a SERVER socket.
+    #[cfg(feature = "draft")]
+    pub fn set_routing_id(&self, routing_id: u32) {
+        unsafe { czmq_sys::zframe_set_routing_id(self.zframe, routing_id) };
+    }
+
+    /// The RADIO group a frame was published to, if any.
+    #[cfg(feature = "draft")]
+    pub fn group(&self) -> Option<String> {
+        let group = unsafe { czmq_sys::zframe_group(self.zframe) };
+
+        if group == ptr::null() {
+            return None;
+        }
+
+        match unsafe { CStr::from_ptr(group) }.to_string_lossy().into_owned() {
+            ref g if g.is_empty() => None,
+            g => Some(g),
+        }
+    }
+
+    /// Set the group to publish a frame to on a RADIO socket. Groups
+    /// are at most 15 bytes.
+    #[cfg(feature = "draft")]
+    pub fn set_group(&self, group: &str) -> Result<()> {
+        let group_c = CString::new(group)?;
+        let rc = unsafe { czmq_sys::zframe_set_group(self.zframe, group_c.as_ptr()) };
+
+        if rc == -1 {
+            Err(Error::new(ErrorKind::InvalidArg, ZFrameError::CmdFailed))
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn reset(&self, data: &[u8]) {
         unsafe { czmq_sys::zframe_reset(self.zframe, data.as_ptr() as *const c_void, data.len() as u64) };
     }
 
     pub fn print(&self, prefix: Option<&str>) {
-        let prefix_ptr = match prefix {
-            Some(p) => CString::new(p).unwrap_or(CString::new("").unwrap()).as_ptr(),
+        // Keep the CString alive until zframe_print() has returned
+        let prefix_c = prefix.map(|p| CString::new(p).unwrap_or(CString::new("").unwrap()));
+        let prefix_ptr = match prefix_c {
+            Some(ref p) => p.as_ptr(),
             None => ptr::null(),
         };
         unsafe { czmq_sys::zframe_print(self.zframe, prefix_ptr) };
@@ -233,10 +400,41 @@ impl RawInterface<czmq_sys::zframe_t> for ZFrame {
     }
 }
 
+// The most bytes of a frame that Debug output will show
+const DEBUG_BYTES_MAX: usize = 32;
+
+/// Formats frame contents for Debug output: as a string if they're
+/// printable UTF-8, otherwise as hex, truncated to `DEBUG_BYTES_MAX`.
+pub struct DebugBytes<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Debug for DebugBytes<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = if self.0.len() > DEBUG_BYTES_MAX {
+            &self.0[..DEBUG_BYTES_MAX]
+        } else {
+            self.0
+        };
+
+        match str::from_utf8(bytes) {
+            Ok(s) if !s.chars().any(char::is_control) => write!(f, "{:?}", s)?,
+            _ => for b in bytes {
+                write!(f, "{:02x}", b)?;
+            },
+        }
+
+        if bytes.len() < self.0.len() {
+            write!(f, "... ({} bytes)", self.0.len())?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum ZFrameError {
     Instantiate,
     CmdFailed,
+    WrongSize(usize, usize),
 }
 
 impl fmt::Display for ZFrameError {
@@ -244,6 +442,7 @@ impl fmt::Display for ZFrameError {
         match *self {
             ZFrameError::Instantiate => write!(f, "Could not instantiate new ZFrame struct"),
             ZFrameError::CmdFailed => write!(f, "ZFrame command failed"),
+            ZFrameError::WrongSize(expected, got) => write!(f, "Expected a {} byte frame but got {} bytes", expected, got),
         }
     }
 }
@@ -253,6 +452,7 @@ impl error::Error for ZFrameError {
         match *self {
             ZFrameError::Instantiate => "Could not instantiate new ZFrame struct",
             ZFrameError::CmdFailed => "ZFrame command failed",
+            ZFrameError::WrongSize(..) => "Frame is the wrong size",
         }
     }
 }
@@ -260,7 +460,7 @@ impl error::Error for ZFrameError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use {zmq, ZSock, ZSys};
+    use crate::{ZSock, ZSys};
 
     #[test]
     fn test_sendrecv_zmq() {
@@ -299,6 +499,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_copy_into() {
+        let zframe = ZFrame::from("Danger zone!").unwrap();
+        let mut buf = b"Lana".to_vec();
+        zframe.copy_into(&mut buf);
+        assert_eq!(buf, b"Danger zone!");
+    }
+
     #[test]
     fn test_dup() {
         let zframe = ZFrame::from("moo cow").unwrap();
@@ -333,6 +541,24 @@ mod tests {
         assert!(!zframe.more());
     }
 
+    #[test]
+    fn test_with_more() {
+        let zframe = ZFrame::with_more(b"moo", true).unwrap();
+        assert!(zframe.more());
+        assert_eq!(zframe.as_bytes(), b"moo");
+        assert!(!ZFrame::with_more(&[], false).unwrap().more());
+    }
+
+    #[test]
+    fn test_debug() {
+        let frame = ZFrame::new(b"moo").unwrap();
+        assert_eq!(format!("{:?}", frame), "ZFrame { size: 3, more: false, data: \"moo\" }");
+
+        let frame = ZFrame::new(&[0; 40]).unwrap();
+        assert_eq!(format!("{:?}", DebugBytes(&[0xde, 0xad])), "dead");
+        assert!(format!("{:?}", frame).ends_with("... (40 bytes) }"));
+    }
+
     #[test]
     fn test_eq() {
         let zframe1 = ZFrame::from("Steve Holt!").unwrap();
@@ -347,6 +573,24 @@ mod tests {
         assert_eq!(zframe.data().unwrap().unwrap(), "Holy shitsnacks!");
     }
 
+    #[test]
+    fn test_numbers() {
+        assert_eq!(ZFrame::from_u8(7).unwrap().as_bytes(), &[7]);
+        assert_eq!(ZFrame::from_u16(0x0102).unwrap().as_bytes(), &[1, 2]);
+        assert_eq!(ZFrame::from_u32(0x0102_0304).unwrap().as_bytes(), &[1, 2, 3, 4]);
+        assert_eq!(ZFrame::from_u64(1).unwrap().as_bytes(), &[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(ZFrame::from_bool(true).unwrap().as_bytes(), &[1]);
+
+        assert_eq!(ZFrame::from_u16(u16::max_value()).unwrap().to_u16().unwrap(), u16::max_value());
+        assert_eq!(ZFrame::from_u32(42).unwrap().to_u32().unwrap(), 42);
+        assert_eq!(ZFrame::from_u64(1 << 40).unwrap().to_u64().unwrap(), 1 << 40);
+        assert!(!ZFrame::from_bool(false).unwrap().to_bool().unwrap());
+        assert!(ZFrame::new(&[2]).unwrap().to_bool().unwrap());
+
+        let e = ZFrame::from_u32(42).unwrap().to_u64().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+    }
+
     // XXX We need to capture output here before we can test.
     // #[test]
     // fn test_print() {
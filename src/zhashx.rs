@@ -1,10 +1,11 @@
 //! Module: czmq-zhashx
 
-use {czmq_sys, Colander, Error, ErrorKind, RawInterface, Result};
-use std::{error, fmt, mem, ptr};
+use crate::{Colander, Error, ErrorKind, RawInterface, Result};
+use std::{error, fmt, mem, ptr, vec};
 use std::any::Any;
-use std::ffi::CString;
-use std::os::raw::c_void;
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_void};
 
 pub struct ZHashX {
     zhashx: *mut czmq_sys::zhashx_t,
@@ -33,12 +34,12 @@ impl ZHashX {
     // pub fn zhashx_unpack(frame: *mut zframe_t) -> *mut zhashx_t;
     // pub fn zhashx_destroy(self_p: *mut *mut zhashx_t);
 
-    pub fn insert(&self, key: &str, item: Box<Any>) -> Result<()> {
+    pub fn insert(&self, key: &str, item: Box<dyn Any>) -> Result<()> {
         self.insert_raw(key, Box::into_raw(item) as *mut c_void)
     }
 
     pub fn insert_raw(&self, key: &str, item: *mut c_void) -> Result<()> {
-        let key_c = try!(CString::new(key));
+        let key_c = CString::new(key)?;
         let rc = unsafe { czmq_sys::zhashx_insert(self.zhashx, key_c.as_ptr() as *const c_void, item) };
 
         // Deliberately leak this memory, which will be managed by C
@@ -51,7 +52,7 @@ impl ZHashX {
         }
     }
 
-    pub fn update(&self, key: &str, item: Box<Any>) {
+    pub fn update(&self, key: &str, item: Box<dyn Any>) {
         let key_c = CString::new(key).unwrap_or(CString::new("").unwrap());
         unsafe { czmq_sys::zhashx_update(self.zhashx, key_c.as_ptr() as *const c_void, Box::into_raw(item) as *mut c_void) };
 
@@ -59,7 +60,7 @@ impl ZHashX {
         mem::forget(key_c);
     }
 
-    pub fn delete(&self, key: &str) {
+    pub fn delete(&mut self, key: &str) {
         let key_c = CString::new(key).unwrap_or(CString::new("").unwrap());
         unsafe { czmq_sys::zhashx_delete(self.zhashx, key_c.as_ptr() as *const c_void) };
     }
@@ -86,7 +87,30 @@ impl ZHashX {
     //                      key: *const ::std::os::raw::c_void,
     //                      free_fn: zhashx_free_fn)
     //  -> *mut ::std::os::raw::c_void;
-    // pub fn zhashx_size(_self: *mut zhashx_t) -> size_t;
+    pub fn len(&self) -> usize {
+        unsafe { czmq_sys::zhashx_size(self.zhashx) as usize }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the table's keys, borrowed from the table. The
+    /// keys are listed up front, so iterators can be nested.
+    pub fn keys(&self) -> ZHashXKeys {
+        let mut keys = Vec::with_capacity(self.len());
+        let mut item = unsafe { czmq_sys::zhashx_first(self.zhashx) };
+        while item != ptr::null_mut() {
+            keys.push(unsafe { czmq_sys::zhashx_cursor(self.zhashx) } as *const c_char);
+            item = unsafe { czmq_sys::zhashx_next(self.zhashx) };
+        }
+
+        ZHashXKeys {
+            keys: keys.into_iter(),
+            _hash: PhantomData,
+        }
+    }
+
     // pub fn zhashx_keys(_self: *mut zhashx_t) -> *mut zhashxx_t;
     // pub fn zhashx_values(_self: *mut zhashx_t) -> *mut zhashxx_t;
     // pub fn zhashx_first(_self: *mut zhashx_t) -> *mut ::std::os::raw::c_void;
@@ -141,6 +165,30 @@ impl RawInterface<czmq_sys::zhashx_t> for ZHashX {
     }
 }
 
+// Keys are only freed through `&mut ZHashX`, so the listed keys
+// outlive the borrow.
+pub struct ZHashXKeys<'a> {
+    keys: vec::IntoIter<*const c_char>,
+    _hash: PhantomData<&'a ZHashX>,
+}
+
+unsafe impl<'a> Send for ZHashXKeys<'a> {}
+unsafe impl<'a> Sync for ZHashXKeys<'a> {}
+
+impl<'a> Iterator for ZHashXKeys<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.keys.next().map(|key| unsafe { CStr::from_ptr(key) }.to_str().unwrap_or(""))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for ZHashXKeys<'a> {}
+
 #[derive(Debug)]
 pub enum ZHashXError {
     CmdFailed,
@@ -164,12 +212,12 @@ impl error::Error for ZHashXError {
 
 #[cfg(test)]
 mod tests {
-    use ZCert;
+    use crate::ZCert;
     use super::*;
 
     #[test]
     fn test_crud() {
-        let hash = ZHashX::new();
+        let mut hash = ZHashX::new();
 
         let test_value = ZCert::new().unwrap();
         let pubkey = test_value.public_txt().to_string();
@@ -188,4 +236,21 @@ mod tests {
         hash.delete("mykey");
         assert!(hash.lookup::<ZCert>("mykey").is_none());
     }
+
+    #[test]
+    fn test_keys() {
+        let hash = ZHashX::new();
+        assert!(hash.is_empty());
+
+        hash.insert("moo", Box::new(1)).unwrap();
+        hash.insert("cow", Box::new(2)).unwrap();
+        assert_eq!(hash.len(), 2);
+
+        let mut keys: Vec<_> = hash.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["cow", "moo"]);
+
+        let pairs = hash.keys().flat_map(|a| hash.keys().map(move |b| (a, b))).count();
+        assert_eq!(pairs, 4);
+    }
 }
@@ -1,12 +1,16 @@
 //! Module: czmq-zlist
 
-use czmq_sys;
-use std::ptr;
+use std::{ptr, vec};
 use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
 
 pub struct ZList<T> {
     zlist: *mut czmq_sys::zlist_t,
     _list_type: Option<T>,
+    // Items the caller has walked through with `next()`, where 0
+    // means the cursor is before the first item
+    cursor: usize,
 }
 
 impl<T> ZList<T> {
@@ -14,6 +18,49 @@ impl<T> ZList<T> {
         ZList {
             zlist: ptr,
             _list_type: None,
+            cursor: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        unsafe { czmq_sys::zlist_size(self.zlist) as usize }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ZList<&'static str> {
+    /// Iterate over the list's strings, which are borrowed from the
+    /// list. The strings are listed up front, so iterators can be
+    /// nested and the cursor used by `next()` is left alone.
+    pub fn iter(&self) -> ZListIter {
+        let mut items = Vec::with_capacity(self.len());
+        let mut item = unsafe { czmq_sys::zlist_first(self.zlist) };
+        while item != ptr::null_mut() {
+            items.push(item);
+            item = unsafe { czmq_sys::zlist_next(self.zlist) };
+        }
+
+        // CZMQ has no way to walk the list without moving the cursor,
+        // so put it back where `next()` left it. Stepping past the
+        // last item leaves the cursor before the first one again.
+        unsafe {
+            if self.cursor == 0 {
+                czmq_sys::zlist_last(self.zlist);
+                czmq_sys::zlist_next(self.zlist);
+            } else {
+                czmq_sys::zlist_first(self.zlist);
+                for _ in 1..self.cursor {
+                    czmq_sys::zlist_next(self.zlist);
+                }
+            }
+        }
+
+        ZListIter {
+            items: items.into_iter(),
+            _list: PhantomData,
         }
     }
 }
@@ -25,9 +72,39 @@ impl Iterator for ZList<&'static str> {
         let ptr = unsafe { czmq_sys::zlist_next(self.zlist) };
 
         if ptr == ptr::null_mut() {
+            self.cursor = 0;
             None
         } else {
+            self.cursor += 1;
             Some(unsafe { CStr::from_ptr(ptr as *const i8) }.to_str().unwrap_or(""))
         }
     }
 }
+
+impl<'a> IntoIterator for &'a ZList<&'static str> {
+    type Item = &'a str;
+    type IntoIter = ZListIter<'a>;
+
+    fn into_iter(self) -> ZListIter<'a> {
+        self.iter()
+    }
+}
+
+pub struct ZListIter<'a> {
+    items: vec::IntoIter<*mut c_void>,
+    _list: PhantomData<&'a ZList<&'static str>>,
+}
+
+impl<'a> Iterator for ZListIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.items.next().map(|item| unsafe { CStr::from_ptr(item as *const i8) }.to_str().unwrap_or(""))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for ZListIter<'a> {}
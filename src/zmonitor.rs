@@ -1,11 +1,13 @@
 //! Module: czmq-zmonitor
 
-use {czmq_sys, Error, ErrorKind, RawInterface, Result, Sockish, ZActor, ZMsg};
+use crate::{Error, ErrorKind, RawInterface, Result, SocketLike, ZActor, ZMsg};
+use crate::capability::Capability;
 use std::{error, ptr, result};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::os::raw::c_void;
+use crate::zactor::commands;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ZMonitorEvents {
     Connected,
     ConnectDelayed,
@@ -18,44 +20,66 @@ pub enum ZMonitorEvents {
     CloseFailed,
     Disconnected,
     MonitorStopped,
+    HandshakeSucceeded,
+    HandshakeFailedNoDetail,
+    HandshakeFailedProtocol,
+    HandshakeFailedAuth,
     All,
     Unknown,
 }
 
 impl ZMonitorEvents {
-    pub fn to_str<'a>(&'a self) -> &'a str {
+    pub fn to_str(&self) -> &'static str {
         match self {
-            &ZMonitorEvents::Connected      => "CONNECTED",
-            &ZMonitorEvents::ConnectDelayed => "CONNECT_DELAYED",
-            &ZMonitorEvents::ConnectRetried => "CONNECT_RETRIED",
-            &ZMonitorEvents::Listening      => "LISTENING",
-            &ZMonitorEvents::BindFailed     => "BIND_FAILED",
-            &ZMonitorEvents::Accepted       => "ACCEPTED",
-            &ZMonitorEvents::AcceptFailed   => "ACCEPT_FAILED",
-            &ZMonitorEvents::Closed         => "CLOSED",
-            &ZMonitorEvents::CloseFailed    => "CLOSE_FAILED",
-            &ZMonitorEvents::Disconnected   => "DISCONNECTED",
-            &ZMonitorEvents::MonitorStopped => "MONITOR_STOPPED",
-            &ZMonitorEvents::All            => "ALL",
-            &ZMonitorEvents::Unknown        => "UNKNOWN",
+            &ZMonitorEvents::Connected               => "CONNECTED",
+            &ZMonitorEvents::ConnectDelayed          => "CONNECT_DELAYED",
+            &ZMonitorEvents::ConnectRetried          => "CONNECT_RETRIED",
+            &ZMonitorEvents::Listening               => "LISTENING",
+            &ZMonitorEvents::BindFailed              => "BIND_FAILED",
+            &ZMonitorEvents::Accepted                => "ACCEPTED",
+            &ZMonitorEvents::AcceptFailed            => "ACCEPT_FAILED",
+            &ZMonitorEvents::Closed                  => "CLOSED",
+            &ZMonitorEvents::CloseFailed             => "CLOSE_FAILED",
+            &ZMonitorEvents::Disconnected            => "DISCONNECTED",
+            &ZMonitorEvents::MonitorStopped          => "MONITOR_STOPPED",
+            &ZMonitorEvents::HandshakeSucceeded      => "HANDSHAKE_SUCCEEDED",
+            &ZMonitorEvents::HandshakeFailedNoDetail => "HANDSHAKE_FAILED_NO_DETAIL",
+            &ZMonitorEvents::HandshakeFailedProtocol => "HANDSHAKE_FAILED_PROTOCOL",
+            &ZMonitorEvents::HandshakeFailedAuth     => "HANDSHAKE_FAILED_AUTH",
+            &ZMonitorEvents::All                     => "ALL",
+            &ZMonitorEvents::Unknown                 => "UNKNOWN",
         }
     }
 
     pub fn from_str(event: &str) -> ZMonitorEvents {
         match event {
-            "CONNECTED"       => ZMonitorEvents::Connected,
-            "CONNECT_DELAYED" => ZMonitorEvents::ConnectDelayed,
-            "CONNECT_RETRIED" => ZMonitorEvents::ConnectRetried,
-            "LISTENING"       => ZMonitorEvents::Listening,
-            "BIND_FAILED"     => ZMonitorEvents::BindFailed,
-            "ACCEPTED"        => ZMonitorEvents::Accepted,
-            "ACCEPT_FAILED"   => ZMonitorEvents::AcceptFailed,
-            "CLOSED"          => ZMonitorEvents::Closed,
-            "CLOSE_FAILED"    => ZMonitorEvents::CloseFailed,
-            "DISCONNECTED"    => ZMonitorEvents::Disconnected,
-            "MONITOR_STOPPED" => ZMonitorEvents::MonitorStopped,
-            "ALL"             => ZMonitorEvents::All,
-            _                 => ZMonitorEvents::Unknown,
+            "CONNECTED"                  => ZMonitorEvents::Connected,
+            "CONNECT_DELAYED"            => ZMonitorEvents::ConnectDelayed,
+            "CONNECT_RETRIED"            => ZMonitorEvents::ConnectRetried,
+            "LISTENING"                  => ZMonitorEvents::Listening,
+            "BIND_FAILED"                => ZMonitorEvents::BindFailed,
+            "ACCEPTED"                   => ZMonitorEvents::Accepted,
+            "ACCEPT_FAILED"              => ZMonitorEvents::AcceptFailed,
+            "CLOSED"                     => ZMonitorEvents::Closed,
+            "CLOSE_FAILED"               => ZMonitorEvents::CloseFailed,
+            "DISCONNECTED"               => ZMonitorEvents::Disconnected,
+            "MONITOR_STOPPED"            => ZMonitorEvents::MonitorStopped,
+            "HANDSHAKE_SUCCEEDED"        => ZMonitorEvents::HandshakeSucceeded,
+            "HANDSHAKE_FAILED_NO_DETAIL" => ZMonitorEvents::HandshakeFailedNoDetail,
+            "HANDSHAKE_FAILED_PROTOCOL"  => ZMonitorEvents::HandshakeFailedProtocol,
+            "HANDSHAKE_FAILED_AUTH"      => ZMonitorEvents::HandshakeFailedAuth,
+            "ALL"                        => ZMonitorEvents::All,
+            _                            => ZMonitorEvents::Unknown,
+        }
+    }
+
+    fn is_handshake(&self) -> bool {
+        match *self {
+            ZMonitorEvents::HandshakeSucceeded |
+            ZMonitorEvents::HandshakeFailedNoDetail |
+            ZMonitorEvents::HandshakeFailedProtocol |
+            ZMonitorEvents::HandshakeFailedAuth => true,
+            _ => false,
         }
     }
 }
@@ -66,6 +90,7 @@ impl Display for ZMonitorEvents {
     }
 }
 
+#[derive(Debug)]
 pub struct ZMonitor {
     zactor: ZActor,
 }
@@ -73,8 +98,18 @@ pub struct ZMonitor {
 unsafe impl Send for ZMonitor {}
 
 impl ZMonitor {
-    pub fn new<S: Sockish>(zsock: &mut S) -> Result<ZMonitor> {
-        let zactor = unsafe { czmq_sys::zactor_new(czmq_sys::zmonitor, zsock.as_mut_ptr()) };
+    /// Configure a monitor before starting it. The builder sends its
+    /// options in the order the actor expects, ending with START.
+    pub fn builder<S: SocketLike>(zsock: S) -> ZMonitorBuilder<S> {
+        ZMonitorBuilder {
+            zsock: zsock,
+            events: Vec::new(),
+            verbose: false,
+        }
+    }
+
+    pub fn new<S: SocketLike>(mut zsock: S) -> Result<ZMonitor> {
+        let zactor = unsafe { czmq_sys::zactor_new(czmq_sys::zmonitor, zsock.as_socket_ptr()) };
 
         if zactor == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZMonitorError::Instantiate))
@@ -85,18 +120,19 @@ impl ZMonitor {
         }
     }
 
+    /// Choose the events to listen for. Handshake events fail with
+    /// `ErrorKind::Unsupported` on libraries too old to report them,
+    /// as older CZMQs would silently ignore them.
     pub fn set_attrs(&self, attrs: &[ZMonitorEvents]) -> Result<()> {
-        let msg = ZMsg::new();
-        try!(msg.addstr("LISTEN"));
-        for a in attrs {
-            try!(msg.addstr(a.to_str()));
+        if attrs.iter().any(ZMonitorEvents::is_handshake) {
+            require!(Capability::MonitorHandshake);
         }
 
-        self.zactor.send(msg)
+        self.zactor.send_cmd_iter(commands::LISTEN, attrs.iter().map(ZMonitorEvents::to_str))
     }
 
     pub fn get_attr(&mut self) -> Result<result::Result<ZMonitorEvents, Vec<u8>>> {
-        let msg = try!(ZMsg::recv(&mut self.zactor));
+        let mut msg = ZMsg::recv(&mut self.zactor)?;
 
         match msg.popstr() {
             Some(result) => match result {
@@ -107,13 +143,70 @@ impl ZMonitor {
         }
     }
 
+    /// Like `get_attr()`, but also returns the endpoint the event
+    /// happened on. Events this crate doesn't know are `Unknown`.
+    pub fn get_event(&mut self) -> Result<(ZMonitorEvents, String)> {
+        let mut msg = ZMsg::recv(&mut self.zactor)?;
+
+        let event = match msg.popstr() {
+            Some(Ok(s)) => ZMonitorEvents::from_str(&s),
+            Some(Err(_)) => ZMonitorEvents::Unknown,
+            None => return Err(Error::new(ErrorKind::MissingFrame, ZMonitorError::MissingAttr)),
+        };
+        // The event's value, e.g. a file descriptor or errno
+        msg.pop();
+        let endpoint = match msg.popstr() {
+            Some(Ok(endpoint)) => endpoint,
+            _ => String::new(),
+        };
+
+        Ok((event, endpoint))
+    }
+
     pub fn start(&self) -> Result<()> {
-        try!(self.zactor.send_str("START"));
+        self.zactor.send_cmd(commands::START, &[])?;
         self.zactor.sock().wait()
     }
 
     pub fn verbose(&self) -> Result<()> {
-        self.zactor.send_str("VERBOSE")
+        self.zactor.send_cmd(commands::VERBOSE, &[])
+    }
+
+    pub(crate) fn actor(&self) -> &ZActor {
+        &self.zactor
+    }
+}
+
+pub struct ZMonitorBuilder<S: SocketLike> {
+    zsock: S,
+    events: Vec<ZMonitorEvents>,
+    verbose: bool,
+}
+
+impl<S: SocketLike> ZMonitorBuilder<S> {
+    pub fn events(mut self, events: &[ZMonitorEvents]) -> ZMonitorBuilder<S> {
+        self.events.extend(events.iter().cloned());
+        self
+    }
+
+    pub fn verbose(mut self) -> ZMonitorBuilder<S> {
+        self.verbose = true;
+        self
+    }
+
+    pub fn start(self) -> Result<ZMonitor> {
+        let monitor = ZMonitor::new(self.zsock)?;
+
+        if self.verbose {
+            monitor.verbose()?;
+        }
+
+        if !self.events.is_empty() {
+            monitor.set_attrs(&self.events)?;
+        }
+
+        monitor.start()?;
+        Ok(monitor)
     }
 }
 
@@ -144,7 +237,7 @@ impl error::Error for ZMonitorError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use {ZSock, SocketType, ZSys};
+    use crate::{ZSock, SocketType, ZSys};
 
     #[test]
     fn test_attrs() {
@@ -167,6 +260,48 @@ mod tests {
         assert_eq!(client_mon.get_attr().unwrap().unwrap(), ZMonitorEvents::Connected);
     }
 
+    #[test]
+    fn test_get_event() {
+        ZSys::init();
+
+        let mut server = ZSock::new(SocketType::PULL);
+        let mut server_mon = ZMonitor::builder(&mut server).events(&[ZMonitorEvents::Listening]).start().unwrap();
+
+        server.bind("ipc://zmonitor_test_event").unwrap();
+        assert_eq!(server_mon.get_event().unwrap(), (ZMonitorEvents::Listening, "ipc://zmonitor_test_event".into()));
+    }
+
+    #[test]
+    fn test_builder() {
+        ZSys::init();
+
+        let mut server = ZSock::new(SocketType::PULL);
+        let mut server_mon = ZMonitor::builder(&mut server)
+                                      .events(&[ZMonitorEvents::Listening])
+                                      .events(&[ZMonitorEvents::Accepted])
+                                      .verbose()
+                                      .start()
+                                      .unwrap();
+
+        server.bind("ipc://zmonitor_test_builder").unwrap();
+        assert_eq!(server_mon.get_attr().unwrap().unwrap(), ZMonitorEvents::Listening);
+    }
+
+    #[test]
+    fn test_handshake_events() {
+        ZSys::init();
+
+        let mut zsock = ZSock::new(SocketType::REP);
+        let zmonitor = ZMonitor::new(&mut zsock).unwrap();
+        let result = zmonitor.set_attrs(&[ZMonitorEvents::HandshakeSucceeded]);
+
+        if crate::capabilities().has(Capability::MonitorHandshake) {
+            result.unwrap();
+        } else {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+        }
+    }
+
     #[test]
     fn test_verbose() {
         ZSys::init();
@@ -1,13 +1,27 @@
 //! Module: czmq-zmsg
 
-use {czmq_sys, Error, ErrorKind, RawInterface, Result, Sockish, ZFrame};
-use std::{error, fmt, mem, ptr, result};
+use crate::{Error, ErrorKind, RawInterface, Result, SocketLike, ZFrame, ZFRAME_MORE, ZFRAME_REUSE};
+use crate::zframe::DebugBytes;
+use crate::error::retry_interrupted;
+use crate::history::{self, HistoryOp};
+use crate::trace;
+use std::{error, fmt, mem, ptr, result, slice, str, vec};
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::convert::TryInto;
 use std::ffi::{CStr, CString};
 
-#[derive(Debug, Eq)]
+/// The largest frame that can be encoded with `ZMsg::encode()`, as
+/// the encoding stores frame sizes in at most 4 bytes.
+pub const ZMSG_ENCODE_FRAME_MAX: usize = 0xFFFF_FFFF;
+
+#[derive(Eq)]
 pub struct ZMsg {
     zmsg: *mut czmq_sys::zmsg_t,
     owned: bool,
+    // Frames the caller has walked through with `first()` and
+    // `next()`, where 0 means the cursor is before the first frame
+    cursor: Cell<usize>,
 }
 
 unsafe impl Send for ZMsg {}
@@ -21,6 +35,24 @@ impl Drop for ZMsg {
     }
 }
 
+impl fmt::Debug for ZMsg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZMsg")
+         .field("size", &self.size())
+         .field("content_size", &unsafe { czmq_sys::zmsg_content_size(self.zmsg) })
+         .field("frames", &DebugFrames(self))
+         .finish()
+    }
+}
+
+struct DebugFrames<'a>(&'a ZMsg);
+
+impl<'a> fmt::Debug for DebugFrames<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(DebugBytes)).finish()
+    }
+}
+
 impl Iterator for ZMsg {
     type Item = ZFrame;
 
@@ -40,20 +72,45 @@ impl ZMsg {
         ZMsg {
             zmsg: unsafe { czmq_sys::zmsg_new() },
             owned: true,
+            cursor: Cell::new(0),
+        }
+    }
+
+    /// Create a message from a sequence of frame buffers. This is a
+    /// convenient way to build arbitrary messages, e.g. in property
+    /// tests.
+    pub fn from_frames<I, B>(frames: I) -> Result<ZMsg>
+        where I: IntoIterator<Item = B>,
+              B: AsRef<[u8]> {
+        let msg = ZMsg::new();
+        for frame in frames {
+            msg.addbytes(frame.as_ref())?;
         }
+        Ok(msg)
     }
 
-    pub fn recv<S: Sockish>(source: &mut S) -> Result<ZMsg> {
-        let zmsg = unsafe { czmq_sys::zmsg_recv(source.as_mut_ptr()) };
+    pub fn recv<S: SocketLike>(mut source: S) -> Result<ZMsg> {
+        let source = source.as_socket_ptr();
+        let span = trace::recv(source);
+        let zmsg = retry_interrupted(|| unsafe { czmq_sys::zmsg_recv(source) }, |p| *p == ptr::null_mut());
 
-        if zmsg == ptr::null_mut() {
-            Err(Error::new(ErrorKind::NullPtr, ZMsgError::CmdFailed))
+        let result = if zmsg == ptr::null_mut() {
+            Err(Error::from_errno(ErrorKind::NullPtr, ZMsgError::CmdFailed))
         } else {
-            Ok(ZMsg {
+            let msg = ZMsg {
                 zmsg: zmsg,
                 owned: true,
-            })
-        }
+                cursor: Cell::new(0),
+            };
+            span.record_msg(&msg);
+            Ok(msg)
+        };
+
+        history::record(source, &result, || match result {
+            Ok(ref msg) => HistoryOp::Recv { frames: msg.size(), bytes: msg.content_size() },
+            Err(_) => HistoryOp::Recv { frames: 0, bytes: 0 },
+        });
+        result
     }
 
     // XXX We'll have to roll our own here as we can't imitate a C
@@ -61,7 +118,14 @@ impl ZMsg {
     // pub fn zmsg_load(file: *mut FILE) -> *mut zmsg_t;
 
     pub fn encode(&self) -> Result<ZFrame> {
+        // The C encoder silently truncates the size of any frame that
+        // won't fit in 4 bytes, so catch that here instead.
+        if self.iter().any(|f| f.len() > ZMSG_ENCODE_FRAME_MAX) {
+            return Err(Error::new(ErrorKind::InvalidArg, ZMsgError::FrameTooLarge));
+        }
+
         let zframe = unsafe { czmq_sys::zmsg_encode(self.zmsg) };
+        self.restore_cursor();
 
         if zframe == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZMsgError::CmdFailed))
@@ -70,8 +134,17 @@ impl ZMsg {
         }
     }
 
+    /// Decode a frame created by `encode()`. Malformed input returns
+    /// an `ErrorKind::InvalidArg` error rather than being handed to
+    /// the C decoder.
     pub fn decode(frame: &mut ZFrame) -> Result<ZMsg> {
-        let zmsg = unsafe { czmq_sys::zmsg_decode(frame.as_mut_ptr()) };
+        let ptr = frame.as_mut_ptr();
+
+        if Self::encoded_frames(unsafe { frame_bytes(ptr) }).is_none() {
+            return Err(Error::new(ErrorKind::InvalidArg, ZMsgError::InvalidEncoding));
+        }
+
+        let zmsg = unsafe { czmq_sys::zmsg_decode(ptr) };
 
         if zmsg == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZMsgError::CmdFailed))
@@ -79,10 +152,138 @@ impl ZMsg {
             Ok(ZMsg {
                 zmsg: zmsg,
                 owned: true,
+                cursor: Cell::new(0),
             })
         }
     }
 
+    /// Decode a byte buffer created by `encode()`.
+    pub fn decode_bytes(bytes: &[u8]) -> Result<ZMsg> {
+        let mut frame = ZFrame::new(bytes)?;
+        Self::decode(&mut frame)
+    }
+
+    /// Count the frames in an encoded message, or return `None` if
+    /// the encoding is malformed. Never panics, whatever the input.
+    pub fn encoded_frames(bytes: &[u8]) -> Option<usize> {
+        let mut index = 0;
+        let mut frames = 0;
+
+        while index < bytes.len() {
+            let mut size = bytes[index] as usize;
+            index += 1;
+
+            if size == 0xFF {
+                if bytes.len() - index < 4 {
+                    return None;
+                }
+
+                size = ((bytes[index] as usize) << 24) |
+                       ((bytes[index + 1] as usize) << 16) |
+                       ((bytes[index + 2] as usize) << 8) |
+                         bytes[index + 3] as usize;
+                index += 4;
+            }
+
+            if bytes.len() - index < size {
+                return None;
+            }
+
+            index += size;
+            frames += 1;
+        }
+
+        Some(frames)
+    }
+
+    /// Dump the message in a stable, line based format for snapshot
+    /// tests, like:
+    ///
+    /// ```text
+    /// ZMsg: 2 frames, 7 bytes
+    /// [0] 5 bytes "HELLO"
+    ///     48454c4c4f
+    /// [1] 2 bytes
+    ///     0001
+    /// ```
+    ///
+    /// Frames that are printable UTF-8 get a preview after their size.
+    /// The hex is authoritative, so `from_canonical_str()` can rebuild
+    /// the message exactly.
+    pub fn to_canonical_string(&self) -> String {
+        use std::fmt::Write;
+
+        let mut dump = format!("ZMsg: {} frames, {} bytes\n", self.size(), self.content_size());
+        for (index, frame) in self.iter().enumerate() {
+            write!(dump, "[{}] {} bytes", index, frame.len()).unwrap();
+
+            match str::from_utf8(frame) {
+                Ok(s) if !s.is_empty() && !s.chars().any(char::is_control) => {
+                    let preview: String = s.chars().take(CANONICAL_PREVIEW_MAX).collect();
+                    write!(dump, " {:?}", preview).unwrap();
+                    if preview.len() < s.len() {
+                        dump.push_str("...");
+                    }
+                },
+                _ => (),
+            }
+            dump.push('\n');
+
+            for chunk in frame.chunks(CANONICAL_HEX_WIDTH) {
+                dump.push_str("    ");
+                for b in chunk {
+                    write!(dump, "{:02x}", b).unwrap();
+                }
+                dump.push('\n');
+            }
+        }
+
+        dump
+    }
+
+    /// Rebuild a message from the output of `to_canonical_string()`.
+    /// Blank lines and trailing whitespace are ignored, so dumps can be
+    /// pasted into snapshot files as they are.
+    pub fn from_canonical_str(dump: &str) -> Result<ZMsg> {
+        let invalid = |line: usize| Error::new(ErrorKind::InvalidArg, ZMsgError::InvalidDump(line + 1));
+        let mut lines = dump.lines()
+            .map(str::trim_end)
+            .enumerate()
+            .filter(|&(_, l)| !l.is_empty())
+            .peekable();
+
+        let (header_line, header) = lines.next().ok_or_else(|| invalid(0))?;
+        let (frame_count, byte_count) = parse_canonical_header(header).ok_or_else(|| invalid(header_line))?;
+
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        while let Some((n, line)) = lines.next() {
+            let (index, size) = parse_canonical_frame(line).ok_or_else(|| invalid(n))?;
+            if index != frames.len() {
+                return Err(invalid(n));
+            }
+
+            let mut frame = Vec::with_capacity(size);
+            while let Some(&(n, line)) = lines.peek() {
+                if !line.starts_with(' ') {
+                    break;
+                }
+                parse_hex(line.trim_start(), &mut frame).ok_or_else(|| invalid(n))?;
+                lines.next();
+            }
+
+            if frame.len() != size {
+                return Err(invalid(n));
+            }
+            frames.push(frame);
+        }
+
+        if frames.len() != frame_count || frames.iter().map(Vec::len).sum::<usize>() != byte_count {
+            return Err(invalid(header_line));
+        }
+
+        ZMsg::from_frames(&frames)
+    }
+
     pub fn new_signal(status: u8) -> Result<ZMsg> {
         let zmsg = unsafe { czmq_sys::zmsg_new_signal(status) };
 
@@ -92,19 +293,87 @@ impl ZMsg {
             Ok(ZMsg {
                 zmsg: zmsg,
                 owned: true,
+                cursor: Cell::new(0),
             })
         }
     }
 
-    pub fn send<D: Sockish>(self, dest: &mut D) -> Result<()> {
+    // zmsg_send() destroys the message even if sending fails, so
+    // unlike the other blocking calls this is never retried on EINTR.
+    pub fn send<D: SocketLike>(self, mut dest: D) -> Result<()> {
         let mut zmsg = self;
+        let dest = dest.as_socket_ptr();
+        let _span = trace::send(dest, &zmsg);
+        let (frames, bytes) = (zmsg.size(), zmsg.content_size());
 
-        let rc = unsafe { czmq_sys::zmsg_send(&mut zmsg.zmsg, dest.as_mut_ptr()) };
-        if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZMsgError::CmdFailed))
+        let rc = unsafe { czmq_sys::zmsg_send(&mut zmsg.zmsg, dest) };
+        let result = if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZMsgError::CmdFailed))
         } else {
             Ok(())
+        };
+
+        history::record(dest, &result, || HistoryOp::Send { frames: frames, bytes: bytes });
+        result
+    }
+
+    /// Send a batch of messages without consuming them. Each message
+    /// is copied and handed to CZMQ whole, so it costs the same few
+    /// FFI calls however many frames it has, and like `send()` its
+    /// frames go out together. Stops at the first message that fails.
+    pub fn send_batch<D: SocketLike>(msgs: &[ZMsg], mut dest: D) -> Result<()> {
+        for msg in msgs {
+            msg.dup()?.send(&mut dest)?;
+        }
+
+        Ok(())
+    }
+
+    // Send the message's frames with the REUSE flag, leaving the
+    // message intact, for callers that want to avoid the copy that
+    // `send_batch()` makes.
+    pub(crate) fn send_reused<D: SocketLike>(&self, mut dest: D) -> Result<()> {
+        let dest = dest.as_socket_ptr();
+        let (frames, bytes) = (self.size(), self.content_size());
+        let mut frame = unsafe { czmq_sys::zmsg_first(self.zmsg) };
+        let mut result = Ok(());
+
+        while frame != ptr::null_mut() {
+            let next = unsafe { czmq_sys::zmsg_next(self.zmsg) };
+            let flags = if next == ptr::null_mut() {
+                ZFRAME_REUSE
+            } else {
+                ZFRAME_REUSE | ZFRAME_MORE
+            };
+
+            let rc = retry_interrupted(|| unsafe { czmq_sys::zframe_send(&mut frame, dest, flags.bits()) }, |rc| *rc == -1);
+            if rc == -1 {
+                result = Err(Error::from_errno(ErrorKind::NonZero, ZMsgError::CmdFailed));
+                break;
+            }
+
+            frame = next;
+        }
+
+        history::record(dest, &result, || HistoryOp::Send { frames: frames, bytes: bytes });
+        result
+    }
+
+    /// Receive up to `max` messages. Blocks until the first message
+    /// arrives, then drains whatever else is already queued without
+    /// waiting.
+    pub fn recv_batch<S: SocketLike>(mut source: S, max: usize) -> Result<Vec<ZMsg>> {
+        let mut msgs = Vec::new();
+
+        while msgs.len() < max {
+            if !msgs.is_empty() && unsafe { czmq_sys::zsock_events(source.as_socket_ptr()) } & POLLIN == 0 {
+                break;
+            }
+
+            msgs.push(ZMsg::recv(&mut source)?);
         }
+
+        Ok(msgs)
     }
 
     // pub fn zmsg_sendm(self_p: *mut *mut zmsg_t,
@@ -115,10 +384,14 @@ impl ZMsg {
         unsafe { czmq_sys::zmsg_size(self.zmsg) as usize }
     }
 
-    // pub fn zmsg_content_size(_self: *mut zmsg_t) -> size_t;
+    /// The total size of all frames, in bytes.
+    pub fn content_size(&self) -> usize {
+        unsafe { czmq_sys::zmsg_content_size(self.zmsg) as usize }
+    }
 
     pub fn prepend(&self, frame: ZFrame) -> Result<()> {
         let rc = unsafe { czmq_sys::zmsg_prepend(self.zmsg, &mut frame.into_raw()) };
+        self.cursor.set(0);
 
         if rc == 0 {
             Ok(())
@@ -129,6 +402,7 @@ impl ZMsg {
 
     pub fn append(&self, frame: ZFrame) -> Result<()> {
         let rc = unsafe { czmq_sys::zmsg_append(self.zmsg, &mut frame.into_raw()) };
+        self.cursor.set(0);
 
         if rc == 0 {
             Ok(())
@@ -137,8 +411,9 @@ impl ZMsg {
         }
     }
 
-    pub fn pop(&self) -> Option<ZFrame> {
+    pub fn pop(&mut self) -> Option<ZFrame> {
         let ptr = unsafe { czmq_sys::zmsg_pop(self.zmsg) };
+        self.cursor.set(0);
 
         if ptr == ptr::null_mut() {
             None
@@ -147,6 +422,35 @@ impl ZMsg {
         }
     }
 
+    /// Remove the routing envelope from the front of the message:
+    /// every frame up to the first empty delimiter frame, which is
+    /// removed too. Returns `None`, and leaves the message as it was,
+    /// if there's no delimiter.
+    pub fn pop_envelope(&mut self) -> Result<Option<Vec<Vec<u8>>>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = self.pop() {
+            if frame.size() == 0 {
+                return Ok(Some(frames.iter().map(|f| f.as_bytes().to_vec()).collect()));
+            }
+            frames.push(frame);
+        }
+
+        for frame in frames.into_iter().rev() {
+            self.prepend(frame)?;
+        }
+        Ok(None)
+    }
+
+    /// Add a routing envelope and its empty delimiter frame to the
+    /// front of the message, as `pop_envelope()` returns them.
+    pub fn push_envelope(&self, envelope: &[Vec<u8>]) -> Result<()> {
+        self.pushbytes(&[])?;
+        for frame in envelope.iter().rev() {
+            self.pushbytes(frame)?;
+        }
+        Ok(())
+    }
+
     // pub fn zmsg_pushmem(_self: *mut zmsg_t,
     //                     src: *const ::std::os::raw::c_void, size: size_t)
     //  -> ::std::os::raw::c_int;
@@ -156,6 +460,7 @@ impl ZMsg {
     pub fn pushstr(&self, string: &str) -> Result<()> {
         let string_c = CString::new(string).unwrap_or(CString::new("").unwrap());
         let rc = unsafe { czmq_sys::zmsg_pushstr(self.zmsg, string_c.as_ptr()) };
+        self.cursor.set(0);
 
         // Deliberately leak this memory, which will be managed by C
         mem::forget(string_c);
@@ -170,6 +475,7 @@ impl ZMsg {
     pub fn addstr(&self, string: &str) -> Result<()> {
         let string_c = CString::new(string).unwrap_or(CString::new("").unwrap());
         let rc = unsafe { czmq_sys::zmsg_addstr(self.zmsg, string_c.as_ptr()) };
+        self.cursor.set(0);
 
         // Deliberately leak this memory, which will be managed by C
         mem::forget(string_c);
@@ -188,8 +494,9 @@ impl ZMsg {
     //                     format: *const ::std::os::raw::c_char, ...)
     //  -> ::std::os::raw::c_int;
 
-    pub fn popstr(&self) -> Option<result::Result<String, Vec<u8>>> {
+    pub fn popstr(&mut self) -> Option<result::Result<String, Vec<u8>>> {
         let ptr = unsafe { czmq_sys::zmsg_popstr(self.zmsg) };
+        self.cursor.set(0);
 
         if ptr == ptr::null_mut() {
             None
@@ -204,22 +511,22 @@ impl ZMsg {
     }
 
     pub fn pushbytes(&self, bytes: &[u8]) -> Result<()> {
-        let zframe = try!(ZFrame::new(bytes));
-        try!(self.prepend(zframe));
+        let zframe = ZFrame::new(bytes)?;
+        self.prepend(zframe)?;
         Ok(())
     }
 
     pub fn addbytes(&self, bytes: &[u8]) -> Result<()> {
-        let zframe = try!(ZFrame::new(bytes));
-        try!(self.append(zframe));
+        let zframe = ZFrame::new(bytes)?;
+        self.append(zframe)?;
         Ok(())
     }
 
-    pub fn popbytes(&self) -> Result<Option<Vec<u8>>> {
+    pub fn popbytes(&mut self) -> Result<Option<Vec<u8>>> {
         let frame = self.pop();
 
         if frame.is_some() {
-            Ok(Some(match try!(frame.unwrap().data()) {
+            Ok(Some(match frame.unwrap().data()? {
                 Ok(s) => s.into_bytes(),
                 Err(b) => b
             }))
@@ -230,6 +537,7 @@ impl ZMsg {
 
     pub fn addmsg(&self, other: ZMsg) -> Result<()> {
         let rc = unsafe { czmq_sys::zmsg_addmsg(self.zmsg, &mut other.into_raw()) };
+        self.cursor.set(0);
 
         if rc == -1 {
             Err(Error::new(ErrorKind::NonZero, ZMsgError::CmdFailed))
@@ -238,8 +546,9 @@ impl ZMsg {
         }
     }
 
-    pub fn popmsg(&self) -> Option<ZMsg> {
+    pub fn popmsg(&mut self) -> Option<ZMsg> {
         let ptr = unsafe { czmq_sys::zmsg_popmsg(self.zmsg) };
+        self.cursor.set(0);
 
         if ptr == ptr::null_mut() {
             None
@@ -248,14 +557,79 @@ impl ZMsg {
         }
     }
 
-    pub fn remove(&self, frame: &mut ZFrame) {
+    /// Pop the first frame, failing with `ErrorKind::InvalidArg` if it
+    /// isn't `expected`. This and the other `pop_*()` accessors fail
+    /// with `ErrorKind::MissingFrame` if the message is empty, so a
+    /// parser can take a message apart one `?` at a time:
+    ///
+    /// ```rust,ignore
+    /// msg.expect_str("HELLO")?;
+    /// let sequence = msg.pop_u32()?;
+    /// let name = msg.pop_str()?;
+    /// ```
+    pub fn expect_str(&mut self, expected: &str) -> Result<()> {
+        self.expect_bytes(expected.as_bytes())
+    }
+
+    pub fn expect_bytes(&mut self, expected: &[u8]) -> Result<()> {
+        let frame = self.pop_required()?;
+        if frame.as_bytes() == expected {
+            Ok(())
+        } else {
+            let (expected, got) = (format!("{:?}", DebugBytes(expected)), format!("{:?}", DebugBytes(frame.as_bytes())));
+            Err(Error::new(ErrorKind::InvalidArg, ZMsgError::UnexpectedFrame(expected, got)))
+        }
+    }
+
+    /// Pop the first frame as UTF-8, failing with
+    /// `ErrorKind::StringConversion` if it isn't.
+    pub fn pop_str(&mut self) -> Result<String> {
+        let frame = self.pop_required()?;
+        String::from_utf8(frame.as_bytes().to_vec()).map_err(|e| Error::new(ErrorKind::StringConversion, e))
+    }
+
+    /// Pop the first frame as a one byte number. See `ZFrame::to_u8()`.
+    pub fn pop_u8(&mut self) -> Result<u8> {
+        self.pop_required()?.to_u8()
+    }
+
+    /// Pop the first frame as a 2 byte number in network byte order.
+    pub fn pop_u16(&mut self) -> Result<u16> {
+        self.pop_required()?.to_u16()
+    }
+
+    /// Pop the first frame as a 4 byte number in network byte order.
+    pub fn pop_u32(&mut self) -> Result<u32> {
+        self.pop_required()?.to_u32()
+    }
+
+    /// Pop the first frame as an 8 byte number in network byte order.
+    pub fn pop_u64(&mut self) -> Result<u64> {
+        self.pop_required()?.to_u64()
+    }
+
+    /// Pop the first frame as the 16 bytes of a binary UUID, as sent by
+    /// zproto and `zuuid_data()`.
+    pub fn pop_uuid(&mut self) -> Result<[u8; 16]> {
+        Ok(self.pop_required()?.sized(16)?.try_into().unwrap())
+    }
+
+    fn pop_required(&mut self) -> Result<ZFrame> {
+        self.pop().ok_or_else(|| Error::new(ErrorKind::MissingFrame, ZMsgError::MissingFrame))
+    }
+
+    /// Remove a frame from the message, resetting the frame cursor.
+    pub fn remove(&mut self, frame: &mut ZFrame) {
         unsafe { czmq_sys::zmsg_remove(self.zmsg, frame.as_mut_ptr()) };
+        self.cursor.set(0);
+        self.restore_cursor();
     }
 
     // pub fn zmsg_remove(_self: *mut zmsg_t, frame: *mut zframe_t);
 
     pub fn first(&self) -> Option<ZFrame> {
         let ptr = unsafe { czmq_sys::zmsg_first(self.zmsg) };
+        self.cursor.set(if ptr == ptr::null_mut() { 0 } else { 1 });
 
         if ptr == ptr::null_mut() {
             None
@@ -266,6 +640,7 @@ impl ZMsg {
 
     pub fn next(&self) -> Option<ZFrame> {
         let ptr = unsafe { czmq_sys::zmsg_next(self.zmsg) };
+        self.cursor.set(if ptr == ptr::null_mut() { 0 } else { self.cursor.get() + 1 });
 
         if ptr == ptr::null_mut() {
             None
@@ -274,10 +649,56 @@ impl ZMsg {
         }
     }
 
+    /// Visit each frame's contents in order without copying them out
+    /// of the message.
+    pub fn for_each_frame<F: FnMut(&[u8])>(&self, f: F) {
+        self.iter().for_each(f);
+    }
+
+    /// Iterate over each frame's contents, borrowed from the message.
+    /// The frames are listed up front, so iterators can be nested and
+    /// the cursor used by `first()` and `next()` is left alone.
+    pub fn iter(&self) -> ZMsgFrames {
+        let mut frames = Vec::with_capacity(self.size());
+        let mut frame = unsafe { czmq_sys::zmsg_first(self.zmsg) };
+        while frame != ptr::null_mut() {
+            frames.push(frame);
+            frame = unsafe { czmq_sys::zmsg_next(self.zmsg) };
+        }
+        self.restore_cursor();
+
+        ZMsgFrames {
+            frames: frames.into_iter(),
+            _msg: PhantomData,
+        }
+    }
+
+    // CZMQ has no way to walk the frames without moving the cursor,
+    // so put it back where `first()` and `next()` left it.
+    fn restore_cursor(&self) {
+        unsafe {
+            match self.cursor.get() {
+                // Stepping past the last frame leaves the cursor
+                // before the first one again
+                0 => {
+                    czmq_sys::zmsg_last(self.zmsg);
+                    czmq_sys::zmsg_next(self.zmsg);
+                },
+                n => {
+                    czmq_sys::zmsg_first(self.zmsg);
+                    for _ in 1..n {
+                        czmq_sys::zmsg_next(self.zmsg);
+                    }
+                },
+            }
+        }
+    }
+
     // We can't call this fn last() as it conflicts with
     // Iterator::last(), which is implemented for ZMsg.
     pub fn ref_last(&self) -> Option<ZFrame> {
         let ptr = unsafe { czmq_sys::zmsg_last(self.zmsg) };
+        self.cursor.set(if ptr == ptr::null_mut() { 0 } else { self.size() });
 
         if ptr == ptr::null_mut() {
             None
@@ -291,6 +712,7 @@ impl ZMsg {
 
     pub fn dup(&self) -> Result<ZMsg> {
         let ptr = unsafe { czmq_sys::zmsg_dup(self.zmsg) };
+        self.restore_cursor();
 
         if ptr == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZMsgError::CmdFailed))
@@ -301,14 +723,19 @@ impl ZMsg {
 
     pub fn print(&self) {
         unsafe { czmq_sys::zmsg_print(self.zmsg) };
+        self.restore_cursor();
     }
 
     pub fn eq(&self, other: &ZMsg) -> bool {
-        unsafe { czmq_sys::zmsg_eq(self.zmsg, other.zmsg) == 1 }
+        let eq = unsafe { czmq_sys::zmsg_eq(self.zmsg, other.zmsg) == 1 };
+        self.restore_cursor();
+        other.restore_cursor();
+        eq
     }
 
     pub fn signal(&self) -> Result<u8> {
         let signal = unsafe { czmq_sys::zmsg_signal(self.zmsg) };
+        self.restore_cursor();
         if signal == -1 {
             Err(Error::new(ErrorKind::NonZero, ZMsgError::CmdFailed))
         } else {
@@ -317,11 +744,49 @@ impl ZMsg {
     }
 }
 
+// Bytes per line of hex in a canonical dump
+const CANONICAL_HEX_WIDTH: usize = 32;
+// Characters of a frame shown in a canonical dump's preview
+const CANONICAL_PREVIEW_MAX: usize = 40;
+
+// "ZMsg: 2 frames, 7 bytes"
+fn parse_canonical_header(line: &str) -> Option<(usize, usize)> {
+    let rest = line.strip_prefix("ZMsg: ")?;
+    let (frames, bytes) = rest.split_once(" frames, ")?;
+    Some((frames.parse().ok()?, bytes.strip_suffix(" bytes")?.parse().ok()?))
+}
+
+// "[0] 5 bytes", followed by an optional preview
+fn parse_canonical_frame(line: &str) -> Option<(usize, usize)> {
+    let (index, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    let mut words = rest.split(' ');
+    let size = words.next()?.parse().ok()?;
+    if words.next()? != "bytes" {
+        return None;
+    }
+    Some((index.parse().ok()?, size))
+}
+
+fn parse_hex(hex: &str, bytes: &mut Vec<u8>) -> Option<()> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    for i in (0..hex.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&hex[i..i + 2], 16).ok()?);
+    }
+    Some(())
+}
+
+// ZMQ_POLLIN bit of the ZMQ_EVENTS socket option
+const POLLIN: i32 = 1;
+
 impl RawInterface<czmq_sys::zmsg_t> for ZMsg {
     unsafe fn from_raw(ptr: *mut czmq_sys::zmsg_t, owned: bool) -> ZMsg {
         ZMsg {
             zmsg: ptr,
             owned: owned,
+            cursor: Cell::new(0),
         }
     }
 
@@ -335,15 +800,71 @@ impl RawInterface<czmq_sys::zmsg_t> for ZMsg {
     }
 }
 
+impl<'a> IntoIterator for &'a ZMsg {
+    type Item = &'a [u8];
+    type IntoIter = ZMsgFrames<'a>;
+
+    fn into_iter(self) -> ZMsgFrames<'a> {
+        self.iter()
+    }
+}
+
+// Frames are only removed through `&mut ZMsg`, so the listed frames
+// outlive the borrow.
+pub struct ZMsgFrames<'a> {
+    frames: vec::IntoIter<*mut czmq_sys::zframe_t>,
+    _msg: PhantomData<&'a ZMsg>,
+}
+
+unsafe impl<'a> Send for ZMsgFrames<'a> {}
+unsafe impl<'a> Sync for ZMsgFrames<'a> {}
+
+impl<'a> Iterator for ZMsgFrames<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        self.frames.next().map(|frame| unsafe { frame_bytes(frame) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.frames.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for ZMsgFrames<'a> {}
+
+// Borrow a frame's contents. The caller must not let the slice
+// outlive the frame.
+unsafe fn frame_bytes<'a>(frame: *mut czmq_sys::zframe_t) -> &'a [u8] {
+    let size = czmq_sys::zframe_size(frame) as usize;
+    let data = czmq_sys::zframe_data(frame);
+
+    if size == 0 || data == ptr::null_mut() {
+        &[]
+    } else {
+        slice::from_raw_parts(data, size)
+    }
+}
+
 #[derive(Debug)]
 pub enum ZMsgError {
     CmdFailed,
+    FrameTooLarge,
+    InvalidDump(usize),
+    InvalidEncoding,
+    MissingFrame,
+    UnexpectedFrame(String, String),
 }
 
 impl fmt::Display for ZMsgError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ZMsgError::CmdFailed => write!(f, "ZMsg command failed"),
+            ZMsgError::FrameTooLarge => write!(f, "Frame is too large to encode"),
+            ZMsgError::InvalidDump(line) => write!(f, "Canonical dump is malformed at line {}", line),
+            ZMsgError::InvalidEncoding => write!(f, "Encoded message is malformed"),
+            ZMsgError::MissingFrame => write!(f, "Message has no more frames"),
+            ZMsgError::UnexpectedFrame(ref expected, ref got) => write!(f, "Expected frame {} but got {}", expected, got),
         }
     }
 }
@@ -352,6 +873,11 @@ impl error::Error for ZMsgError {
     fn description(&self) -> &str {
         match *self {
             ZMsgError::CmdFailed => "ZMsg command failed",
+            ZMsgError::FrameTooLarge => "Frame is too large to encode",
+            ZMsgError::InvalidDump(_) => "Canonical dump is malformed",
+            ZMsgError::InvalidEncoding => "Encoded message is malformed",
+            ZMsgError::MissingFrame => "Message has no more frames",
+            ZMsgError::UnexpectedFrame(..) => "Unexpected frame",
         }
     }
 }
@@ -359,7 +885,7 @@ impl error::Error for ZMsgError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use {zmq, ZCert, ZFrame, ZSock, ZSys};
+    use crate::{ErrorKind, ZCert, ZFrame, ZSock, ZSys};
 
     #[test]
     fn test_sendrecv_zmq() {
@@ -375,7 +901,7 @@ mod tests {
         zmsg.addstr("Hello world!").unwrap();
         zmsg.send(&mut client).unwrap();
 
-        let zmsg_recv = ZMsg::recv(&mut server).unwrap();
+        let mut zmsg_recv = ZMsg::recv(&mut server).unwrap();
         assert_eq!(zmsg_recv.popstr().unwrap().unwrap(), "Hello world!");
     }
 
@@ -390,7 +916,22 @@ mod tests {
         zmsg.addstr("Hello world!").unwrap();
         zmsg.send(&mut client).unwrap();
 
-        let msg = ZMsg::recv(&mut server).unwrap();
+        let mut msg = ZMsg::recv(&mut server).unwrap();
+        assert_eq!(msg.popstr().unwrap().unwrap(), "Hello world!");
+    }
+
+    #[test]
+    fn test_sendrecv_shared_ref() {
+        ZSys::init();
+
+        let server = ZSock::new_rep("inproc://zmsg_sendrecv_shared_ref").unwrap();
+        let client = ZSock::new_req("inproc://zmsg_sendrecv_shared_ref").unwrap();
+
+        let zmsg = ZMsg::new();
+        zmsg.addstr("Hello world!").unwrap();
+        zmsg.send(&client).unwrap();
+
+        let mut msg = ZMsg::recv(&server).unwrap();
         assert_eq!(msg.popstr().unwrap().unwrap(), "Hello world!");
     }
 
@@ -398,10 +939,114 @@ mod tests {
     fn test_encode_decode() {
         let msg = ZMsg::new();
         msg.addstr("moo").unwrap();
-        let msg_decoded = ZMsg::decode(&mut msg.encode().unwrap()).unwrap();
+        let mut msg_decoded = ZMsg::decode(&mut msg.encode().unwrap()).unwrap();
         assert_eq!(msg_decoded.popstr().unwrap().unwrap(), "moo");
     }
 
+    #[test]
+    fn test_from_frames() {
+        let mut msg = ZMsg::from_frames(&["moo", "cow"]).unwrap();
+        assert_eq!(msg.size(), 2);
+        assert_eq!(msg.popstr().unwrap().unwrap(), "moo");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "cow");
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut msg = ZMsg::from_frames(&["body"]).unwrap();
+        msg.push_envelope(&[b"client".to_vec(), b"broker".to_vec()]).unwrap();
+        assert_eq!(msg.size(), 4);
+
+        let envelope = msg.pop_envelope().unwrap().unwrap();
+        assert_eq!(envelope, vec![b"client".to_vec(), b"broker".to_vec()]);
+        assert_eq!(msg.size(), 1);
+
+        // Without a delimiter, nothing is removed
+        assert!(msg.pop_envelope().unwrap().is_none());
+        assert_eq!(msg.popstr().unwrap().unwrap(), "body");
+        assert!(msg.pop_envelope().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert_eq!(ZMsg::encoded_frames(&[]), Some(0));
+        assert_eq!(ZMsg::encoded_frames(&[3, b'm', b'o', b'o', 0]), Some(2));
+        assert_eq!(ZMsg::encoded_frames(&[4, b'm', b'o', b'o']), None);
+        assert_eq!(ZMsg::encoded_frames(&[0xFF, 0, 0]), None);
+        assert_eq!(ZMsg::encoded_frames(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), None);
+
+        let err = ZMsg::decode_bytes(&[4, b'm', b'o', b'o']).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidArg);
+
+        let msg = ZMsg::from_frames(&[vec![1u8; 300], vec![]]).unwrap();
+        let encoded = msg.encode().unwrap();
+        let bytes = match encoded.data().unwrap() {
+            Ok(s) => s.into_bytes(),
+            Err(b) => b,
+        };
+        assert_eq!(ZMsg::encoded_frames(&bytes), Some(2));
+        assert_eq!(ZMsg::decode_bytes(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let msg = ZMsg::from_frames(&[&b"HELLO"[..], b"", b"\x00\x01"]).unwrap();
+        assert_eq!(msg.to_canonical_string(), "ZMsg: 3 frames, 7 bytes\n\
+                                               [0] 5 bytes \"HELLO\"\n    48454c4c4f\n\
+                                               [1] 0 bytes\n\
+                                               [2] 2 bytes\n    0001\n");
+
+        let msg = ZMsg::from_frames(&[vec![b'x'; 100], (0..=255).collect()]).unwrap();
+        let dump = msg.to_canonical_string();
+        assert!(dump.contains(&format!("[0] 100 bytes \"{}\"...\n", "x".repeat(40))));
+        assert_eq!(ZMsg::from_canonical_str(&dump).unwrap(), msg);
+
+        // Snapshot files are often reindented or gain a trailing newline
+        let pasted = "\nZMsg: 1 frames, 3 bytes  \n[0] 3 bytes \"moo\"\n      6d6f6f\n\n";
+        assert_eq!(ZMsg::from_canonical_str(pasted).unwrap().popstr().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_canonical_malformed() {
+        let invalid = |dump: &str| ZMsg::from_canonical_str(dump).unwrap_err().kind();
+        assert_eq!(invalid(""), ErrorKind::InvalidArg);
+        assert_eq!(invalid("ZMsg: 1 frames, 3 bytes\n[0] 3 bytes\n    6d6f\n"), ErrorKind::InvalidArg);
+        assert_eq!(invalid("ZMsg: 1 frames, 3 bytes\n[0] 3 bytes\n    6d6f6g\n"), ErrorKind::InvalidArg);
+        assert_eq!(invalid("ZMsg: 2 frames, 3 bytes\n[0] 3 bytes\n    6d6f6f\n"), ErrorKind::InvalidArg);
+        assert_eq!(invalid("ZMsg: 1 frames, 3 bytes\n[1] 3 bytes\n    6d6f6f\n"), ErrorKind::InvalidArg);
+
+        let err = ZMsg::from_canonical_str("ZMsg: 1 frames, 0 bytes\n\n[0] zero bytes\n").unwrap_err();
+        assert!(err.to_string().ends_with("Canonical dump is malformed at line 3"));
+    }
+
+    #[test]
+    fn test_batch() {
+        ZSys::init();
+
+        let mut server = ZSock::new_pull("inproc://zmsg_test_batch").unwrap();
+        let mut client = ZSock::new_push("inproc://zmsg_test_batch").unwrap();
+
+        let msgs = vec![
+            ZMsg::from_frames(&["1", "a"]).unwrap(),
+            ZMsg::from_frames(&["2", "b"]).unwrap(),
+            ZMsg::from_frames(&["3", "c"]).unwrap(),
+        ];
+        ZMsg::send_batch(&msgs, &mut client).unwrap();
+        ZMsg::send_batch(&msgs[..1], &mut client).unwrap();
+
+        let batch = ZMsg::recv_batch(&mut server, 2).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0], msgs[0]);
+        assert_eq!(batch[1], msgs[1]);
+
+        let batch = ZMsg::recv_batch(&mut server, 10).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0], msgs[2]);
+        assert_eq!(batch[1], msgs[0]);
+
+        assert!(ZMsg::recv_batch(&mut server, 0).unwrap().is_empty());
+    }
+
     #[test]
     fn test_signal() {
         let msg = ZMsg::new_signal(97).unwrap();
@@ -415,30 +1060,36 @@ mod tests {
         assert_eq!(msg.size(), 1);
     }
 
+    #[test]
+    fn test_content_size() {
+        let msg = ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap();
+        assert_eq!(msg.content_size(), 6);
+    }
+
     #[test]
     fn test_prepend() {
-        let msg = ZMsg::new();
+        let mut msg = ZMsg::new();
         msg.prepend(ZFrame::from("123").unwrap()).unwrap();
         assert_eq!(msg.popstr().unwrap().unwrap(), "123");
     }
 
     #[test]
     fn test_append() {
-        let msg = ZMsg::new();
+        let mut msg = ZMsg::new();
         msg.append(ZFrame::from("123").unwrap()).unwrap();
         assert_eq!(msg.popstr().unwrap().unwrap(), "123");
     }
 
     #[test]
     fn test_pop() {
-        let msg = ZMsg::new();
+        let mut msg = ZMsg::new();
         msg.addstr("123").unwrap();
         assert_eq!(msg.pop().unwrap().data().unwrap().unwrap(), "123");
     }
 
     #[test]
     fn test_push_add_popstr() {
-        let msg = ZMsg::new();
+        let mut msg = ZMsg::new();
         msg.addstr("456").unwrap();
         msg.pushstr("123").unwrap();
         assert_eq!(msg.popstr().unwrap().unwrap(), "123");
@@ -450,7 +1101,7 @@ mod tests {
         cert.set_meta("key", "value");
         let encoded = cert.encode_meta();
 
-        let msg = ZMsg::new();
+        let mut msg = ZMsg::new();
         msg.addbytes("123".as_bytes()).unwrap();
         msg.pushbytes(&encoded).unwrap();
         assert_eq!(msg.popbytes().unwrap().unwrap(), encoded);
@@ -461,7 +1112,7 @@ mod tests {
         let child_msg = ZMsg::new();
         child_msg.addstr("test").unwrap();
 
-        let parent_msg = ZMsg::new();
+        let mut parent_msg = ZMsg::new();
         parent_msg.addmsg(child_msg).unwrap();
         assert_eq!(parent_msg.popmsg().unwrap().popstr().unwrap().unwrap(), "test");
     }
@@ -469,7 +1120,7 @@ mod tests {
     #[test]
     fn test_remove() {
         let frame = ZFrame::from("baa").unwrap();
-        let msg = ZMsg::new();
+        let mut msg = ZMsg::new();
         msg.append(frame).unwrap();
         msg.remove(&mut msg.next().unwrap());
     }
@@ -491,6 +1142,50 @@ mod tests {
         assert_eq!(frame.data().unwrap().unwrap(), "3");
     }
 
+    #[test]
+    fn test_for_each_frame() {
+        let msg = ZMsg::from_frames(&["1", "", "3"]).unwrap();
+
+        let mut frames = Vec::new();
+        msg.for_each_frame(|f| frames.push(f.to_vec()));
+        assert_eq!(frames, vec![b"1".to_vec(), Vec::new(), b"3".to_vec()]);
+        assert_eq!(msg.size(), 3);
+    }
+
+    #[test]
+    fn test_iter_borrowed() {
+        let msg = ZMsg::from_frames(&["1", "2", "3"]).unwrap();
+
+        let frames: Vec<&[u8]> = msg.iter().collect();
+        assert_eq!(frames, vec![b"1", b"2", b"3"]);
+
+        let mut count = 0;
+        for _ in &msg {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_iter_cursor() {
+        let msg = ZMsg::from_frames(&["1", "2", "3"]).unwrap();
+
+        // Iterators are independent of each other...
+        let pairs = msg.iter().flat_map(|a| msg.iter().map(move |b| (a, b))).count();
+        assert_eq!(pairs, 9);
+
+        // ...and of the cursor used by first() and next()
+        assert_eq!(msg.first().unwrap().as_bytes(), b"1");
+        assert_eq!(msg.iter().count(), 3);
+        msg.to_canonical_string();
+        assert_eq!(msg.next().unwrap().as_bytes(), b"2");
+        assert!(msg.dup().unwrap().eq(&msg));
+        assert_eq!(msg.next().unwrap().as_bytes(), b"3");
+        assert!(msg.next().is_none());
+        assert_eq!(msg.iter().count(), 3);
+        assert_eq!(msg.next().unwrap().as_bytes(), b"1");
+    }
+
     #[test]
     fn test_iter() {
         let msg = ZMsg::new();
@@ -529,4 +1224,31 @@ mod tests {
 
         assert_eq!(one, two);
     }
+
+    #[test]
+    fn test_pop_typed() {
+        let mut msg = ZMsg::new();
+        msg.addstr("HELLO").unwrap();
+        msg.addbytes(&[7]).unwrap();
+        msg.addbytes(&[1, 2]).unwrap();
+        msg.addbytes(&[0, 0, 1, 0]).unwrap();
+        msg.addbytes(&[0, 0, 0, 0, 0, 0, 0, 42]).unwrap();
+        msg.addbytes(&[9; 16]).unwrap();
+        msg.addstr("moo").unwrap();
+        msg.addbytes(&[0xff]).unwrap();
+        msg.addbytes(&[1, 2, 3]).unwrap();
+        msg.addstr("BYE").unwrap();
+
+        msg.expect_str("HELLO").unwrap();
+        assert_eq!(msg.pop_u8().unwrap(), 7);
+        assert_eq!(msg.pop_u16().unwrap(), 0x0102);
+        assert_eq!(msg.pop_u32().unwrap(), 256);
+        assert_eq!(msg.pop_u64().unwrap(), 42);
+        assert_eq!(msg.pop_uuid().unwrap(), [9; 16]);
+        assert_eq!(msg.pop_str().unwrap(), "moo");
+        assert_eq!(msg.pop_str().unwrap_err().kind(), ErrorKind::StringConversion);
+        assert_eq!(msg.pop_u32().unwrap_err().kind(), ErrorKind::InvalidArg);
+        assert_eq!(msg.expect_str("HELLO").unwrap_err().kind(), ErrorKind::InvalidArg);
+        assert_eq!(msg.pop_u8().unwrap_err().kind(), ErrorKind::MissingFrame);
+    }
 }
@@ -1,8 +1,9 @@
 //! Module: czmq-zpoller
 
-use {czmq_sys, Error, ErrorKind, Result, Sockish};
-use std::{error, fmt, ptr};
+use crate::{Error, ErrorKind, Result, Sockish, SocketLike};
+use std::{cmp, error, fmt, ptr};
 use std::os::raw::c_int;
+use std::time::Duration;
 
 pub struct ZPoller {
     zpoller: *mut czmq_sys::zpoller_t,
@@ -31,8 +32,8 @@ impl ZPoller {
         })
     }
 
-    pub fn add<S: Sockish>(&mut self, reader: &mut S) -> Result<()> {
-        let rc = unsafe { czmq_sys::zpoller_add(self.zpoller, reader.as_mut_ptr()) };
+    pub fn add<S: SocketLike>(&mut self, mut reader: S) -> Result<()> {
+        let rc = unsafe { czmq_sys::zpoller_add(self.zpoller, reader.as_socket_ptr()) };
 
         if rc == -1 {
             Err(Error::new(ErrorKind::NonZero, ZPollerError::CmdFailed))
@@ -41,8 +42,8 @@ impl ZPoller {
         }
     }
 
-    pub fn remove<S: Sockish>(&mut self, reader: &mut S) -> Result<()> {
-        let rc = unsafe { czmq_sys::zpoller_remove(self.zpoller, reader.as_mut_ptr()) };
+    pub fn remove<S: SocketLike>(&mut self, mut reader: S) -> Result<()> {
+        let rc = unsafe { czmq_sys::zpoller_remove(self.zpoller, reader.as_socket_ptr()) };
 
         if rc == -1 {
             Err(Error::new(ErrorKind::NonZero, ZPollerError::CmdFailed))
@@ -66,6 +67,21 @@ impl ZPoller {
         }
     }
 
+    /// Wait up to `timeout` for a reader, returning `None` if the
+    /// timeout expires. A `timeout` of `None` waits forever. Unlike
+    /// `wait()`, an interrupt or terminated context is an
+    /// `ErrorKind::Interrupted` error, so polling loops can be stopped
+    /// with a signal.
+    pub fn wait_timeout<S: Sockish>(&self, timeout: Option<Duration>) -> Result<Option<S>> {
+        let millis = timeout.map(|t| cmp::min(t.as_millis(), c_int::max_value() as u128) as u32);
+
+        match self.wait(millis) {
+            Some(reader) => Ok(Some(reader)),
+            None if self.terminated() => Err(Error::new(ErrorKind::Interrupted, ZPollerError::Interrupted)),
+            None => Ok(None),
+        }
+    }
+
     pub fn expired(&self) -> bool {
         unsafe { czmq_sys::zpoller_expired(self.zpoller) == 1 }
     }
@@ -83,6 +99,7 @@ impl ZPoller {
 pub enum ZPollerError {
     CmdFailed,
     Instantiate,
+    Interrupted,
 }
 
 impl fmt::Display for ZPollerError {
@@ -90,6 +107,7 @@ impl fmt::Display for ZPollerError {
         match *self {
             ZPollerError::CmdFailed => write!(f, "ZPoller command failed"),
             ZPollerError::Instantiate => write!(f, "Could not instantiate new ZPoller struct"),
+            ZPollerError::Interrupted => write!(f, "Interrupted while waiting for a reader"),
         }
     }
 }
@@ -99,13 +117,14 @@ impl error::Error for ZPollerError {
         match *self {
             ZPollerError::CmdFailed => "ZPoller command failed",
             ZPollerError::Instantiate => "Could not instantiate new ZPoller struct",
+            ZPollerError::Interrupted => "Interrupted while waiting for a reader",
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use {ZSock, SocketType, ZSys};
+    use crate::{ZSock, SocketType, ZSys};
     use super::*;
 
     #[test]
@@ -152,4 +171,27 @@ mod tests {
         assert!(poller.expired());
         assert!(!poller.terminated());
     }
+
+    #[test]
+    fn test_wait_timeout() {
+        ZSys::init();
+
+        let mut server = ZSock::new_rep("inproc://zpoller_test_wait_timeout").unwrap();
+        let client = ZSock::new_req("inproc://zpoller_test_wait_timeout").unwrap();
+
+        let mut poller = ZPoller::new().unwrap();
+        poller.add(&mut server).unwrap();
+
+        let sock: Option<ZSock> = poller.wait_timeout(Some(Duration::from_millis(0))).unwrap();
+        assert!(sock.is_none());
+
+        client.send_str("moo").unwrap();
+        let sock: Option<ZSock> = poller.wait_timeout(Some(Duration::from_millis(500))).unwrap();
+        assert!(sock.is_some());
+        // Too long for a c_int, but must still not block forever
+        let sock: Option<ZSock> = poller.wait_timeout(Some(Duration::from_secs(u64::max_value()))).unwrap();
+        assert!(sock.is_some());
+        let sock: Option<ZSock> = poller.wait_timeout(None).unwrap();
+        assert!(sock.is_some());
+    }
 }
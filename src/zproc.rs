@@ -0,0 +1,98 @@
+//! Module: czmq-zproc
+//!
+//! Process-wide settings from CZMQ's draft `zproc` class. Only
+//! compiled with the `draft` feature, as the symbols are missing from
+//! CZMQ builds without drafts.
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+pub struct ZProc;
+
+impl ZProc {
+    /// The CZMQ version linked against, as `major * 10000 + minor *
+    /// 100 + patch`.
+    pub fn czmq_version() -> i32 {
+        unsafe { czmq_sys::zproc_czmq_version() }
+    }
+
+    pub fn interrupted() -> bool {
+        unsafe { czmq_sys::zproc_interrupted() == 1 }
+    }
+
+    pub fn has_curve() -> bool {
+        unsafe { czmq_sys::zproc_has_curve() == 1 }
+    }
+
+    pub fn hostname() -> Option<String> {
+        let mut ptr = unsafe { czmq_sys::zproc_hostname() };
+
+        if ptr == ptr::null_mut() {
+            None
+        } else {
+            let hostname = unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned();
+            unsafe { czmq_sys::zstr_free(&mut ptr) };
+            Some(hostname)
+        }
+    }
+
+    /// Set the number of IO threads. Must be called before any
+    /// sockets are created.
+    pub fn set_io_threads(io_threads: usize) {
+        unsafe { czmq_sys::zproc_set_io_threads(io_threads as u64) };
+    }
+
+    /// Set the maximum number of sockets. Must be called before any
+    /// sockets are created.
+    pub fn set_max_sockets(max_sockets: usize) {
+        unsafe { czmq_sys::zproc_set_max_sockets(max_sockets as u64) };
+    }
+
+    /// Set the network interface used for broadcasts, like `zbeacon`.
+    pub fn set_biface(biface: &str) {
+        let biface_c = CString::new(biface).unwrap_or(CString::new("").unwrap());
+        unsafe { czmq_sys::zproc_set_biface(biface_c.as_ptr()) };
+    }
+
+    pub fn biface() -> Option<String> {
+        let biface = unsafe { czmq_sys::zproc_biface() };
+
+        if biface == ptr::null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(biface) }.to_string_lossy().into_owned())
+        }
+    }
+
+    pub fn set_log_ident(ident: &str) {
+        let ident_c = CString::new(ident).unwrap_or(CString::new("").unwrap());
+        unsafe { czmq_sys::zproc_set_log_ident(ident_c.as_ptr()) };
+    }
+
+    /// Publish log messages on a PUB socket at `endpoint`, as well as
+    /// logging them locally.
+    pub fn set_log_sender(endpoint: &str) {
+        let endpoint_c = CString::new(endpoint).unwrap_or(CString::new("").unwrap());
+        unsafe { czmq_sys::zproc_set_log_sender(endpoint_c.as_ptr()) };
+    }
+
+    /// Log to syslog rather than stdout.
+    pub fn set_log_system(log_system: bool) {
+        unsafe { czmq_sys::zproc_set_log_system(if log_system { 1 } else { 0 }) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ZSys;
+
+    #[test]
+    fn test_zproc() {
+        ZSys::init();
+
+        assert!(ZProc::czmq_version() >= 40000);
+        assert!(!ZProc::interrupted());
+        assert!(ZProc::hostname().is_some());
+    }
+}
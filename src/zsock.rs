@@ -1,11 +1,70 @@
 //! Module: czmq-zsock
 
-use {czmq_sys, Error, ErrorKind, RawInterface, Result, Sockish, ZMonitor};
-use std::{error, fmt, mem, ptr, result};
+use crate::{Error, ErrorKind, Keepalive, RawInterface, ReconnectPolicy, Result, Sockish, SocketLike, ToEndpoint, ZMonitor, ZMsg, ZSys};
+use crate::capability::Capability;
+use crate::endpoint::check_transport;
+use crate::error::retry_interrupted;
+use crate::history::{self, HistoryEvent, HistoryOp, LastError};
+use crate::leaks;
+use crate::recvbuffer::recv_frame_into;
+use crate::waiter::{self, Blocking, Waiter};
+#[cfg(feature = "serde")]
+use crate::serde_zmsg;
+#[cfg(feature = "draft")]
+use crate::ZFrame;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::{cmp, error, fmt, mem, ptr, result, slice};
+#[cfg(feature = "draft")]
+use std::convert::TryInto;
+use std::io::IoSlice;
 use std::ffi::{CStr, CString};
-use std::os::raw::c_void;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use zmq::{Mechanism, SocketType};
 
+/// How `ZSock::send_vectored()` maps buffers onto frames.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VectoredMode {
+    /// Send each buffer as its own frame of a multipart message.
+    Multipart,
+    /// Send all buffers as a single frame.
+    Coalesced,
+}
+
+/// What `ZSock::close_graceful()` had to throw away.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CloseReport {
+    /// Messages that had arrived but were never received.
+    pub unread: usize,
+    /// Messages still queued for sending when the timeout ran out,
+    /// or `None` without the `draft` feature.
+    pub unsent: Option<u64>,
+}
+
+/// How a GSSAPI principal name is interpreted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GssapiNameType {
+    /// A host-based service name, like `zmq@example.com`. The default.
+    Hostbased = 0,
+    /// A local user name.
+    UserName = 1,
+    /// A Kerberos principal, like `zmq/example.com@EXAMPLE.COM`.
+    Krb5Principal = 2,
+}
+
+impl GssapiNameType {
+    fn from_raw(nametype: c_int) -> Result<GssapiNameType> {
+        match nametype {
+            0 => Ok(GssapiNameType::Hostbased),
+            1 => Ok(GssapiNameType::UserName),
+            2 => Ok(GssapiNameType::Krb5Principal),
+            _ => Err(Error::new(ErrorKind::InvalidArg, ZSockError::UnknownNameType(nametype))),
+        }
+    }
+}
+
 pub struct ZSock {
     zsock: *mut czmq_sys::zsock_t,
     owned: bool,
@@ -14,11 +73,27 @@ pub struct ZSock {
 impl Drop for ZSock {
     fn drop(&mut self) {
         if self.owned {
+            history::forget(self.zsock as *mut c_void);
+            leaks::destroyed(self.zsock as *mut c_void);
             unsafe { czmq_sys::zsock_destroy(&mut self.zsock) };
         }
     }
 }
 
+impl fmt::Debug for ZSock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ZSock")
+         .field("type", &self.type_str().ok().and_then(|t| t.ok()))
+         .field("endpoint", &self.endpoint().ok())
+         .field("sndhwm", &self.sndhwm().ok())
+         .field("rcvhwm", &self.rcvhwm().ok())
+         .field("linger", &self.linger().ok())
+         .field("sndtimeo", &self.sndtimeo())
+         .field("rcvtimeo", &self.rcvtimeo())
+         .finish()
+    }
+}
+
 impl PartialEq for ZSock {
     fn eq(&self, other: &ZSock) -> bool {
         self.zsock == other.zsock
@@ -29,9 +104,31 @@ unsafe impl Send for ZSock {}
 
 impl ZSock {
     pub fn new(sock_type: SocketType) -> ZSock {
-        ZSock {
-            zsock: unsafe { czmq_sys::zsock_new(sock_type as i32) },
+        ZSock::owned(unsafe { czmq_sys::zsock_new(sock_type as i32) }, "")
+    }
+
+    // Take ownership of a new socket, created with `endpoints`
+    fn owned(zsock: *mut czmq_sys::zsock_t, endpoints: &str) -> ZSock {
+        let sock = ZSock {
+            zsock: zsock,
             owned: true,
+        };
+        // A new socket may reuse the address of one destroyed outside
+        // this crate, whose history would otherwise carry over
+        history::forget(zsock as *mut c_void);
+        leaks::created(zsock as *mut c_void, || sock.leak_name());
+        // Only endpoints marked `@` are sure to be bound; the others
+        // may be connections to another process's socket
+        for endpoint in endpoints.split(',').filter_map(|e| e.trim().strip_prefix('@')) {
+            leaks::bound(zsock as *mut c_void, endpoint);
+        }
+        sock
+    }
+
+    fn leak_name(&self) -> String {
+        match self.type_str() {
+            Ok(Ok(t)) => format!("ZSock({})", t),
+            _ => "ZSock".to_string(),
         }
     }
 
@@ -41,16 +138,13 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
     pub fn new_sub(endpoint: &str, subscribe: Option<&str>) -> Result<ZSock> {
         let subscribe_ptr = match subscribe {
-            Some(s) => try!(CString::new(s)).into_raw(),
+            Some(s) => CString::new(s)?.into_raw(),
             None => ptr::null_mut(),
         };
 
@@ -63,10 +157,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -76,10 +167,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -89,10 +177,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -102,10 +187,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -115,10 +197,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -128,10 +207,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -141,10 +217,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -154,10 +227,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -167,10 +237,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -180,10 +247,7 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
@@ -193,20 +257,107 @@ impl ZSock {
         if zsock == ptr::null_mut() {
             Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
         } else {
-            Ok(ZSock {
-                zsock: zsock,
-                owned: true,
-            })
+            Ok(ZSock::owned(zsock, endpoint))
         }
     }
 
-    pub fn bind(&self, endpoint: &str) -> Result<i32> {
-        let rc = unsafe { czmq_sys::zsock_bind(self.zsock, "%s\0".as_ptr() as *const i8, try!(CString::new(endpoint)).as_ptr() as *const i8) };
-        if rc == -1 {
+    /// Create a SERVER socket, which binds by default and addresses
+    /// replies by their frames' routing ids. This and the other draft
+    /// socket types need the `draft` feature, and fail with
+    /// `ErrorKind::Unsupported` if libzmq was built without drafts.
+    #[cfg(feature = "draft")]
+    pub fn new_server(endpoint: &str) -> Result<ZSock> {
+        require!(Capability::Draft);
+        let zsock = unsafe { czmq_sys::zsock_new_server(CString::new(endpoint).unwrap().as_ptr()) };
+
+        if zsock == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
+        } else {
+            Ok(ZSock::owned(zsock, endpoint))
+        }
+    }
+
+    /// Create a CLIENT socket, which connects by default.
+    #[cfg(feature = "draft")]
+    pub fn new_client(endpoint: &str) -> Result<ZSock> {
+        require!(Capability::Draft);
+        let zsock = unsafe { czmq_sys::zsock_new_client(CString::new(endpoint).unwrap().as_ptr()) };
+
+        if zsock == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
+        } else {
+            Ok(ZSock::owned(zsock, endpoint))
+        }
+    }
+
+    /// Create a RADIO socket, which connects by default and publishes
+    /// to the group set with `ZFrame::set_group()`.
+    #[cfg(feature = "draft")]
+    pub fn new_radio(endpoint: &str) -> Result<ZSock> {
+        require!(Capability::Draft);
+        let zsock = unsafe { czmq_sys::zsock_new_radio(CString::new(endpoint).unwrap().as_ptr()) };
+
+        if zsock == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
+        } else {
+            Ok(ZSock::owned(zsock, endpoint))
+        }
+    }
+
+    /// Create a DISH socket, which binds by default and receives the
+    /// groups it `join()`s.
+    #[cfg(feature = "draft")]
+    pub fn new_dish(endpoint: &str) -> Result<ZSock> {
+        require!(Capability::Draft);
+        let zsock = unsafe { czmq_sys::zsock_new_dish(CString::new(endpoint).unwrap().as_ptr()) };
+
+        if zsock == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
+        } else {
+            Ok(ZSock::owned(zsock, endpoint))
+        }
+    }
+
+    /// Create a SCATTER socket, which connects by default.
+    #[cfg(feature = "draft")]
+    pub fn new_scatter(endpoint: &str) -> Result<ZSock> {
+        require!(Capability::Draft);
+        let zsock = unsafe { czmq_sys::zsock_new_scatter(CString::new(endpoint).unwrap().as_ptr()) };
+
+        if zsock == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
+        } else {
+            Ok(ZSock::owned(zsock, endpoint))
+        }
+    }
+
+    /// Create a GATHER socket, which binds by default.
+    #[cfg(feature = "draft")]
+    pub fn new_gather(endpoint: &str) -> Result<ZSock> {
+        require!(Capability::Draft);
+        let zsock = unsafe { czmq_sys::zsock_new_gather(CString::new(endpoint).unwrap().as_ptr()) };
+
+        if zsock == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CreateSock))
+        } else {
+            Ok(ZSock::owned(zsock, endpoint))
+        }
+    }
+
+    pub fn bind<E: ToEndpoint + ?Sized>(&self, endpoint: &E) -> Result<i32> {
+        let endpoint = endpoint.to_endpoint();
+        check_transport(&endpoint)?;
+        let endpoint_c = CString::new(endpoint.as_bytes())?;
+        let rc = unsafe { czmq_sys::zsock_bind(self.zsock, "%s\0".as_ptr() as *const i8, endpoint_c.as_ptr()) };
+        let result = if rc == -1 {
             Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
+            leaks::bound(self.zsock as *mut c_void, &endpoint);
             Ok(rc)
-        }
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Bind(endpoint.to_string()));
+        result
     }
 
     pub fn endpoint<'a>(&'a self) -> Result<&'a str> {
@@ -215,39 +366,116 @@ impl ZSock {
         if endpoint_c == ptr::null() {
             Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
-            let s = try!(unsafe { CStr::from_ptr(endpoint_c) }.to_str());
+            let s = unsafe { CStr::from_ptr(endpoint_c) }.to_str()?;
             Ok(s)
         }
     }
 
-    pub fn unbind(&self, endpoint: &str) -> Result<()> {
-        let rc = unsafe { czmq_sys::zsock_unbind(self.zsock, "%s\0".as_ptr() as *const i8, endpoint.as_ptr() as *const i8) };
-        if rc == -1 {
+    pub fn unbind<E: ToEndpoint + ?Sized>(&self, endpoint: &E) -> Result<()> {
+        let endpoint = endpoint.to_endpoint();
+        let endpoint_c = CString::new(endpoint.as_bytes())?;
+        let rc = unsafe { czmq_sys::zsock_unbind(self.zsock, "%s\0".as_ptr() as *const i8, endpoint_c.as_ptr()) };
+        let result = if rc == -1 {
             Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
             Ok(())
-        }
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Unbind(endpoint.to_string()));
+        result
     }
 
-    pub fn connect(&self, endpoint: &str) -> Result<()> {
-        let rc = unsafe { czmq_sys::zsock_connect(self.zsock, "%s\0".as_ptr() as *const i8, try!(CString::new(endpoint)).as_ptr() as *const i8) };
-        if rc == -1 {
+    pub fn connect<E: ToEndpoint + ?Sized>(&self, endpoint: &E) -> Result<()> {
+        let endpoint = endpoint.to_endpoint();
+        check_transport(&endpoint)?;
+        let endpoint_c = CString::new(endpoint.as_bytes())?;
+        let rc = unsafe { czmq_sys::zsock_connect(self.zsock, "%s\0".as_ptr() as *const i8, endpoint_c.as_ptr()) };
+        let result = if rc == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(())
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Connect(endpoint.to_string()));
+        result
+    }
+
+    pub fn disconnect<E: ToEndpoint + ?Sized>(&self, endpoint: &E) -> Result<()> {
+        let endpoint = endpoint.to_endpoint();
+        let endpoint_c = CString::new(endpoint.as_bytes())?;
+        let rc = unsafe { czmq_sys::zsock_disconnect(self.zsock, "%s\0".as_ptr() as *const i8, endpoint_c.as_ptr()) };
+        let result = if rc == -1 {
             Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
             Ok(())
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Disconnect(endpoint.to_string()));
+        result
+    }
+
+    /// Join a group, on a DISH socket.
+    #[cfg(feature = "draft")]
+    pub fn join(&self, group: &str) -> Result<()> {
+        let group_c = CString::new(group)?;
+        let rc = unsafe { czmq_sys::zsock_join(self.zsock as *mut c_void, group_c.as_ptr()) };
+        if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(())
         }
     }
 
-    pub fn disconnect(&self, endpoint: &str) -> Result<()> {
-        let rc = unsafe { czmq_sys::zsock_disconnect(self.zsock, "%s\0".as_ptr() as *const i8, endpoint.as_ptr() as *const i8) };
+    #[cfg(feature = "draft")]
+    pub fn leave(&self, group: &str) -> Result<()> {
+        let group_c = CString::new(group)?;
+        let rc = unsafe { czmq_sys::zsock_leave(self.zsock as *mut c_void, group_c.as_ptr()) };
         if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
             Ok(())
         }
     }
 
-    pub fn attach(&self, endpoints: &[&str], serverish: bool) -> Result<()> {
+    /// The routing id of the last message received on a SERVER
+    /// socket, which CZMQ uses to address `send_str()` and friends.
+    #[cfg(feature = "draft")]
+    pub fn routing_id(&self) -> u32 {
+        unsafe { czmq_sys::zsock_routing_id(self.zsock) }
+    }
+
+    /// Address the next message sent on a SERVER socket.
+    #[cfg(feature = "draft")]
+    pub fn set_routing_id(&self, routing_id: u32) {
+        unsafe { czmq_sys::zsock_set_routing_id(self.zsock, routing_id) };
+    }
+
+    /// Keep a log of the last `capacity` operations on this socket,
+    /// for `debug_history()`. A capacity of 0 turns the log off. The
+    /// log lasts until the socket is destroyed.
+    pub fn set_history(&self, capacity: usize) {
+        history::set_capacity(self.zsock as *mut c_void, capacity);
+    }
+
+    /// The operations logged since `set_history()` was called, oldest
+    /// first.
+    pub fn debug_history(&self) -> Vec<HistoryEvent> {
+        history::events(self.zsock as *mut c_void)
+    }
+
+    /// The last operation on this socket that failed, if any, from any
+    /// handle to it. Unlike `debug_history()`, this needs no setup.
+    pub fn last_error(&self) -> Option<LastError> {
+        history::last_error(self.zsock as *mut c_void)
+    }
+
+    /// Forget the last error, so `last_error()` only reports failures
+    /// from here on.
+    pub fn clear_last_error(&self) {
+        history::clear_last_error(self.zsock as *mut c_void);
+    }
+
+    pub fn attach<E: ToEndpoint>(&self, endpoints: &[E], serverish: bool) -> Result<()> {
         let endpoints_c = CString::new(Self::concat_endpoints(endpoints)).unwrap_or(CString::new("").unwrap());
 
         let rc = unsafe { czmq_sys::zsock_attach(self.zsock, endpoints_c.as_ptr(), if serverish { 1 } else { 0 }) };
@@ -277,23 +505,29 @@ impl ZSock {
     pub fn send_str(&self, data: &str) -> Result<()> {
         let data_c = CString::new(data).unwrap_or(CString::new("").unwrap());
 
-        let rc = unsafe { czmq_sys::zsock_send(self.zsock as *mut c_void, "s\0".as_ptr() as *const i8, data_c.as_ptr()) };
-        if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        let rc = retry_interrupted(|| unsafe { czmq_sys::zsock_send(self.zsock as *mut c_void, "s\0".as_ptr() as *const i8, data_c.as_ptr()) }, |rc| *rc == -1);
+        let result = if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
             Ok(())
-        }
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Send { frames: 1, bytes: data_c.as_bytes().len() });
+        result
     }
 
     pub fn recv_str(&self) -> Result<result::Result<String, Vec<u8>>> {
         let mut ptr = ptr::null();
 
-        let rc = unsafe { czmq_sys::zsock_recv(self.zsock as *mut c_void, "s\0".as_ptr() as *const i8, &mut ptr) };
+        let rc = retry_interrupted(|| unsafe { czmq_sys::zsock_recv(self.zsock as *mut c_void, "s\0".as_ptr() as *const i8, &mut ptr) }, |rc| *rc == -1);
         if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+            let result = Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed));
+            history::record(self.zsock as *mut c_void, &result, || HistoryOp::Recv { frames: 0, bytes: 0 });
+            result
         } else {
             let c_str = unsafe { CStr::from_ptr(ptr) };
             let bytes = c_str.to_bytes();
+            history::record(self.zsock as *mut c_void, &Ok(()), || HistoryOp::Recv { frames: 1, bytes: bytes.len() });
 
             match String::from_utf8(bytes.to_vec()) {
                 Ok(s) => Ok(Ok(s)),
@@ -302,6 +536,280 @@ impl ZSock {
         }
     }
 
+    /// Receive a single frame into `buf`, reusing its allocation.
+    /// Returns the frame size; use `rcvmore()` to check whether more
+    /// frames follow.
+    pub fn recv_into(&self, buf: &mut Vec<u8>) -> Result<usize> {
+        let handle = unsafe { czmq_sys::zsock_resolve(self.zsock as *mut c_void) };
+        let result = recv_frame_into(handle, buf).map(|_| buf.len());
+        history::record(self.zsock as *mut c_void, &result, || match result {
+            Ok(bytes) => HistoryOp::Recv { frames: 1, bytes: bytes },
+            Err(_) => HistoryOp::Recv { frames: 0, bytes: 0 },
+        });
+        result
+    }
+
+    /// Send a single frame without copying `data`. The buffer is
+    /// handed to libzmq and dropped once it has been sent.
+    pub fn send_zero_copy(&self, data: Vec<u8>) -> Result<()> {
+        let ptr = data.as_ptr() as *mut c_void;
+        let len = data.len();
+        let hint = Box::into_raw(Box::new(data)) as *mut c_void;
+        let result = unsafe { self.send_owned(ptr, len, free_vec, hint) };
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Send { frames: 1, bytes: len });
+        result
+    }
+
+    /// Send a single frame that shares `data` with the caller. The
+    /// reference held by libzmq is released once it has been sent.
+    pub fn send_arc(&self, data: Arc<[u8]>) -> Result<()> {
+        let ptr = data.as_ptr() as *mut c_void;
+        let len = data.len();
+        let hint = Box::into_raw(Box::new(data)) as *mut c_void;
+        let result = unsafe { self.send_owned(ptr, len, free_arc, hint) };
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Send { frames: 1, bytes: len });
+        result
+    }
+
+    /// Send several buffers as one message without concatenating them
+    /// first, e.g. to keep a protocol's header and body separate.
+    /// Each buffer is copied once, straight into the outgoing frame.
+    ///
+    /// Every frame is built before the first is sent, so running out
+    /// of memory can't leave part of a multipart message queued. Once
+    /// libzmq accepts the first frame it accepts the rest, unless the
+    /// context is terminated, in which case the frames already queued
+    /// are discarded rather than delivered.
+    pub fn send_vectored(&self, bufs: &[IoSlice], mode: VectoredMode) -> Result<()> {
+        if bufs.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidArg, ZSockError::NoBuffers));
+        }
+
+        let result = match mode {
+            VectoredMode::Multipart => {
+                let frames: Vec<_> = bufs.iter().map(slice::from_ref).collect();
+                unsafe { self.send_copied(&frames) }
+            },
+            VectoredMode::Coalesced => unsafe { self.send_copied(&[bufs]) },
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Send {
+            frames: if mode == VectoredMode::Multipart { bufs.len() } else { 1 },
+            bytes: bufs.iter().map(|b| b.len()).sum(),
+        });
+        result
+    }
+
+    // Copy each of `frames` back to back into its own zmq_msg_t, then
+    // send them as one message. The messages are initialised in place
+    // and never moved, as libzmq requires.
+    unsafe fn send_copied(&self, frames: &[&[IoSlice]]) -> Result<()> {
+        let mut msgs: Vec<czmq_sys::zmq_msg_t> = frames.iter().map(|_| czmq_sys::zmq_msg_t::default()).collect();
+
+        for (i, bufs) in frames.iter().enumerate() {
+            let len = bufs.iter().fold(0, |len, b| len + b.len());
+            if czmq_sys::zmq_msg_init_size(&mut msgs[i], len as u64) == -1 {
+                let err = Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed);
+                for msg in &mut msgs[..i] {
+                    czmq_sys::zmq_msg_close(msg);
+                }
+                return Err(err);
+            }
+
+            let data = czmq_sys::zmq_msg_data(&mut msgs[i]) as *mut u8;
+            let mut offset = 0;
+            for buf in bufs.iter() {
+                ptr::copy_nonoverlapping(buf.as_ptr(), data.offset(offset as isize), buf.len());
+                offset += buf.len();
+            }
+        }
+
+        let handle = czmq_sys::zsock_resolve(self.zsock as *mut c_void);
+        for i in 0..msgs.len() {
+            let flags = if i + 1 < msgs.len() { SNDMORE } else { 0 };
+            let rc = retry_interrupted(|| czmq_sys::zmq_msg_send(&mut msgs[i], handle, flags), |rc| *rc == -1);
+            if rc == -1 {
+                let err = Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed);
+                for msg in &mut msgs[i..] {
+                    czmq_sys::zmq_msg_close(msg);
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Wrap a buffer owned by `hint` in a zmq_msg_t and send it.
+    // `free` is responsible for releasing `hint`, which libzmq will
+    // call exactly once, whether or not the send succeeds.
+    unsafe fn send_owned(&self, data: *mut c_void, len: usize, free: FreeFn, hint: *mut c_void) -> Result<()> {
+        let mut msg = czmq_sys::zmq_msg_t::default();
+        let rc = czmq_sys::zmq_msg_init_data(&mut msg, data, len as u64, free as *mut czmq_sys::zmq_free_fn, hint);
+        if rc == -1 {
+            free(data, hint);
+            return Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed));
+        }
+
+        let handle = czmq_sys::zsock_resolve(self.zsock as *mut c_void);
+        let rc = retry_interrupted(|| czmq_sys::zmq_msg_send(&mut msg, handle, 0), |rc| *rc == -1);
+        if rc == -1 {
+            let err = Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed);
+            czmq_sys::zmq_msg_close(&mut msg);
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether a message can be received without blocking.
+    pub fn readable(&self) -> bool {
+        let events = unsafe { czmq_sys::zsock_events(self.zsock as *mut c_void) };
+        events & POLLIN != 0
+    }
+
+    /// Whether a message can be sent without blocking, i.e. the socket
+    /// has a peer and hasn't reached its high-water mark. Note that
+    /// PUB sockets always report as writable, as they drop messages
+    /// instead of blocking, unless `ZMQ_XPUB_NODROP` is set.
+    pub fn writable(&self) -> bool {
+        let events = unsafe { czmq_sys::zsock_events(self.zsock as *mut c_void) };
+        events & POLLOUT != 0
+    }
+
+    /// Wait for the socket to become writable, then send `msg`. This
+    /// lets producers apply backpressure when the high-water mark is
+    /// hit, rather than losing messages. A `timeout` of `None` waits
+    /// forever; otherwise an `ErrorKind::Timeout` error is returned
+    /// (and `msg` dropped) if the socket isn't writable in time.
+    pub fn send_or_block_until_writable(&mut self, msg: ZMsg, timeout: Option<Duration>) -> Result<()> {
+        let mut blocking = Blocking::register(self)?;
+
+        if waiter::block_on(waiter::wait_for(&mut blocking, self, ZSock::writable, timeout))? {
+            msg.send(self)
+        } else {
+            Err(Error::new(ErrorKind::Timeout, ZSockError::NotWritable))
+        }
+    }
+
+    /// Close the socket without losing what has already been sent,
+    /// e.g. for a rolling restart. Taking `self` means nothing more
+    /// can be sent. Everything shares a single `timeout`: the socket
+    /// waits to be writable, i.e. connected and below its high-water
+    /// mark, and then for its send queue to drain. Whatever is still
+    /// queued when `timeout` runs out is dropped.
+    ///
+    /// With the `draft` feature, libzmq reports how many messages are
+    /// queued on each connection, so `CloseReport::unsent` counts the
+    /// outgoing messages dropped. Without it libzmq can't say, so the
+    /// socket lingers for what is left of `timeout` to let the queue
+    /// drain in the background, and `unsent` is `None`.
+    pub fn close_graceful(mut self, timeout: Duration) -> Result<CloseReport> {
+        let deadline = Instant::now() + timeout;
+
+        let mut unread = 0;
+        while self.readable() {
+            ZMsg::recv(&self)?;
+            unread += 1;
+        }
+
+        // Receive-only sockets would never become writable
+        match self.zsock_type() {
+            SocketType::PULL | SocketType::SUB => (),
+            _ => {
+                let mut blocking = Blocking::register(&self)?;
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                waiter::block_on(waiter::wait_for(&mut blocking, &mut self, ZSock::writable, Some(remaining)))?;
+            },
+        }
+
+        #[cfg(feature = "draft")]
+        let unsent = Some(self.drain_until(deadline)?);
+        #[cfg(not(feature = "draft"))]
+        let unsent = None;
+
+        self.set_linger_duration(Some(deadline.saturating_duration_since(Instant::now())));
+        Ok(CloseReport {
+            unread: unread,
+            unsent: unsent,
+        })
+    }
+
+    // Wait until nothing is queued for sending or `deadline` passes,
+    // returning how many messages are still queued. libzmq reports
+    // each connection's queue to a socket monitor on request.
+    #[cfg(feature = "draft")]
+    fn drain_until(&self, deadline: Instant) -> Result<u64> {
+        let handle = unsafe { czmq_sys::zsock_resolve(self.zsock as *mut c_void) };
+        let endpoint = format!("inproc://zsock-pipes-stats-{:p}", self.zsock);
+        let endpoint_c = CString::new(endpoint.as_str()).unwrap();
+
+        let rc = unsafe { czmq_sys::zmq_socket_monitor_versioned(handle, endpoint_c.as_ptr(), ZMQ_EVENT_PIPES_STATS, 2, ZMQ_PAIR) };
+        if rc == -1 {
+            return Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed));
+        }
+
+        let result = ZSock::new_pair(&format!(">{}", endpoint)).and_then(|monitor| loop {
+            if unsafe { czmq_sys::zmq_socket_monitor_pipes_stats(handle) } == -1 {
+                break Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed));
+            }
+
+            // Each connection answers separately, so collect answers
+            // until none arrive for a moment
+            let mut queued = 0;
+            monitor.set_rcvtimeo(Some(PIPES_STATS_WAIT));
+            while let Ok(mut event) = ZMsg::recv(&monitor) {
+                queued += pipes_stats_outbound(&mut event).unwrap_or(0);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if queued == 0 || remaining == Duration::from_millis(0) {
+                break Ok(queued);
+            }
+            std::thread::sleep(cmp::min(remaining, Duration::from_millis(PIPES_STATS_WAIT as u64)));
+        });
+
+        unsafe { czmq_sys::zmq_socket_monitor(handle, ptr::null(), 0) };
+        result
+    }
+
+    /// Serialize `value` with `serde_zmsg` and send it as a single
+    /// message.
+    #[cfg(feature = "serde")]
+    pub fn send_serde<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        serde_zmsg::to_msg(value)?.send(self)
+    }
+
+    /// Receive a message and deserialize it with `serde_zmsg`.
+    #[cfg(feature = "serde")]
+    pub fn recv_serde<T: DeserializeOwned>(&self) -> Result<T> {
+        let msg = ZMsg::recv(self)?;
+        serde_zmsg::from_msg(&msg)
+    }
+
+    /// Encode `value` as MessagePack and send it as a single frame.
+    /// Structs are encoded as maps, so fields can be added or
+    /// reordered without breaking older receivers.
+    #[cfg(feature = "rmp")]
+    pub fn send_msgpack<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        let buf = rmp_serde::to_vec_named(value).map_err(|e| Error::new(ErrorKind::InvalidArg, e))?;
+        let msg = ZMsg::new();
+        msg.addbytes(&buf)?;
+        msg.send(self)
+    }
+
+    /// Receive a single frame message and decode it from MessagePack.
+    #[cfg(feature = "rmp")]
+    pub fn recv_msgpack<T: DeserializeOwned>(&self) -> Result<T> {
+        let mut msg = ZMsg::recv(self)?;
+        if msg.size() != 1 {
+            return Err(Error::new(ErrorKind::InvalidArg, ZSockError::FrameCount(msg.size())));
+        }
+
+        let frame = msg.pop().unwrap();
+        rmp_serde::from_slice(frame.as_bytes()).map_err(|e| Error::new(ErrorKind::InvalidArg, e))
+    }
+
     // pub fn zsock_bsend(_self: *mut ::std::os::raw::c_void,
     //                    picture: *const ::std::os::raw::c_char, ...)
     //  -> ::std::os::raw::c_int;
@@ -311,21 +819,27 @@ impl ZSock {
     // pub fn zsock_set_unbounded(_self: *mut ::std::os::raw::c_void);
 
     pub fn signal(&self, status: u8) -> Result<()> {
-        let rc = unsafe { czmq_sys::zsock_signal(self.zsock as *mut c_void, status) };
-        if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        let rc = retry_interrupted(|| unsafe { czmq_sys::zsock_signal(self.zsock as *mut c_void, status) }, |rc| *rc == -1);
+        let result = if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
             Ok(())
-        }
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Signal(status));
+        result
     }
 
     pub fn wait(&self) -> Result<()> {
-        let rc = unsafe { czmq_sys::zsock_wait(self.zsock as *mut c_void) };
-        if rc == -1 {
-            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        let rc = retry_interrupted(|| unsafe { czmq_sys::zsock_wait(self.zsock as *mut c_void) }, |rc| *rc == -1);
+        let result = if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
         } else {
             Ok(())
-        }
+        };
+
+        history::record(self.zsock as *mut c_void, &result, || HistoryOp::Wait);
+        result
     }
 
     pub fn flush(&self) {
@@ -335,10 +849,22 @@ impl ZSock {
     // pub fn zsock_is(_self: *mut ::std::os::raw::c_void) -> u8;
     // pub fn zsock_resolve(_self: *mut ::std::os::raw::c_void)
     //  -> *mut ::std::os::raw::c_void;
-    // pub fn zsock_tos(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_tos(_self: *mut ::std::os::raw::c_void,
-    //                      tos: ::std::os::raw::c_int);
+
+    pub fn tos(&self) -> Result<i32> {
+        let tos = unsafe { czmq_sys::zsock_tos(self.zsock as *mut c_void) };
+
+        if tos == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(tos)
+        }
+    }
+
+    pub fn set_tos(&self, tos: i32) {
+        history::option(self.zsock as *mut c_void, "tos", &tos);
+        unsafe { czmq_sys::zsock_set_tos(self.zsock as *mut c_void, tos) };
+    }
+
     // pub fn zsock_set_router_handover(_self: *mut ::std::os::raw::c_void,
     //                                  router_handover: ::std::os::raw::c_int);
     // pub fn zsock_set_router_mandatory(_self: *mut ::std::os::raw::c_void,
@@ -370,6 +896,7 @@ impl ZSock {
     }
 
     pub fn set_zap_domain(&self, zap_domain: &str) {
+        history::option(self.zsock as *mut c_void, "zap_domain", &zap_domain);
         let zap_domain_c = CString::new(zap_domain).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_zap_domain(self.zsock as *mut c_void, zap_domain_c);
@@ -388,7 +915,7 @@ impl ZSock {
                 1 => Ok(Mechanism::ZMQ_PLAIN),
                 2 => Ok(Mechanism::ZMQ_CURVE),
                 3 => Ok(Mechanism::ZMQ_GSSAPI),
-                _ => unreachable!(),
+                _ => Err(Error::new(ErrorKind::InvalidArg, ZSockError::UnknownMechanism(mechanism))),
             }
         }
     }
@@ -398,6 +925,7 @@ impl ZSock {
     }
 
     pub fn set_plain_server(&self, plain: bool) {
+        history::option(self.zsock as *mut c_void, "plain_server", &plain);
         unsafe { czmq_sys::zsock_set_plain_server(self.zsock as *mut c_void, if plain { 1 } else { 0 }) };
     }
 
@@ -418,6 +946,7 @@ impl ZSock {
     }
 
     pub fn set_plain_username(&self, username: &str) {
+        history::option(self.zsock as *mut c_void, "plain_username", &username);
         let username_c = CString::new(username).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_plain_username(self.zsock as *mut c_void, username_c);
@@ -442,6 +971,7 @@ impl ZSock {
     }
 
     pub fn set_plain_password(&self, password: &str) {
+        history::option(self.zsock as *mut c_void, "plain_password", &"<secret>");
         let password_c = CString::new(password).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_plain_password(self.zsock as *mut c_void, password_c);
@@ -454,6 +984,7 @@ impl ZSock {
     }
 
     pub fn set_curve_server(&self, curve: bool) {
+        history::option(self.zsock as *mut c_void, "curve_server", &curve);
         unsafe { czmq_sys::zsock_set_curve_server(self.zsock as *mut c_void, if curve { 1 } else { 0 }) };
     }
 
@@ -474,6 +1005,7 @@ impl ZSock {
     }
 
     pub fn set_curve_publickey(&self, key: &str) {
+        history::option(self.zsock as *mut c_void, "curve_publickey", &key);
         let key_c = CString::new(key).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_curve_publickey(self.zsock as *mut c_void, key_c);
@@ -482,6 +1014,7 @@ impl ZSock {
     }
 
     pub fn set_curve_publickey_bin(&self, key: &[u8]) {
+        history::option(self.zsock as *mut c_void, "curve_publickey_bin", &key);
         unsafe { czmq_sys::zsock_set_curve_publickey_bin(self.zsock as *mut c_void, key.as_ptr()) };
     }
 
@@ -502,6 +1035,7 @@ impl ZSock {
     }
 
     pub fn set_curve_secretkey(&self, key: &str) {
+        history::option(self.zsock as *mut c_void, "curve_secretkey", &"<secret>");
         let key_c = CString::new(key).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_curve_secretkey(self.zsock as *mut c_void, key_c);
@@ -510,6 +1044,7 @@ impl ZSock {
     }
 
     pub fn set_curve_secretkey_bin(&self, key: &[u8]) {
+        history::option(self.zsock as *mut c_void, "curve_secretkey_bin", &"<secret>");
         unsafe { czmq_sys::zsock_set_curve_secretkey_bin(self.zsock as *mut c_void, key.as_ptr()) };
     }
 
@@ -530,6 +1065,7 @@ impl ZSock {
     }
 
     pub fn set_curve_serverkey(&self, key: &str) {
+        history::option(self.zsock as *mut c_void, "curve_serverkey", &key);
         let key_c = CString::new(key).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_curve_serverkey(self.zsock as *mut c_void, key_c);
@@ -538,47 +1074,141 @@ impl ZSock {
     }
 
     pub fn set_curve_serverkey_bin(&self, key: &[u8]) {
+        history::option(self.zsock as *mut c_void, "curve_serverkey_bin", &key);
         unsafe { czmq_sys::zsock_set_curve_serverkey_bin(self.zsock as *mut c_void, key.as_ptr()) };
     }
 
-    // pub fn gssapi_server(&self) -> bool {
-    //     unsafe { czmq_sys::zsock_gssapi_server(self.zsock as *mut c_void) == 1 }
-    // }
-    //
-    // pub fn set_gssapi_server(&self, gssapi: bool) {
-    //     unsafe { czmq_sys::zsock_set_gssapi_server(self.zsock as *mut c_void, if gssapi { 1 } else { 0 }) };
-    // }
+    // CZMQ asserts if libzmq rejects a GSSAPI option, which it does
+    // when built without GSSAPI, so check for support first
+    fn check_gssapi() -> Result<()> {
+        if ZSys::has("gssapi") {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InvalidArg, ZSockError::NoGssapi))
+        }
+    }
+
+    pub fn gssapi_server(&self) -> Result<bool> {
+        Self::check_gssapi()?;
+        Ok(unsafe { czmq_sys::zsock_gssapi_server(self.zsock as *mut c_void) == 1 })
+    }
+
+    pub fn set_gssapi_server(&self, gssapi: bool) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "gssapi_server", &gssapi);
+        Self::check_gssapi()?;
+        unsafe { czmq_sys::zsock_set_gssapi_server(self.zsock as *mut c_void, if gssapi { 1 } else { 0 }) };
+        Ok(())
+    }
+
+    /// Whether GSSAPI only authenticates, leaving messages
+    /// unencrypted.
+    pub fn gssapi_plaintext(&self) -> Result<bool> {
+        Self::check_gssapi()?;
+        Ok(unsafe { czmq_sys::zsock_gssapi_plaintext(self.zsock as *mut c_void) == 1 })
+    }
+
+    pub fn set_gssapi_plaintext(&self, plaintext: bool) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "gssapi_plaintext", &plaintext);
+        Self::check_gssapi()?;
+        unsafe { czmq_sys::zsock_set_gssapi_plaintext(self.zsock as *mut c_void, if plaintext { 1 } else { 0 }) };
+        Ok(())
+    }
+
+    /// The name of this socket's own principal, whose credentials
+    /// are used to authenticate.
+    pub fn gssapi_principal(&self) -> Result<result::Result<String, Vec<u8>>> {
+        Self::check_gssapi()?;
+        let principal = unsafe { czmq_sys::zsock_gssapi_principal(self.zsock as *mut c_void) };
+
+        if principal == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CmdFailed))
+        } else {
+            Ok(unsafe { take_string(principal) })
+        }
+    }
+
+    pub fn set_gssapi_principal(&self, principal: &str) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "gssapi_principal", &principal);
+        Self::check_gssapi()?;
+        let principal_c = CString::new(principal)?;
+        unsafe { czmq_sys::zsock_set_gssapi_principal(self.zsock as *mut c_void, principal_c.as_ptr()) };
+        Ok(())
+    }
+
+    /// The name of the server principal a client connects to.
+    pub fn gssapi_service_principal(&self) -> Result<result::Result<String, Vec<u8>>> {
+        Self::check_gssapi()?;
+        let principal = unsafe { czmq_sys::zsock_gssapi_service_principal(self.zsock as *mut c_void) };
+
+        if principal == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CmdFailed))
+        } else {
+            Ok(unsafe { take_string(principal) })
+        }
+    }
+
+    pub fn set_gssapi_service_principal(&self, principal: &str) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "gssapi_service_principal", &principal);
+        Self::check_gssapi()?;
+        let principal_c = CString::new(principal)?;
+        unsafe { czmq_sys::zsock_set_gssapi_service_principal(self.zsock as *mut c_void, principal_c.as_ptr()) };
+        Ok(())
+    }
+
+    /// How `gssapi_principal()` is interpreted.
+    pub fn gssapi_principal_nametype(&self) -> Result<GssapiNameType> {
+        Self::check_gssapi()?;
+        GssapiNameType::from_raw(self.option_int(ZMQ_GSSAPI_PRINCIPAL_NAMETYPE)?)
+    }
+
+    pub fn set_gssapi_principal_nametype(&self, nametype: GssapiNameType) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "gssapi_principal_nametype", &nametype);
+        Self::check_gssapi()?;
+        self.set_option(ZMQ_GSSAPI_PRINCIPAL_NAMETYPE, &(nametype as c_int).to_ne_bytes())
+    }
+
+    /// How `gssapi_service_principal()` is interpreted.
+    pub fn gssapi_service_principal_nametype(&self) -> Result<GssapiNameType> {
+        Self::check_gssapi()?;
+        GssapiNameType::from_raw(self.option_int(ZMQ_GSSAPI_SERVICE_PRINCIPAL_NAMETYPE)?)
+    }
+
+    pub fn set_gssapi_service_principal_nametype(&self, nametype: GssapiNameType) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "gssapi_service_principal_nametype", &nametype);
+        Self::check_gssapi()?;
+        self.set_option(ZMQ_GSSAPI_SERVICE_PRINCIPAL_NAMETYPE, &(nametype as c_int).to_ne_bytes())
+    }
+
+    pub fn ipv6(&self) -> bool {
+        unsafe { czmq_sys::zsock_ipv6(self.zsock as *mut c_void) == 1 }
+    }
+
+    pub fn set_ipv6(&self, ipv6: bool) {
+        history::option(self.zsock as *mut c_void, "ipv6", &ipv6);
+        unsafe { czmq_sys::zsock_set_ipv6(self.zsock as *mut c_void, if ipv6 { 1 } else { 0 }) };
+    }
+
+    pub fn immediate(&self) -> bool {
+        unsafe { czmq_sys::zsock_immediate(self.zsock as *mut c_void) == 1 }
+    }
+
+    pub fn set_immediate(&self, immediate: bool) {
+        history::option(self.zsock as *mut c_void, "immediate", &immediate);
+        unsafe { czmq_sys::zsock_set_immediate(self.zsock as *mut c_void, if immediate { 1 } else { 0 }) };
+    }
 
-    // pub fn zsock_gssapi_plaintext(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_gssapi_plaintext(_self: *mut ::std::os::raw::c_void,
-    //                                   gssapi_plaintext:
-    //                                       ::std::os::raw::c_int);
-    // pub fn zsock_gssapi_principal(_self: *mut ::std::os::raw::c_void)
-    //  -> *mut ::std::os::raw::c_char;
-    // pub fn zsock_set_gssapi_principal(_self: *mut ::std::os::raw::c_void,
-    //                                   gssapi_principal:
-    //                                       *const ::std::os::raw::c_char);
-    // pub fn zsock_gssapi_service_principal(_self: *mut ::std::os::raw::c_void)
-    //  -> *mut ::std::os::raw::c_char;
-    // pub fn zsock_set_gssapi_service_principal(_self:
-    //                                               *mut ::std::os::raw::c_void,
-    //                                           gssapi_service_principal:
-    //                                               *const ::std::os::raw::c_char);
-    // pub fn zsock_ipv6(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_ipv6(_self: *mut ::std::os::raw::c_void,
-    //                       ipv6: ::std::os::raw::c_int);
-    // pub fn zsock_immediate(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_immediate(_self: *mut ::std::os::raw::c_void,
-    //                            immediate: ::std::os::raw::c_int);
     // pub fn zsock_set_router_raw(_self: *mut ::std::os::raw::c_void,
     //                             router_raw: ::std::os::raw::c_int);
-    // pub fn zsock_ipv4only(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_ipv4only(_self: *mut ::std::os::raw::c_void,
-    //                           ipv4only: ::std::os::raw::c_int);
+
+    pub fn ipv4only(&self) -> bool {
+        unsafe { czmq_sys::zsock_ipv4only(self.zsock as *mut c_void) == 1 }
+    }
+
+    pub fn set_ipv4only(&self, ipv4only: bool) {
+        history::option(self.zsock as *mut c_void, "ipv4only", &ipv4only);
+        unsafe { czmq_sys::zsock_set_ipv4only(self.zsock as *mut c_void, if ipv4only { 1 } else { 0 }) };
+    }
+
     // pub fn zsock_set_delay_attach_on_connect(_self:
     //                                              *mut ::std::os::raw::c_void,
     //                                          delay_attach_on_connect:
@@ -615,6 +1245,7 @@ impl ZSock {
     }
 
     pub fn set_sndhwm(&self, sndhwm: i32) {
+        history::option(self.zsock as *mut c_void, "sndhwm", &sndhwm);
         unsafe { czmq_sys::zsock_set_sndhwm(self.zsock as *mut c_void, sndhwm) };
     }
 
@@ -629,15 +1260,27 @@ impl ZSock {
     }
 
     pub fn set_rcvhwm(&self, rcvhwm: i32) {
+        history::option(self.zsock as *mut c_void, "rcvhwm", &rcvhwm);
         unsafe { czmq_sys::zsock_set_rcvhwm(self.zsock as *mut c_void, rcvhwm) };
     }
 
-    // pub fn zsock_affinity(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_affinity(_self: *mut ::std::os::raw::c_void,
-    //                           affinity: ::std::os::raw::c_int);
+    pub fn affinity(&self) -> Result<i32> {
+        let affinity = unsafe { czmq_sys::zsock_affinity(self.zsock as *mut c_void) };
+
+        if affinity == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(affinity)
+        }
+    }
+
+    pub fn set_affinity(&self, affinity: i32) {
+        history::option(self.zsock as *mut c_void, "affinity", &affinity);
+        unsafe { czmq_sys::zsock_set_affinity(self.zsock as *mut c_void, affinity) };
+    }
 
     pub fn set_subscribe(&self, subscribe: &str) {
+        history::option(self.zsock as *mut c_void, "subscribe", &subscribe);
         let subscribe_c = CString::new(subscribe).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_subscribe(self.zsock as *mut c_void, subscribe_c);
@@ -646,6 +1289,7 @@ impl ZSock {
     }
 
     pub fn set_unsubscribe(&self, unsubscribe: &str) {
+        history::option(self.zsock as *mut c_void, "unsubscribe", &unsubscribe);
         let unsubscribe_c = CString::new(unsubscribe).unwrap_or(CString::new("").unwrap()).into_raw();
         unsafe {
             czmq_sys::zsock_set_unsubscribe(self.zsock as *mut c_void, unsubscribe_c);
@@ -669,7 +1313,8 @@ impl ZSock {
     }
 
     pub fn set_identity(&self, identity: &str) -> Result<()> {
-        let identity_c = try!(CString::new(identity));
+        history::option(self.zsock as *mut c_void, "identity", &identity);
+        let identity_c = CString::new(identity)?;
         unsafe { czmq_sys::zsock_set_identity(self.zsock as *mut c_void, identity_c.as_ptr()) };
 
         // Deliberately leak this memory, which will be managed by C
@@ -677,22 +1322,90 @@ impl ZSock {
         Ok(())
     }
 
-    // pub fn zsock_rate(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_rate(_self: *mut ::std::os::raw::c_void,
-    //                       rate: ::std::os::raw::c_int);
-    // pub fn zsock_recovery_ivl(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_recovery_ivl(_self: *mut ::std::os::raw::c_void,
-    //                               recovery_ivl: ::std::os::raw::c_int);
-    // pub fn zsock_sndbuf(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_sndbuf(_self: *mut ::std::os::raw::c_void,
-    //                         sndbuf: ::std::os::raw::c_int);
-    // pub fn zsock_rcvbuf(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_rcvbuf(_self: *mut ::std::os::raw::c_void,
-    //                         rcvbuf: ::std::os::raw::c_int);
+    /// Like `identity()`, but for identities that aren't valid
+    /// strings, such as those ROUTER sockets generate.
+    pub fn identity_bin(&self) -> Result<Vec<u8>> {
+        self.option_bytes(ZMQ_ROUTING_ID, IDENTITY_MAX)
+    }
+
+    /// Set a binary identity, which ROUTER peers will address this
+    /// socket by. It must be 1 to 255 bytes long and not start with a
+    /// zero byte, which libzmq reserves, and be set before the socket
+    /// connects.
+    pub fn set_identity_bin(&self, identity: &[u8]) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "identity", &identity);
+        if identity.is_empty() || identity.len() > IDENTITY_MAX || identity[0] == 0 {
+            return Err(Error::new(ErrorKind::InvalidArg, ZSockError::InvalidIdentity));
+        }
+
+        self.set_option(ZMQ_ROUTING_ID, identity)
+    }
+
+    /// The maximum send rate of a PGM, EPGM or NORM socket, in
+    /// kilobits per second.
+    pub fn rate(&self) -> Result<i32> {
+        let rate = unsafe { czmq_sys::zsock_rate(self.zsock as *mut c_void) };
+
+        if rate == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(rate)
+        }
+    }
+
+    pub fn set_rate(&self, rate: i32) {
+        history::option(self.zsock as *mut c_void, "rate", &rate);
+        unsafe { czmq_sys::zsock_set_rate(self.zsock as *mut c_void, rate) };
+    }
+
+    /// How long, in milliseconds, a multicast receiver can be absent
+    /// and still recover the messages it missed.
+    pub fn recovery_ivl(&self) -> Result<i32> {
+        let recovery_ivl = unsafe { czmq_sys::zsock_recovery_ivl(self.zsock as *mut c_void) };
+
+        if recovery_ivl == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(recovery_ivl)
+        }
+    }
+
+    pub fn set_recovery_ivl(&self, recovery_ivl: i32) {
+        history::option(self.zsock as *mut c_void, "recovery_ivl", &recovery_ivl);
+        unsafe { czmq_sys::zsock_set_recovery_ivl(self.zsock as *mut c_void, recovery_ivl) };
+    }
+
+    /// The kernel's send buffer size, or `None` for the OS default.
+    pub fn sndbuf(&self) -> Option<i32> {
+        let sndbuf = unsafe { czmq_sys::zsock_sndbuf(self.zsock as *mut c_void) };
+
+        if sndbuf == -1 {
+            None
+        } else {
+            Some(sndbuf)
+        }
+    }
+
+    pub fn set_sndbuf(&self, sndbuf: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "sndbuf", &sndbuf);
+        unsafe { czmq_sys::zsock_set_sndbuf(self.zsock as *mut c_void, sndbuf.unwrap_or(-1)) };
+    }
+
+    /// The kernel's receive buffer size, or `None` for the OS default.
+    pub fn rcvbuf(&self) -> Option<i32> {
+        let rcvbuf = unsafe { czmq_sys::zsock_rcvbuf(self.zsock as *mut c_void) };
+
+        if rcvbuf == -1 {
+            None
+        } else {
+            Some(rcvbuf)
+        }
+    }
+
+    pub fn set_rcvbuf(&self, rcvbuf: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "rcvbuf", &rcvbuf);
+        unsafe { czmq_sys::zsock_set_rcvbuf(self.zsock as *mut c_void, rcvbuf.unwrap_or(-1)) };
+    }
 
     pub fn linger(&self) -> Result<i32> {
         let linger = unsafe { czmq_sys::zsock_linger(self.zsock as *mut c_void) };
@@ -705,30 +1418,138 @@ impl ZSock {
     }
 
     pub fn set_linger(&self, linger: i32) {
+        history::option(self.zsock as *mut c_void, "linger", &linger);
         unsafe { czmq_sys::zsock_set_linger(self.zsock as *mut c_void, linger) };
     }
 
-    // pub fn zsock_reconnect_ivl(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_reconnect_ivl(_self: *mut ::std::os::raw::c_void,
-    //                                reconnect_ivl: ::std::os::raw::c_int);
-    // pub fn zsock_reconnect_ivl_max(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_reconnect_ivl_max(_self: *mut ::std::os::raw::c_void,
-    //                                    reconnect_ivl_max:
-    //                                        ::std::os::raw::c_int);
-    // pub fn zsock_backlog(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_backlog(_self: *mut ::std::os::raw::c_void,
-    //                          backlog: ::std::os::raw::c_int);
-    // pub fn zsock_maxmsgsize(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_maxmsgsize(_self: *mut ::std::os::raw::c_void,
-    //                             maxmsgsize: ::std::os::raw::c_int);
-    // pub fn zsock_multicast_hops(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_multicast_hops(_self: *mut ::std::os::raw::c_void,
-    //                                 multicast_hops: ::std::os::raw::c_int);
+    pub fn reconnect_ivl(&self) -> Result<i32> {
+        let reconnect_ivl = unsafe { czmq_sys::zsock_reconnect_ivl(self.zsock as *mut c_void) };
+
+        if reconnect_ivl == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(reconnect_ivl)
+        }
+    }
+
+    pub fn set_reconnect_ivl(&self, reconnect_ivl: i32) {
+        history::option(self.zsock as *mut c_void, "reconnect_ivl", &reconnect_ivl);
+        unsafe { czmq_sys::zsock_set_reconnect_ivl(self.zsock as *mut c_void, reconnect_ivl) };
+    }
+
+    pub fn reconnect_ivl_max(&self) -> Result<i32> {
+        let reconnect_ivl_max = unsafe { czmq_sys::zsock_reconnect_ivl_max(self.zsock as *mut c_void) };
+
+        if reconnect_ivl_max == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(reconnect_ivl_max)
+        }
+    }
+
+    pub fn set_reconnect_ivl_max(&self, reconnect_ivl_max: i32) {
+        history::option(self.zsock as *mut c_void, "reconnect_ivl_max", &reconnect_ivl_max);
+        unsafe { czmq_sys::zsock_set_reconnect_ivl_max(self.zsock as *mut c_void, reconnect_ivl_max) };
+    }
+
+    /// Set the reconnect interval, backoff and stop conditions in one
+    /// go. Stop conditions are a libzmq draft, so a policy with any
+    /// set fails with `ErrorKind::Unsupported` unless libzmq was
+    /// built with drafts.
+    pub fn set_reconnect_policy(&self, policy: &ReconnectPolicy) -> Result<()> {
+        let stop = policy.stop_flags();
+        if stop != 0 {
+            require!(Capability::Draft);
+        }
+
+        self.set_reconnect_ivl(to_millis(Some(policy.interval)));
+        self.set_reconnect_ivl_max(policy.max_interval.map_or(0, |max| to_millis(Some(max))));
+        if stop != 0 || Capability::Draft.is_available() {
+            history::option(self.zsock as *mut c_void, "reconnect_stop", &stop);
+            self.set_option(ZMQ_RECONNECT_STOP, &stop.to_ne_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn reconnect_policy(&self) -> Result<ReconnectPolicy> {
+        let max = self.reconnect_ivl_max()?;
+        let stop = if Capability::Draft.is_available() { self.option_int(ZMQ_RECONNECT_STOP)? } else { 0 };
+
+        Ok(ReconnectPolicy::from_raw(
+            Duration::from_millis(self.reconnect_ivl()? as u64),
+            if max == 0 { None } else { Some(Duration::from_millis(max as u64)) },
+            stop))
+    }
+
+    pub fn backlog(&self) -> Result<i32> {
+        let backlog = unsafe { czmq_sys::zsock_backlog(self.zsock as *mut c_void) };
+
+        if backlog == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(backlog)
+        }
+    }
+
+    pub fn set_backlog(&self, backlog: i32) {
+        history::option(self.zsock as *mut c_void, "backlog", &backlog);
+        unsafe { czmq_sys::zsock_set_backlog(self.zsock as *mut c_void, backlog) };
+    }
+
+    pub fn maxmsgsize(&self) -> Option<i32> {
+        let maxmsgsize = unsafe { czmq_sys::zsock_maxmsgsize(self.zsock as *mut c_void) };
+
+        if maxmsgsize == -1 {
+            None
+        } else {
+            Some(maxmsgsize)
+        }
+    }
+
+    pub fn set_maxmsgsize(&self, maxmsgsize: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "maxmsgsize", &maxmsgsize);
+        unsafe { czmq_sys::zsock_set_maxmsgsize(self.zsock as *mut c_void, maxmsgsize.unwrap_or(-1)) };
+    }
+
+    pub fn multicast_hops(&self) -> Result<i32> {
+        let multicast_hops = unsafe { czmq_sys::zsock_multicast_hops(self.zsock as *mut c_void) };
+
+        if multicast_hops == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(multicast_hops)
+        }
+    }
+
+    pub fn set_multicast_hops(&self, multicast_hops: i32) {
+        history::option(self.zsock as *mut c_void, "multicast_hops", &multicast_hops);
+        unsafe { czmq_sys::zsock_set_multicast_hops(self.zsock as *mut c_void, multicast_hops) };
+    }
+
+    /// The largest transport data unit, in bytes, that a PGM, EPGM or
+    /// NORM socket sends. Keep it under the network's MTU.
+    pub fn multicast_maxtpdu(&self) -> Result<i32> {
+        self.option_int(ZMQ_MULTICAST_MAXTPDU)
+    }
+
+    pub fn set_multicast_maxtpdu(&self, maxtpdu: i32) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "multicast_maxtpdu", &maxtpdu);
+        self.set_option(ZMQ_MULTICAST_MAXTPDU, &maxtpdu.to_ne_bytes())
+    }
+
+    /// Whether a multicast socket receives its own messages when the
+    /// sender and receiver are on the same host. Enabled by default.
+    #[cfg(feature = "draft")]
+    pub fn multicast_loop(&self) -> Result<bool> {
+        self.option_int(ZMQ_MULTICAST_LOOP).map(|l| l == 1)
+    }
+
+    #[cfg(feature = "draft")]
+    pub fn set_multicast_loop(&self, multicast_loop: bool) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "multicast_loop", &multicast_loop);
+        let multicast_loop: c_int = if multicast_loop { 1 } else { 0 };
+        self.set_option(ZMQ_MULTICAST_LOOP, &multicast_loop.to_ne_bytes())
+    }
 
     pub fn rcvtimeo(&self) -> Option<i32> {
         let timeout = unsafe { czmq_sys::zsock_rcvtimeo(self.zsock as *mut c_void) };
@@ -741,6 +1562,7 @@ impl ZSock {
     }
 
     pub fn set_rcvtimeo(&self, timeout: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "rcvtimeo", &timeout);
         unsafe { czmq_sys::zsock_set_rcvtimeo(self.zsock as *mut c_void, timeout.unwrap_or(-1)) };
     }
 
@@ -755,78 +1577,440 @@ impl ZSock {
     }
 
     pub fn set_sndtimeo(&self, timeout: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "sndtimeo", &timeout);
         unsafe { czmq_sys::zsock_set_sndtimeo(self.zsock as *mut c_void, timeout.unwrap_or(-1)) };
     }
 
+    /// Like `linger()`, but as a `Duration`. `None` means the socket
+    /// waits indefinitely for pending messages when closed.
+    pub fn linger_duration(&self) -> Result<Option<Duration>> {
+        self.linger().map(from_millis)
+    }
+
+    pub fn set_linger_duration(&self, linger: Option<Duration>) {
+        self.set_linger(to_millis(linger));
+    }
+
+    /// Like `recovery_ivl()`, but as a `Duration`.
+    pub fn recovery_ivl_duration(&self) -> Result<Duration> {
+        self.recovery_ivl().map(|ivl| Duration::from_millis(ivl as u64))
+    }
+
+    pub fn set_recovery_ivl_duration(&self, recovery_ivl: Duration) {
+        self.set_recovery_ivl(to_millis(Some(recovery_ivl)));
+    }
+
+    /// Like `rcvtimeo()`, but as a `Duration`. `None` means receives
+    /// block forever.
+    pub fn rcvtimeo_duration(&self) -> Option<Duration> {
+        self.rcvtimeo().and_then(from_millis)
+    }
+
+    pub fn set_rcvtimeo_duration(&self, timeout: Option<Duration>) {
+        self.set_rcvtimeo(Some(to_millis(timeout)));
+    }
+
+    /// Like `sndtimeo()`, but as a `Duration`. `None` means sends
+    /// block forever.
+    pub fn sndtimeo_duration(&self) -> Option<Duration> {
+        self.sndtimeo().and_then(from_millis)
+    }
+
+    pub fn set_sndtimeo_duration(&self, timeout: Option<Duration>) {
+        self.set_sndtimeo(Some(to_millis(timeout)));
+    }
+
+    /// Interval between ZMTP heartbeat PINGs. Zero disables
+    /// heartbeating.
+    pub fn heartbeat_ivl(&self) -> Result<Duration> {
+        let ivl = unsafe { czmq_sys::zsock_heartbeat_ivl(self.zsock as *mut c_void) };
+
+        if ivl == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(Duration::from_millis(ivl as u64))
+        }
+    }
+
+    pub fn set_heartbeat_ivl(&self, ivl: Duration) {
+        history::option(self.zsock as *mut c_void, "heartbeat_ivl", &ivl);
+        unsafe { czmq_sys::zsock_set_heartbeat_ivl(self.zsock as *mut c_void, to_millis(Some(ivl))) };
+    }
+
+    /// How long the remote peer should wait for traffic before timing
+    /// out the connection.
+    pub fn heartbeat_ttl(&self) -> Result<Duration> {
+        let ttl = unsafe { czmq_sys::zsock_heartbeat_ttl(self.zsock as *mut c_void) };
+
+        if ttl == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(Duration::from_millis(ttl as u64))
+        }
+    }
+
+    pub fn set_heartbeat_ttl(&self, ttl: Duration) {
+        history::option(self.zsock as *mut c_void, "heartbeat_ttl", &ttl);
+        unsafe { czmq_sys::zsock_set_heartbeat_ttl(self.zsock as *mut c_void, to_millis(Some(ttl))) };
+    }
+
+    /// How long to wait for a PONG before timing out the connection.
+    /// Zero means the same as `heartbeat_ivl()`.
+    pub fn heartbeat_timeout(&self) -> Result<Duration> {
+        let timeout = unsafe { czmq_sys::zsock_heartbeat_timeout(self.zsock as *mut c_void) };
+
+        if timeout == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(Duration::from_millis(timeout as u64))
+        }
+    }
+
+    pub fn set_heartbeat_timeout(&self, timeout: Duration) {
+        history::option(self.zsock as *mut c_void, "heartbeat_timeout", &timeout);
+        unsafe { czmq_sys::zsock_set_heartbeat_timeout(self.zsock as *mut c_void, to_millis(Some(timeout))) };
+    }
+
+    /// Set the heartbeat interval, timeout and TTL together.
+    pub fn set_keepalive(&self, keepalive: &Keepalive) {
+        self.set_heartbeat_ivl(keepalive.interval);
+        self.set_heartbeat_timeout(keepalive.timeout.unwrap_or_default());
+        self.set_heartbeat_ttl(keepalive.ttl.unwrap_or_default());
+    }
+
+    pub fn keepalive(&self) -> Result<Keepalive> {
+        Ok(Keepalive::from_raw(self.heartbeat_ivl()?, self.heartbeat_timeout()?, self.heartbeat_ttl()?))
+    }
+
     pub fn set_xpub_verbose(&self, verbose: bool) {
+        history::option(self.zsock as *mut c_void, "xpub_verbose", &verbose);
         unsafe { czmq_sys::zsock_set_xpub_verbose(self.zsock as *mut c_void, if verbose { 1 } else { 0 }) };
     }
 
-    // pub fn zsock_tcp_keepalive(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_tcp_keepalive(_self: *mut ::std::os::raw::c_void,
-    //                                tcp_keepalive: ::std::os::raw::c_int);
-    // pub fn zsock_tcp_keepalive_idle(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_tcp_keepalive_idle(_self: *mut ::std::os::raw::c_void,
-    //                                     tcp_keepalive_idle:
-    //                                         ::std::os::raw::c_int);
-    // pub fn zsock_tcp_keepalive_cnt(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_tcp_keepalive_cnt(_self: *mut ::std::os::raw::c_void,
-    //                                    tcp_keepalive_cnt:
-    //                                        ::std::os::raw::c_int);
-    // pub fn zsock_tcp_keepalive_intvl(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_set_tcp_keepalive_intvl(_self: *mut ::std::os::raw::c_void,
-    //                                      tcp_keepalive_intvl:
-    //                                          ::std::os::raw::c_int);
-    // pub fn zsock_tcp_accept_filter(_self: *mut ::std::os::raw::c_void)
-    //  -> *mut ::std::os::raw::c_char;
-    // pub fn zsock_set_tcp_accept_filter(_self: *mut ::std::os::raw::c_void,
-    //                                    tcp_accept_filter:
-    //                                        *const ::std::os::raw::c_char);
+    /// Like `set_xpub_verbose()`, but pass on every unsubscription
+    /// as well, not just the last for each topic, so that
+    /// subscriptions can be counted.
+    pub fn set_xpub_verboser(&self, verboser: bool) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "xpub_verboser", &verboser);
+        self.set_option(ZMQ_XPUB_VERBOSER, &(if verboser { 1 as c_int } else { 0 }).to_ne_bytes())
+    }
+
+    /// Send `msg` to every subscriber when it connects. Subscribers
+    /// only get it if they subscribe to it, so it's usually its own
+    /// topic, e.g. "WELCOME".
+    pub fn set_xpub_welcome_msg(&self, msg: &[u8]) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "xpub_welcome_msg", &msg);
+        self.set_option(ZMQ_XPUB_WELCOME_MSG, msg)
+    }
+
+    pub fn tcp_keepalive(&self) -> Option<i32> {
+        let tcp_keepalive = unsafe { czmq_sys::zsock_tcp_keepalive(self.zsock as *mut c_void) };
+
+        if tcp_keepalive == -1 {
+            None
+        } else {
+            Some(tcp_keepalive)
+        }
+    }
+
+    pub fn set_tcp_keepalive(&self, tcp_keepalive: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "tcp_keepalive", &tcp_keepalive);
+        unsafe { czmq_sys::zsock_set_tcp_keepalive(self.zsock as *mut c_void, tcp_keepalive.unwrap_or(-1)) };
+    }
+
+    pub fn tcp_keepalive_idle(&self) -> Option<i32> {
+        let tcp_keepalive_idle = unsafe { czmq_sys::zsock_tcp_keepalive_idle(self.zsock as *mut c_void) };
+
+        if tcp_keepalive_idle == -1 {
+            None
+        } else {
+            Some(tcp_keepalive_idle)
+        }
+    }
+
+    pub fn set_tcp_keepalive_idle(&self, tcp_keepalive_idle: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "tcp_keepalive_idle", &tcp_keepalive_idle);
+        unsafe { czmq_sys::zsock_set_tcp_keepalive_idle(self.zsock as *mut c_void, tcp_keepalive_idle.unwrap_or(-1)) };
+    }
+
+    pub fn tcp_keepalive_cnt(&self) -> Option<i32> {
+        let tcp_keepalive_cnt = unsafe { czmq_sys::zsock_tcp_keepalive_cnt(self.zsock as *mut c_void) };
+
+        if tcp_keepalive_cnt == -1 {
+            None
+        } else {
+            Some(tcp_keepalive_cnt)
+        }
+    }
+
+    pub fn set_tcp_keepalive_cnt(&self, tcp_keepalive_cnt: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "tcp_keepalive_cnt", &tcp_keepalive_cnt);
+        unsafe { czmq_sys::zsock_set_tcp_keepalive_cnt(self.zsock as *mut c_void, tcp_keepalive_cnt.unwrap_or(-1)) };
+    }
+
+    pub fn tcp_keepalive_intvl(&self) -> Option<i32> {
+        let tcp_keepalive_intvl = unsafe { czmq_sys::zsock_tcp_keepalive_intvl(self.zsock as *mut c_void) };
+
+        if tcp_keepalive_intvl == -1 {
+            None
+        } else {
+            Some(tcp_keepalive_intvl)
+        }
+    }
+
+    pub fn set_tcp_keepalive_intvl(&self, tcp_keepalive_intvl: Option<i32>) {
+        history::option(self.zsock as *mut c_void, "tcp_keepalive_intvl", &tcp_keepalive_intvl);
+        unsafe { czmq_sys::zsock_set_tcp_keepalive_intvl(self.zsock as *mut c_void, tcp_keepalive_intvl.unwrap_or(-1)) };
+    }
+
+    pub fn tcp_accept_filter(&self) -> Result<result::Result<String, Vec<u8>>> {
+        let tcp_accept_filter = unsafe { czmq_sys::zsock_tcp_accept_filter(self.zsock as *mut c_void) };
+
+        if tcp_accept_filter == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CmdFailed))
+        } else {
+            Ok(unsafe { take_string(tcp_accept_filter) })
+        }
+    }
+
+    pub fn set_tcp_accept_filter(&self, tcp_accept_filter: &str) {
+        history::option(self.zsock as *mut c_void, "tcp_accept_filter", &tcp_accept_filter);
+        let tcp_accept_filter_c = CString::new(tcp_accept_filter).unwrap_or(CString::new("").unwrap());
+        unsafe { czmq_sys::zsock_set_tcp_accept_filter(self.zsock as *mut c_void, tcp_accept_filter_c.as_ptr()) };
+    }
+
+    /// The PEM certificate a `wss://` socket presents when it binds.
+    /// Set it, and the key, before binding.
+    #[cfg(feature = "draft")]
+    pub fn set_wss_cert_pem(&self, pem: &str) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "wss_cert_pem", &pem);
+        self.set_option_str(ZMQ_WSS_CERT_PEM, pem)
+    }
+
+    /// The PEM private key for `set_wss_cert_pem()`.
+    #[cfg(feature = "draft")]
+    pub fn set_wss_key_pem(&self, pem: &str) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "wss_key_pem", &"<secret>");
+        self.set_option_str(ZMQ_WSS_KEY_PEM, pem)
+    }
+
+    /// A PEM certificate that a connecting `wss://` socket trusts, in
+    /// addition to or instead of the system's CAs.
+    #[cfg(feature = "draft")]
+    pub fn set_wss_trust_pem(&self, pem: &str) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "wss_trust_pem", &pem);
+        self.set_option_str(ZMQ_WSS_TRUST_PEM, pem)
+    }
+
+    /// Whether a connecting `wss://` socket trusts the system's CAs.
+    /// Enabled by default.
+    #[cfg(feature = "draft")]
+    pub fn set_wss_trust_system(&self, trust: bool) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "wss_trust_system", &trust);
+        let trust: c_int = if trust { 1 } else { 0 };
+        self.set_option(ZMQ_WSS_TRUST_SYSTEM, &trust.to_ne_bytes())
+    }
+
+    /// The hostname a connecting `wss://` socket checks the server's
+    /// certificate against, if it differs from the endpoint's host.
+    #[cfg(feature = "draft")]
+    pub fn set_wss_hostname(&self, hostname: &str) -> Result<()> {
+        history::option(self.zsock as *mut c_void, "wss_hostname", &hostname);
+        self.set_option_str(ZMQ_WSS_HOSTNAME, hostname)
+    }
 
     pub fn rcvmore(&self) -> bool {
         unsafe { czmq_sys::zsock_rcvmore(self.zsock as *mut c_void) == 1 }
     }
 
-    // pub fn zsock_fd(_self: *mut ::std::os::raw::c_void) -> SOCKET;
-    // pub fn zsock_events(_self: *mut ::std::os::raw::c_void)
-    //  -> ::std::os::raw::c_int;
-    // pub fn zsock_last_endpoint(_self: *mut ::std::os::raw::c_void)
-    //  -> *mut ::std::os::raw::c_char;
+    /// The socket's `ZMQ_FD`, which signals when its events may have
+    /// changed. It is edge triggered, so check `readable()` or
+    /// `writable()` before waiting on it, not after.
+    pub fn fd(&self) -> c_int {
+        unsafe { czmq_sys::zsock_fd(self.zsock as *mut c_void) }
+    }
+
+    pub fn last_endpoint(&self) -> Result<result::Result<String, Vec<u8>>> {
+        let last_endpoint = unsafe { czmq_sys::zsock_last_endpoint(self.zsock as *mut c_void) };
+
+        if last_endpoint == ptr::null_mut() {
+            Err(Error::new(ErrorKind::NullPtr, ZSockError::CmdFailed))
+        } else {
+            Ok(unsafe { take_string(last_endpoint) })
+        }
+    }
 
     pub fn monitor(&mut self) -> Result<ZMonitor> {
         ZMonitor::new(self)
     }
 
-    fn concat_endpoints(endpoints: &[&str]) -> String {
-        let mut endpoint_str = String::new();
-        let mut iter = 0;
-        for e in endpoints {
-            endpoint_str.push_str(e);
+    // Set an option that CZMQ has no setter for, straight on the
+    // libzmq socket
+    fn set_option(&self, option: c_int, value: &[u8]) -> Result<()> {
+        let rc = unsafe {
+            let sock = czmq_sys::zsock_resolve(self.zsock as *mut c_void);
+            czmq_sys::zmq_setsockopt(sock, option, value.as_ptr() as *const c_void, value.len() as u64)
+        };
 
-            if iter < endpoints.len() {
-                endpoint_str.push_str(",");
-            }
+        if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "draft")]
+    fn set_option_str(&self, option: c_int, value: &str) -> Result<()> {
+        self.set_option(option, value.as_bytes())
+    }
 
-            iter += 1;
+    fn option_bytes(&self, option: c_int, max: usize) -> Result<Vec<u8>> {
+        let mut value = vec![0; max];
+        let mut len = max as u64;
+        let rc = unsafe {
+            let sock = czmq_sys::zsock_resolve(self.zsock as *mut c_void);
+            czmq_sys::zmq_getsockopt(sock, option, value.as_mut_ptr() as *mut c_void, &mut len)
+        };
+
+        if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            value.truncate(len as usize);
+            Ok(value)
         }
+    }
+
+    fn option_int(&self, option: c_int) -> Result<c_int> {
+        let mut value: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as u64;
+        let rc = unsafe {
+            let sock = czmq_sys::zsock_resolve(self.zsock as *mut c_void);
+            czmq_sys::zmq_getsockopt(sock, option, &mut value as *mut c_int as *mut c_void, &mut len)
+        };
 
-        endpoint_str
+        if rc == -1 {
+            Err(Error::from_errno(ErrorKind::NonZero, ZSockError::CmdFailed))
+        } else {
+            Ok(value)
+        }
     }
+
+    fn concat_endpoints<E: ToEndpoint>(endpoints: &[E]) -> String {
+        let endpoints: Vec<_> = endpoints.iter().map(|e| e.to_endpoint()).collect();
+        endpoints.join(",")
+    }
+}
+
+// ZMQ_POLLIN bit of the ZMQ_EVENTS socket option
+const POLLIN: i32 = 1;
+// ZMQ_POLLOUT bit of the ZMQ_EVENTS socket option
+const POLLOUT: i32 = 2;
+
+// ZMQ_SNDMORE send flag
+const SNDMORE: c_int = 2;
+
+// The longest identity libzmq allows
+const IDENTITY_MAX: usize = 255;
+
+// Socket options that CZMQ doesn't wrap, or wraps as strings
+const ZMQ_ROUTING_ID: c_int = 5;
+const ZMQ_XPUB_WELCOME_MSG: c_int = 72;
+const ZMQ_XPUB_VERBOSER: c_int = 78;
+const ZMQ_MULTICAST_MAXTPDU: c_int = 84;
+const ZMQ_GSSAPI_PRINCIPAL_NAMETYPE: c_int = 90;
+const ZMQ_GSSAPI_SERVICE_PRINCIPAL_NAMETYPE: c_int = 91;
+
+// libzmq's draft options, which CZMQ doesn't wrap
+const ZMQ_RECONNECT_STOP: c_int = 109;
+#[cfg(feature = "draft")]
+const ZMQ_MULTICAST_LOOP: c_int = 96;
+#[cfg(feature = "draft")]
+const ZMQ_WSS_KEY_PEM: c_int = 1090;
+#[cfg(feature = "draft")]
+const ZMQ_WSS_CERT_PEM: c_int = 1091;
+#[cfg(feature = "draft")]
+const ZMQ_WSS_TRUST_PEM: c_int = 1092;
+#[cfg(feature = "draft")]
+const ZMQ_WSS_HOSTNAME: c_int = 1093;
+#[cfg(feature = "draft")]
+const ZMQ_WSS_TRUST_SYSTEM: c_int = 1094;
+
+// Monitoring a socket's queues, which is in libzmq's draft API
+#[cfg(feature = "draft")]
+const ZMQ_PAIR: c_int = 0;
+#[cfg(feature = "draft")]
+const ZMQ_EVENT_PIPES_STATS: u64 = 0x10000;
+// How long to wait for more queue reports, in milliseconds
+#[cfg(feature = "draft")]
+const PIPES_STATS_WAIT: i32 = 20;
+
+// Read the outbound queue size from a version 2 monitor event. Its
+// frames are the event, the number of values, the values, then the
+// local and remote endpoints; a PIPES_STATS event's values are the
+// outbound then inbound queue sizes.
+#[cfg(feature = "draft")]
+fn pipes_stats_outbound(event: &mut ZMsg) -> Option<u64> {
+    fn value(frame: ZFrame) -> Option<u64> {
+        frame.as_bytes().try_into().ok().map(u64::from_ne_bytes)
+    }
+
+    match (event.pop().and_then(value), event.pop().and_then(value)) {
+        (Some(ZMQ_EVENT_PIPES_STATS), Some(n)) if n >= 1 => event.pop().and_then(value),
+        _ => None,
+    }
+}
+
+// Convert a time option to milliseconds, where `None` is infinite.
+// Durations too long for a c_int are clamped rather than wrapping.
+fn to_millis(duration: Option<Duration>) -> c_int {
+    match duration {
+        Some(d) => cmp::min(d.as_millis(), c_int::max_value() as u128) as c_int,
+        None => -1,
+    }
+}
+
+fn from_millis(millis: c_int) -> Option<Duration> {
+    if millis < 0 {
+        None
+    } else {
+        Some(Duration::from_millis(millis as u64))
+    }
+}
+
+// Copy a string returned by a CZMQ option getter, which the caller
+// owns, then free it.
+unsafe fn take_string(mut ptr: *mut c_char) -> result::Result<String, Vec<u8>> {
+    let bytes = CStr::from_ptr(ptr).to_bytes().to_vec();
+    czmq_sys::zstr_free(&mut ptr);
+    String::from_utf8(bytes).map_err(|e| e.into_bytes())
+}
+
+type FreeFn = unsafe extern "C" fn(*mut c_void, *mut c_void);
+
+unsafe extern "C" fn free_vec(_data: *mut c_void, hint: *mut c_void) {
+    drop(Box::from_raw(hint as *mut Vec<u8>));
+}
+
+unsafe extern "C" fn free_arc(_data: *mut c_void, hint: *mut c_void) {
+    drop(Box::from_raw(hint as *mut Arc<[u8]>));
 }
 
 impl RawInterface<c_void> for ZSock {
     unsafe fn from_raw(ptr: *mut c_void, owned: bool) -> ZSock {
-        ZSock {
+        let sock = ZSock {
             zsock: ptr as *mut czmq_sys::zsock_t,
             owned: owned,
+        };
+        if owned {
+            leaks::created(ptr, || sock.leak_name());
         }
+        sock
     }
 
     fn into_raw(mut self) -> *mut c_void {
+        if self.owned {
+            leaks::destroyed(self.zsock as *mut c_void);
+        }
         self.owned = false;
         self.zsock as *mut c_void
     }
@@ -838,10 +2022,30 @@ impl RawInterface<c_void> for ZSock {
 
 impl Sockish for ZSock {}
 
+impl SocketLike for ZSock {
+    fn as_socket_ptr(&mut self) -> *mut c_void {
+        self.zsock as *mut c_void
+    }
+}
+
+impl<'a> SocketLike for &'a ZSock {
+    fn as_socket_ptr(&mut self) -> *mut c_void {
+        self.zsock as *mut c_void
+    }
+}
+
 #[derive(Debug)]
 pub enum ZSockError {
     CreateSock,
     CmdFailed,
+    #[cfg(feature = "rmp")]
+    FrameCount(usize),
+    InvalidIdentity,
+    NoBuffers,
+    NoGssapi,
+    NotWritable,
+    UnknownMechanism(i32),
+    UnknownNameType(i32),
 }
 
 impl fmt::Display for ZSockError {
@@ -849,6 +2053,14 @@ impl fmt::Display for ZSockError {
         match *self {
             ZSockError::CreateSock => write!(f, "Could not create socket"),
             ZSockError::CmdFailed => write!(f, "Socket command failed"),
+            #[cfg(feature = "rmp")]
+            ZSockError::FrameCount(n) => write!(f, "Expected a single frame message, got {} frames", n),
+            ZSockError::InvalidIdentity => write!(f, "Identity must be 1-255 bytes and not start with a zero byte"),
+            ZSockError::NoBuffers => write!(f, "Need at least one buffer to send"),
+            ZSockError::NoGssapi => write!(f, "libzmq was built without GSSAPI"),
+            ZSockError::NotWritable => write!(f, "Socket did not become writable"),
+            ZSockError::UnknownMechanism(m) => write!(f, "Unknown security mechanism: {}", m),
+            ZSockError::UnknownNameType(t) => write!(f, "Unknown GSSAPI name type: {}", t),
         }
     }
 }
@@ -858,18 +2070,54 @@ impl error::Error for ZSockError {
         match *self {
             ZSockError::CreateSock => "Could not create socket",
             ZSockError::CmdFailed => "Socket command failed",
+            #[cfg(feature = "rmp")]
+            ZSockError::FrameCount(_) => "Expected a single frame message",
+            ZSockError::InvalidIdentity => "Identity is invalid",
+            ZSockError::NoBuffers => "Need at least one buffer to send",
+            ZSockError::NoGssapi => "libzmq was built without GSSAPI",
+            ZSockError::NotWritable => "Socket did not become writable",
+            ZSockError::UnknownMechanism(_) => "Unknown security mechanism",
+            ZSockError::UnknownNameType(_) => "Unknown GSSAPI name type",
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::IoSlice;
+    use std::sync::Arc;
     use std::thread::sleep;
     use std::time::Duration;
     use super::*;
-    use {ZFrame, ZMsg, ZSys};
+    use crate::{Endpoint, ErrorKind, ZFrame, ZMsg, ZSys};
     use zmq::{self, Mechanism, SocketType};
 
+    #[cfg(feature = "rmp")]
+    #[test]
+    fn test_msgpack() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Moo {
+            name: String,
+            volume: u8,
+        }
+
+        ZSys::init();
+
+        let server = ZSock::new_pull("@inproc://zsock_test_msgpack").unwrap();
+        let client = ZSock::new_push(">inproc://zsock_test_msgpack").unwrap();
+
+        let moo = Moo { name: "cow".into(), volume: 11 };
+        client.send_msgpack(&moo).unwrap();
+        assert_eq!(server.recv_msgpack::<Moo>().unwrap(), moo);
+
+        client.send_str("not msgpack").unwrap();
+        assert!(server.recv_msgpack::<Moo>().is_err());
+
+        ZMsg::from_frames(&[&b"a"[..], b"b"]).unwrap().send(&client).unwrap();
+        let e = server.recv_msgpack::<Moo>().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidArg);
+    }
+
     #[test]
     fn test_new_pub() {
         ZSys::init();
@@ -904,7 +2152,7 @@ mod tests {
 
         sleep(Duration::from_millis(200));
 
-        let incoming = ZMsg::recv(&mut zsub).unwrap();
+        let mut incoming = ZMsg::recv(&mut zsub).unwrap();
         assert_eq!(incoming.popstr().unwrap().unwrap(), "moo");
         assert_eq!(incoming.popstr().unwrap().unwrap(), "cow");
     }
@@ -1012,6 +2260,116 @@ mod tests {
         assert!(zsock.unbind("inproc://test_unbind").is_ok());
     }
 
+    #[test]
+    fn test_bind_endpoint() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::PULL);
+        let port = server.bind(&Endpoint::PortRange { addr: "127.0.0.1".into(), start: Some(60000), end: None }).unwrap();
+        assert!(port >= 60000);
+
+        let client = ZSock::new(SocketType::PUSH);
+        client.connect(&Endpoint::Tcp { addr: "127.0.0.1".into(), port: port as u16 }).unwrap();
+        client.send_str("moo").unwrap();
+        assert_eq!(server.recv_str().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_ws() {
+        ZSys::init();
+
+        // WS is a draft transport, so libzmq may have been built
+        // without it
+        if !ZSys::has("ws") {
+            return;
+        }
+
+        let server = ZSock::new(SocketType::PULL);
+        server.bind("ws://127.0.0.1:*/moo").unwrap();
+        let endpoint: Endpoint = server.last_endpoint().unwrap().unwrap().parse().unwrap();
+
+        let client = ZSock::new(SocketType::PUSH);
+        client.connect(&endpoint).unwrap();
+        client.send_str("moo").unwrap();
+        assert_eq!(server.recv_str().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_unsupported_transport() {
+        ZSys::init();
+
+        let zsock = ZSock::new(SocketType::PULL);
+        let endpoint = Endpoint::Vmci { cid: None, port: None };
+        if !endpoint.is_supported() {
+            assert_eq!(zsock.bind(&endpoint).unwrap_err().kind(), ErrorKind::InvalidArg);
+            assert_eq!(zsock.connect("vmci://2:5555").unwrap_err().kind(), ErrorKind::InvalidArg);
+        }
+    }
+
+    #[cfg(feature = "draft")]
+    #[test]
+    fn test_server_client() {
+        ZSys::init();
+
+        if !ZSys::has("draft") {
+            assert_eq!(ZSock::new_server("inproc://zsock_test_server").unwrap_err().kind(), ErrorKind::Unsupported);
+            return;
+        }
+
+        let server = ZSock::new_server("inproc://zsock_test_server").unwrap();
+        let client = ZSock::new_client("inproc://zsock_test_server").unwrap();
+
+        client.send_str("moo").unwrap();
+        let frame = ZFrame::recv(&server).unwrap();
+        assert_eq!(frame.data().unwrap().unwrap(), "moo");
+        assert!(frame.routing_id() != 0);
+
+        let reply = ZFrame::from("cow").unwrap();
+        reply.set_routing_id(frame.routing_id());
+        reply.send(&server, None).unwrap();
+        assert_eq!(client.recv_str().unwrap().unwrap(), "cow");
+    }
+
+    #[cfg(feature = "draft")]
+    #[test]
+    fn test_radio_dish() {
+        ZSys::init();
+
+        if !ZSys::has("draft") {
+            return;
+        }
+
+        let dish = ZSock::new_dish("inproc://zsock_test_radio").unwrap();
+        dish.join("cows").unwrap();
+        let radio = ZSock::new_radio("inproc://zsock_test_radio").unwrap();
+        // Joins travel to the radio asynchronously
+        sleep(Duration::from_millis(100));
+
+        for group in &["pigs", "cows"] {
+            let frame = ZFrame::from("moo").unwrap();
+            frame.set_group(group).unwrap();
+            frame.send(&radio, None).unwrap();
+        }
+
+        let frame = ZFrame::recv(&dish).unwrap();
+        assert_eq!(frame.group().unwrap(), "cows");
+        dish.leave("cows").unwrap();
+    }
+
+    #[cfg(feature = "draft")]
+    #[test]
+    fn test_wss_options() {
+        ZSys::init();
+
+        if !ZSys::has("wss") {
+            return;
+        }
+
+        let zsock = ZSock::new(SocketType::REQ);
+        zsock.set_wss_trust_system(false).unwrap();
+        zsock.set_wss_hostname("localhost").unwrap();
+    }
+
     #[test]
     fn test_connect() {
         ZSys::init();
@@ -1058,6 +2416,56 @@ mod tests {
         assert_eq!(server.recv_str().unwrap().unwrap(), "This is a test string.");
     }
 
+    #[test]
+    fn test_recv_timeout_kind() {
+        ZSys::init();
+
+        let zsock = ZSock::new(SocketType::PULL);
+        zsock.set_rcvtimeo(Some(10));
+        assert_eq!(zsock.recv_str().unwrap_err().kind(), ErrorKind::NonZero);
+    }
+
+    #[test]
+    fn test_recv_into() {
+        ZSys::init();
+
+        let server = ZSock::new_pull("inproc://zsock_test_recv_into").unwrap();
+        let client = ZSock::new_push("inproc://zsock_test_recv_into").unwrap();
+
+        let mut buf = Vec::with_capacity(16);
+        client.send_str("moo").unwrap();
+        assert_eq!(server.recv_into(&mut buf).unwrap(), 3);
+        assert_eq!(buf, b"moo");
+        assert!(!server.rcvmore());
+        assert_eq!(buf.capacity(), 16);
+    }
+
+    #[test]
+    fn test_send_zero_copy() {
+        ZSys::init();
+
+        let mut server = ZSock::new_pull("inproc://zsock_test_send_zero_copy").unwrap();
+        let client = ZSock::new_push("inproc://zsock_test_send_zero_copy").unwrap();
+
+        client.send_zero_copy(b"moo".to_vec()).unwrap();
+        assert_eq!(ZFrame::recv(&mut server).unwrap().data().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_send_arc() {
+        ZSys::init();
+
+        let mut server = ZSock::new_pull("inproc://zsock_test_send_arc").unwrap();
+        let client = ZSock::new_push("inproc://zsock_test_send_arc").unwrap();
+
+        let data: Arc<[u8]> = Arc::from(&b"cow"[..]);
+        client.send_arc(data.clone()).unwrap();
+        assert_eq!(ZFrame::recv(&mut server).unwrap().data().unwrap().unwrap(), "cow");
+
+        // libzmq releases its reference when the received frame drops
+        assert_eq!(Arc::strong_count(&data), 1);
+    }
+
     #[test]
     fn test_zap_domain() {
         ZSys::init();
@@ -1180,6 +2588,17 @@ mod tests {
         assert_eq!(zsock.identity().unwrap().unwrap(), "moo");
     }
 
+    #[test]
+    fn test_identity_bin() {
+        ZSys::init();
+
+        let zsock = ZSock::new(SocketType::DEALER);
+        zsock.set_identity_bin(b"\xffmoo\x00").unwrap();
+        assert_eq!(zsock.identity_bin().unwrap(), b"\xffmoo\x00");
+        assert!(zsock.set_identity_bin(b"").is_err());
+        assert!(zsock.set_identity_bin(b"\x00moo").is_err());
+    }
+
     #[test]
     fn test_linger() {
         ZSys::init();
@@ -1189,6 +2608,112 @@ mod tests {
         assert_eq!(zsock.linger().unwrap(), 2000);
     }
 
+    #[test]
+    fn test_send_vectored() {
+        ZSys::init();
+
+        let mut server = ZSock::new_pull("@inproc://zsock_test_send_vectored").unwrap();
+        let client = ZSock::new_push(">inproc://zsock_test_send_vectored").unwrap();
+
+        let bufs = [IoSlice::new(b"head"), IoSlice::new(b""), IoSlice::new(b"body")];
+        client.send_vectored(&bufs, VectoredMode::Multipart).unwrap();
+        client.send_vectored(&bufs, VectoredMode::Coalesced).unwrap();
+
+        let mut msg = ZMsg::recv(&mut server).unwrap();
+        assert_eq!(msg.size(), 3);
+        assert_eq!(msg.popstr().unwrap().unwrap(), "head");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "");
+        assert_eq!(msg.popstr().unwrap().unwrap(), "body");
+
+        let mut msg = ZMsg::recv(&mut server).unwrap();
+        assert_eq!(msg.size(), 1);
+        assert_eq!(msg.popstr().unwrap().unwrap(), "headbody");
+
+        assert_eq!(client.send_vectored(&[], VectoredMode::Multipart).unwrap_err().kind(), ErrorKind::InvalidArg);
+
+        // A failed send leaves nothing behind to prefix the next message
+        let client = ZSock::new(SocketType::PUSH);
+        client.set_sndtimeo(Some(0));
+        client.bind("inproc://zsock_test_send_vectored_partial").unwrap();
+        assert!(client.send_vectored(&bufs, VectoredMode::Multipart).is_err());
+
+        let server = ZSock::new_pull(">inproc://zsock_test_send_vectored_partial").unwrap();
+        client.set_sndtimeo(None);
+        client.send_vectored(&bufs[..1], VectoredMode::Multipart).unwrap();
+        let mut msg = ZMsg::recv(&server).unwrap();
+        assert_eq!(msg.size(), 1);
+        assert_eq!(msg.popstr().unwrap().unwrap(), "head");
+    }
+
+    #[test]
+    fn test_send_or_block_until_writable() {
+        ZSys::init();
+
+        let mut client = ZSock::new_push("@inproc://zsock_test_writable").unwrap();
+        assert!(!client.writable());
+
+        let msg = ZMsg::new();
+        msg.addstr("moo").unwrap();
+        let err = client.send_or_block_until_writable(msg, Some(Duration::from_millis(10))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+
+        let server = ZSock::new_pull(">inproc://zsock_test_writable").unwrap();
+        assert!(client.writable());
+
+        let msg = ZMsg::new();
+        msg.addstr("moo").unwrap();
+        client.send_or_block_until_writable(msg, None).unwrap();
+        assert_eq!(server.recv_str().unwrap().unwrap(), "moo");
+    }
+
+    #[test]
+    fn test_option_getters() {
+        ZSys::init();
+
+        let zsock = ZSock::new(SocketType::DEALER);
+
+        zsock.set_immediate(true);
+        assert!(zsock.immediate());
+        zsock.set_sndbuf(Some(65536));
+        assert_eq!(zsock.sndbuf(), Some(65536));
+        zsock.set_reconnect_ivl(250);
+        assert_eq!(zsock.reconnect_ivl().unwrap(), 250);
+        zsock.set_maxmsgsize(None);
+        assert_eq!(zsock.maxmsgsize(), None);
+        zsock.set_tcp_keepalive(Some(1));
+        assert_eq!(zsock.tcp_keepalive(), Some(1));
+        zsock.set_rate(1000);
+        assert_eq!(zsock.rate().unwrap(), 1000);
+        zsock.set_multicast_maxtpdu(1400).unwrap();
+        assert_eq!(zsock.multicast_maxtpdu().unwrap(), 1400);
+
+        zsock.bind("inproc://zsock_test_option_getters").unwrap();
+        assert_eq!(zsock.last_endpoint().unwrap().unwrap(), "inproc://zsock_test_option_getters");
+    }
+
+    #[test]
+    fn test_duration_options() {
+        ZSys::init();
+
+        let zsock = ZSock::new(SocketType::DEALER);
+
+        zsock.set_linger_duration(Some(Duration::from_millis(1500)));
+        assert_eq!(zsock.linger().unwrap(), 1500);
+        zsock.set_linger_duration(None);
+        assert_eq!(zsock.linger_duration().unwrap(), None);
+
+        zsock.set_rcvtimeo_duration(Some(Duration::from_secs(2)));
+        assert_eq!(zsock.rcvtimeo(), Some(2000));
+        zsock.set_sndtimeo_duration(None);
+        assert_eq!(zsock.sndtimeo_duration(), None);
+
+        zsock.set_heartbeat_ivl(Duration::from_millis(100));
+        assert_eq!(zsock.heartbeat_ivl().unwrap(), Duration::from_millis(100));
+
+        zsock.set_recovery_ivl_duration(Duration::from_secs(5));
+        assert_eq!(zsock.recovery_ivl().unwrap(), 5000);
+    }
+
     #[test]
     fn test_mechanism() {
         ZSys::init();
@@ -1234,6 +2759,104 @@ mod tests {
         assert_eq!(zsock.plain_password().unwrap().unwrap(), "ohtheinternet'soncomputersnow");
     }
 
+    #[test]
+    fn test_gssapi() {
+        ZSys::init();
+
+        let zsock = ZSock::new(SocketType::REQ);
+        if !ZSys::has("gssapi") {
+            assert_eq!(zsock.set_gssapi_server(true).unwrap_err().kind(), ErrorKind::InvalidArg);
+            return;
+        }
+
+        zsock.set_gssapi_plaintext(true).unwrap();
+        assert!(zsock.gssapi_plaintext().unwrap());
+        zsock.set_gssapi_service_principal("zmq/moo.example.com@EXAMPLE.COM").unwrap();
+        assert_eq!(zsock.gssapi_service_principal().unwrap().unwrap(), "zmq/moo.example.com@EXAMPLE.COM");
+        zsock.set_gssapi_service_principal_nametype(GssapiNameType::Krb5Principal).unwrap();
+        assert_eq!(zsock.gssapi_service_principal_nametype().unwrap(), GssapiNameType::Krb5Principal);
+    }
+
+    #[test]
+    fn test_history() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::PULL);
+        server.set_history(3);
+        server.bind("inproc://zsock_test_history").unwrap();
+        server.set_rcvtimeo(Some(0));
+        assert!(server.recv_str().is_err());
+
+        let client = ZSock::new_push("inproc://zsock_test_history").unwrap();
+        ZMsg::from_frames(&[&b"moo"[..], b"cow"]).unwrap().send(&client).unwrap();
+        ZMsg::recv(&server).unwrap();
+
+        let history = server.debug_history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].op, HistoryOp::SetOption("rcvtimeo", "Some(0)".into()));
+        assert_eq!(history[1].op, HistoryOp::Recv { frames: 0, bytes: 0 });
+        assert!(history[1].error.is_some());
+        assert_eq!(history[2].op, HistoryOp::Recv { frames: 2, bytes: 6 });
+        assert!(client.debug_history().is_empty());
+
+        server.set_history(0);
+        assert!(server.debug_history().is_empty());
+    }
+
+    #[test]
+    fn test_history_entry_points() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::PAIR);
+        server.set_history(8);
+        server.bind("inproc://zsock_test_history_entry_points").unwrap();
+        let client = ZSock::new_pair(">inproc://zsock_test_history_entry_points").unwrap();
+
+        server.send_zero_copy(b"moo".to_vec()).unwrap();
+        server.send_arc(Arc::from(&b"cow"[..])).unwrap();
+        server.send_vectored(&[IoSlice::new(b"mo"), IoSlice::new(b"o")], VectoredMode::Multipart).unwrap();
+        server.signal(3).unwrap();
+        client.send_str("moo").unwrap();
+        let mut buf = Vec::new();
+        server.recv_into(&mut buf).unwrap();
+        client.signal(0).unwrap();
+        server.wait().unwrap();
+
+        let ops: Vec<_> = server.debug_history().into_iter().map(|e| e.op).collect();
+        assert_eq!(ops, vec![
+            HistoryOp::Bind("inproc://zsock_test_history_entry_points".into()),
+            HistoryOp::Send { frames: 1, bytes: 3 },
+            HistoryOp::Send { frames: 1, bytes: 3 },
+            HistoryOp::Send { frames: 2, bytes: 3 },
+            HistoryOp::Signal(3),
+            HistoryOp::Recv { frames: 1, bytes: 3 },
+            HistoryOp::Wait,
+        ]);
+    }
+
+    #[test]
+    fn test_last_error() {
+        ZSys::init();
+
+        let server = ZSock::new(SocketType::PULL);
+        server.bind("inproc://zsock_test_last_error").unwrap();
+        assert!(server.last_error().is_none());
+
+        let other = ZSock::new(SocketType::PULL);
+        assert!(other.bind("inproc://zsock_test_last_error").is_err());
+        let e = other.last_error().unwrap();
+        assert_eq!(e.op, HistoryOp::Bind("inproc://zsock_test_last_error".into()));
+        assert_eq!(e.kind, ErrorKind::NonZero);
+        assert_eq!(e.errno, Some(zmq::Error::EADDRINUSE as i32));
+
+        // A later success doesn't clear it
+        other.bind("inproc://zsock_test_last_error_2").unwrap();
+        assert!(other.last_error().is_some());
+        other.clear_last_error();
+        assert!(other.last_error().is_none());
+        assert!(server.last_error().is_none());
+    }
+
     #[test]
     fn test_curve_server() {
         ZSys::init();
@@ -1299,6 +2922,48 @@ mod tests {
         assert_eq!(x, 2);
     }
 
+    #[test]
+    fn test_xpub_welcome_msg() {
+        ZSys::init();
+
+        let xpub = ZSock::new(SocketType::XPUB);
+        xpub.set_xpub_welcome_msg(b"WELCOME").unwrap();
+        xpub.bind("inproc://zsock_test_xpub_welcome_msg").unwrap();
+        let sub = ZSock::new_sub("inproc://zsock_test_xpub_welcome_msg", Some("WELCOME")).unwrap();
+        sub.set_rcvtimeo(Some(1000));
+
+        assert_eq!(sub.recv_str().unwrap().unwrap(), "WELCOME");
+    }
+
+    #[test]
+    fn test_close_graceful() {
+        ZSys::init();
+
+        // Over TCP, so that the queue drains into the connection
+        let pull = ZSock::new(SocketType::PULL);
+        let port = pull.bind("tcp://127.0.0.1:*").unwrap();
+        let push = ZSock::new_push(&format!(">tcp://127.0.0.1:{}", port)).unwrap();
+        for _ in 0..10 {
+            push.send_str("moo").unwrap();
+        }
+
+        // Nothing unread, and the queue drains to the connected peer
+        let report = push.close_graceful(Duration::from_secs(1)).unwrap();
+        assert_eq!(report.unread, 0);
+        if cfg!(feature = "draft") {
+            assert_eq!(report.unsent, Some(0));
+        } else {
+            assert_eq!(report.unsent, None);
+        }
+        pull.set_rcvtimeo(Some(1000));
+        for _ in 0..9 {
+            assert_eq!(pull.recv_str().unwrap().unwrap(), "moo");
+        }
+
+        // The last message arrives but is never read
+        assert_eq!(pull.close_graceful(Duration::from_secs(1)).unwrap().unread, 1);
+    }
+
     #[test]
     fn test_monitor() {
         ZSys::init();
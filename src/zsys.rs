@@ -1,13 +1,16 @@
 //! Module: czmq-zsys
 
-use {czmq_sys, RawInterface, Result};
-use error::{Error, ErrorKind};
+use crate::{RawInterface, Result};
+use crate::error::{Error, ErrorKind};
 use std::{error, fmt, ptr};
+use std::ffi::CString;
 use std::os::raw::c_void;
-use std::sync::{Once, ONCE_INIT};
-use zsock::ZSock;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::zsock::ZSock;
 
-static INIT_ZSYS: Once = ONCE_INIT;
+static INIT_ZSYS: Once = Once::new();
+static RETRY_INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
 pub struct ZSys;
 
@@ -33,9 +36,30 @@ impl ZSys {
         }
     }
 
+    /// Whether libzmq was built with `capability`, e.g. a transport
+    /// like `"ipc"`, `"ws"` or `"wss"`, or a mechanism like `"curve"`.
+    pub fn has(capability: &str) -> bool {
+        match CString::new(capability) {
+            Ok(c) => unsafe { czmq_sys::zmq_has(c.as_ptr()) == 1 },
+            Err(_) => false,
+        }
+    }
+
     pub fn is_interrupted() -> bool {
         unsafe { czmq_sys::zsys_interrupted == 1 }
     }
+
+    /// When enabled, blocking send/recv calls that fail with EINTR
+    /// are retried automatically, unless CZMQ's signal handler has
+    /// caught an interrupt (see `is_interrupted()`). Disabled by
+    /// default.
+    pub fn set_retry_interrupted(retry: bool) {
+        RETRY_INTERRUPTED.store(retry, Ordering::SeqCst);
+    }
+
+    pub fn retry_interrupted() -> bool {
+        RETRY_INTERRUPTED.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug)]
@@ -62,6 +86,15 @@ impl error::Error for ZSysError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ZMsg;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    // The tests that change the process wide retry setting take this
+    // first, so they don't undo each other's changes
+    static RETRY_LOCK: Mutex<()> = Mutex::new(());
 
     #[test]
     fn test_create_pipe() {
@@ -74,10 +107,106 @@ mod tests {
         assert_eq!(frontend.recv_str().unwrap().unwrap(), "My family laughed when I told them I was going to be a comedian. Well...they aren't laughing now!");
     }
 
+    #[test]
+    fn test_has() {
+        assert!(ZSys::has("ipc") || cfg!(windows));
+        assert!(!ZSys::has("moo"));
+        assert!(!ZSys::has("m\0o"));
+    }
+
     #[test]
     fn test_is_interrupted() {
         // This test is half hearted as we can't test the interrupt
         // case.
         assert!(!ZSys::is_interrupted());
     }
+
+    #[test]
+    fn test_retry_interrupted() {
+        // The setting is process wide, so leave it as other tests
+        // found it
+        let _lock = RETRY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = ZSys::retry_interrupted();
+        ZSys::set_retry_interrupted(true);
+        assert!(ZSys::retry_interrupted());
+        ZSys::set_retry_interrupted(false);
+        assert!(!ZSys::retry_interrupted());
+        ZSys::set_retry_interrupted(previous);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recv_interrupted() {
+        use std::os::unix::thread::JoinHandleExt;
+
+        extern "C" fn ignore(_: libc::c_int) {}
+
+        ZSys::init();
+        let _lock = RETRY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = ZSys::retry_interrupted();
+
+        // Without SA_RESTART, the signal makes a blocked call fail
+        // with EINTR
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+        }
+
+        for &retry in &[false, true] {
+            ZSys::set_retry_interrupted(retry);
+
+            let endpoint = format!("inproc://zsys_test_recv_interrupted_{}", retry);
+            let server = ZSock::new_pull(&format!("@{}", endpoint)).unwrap();
+            let client = ZSock::new_push(&format!(">{}", endpoint)).unwrap();
+            server.set_rcvtimeo(Some(5000));
+
+            let done = Arc::new(AtomicBool::new(false));
+            let receiver = {
+                let done = done.clone();
+                thread::spawn(move || {
+                    let result = server.recv_str().map_err(|e| e.kind());
+                    done.store(true, Ordering::SeqCst);
+                    result
+                })
+            };
+
+            // A signal sent before the receiver blocks is missed, so
+            // keep sending until it has had time to land
+            let attempts = if retry { 10 } else { 200 };
+            for _ in 0..attempts {
+                if done.load(Ordering::SeqCst) {
+                    break;
+                }
+                unsafe { libc::pthread_kill(receiver.as_pthread_t(), libc::SIGUSR1) };
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            if retry {
+                assert!(!done.load(Ordering::SeqCst));
+                client.send_str("moo").unwrap();
+                assert_eq!(receiver.join().unwrap().unwrap().unwrap(), "moo");
+            } else {
+                assert_eq!(receiver.join().unwrap().unwrap_err(), ErrorKind::Interrupted);
+            }
+        }
+
+        ZSys::set_retry_interrupted(previous);
+    }
+
+    #[test]
+    fn test_recv_terminated() {
+        let mut ctx = zmq::Context::new();
+        let mut sock = ctx.socket(zmq::PULL).unwrap();
+        sock.bind("inproc://zsys_test_recv_terminated").unwrap();
+
+        let receiver = thread::spawn(move || ZMsg::recv(&mut sock).err().map(|e| e.kind()));
+        thread::sleep(Duration::from_millis(50));
+
+        // Terminating wakes the blocked recv with ETERM, then waits
+        // for the receiver to close its socket
+        ctx.destroy().unwrap();
+        assert_eq!(receiver.join().unwrap(), Some(ErrorKind::Terminated));
+    }
 }
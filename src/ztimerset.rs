@@ -0,0 +1,179 @@
+//! Module: czmq-ztimerset
+//!
+//! Repeating timers from CZMQ's draft `ztimerset` class, for use
+//! alongside a `ZPoller`: wait for at most `timeout()`, then call
+//! `execute()` to run the handlers of any timers that are due. Only
+//! compiled with the `draft` feature.
+
+use crate::{Error, ErrorKind, Result};
+use std::{cmp, error, fmt, ptr};
+use std::collections::HashMap;
+use std::os::raw::{c_int, c_void};
+use std::time::Duration;
+
+type Handler = Box<dyn FnMut(i32) + Send>;
+
+pub struct ZTimerSet {
+    ztimerset: *mut czmq_sys::ztimerset_t,
+    // Boxed twice so that the pointer CZMQ holds stays put
+    handlers: HashMap<i32, Box<Handler>>,
+}
+
+unsafe impl Send for ZTimerSet {}
+
+impl Drop for ZTimerSet {
+    fn drop(&mut self) {
+        unsafe { czmq_sys::ztimerset_destroy(&mut self.ztimerset) };
+    }
+}
+
+impl ZTimerSet {
+    pub fn new() -> Result<ZTimerSet> {
+        let ztimerset = unsafe { czmq_sys::ztimerset_new() };
+
+        if ztimerset == ptr::null_mut() {
+            return Err(Error::new(ErrorKind::NullPtr, ZTimerSetError::Instantiate));
+        }
+
+        Ok(ZTimerSet {
+            ztimerset: ztimerset,
+            handlers: HashMap::new(),
+        })
+    }
+
+    /// Call `handler` with the timer's id every `interval`, until the
+    /// timer is cancelled. Returns the timer's id.
+    pub fn add<F>(&mut self, interval: Duration, handler: F) -> Result<i32>
+        where F: FnMut(i32) + Send + 'static {
+        let mut handler: Box<Handler> = Box::new(Box::new(handler));
+        let arg = &mut *handler as *mut Handler as *mut c_void;
+        let id = unsafe { czmq_sys::ztimerset_add(self.ztimerset, to_millis(interval), run_handler, arg) };
+
+        if id == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZTimerSetError::CmdFailed))
+        } else {
+            self.handlers.insert(id, handler);
+            Ok(id)
+        }
+    }
+
+    pub fn cancel(&mut self, id: i32) -> Result<()> {
+        let rc = unsafe { czmq_sys::ztimerset_cancel(self.ztimerset, id) };
+
+        if rc == -1 {
+            Err(Error::new(ErrorKind::InvalidArg, ZTimerSetError::UnknownTimer(id)))
+        } else {
+            self.handlers.remove(&id);
+            Ok(())
+        }
+    }
+
+    /// Change a timer's interval, from the next time it fires.
+    pub fn set_interval(&self, id: i32, interval: Duration) -> Result<()> {
+        let rc = unsafe { czmq_sys::ztimerset_set_interval(self.ztimerset, id, to_millis(interval)) };
+
+        if rc == -1 {
+            Err(Error::new(ErrorKind::InvalidArg, ZTimerSetError::UnknownTimer(id)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Restart a timer's interval from now.
+    pub fn reset(&self, id: i32) -> Result<()> {
+        let rc = unsafe { czmq_sys::ztimerset_reset(self.ztimerset, id) };
+
+        if rc == -1 {
+            Err(Error::new(ErrorKind::InvalidArg, ZTimerSetError::UnknownTimer(id)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// How long until the next timer is due, or `None` if there are
+    /// no timers.
+    pub fn timeout(&self) -> Option<Duration> {
+        let timeout = unsafe { czmq_sys::ztimerset_timeout(self.ztimerset) };
+
+        if timeout < 0 {
+            None
+        } else {
+            Some(Duration::from_millis(timeout as u64))
+        }
+    }
+
+    /// Run the handlers of every timer that is due.
+    pub fn execute(&mut self) -> Result<()> {
+        let rc = unsafe { czmq_sys::ztimerset_execute(self.ztimerset) };
+
+        if rc == -1 {
+            Err(Error::new(ErrorKind::NonZero, ZTimerSetError::CmdFailed))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+unsafe extern "C" fn run_handler(timer_id: c_int, arg: *mut c_void) {
+    let handler = &mut *(arg as *mut Handler);
+    handler(timer_id);
+}
+
+fn to_millis(interval: Duration) -> u64 {
+    cmp::min(interval.as_millis(), c_int::max_value() as u128) as u64
+}
+
+#[derive(Debug)]
+pub enum ZTimerSetError {
+    Instantiate,
+    CmdFailed,
+    UnknownTimer(i32),
+}
+
+impl fmt::Display for ZTimerSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZTimerSetError::Instantiate => write!(f, "Could not instantiate new ZTimerSet struct"),
+            ZTimerSetError::CmdFailed => write!(f, "ZTimerSet command failed"),
+            ZTimerSetError::UnknownTimer(id) => write!(f, "No timer with id {}", id),
+        }
+    }
+}
+
+impl error::Error for ZTimerSetError {
+    fn description(&self) -> &str {
+        match *self {
+            ZTimerSetError::Instantiate => "Could not instantiate new ZTimerSet struct",
+            ZTimerSetError::CmdFailed => "ZTimerSet command failed",
+            ZTimerSetError::UnknownTimer(_) => "No timer with that id",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_timers() {
+        let mut timers = ZTimerSet::new().unwrap();
+        assert!(timers.timeout().is_none());
+
+        let fired = Arc::new(AtomicUsize::new(0));
+        let f = fired.clone();
+        let id = timers.add(Duration::from_millis(10), move |_| { f.fetch_add(1, Ordering::SeqCst); }).unwrap();
+        assert!(timers.timeout().unwrap() <= Duration::from_millis(10));
+
+        while fired.load(Ordering::SeqCst) < 2 {
+            thread::sleep(timers.timeout().unwrap());
+            timers.execute().unwrap();
+        }
+
+        timers.cancel(id).unwrap();
+        assert!(timers.timeout().is_none());
+        assert!(timers.cancel(id).is_err());
+    }
+}
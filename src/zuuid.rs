@@ -0,0 +1,50 @@
+//! Module: czmq-zuuid
+//!
+//! Random UUIDs from CZMQ's `zuuid`, for the tickets, keys and node
+//! ids that several patterns hand out.
+
+use std::{ptr, slice};
+use std::ffi::CStr;
+
+pub(crate) const ZUUID_LEN: usize = 16;
+
+pub(crate) struct ZUuid {
+    pub data: [u8; ZUUID_LEN],
+    /// The UUID as 32 upper case hex digits.
+    pub string: String,
+}
+
+impl ZUuid {
+    /// Generate a random UUID, or `None` if CZMQ couldn't.
+    pub fn new() -> Option<ZUuid> {
+        let mut uuid = unsafe { czmq_sys::zuuid_new() };
+
+        if uuid == ptr::null_mut() {
+            return None;
+        }
+
+        let mut data = [0; ZUUID_LEN];
+        data.copy_from_slice(unsafe { slice::from_raw_parts(czmq_sys::zuuid_data(uuid), ZUUID_LEN) });
+        let string = unsafe { CStr::from_ptr(czmq_sys::zuuid_str(uuid)) }.to_string_lossy().into_owned();
+        unsafe { czmq_sys::zuuid_destroy(&mut uuid) };
+
+        Some(ZUuid {
+            data: data,
+            string: string,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let a = ZUuid::new().unwrap();
+        let b = ZUuid::new().unwrap();
+        assert!(a.data != b.data);
+        assert_eq!(a.string.len(), ZUUID_LEN * 2);
+        assert!(a.string.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
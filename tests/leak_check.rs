@@ -0,0 +1,37 @@
+// Leak checks track sockets process wide, so this lives in its own
+// test binary, away from the crate's other socket tests.
+
+use czmq::{ZSock, ZSys};
+use czmq::testing::LeakCheck;
+use tempdir::TempDir;
+
+#[test]
+fn test_leak_check() {
+    ZSys::init();
+
+    // Bound before any check started, so it isn't tracked
+    let dir = TempDir::new("leak_check").unwrap();
+    let ipc = format!("ipc://{}", dir.path().join("sock").display());
+    let server = ZSock::new_pull(&format!("@{}", ipc)).unwrap();
+
+    let check = LeakCheck::start();
+    let sock = ZSock::new_pull("@inproc://testing_leak_check").unwrap();
+    let leaked = check.leaks();
+    let leak = leaked.iter().find(|l| l.name == "ZSock(PULL)").unwrap();
+    assert!(leak.backtrace.contains("test_leak_check"));
+
+    // A check only covers what was created after it started
+    let inner = LeakCheck::start();
+    assert!(inner.leaks().is_empty());
+
+    // Connecting to the server's ipc endpoint doesn't make its file
+    // ours to clean up
+    drop(ZSock::new_push(&format!(">{}", ipc)).unwrap());
+    inner.finish().unwrap();
+    assert!(dir.path().join("sock").exists());
+
+    let e = check.finish().unwrap_err();
+    assert!(e.to_string().contains("ZSock(PULL)"));
+    drop(sock);
+    drop(server);
+}